@@ -112,7 +112,7 @@ async fn do_update_certificates(
                             webhook::NotificationCategory::ParseCertificateFail,
                         level: webhook::NotificationLevel::Error,
                         msg: errors,
-                        remark: None,
+                        ..Default::default()
                     }).await;
                 }
             },
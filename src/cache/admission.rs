@@ -0,0 +1,72 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use pingora_limits::rate::Rate;
+use rand::Rng;
+use std::time::Duration;
+
+// the reason of uncacheable response which is not admitted,
+// it's skipped by the predictor, so the key can be cached later
+pub static NOT_ADMITTED: &str = "NotAdmitted";
+
+/// The admission policy of cache, it's used to avoid polluting
+/// the storage with the objects which are requested only once.
+pub struct CacheAdmission {
+    // the object is admitted if it's requested at least min hits
+    // in the window
+    min_hits: isize,
+    rate: Rate,
+}
+
+impl CacheAdmission {
+    pub fn new(min_hits: isize, window: Duration) -> Self {
+        Self {
+            min_hits,
+            rate: Rate::new(window),
+        }
+    }
+    /// Record the request of key, and check whether it's admitted.
+    pub fn observe(&self, key: &str) -> bool {
+        self.rate.observe(&key, 1) >= self.min_hits
+    }
+}
+
+/// Check whether the object of size is admitted, the object which is
+/// not larger than max size is always admitted, otherwise it's admitted
+/// by the probability of `max size / size`.
+pub fn is_size_admitted(size: usize, max_size: usize) -> bool {
+    if size <= max_size {
+        return true;
+    }
+    rand::thread_rng().gen_range(0..size) < max_size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_size_admitted, CacheAdmission};
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+
+    #[test]
+    fn test_cache_admission() {
+        let admission = CacheAdmission::new(2, Duration::from_secs(60));
+        assert_eq!(false, admission.observe("GET:/books"));
+        assert_eq!(true, admission.observe("GET:/books"));
+        assert_eq!(false, admission.observe("GET:/users"));
+
+        assert_eq!(true, is_size_admitted(1024, 1024));
+        assert_eq!(false, is_size_admitted(usize::MAX, 1));
+        assert_eq!(true, (0..1000).any(|_| is_size_admitted(2048, 1024)));
+    }
+}
@@ -14,33 +14,199 @@
 
 use super::http_cache::{CacheObject, HttpCacheStats, HttpCacheStorage};
 use super::{Error, Result, PAGE_SIZE};
-#[cfg(feature = "full")]
+#[cfg(feature = "metrics")]
 use crate::state::{CACHE_READING_TIME, CACHE_WRITING_TIME};
 use crate::util;
+use ahash::AHashMap;
 use async_trait::async_trait;
 use bytes::Bytes;
-#[cfg(feature = "full")]
+use bytesize::ByteSize;
+#[cfg(feature = "metrics")]
 use prometheus::Histogram;
 use scopeguard::defer;
-use std::path::Path;
-use std::sync::atomic::{AtomicU32, Ordering};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
 use std::time::SystemTime;
 use tinyufo::TinyUfo;
 use tokio::fs;
+use tokio::io::AsyncWriteExt;
 use tracing::{debug, error, info};
 use walkdir::WalkDir;
 
+// max levels of shard directory
+const MAX_SHARD_LEVELS: usize = 4;
+// the directory of tag records
+const TAGS_DIR: &str = ".tags";
+// the checkpoint file of index
+const INDEX_FILE: &str = ".index";
+
+// the compact metadata of cache file
+struct FileIndexEntry {
+    size: u32,
+    // the last accessed time(seconds), it's updated with read lock
+    accessed: AtomicU32,
+}
+
+/// The index of cache files, it's loaded from the checkpoint or rebuilt
+/// from the cache directory in background when the file cache is created,
+/// so the size limit works after restart.
+#[derive(Default)]
+struct FileIndex {
+    entries: RwLock<AHashMap<String, FileIndexEntry>>,
+    size: AtomicU64,
+    // the index is loaded from checkpoint or cache directory
+    loaded: AtomicBool,
+}
+
+#[inline]
+fn now_seconds() -> u32 {
+    util::now().as_secs() as u32
+}
+
+impl FileIndex {
+    fn insert(&self, name: String, size: u32, accessed: u32) {
+        let mut entries =
+            self.entries.write().unwrap_or_else(|e| e.into_inner());
+        if let Some(prev) = entries.insert(
+            name,
+            FileIndexEntry {
+                size,
+                accessed: AtomicU32::new(accessed),
+            },
+        ) {
+            self.size.fetch_sub(prev.size as u64, Ordering::Relaxed);
+        }
+        self.size.fetch_add(size as u64, Ordering::Relaxed);
+    }
+    /// Update the accessed time of entry, it returns false if the entry
+    /// is not found.
+    fn touch(&self, name: &str) -> bool {
+        let entries = self.entries.read().unwrap_or_else(|e| e.into_inner());
+        if let Some(entry) = entries.get(name) {
+            entry.accessed.store(now_seconds(), Ordering::Relaxed);
+            return true;
+        }
+        false
+    }
+    /// Touch the entry or insert it if not exists,
+    /// the file may be written after the last checkpoint.
+    fn touch_or_insert(&self, name: &str, size: u32) {
+        if !self.touch(name) {
+            self.insert(name.to_string(), size, now_seconds());
+        }
+    }
+    /// Convert the index to checkpoint data, the first line is the levels
+    /// of shard, and each line of entry is `size\taccessed\tname`.
+    fn to_checkpoint(&self, levels: usize) -> String {
+        let entries = self.entries.read().unwrap_or_else(|e| e.into_inner());
+        let mut data = String::with_capacity(entries.len() * 64);
+        data.push_str(&format!("levels={levels}\n"));
+        for (name, entry) in entries.iter() {
+            data.push_str(&format!(
+                "{}\t{}\t{name}\n",
+                entry.size,
+                entry.accessed.load(Ordering::Relaxed)
+            ));
+        }
+        data
+    }
+    /// Load the entries from checkpoint data, it returns false if the
+    /// levels of shard is changed.
+    fn load_checkpoint(&self, data: &str, levels: usize) -> bool {
+        let mut lines = data.lines();
+        if lines.next() != Some(format!("levels={levels}").as_str()) {
+            return false;
+        }
+        for line in lines {
+            let mut arr = line.splitn(3, '\t');
+            let (Some(size), Some(accessed), Some(name)) =
+                (arr.next(), arr.next(), arr.next())
+            else {
+                continue;
+            };
+            let (Ok(size), Ok(accessed)) =
+                (size.parse::<u32>(), accessed.parse::<u32>())
+            else {
+                continue;
+            };
+            self.insert(name.to_string(), size, accessed);
+        }
+        true
+    }
+    fn remove(&self, name: &str) {
+        let mut entries =
+            self.entries.write().unwrap_or_else(|e| e.into_inner());
+        if let Some(prev) = entries.remove(name) {
+            self.size.fetch_sub(prev.size as u64, Ordering::Relaxed);
+        }
+    }
+    /// Remove the entries whose name starts with the prefix,
+    /// the count of removed entries is returned.
+    fn remove_prefix(&self, prefix: &str) -> usize {
+        let mut entries =
+            self.entries.write().unwrap_or_else(|e| e.into_inner());
+        let count = entries.len();
+        entries.retain(|name, entry| {
+            if !name.starts_with(prefix) {
+                return true;
+            }
+            self.size.fetch_sub(entry.size as u64, Ordering::Relaxed);
+            false
+        });
+        count - entries.len()
+    }
+    fn len(&self) -> usize {
+        self.entries.read().unwrap_or_else(|e| e.into_inner()).len()
+    }
+    /// Remove the least recently accessed entries until the size of
+    /// index is less than the limit, the names of them are returned.
+    fn evict(&self, limit: u64) -> Vec<String> {
+        let mut entries =
+            self.entries.write().unwrap_or_else(|e| e.into_inner());
+        let mut size = self.size.load(Ordering::Relaxed);
+        if size <= limit {
+            return vec![];
+        }
+        let mut items: Vec<(u32, String)> = entries
+            .iter()
+            .map(|(name, entry)| {
+                (entry.accessed.load(Ordering::Relaxed), name.clone())
+            })
+            .collect();
+        items.sort_unstable();
+        let mut names = vec![];
+        for (_, name) in items {
+            if size <= limit {
+                break;
+            }
+            if let Some(prev) = entries.remove(&name) {
+                size -= (prev.size as u64).min(size);
+                self.size.fetch_sub(prev.size as u64, Ordering::Relaxed);
+                names.push(name);
+            }
+        }
+        names
+    }
+}
+
 pub struct FileCache {
     pub directory: String,
     reading: AtomicU32,
     reading_max: u32,
-    #[cfg(feature = "full")]
+    #[cfg(feature = "metrics")]
     read_time: Box<Histogram>,
     writing: AtomicU32,
     writing_max: u32,
-    #[cfg(feature = "full")]
+    #[cfg(feature = "metrics")]
     write_time: Box<Histogram>,
     cache: Option<TinyUfo<String, CacheObject>>,
+    // the levels of shard directory, zero means no shard
+    levels: usize,
+    // the max size of cache files, zero means no limit
+    max_size: u64,
+    index: Arc<FileIndex>,
 }
 
 struct FileCacheParams {
@@ -48,12 +214,18 @@ struct FileCacheParams {
     reading_max: u32,
     writing_max: u32,
     cache_max: usize,
+    levels: usize,
+    max_size: u64,
 }
 
 fn parse_params(dir: &str) -> FileCacheParams {
     let mut reading_max = 10 * 1000;
     let mut writing_max = 1000;
     let mut cache_max = 100;
+    // the cache files are not sharded by default,
+    // so the files of the previous flat layout are still valid
+    let mut levels = 0;
+    let mut max_size = 0;
     let dir = if let Some((dir, query)) = dir.split_once('?') {
         let m = util::convert_query_map(query);
         if let Some(max) = m.get("reading_max") {
@@ -65,6 +237,18 @@ fn parse_params(dir: &str) -> FileCacheParams {
         if let Some(value) = m.get("cache_max") {
             cache_max = value.parse::<usize>().unwrap_or(cache_max);
         }
+        if let Some(value) = m.get("levels") {
+            levels = value
+                .parse::<usize>()
+                .unwrap_or(levels)
+                .min(MAX_SHARD_LEVELS);
+        }
+        if let Some(value) = m.get("max_size") {
+            max_size = value
+                .parse::<ByteSize>()
+                .map(|v| v.as_u64())
+                .unwrap_or(max_size);
+        }
         util::resolve_path(dir)
     } else {
         util::resolve_path(dir)
@@ -74,10 +258,90 @@ fn parse_params(dir: &str) -> FileCacheParams {
         reading_max,
         writing_max,
         cache_max,
+        levels,
+        max_size,
+    }
+}
+
+/// Get the name of cache in index, the namespace is the prefix of it.
+#[inline]
+fn get_index_name(key: &str, namespace: &str) -> String {
+    if namespace.is_empty() {
+        key.to_string()
+    } else {
+        format!("{namespace}/{key}")
+    }
+}
+
+/// Get the shard directories of key, each level uses two hex chars
+/// of the crc32 of key, e.g. `a1/b2` for two levels.
+fn get_shard(key: &str, levels: usize) -> String {
+    let hash = format!("{:08x}", crc32fast::hash(key.as_bytes()));
+    (0..levels.min(MAX_SHARD_LEVELS))
+        .map(|i| &hash[i * 2..i * 2 + 2])
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Convert the relative path of cache file to the name in index,
+/// it returns none if the file is not in the shard directory.
+fn relative_to_name(relative: &Path, levels: usize) -> Option<String> {
+    let parts: Vec<String> = relative
+        .iter()
+        .map(|item| item.to_string_lossy().to_string())
+        .collect();
+    // the tag records and checkpoint of index are hidden files
+    if parts.len() <= levels || parts[0].starts_with('.') {
+        return None;
+    }
+    let key = &parts[parts.len() - 1];
+    let shard = parts[parts.len() - 1 - levels..parts.len() - 1].join("/");
+    if shard != get_shard(key, levels) {
+        return None;
     }
+    let namespace = parts[..parts.len() - 1 - levels].join("/");
+    Some(get_index_name(key, &namespace))
 }
 
-/// Create a file cache and use tinyufo for hotspot data caching
+/// Load the index from checkpoint, if the checkpoint is not found,
+/// the index is rebuilt from cache directory. The entries of checkpoint
+/// are validated lazily when they are read.
+fn load_index(index: &FileIndex, dir: &str, levels: usize) {
+    let checkpoint = Path::new(dir).join(INDEX_FILE);
+    if std::fs::read_to_string(&checkpoint)
+        .map(|data| index.load_checkpoint(&data, levels))
+        .unwrap_or_default()
+    {
+        return;
+    }
+    for entry in WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let Some(name) = entry
+            .path()
+            .strip_prefix(dir)
+            .ok()
+            .and_then(|relative| relative_to_name(relative, levels))
+        else {
+            continue;
+        };
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        let accessed = metadata
+            .accessed()
+            .or_else(|_| metadata.modified())
+            .ok()
+            .and_then(|value| value.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map(|value| value.as_secs() as u32)
+            .unwrap_or_default();
+        index.insert(name, metadata.len() as u32, accessed);
+    }
+}
+
+/// Create a file cache and use tinyufo for hotspot data caching,
+/// the cache files are sharded by the hash of key.
 pub fn new_file_cache(dir: &str) -> Result<FileCache> {
     let params = parse_params(dir);
 
@@ -85,11 +349,27 @@ pub fn new_file_cache(dir: &str) -> Result<FileCache> {
     if !path.exists() {
         std::fs::create_dir_all(path).map_err(|e| Error::Io { source: e })?;
     }
+    // walking the cache directory may be slow, so the index is loaded
+    // in background and the cache can be used at once
+    let index = Arc::new(FileIndex::default());
+    let loading = index.clone();
+    let dir = params.directory.clone();
+    let levels = params.levels;
+    std::thread::Builder::new()
+        .name("pingap-file-cache-index".to_string())
+        .spawn(move || {
+            load_index(&loading, &dir, levels);
+            loading.loaded.store(true, Ordering::Release);
+            info!(dir, entries = loading.len(), "load file cache index");
+        })
+        .map_err(|e| Error::Io { source: e })?;
     info!(
         dir = params.directory,
         reading_max = params.reading_max,
         writing_max = params.writing_max,
         cache_max = params.cache_max,
+        levels = params.levels,
+        max_size = ByteSize::b(params.max_size).to_string(),
         "new file cache"
     );
     let mut cache = None;
@@ -102,18 +382,74 @@ pub fn new_file_cache(dir: &str) -> Result<FileCache> {
         directory: params.directory,
         reading: AtomicU32::new(0),
         reading_max: params.reading_max,
-        #[cfg(feature = "full")]
+        #[cfg(feature = "metrics")]
         read_time: CACHE_READING_TIME.clone(),
         writing: AtomicU32::new(0),
         writing_max: params.writing_max,
-        #[cfg(feature = "full")]
+        #[cfg(feature = "metrics")]
         write_time: CACHE_WRITING_TIME.clone(),
         cache,
+        levels: params.levels,
+        max_size: params.max_size,
+        index,
     })
 }
 
+impl FileCache {
+    /// Get the file path of cache, e.g. `dir/namespace/a1/b2/key`.
+    fn get_file(&self, key: &str, namespace: &str) -> PathBuf {
+        let mut file = PathBuf::from(&self.directory);
+        if !namespace.is_empty() {
+            file.push(namespace);
+        }
+        if self.levels > 0 {
+            file.push(get_shard(key, self.levels));
+        }
+        file.push(key);
+        file
+    }
+    /// Get the record file of tag, the name of it is the hash of tag.
+    fn get_tag_file(&self, tag: &str) -> PathBuf {
+        Path::new(&self.directory)
+            .join(TAGS_DIR)
+            .join(hex::encode(Sha256::digest(tag.as_bytes())))
+    }
+    /// Remove the least recently accessed cache files
+    /// if the size of them is over the limit.
+    async fn evict(&self) {
+        if self.max_size == 0 {
+            return;
+        }
+        // evict to 90% of max size, avoid evicting on each writing
+        let names = self.index.evict(self.max_size / 10 * 9);
+        for name in names.iter() {
+            let (namespace, key) = name.rsplit_once('/').unwrap_or(("", name));
+            if let Some(c) = &self.cache {
+                c.remove(&key.to_string());
+            }
+            let file = self.get_file(key, namespace);
+            if let Err(e) = fs::remove_file(&file).await {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    error!(
+                        err = e.to_string(),
+                        file = file.to_string_lossy().to_string(),
+                        "remove cache file fail"
+                    );
+                }
+            }
+        }
+        if !names.is_empty() {
+            debug!(count = names.len(), "evict cache files");
+        }
+    }
+}
+
 #[async_trait]
 impl HttpCacheStorage for FileCache {
+    #[inline]
+    fn category(&self) -> &'static str {
+        "file"
+    }
     /// Get cache object from tinyufo,
     /// if not exists, then get from the file.
     async fn get(
@@ -122,18 +458,16 @@ impl HttpCacheStorage for FileCache {
         namespace: &str,
     ) -> Result<Option<CacheObject>> {
         debug!(key, namespace, "get cache from file");
+        let name = get_index_name(key, namespace);
         if let Some(Some(obj)) =
             self.cache.as_ref().map(|c| c.get(&key.to_string()))
         {
+            self.index.touch(&name);
             return Ok(Some(obj));
         }
-        #[cfg(feature = "full")]
+        #[cfg(feature = "metrics")]
         let start = SystemTime::now();
-        let file = if namespace.is_empty() {
-            Path::new(&self.directory).join(key)
-        } else {
-            Path::new(&self.directory).join(format!("{namespace}/{key}"))
-        };
+        let file = self.get_file(key, namespace);
         // add reading count
         let count = self.reading.fetch_add(1, Ordering::Relaxed);
         defer!(self.reading.fetch_sub(1, Ordering::Relaxed););
@@ -144,7 +478,7 @@ impl HttpCacheStorage for FileCache {
             });
         }
         let result = fs::read(file).await;
-        #[cfg(feature = "full")]
+        #[cfg(feature = "metrics")]
         self.read_time.observe(util::elapsed_second(start));
         let buf = match result {
             Ok(buf) => Ok(buf),
@@ -157,8 +491,10 @@ impl HttpCacheStorage for FileCache {
             },
         }?;
         if buf.len() < 8 {
+            self.index.remove(&name);
             Ok(None)
         } else {
+            self.index.touch_or_insert(&name, buf.len() as u32);
             Ok(Some(CacheObject::from(Bytes::from(buf))))
         }
     }
@@ -174,14 +510,11 @@ impl HttpCacheStorage for FileCache {
         if let Some(c) = &self.cache {
             c.put(key.to_string(), data.clone(), weight);
         }
-        #[cfg(feature = "full")]
+        #[cfg(feature = "metrics")]
         let start = SystemTime::now();
         let buf: Bytes = data.into();
-        let file = if namespace.is_empty() {
-            Path::new(&self.directory).join(key)
-        } else {
-            Path::new(&self.directory).join(format!("{namespace}/{key}"))
-        };
+        let size = buf.len() as u32;
+        let file = self.get_file(key, namespace);
         // add writing count
         let count = self.writing.fetch_add(1, Ordering::Relaxed);
         defer!(self.writing.fetch_sub(1, Ordering::Relaxed););
@@ -191,10 +524,19 @@ impl HttpCacheStorage for FileCache {
                 message: "too many writing".to_string(),
             });
         }
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir)
+                .await
+                .map_err(|e| Error::Io { source: e })?;
+        }
         let result = fs::write(file, buf).await;
-        #[cfg(feature = "full")]
+        #[cfg(feature = "metrics")]
         self.write_time.observe(util::elapsed_second(start));
-        result.map_err(|e| Error::Io { source: e })
+        result.map_err(|e| Error::Io { source: e })?;
+        self.index
+            .insert(get_index_name(key, namespace), size, now_seconds());
+        self.evict().await;
+        Ok(())
     }
     /// Remove cache object from file, tinyufo doesn't support remove now.
     async fn remove(
@@ -206,15 +548,103 @@ impl HttpCacheStorage for FileCache {
         if let Some(c) = &self.cache {
             c.remove(&key.to_string());
         }
-        let file = if namespace.is_empty() {
-            Path::new(&self.directory).join(key)
-        } else {
-            Path::new(&self.directory).join(format!("{namespace}/{key}"))
+        self.index.remove(&get_index_name(key, namespace));
+        let file = self.get_file(key, namespace);
+        if let Err(e) = fs::remove_file(file).await {
+            // the file may be removed by clear task
+            if e.kind() != std::io::ErrorKind::NotFound {
+                return Err(Error::Io { source: e });
+            }
+        }
+        Ok(None)
+    }
+    /// Append the key and namespace to the record files of tags.
+    async fn add_tags(
+        &self,
+        key: &str,
+        namespace: &str,
+        tags: &[String],
+    ) -> Result<()> {
+        let line = format!("{namespace}\t{key}\n");
+        for tag in tags.iter() {
+            let file = self.get_tag_file(tag);
+            if let Some(dir) = file.parent() {
+                fs::create_dir_all(dir)
+                    .await
+                    .map_err(|e| Error::Io { source: e })?;
+            }
+            let mut f = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(file)
+                .await
+                .map_err(|e| Error::Io { source: e })?;
+            f.write_all(line.as_bytes())
+                .await
+                .map_err(|e| Error::Io { source: e })?;
+        }
+        Ok(())
+    }
+    /// Read the record file of tag and remove it.
+    async fn take_tag(&self, tag: &str) -> Result<Vec<(String, String)>> {
+        let file = self.get_tag_file(tag);
+        let data = match fs::read_to_string(&file).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(vec![]);
+            },
+            Err(e) => return Err(Error::Io { source: e }),
         };
-        fs::remove_file(file)
+        let _ = fs::remove_file(&file).await;
+        let mut items = vec![];
+        for line in data.lines() {
+            let Some((namespace, key)) = line.split_once('\t') else {
+                continue;
+            };
+            let item = (key.to_string(), namespace.to_string());
+            if !items.contains(&item) {
+                items.push(item);
+            }
+        }
+        Ok(items)
+    }
+    /// Remove the directory of namespace, the count of indexed files
+    /// is returned.
+    async fn clear_namespace(&self, namespace: &str) -> Result<i32> {
+        if namespace.is_empty()
+            || namespace.starts_with('/')
+            || namespace.split('/').any(|item| item == "..")
+        {
+            return Err(Error::Invalid {
+                message: format!("namespace({namespace}) is invalid"),
+            });
+        }
+        let count = self.index.remove_prefix(&format!("{namespace}/"));
+        let dir = Path::new(&self.directory).join(namespace);
+        if let Err(e) = fs::remove_dir_all(dir).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                return Err(Error::Io { source: e });
+            }
+        }
+        Ok(count as i32)
+    }
+    /// Save the index to checkpoint file, it's written to a temp file
+    /// first, so the checkpoint won't be broken if pingap exits.
+    /// The index which is still loading is not saved.
+    async fn checkpoint(&self) -> Result<bool> {
+        if !self.index.loaded.load(Ordering::Acquire) {
+            return Ok(false);
+        }
+        let data = self.index.to_checkpoint(self.levels);
+        let file = Path::new(&self.directory).join(INDEX_FILE);
+        let tmp = Path::new(&self.directory).join(format!("{INDEX_FILE}.tmp"));
+        fs::write(&tmp, data)
             .await
             .map_err(|e| Error::Io { source: e })?;
-        Ok(None)
+        fs::rename(&tmp, &file)
+            .await
+            .map_err(|e| Error::Io { source: e })?;
+        Ok(true)
     }
     /// Get the stats of file cache
     #[inline]
@@ -246,6 +676,16 @@ impl HttpCacheStorage for FileCache {
             match fs::remove_file(entry.path()).await {
                 Ok(()) => {
                     success += 1;
+                    if let Some(name) = entry
+                        .path()
+                        .strip_prefix(&self.directory)
+                        .ok()
+                        .and_then(|relative| {
+                            relative_to_name(relative, self.levels)
+                        })
+                    {
+                        self.index.remove(&name);
+                    }
                 },
                 Err(e) => {
                     fail += 1;
@@ -263,13 +703,20 @@ impl HttpCacheStorage for FileCache {
 
 #[cfg(test)]
 mod tests {
-    use super::{new_file_cache, parse_params};
+    use super::{get_shard, new_file_cache, parse_params, FileCache};
     use crate::cache::http_cache::{CacheObject, HttpCacheStorage};
     use bytes::Bytes;
     use pretty_assertions::assert_eq;
+    use std::sync::atomic::Ordering;
     use std::time::{Duration, SystemTime};
     use tempfile::TempDir;
 
+    async fn wait_index_loaded(cache: &FileCache) {
+        while !cache.index.loaded.load(Ordering::Acquire) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
     #[test]
     fn test_parse_params() {
         let params = parse_params(
@@ -278,6 +725,107 @@ mod tests {
         assert_eq!(1000, params.reading_max);
         assert_eq!(500, params.writing_max);
         assert_eq!(100, params.cache_max);
+        assert_eq!(0, params.levels);
+        assert_eq!(0, params.max_size);
+
+        let params = parse_params("~/pingap?levels=1&max_size=1mb");
+        assert_eq!(1, params.levels);
+        assert_eq!(1000 * 1000, params.max_size);
+    }
+
+    #[test]
+    fn test_get_shard() {
+        assert_eq!("", get_shard("key", 0));
+        assert_eq!(2, get_shard("key", 1).len());
+        assert_eq!(5, get_shard("key", 2).len());
+        assert_eq!(get_shard("key", 2), get_shard("key", 2));
+    }
+
+    #[tokio::test]
+    async fn test_file_cache_index() {
+        let dir = TempDir::new().unwrap();
+        let dir = dir.into_path().to_string_lossy().to_string();
+        let obj = CacheObject {
+            meta: (b"Hello".to_vec(), b"World".to_vec()),
+            body: Bytes::from(vec![0; 100]),
+            ..Default::default()
+        };
+        let cache =
+            new_file_cache(&format!("{dir}?cache_max=0&max_size=300&levels=2"))
+                .unwrap();
+        wait_index_loaded(&cache).await;
+        cache.put("a", "ns", obj.clone(), 1).await.unwrap();
+        cache.put("b", "", obj.clone(), 1).await.unwrap();
+        assert_eq!(2, cache.index.len());
+        let file = cache.get_file("a", "ns");
+        assert_eq!(true, file.exists());
+        assert_eq!(
+            format!("{dir}/ns/{}/a", get_shard("a", 2)),
+            file.to_string_lossy()
+        );
+
+        // the index is rebuilt from directory
+        let cache =
+            new_file_cache(&format!("{dir}?cache_max=0&max_size=300&levels=2"))
+                .unwrap();
+        wait_index_loaded(&cache).await;
+        assert_eq!(2, cache.index.len());
+        assert_eq!(obj, cache.get("a", "ns").await.unwrap().unwrap());
+
+        // over the max size, the oldest one is evicted
+        cache.put("c", "", obj.clone(), 1).await.unwrap();
+        assert_eq!(true, cache.index.len() < 3);
+        assert_eq!(true, cache.get("c", "").await.unwrap().is_some());
+
+        // the tags are recorded to file
+        let tags = vec!["product-1".to_string(), "product-2".to_string()];
+        cache.add_tags("c", "", &tags).await.unwrap();
+        cache.add_tags("a", "ns", &tags[..1]).await.unwrap();
+        assert_eq!(2, cache.index.len());
+        assert_eq!(
+            vec![
+                ("c".to_string(), "".to_string()),
+                ("a".to_string(), "ns".to_string())
+            ],
+            cache.take_tag("product-1").await.unwrap()
+        );
+        assert_eq!(true, cache.take_tag("product-1").await.unwrap().is_empty());
+
+        // clear the namespace
+        assert_eq!(1, cache.clear_namespace("ns").await.unwrap());
+        assert_eq!(true, cache.get("a", "ns").await.unwrap().is_none());
+        assert_eq!(true, cache.clear_namespace("../ns").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_file_cache_checkpoint() {
+        let dir = TempDir::new().unwrap();
+        let dir = dir.into_path().to_string_lossy().to_string();
+        let obj = CacheObject {
+            meta: (b"Hello".to_vec(), b"World".to_vec()),
+            body: Bytes::from_static(b"Hello World!"),
+            ..Default::default()
+        };
+        let cache = new_file_cache(&format!("{dir}?cache_max=0")).unwrap();
+        wait_index_loaded(&cache).await;
+        cache.put("a", "ns", obj.clone(), 1).await.unwrap();
+        assert_eq!(true, cache.checkpoint().await.unwrap());
+        // written after checkpoint
+        cache.put("b", "", obj.clone(), 1).await.unwrap();
+
+        // the index is loaded from checkpoint
+        let cache = new_file_cache(&format!("{dir}?cache_max=0")).unwrap();
+        wait_index_loaded(&cache).await;
+        assert_eq!(1, cache.index.len());
+        // the file is added to index when it's read
+        assert_eq!(obj, cache.get("b", "").await.unwrap().unwrap());
+        assert_eq!(2, cache.index.len());
+
+        // the checkpoint is ignored if levels is changed
+        let cache =
+            new_file_cache(&format!("{dir}?cache_max=0&levels=1")).unwrap();
+        wait_index_loaded(&cache).await;
+        assert_eq!(0, cache.index.len());
     }
 
     #[tokio::test]
@@ -289,6 +837,7 @@ mod tests {
         let obj = CacheObject {
             meta: (b"Hello".to_vec(), b"World".to_vec()),
             body: Bytes::from_static(b"Hello World!"),
+            ..Default::default()
         };
         let result = cache.get(key, "").await.unwrap();
         assert_eq!(true, result.is_none());
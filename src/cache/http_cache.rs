@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use super::keys::{CacheKeyInfo, CacheKeyList, CacheKeys};
 use super::{file, Error, Result, PAGE_SIZE};
 use crate::config::get_current_config;
 use crate::service::SimpleServiceTaskFuture;
@@ -24,7 +25,10 @@ use pingora::cache::trace::SpanHandle;
 use pingora::cache::{
     CacheKey, CacheMeta, HitHandler, MissHandler, PurgeType, Storage,
 };
+use serde::Serialize;
 use std::any::Any;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 use tracing::info;
@@ -35,9 +39,70 @@ type BinaryMeta = (Vec<u8>, Vec<u8>);
 pub struct CacheObject {
     pub meta: BinaryMeta,
     pub body: Bytes,
+    // the body is compressed by zstd
+    pub compressed: bool,
 }
 
 const META_SIZE_LENGTH: usize = 8;
+// the highest bit of meta size is used as compressed flag
+const COMPRESSED_FLAG: u32 = 1 << 31;
+// the min size of body to compress
+const MIN_COMPRESS_SIZE: usize = 1024;
+// the user tag of cache key, the compressed body is passed through
+// to the client which accepts zstd
+pub const ACCEPT_ZSTD_TAG: &str = "zstd";
+
+/// Check whether the client accepts zstd by `Accept-Encoding`,
+/// the range request isn't accepted because the range is for
+/// the identity body.
+pub fn is_zstd_accepted(headers: &http::HeaderMap) -> bool {
+    if headers.contains_key(http::header::RANGE) {
+        return false;
+    }
+    let Some(value) = headers
+        .get(http::header::ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+    value.split(',').any(|item| {
+        let mut arr = item.split(';').map(|item| item.trim());
+        if !arr.next().unwrap_or_default().eq_ignore_ascii_case("zstd") {
+            return false;
+        }
+        // zstd;q=0 means not acceptable
+        arr.filter_map(|item| item.strip_prefix("q="))
+            .all(|q| q.parse::<f32>().unwrap_or_default() > 0.0)
+    })
+}
+
+impl CacheObject {
+    /// Compress the body by zstd, it's only compressed when
+    /// the compressed data is smaller.
+    fn compress(&mut self, level: i32) -> Result<()> {
+        if self.compressed || self.body.len() < MIN_COMPRESS_SIZE {
+            return Ok(());
+        }
+        let data = zstd::bulk::compress(&self.body, level)
+            .map_err(|e| Error::Io { source: e })?;
+        if data.len() < self.body.len() {
+            self.body = data.into();
+            self.compressed = true;
+        }
+        Ok(())
+    }
+    /// Decompress the body if it's compressed.
+    fn decompress(&mut self) -> Result<()> {
+        if !self.compressed {
+            return Ok(());
+        }
+        let data = zstd::stream::decode_all(self.body.as_ref())
+            .map_err(|e| Error::Io { source: e })?;
+        self.body = data.into();
+        self.compressed = false;
+        Ok(())
+    }
+}
 
 /// Create a cache object from bytes.
 impl From<Bytes> for CacheObject {
@@ -49,8 +114,10 @@ impl From<Bytes> for CacheObject {
         }
         let mut data = value;
 
-        let meta0_size = data.get_u32() as usize;
+        let meta0_size = data.get_u32();
         let meta1_size = data.get_u32() as usize;
+        let compressed = meta0_size & COMPRESSED_FLAG != 0;
+        let meta0_size = (meta0_size & !COMPRESSED_FLAG) as usize;
 
         let meta0 = data.split_to(meta0_size).to_vec();
         let meta1 = data.split_to(meta1_size).to_vec();
@@ -58,6 +125,7 @@ impl From<Bytes> for CacheObject {
         Self {
             meta: (meta0, meta1),
             body: data,
+            compressed,
         }
     }
 }
@@ -67,7 +135,10 @@ impl From<CacheObject> for Bytes {
         let meta_size =
             value.meta.0.len() + value.meta.1.len() + META_SIZE_LENGTH;
         let mut buf = BytesMut::with_capacity(value.body.len() + meta_size);
-        let meta0_size = value.meta.0.len() as u32;
+        let mut meta0_size = value.meta.0.len() as u32;
+        if value.compressed {
+            meta0_size |= COMPRESSED_FLAG;
+        }
         let meta1_size = value.meta.1.len() as u32;
         buf.put_u32(meta0_size);
         buf.put_u32(meta1_size);
@@ -79,6 +150,35 @@ impl From<CacheObject> for Bytes {
     }
 }
 
+#[cfg(any(feature = "redis", feature = "memcached"))]
+/// Get the ttl of cache object from the meta of cache, it's the fresh
+/// time and the stale time of cache. The default ttl is used if the meta
+/// can't be parsed, and the ttl is limited by max ttl.
+pub(super) fn get_object_ttl(
+    data: &CacheObject,
+    default_ttl: Duration,
+    max_ttl: Option<Duration>,
+) -> Duration {
+    let ttl =
+        if let Ok(meta) = CacheMeta::deserialize(&data.meta.0, &data.meta.1) {
+            let stale = meta
+                .serve_stale_while_revalidate_sec()
+                .max(meta.serve_stale_if_error_sec());
+            meta.fresh_until()
+                .duration_since(SystemTime::now())
+                .unwrap_or_default()
+                + Duration::from_secs(stale as u64)
+        } else {
+            default_ttl
+        };
+    let ttl = if let Some(max_ttl) = max_ttl {
+        ttl.min(max_ttl)
+    } else {
+        ttl
+    };
+    ttl.max(Duration::from_secs(1))
+}
+
 #[derive(Debug)]
 pub struct HttpCacheStats {
     pub reading: u32,
@@ -87,6 +187,10 @@ pub struct HttpCacheStats {
 
 #[async_trait]
 pub trait HttpCacheStorage: Sync + Send {
+    // the category of storage, e.g. memory, file or redis
+    fn category(&self) -> &'static str {
+        "memory"
+    }
     // get cache object from storage
     async fn get(
         &self,
@@ -115,6 +219,60 @@ pub trait HttpCacheStorage: Sync + Send {
     ) -> Result<(i32, i32)> {
         Ok((-1, -1))
     }
+    // clear all objects of namespace, -1 means not supported
+    async fn clear_namespace(&self, _namespace: &str) -> Result<i32> {
+        Ok(-1)
+    }
+    // record the tags of object, they are used to purge by tag
+    async fn add_tags(
+        &self,
+        _key: &str,
+        _namespace: &str,
+        _tags: &[String],
+    ) -> Result<()> {
+        Ok(())
+    }
+    // get the key and namespace of objects which have the tag,
+    // and the records of tag are removed
+    async fn take_tag(&self, _tag: &str) -> Result<Vec<(String, String)>> {
+        Ok(vec![])
+    }
+    // save the index of storage, it's used to warm restart,
+    // false means not supported
+    async fn checkpoint(&self) -> Result<bool> {
+        Ok(false)
+    }
+    // refresh the recency of object and update its weight if it's set,
+    // false means the object is not found or not supported
+    async fn touch(
+        &self,
+        _key: &str,
+        _namespace: &str,
+        _weight: Option<u16>,
+    ) -> Result<bool> {
+        Ok(false)
+    }
+    // extend the fresh time of object from now, it's saved with the
+    // updated meta, false means the object is not found
+    async fn extend_ttl(
+        &self,
+        key: &str,
+        namespace: &str,
+        ttl: Duration,
+    ) -> Result<bool> {
+        let Some(mut obj) = self.get(key, namespace).await? else {
+            return Ok(false);
+        };
+        obj.meta = extend_meta_ttl(&obj, ttl)?;
+        let weight = get_wegiht(obj.body.len());
+        self.put(key, namespace, obj, weight).await?;
+        Ok(true)
+    }
+    // get the count and bytes of objects in storage,
+    // none means not supported
+    fn size(&self) -> Option<(usize, usize)> {
+        None
+    }
     // get reading and writing stats of storage
     fn stats(&self) -> Option<HttpCacheStats> {
         None
@@ -153,6 +311,13 @@ async fn do_file_storage_clear(
 pub fn new_file_storage_clear_service(
 ) -> Option<(String, SimpleServiceTaskFuture)> {
     let dir = get_current_config().basic.cache_directory.as_ref()?.clone();
+    // the redis, memcached and s3 cache don't need to be cleared by pingap
+    if super::is_redis_url(&dir)
+        || super::is_memcached_url(&dir)
+        || super::is_s3_url(&dir)
+    {
+        return None;
+    }
     let task: SimpleServiceTaskFuture = Box::new(move |count: u32| {
         Box::pin({
             let value = dir.clone();
@@ -165,9 +330,82 @@ pub fn new_file_storage_clear_service(
     Some(("cacheStorageClear".to_string(), task))
 }
 
+/// The hit, miss and eviction counts of http cache.
+#[derive(Default)]
+pub(crate) struct HttpCacheCounter {
+    hit: AtomicU64,
+    miss: AtomicU64,
+    eviction: AtomicU64,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct HttpCacheSummary {
+    pub backend: String,
+    // the count and bytes of cached objects
+    pub entries: usize,
+    pub bytes: usize,
+    pub hit: u64,
+    pub miss: u64,
+    pub eviction: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reading: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub writing: Option<u32>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CacheLookupDetail {
+    pub hash: String,
+    pub key: String,
+    pub namespace: String,
+    pub status: u16,
+    pub size: usize,
+    // the age of cache(seconds)
+    pub age: u64,
+    // the remaining fresh time of cache(seconds), 0 means stale
+    pub ttl: u64,
+    // the request headers which the variants of cache are varied by
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub vary: Vec<String>,
+    pub headers: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CacheKeyDetail {
+    #[serde(flatten)]
+    pub info: CacheKeyInfo,
+    pub status: u16,
+    pub headers: BTreeMap<String, String>,
+    // the expired time of cache(seconds)
+    pub fresh_until: u64,
+}
+
 pub struct HttpCache {
     pub directory: Option<String>,
     pub(crate) cached: Arc<dyn HttpCacheStorage>,
+    pub(crate) keys: Arc<CacheKeys>,
+    pub(crate) counter: Arc<HttpCacheCounter>,
+    // the zstd level of cache object, 0 means not compressed
+    pub(crate) compression_level: i32,
+}
+
+fn get_meta_headers(meta: &CacheMeta) -> BTreeMap<String, String> {
+    let mut headers = BTreeMap::new();
+    for (name, value) in meta.headers().iter() {
+        headers.insert(
+            name.to_string(),
+            value.to_str().unwrap_or_default().to_string(),
+        );
+    }
+    headers
+}
+
+fn deserialize_meta(obj: &CacheObject) -> Result<CacheMeta> {
+    CacheMeta::deserialize(&obj.meta.0, &obj.meta.1).map_err(|e| {
+        Error::Invalid {
+            message: e.to_string(),
+        }
+    })
 }
 
 impl HttpCache {
@@ -175,6 +413,194 @@ impl HttpCache {
     pub fn stats(&self) -> Option<HttpCacheStats> {
         self.cached.stats()
     }
+    /// Get the summary of cache, the entries and bytes are
+    /// counted by the indexed keys.
+    pub fn summary(&self) -> HttpCacheSummary {
+        // the size of storage is more accurate than the index
+        let (entries, bytes) =
+            self.cached.size().unwrap_or_else(|| self.keys.summary());
+        let stats = self.cached.stats();
+        HttpCacheSummary {
+            backend: self.backend().to_string(),
+            entries,
+            bytes,
+            hit: self.counter.hit.load(Ordering::Relaxed),
+            miss: self.counter.miss.load(Ordering::Relaxed),
+            eviction: self.counter.eviction.load(Ordering::Relaxed),
+            reading: stats.as_ref().map(|item| item.reading),
+            writing: stats.as_ref().map(|item| item.writing),
+        }
+    }
+    /// Save the index of storage for warm restart.
+    #[inline]
+    pub async fn checkpoint(&self) -> Result<bool> {
+        self.cached.checkpoint().await
+    }
+    /// The category of storage backend, e.g. memory, file or redis.
+    #[inline]
+    pub fn backend(&self) -> &'static str {
+        self.cached.category()
+    }
+    /// List the cache keys which contain the pattern.
+    #[inline]
+    pub fn list_keys(
+        &self,
+        pattern: &str,
+        offset: usize,
+        limit: usize,
+    ) -> CacheKeyList {
+        self.keys.list(pattern, offset, limit)
+    }
+    /// Remove the cache by hash, the key is removed from index too.
+    pub async fn remove(&self, hash: &str, namespace: &str) -> Result<bool> {
+        self.keys.remove(hash);
+        let removed = self.cached.remove(hash, namespace).await?;
+        Ok(removed.is_some())
+    }
+    /// Refresh the cache and update its weight if it's set.
+    #[inline]
+    pub async fn touch(
+        &self,
+        hash: &str,
+        namespace: &str,
+        weight: Option<u16>,
+    ) -> Result<bool> {
+        self.cached.touch(hash, namespace, weight).await
+    }
+    /// Extend the fresh time of cache from now.
+    #[inline]
+    pub async fn extend_ttl(
+        &self,
+        hash: &str,
+        namespace: &str,
+        ttl: Duration,
+    ) -> Result<bool> {
+        self.cached.extend_ttl(hash, namespace, ttl).await
+    }
+    /// Remove the caches whose key starts with the prefix,
+    /// only the indexed keys can be found, so the caches which are
+    /// evicted from index or added by other instances are not purged.
+    pub async fn remove_by_prefix(&self, prefix: &str) -> Result<usize> {
+        self.remove_all(self.keys.find_by_prefix(prefix)).await
+    }
+    /// Remove the cache by hash, the namespace is got from index.
+    pub async fn remove_by_hash(&self, hash: &str) -> Result<bool> {
+        let namespace = self
+            .keys
+            .get(hash)
+            .map(|info| info.namespace)
+            .unwrap_or_default();
+        self.remove(hash, &namespace).await
+    }
+    /// Remove the caches whose key matches the wildcard pattern,
+    /// only the indexed keys can be found(same as prefix).
+    pub async fn remove_by_pattern(&self, pattern: &str) -> Result<usize> {
+        let pattern =
+            glob::Pattern::new(pattern).map_err(|e| Error::Invalid {
+                message: e.to_string(),
+            })?;
+        self.remove_all(self.keys.find_by_pattern(&pattern)).await
+    }
+    /// Remove all caches of the namespace, the indexed keys are removed
+    /// first, then the storage clears the objects which are not indexed.
+    /// The storage which doesn't support clearing namespace only
+    /// removes the indexed keys.
+    pub async fn remove_by_namespace(&self, namespace: &str) -> Result<usize> {
+        let count = self
+            .remove_all(self.keys.find_by_namespace(namespace))
+            .await?;
+        let cleared = self.cached.clear_namespace(namespace).await?;
+        Ok(count.max(cleared.max(0) as usize))
+    }
+    /// Remove the caches which have the tag, the tags recorded by storage
+    /// are used too, so the caches can be purged after restart.
+    pub async fn remove_by_tag(&self, tag: &str) -> Result<usize> {
+        let mut items = self.keys.find_by_tag(tag);
+        for item in self.cached.take_tag(tag).await? {
+            if !items.contains(&item) {
+                items.push(item);
+            }
+        }
+        self.remove_all(items).await
+    }
+    async fn remove_all(&self, items: Vec<(String, String)>) -> Result<usize> {
+        let mut count = 0;
+        for (hash, namespace) in items.iter() {
+            if self.remove(hash, namespace).await? {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+    /// Get the detail of cache key, only the response headers
+    /// are returned(without body).
+    pub async fn inspect_key(
+        &self,
+        hash: &str,
+    ) -> Result<Option<CacheKeyDetail>> {
+        let Some(info) = self.keys.get(hash) else {
+            return Ok(None);
+        };
+        let Some(obj) = self.cached.get(hash, &info.namespace).await? else {
+            self.keys.remove(hash);
+            return Ok(None);
+        };
+        let meta = deserialize_meta(&obj)?;
+        let headers = get_meta_headers(&meta);
+        let fresh_until = meta
+            .fresh_until()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        Ok(Some(CacheKeyDetail {
+            info,
+            status: meta.response_header().status.as_u16(),
+            headers,
+            fresh_until,
+        }))
+    }
+    /// Look up the cache by the primary key and namespace,
+    /// it's found from storage even if the key isn't indexed.
+    pub async fn lookup_key(
+        &self,
+        key: &str,
+        namespace: &str,
+    ) -> Result<Option<CacheLookupDetail>> {
+        let hash = CacheKey::new(namespace, key, "").combined();
+        let Some(obj) = self.cached.get(&hash, namespace).await? else {
+            return Ok(None);
+        };
+        let meta = deserialize_meta(&obj)?;
+        let now = SystemTime::now();
+        let age = now
+            .duration_since(meta.created())
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let ttl = meta
+            .fresh_until()
+            .duration_since(now)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let vary = meta
+            .headers()
+            .get_all(http::header::VARY)
+            .iter()
+            .flat_map(|value| value.to_str().unwrap_or_default().split(','))
+            .map(|value| value.trim().to_lowercase())
+            .filter(|value| !value.is_empty())
+            .collect();
+        Ok(Some(CacheLookupDetail {
+            hash,
+            key: key.to_string(),
+            namespace: namespace.to_string(),
+            status: meta.response_header().status.as_u16(),
+            size: obj.body.len(),
+            age,
+            ttl,
+            vary,
+            headers: get_meta_headers(&meta),
+        }))
+    }
 }
 
 pub struct CompleteHit {
@@ -252,7 +678,13 @@ pub struct ObjectMissHandler {
     // these are used only in finish() to data from temp to cache
     key: String,
     namespace: String,
+    // the readable primary key, it's recorded to cache keys
+    primary_key: String,
+    tags: Vec<String>,
+    // the zstd level of body, 0 means not compressed
+    compression_level: i32,
     cache: Arc<dyn HttpCacheStorage>,
+    keys: Arc<CacheKeys>,
 }
 
 #[async_trait]
@@ -267,28 +699,131 @@ impl HandleMiss for ObjectMissHandler {
     }
 
     async fn finish(self: Box<Self>) -> pingora::Result<usize> {
-        let size = self.body.len(); // FIXME: this just body size, also track meta size
+        let mut obj = CacheObject {
+            meta: self.meta,
+            body: self.body.into(),
+            ..Default::default()
+        };
+        if self.compression_level > 0 {
+            let level = self.compression_level;
+            // compress in blocking thread to avoid blocking the runtime
+            obj =
+                tokio::task::spawn_blocking(move || -> Result<CacheObject> {
+                    obj.compress(level)?;
+                    Ok(obj)
+                })
+                .await
+                .map_err(|e| Error::Invalid {
+                    message: e.to_string(),
+                })??;
+        }
+        let size = obj.body.len(); // FIXME: this just body size, also track meta size
         let _ = self
             .cache
-            .put(
-                &self.key,
-                &self.namespace,
-                CacheObject {
-                    meta: self.meta,
-                    body: self.body.into(),
-                },
-                get_wegiht(size),
-            )
+            .put(&self.key, &self.namespace, obj, get_wegiht(size))
             .await?;
+        if !self.tags.is_empty() {
+            self.cache
+                .add_tags(&self.key, &self.namespace, &self.tags)
+                .await?;
+        }
+        self.keys.add(
+            &self.key,
+            &self.primary_key,
+            &self.namespace,
+            size,
+            self.tags,
+        );
 
         Ok(size)
     }
 }
 
+/// Check whether the body of response is compressible,
+/// the encoded response and binary content are not compressed.
+fn is_compressible(headers: &http::HeaderMap) -> bool {
+    if headers.contains_key(http::header::CONTENT_ENCODING) {
+        return false;
+    }
+    let Some(content_type) = headers
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+    let content_type = content_type.to_lowercase();
+    content_type.starts_with("text/")
+        || [
+            "json",
+            "javascript",
+            "xml",
+            "wasm",
+            "font/",
+            "x-www-form-urlencoded",
+        ]
+        .iter()
+        .any(|item| content_type.contains(item))
+}
+
+/// Get the tags of cache from `Cache-Tag`, `Surrogate-Key` and `xkey`
+/// headers, the tags are separated by comma or space.
+fn get_cache_tags(headers: &http::HeaderMap) -> Vec<String> {
+    let mut tags = vec![];
+    for name in ["cache-tag", "surrogate-key", "xkey"] {
+        for value in headers.get_all(name).iter() {
+            let value = value.to_str().unwrap_or_default();
+            for tag in value.split([',', ' ']) {
+                let tag = tag.trim();
+                if !tag.is_empty() && !tags.iter().any(|item| item == tag) {
+                    tags.push(tag.to_string());
+                }
+            }
+        }
+    }
+    tags
+}
+
+/// Rebuild the meta of cache with the new fresh time,
+/// the stale durations and response header are kept.
+fn extend_meta_ttl(
+    obj: &CacheObject,
+    ttl: Duration,
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    let meta = deserialize_meta(obj)?;
+    let now = SystemTime::now();
+    CacheMeta::new(
+        now + ttl,
+        meta.created(),
+        meta.stale_while_revalidate_sec(),
+        meta.stale_if_error_sec(),
+        meta.response_header().clone(),
+    )
+    .serialize()
+    .map_err(|e| Error::Invalid {
+        message: e.to_string(),
+    })
+}
+
+/// Create the meta of the zstd body which is passed through,
+/// the content length is the size of compressed body.
+fn new_zstd_meta(meta: &CacheMeta, size: usize) -> CacheMeta {
+    let mut header = meta.response_header().clone();
+    let _ = header.insert_header(http::header::CONTENT_ENCODING, "zstd");
+    let _ = header.insert_header(http::header::CONTENT_LENGTH, size);
+    let _ = header.append_header(http::header::VARY, "Accept-Encoding");
+    CacheMeta::new(
+        meta.fresh_until(),
+        meta.created(),
+        meta.stale_while_revalidate_sec(),
+        meta.stale_if_error_sec(),
+        header,
+    )
+}
+
 // 40MB
 static MAX_ONE_CACHE_SIZE: usize = 10 * 1024 * PAGE_SIZE;
 
-fn get_wegiht(size: usize) -> u16 {
+pub(super) fn get_wegiht(size: usize) -> u16 {
     if size <= PAGE_SIZE {
         return 1;
     }
@@ -307,9 +842,20 @@ impl Storage for HttpCache {
     ) -> pingora::Result<Option<(CacheMeta, HitHandler)>> {
         let namespace = key.namespace();
         let hash = key.combined();
-        if let Some(obj) = self.cached.get(&hash, namespace).await? {
-            let meta = CacheMeta::deserialize(&obj.meta.0, &obj.meta.1)?;
+        if let Some(mut obj) = self.cached.get(&hash, namespace).await? {
+            // the compressed body is passed through if the client accepts
+            // zstd, otherwise it's decoded
+            let passthrough = obj.compressed && key.user_tag == ACCEPT_ZSTD_TAG;
+            if !passthrough {
+                obj.decompress()?;
+            }
+            self.counter.hit.fetch_add(1, Ordering::Relaxed);
+            self.keys.hit(&hash);
             let size = obj.body.len();
+            let mut meta = CacheMeta::deserialize(&obj.meta.0, &obj.meta.1)?;
+            if passthrough {
+                meta = new_zstd_meta(&meta, size);
+            }
             let hit_handler = CompleteHit {
                 body: obj.body,
                 done: false,
@@ -318,6 +864,7 @@ impl Storage for HttpCache {
             };
             Ok(Some((meta, Box::new(hit_handler))))
         } else {
+            self.counter.miss.fetch_add(1, Ordering::Relaxed);
             Ok(None)
         }
     }
@@ -342,12 +889,22 @@ impl Storage for HttpCache {
             capacity
         };
         let hash = key.combined();
+        let tags = get_cache_tags(meta.headers());
+        let compression_level = if is_compressible(meta.headers()) {
+            self.compression_level
+        } else {
+            0
+        };
         let meta = meta.serialize()?;
         let miss_handler = ObjectMissHandler {
             meta,
             key: hash,
             namespace: key.namespace().to_string(),
+            primary_key: key.primary_key().to_string(),
+            tags,
+            compression_level,
             cache: self.cached.clone(),
+            keys: self.keys.clone(),
             body: BytesMut::with_capacity(size),
         };
         Ok(Box::new(miss_handler))
@@ -356,12 +913,16 @@ impl Storage for HttpCache {
     async fn purge(
         &'static self,
         key: &CompactCacheKey,
-        _type: PurgeType,
+        purge_type: PurgeType,
         _trace: &SpanHandle,
     ) -> pingora::Result<bool> {
+        if matches!(purge_type, PurgeType::Eviction) {
+            self.counter.eviction.fetch_add(1, Ordering::Relaxed);
+        }
         // This usually purges the primary key because, without a lookup,
         // the variance key is usually empty
         let hash = key.combined();
+        self.keys.remove(&hash);
         // TODO get namespace of cache key
         let cache_removed =
             if let Ok(result) = self.cached.remove(&hash, "").await {
@@ -407,12 +968,20 @@ impl Storage for HttpCache {
 
 #[cfg(test)]
 mod tests {
-    use super::{CompleteHit, HttpCacheStorage, ObjectMissHandler};
+    use super::{
+        get_cache_tags, is_compressible, is_zstd_accepted, CacheObject,
+        CompleteHit, HttpCacheStorage, ObjectMissHandler,
+    };
+    use crate::cache::keys::CacheKeys;
     use crate::cache::tiny::new_tiny_ufo_cache;
     use bytes::{Bytes, BytesMut};
+    use pingora::cache::key::CacheHashKey;
     use pingora::cache::storage::{HitHandler, MissHandler};
+    use pingora::cache::{CacheKey, CacheMeta};
+    use pingora::http::ResponseHeader;
     use pretty_assertions::assert_eq;
     use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
 
     #[tokio::test]
     async fn test_complete_hit() {
@@ -440,12 +1009,17 @@ mod tests {
         let key = "key";
 
         let cache = Arc::new(new_tiny_ufo_cache(10, 10));
+        let keys = Arc::new(CacheKeys::default());
         let obj = ObjectMissHandler {
             meta: (b"Hello".to_vec(), b"World".to_vec()),
             body: BytesMut::new(),
             key: key.to_string(),
             namespace: "".to_string(),
+            primary_key: "GET:/".to_string(),
+            tags: vec!["home".to_string()],
+            compression_level: 0,
             cache: cache.clone(),
+            keys: keys.clone(),
         };
         let mut handle: MissHandler = Box::new(obj);
 
@@ -457,5 +1031,123 @@ mod tests {
 
         let data = cache.get(key, "").await.unwrap().unwrap();
         assert_eq!("Hello World!", std::str::from_utf8(&data.body).unwrap());
+        assert_eq!("GET:/", keys.get(key).unwrap().key);
+        assert_eq!(vec!["home".to_string()], keys.get(key).unwrap().tags);
+    }
+
+    #[tokio::test]
+    async fn test_lookup_key() {
+        let cache = crate::cache::new_tiny_ufo_cache(1024 * 1024);
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header("Vary", "Accept-Encoding").unwrap();
+        let now = SystemTime::now();
+        let meta =
+            CacheMeta::new(now + Duration::from_secs(60), now, 0, 0, resp);
+        let key = "GET:/pingap";
+        let hash = CacheKey::new("", key, "").combined();
+        cache
+            .cached
+            .put(
+                &hash,
+                "",
+                CacheObject {
+                    meta: meta.serialize().unwrap(),
+                    body: Bytes::from_static(b"Hello World!"),
+                    ..Default::default()
+                },
+                1,
+            )
+            .await
+            .unwrap();
+        let detail = cache.lookup_key(key, "").await.unwrap().unwrap();
+        assert_eq!(hash, detail.hash);
+        assert_eq!(200, detail.status);
+        assert_eq!(12, detail.size);
+        assert_eq!(true, detail.ttl > 50);
+        assert_eq!(vec!["accept-encoding".to_string()], detail.vary);
+        assert_eq!(
+            true,
+            cache.lookup_key("GET:/", "").await.unwrap().is_none()
+        );
+
+        let summary = cache.summary();
+        assert_eq!("memory", summary.backend);
+        assert_eq!(0, summary.hit);
+    }
+
+    #[test]
+    fn test_cache_object_compress() {
+        let body = Bytes::from("Hello World!".repeat(100));
+        let mut obj = CacheObject {
+            meta: (b"Hello".to_vec(), b"World".to_vec()),
+            body: body.clone(),
+            ..Default::default()
+        };
+        obj.compress(3).unwrap();
+        assert_eq!(true, obj.compressed);
+        assert_eq!(true, obj.body.len() < body.len());
+
+        // the compressed flag is saved
+        let data: Bytes = obj.into();
+        let mut obj = CacheObject::from(data);
+        assert_eq!(true, obj.compressed);
+        assert_eq!(b"Hello".to_vec(), obj.meta.0);
+        // the compressed body is zstd, it can be passed through
+        assert_eq!(
+            body.to_vec(),
+            zstd::stream::decode_all(obj.body.as_ref()).unwrap_or_default()
+        );
+        assert_eq!(true, obj.decompress().is_ok());
+        assert_eq!(false, obj.compressed);
+        assert_eq!(body, obj.body);
+
+        // small body isn't compressed
+        let mut obj = CacheObject {
+            body: Bytes::from_static(b"Hello World!"),
+            ..Default::default()
+        };
+        obj.compress(3).unwrap();
+        assert_eq!(false, obj.compressed);
+
+        let mut headers = http::HeaderMap::new();
+        headers.insert("Content-Type", "application/json".parse().unwrap());
+        assert_eq!(true, is_compressible(&headers));
+        headers.insert("Content-Encoding", "gzip".parse().unwrap());
+        assert_eq!(false, is_compressible(&headers));
+        headers.remove("Content-Encoding");
+        headers.insert("Content-Type", "image/png".parse().unwrap());
+        assert_eq!(false, is_compressible(&headers));
+    }
+
+    #[test]
+    fn test_get_cache_tags() {
+        let mut headers = http::HeaderMap::new();
+        headers.insert("Cache-Tag", "user, book".parse().unwrap());
+        headers.insert("Surrogate-Key", "book home".parse().unwrap());
+        headers.insert("xkey", "product-1".parse().unwrap());
+        assert_eq!(
+            vec![
+                "user".to_string(),
+                "book".to_string(),
+                "home".to_string(),
+                "product-1".to_string()
+            ],
+            get_cache_tags(&headers)
+        );
+    }
+
+    #[test]
+    fn test_is_zstd_accepted() {
+        let mut headers = http::HeaderMap::new();
+        assert_eq!(false, is_zstd_accepted(&headers));
+        headers.insert("Accept-Encoding", "gzip, zstd".parse().unwrap());
+        assert_eq!(true, is_zstd_accepted(&headers));
+        headers.insert("Accept-Encoding", "ZSTD;q=0.5".parse().unwrap());
+        assert_eq!(true, is_zstd_accepted(&headers));
+        headers.insert("Accept-Encoding", "gzip, zstd;q=0".parse().unwrap());
+        assert_eq!(false, is_zstd_accepted(&headers));
+        headers.insert("Accept-Encoding", "zstd".parse().unwrap());
+        headers.insert("Range", "bytes=0-10".parse().unwrap());
+        assert_eq!(false, is_zstd_accepted(&headers));
     }
 }
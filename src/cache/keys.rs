@@ -0,0 +1,322 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::util;
+use ahash::RandomState;
+use lru::LruCache;
+use serde::Serialize;
+use std::hash::BuildHasher;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+
+// max count of the indexed cache keys
+const MAX_INDEXED_KEYS: usize = 100_000;
+// the index is sharded to reduce the contention of lock
+const SHARDS: usize = 16;
+
+struct CacheKeyEntry {
+    key: String,
+    namespace: String,
+    size: usize,
+    // the tags of cache, they are used to purge the related caches
+    tags: Vec<String>,
+    created_at: u64,
+    hits: AtomicU64,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CacheKeyInfo {
+    // the hash of cache key, it's used to inspect the cache
+    pub hash: String,
+    // the primary key of cache
+    pub key: String,
+    pub namespace: String,
+    pub size: usize,
+    // the age of cache(seconds)
+    pub age: u64,
+    pub hits: u64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct CacheKeyList {
+    pub total: usize,
+    pub items: Vec<CacheKeyInfo>,
+}
+
+type CacheKeyShard = RwLock<LruCache<String, CacheKeyEntry>>;
+
+/// The index of cache keys, the storage only saves the hash of key,
+/// so the readable key is recorded here for inspecting.
+/// Only the latest keys added by this instance are indexed, the oldest
+/// key of shard is evicted if it's full. The lookup doesn't change the
+/// index, so the key evicted by storage is kept until it's purged
+/// or evicted from index.
+pub struct CacheKeys {
+    state: RandomState,
+    shards: Vec<CacheKeyShard>,
+}
+
+impl Default for CacheKeys {
+    fn default() -> Self {
+        Self::new(MAX_INDEXED_KEYS)
+    }
+}
+
+impl CacheKeys {
+    /// Create the index with the max count of keys.
+    pub fn new(capacity: usize) -> Self {
+        let capacity = NonZeroUsize::new((capacity / SHARDS).max(1))
+            .unwrap_or(NonZeroUsize::MIN);
+        Self {
+            state: RandomState::new(),
+            shards: (0..SHARDS)
+                .map(|_| RwLock::new(LruCache::new(capacity)))
+                .collect(),
+        }
+    }
+    fn get_shard(&self, hash: &str) -> &CacheKeyShard {
+        let index = self.state.hash_one(hash) as usize % self.shards.len();
+        &self.shards[index]
+    }
+    /// Add the cache key to index, the oldest key of shard will be
+    /// removed if the shard is full.
+    pub fn add(
+        &self,
+        hash: &str,
+        key: &str,
+        namespace: &str,
+        size: usize,
+        tags: Vec<String>,
+    ) {
+        let mut shard = self
+            .get_shard(hash)
+            .write()
+            .unwrap_or_else(|e| e.into_inner());
+        shard.put(
+            hash.to_string(),
+            CacheKeyEntry {
+                key: key.to_string(),
+                namespace: namespace.to_string(),
+                size,
+                tags,
+                created_at: util::now().as_secs(),
+                hits: AtomicU64::new(0),
+            },
+        );
+    }
+    /// Increase the hit count of cache key,
+    /// only the read lock of shard is required.
+    pub fn hit(&self, hash: &str) {
+        let shard = self
+            .get_shard(hash)
+            .read()
+            .unwrap_or_else(|e| e.into_inner());
+        if let Some(entry) = shard.peek(hash) {
+            entry.hits.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    /// Remove the cache key from index.
+    pub fn remove(&self, hash: &str) {
+        let mut shard = self
+            .get_shard(hash)
+            .write()
+            .unwrap_or_else(|e| e.into_inner());
+        shard.pop(hash);
+    }
+    /// Get the info of cache key by hash.
+    pub fn get(&self, hash: &str) -> Option<CacheKeyInfo> {
+        let shard = self
+            .get_shard(hash)
+            .read()
+            .unwrap_or_else(|e| e.into_inner());
+        let now = util::now().as_secs();
+        shard
+            .peek(hash)
+            .map(|entry| new_cache_key_info(hash, entry, now))
+    }
+    /// Get the count and total size of indexed cache keys.
+    pub fn summary(&self) -> (usize, usize) {
+        let mut count = 0;
+        let mut size = 0;
+        for shard in self.shards.iter() {
+            let shard = shard.read().unwrap_or_else(|e| e.into_inner());
+            count += shard.len();
+            size += shard.iter().map(|(_, entry)| entry.size).sum::<usize>();
+        }
+        (count, size)
+    }
+    /// Find the hash and namespace of cache keys which start with the prefix.
+    pub fn find_by_prefix(&self, prefix: &str) -> Vec<(String, String)> {
+        self.find(|entry| entry.key.starts_with(prefix))
+    }
+    /// Find the hash and namespace of cache keys which have the tag.
+    pub fn find_by_tag(&self, tag: &str) -> Vec<(String, String)> {
+        self.find(|entry| entry.tags.iter().any(|item| item == tag))
+    }
+    /// Find the hash and namespace of cache keys which match the wildcard
+    /// pattern, e.g. `GET:/api/*/detail`.
+    pub fn find_by_pattern(
+        &self,
+        pattern: &glob::Pattern,
+    ) -> Vec<(String, String)> {
+        self.find(|entry| pattern.matches(&entry.key))
+    }
+    /// Find the hash and namespace of cache keys in the namespace.
+    pub fn find_by_namespace(&self, namespace: &str) -> Vec<(String, String)> {
+        self.find(|entry| entry.namespace == namespace)
+    }
+    fn find(
+        &self,
+        filter: impl Fn(&CacheKeyEntry) -> bool,
+    ) -> Vec<(String, String)> {
+        let mut items = vec![];
+        for shard in self.shards.iter() {
+            let shard = shard.read().unwrap_or_else(|e| e.into_inner());
+            items.extend(
+                shard.iter().filter(|(_, entry)| filter(entry)).map(
+                    |(hash, entry)| (hash.clone(), entry.namespace.clone()),
+                ),
+            );
+        }
+        items
+    }
+    /// List the cache keys which contain the pattern,
+    /// the result is sorted by key and paginated by offset and limit.
+    pub fn list(
+        &self,
+        pattern: &str,
+        offset: usize,
+        limit: usize,
+    ) -> CacheKeyList {
+        let now = util::now().as_secs();
+        let mut items = vec![];
+        for shard in self.shards.iter() {
+            let shard = shard.read().unwrap_or_else(|e| e.into_inner());
+            items.extend(
+                shard
+                    .iter()
+                    .filter(|(_, entry)| {
+                        pattern.is_empty() || entry.key.contains(pattern)
+                    })
+                    .map(|(hash, entry)| new_cache_key_info(hash, entry, now)),
+            );
+        }
+        items.sort_by(|a, b| a.key.cmp(&b.key));
+        let total = items.len();
+        let items = items.into_iter().skip(offset).take(limit).collect();
+        CacheKeyList { total, items }
+    }
+}
+
+fn new_cache_key_info(
+    hash: &str,
+    entry: &CacheKeyEntry,
+    now: u64,
+) -> CacheKeyInfo {
+    CacheKeyInfo {
+        hash: hash.to_string(),
+        key: entry.key.clone(),
+        namespace: entry.namespace.clone(),
+        size: entry.size,
+        age: now.saturating_sub(entry.created_at),
+        hits: entry.hits.load(Ordering::Relaxed),
+        tags: entry.tags.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CacheKeys;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_cache_keys() {
+        let keys = CacheKeys::default();
+        keys.add(
+            "hash1",
+            "GET:/api/users",
+            "pingap",
+            1024,
+            vec!["users".to_string()],
+        );
+        keys.add(
+            "hash2",
+            "GET:/api/books",
+            "",
+            512,
+            vec!["books".to_string(), "api".to_string()],
+        );
+        keys.add("hash3", "GET:/static/logo.png", "", 2048, vec![]);
+        keys.hit("hash1");
+        keys.hit("hash1");
+
+        let info = keys.get("hash1").unwrap();
+        assert_eq!("GET:/api/users", info.key);
+        assert_eq!("pingap", info.namespace);
+        assert_eq!(1024, info.size);
+        assert_eq!(2, info.hits);
+        assert_eq!((3, 3584), keys.summary());
+
+        let result = keys.list("/api/", 0, 10);
+        assert_eq!(2, result.total);
+        assert_eq!("GET:/api/books", result.items[0].key);
+
+        let result = keys.list("", 1, 1);
+        assert_eq!(3, result.total);
+        assert_eq!(1, result.items.len());
+        assert_eq!("GET:/api/users", result.items[0].key);
+
+        let mut result = keys.find_by_prefix("GET:/api/");
+        result.sort();
+        assert_eq!(
+            vec![
+                ("hash1".to_string(), "pingap".to_string()),
+                ("hash2".to_string(), "".to_string())
+            ],
+            result
+        );
+        assert_eq!(
+            vec![("hash2".to_string(), "".to_string())],
+            keys.find_by_tag("books")
+        );
+        assert_eq!(true, keys.find_by_tag("static").is_empty());
+        assert_eq!(
+            vec![("hash3".to_string(), "".to_string())],
+            keys.find_by_pattern(&glob::Pattern::new("GET:/*/*.png").unwrap())
+        );
+        assert_eq!(
+            vec![("hash1".to_string(), "pingap".to_string())],
+            keys.find_by_namespace("pingap")
+        );
+
+        keys.remove("hash1");
+        assert_eq!(true, keys.get("hash1").is_none());
+    }
+
+    #[test]
+    fn test_cache_keys_eviction() {
+        let keys = CacheKeys::new(32);
+        for i in 0..1000 {
+            keys.add(&format!("hash{i}"), &format!("GET:/{i}"), "", 1, vec![]);
+        }
+        let (count, size) = keys.summary();
+        assert_eq!(true, count <= 32);
+        assert_eq!(count, size);
+        // the latest key is kept
+        assert_eq!("GET:/999", keys.get("hash999").unwrap().key);
+    }
+}
@@ -0,0 +1,398 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::http_cache::{get_object_ttl, CacheObject, HttpCacheStorage};
+use super::{Error, Result};
+use async_trait::async_trait;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use humantime::parse_duration;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::{debug, info};
+
+// the count of virtual nodes of each server in hash ring
+const VIRTUAL_NODES: usize = 160;
+// the max ttl of relative expiration, it's unix time if greater than it
+const MAX_RELATIVE_TTL: u64 = 30 * 24 * 3600;
+const HEADER_SIZE: usize = 24;
+
+const MAGIC_REQUEST: u8 = 0x80;
+const MAGIC_RESPONSE: u8 = 0x81;
+const OPCODE_GET: u8 = 0x00;
+const OPCODE_SET: u8 = 0x01;
+const OPCODE_DELETE: u8 = 0x04;
+const STATUS_OK: u16 = 0x0000;
+const STATUS_KEY_NOT_FOUND: u16 = 0x0001;
+
+#[derive(Debug, PartialEq)]
+struct MemcachedCacheParams {
+    servers: Vec<String>,
+    prefix: String,
+    // the ttl of cache if its meta can't be parsed
+    ttl: Duration,
+    max_ttl: Option<Duration>,
+    timeout: Duration,
+    // max idle connections of each server
+    pool_size: usize,
+}
+
+/// Parse the params of memcached cache, e.g.
+/// `memcached://127.0.0.1:11211,127.0.0.1:11212?prefix=pingap:&ttl=1h`.
+fn parse_params(url: &str) -> Result<MemcachedCacheParams> {
+    let value = url.strip_prefix("memcached://").ok_or(Error::Invalid {
+        message: format!("invalid memcached url: {url}"),
+    })?;
+    let (hosts, query) = value.split_once('?').unwrap_or((value, ""));
+    let mut params = MemcachedCacheParams {
+        servers: hosts
+            .split(',')
+            .map(|host| host.trim())
+            .filter(|host| !host.is_empty())
+            .map(|host| {
+                if host.contains(':') {
+                    host.to_string()
+                } else {
+                    format!("{host}:11211")
+                }
+            })
+            .collect(),
+        prefix: "pingap:".to_string(),
+        ttl: Duration::from_secs(3600),
+        max_ttl: None,
+        timeout: Duration::from_secs(3),
+        pool_size: 10,
+    };
+    if params.servers.is_empty() {
+        return Err(Error::Invalid {
+            message: format!("servers of memcached are empty: {url}"),
+        });
+    }
+    for item in query.split('&').filter(|item| !item.is_empty()) {
+        let (key, value) = item.split_once('=').unwrap_or((item, ""));
+        let get_duration = || {
+            parse_duration(value).map_err(|e| Error::Invalid {
+                message: e.to_string(),
+            })
+        };
+        match key {
+            "prefix" => params.prefix = value.to_string(),
+            "ttl" => params.ttl = get_duration()?,
+            "max_ttl" => params.max_ttl = Some(get_duration()?),
+            "timeout" => params.timeout = get_duration()?,
+            "pool_size" => {
+                params.pool_size =
+                    value.parse::<usize>().unwrap_or(params.pool_size)
+            },
+            _ => {},
+        }
+    }
+    Ok(params)
+}
+
+/// The consistent hash ring of servers, the key is mapped to
+/// the first virtual node whose hash is greater than or equal to it.
+struct HashRing {
+    nodes: Vec<(u32, usize)>,
+}
+
+impl HashRing {
+    fn new(servers: &[String]) -> Self {
+        let mut nodes = Vec::with_capacity(servers.len() * VIRTUAL_NODES);
+        for (index, server) in servers.iter().enumerate() {
+            for i in 0..VIRTUAL_NODES {
+                let hash = crc32fast::hash(format!("{server}-{i}").as_bytes());
+                nodes.push((hash, index));
+            }
+        }
+        nodes.sort_unstable();
+        Self { nodes }
+    }
+    fn get(&self, key: &str) -> usize {
+        let hash = crc32fast::hash(key.as_bytes());
+        let index = self.nodes.partition_point(|(value, _)| *value < hash);
+        self.nodes
+            .get(index)
+            .or_else(|| self.nodes.first())
+            .map(|(_, server)| *server)
+            .unwrap_or_default()
+    }
+}
+
+struct Response {
+    status: u16,
+    value: Bytes,
+}
+
+/// Create the request packet of binary protocol.
+fn new_request(opcode: u8, key: &str, extras: &[u8], value: &[u8]) -> Bytes {
+    let body_size = extras.len() + key.len() + value.len();
+    let mut buf = BytesMut::with_capacity(HEADER_SIZE + body_size);
+    buf.put_u8(MAGIC_REQUEST);
+    buf.put_u8(opcode);
+    buf.put_u16(key.len() as u16);
+    buf.put_u8(extras.len() as u8);
+    // data type
+    buf.put_u8(0);
+    // vbucket id
+    buf.put_u16(0);
+    buf.put_u32(body_size as u32);
+    // opaque
+    buf.put_u32(0);
+    // cas
+    buf.put_u64(0);
+    buf.put_slice(extras);
+    buf.put_slice(key.as_bytes());
+    buf.put_slice(value);
+    buf.freeze()
+}
+
+/// The cache storage of memcached, the keys are distributed to servers
+/// by consistent hashing, and the expiration is set by the cache meta.
+pub struct MemcachedCache {
+    params: MemcachedCacheParams,
+    ring: HashRing,
+    // the idle connections of servers
+    pools: Vec<Mutex<Vec<TcpStream>>>,
+}
+
+/// Create a memcached cache, the connections are created when they are used.
+pub fn new_memcached_cache(url: &str) -> Result<MemcachedCache> {
+    let params = parse_params(url)?;
+    info!(
+        servers = params.servers.join(","),
+        prefix = params.prefix,
+        "new memcached cache"
+    );
+    let ring = HashRing::new(&params.servers);
+    let pools = params.servers.iter().map(|_| Mutex::new(vec![])).collect();
+    Ok(MemcachedCache {
+        params,
+        ring,
+        pools,
+    })
+}
+
+impl MemcachedCache {
+    fn get_key(&self, key: &str, namespace: &str) -> String {
+        if namespace.is_empty() {
+            format!("{}{key}", self.params.prefix)
+        } else {
+            format!("{}{namespace}:{key}", self.params.prefix)
+        }
+    }
+    async fn get_conn(&self, index: usize) -> Result<TcpStream> {
+        let conn = self.pools[index]
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pop();
+        if let Some(conn) = conn {
+            return Ok(conn);
+        }
+        let addr = &self.params.servers[index];
+        tokio::time::timeout(self.params.timeout, TcpStream::connect(addr))
+            .await
+            .map_err(|_| Error::Invalid {
+                message: format!("connect to memcached({addr}) timeout"),
+            })?
+            .map_err(|e| Error::Io { source: e })
+    }
+    fn release_conn(&self, index: usize, conn: TcpStream) {
+        let mut pool =
+            self.pools[index].lock().unwrap_or_else(|e| e.into_inner());
+        if pool.len() < self.params.pool_size {
+            pool.push(conn);
+        }
+    }
+    /// Send the request to the server of key and read the response,
+    /// the connection is released to pool only if it succeeds.
+    async fn request(&self, key: &str, packet: Bytes) -> Result<Response> {
+        let index = self.ring.get(key);
+        let mut conn = self.get_conn(index).await?;
+        let resp = tokio::time::timeout(self.params.timeout, async {
+            conn.write_all(&packet).await?;
+            let mut header = [0; HEADER_SIZE];
+            conn.read_exact(&mut header).await?;
+            let mut header = &header[..];
+            let magic = header.get_u8();
+            let _opcode = header.get_u8();
+            let key_size = header.get_u16() as usize;
+            let extras_size = header.get_u8() as usize;
+            let _data_type = header.get_u8();
+            let status = header.get_u16();
+            let body_size = header.get_u32() as usize;
+            let mut body = vec![0; body_size];
+            conn.read_exact(&mut body).await?;
+            if magic != MAGIC_RESPONSE || body_size < extras_size + key_size {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "invalid response of memcached",
+                ));
+            }
+            let value = Bytes::from(body).slice(extras_size + key_size..);
+            Ok(Response { status, value })
+        })
+        .await
+        .map_err(|_| Error::Invalid {
+            message: "request of memcached timeout".to_string(),
+        })?
+        .map_err(|e| Error::Io { source: e })?;
+        self.release_conn(index, conn);
+        Ok(resp)
+    }
+}
+
+fn check_status(resp: &Response) -> Result<()> {
+    if resp.status == STATUS_OK || resp.status == STATUS_KEY_NOT_FOUND {
+        return Ok(());
+    }
+    Err(Error::Invalid {
+        message: format!(
+            "memcached error({}): {}",
+            resp.status,
+            std::str::from_utf8(&resp.value).unwrap_or_default()
+        ),
+    })
+}
+
+#[async_trait]
+impl HttpCacheStorage for MemcachedCache {
+    #[inline]
+    fn category(&self) -> &'static str {
+        "memcached"
+    }
+    async fn get(
+        &self,
+        key: &str,
+        namespace: &str,
+    ) -> Result<Option<CacheObject>> {
+        debug!(key, namespace, "get cache from memcached");
+        let key = self.get_key(key, namespace);
+        let resp = self
+            .request(&key, new_request(OPCODE_GET, &key, &[], &[]))
+            .await?;
+        check_status(&resp)?;
+        if resp.status != STATUS_OK || resp.value.len() < 8 {
+            return Ok(None);
+        }
+        Ok(Some(CacheObject::from(resp.value)))
+    }
+    async fn put(
+        &self,
+        key: &str,
+        namespace: &str,
+        data: CacheObject,
+        _weight: u16,
+    ) -> Result<()> {
+        debug!(key, namespace, "put cache to memcached");
+        let ttl = get_object_ttl(&data, self.params.ttl, self.params.max_ttl)
+            .as_secs()
+            .min(MAX_RELATIVE_TTL);
+        let key = self.get_key(key, namespace);
+        let buf: Bytes = data.into();
+        let mut extras = BytesMut::with_capacity(8);
+        // flags
+        extras.put_u32(0);
+        extras.put_u32(ttl as u32);
+        let resp = self
+            .request(&key, new_request(OPCODE_SET, &key, &extras, &buf))
+            .await?;
+        check_status(&resp)
+    }
+    async fn remove(
+        &self,
+        key: &str,
+        namespace: &str,
+    ) -> Result<Option<CacheObject>> {
+        debug!(key, namespace, "remove cache from memcached");
+        let key = self.get_key(key, namespace);
+        let resp = self
+            .request(&key, new_request(OPCODE_DELETE, &key, &[], &[]))
+            .await?;
+        check_status(&resp)?;
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{new_request, parse_params, HashRing, OPCODE_SET};
+    use crate::cache::is_memcached_url;
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_params() {
+        let params = parse_params(
+            "memcached://127.0.0.1:11211,127.0.0.2?prefix=cache:&ttl=10m&max_ttl=1h&timeout=1s&pool_size=5",
+        )
+        .unwrap();
+        assert_eq!(
+            vec!["127.0.0.1:11211".to_string(), "127.0.0.2:11211".to_string()],
+            params.servers
+        );
+        assert_eq!("cache:", params.prefix);
+        assert_eq!(Duration::from_secs(600), params.ttl);
+        assert_eq!(Some(Duration::from_secs(3600)), params.max_ttl);
+        assert_eq!(Duration::from_secs(1), params.timeout);
+        assert_eq!(5, params.pool_size);
+
+        assert_eq!(true, parse_params("memcached://").is_err());
+        assert_eq!(true, parse_params("redis://127.0.0.1").is_err());
+
+        assert_eq!(true, is_memcached_url("memcached://127.0.0.1"));
+        assert_eq!(false, is_memcached_url("/opt/pingap/cache"));
+    }
+
+    #[test]
+    fn test_hash_ring() {
+        let servers = vec![
+            "127.0.0.1:11211".to_string(),
+            "127.0.0.2:11211".to_string(),
+            "127.0.0.3:11211".to_string(),
+        ];
+        let ring = HashRing::new(&servers);
+        let mut counts = [0; 3];
+        for i in 0..3000 {
+            counts[ring.get(&format!("key-{i}"))] += 1;
+        }
+        // the keys are distributed to all servers
+        assert_eq!(true, counts.iter().all(|count| *count > 500));
+        // the same key is always mapped to the same server
+        assert_eq!(ring.get("pingap"), ring.get("pingap"));
+
+        // only the keys of removed server are remapped
+        let ring2 = HashRing::new(&servers[..2]);
+        for i in 0..100 {
+            let key = format!("key-{i}");
+            let index = ring.get(&key);
+            if index < 2 {
+                assert_eq!(index, ring2.get(&key));
+            }
+        }
+    }
+
+    #[test]
+    fn test_new_request() {
+        let packet = new_request(OPCODE_SET, "key", &[0; 8], b"value");
+        assert_eq!(24 + 8 + 3 + 5, packet.len());
+        assert_eq!(0x80, packet[0]);
+        assert_eq!(OPCODE_SET, packet[1]);
+        assert_eq!([0, 3], packet[2..4]);
+        assert_eq!(8, packet[4]);
+        assert_eq!([0, 0, 0, 16], packet[8..12]);
+        assert_eq!(b"keyvalue", &packet[32..]);
+    }
+}
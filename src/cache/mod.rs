@@ -13,12 +13,25 @@
 // limitations under the License.
 
 use crate::util;
+use http_cache::{HttpCacheCounter, HttpCacheStorage};
 use snafu::Snafu;
 use std::sync::Arc;
 
+mod admission;
 mod file;
 mod http_cache;
+mod keys;
+#[cfg(feature = "memcached")]
+mod memcached;
+mod prefetch;
+#[cfg(feature = "redis")]
+mod redis;
+#[cfg(feature = "s3")]
+mod s3;
+mod tiered;
 mod tiny;
+mod vary;
+mod warm_up;
 
 pub static PAGE_SIZE: usize = 4096;
 
@@ -30,6 +43,12 @@ pub enum Error {
     Invalid { message: String },
     #[snafu(display("Over quota error, max: {max}, {message}"))]
     OverQuota { max: u32, message: String },
+    #[cfg(feature = "redis")]
+    #[snafu(display("Redis error: {source}"))]
+    Redis { source: ::redis::RedisError },
+    #[cfg(feature = "s3")]
+    #[snafu(display("Request error: {source}"))]
+    Request { source: reqwest::Error },
 }
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
@@ -39,21 +58,119 @@ impl From<Error> for pingora::BError {
     }
 }
 
-pub fn new_tiny_ufo_cache(size: usize) -> HttpCache {
+/// Create the http cache of storage, the keys and counter are new,
+/// and the compression level is set by the config of cache later.
+fn new_http_cache(cached: Arc<dyn HttpCacheStorage>) -> HttpCache {
     HttpCache {
         directory: None,
-        cached: Arc::new(tiny::new_tiny_ufo_cache(size / PAGE_SIZE, size)),
+        cached,
+        keys: Arc::new(CacheKeys::default()),
+        counter: Arc::new(HttpCacheCounter::default()),
+        compression_level: 0,
     }
 }
+
+/// Create a tiny ufo cache, the objects are stored in memory.
+pub fn new_tiny_ufo_cache(size: usize) -> HttpCache {
+    new_http_cache(Arc::new(tiny::new_tiny_ufo_cache(size / PAGE_SIZE, size)))
+}
+
+/// Create a file cache, the objects are stored in the directory.
 pub fn new_file_cache(dir: &str) -> Result<HttpCache> {
     let cache = file::new_file_cache(dir)?;
     Ok(HttpCache {
         directory: Some(cache.directory.clone()),
-        cached: Arc::new(cache),
+        ..new_http_cache(Arc::new(cache))
     })
 }
 
-pub use http_cache::{new_file_storage_clear_service, HttpCache};
+/// Check the cache directory is the url of redis.
+pub fn is_redis_url(value: &str) -> bool {
+    value.starts_with("redis://") || value.starts_with("rediss://")
+}
+
+/// Check the cache directory is the url of memcached.
+pub fn is_memcached_url(value: &str) -> bool {
+    value.starts_with("memcached://")
+}
+
+/// Check the cache directory is the url of s3.
+pub fn is_s3_url(value: &str) -> bool {
+    value.starts_with("s3://")
+}
+
+/// Create the error of cache backend which isn't compiled.
+#[cfg(not(all(feature = "redis", feature = "memcached", feature = "s3")))]
+fn new_feature_disabled_error(feature: &str) -> Error {
+    Error::Invalid {
+        message: format!("{feature} cache requires the {feature} feature"),
+    }
+}
+
+/// Create a redis cache, the objects are shared by multiple instances.
+#[cfg(feature = "redis")]
+pub fn new_redis_cache(url: &str) -> Result<HttpCache> {
+    let cache = redis::new_redis_cache(url)?;
+    Ok(new_http_cache(Arc::new(cache)))
+}
+
+#[cfg(not(feature = "redis"))]
+pub fn new_redis_cache(_url: &str) -> Result<HttpCache> {
+    Err(new_feature_disabled_error("redis"))
+}
+
+/// Create a memcached cache, the objects are distributed to servers
+/// by consistent hashing.
+#[cfg(feature = "memcached")]
+pub fn new_memcached_cache(url: &str) -> Result<HttpCache> {
+    let cache = memcached::new_memcached_cache(url)?;
+    Ok(new_http_cache(Arc::new(cache)))
+}
+
+#[cfg(not(feature = "memcached"))]
+pub fn new_memcached_cache(_url: &str) -> Result<HttpCache> {
+    Err(new_feature_disabled_error("memcached"))
+}
+
+/// Create a s3 cache, the objects are stored in the bucket,
+/// it's used for the large and cheap cache.
+#[cfg(feature = "s3")]
+pub fn new_s3_cache(url: &str) -> Result<HttpCache> {
+    let cache = s3::new_s3_cache(url)?;
+    Ok(new_http_cache(Arc::new(cache)))
+}
+
+#[cfg(not(feature = "s3"))]
+pub fn new_s3_cache(_url: &str) -> Result<HttpCache> {
+    Err(new_feature_disabled_error("s3"))
+}
+
+/// Create a tiered cache, the memory cache is used for hot objects
+/// and the storage of cache is used for the long tail.
+pub fn new_tiered_cache(cache: HttpCache, memory_size: usize) -> HttpCache {
+    HttpCache {
+        directory: cache.directory,
+        cached: Arc::new(tiered::new_tiered_cache(cache.cached, memory_size)),
+        keys: cache.keys,
+        counter: cache.counter,
+        compression_level: cache.compression_level,
+    }
+}
+
+pub use admission::{is_size_admitted, CacheAdmission, NOT_ADMITTED};
+pub use http_cache::{
+    is_zstd_accepted, new_file_storage_clear_service, CacheKeyDetail,
+    CacheLookupDetail, CacheObject, HttpCache, HttpCacheSummary,
+    ACCEPT_ZSTD_TAG,
+};
+pub use keys::{CacheKeyInfo, CacheKeyList, CacheKeys};
+pub use prefetch::{
+    add_prefetch, is_prefetch_request, new_prefetch_service, PrefetchItem,
+    HTTP_HEADER_PREFETCH,
+};
+pub use tiered::split_memory_size;
+pub use vary::{get_cache_variance, is_vary_cacheable};
+pub use warm_up::{is_warming_up, new_warm_up_service, warm_up};
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,211 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::service::{CommonServiceTask, ServiceTask};
+use ahash::AHashMap;
+use async_trait::async_trait;
+use futures::StreamExt;
+use nanoid::nanoid;
+use once_cell::sync::Lazy;
+use pingora::http::RequestHeader;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tracing::{debug, error, info};
+
+// max count of the tracked prefetch items
+const MAX_PREFETCH_ITEMS: usize = 10_000;
+// max count of the concurrent prefetch requests
+const MAX_PREFETCH_CONCURRENCY: usize = 10;
+
+pub static HTTP_HEADER_PREFETCH: &str = "x-pingap-prefetch";
+
+// the token of prefetch request, it's generated for each process,
+// so the cache can't be forced to expire by the client
+static PREFETCH_TOKEN: Lazy<String> = Lazy::new(|| nanoid!(32));
+
+static PREFETCH_ITEMS: Lazy<Mutex<AHashMap<String, PrefetchItem>>> =
+    Lazy::new(|| Mutex::new(AHashMap::new()));
+
+#[derive(Debug, Clone)]
+pub struct PrefetchItem {
+    // the url of prefetch request, it's sent to the listener of server
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    // the expired time of cache
+    pub fresh_until: SystemTime,
+    // the cache is prefetched when it's hit at least min hits
+    pub min_hits: u32,
+    hits: u32,
+}
+
+impl PrefetchItem {
+    pub fn new(
+        url: String,
+        headers: Vec<(String, String)>,
+        fresh_until: SystemTime,
+        min_hits: u32,
+    ) -> Self {
+        Self {
+            url,
+            headers,
+            fresh_until,
+            min_hits,
+            hits: 0,
+        }
+    }
+}
+
+/// Record the hit of cache which will be expired soon,
+/// the hot cache will be prefetched by the background service.
+pub fn add_prefetch(hash: &str, item: PrefetchItem) {
+    let mut items = PREFETCH_ITEMS.lock().unwrap_or_else(|e| e.into_inner());
+    if items.len() >= MAX_PREFETCH_ITEMS && !items.contains_key(hash) {
+        return;
+    }
+    let entry = items.entry(hash.to_string()).or_insert(item);
+    entry.hits += 1;
+}
+
+/// Check whether the request is sent by the prefetch service.
+pub fn is_prefetch_request(req: &RequestHeader) -> bool {
+    req.headers
+        .get(HTTP_HEADER_PREFETCH)
+        .map(|value| value.as_bytes() == PREFETCH_TOKEN.as_bytes())
+        .unwrap_or_default()
+}
+
+/// Take the items which should be prefetched,
+/// the expired items are removed too.
+fn take_prefetch_items(now: SystemTime) -> Vec<PrefetchItem> {
+    let mut items = PREFETCH_ITEMS.lock().unwrap_or_else(|e| e.into_inner());
+    let mut result = vec![];
+    items.retain(|_, item| {
+        if item.fresh_until <= now {
+            return false;
+        }
+        if item.hits >= item.min_hits {
+            result.push(item.clone());
+            return false;
+        }
+        true
+    });
+    result
+}
+
+struct PrefetchTask {
+    client: reqwest::Client,
+}
+
+#[async_trait]
+impl ServiceTask for PrefetchTask {
+    async fn run(&self) -> Option<bool> {
+        let items = take_prefetch_items(SystemTime::now());
+        if items.is_empty() {
+            return None;
+        }
+        let count = items.len();
+        futures::stream::iter(items)
+            .for_each_concurrent(MAX_PREFETCH_CONCURRENCY, |item| async move {
+                let mut req = self
+                    .client
+                    .get(&item.url)
+                    .header(HTTP_HEADER_PREFETCH, PREFETCH_TOKEN.as_str());
+                for (name, value) in item.headers.iter() {
+                    req = req.header(name, value);
+                }
+                match req.send().await {
+                    Ok(resp) => {
+                        debug!(
+                            url = item.url,
+                            status = resp.status().as_u16(),
+                            "prefetch cache"
+                        );
+                    },
+                    Err(e) => {
+                        error!(
+                            error = e.to_string(),
+                            url = item.url,
+                            "prefetch cache fail"
+                        );
+                    },
+                };
+            })
+            .await;
+        info!(count, "prefetch cache");
+        None
+    }
+    fn description(&self) -> String {
+        "cachePrefetch".to_string()
+    }
+}
+
+/// Create the background service which prefetches the hot cache
+/// before it's expired, so the client won't wait for upstream.
+pub fn new_prefetch_service() -> Option<CommonServiceTask> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .map_err(|e| {
+            error!(error = e.to_string(), "create prefetch client fail");
+        })
+        .ok()?;
+    Some(CommonServiceTask::new(
+        Duration::from_secs(10),
+        PrefetchTask { client },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        add_prefetch, is_prefetch_request, take_prefetch_items, PrefetchItem,
+        HTTP_HEADER_PREFETCH, PREFETCH_TOKEN,
+    };
+    use pingora::http::RequestHeader;
+    use pretty_assertions::assert_eq;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_prefetch() {
+        let now = SystemTime::now();
+        let fresh_until = now + Duration::from_secs(10);
+        let item = PrefetchItem::new(
+            "http://127.0.0.1:6188/".to_string(),
+            vec![("host".to_string(), "pingap.io".to_string())],
+            fresh_until,
+            2,
+        );
+        add_prefetch("hash1", item.clone());
+        // only hit once
+        assert_eq!(true, take_prefetch_items(now).is_empty());
+        add_prefetch("hash1", item.clone());
+        let items = take_prefetch_items(now);
+        assert_eq!(1, items.len());
+        assert_eq!("http://127.0.0.1:6188/", items[0].url);
+        assert_eq!(true, take_prefetch_items(now).is_empty());
+
+        // the expired item is removed
+        add_prefetch("hash2", item);
+        assert_eq!(true, take_prefetch_items(fresh_until).is_empty());
+
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        assert_eq!(false, is_prefetch_request(&req));
+        req.insert_header(HTTP_HEADER_PREFETCH, "abc").unwrap();
+        assert_eq!(false, is_prefetch_request(&req));
+        req.insert_header(HTTP_HEADER_PREFETCH, PREFETCH_TOKEN.as_str())
+            .unwrap();
+        assert_eq!(true, is_prefetch_request(&req));
+    }
+}
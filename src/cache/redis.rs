@@ -0,0 +1,392 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::http_cache::{get_object_ttl, CacheObject, HttpCacheStorage};
+use super::{Error, Result};
+use ::redis::aio::{ConnectionLike, ConnectionManager};
+use ::redis::cluster::ClusterClient;
+use ::redis::cluster_async::ClusterConnection;
+use ::redis::{Cmd, Pipeline, RedisFuture, Value};
+use async_trait::async_trait;
+use bytes::Bytes;
+use humantime::parse_duration;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+use tracing::{debug, info};
+
+#[derive(Clone)]
+enum RedisConnection {
+    Single(ConnectionManager),
+    Cluster(ClusterConnection),
+}
+
+impl ConnectionLike for RedisConnection {
+    fn req_packed_command<'a>(
+        &'a mut self,
+        cmd: &'a Cmd,
+    ) -> RedisFuture<'a, Value> {
+        match self {
+            RedisConnection::Single(conn) => conn.req_packed_command(cmd),
+            RedisConnection::Cluster(conn) => conn.req_packed_command(cmd),
+        }
+    }
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        match self {
+            RedisConnection::Single(conn) => {
+                conn.req_packed_commands(cmd, offset, count)
+            },
+            RedisConnection::Cluster(conn) => {
+                conn.req_packed_commands(cmd, offset, count)
+            },
+        }
+    }
+    fn get_db(&self) -> i64 {
+        match self {
+            RedisConnection::Single(conn) => conn.get_db(),
+            RedisConnection::Cluster(conn) => conn.get_db(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct RedisCacheParams {
+    // the urls of redis, more than one means redis cluster
+    nodes: Vec<String>,
+    prefix: String,
+    // the ttl of cache if its meta can't be parsed
+    ttl: Duration,
+    max_ttl: Option<Duration>,
+}
+
+/// Parse the params of redis cache, e.g.
+/// `redis://127.0.0.1:6379,127.0.0.1:6380?prefix=pingap:&ttl=1h&max_ttl=1d`,
+/// the other query params are passed to redis client.
+fn parse_params(url: &str) -> Result<RedisCacheParams> {
+    let (scheme, value) = url.split_once("://").ok_or(Error::Invalid {
+        message: format!("invalid redis url: {url}"),
+    })?;
+    let (hosts, query) = value.split_once('?').unwrap_or((value, ""));
+    let mut prefix = "pingap:".to_string();
+    let mut ttl = Duration::from_secs(3600);
+    let mut max_ttl = None;
+    let mut rest = vec![];
+    for item in query.split('&').filter(|item| !item.is_empty()) {
+        let (key, value) = item.split_once('=').unwrap_or((item, ""));
+        match key {
+            "prefix" => prefix = value.to_string(),
+            "ttl" | "max_ttl" => {
+                let d = parse_duration(value).map_err(|e| Error::Invalid {
+                    message: e.to_string(),
+                })?;
+                if key == "ttl" {
+                    ttl = d;
+                } else {
+                    max_ttl = Some(d);
+                }
+            },
+            _ => rest.push(item),
+        }
+    }
+    let query = if rest.is_empty() {
+        "".to_string()
+    } else {
+        format!("?{}", rest.join("&"))
+    };
+    let nodes = hosts
+        .split(',')
+        .map(|host| format!("{scheme}://{}{query}", host.trim()))
+        .collect();
+    Ok(RedisCacheParams {
+        nodes,
+        prefix,
+        ttl,
+        max_ttl,
+    })
+}
+
+/// The cache storage of redis, it's shared by multiple instances,
+/// and the ttl of redis key is set by the expiration of cache.
+pub struct RedisCache {
+    params: RedisCacheParams,
+    conn: OnceCell<RedisConnection>,
+}
+
+/// Create a redis cache, the connection is created when it is used.
+pub fn new_redis_cache(url: &str) -> Result<RedisCache> {
+    let params = parse_params(url)?;
+    info!(
+        nodes = params.nodes.join(","),
+        prefix = params.prefix,
+        "new redis cache"
+    );
+    Ok(RedisCache {
+        params,
+        conn: OnceCell::new(),
+    })
+}
+
+impl RedisCache {
+    async fn get_conn(&self) -> Result<RedisConnection> {
+        let conn = self
+            .conn
+            .get_or_try_init(|| async {
+                let nodes = &self.params.nodes;
+                if nodes.len() > 1 {
+                    let client = ClusterClient::new(nodes.clone())
+                        .map_err(|e| Error::Redis { source: e })?;
+                    let conn = client
+                        .get_async_connection()
+                        .await
+                        .map_err(|e| Error::Redis { source: e })?;
+                    Ok(RedisConnection::Cluster(conn))
+                } else {
+                    let client = ::redis::Client::open(nodes[0].as_str())
+                        .map_err(|e| Error::Redis { source: e })?;
+                    let conn = ConnectionManager::new(client)
+                        .await
+                        .map_err(|e| Error::Redis { source: e })?;
+                    Ok(RedisConnection::Single(conn))
+                }
+            })
+            .await?;
+        Ok(conn.clone())
+    }
+    fn get_key(&self, key: &str, namespace: &str) -> String {
+        if namespace.is_empty() {
+            format!("{}{key}", self.params.prefix)
+        } else {
+            format!("{}{namespace}:{key}", self.params.prefix)
+        }
+    }
+    fn get_tag_key(&self, tag: &str) -> String {
+        format!("{}tag:{tag}", self.params.prefix)
+    }
+    fn get_ttl(&self, data: &CacheObject) -> Duration {
+        get_object_ttl(data, self.params.ttl, self.params.max_ttl)
+    }
+}
+
+#[async_trait]
+impl HttpCacheStorage for RedisCache {
+    #[inline]
+    fn category(&self) -> &'static str {
+        "redis"
+    }
+    async fn get(
+        &self,
+        key: &str,
+        namespace: &str,
+    ) -> Result<Option<CacheObject>> {
+        debug!(key, namespace, "get cache from redis");
+        let mut conn = self.get_conn().await?;
+        let data: Option<Vec<u8>> = ::redis::cmd("GET")
+            .arg(self.get_key(key, namespace))
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| Error::Redis { source: e })?;
+        Ok(data
+            .filter(|data| data.len() >= 8)
+            .map(|data| CacheObject::from(Bytes::from(data))))
+    }
+    async fn put(
+        &self,
+        key: &str,
+        namespace: &str,
+        data: CacheObject,
+        _weight: u16,
+    ) -> Result<()> {
+        debug!(key, namespace, "put cache to redis");
+        let ttl = self.get_ttl(&data);
+        let buf: Bytes = data.into();
+        let mut conn = self.get_conn().await?;
+        let _: () = ::redis::cmd("SET")
+            .arg(self.get_key(key, namespace))
+            .arg(buf.as_ref())
+            .arg("EX")
+            .arg(ttl.as_secs())
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| Error::Redis { source: e })?;
+        Ok(())
+    }
+    /// Remove the cache from redis, the get and del commands
+    /// are sent in one pipeline.
+    async fn remove(
+        &self,
+        key: &str,
+        namespace: &str,
+    ) -> Result<Option<CacheObject>> {
+        debug!(key, namespace, "remove cache from redis");
+        let key = self.get_key(key, namespace);
+        let mut conn = self.get_conn().await?;
+        let (data,): (Option<Vec<u8>>,) = ::redis::pipe()
+            .atomic()
+            .get(&key)
+            .del(&key)
+            .ignore()
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| Error::Redis { source: e })?;
+        Ok(data
+            .filter(|data| data.len() >= 8)
+            .map(|data| CacheObject::from(Bytes::from(data))))
+    }
+    /// Add the key to the set of tags in pipeline, the set is expired
+    /// after the max ttl(default one day).
+    async fn add_tags(
+        &self,
+        key: &str,
+        namespace: &str,
+        tags: &[String],
+    ) -> Result<()> {
+        let ttl = self
+            .params
+            .max_ttl
+            .unwrap_or(Duration::from_secs(24 * 3600))
+            .as_secs();
+        let member = format!("{key}:{namespace}");
+        let mut pipe = ::redis::pipe();
+        for tag in tags.iter() {
+            let tag_key = self.get_tag_key(tag);
+            pipe.sadd(&tag_key, &member)
+                .ignore()
+                .expire(&tag_key, ttl as i64)
+                .ignore();
+        }
+        let mut conn = self.get_conn().await?;
+        let _: () = pipe
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| Error::Redis { source: e })?;
+        Ok(())
+    }
+    async fn take_tag(&self, tag: &str) -> Result<Vec<(String, String)>> {
+        let tag_key = self.get_tag_key(tag);
+        let mut conn = self.get_conn().await?;
+        let (members,): (Vec<String>,) = ::redis::pipe()
+            .atomic()
+            .smembers(&tag_key)
+            .del(&tag_key)
+            .ignore()
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| Error::Redis { source: e })?;
+        Ok(members
+            .iter()
+            .filter_map(|item| item.split_once(':'))
+            .map(|(key, namespace)| (key.to_string(), namespace.to_string()))
+            .collect())
+    }
+    /// Scan the keys of namespace and delete them in pipeline,
+    /// the scan of redis cluster is not supported.
+    async fn clear_namespace(&self, namespace: &str) -> Result<i32> {
+        if namespace.is_empty() {
+            return Ok(-1);
+        }
+        let mut conn = self.get_conn().await?;
+        if matches!(conn, RedisConnection::Cluster(_)) {
+            return Ok(-1);
+        }
+        let pattern = format!("{}{namespace}:*", self.params.prefix);
+        let mut cursor = 0_u64;
+        let mut count = 0;
+        loop {
+            let (next, keys): (u64, Vec<String>) = ::redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(&pattern)
+                .arg("COUNT")
+                .arg(1000)
+                .query_async(&mut conn)
+                .await
+                .map_err(|e| Error::Redis { source: e })?;
+            if !keys.is_empty() {
+                let mut pipe = ::redis::pipe();
+                for key in keys.iter() {
+                    pipe.del(key).ignore();
+                }
+                let _: () = pipe
+                    .query_async(&mut conn)
+                    .await
+                    .map_err(|e| Error::Redis { source: e })?;
+                count += keys.len() as i32;
+            }
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{new_redis_cache, parse_params};
+    use crate::cache::http_cache::CacheObject;
+    use crate::cache::is_redis_url;
+    use bytes::Bytes;
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_params() {
+        let params = parse_params(
+            "redis://127.0.0.1:6379,127.0.0.1:6380?prefix=cache:&ttl=10m&max_ttl=1h&protocol=resp3",
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                "redis://127.0.0.1:6379?protocol=resp3".to_string(),
+                "redis://127.0.0.1:6380?protocol=resp3".to_string(),
+            ],
+            params.nodes
+        );
+        assert_eq!("cache:", params.prefix);
+        assert_eq!(Duration::from_secs(600), params.ttl);
+        assert_eq!(Some(Duration::from_secs(3600)), params.max_ttl);
+
+        let params = parse_params("redis://127.0.0.1:6379/0").unwrap();
+        assert_eq!(vec!["redis://127.0.0.1:6379/0".to_string()], params.nodes);
+        assert_eq!("pingap:", params.prefix);
+
+        assert_eq!(true, parse_params("127.0.0.1:6379").is_err());
+        assert_eq!(true, parse_params("redis://127.0.0.1?ttl=1x").is_err());
+
+        assert_eq!(true, is_redis_url("redis://127.0.0.1"));
+        assert_eq!(true, is_redis_url("rediss://127.0.0.1"));
+        assert_eq!(false, is_redis_url("/opt/pingap/cache"));
+    }
+
+    #[test]
+    fn test_redis_cache_key_ttl() {
+        let cache = new_redis_cache("redis://127.0.0.1:6379?ttl=5m&max_ttl=1m")
+            .unwrap();
+        assert_eq!("pingap:abc", cache.get_key("abc", ""));
+        assert_eq!("pingap:ns:abc", cache.get_key("abc", "ns"));
+        assert_eq!("pingap:tag:product", cache.get_tag_key("product"));
+        let obj = CacheObject {
+            meta: (b"Hello".to_vec(), b"World".to_vec()),
+            body: Bytes::from_static(b"Hello World!"),
+            ..Default::default()
+        };
+        // invalid meta, the default ttl is used and limited by max ttl
+        assert_eq!(Duration::from_secs(60), cache.get_ttl(&obj));
+    }
+}
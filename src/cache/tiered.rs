@@ -0,0 +1,240 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::http_cache::{
+    get_wegiht, CacheObject, HttpCacheStats, HttpCacheStorage,
+};
+use super::tiny::{new_tiny_ufo_cache, TinyUfoCache};
+use super::{Result, PAGE_SIZE};
+use async_trait::async_trait;
+use bytesize::ByteSize;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tracing::{debug, error};
+
+const MEMORY_SIZE_PARAM: &str = "memory_size";
+
+/// Split the memory size from the query of cache url,
+/// e.g. `/opt/pingap/cache?memory_size=100mb`,
+/// the url without memory size is returned with it.
+pub fn split_memory_size(url: &str) -> (String, usize) {
+    let Some((path, query)) = url.split_once('?') else {
+        return (url.to_string(), 0);
+    };
+    let mut memory_size = 0;
+    let mut rest = vec![];
+    for item in query.split('&').filter(|item| !item.is_empty()) {
+        match item.split_once('=') {
+            Some((MEMORY_SIZE_PARAM, value)) => {
+                memory_size = value
+                    .parse::<ByteSize>()
+                    .map(|v| v.as_u64() as usize)
+                    .unwrap_or_default();
+            },
+            _ => rest.push(item),
+        }
+    }
+    if rest.is_empty() {
+        (path.to_string(), memory_size)
+    } else {
+        (format!("{path}?{}", rest.join("&")), memory_size)
+    }
+}
+
+/// The tiered cache combines the memory cache for hot objects and
+/// the storage(e.g. file) for the long tail. The object is got from
+/// memory first, and promoted to memory if it's hit in storage.
+/// The object is written to memory and storage(asynchronously).
+pub struct TieredCache {
+    memory: TinyUfoCache,
+    // the object larger than it is not saved in memory
+    memory_object_max: usize,
+    storage: Arc<dyn HttpCacheStorage>,
+}
+
+/// Create a tiered cache, the size of memory tier is limited by
+/// memory size, and the object larger than 1/100 of it is only
+/// saved in storage.
+pub fn new_tiered_cache(
+    storage: Arc<dyn HttpCacheStorage>,
+    memory_size: usize,
+) -> TieredCache {
+    TieredCache {
+        memory: new_tiny_ufo_cache(
+            (memory_size / PAGE_SIZE).max(1),
+            memory_size,
+        ),
+        memory_object_max: (memory_size / 100).max(PAGE_SIZE),
+        storage,
+    }
+}
+
+impl TieredCache {
+    async fn put_memory(&self, key: &str, namespace: &str, data: CacheObject) {
+        let size = data.body.len();
+        if size > self.memory_object_max {
+            return;
+        }
+        let _ = self
+            .memory
+            .put(key, namespace, data, get_wegiht(size))
+            .await;
+    }
+}
+
+#[async_trait]
+impl HttpCacheStorage for TieredCache {
+    #[inline]
+    fn category(&self) -> &'static str {
+        "tiered"
+    }
+    async fn get(
+        &self,
+        key: &str,
+        namespace: &str,
+    ) -> Result<Option<CacheObject>> {
+        if let Some(obj) = self.memory.get(key, namespace).await? {
+            return Ok(Some(obj));
+        }
+        let Some(obj) = self.storage.get(key, namespace).await? else {
+            return Ok(None);
+        };
+        debug!(key, namespace, "promote cache to memory");
+        self.put_memory(key, namespace, obj.clone()).await;
+        Ok(Some(obj))
+    }
+    async fn put(
+        &self,
+        key: &str,
+        namespace: &str,
+        data: CacheObject,
+        weight: u16,
+    ) -> Result<()> {
+        self.put_memory(key, namespace, data.clone()).await;
+        let storage = self.storage.clone();
+        let key = key.to_string();
+        let namespace = namespace.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = storage.put(&key, &namespace, data, weight).await {
+                error!(
+                    error = e.to_string(),
+                    key, namespace, "put cache to storage fail"
+                );
+            }
+        });
+        Ok(())
+    }
+    async fn remove(
+        &self,
+        key: &str,
+        namespace: &str,
+    ) -> Result<Option<CacheObject>> {
+        let obj = self.memory.remove(key, namespace).await?;
+        let result = self.storage.remove(key, namespace).await?;
+        Ok(result.or(obj))
+    }
+    async fn clear(&self, access_before: SystemTime) -> Result<(i32, i32)> {
+        self.storage.clear(access_before).await
+    }
+    async fn clear_namespace(&self, namespace: &str) -> Result<i32> {
+        self.storage.clear_namespace(namespace).await
+    }
+    async fn add_tags(
+        &self,
+        key: &str,
+        namespace: &str,
+        tags: &[String],
+    ) -> Result<()> {
+        self.storage.add_tags(key, namespace, tags).await
+    }
+    async fn take_tag(&self, tag: &str) -> Result<Vec<(String, String)>> {
+        self.storage.take_tag(tag).await
+    }
+    async fn checkpoint(&self) -> Result<bool> {
+        self.storage.checkpoint().await
+    }
+    async fn touch(
+        &self,
+        key: &str,
+        namespace: &str,
+        weight: Option<u16>,
+    ) -> Result<bool> {
+        let touched = self.memory.touch(key, namespace, weight).await?;
+        Ok(self.storage.touch(key, namespace, weight).await? || touched)
+    }
+    fn size(&self) -> Option<(usize, usize)> {
+        self.storage.size()
+    }
+    fn stats(&self) -> Option<HttpCacheStats> {
+        self.storage.stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{new_tiered_cache, split_memory_size};
+    use crate::cache::file::new_file_cache;
+    use crate::cache::http_cache::{CacheObject, HttpCacheStorage};
+    use bytes::Bytes;
+    use pretty_assertions::assert_eq;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_split_memory_size() {
+        assert_eq!(
+            ("/opt/cache".to_string(), 0),
+            split_memory_size("/opt/cache")
+        );
+        assert_eq!(
+            ("/opt/cache".to_string(), 1000 * 1000),
+            split_memory_size("/opt/cache?memory_size=1mb")
+        );
+        assert_eq!(
+            ("redis://127.0.0.1?prefix=cache:".to_string(), 1024),
+            split_memory_size(
+                "redis://127.0.0.1?memory_size=1kib&prefix=cache:"
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tiered_cache() {
+        let dir = TempDir::new().unwrap();
+        let dir = dir.into_path().to_string_lossy().to_string();
+        let storage =
+            Arc::new(new_file_cache(&format!("{dir}?cache_max=0")).unwrap());
+        let cache = new_tiered_cache(storage.clone(), 1024 * 1024);
+        let obj = CacheObject {
+            meta: (b"Hello".to_vec(), b"World".to_vec()),
+            body: Bytes::from_static(b"Hello World!"),
+            ..Default::default()
+        };
+        cache.put("key", "", obj.clone(), 1).await.unwrap();
+        assert_eq!(obj, cache.get("key", "").await.unwrap().unwrap());
+        // wait for writing to storage
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(obj, storage.get("key", "").await.unwrap().unwrap());
+
+        // promote to memory
+        let cache = new_tiered_cache(storage.clone(), 1024 * 1024);
+        assert_eq!(true, cache.memory.get("key", "").await.unwrap().is_none());
+        assert_eq!(obj, cache.get("key", "").await.unwrap().unwrap());
+        assert_eq!(obj, cache.memory.get("key", "").await.unwrap().unwrap());
+
+        cache.remove("key", "").await.unwrap();
+        assert_eq!(true, cache.get("key", "").await.unwrap().is_none());
+    }
+}
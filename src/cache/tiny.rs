@@ -15,17 +15,58 @@
 use super::http_cache::{CacheObject, HttpCacheStorage};
 use super::Result;
 use async_trait::async_trait;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use tinyufo::TinyUfo;
 use tracing::debug;
 
 pub struct TinyUfoCache {
     cache: TinyUfo<String, CacheObject>,
+    // the count and bytes of cached objects
+    count: AtomicUsize,
+    bytes: AtomicUsize,
+}
+
+#[inline]
+fn get_object_size(obj: &CacheObject) -> usize {
+    obj.meta.0.len() + obj.meta.1.len() + obj.body.len()
 }
 
 impl TinyUfoCache {
     fn new(total_weight_limit: usize, estimated_size: usize) -> Self {
         Self {
             cache: TinyUfo::new(total_weight_limit, estimated_size),
+            count: AtomicUsize::new(0),
+            bytes: AtomicUsize::new(0),
+        }
+    }
+    fn add_size(&self, obj: &CacheObject) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.bytes
+            .fetch_add(get_object_size(obj), Ordering::Relaxed);
+    }
+    fn sub_size(&self, obj: &CacheObject) {
+        let size = get_object_size(obj);
+        let _ = self.count.fetch_update(
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+            |value| Some(value.saturating_sub(1)),
+        );
+        let _ = self.bytes.fetch_update(
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+            |value| Some(value.saturating_sub(size)),
+        );
+    }
+    /// Put the object and update the size, the old object of key
+    /// is removed first, and the evicted objects are subtracted.
+    fn put_object(&self, key: &str, data: CacheObject, weight: u16) {
+        let key = key.to_string();
+        if let Some(old) = self.cache.remove(&key) {
+            self.sub_size(&old);
+        }
+        self.add_size(&data);
+        for item in self.cache.put(key, data, weight) {
+            self.sub_size(&item.data);
         }
     }
 }
@@ -58,7 +99,7 @@ impl HttpCacheStorage for TinyUfoCache {
         weight: u16,
     ) -> Result<()> {
         debug!(key, "put cache to tinyufo");
-        self.cache.put(key.to_string(), data, weight);
+        self.put_object(key, data, weight);
         Ok(())
     }
     // remove object from storage
@@ -69,8 +110,33 @@ impl HttpCacheStorage for TinyUfoCache {
     ) -> Result<Option<CacheObject>> {
         debug!(key, "remove cache from tinyufo");
         let result = self.cache.remove(&key.to_string());
+        if let Some(obj) = &result {
+            self.sub_size(obj);
+        }
         Ok(result)
     }
+    /// Refresh the object by getting it, and put it again
+    /// if the weight is changed.
+    async fn touch(
+        &self,
+        key: &str,
+        _namespace: &str,
+        weight: Option<u16>,
+    ) -> Result<bool> {
+        let Some(obj) = self.cache.get(&key.to_string()) else {
+            return Ok(false);
+        };
+        if let Some(weight) = weight {
+            self.put_object(key, obj, weight);
+        }
+        Ok(true)
+    }
+    fn size(&self) -> Option<(usize, usize)> {
+        Some((
+            self.count.load(Ordering::Relaxed),
+            self.bytes.load(Ordering::Relaxed),
+        ))
+    }
 }
 
 #[cfg(test)]
@@ -78,7 +144,10 @@ mod tests {
     use super::new_tiny_ufo_cache;
     use crate::cache::http_cache::{CacheObject, HttpCacheStorage};
     use bytes::Bytes;
+    use pingora::cache::CacheMeta;
+    use pingora::http::ResponseHeader;
     use pretty_assertions::assert_eq;
+    use std::time::{Duration, SystemTime};
     #[tokio::test]
     async fn test_tiny_ufo_cache() {
         let cache = new_tiny_ufo_cache(10, 10);
@@ -86,6 +155,7 @@ mod tests {
         let obj = CacheObject {
             meta: (b"Hello".to_vec(), b"World".to_vec()),
             body: Bytes::from_static(b"Hello World!"),
+            ..Default::default()
         };
         let result = cache.get(key, "").await.unwrap();
         assert_eq!(true, result.is_none());
@@ -93,8 +163,47 @@ mod tests {
         let result = cache.get(key, "").await.unwrap().unwrap();
         assert_eq!(obj, result);
 
+        // the old object is replaced
+        cache.put(key, "", obj.clone(), 1).await.unwrap();
+        assert_eq!(Some((1, 22)), cache.size());
+        assert_eq!(true, cache.touch(key, "", Some(2)).await.unwrap());
+        assert_eq!(false, cache.touch("abc", "", None).await.unwrap());
+
         cache.remove(key, "").await.unwrap().unwrap();
         let result = cache.get(key, "").await.unwrap();
         assert_eq!(true, result.is_none());
+        assert_eq!(Some((0, 0)), cache.size());
+    }
+
+    #[tokio::test]
+    async fn test_tiny_ufo_cache_extend_ttl() {
+        let cache = new_tiny_ufo_cache(10, 10);
+        let now = SystemTime::now();
+        let resp = ResponseHeader::build(200, None).unwrap();
+        let meta = CacheMeta::new(now, now, 10, 0, resp).serialize().unwrap();
+        let obj = CacheObject {
+            meta,
+            body: Bytes::from_static(b"Hello World!"),
+            ..Default::default()
+        };
+        cache.put("key", "", obj, 1).await.unwrap();
+        assert_eq!(
+            true,
+            cache
+                .extend_ttl("key", "", Duration::from_secs(60))
+                .await
+                .unwrap()
+        );
+        let obj = cache.get("key", "").await.unwrap().unwrap();
+        let meta = CacheMeta::deserialize(&obj.meta.0, &obj.meta.1).unwrap();
+        assert_eq!(true, meta.fresh_sec() > 50);
+        assert_eq!(10, meta.stale_while_revalidate_sec());
+        assert_eq!(
+            false,
+            cache
+                .extend_ttl("abc", "", Duration::from_secs(60))
+                .await
+                .unwrap()
+        );
     }
 }
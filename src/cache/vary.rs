@@ -0,0 +1,151 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use http::{header, HeaderMap};
+use pingora::cache::key::HashBinary;
+use pingora::cache::VarianceBuilder;
+
+/// Get the header names of `Vary`, they are converted to lowercase.
+fn get_vary_names(resp_headers: &HeaderMap) -> Vec<String> {
+    let mut names = vec![];
+    for value in resp_headers.get_all(header::VARY).iter() {
+        let value = value.to_str().unwrap_or_default();
+        for name in value.split(',') {
+            let name = name.trim().to_lowercase();
+            if !name.is_empty() && !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+    names
+}
+
+/// Check the response is cacheable by `Vary`, the response varied by `*`
+/// or the header which isn't in the allowlist can't be cached.
+pub fn is_vary_cacheable(
+    resp_headers: &HeaderMap,
+    allowlist: &[String],
+) -> bool {
+    get_vary_names(resp_headers)
+        .iter()
+        .all(|name| allowlist.contains(name))
+}
+
+/// Normalize the value of varied header, so the similar requests share
+/// one variant, e.g. `gzip, deflate, br` is normalized to `br`.
+fn normalize_value(name: &str, value: &str) -> String {
+    let value = value.to_lowercase();
+    match name {
+        "accept-encoding" => {
+            let encodings: Vec<&str> = value
+                .split(',')
+                .map(|item| item.split(';').next().unwrap_or_default().trim())
+                .collect();
+            ["zstd", "br", "gzip"]
+                .iter()
+                .find(|item| encodings.contains(item))
+                .map(|item| item.to_string())
+                .unwrap_or_default()
+        },
+        "accept-language" => value
+            .split(',')
+            .next()
+            .unwrap_or_default()
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_string(),
+        _ => value.split_whitespace().collect::<Vec<_>>().join(" "),
+    }
+}
+
+/// Get the variance of cache by the varied request headers,
+/// it returns none if the response doesn't vary.
+pub fn get_cache_variance(
+    resp_headers: &HeaderMap,
+    req_headers: &HeaderMap,
+    allowlist: &[String],
+) -> Option<HashBinary> {
+    let names = get_vary_names(resp_headers);
+    let mut variance = VarianceBuilder::new();
+    for name in names.iter() {
+        if !allowlist.contains(name) {
+            continue;
+        }
+        let value = req_headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        let value = normalize_value(name, value);
+        variance.add_owned_value(name, value.into_bytes());
+    }
+    variance.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_cache_variance, is_vary_cacheable, normalize_value};
+    use http::HeaderMap;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_normalize_value() {
+        assert_eq!(
+            "br",
+            normalize_value("accept-encoding", "gzip, deflate, br")
+        );
+        assert_eq!(
+            "zstd",
+            normalize_value("accept-encoding", "zstd;q=1.0, gzip")
+        );
+        assert_eq!("", normalize_value("accept-encoding", "identity"));
+        assert_eq!(
+            "zh-cn",
+            normalize_value("accept-language", "zh-CN,zh;q=0.9,en;q=0.8")
+        );
+        assert_eq!("a b", normalize_value("x-device", " A   B "));
+    }
+
+    #[test]
+    fn test_cache_variance() {
+        let allowlist =
+            vec!["accept-encoding".to_string(), "accept-language".to_string()];
+        let mut resp_headers = HeaderMap::new();
+        assert_eq!(true, is_vary_cacheable(&resp_headers, &allowlist));
+        assert_eq!(
+            true,
+            get_cache_variance(&resp_headers, &HeaderMap::new(), &allowlist)
+                .is_none()
+        );
+
+        resp_headers.insert("Vary", "Accept-Encoding".parse().unwrap());
+        assert_eq!(true, is_vary_cacheable(&resp_headers, &allowlist));
+        let mut gzip = HeaderMap::new();
+        gzip.insert("Accept-Encoding", "gzip, deflate".parse().unwrap());
+        let mut gzip1 = HeaderMap::new();
+        gzip1.insert("Accept-Encoding", "deflate, gzip".parse().unwrap());
+        let mut br = HeaderMap::new();
+        br.insert("Accept-Encoding", "gzip, br".parse().unwrap());
+        let gzip = get_cache_variance(&resp_headers, &gzip, &allowlist);
+        assert_eq!(true, gzip.is_some());
+        assert_eq!(gzip, get_cache_variance(&resp_headers, &gzip1, &allowlist));
+        assert_ne!(gzip, get_cache_variance(&resp_headers, &br, &allowlist));
+
+        resp_headers.insert("Vary", "Accept-Encoding, Cookie".parse().unwrap());
+        assert_eq!(false, is_vary_cacheable(&resp_headers, &allowlist));
+        resp_headers.insert("Vary", "*".parse().unwrap());
+        assert_eq!(false, is_vary_cacheable(&resp_headers, &allowlist));
+    }
+}
@@ -0,0 +1,263 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::config::get_current_config;
+use crate::service::{CommonServiceTask, ServiceTask};
+use async_trait::async_trait;
+use futures::StreamExt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+use tracing::{error, info};
+use url::Url;
+
+// max count of the concurrent warm up requests
+const MAX_WARM_UP_CONCURRENCY: usize = 10;
+// max count of the urls of sitemap
+const MAX_SITEMAP_URLS: usize = 50_000;
+
+static WARMING_UP: AtomicBool = AtomicBool::new(false);
+
+/// Check whether the cache is warming up, the instance shouldn't
+/// be put behind the load balancer before it's done.
+pub fn is_warming_up() -> bool {
+    WARMING_UP.load(Ordering::Relaxed)
+}
+
+/// Get the local address of proxy, the warm up requests are sent to it.
+/// The address of config is used first, otherwise the first server's.
+fn get_local_addr() -> Option<String> {
+    let conf = get_current_config();
+    if let Some(addr) = &conf.basic.cache_warm_up_addr {
+        return Some(addr.clone());
+    }
+    let mut addrs: Vec<&String> =
+        conf.servers.values().map(|item| &item.addr).collect();
+    addrs.sort();
+    let addr = addrs.first()?.split(',').next()?.trim();
+    let addr = if let Some(port) = addr.strip_prefix("0.0.0.0:") {
+        format!("127.0.0.1:{port}")
+    } else if let Some(port) = addr.strip_prefix("[::]:") {
+        format!("[::1]:{port}")
+    } else {
+        addr.to_string()
+    };
+    Some(addr)
+}
+
+/// Convert the url to the url of local proxy,
+/// the host of url is used as host header.
+fn to_local_url(url: &str, addr: &str) -> Option<(String, String)> {
+    let info = Url::parse(url).ok()?;
+    let mut host = info.host_str()?.to_string();
+    if let Some(port) = info.port() {
+        host = format!("{host}:{port}");
+    }
+    let path = &info[url::Position::BeforePath..];
+    Some((format!("http://{addr}{path}"), host))
+}
+
+/// Get the values of `<loc>` from sitemap.
+fn parse_sitemap(data: &str) -> Vec<String> {
+    data.split("<loc>")
+        .skip(1)
+        .filter_map(|item| item.split_once("</loc>"))
+        .map(|(value, _)| value.trim().replace("&amp;", "&"))
+        .filter(|value| !value.is_empty())
+        .take(MAX_SITEMAP_URLS)
+        .collect()
+}
+
+#[inline]
+fn is_sitemap(url: &str) -> bool {
+    url.split('?').next().unwrap_or_default().ends_with(".xml")
+}
+
+struct WarmUp {
+    client: reqwest::Client,
+    addr: String,
+}
+
+impl WarmUp {
+    async fn get(&self, url: &str) -> reqwest::Result<reqwest::Response> {
+        if let Some((local_url, host)) = to_local_url(url, &self.addr) {
+            self.client.get(local_url).header("Host", host).send().await
+        } else {
+            self.client.get(url).send().await
+        }
+    }
+    /// Expand the sitemaps to urls, the sitemap index is supported.
+    async fn expand_urls(&self, urls: &[String]) -> Vec<String> {
+        let mut result = vec![];
+        let mut sitemaps: Vec<(String, usize)> = vec![];
+        for url in urls.iter() {
+            if is_sitemap(url) {
+                sitemaps.push((url.clone(), 0));
+            } else {
+                result.push(url.clone());
+            }
+        }
+        while let Some((url, depth)) = sitemaps.pop() {
+            let data = match self.get(&url).await {
+                Ok(resp) => resp.text().await.unwrap_or_default(),
+                Err(e) => {
+                    error!(error = e.to_string(), url, "get sitemap fail");
+                    continue;
+                },
+            };
+            for loc in parse_sitemap(&data) {
+                // only one level of sitemap index
+                if is_sitemap(&loc) && depth == 0 {
+                    sitemaps.push((loc, depth + 1));
+                } else {
+                    result.push(loc);
+                }
+            }
+        }
+        result
+    }
+    async fn run(&self, urls: &[String]) -> (usize, usize) {
+        let urls = self.expand_urls(urls).await;
+        let success = AtomicUsize::new(0);
+        let counter = &success;
+        futures::stream::iter(urls.iter())
+            .for_each_concurrent(MAX_WARM_UP_CONCURRENCY, |url| async move {
+                match self.get(url).await {
+                    Ok(resp) if resp.status().is_success() => {
+                        // read the body, so the response is cached
+                        let _ = resp.bytes().await;
+                        counter.fetch_add(1, Ordering::Relaxed);
+                    },
+                    Ok(resp) => {
+                        error!(
+                            url,
+                            status = resp.status().as_u16(),
+                            "warm up cache fail"
+                        );
+                    },
+                    Err(e) => {
+                        error!(
+                            error = e.to_string(),
+                            url, "warm up cache fail"
+                        );
+                    },
+                }
+            })
+            .await;
+        (urls.len(), success.load(Ordering::Relaxed))
+    }
+}
+
+/// Fetch the urls through the local proxy to populate the cache,
+/// the urls of sitemap are fetched too.
+pub async fn warm_up(urls: Vec<String>) -> Result<usize, String> {
+    let addr = get_local_addr().ok_or("local address is not found")?;
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .map_err(|e| e.to_string())?;
+    let start = SystemTime::now();
+    WARMING_UP.store(true, Ordering::Relaxed);
+    let (count, success) = WarmUp { client, addr }.run(&urls).await;
+    WARMING_UP.store(false, Ordering::Relaxed);
+    info!(
+        count,
+        success,
+        elapsed =
+            format!("{}ms", start.elapsed().unwrap_or_default().as_millis()),
+        "warm up cache"
+    );
+    Ok(success)
+}
+
+struct WarmUpTask {
+    urls: Vec<String>,
+}
+
+#[async_trait]
+impl ServiceTask for WarmUpTask {
+    async fn run(&self) -> Option<bool> {
+        // wait for the proxy to listen
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        if let Err(e) = warm_up(self.urls.clone()).await {
+            WARMING_UP.store(false, Ordering::Relaxed);
+            error!(error = e, "warm up cache fail");
+        }
+        Some(true)
+    }
+    fn description(&self) -> String {
+        "cacheWarmUp".to_string()
+    }
+}
+
+/// Create the service which warms up the cache on startup,
+/// the ready check fails until it's done.
+pub fn new_warm_up_service() -> Option<CommonServiceTask> {
+    let urls = get_current_config().basic.cache_warm_up_urls.clone()?;
+    if urls.is_empty() {
+        return None;
+    }
+    WARMING_UP.store(true, Ordering::Relaxed);
+    // the task only runs once
+    Some(CommonServiceTask::new(
+        Duration::from_millis(100),
+        WarmUpTask { urls },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_sitemap, parse_sitemap, to_local_url};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_to_local_url() {
+        assert_eq!(
+            Some((
+                "http://127.0.0.1:6188/books?page=1".to_string(),
+                "pingap.io".to_string()
+            )),
+            to_local_url("https://pingap.io/books?page=1", "127.0.0.1:6188")
+        );
+        assert_eq!(
+            Some((
+                "http://127.0.0.1:6188/".to_string(),
+                "pingap.io:8080".to_string()
+            )),
+            to_local_url("http://pingap.io:8080", "127.0.0.1:6188")
+        );
+        assert_eq!(None, to_local_url("/books", "127.0.0.1:6188"));
+    }
+
+    #[test]
+    fn test_parse_sitemap() {
+        let data = r#"<?xml version="1.0" encoding="UTF-8"?>
+<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <url><loc>https://pingap.io/</loc></url>
+  <url>
+    <loc> https://pingap.io/books?a=1&amp;b=2 </loc>
+  </url>
+</urlset>"#;
+        assert_eq!(
+            vec![
+                "https://pingap.io/".to_string(),
+                "https://pingap.io/books?a=1&b=2".to_string()
+            ],
+            parse_sitemap(data)
+        );
+        assert_eq!(true, is_sitemap("https://pingap.io/sitemap.xml"));
+        assert_eq!(true, is_sitemap("https://pingap.io/sitemap.xml?v=1"));
+        assert_eq!(false, is_sitemap("https://pingap.io/books"));
+    }
+}
@@ -0,0 +1,266 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{Error, Result};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use tokio::io::AsyncWriteExt;
+use tracing::{error, info};
+
+// the records are kept in memory if the audit log file isn't set,
+// the oldest ones are dropped and all of them are lost after restart
+const MAX_MEMORY_RECORDS: usize = 1000;
+const DEFAULT_QUERY_LIMIT: usize = 100;
+
+// avoid interleaving the records of concurrent mutations
+static AUDIT_LOCK: Lazy<tokio::sync::Mutex<VecDeque<AuditRecord>>> =
+    Lazy::new(|| tokio::sync::Mutex::new(VecDeque::new()));
+
+/// The audit record of admin mutation, the changes are the summary
+/// of config before and after the mutation.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct AuditRecord {
+    pub created_at: u64,
+    pub actor: String,
+    pub ip: String,
+    pub method: String,
+    pub endpoint: String,
+    pub status: u16,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub changes: Vec<String>,
+}
+
+/// The query of audit records, the latest records are returned first.
+#[derive(Debug, Clone, Default)]
+pub struct AuditQuery {
+    pub actor: Option<String>,
+    // the prefix of endpoint, e.g. /configs/upstreams
+    pub endpoint: Option<String>,
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    pub limit: Option<usize>,
+}
+
+impl AuditQuery {
+    fn is_match(&self, record: &AuditRecord) -> bool {
+        if let Some(actor) = &self.actor {
+            if &record.actor != actor {
+                return false;
+            }
+        }
+        if let Some(endpoint) = &self.endpoint {
+            if !record.endpoint.starts_with(endpoint) {
+                return false;
+            }
+        }
+        if self.since.is_some_and(|since| record.created_at < since) {
+            return false;
+        }
+        if self.until.is_some_and(|until| record.created_at > until) {
+            return false;
+        }
+        true
+    }
+}
+
+fn filter_records<'a>(
+    records: impl DoubleEndedIterator<Item = &'a AuditRecord>,
+    query: &AuditQuery,
+) -> Vec<AuditRecord> {
+    records
+        .rev()
+        .filter(|item| query.is_match(item))
+        .take(query.limit.unwrap_or(DEFAULT_QUERY_LIMIT))
+        .cloned()
+        .collect()
+}
+
+async fn do_append_audit(file: &str, record: &AuditRecord) -> Result<()> {
+    let mut records = AUDIT_LOCK.lock().await;
+    if file.is_empty() {
+        records.push_back(record.clone());
+        if records.len() > MAX_MEMORY_RECORDS {
+            records.pop_front();
+        }
+        return Ok(());
+    }
+    let mut data =
+        serde_json::to_vec(record).map_err(|e| Error::Json { source: e })?;
+    data.push(b'\n');
+    let io_error = |e| Error::Io {
+        source: e,
+        file: file.to_string(),
+    };
+    // the audit log is only appended, it's never rewritten
+    let mut f = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file)
+        .await
+        .map_err(io_error)?;
+    f.write_all(&data).await.map_err(io_error)?;
+    f.flush().await.map_err(io_error)
+}
+
+/// Append the record to the audit log file(json lines),
+/// it's kept in memory if the file is empty.
+pub async fn append_audit(file: &str, record: &AuditRecord) {
+    info!(
+        actor = record.actor,
+        method = record.method,
+        endpoint = record.endpoint,
+        status = record.status,
+        "admin audit"
+    );
+    if let Err(e) = do_append_audit(file, record).await {
+        error!(error = e.to_string(), file, "append audit log fail");
+    }
+}
+
+/// Query the records of the audit log file,
+/// or the records in memory if the file is empty.
+pub async fn query_audit(
+    file: &str,
+    query: &AuditQuery,
+) -> Result<Vec<AuditRecord>> {
+    if file.is_empty() {
+        let records = AUDIT_LOCK.lock().await;
+        return Ok(filter_records(records.iter(), query));
+    }
+    let data = match tokio::fs::read_to_string(file).await {
+        Ok(data) => data,
+        // no mutation is recorded
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(vec![])
+        },
+        Err(e) => {
+            return Err(Error::Io {
+                source: e,
+                file: file.to_string(),
+            })
+        },
+    };
+    // the broken line(e.g. the process is killed while writing) is ignored
+    let records: Vec<AuditRecord> = data
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+    Ok(filter_records(records.iter(), query))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{append_audit, query_audit, AuditQuery, AuditRecord};
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_audit_log() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("audit.log").to_string_lossy().to_string();
+        assert_eq!(
+            0,
+            query_audit(&file, &AuditQuery::default())
+                .await
+                .unwrap()
+                .len()
+        );
+
+        for (index, actor) in ["tree", "ci", "tree"].iter().enumerate() {
+            append_audit(
+                &file,
+                &AuditRecord {
+                    created_at: 100 + index as u64,
+                    actor: actor.to_string(),
+                    method: "PUT".to_string(),
+                    endpoint: format!("/configs/upstreams/u{index}"),
+                    status: 204,
+                    changes: vec![format!("add upstream(u{index})")],
+                    ..Default::default()
+                },
+            )
+            .await;
+        }
+        // the broken line is ignored
+        let mut data = std::fs::read_to_string(&file).unwrap();
+        data.push_str("{\"created_at\":");
+        std::fs::write(&file, data).unwrap();
+
+        let records = query_audit(&file, &AuditQuery::default()).await.unwrap();
+        assert_eq!(
+            vec![102, 101, 100],
+            records
+                .iter()
+                .map(|item| item.created_at)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(vec!["add upstream(u2)".to_string()], records[0].changes);
+
+        let records = query_audit(
+            &file,
+            &AuditQuery {
+                actor: Some("tree".to_string()),
+                limit: Some(1),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(1, records.len());
+        assert_eq!("/configs/upstreams/u2", records[0].endpoint);
+
+        let records = query_audit(
+            &file,
+            &AuditQuery {
+                endpoint: Some("/configs/upstreams/u1".to_string()),
+                since: Some(101),
+                until: Some(101),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(1, records.len());
+        assert_eq!("ci", records[0].actor);
+    }
+
+    #[tokio::test]
+    async fn test_memory_audit_log() {
+        append_audit(
+            "",
+            &AuditRecord {
+                created_at: 1,
+                actor: "memory".to_string(),
+                method: "POST".to_string(),
+                endpoint: "/restart".to_string(),
+                status: 204,
+                ..Default::default()
+            },
+        )
+        .await;
+        let records = query_audit(
+            "",
+            &AuditQuery {
+                actor: Some("memory".to_string()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(1, records.len());
+        assert_eq!("/restart", records[0].endpoint);
+    }
+}
@@ -13,9 +13,11 @@
 // limitations under the License.
 
 use super::{Error, Result};
+use crate::cache::{is_memcached_url, is_redis_url, is_s3_url};
 use crate::discovery::{is_static_discovery, DNS_DISCOVERY};
 use crate::plugin::parse_plugins;
 use crate::proxy::Parser;
+use crate::state::parse_slo_target;
 use crate::util::{self, aes_decrypt, base64_decode};
 use arc_swap::ArcSwap;
 use bytesize::ByteSize;
@@ -42,6 +44,12 @@ pub const CATEGORY_UPSTREAM: &str = "upstream";
 pub const CATEGORY_PLUGIN: &str = "plugin";
 pub const CATEGORY_CERTIFICATE: &str = "certificate";
 pub const CATEGORY_STORAGE: &str = "storage";
+// all categories of config, it's used to save the whole config at once
+pub const CATEGORY_ALL: &str = "all";
+
+// the storage of template, its values are applied to the items
+// which include it, and can be overridden by the items
+static STORAGE_CATEGORY_TEMPLATE: &str = "template";
 
 #[derive(PartialEq, Debug, Default, Clone, EnumString, strum::Display)]
 #[strum(serialize_all = "snake_case")]
@@ -68,6 +76,33 @@ pub enum PluginCategory {
     Csrf,
     Cors,
     AcceptEncoding,
+    Graphql,
+    Session,
+    EventEmitter,
+    Challenge,
+    ClientHints,
+    Concurrency,
+    FaultInjection,
+    JsonSchema,
+    Coalescing,
+    CachePurge,
+    CacheSlice,
+    WellKnown,
+    OpenApi,
+    ClientCert,
+    ApiKey,
+    CookieRewrite,
+    BulkRedirect,
+    EarlyHints,
+    LinkHeader,
+    Tarpit,
+    Ready,
+    Dlp,
+    WebsocketPolicy,
+    Robots,
+    CspNonce,
+    Esi,
+    Wasm,
 }
 
 impl Serialize for PluginCategory {
@@ -238,6 +273,8 @@ pub struct UpstreamConf {
     pub tcp_probe_count: Option<usize>,
     pub tcp_recv_buf: Option<ByteSize>,
     pub tcp_fast_open: Option<bool>,
+    pub validate_content_length: Option<bool>,
+    pub validate_digest: Option<bool>,
     pub includes: Option<Vec<String>>,
     pub remark: Option<String>,
 }
@@ -323,6 +360,10 @@ pub struct LocationConf {
     pub max_processing: Option<i32>,
     pub includes: Option<Vec<String>>,
     pub grpc_web: Option<bool>,
+    pub slo_target: Option<String>,
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    pub slo_latency: Option<Duration>,
     pub remark: Option<String>,
 }
 
@@ -336,6 +377,7 @@ impl LocationConf {
     /// Validate the options of location config.
     /// 1. Convert add and set headers to (HeaderName, HeaderValue).
     /// 2. Parse rewrite path to regexp if it exists.
+    /// 3. Validate the slo target if it exists.
     fn validate(&self, name: &str, upstream_names: &[String]) -> Result<()> {
         // validate header for http
         let validate = |headers: &Option<Vec<String>>| -> Result<()> {
@@ -379,6 +421,11 @@ impl LocationConf {
             let _ =
                 Regex::new(arr[0]).map_err(|e| Error::Regex { source: e })?;
         }
+        if let Some(value) = &self.slo_target {
+            parse_slo_target(value).map_err(|message| Error::Invalid {
+                message: format!("{message}(location:{name})"),
+            })?;
+        }
 
         Ok(())
     }
@@ -421,6 +468,9 @@ pub struct ServerConf {
     pub tls_ciphersuites: Option<String>,
     pub tls_min_version: Option<String>,
     pub tls_max_version: Option<String>,
+    // the ca certificate(pem or base64) to verify client certificate,
+    // the mtls is enabled if it is set
+    pub tls_client_ca: Option<String>,
     pub global_certificates: Option<bool>,
     pub enabled_h2: Option<bool>,
     #[serde(default)]
@@ -431,6 +481,18 @@ pub struct ServerConf {
     pub tcp_interval: Option<Duration>,
     pub tcp_probe_count: Option<usize>,
     pub tcp_fastopen: Option<usize>,
+    // only accept ipv6 connections for `[::]` listener,
+    // it is dual-stack by default
+    pub ipv6_only: Option<bool>,
+    // the max requests of downstream keep-alive connection
+    pub keepalive_requests: Option<u32>,
+    // the idle timeout of downstream keep-alive connection
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    pub keepalive_timeout: Option<Duration>,
+    // the percentage of responses with `Connection: close`,
+    // it's useful for rebalancing behind l4 load balancer
+    pub keepalive_close_percentage: Option<u8>,
     pub prometheus_metrics: Option<String>,
     pub otlp_exporter: Option<String>,
     pub includes: Option<Vec<String>>,
@@ -443,6 +505,8 @@ impl ServerConf {
     /// 1. Parse listen addr to socket addr.
     /// 2. Check the locations are exists.
     /// 3. Parse access log layout success.
+    /// 4. Parse client ca certificate if it exists.
+    /// 5. Check the keepalive close percentage.
     fn validate(&self, name: &str, location_names: &[String]) -> Result<()> {
         for addr in self.addr.split(',') {
             let _ = addr.to_socket_addrs().map_err(|e| Error::Io {
@@ -470,6 +534,16 @@ impl ServerConf {
                 });
             }
         }
+        if let Some(value) = &self.tls_client_ca {
+            validate_cert(value)?;
+        }
+        if self.keepalive_close_percentage.unwrap_or_default() > 100 {
+            return Err(Error::Invalid {
+                message: format!(
+                    "keepalive close percentage should be less than or equal to 100(server:{name})"
+                ),
+            });
+        }
 
         Ok(())
     }
@@ -504,9 +578,58 @@ pub struct BasicConf {
     pub auto_restart_check_interval: Option<Duration>,
     pub cache_directory: Option<String>,
     pub cache_max_size: Option<ByteSize>,
+    // the zstd level of cache objects, the body isn't compressed if not set.
+    // the compressed body is passed through if the client accepts zstd,
+    // otherwise it's decoded on hit
+    pub cache_compression_level: Option<i32>,
+    // the urls or sitemaps which are fetched to warm up cache on startup
+    pub cache_warm_up_urls: Option<Vec<String>>,
+    // the local address of proxy for warm up, default is the first server
+    pub cache_warm_up_addr: Option<String>,
+    pub id_generator: Option<String>,
+    pub node_id: Option<u16>,
+    pub crash_report_dir: Option<String>,
+    // check the config before it's hot reloaded, including the connectivity
+    // of upstreams, the config isn't applied if the check fails
+    pub staged_apply: Option<bool>,
+    // the observation window after config is hot reloaded, the config is
+    // rolled back if the error rate spikes in the window
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    pub rollback_window: Option<Duration>,
+    // the max ratio of 5xx responses in the window, default 0.1
+    pub rollback_error_rate: Option<f64>,
 }
 
 impl BasicConf {
+    /// Validate the options of basic config.
+    pub fn validate(&self) -> Result<()> {
+        util::IdGenerator::from_str(
+            &self.id_generator.clone().unwrap_or_default(),
+        )
+        .map_err(|e| Error::Invalid {
+            message: e.to_string(),
+        })?;
+        let node_id = self.node_id.unwrap_or_default();
+        if node_id > util::MAX_NODE_ID {
+            return Err(Error::Invalid {
+                message: format!(
+                    "node id({node_id}) should be less than {}",
+                    util::MAX_NODE_ID + 1
+                ),
+            });
+        }
+        if let Some(rate) = self.rollback_error_rate {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(Error::Invalid {
+                    message: format!(
+                        "rollback error rate({rate}) should be between 0 and 1"
+                    ),
+                });
+            }
+        }
+        Ok(())
+    }
     pub fn get_pid_file(&self) -> String {
         if let Some(pid_file) = &self.pid_file {
             pid_file.clone()
@@ -631,6 +754,32 @@ impl PingapConf {
             toml::to_string_pretty(&m).map_err(|e| Error::Ser { source: e })?;
         Ok((path, value))
     }
+    /// Get the toml of all categories as (path, toml), each item is a file
+    /// if separation is true. The toml is empty if the category has no item.
+    pub fn get_all_toml(
+        &self,
+        separation: bool,
+    ) -> Result<Vec<(String, String)>> {
+        let mut values = vec![self.get_toml(CATEGORY_BASIC, None)?];
+        let categories = [
+            (CATEGORY_SERVER, self.servers.keys().collect::<Vec<_>>()),
+            (CATEGORY_LOCATION, self.locations.keys().collect()),
+            (CATEGORY_UPSTREAM, self.upstreams.keys().collect()),
+            (CATEGORY_PLUGIN, self.plugins.keys().collect()),
+            (CATEGORY_CERTIFICATE, self.certificates.keys().collect()),
+            (CATEGORY_STORAGE, self.storages.keys().collect()),
+        ];
+        for (category, names) in categories {
+            if !separation {
+                values.push(self.get_toml(category, None)?);
+                continue;
+            }
+            for name in names {
+                values.push(self.get_toml(category, Some(name.as_str()))?);
+            }
+        }
+        Ok(values)
+    }
     pub fn get_storage_value(&self, name: &str) -> Result<String> {
         for (key, item) in self.storages.iter() {
             if key != name {
@@ -650,8 +799,38 @@ impl PingapConf {
     }
 }
 
+/// Resolve the values of template, a template can include other
+/// templates, and its own values override the included ones.
+/// The template of circular include is ignored.
+fn resolve_template(
+    templates: &HashMap<String, String>,
+    name: &str,
+    visited: &mut Vec<String>,
+) -> Table {
+    if visited.iter().any(|item| item == name) {
+        return Table::new();
+    }
+    let Some(mut template) = templates
+        .get(name)
+        .and_then(|value| toml::from_str::<Table>(value).ok())
+    else {
+        return Table::new();
+    };
+    visited.push(name.to_string());
+    let mut values = Table::new();
+    if let Some(Value::Array(includes)) = template.remove("includes") {
+        for item in includes.iter().filter_map(|item| item.as_str()) {
+            values.extend(resolve_template(templates, item, visited));
+        }
+    }
+    visited.pop();
+    values.extend(template);
+    values
+}
+
 fn convert_include_toml(
     data: &HashMap<String, String>,
+    templates: &HashMap<String, String>,
     replace_includes: bool,
     mut value: Value,
 ) -> String {
@@ -662,6 +841,16 @@ fn convert_include_toml(
         return m.to_string();
     }
     if let Some(includes) = m.remove("includes") {
+        // the values of templates are overridden by the item
+        for name in includes.as_array().into_iter().flatten() {
+            let name = name.as_str().unwrap_or_default();
+            if !templates.contains_key(name) {
+                continue;
+            }
+            for (key, value) in resolve_template(templates, name, &mut vec![]) {
+                m.entry(key).or_insert(value);
+            }
+        }
         if let Some(includes) = get_include_toml(data, includes) {
             if let Ok(includes) = toml::from_str::<Table>(&includes) {
                 for (key, value) in includes.iter() {
@@ -692,53 +881,142 @@ fn get_include_toml(
     Some(arr.join("\n"))
 }
 
+fn is_env_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Replace the `${NAME}` placeholders with the values of env,
+/// `${NAME:-default}` uses the default if the env is unset or empty.
+/// The placeholder is kept if the env is unset and no default.
+fn replace_env_placeholders(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let Some(end) = rest[start..].find('}').map(|end| start + end) else {
+            break;
+        };
+        result.push_str(&rest[..start]);
+        let expr = &rest[start + 2..end];
+        let (name, default) = match expr.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (expr, None),
+        };
+        let env = if is_env_name(name) {
+            std::env::var(name).ok()
+        } else {
+            None
+        };
+        match (env, default) {
+            (Some(env), Some(default)) if env.is_empty() => {
+                result.push_str(default)
+            },
+            (Some(env), _) => result.push_str(&env),
+            (None, Some(default)) if is_env_name(name) => {
+                result.push_str(default)
+            },
+            _ => result.push_str(&rest[start..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    result
+}
+
+fn replace_env_value(value: &mut Value) {
+    match value {
+        Value::String(data) => {
+            if data.contains("${") {
+                *data = replace_env_placeholders(data);
+            }
+        },
+        Value::Array(values) => values.iter_mut().for_each(replace_env_value),
+        Value::Table(values) => values.values_mut().for_each(replace_env_value),
+        _ => {},
+    }
+}
+
 fn convert_pingap_config(
     data: &[u8],
     replace_includes: bool,
 ) -> Result<PingapConf, Error> {
-    let data: TomlConfig = toml::from_str(
+    let mut value: Table = toml::from_str(
         std::string::String::from_utf8_lossy(data)
             .to_string()
             .as_str(),
     )
     .map_err(|e| Error::De { source: e })?;
+    // the env placeholders are replaced like the includes,
+    // so they are kept for editing
+    if replace_includes {
+        value.values_mut().for_each(replace_env_value);
+    }
+    let data: TomlConfig = Value::Table(value)
+        .try_into()
+        .map_err(|e| Error::De { source: e })?;
 
     let mut conf = PingapConf {
         basic: data.basic.unwrap_or_default(),
         ..Default::default()
     };
     let mut includes = HashMap::new();
+    let mut templates = HashMap::new();
     for (name, value) in data.storages.unwrap_or_default() {
         let toml = format_toml(&value);
         let storage: StorageConf = toml::from_str(toml.as_str())
             .map_err(|e| Error::De { source: e })?;
-        includes.insert(name.clone(), storage.value.clone());
+        if storage.category == STORAGE_CATEGORY_TEMPLATE {
+            templates.insert(name.clone(), storage.value.clone());
+        } else {
+            includes.insert(name.clone(), storage.value.clone());
+        }
         conf.storages.insert(name, storage);
     }
 
     for (name, value) in data.upstreams.unwrap_or_default() {
-        let toml = convert_include_toml(&includes, replace_includes, value);
+        let toml = convert_include_toml(
+            &includes,
+            &templates,
+            replace_includes,
+            value,
+        );
 
         let upstream: UpstreamConf = toml::from_str(toml.as_str())
             .map_err(|e| Error::De { source: e })?;
         conf.upstreams.insert(name, upstream);
     }
     for (name, value) in data.locations.unwrap_or_default() {
-        let toml = convert_include_toml(&includes, replace_includes, value);
+        let toml = convert_include_toml(
+            &includes,
+            &templates,
+            replace_includes,
+            value,
+        );
 
         let location: LocationConf = toml::from_str(toml.as_str())
             .map_err(|e| Error::De { source: e })?;
         conf.locations.insert(name, location);
     }
     for (name, value) in data.servers.unwrap_or_default() {
-        let toml = convert_include_toml(&includes, replace_includes, value);
+        let toml = convert_include_toml(
+            &includes,
+            &templates,
+            replace_includes,
+            value,
+        );
 
         let server: ServerConf = toml::from_str(toml.as_str())
             .map_err(|e| Error::De { source: e })?;
         conf.servers.insert(name, server);
     }
     for (name, value) in data.plugins.unwrap_or_default() {
-        let plugin: PluginConf = toml::from_str(format_toml(&value).as_str())
+        let toml = convert_include_toml(
+            &includes,
+            &templates,
+            replace_includes,
+            value,
+        );
+        let plugin: PluginConf = toml::from_str(toml.as_str())
             .map_err(|e| Error::De { source: e })?;
         conf.plugins.insert(name, plugin);
     }
@@ -753,6 +1031,28 @@ fn convert_pingap_config(
     Ok(conf)
 }
 
+fn merge_json_patch(target: &mut serde_json::Value, patch: serde_json::Value) {
+    let serde_json::Value::Object(patch) = patch else {
+        *target = patch;
+        return;
+    };
+    if !target.is_object() {
+        *target = serde_json::Value::Object(serde_json::Map::new());
+    }
+    if let serde_json::Value::Object(values) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                values.remove(&key);
+            } else {
+                merge_json_patch(
+                    values.entry(key).or_insert(serde_json::Value::Null),
+                    value,
+                );
+            }
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone, Deserialize, Serialize)]
 struct Description {
     category: String,
@@ -760,12 +1060,48 @@ struct Description {
     data: String,
 }
 
+/// The change of config item, the lines are the diff of modified item.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct ConfigChange {
+    pub action: &'static str,
+    pub category: String,
+    pub name: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub lines: Vec<String>,
+}
+
+impl std::fmt::Display for ConfigChange {
+    /// Format the change as `modify upstream(charts)`,
+    /// the diff lines are indented below.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}({})", self.action, self.category, self.name)?;
+        for line in self.lines.iter() {
+            write!(f, "\n  {line}")?;
+        }
+        Ok(())
+    }
+}
+
 impl PingapConf {
     pub fn new(data: &[u8], replace_includes: bool) -> Result<Self> {
         convert_pingap_config(data, replace_includes)
     }
     /// Validate the options of pinggap config.
     pub fn validate(&self) -> Result<()> {
+        // the items are validated after the templates are applied
+        if self
+            .storages
+            .values()
+            .any(|item| item.category == STORAGE_CATEGORY_TEMPLATE)
+        {
+            let data = toml::to_string_pretty(self)
+                .map_err(|e| Error::Ser { source: e })?;
+            let mut conf = convert_pingap_config(data.as_bytes(), true)?;
+            conf.storages
+                .retain(|_, item| item.category != STORAGE_CATEGORY_TEMPLATE);
+            return conf.validate();
+        }
+        self.basic.validate()?;
         let mut upstream_names = vec![];
         for (name, upstream) in self.upstreams.iter() {
             upstream.validate(name)?;
@@ -803,6 +1139,57 @@ impl PingapConf {
         convert_pingap_config(ping_conf.as_bytes(), true)?;
         Ok(())
     }
+    /// Get the subsystems which are enabled by config.
+    pub fn get_enabled_subsystems(&self) -> Vec<String> {
+        let basic = &self.basic;
+        let mut subsystems = vec![];
+        let mut add = |name: &str, enabled: bool| {
+            if enabled {
+                subsystems.push(name.to_string());
+            }
+        };
+        add("webhook", basic.webhook.is_some());
+        add("sentry", basic.sentry.is_some());
+        add("pyroscope", basic.pyroscope.is_some());
+        let cache_directory = basic.cache_directory.clone().unwrap_or_default();
+        let redis_cache = is_redis_url(&cache_directory);
+        let memcached_cache = is_memcached_url(&cache_directory);
+        let s3_cache = is_s3_url(&cache_directory);
+        add(
+            "file_cache",
+            basic.cache_directory.is_some()
+                && !redis_cache
+                && !memcached_cache
+                && !s3_cache,
+        );
+        add("redis_cache", redis_cache);
+        add("memcached_cache", memcached_cache);
+        add("s3_cache", s3_cache);
+        add("crash_report", basic.crash_report_dir.is_some());
+        add(
+            "prometheus",
+            self.servers
+                .values()
+                .any(|item| item.prometheus_metrics.is_some()),
+        );
+        add(
+            "otel",
+            self.servers
+                .values()
+                .any(|item| item.otlp_exporter.is_some()),
+        );
+        add(
+            "mtls",
+            self.servers
+                .values()
+                .any(|item| item.tls_client_ca.is_some()),
+        );
+        add(
+            "acme",
+            self.certificates.values().any(|item| item.acme.is_some()),
+        );
+        subsystems
+    }
     /// Generate the content hash of config.
     pub fn hash(&self) -> Result<String> {
         let mut lines = vec![];
@@ -814,6 +1201,104 @@ impl PingapConf {
         let hash = crc32fast::hash(lines.join("\n").as_bytes());
         Ok(format!("{:X}", hash))
     }
+    /// Update the config by name, the data is json of the category config.
+    pub fn update(
+        &mut self,
+        category: &str,
+        name: &str,
+        data: &[u8],
+    ) -> Result<()> {
+        let key = name.to_string();
+        let map_err = |e| Error::Json { source: e };
+        match category {
+            CATEGORY_UPSTREAM => {
+                let upstream: UpstreamConf =
+                    serde_json::from_slice(data).map_err(map_err)?;
+                self.upstreams.insert(key, upstream);
+            },
+            CATEGORY_LOCATION => {
+                let location: LocationConf =
+                    serde_json::from_slice(data).map_err(map_err)?;
+                self.locations.insert(key, location);
+            },
+            CATEGORY_SERVER => {
+                let server: ServerConf =
+                    serde_json::from_slice(data).map_err(map_err)?;
+                self.servers.insert(key, server);
+            },
+            CATEGORY_PLUGIN => {
+                let plugin: PluginConf =
+                    serde_json::from_slice(data).map_err(map_err)?;
+                self.plugins.insert(key, plugin);
+            },
+            CATEGORY_CERTIFICATE => {
+                let certificate: CertificateConf =
+                    serde_json::from_slice(data).map_err(map_err)?;
+                self.certificates.insert(key, certificate);
+            },
+            CATEGORY_STORAGE => {
+                let storage: StorageConf =
+                    serde_json::from_slice(data).map_err(map_err)?;
+                self.storages.insert(key, storage);
+            },
+            _ => {
+                self.basic = serde_json::from_slice(data).map_err(map_err)?;
+            },
+        };
+        Ok(())
+    }
+    /// Get the json value of config by name,
+    /// the basic config is returned for basic category.
+    pub fn get_item(
+        &self,
+        category: &str,
+        name: &str,
+    ) -> Option<serde_json::Value> {
+        let value = match category {
+            CATEGORY_UPSTREAM => {
+                serde_json::to_value(self.upstreams.get(name)?)
+            },
+            CATEGORY_LOCATION => {
+                serde_json::to_value(self.locations.get(name)?)
+            },
+            CATEGORY_SERVER => serde_json::to_value(self.servers.get(name)?),
+            CATEGORY_PLUGIN => serde_json::to_value(self.plugins.get(name)?),
+            CATEGORY_CERTIFICATE => {
+                serde_json::to_value(self.certificates.get(name)?)
+            },
+            CATEGORY_STORAGE => serde_json::to_value(self.storages.get(name)?),
+            CATEGORY_BASIC => serde_json::to_value(&self.basic),
+            _ => return None,
+        };
+        value.ok()
+    }
+    /// Get the version of config by name, it's changed if the config is
+    /// modified. The keys of json object are sorted, so it's stable.
+    pub fn get_version(&self, category: &str, name: &str) -> Option<String> {
+        let value = self.get_item(category, name)?;
+        Some(format!(
+            "{:X}",
+            crc32fast::hash(value.to_string().as_bytes())
+        ))
+    }
+    /// Patch the config by name with json merge patch(rfc 7386),
+    /// the null value removes the field.
+    pub fn patch(
+        &mut self,
+        category: &str,
+        name: &str,
+        data: &[u8],
+    ) -> Result<()> {
+        let Some(mut value) = self.get_item(category, name) else {
+            return Err(Error::Invalid {
+                message: format!("{category}({name}) is not found"),
+            });
+        };
+        let patch: serde_json::Value = serde_json::from_slice(data)
+            .map_err(|e| Error::Json { source: e })?;
+        merge_json_patch(&mut value, patch);
+        self.update(category, name, value.to_string().as_bytes())
+    }
     /// Remove the config by name.
     pub fn remove(&mut self, category: &str, name: &str) -> Result<()> {
         match category {
@@ -1000,6 +1485,52 @@ impl PingapConf {
 
         (category_list, diff_result)
     }
+    /// Get the structured changes from current config to other,
+    /// the action is one of add, remove and modify.
+    pub fn changes(&self, other: &PingapConf) -> Vec<ConfigChange> {
+        let current_descriptions = self.descriptions();
+        let new_descriptions = other.descriptions();
+        let new_change = |action, item: &Description, lines| {
+            // the name of description is category:name
+            let name = item
+                .name
+                .split_once(':')
+                .map(|(_, name)| name)
+                .unwrap_or(&item.name);
+            ConfigChange {
+                action,
+                category: item.category.clone(),
+                name: name.to_string(),
+                lines,
+            }
+        };
+        let mut changes = vec![];
+        for item in current_descriptions.iter() {
+            let Some(new_item) =
+                new_descriptions.iter().find(|d| d.name == item.name)
+            else {
+                changes.push(new_change("remove", item, vec![]));
+                continue;
+            };
+            let lines: Vec<String> = diff::lines(&item.data, &new_item.data)
+                .into_iter()
+                .filter_map(|diff| match diff {
+                    diff::Result::Left(l) => Some(format!("-{l}")),
+                    diff::Result::Right(r) => Some(format!("+{r}")),
+                    _ => None,
+                })
+                .collect();
+            if !lines.is_empty() {
+                changes.push(new_change("modify", item, lines));
+            }
+        }
+        for new_item in new_descriptions.iter() {
+            if !current_descriptions.iter().any(|d| d.name == new_item.name) {
+                changes.push(new_change("add", new_item, vec![]));
+            }
+        }
+        changes
+    }
 }
 
 static CURRENT_CONFIG: Lazy<ArcSwap<PingapConf>> =
@@ -1045,8 +1576,9 @@ pub fn get_config_hash() -> String {
 #[cfg(test)]
 mod tests {
     use super::{
-        get_app_name, get_config_hash, set_app_name, set_current_config,
-        validate_cert, BasicConf, CertificateConf, PluginStep,
+        get_app_name, get_config_hash, replace_env_placeholders, set_app_name,
+        set_current_config, validate_cert, BasicConf, CertificateConf,
+        PluginStep,
     };
     use super::{
         LocationConf, PingapConf, PluginCategory, ServerConf, UpstreamConf,
@@ -1056,6 +1588,57 @@ mod tests {
     use pretty_assertions::assert_eq;
     use serde::{Deserialize, Serialize};
     use std::str::FromStr;
+    use std::time::Duration;
+
+    #[test]
+    fn test_replace_env_placeholders() {
+        std::env::set_var("PINGAP_TEST_UPSTREAM", "127.0.0.1:5000");
+        std::env::set_var("PINGAP_TEST_EMPTY", "");
+        assert_eq!(
+            "127.0.0.1:5000",
+            replace_env_placeholders("${PINGAP_TEST_UPSTREAM}")
+        );
+        assert_eq!(
+            "http://127.0.0.1:5000/api",
+            replace_env_placeholders("http://${PINGAP_TEST_UPSTREAM}/api")
+        );
+        assert_eq!(
+            "127.0.0.1:5001",
+            replace_env_placeholders("${PINGAP_TEST_EMPTY:-127.0.0.1:5001}")
+        );
+        assert_eq!(
+            "127.0.0.1:5002",
+            replace_env_placeholders("${PINGAP_TEST_UNSET:-127.0.0.1:5002}")
+        );
+        assert_eq!(
+            "${PINGAP_TEST_UNSET}",
+            replace_env_placeholders("${PINGAP_TEST_UNSET}")
+        );
+        assert_eq!("${a b}${", replace_env_placeholders("${a b}${"));
+
+        let conf = PingapConf::new(
+            br#"[upstreams.charts]
+addrs = ["${PINGAP_TEST_UPSTREAM}"]
+"#,
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            vec!["127.0.0.1:5000".to_string()],
+            conf.upstreams.get("charts").unwrap().addrs
+        );
+        let conf = PingapConf::new(
+            br#"[upstreams.charts]
+addrs = ["${PINGAP_TEST_UPSTREAM}"]
+"#,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            vec!["${PINGAP_TEST_UPSTREAM}".to_string()],
+            conf.upstreams.get("charts").unwrap().addrs
+        );
+    }
 
     #[test]
     fn test_plugin_step() {
@@ -1485,6 +2068,21 @@ work_stealing = true
 
         let auth_token = conf.get_storage_value("authToken").unwrap();
         assert_eq!("47.107.66.241", auth_token);
+
+        let values = conf.get_all_toml(false).unwrap();
+        assert_eq!(7, values.len());
+        assert_eq!("/basic.toml", values[0].0);
+        let values = conf.get_all_toml(true).unwrap();
+        assert_eq!(
+            true,
+            values
+                .iter()
+                .any(|(path, _)| path == "/upstreams/diving.toml")
+        );
+        assert_eq!(
+            true,
+            values.iter().all(|(path, _)| path != "/upstreams.toml")
+        );
     }
 
     #[test]
@@ -1519,6 +2117,112 @@ basic
 "###,
             value.1.join("\n")
         );
+
+        let changes: Vec<String> = conf
+            .changes(&other)
+            .iter()
+            .map(|item| {
+                format!(
+                    "{}:{}:{}:{}",
+                    item.action,
+                    item.category,
+                    item.name,
+                    item.lines.join(",")
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                "modify:basic:basic:-threads = 1,+threads = 5".to_string(),
+                "remove:upstream:diving:".to_string(),
+                "add:server:github:".to_string(),
+            ],
+            changes
+        );
+        assert_eq!(
+            "modify basic(basic)\n  -threads = 1\n  +threads = 5",
+            conf.changes(&other)[0].to_string()
+        );
+    }
+
+    #[test]
+    fn test_config_templates() {
+        let data = r###"
+[storages.timeout]
+category = "template"
+value = """
+connection_timeout = "10s"
+read_timeout = "30s"
+"""
+
+[storages.upstream]
+category = "template"
+value = """
+includes = ["timeout", "upstream"]
+addrs = ["127.0.0.1:5000"]
+read_timeout = "10s"
+"""
+
+[upstreams.charts]
+includes = ["upstream"]
+read_timeout = "5s"
+
+[upstreams.diving]
+includes = ["upstream"]
+addrs = ["127.0.0.1:5001"]
+"###;
+        let conf = PingapConf::new(data.as_bytes(), true).unwrap();
+        let charts = conf.upstreams.get("charts").unwrap();
+        assert_eq!(vec!["127.0.0.1:5000".to_string()], charts.addrs);
+        assert_eq!(Some(Duration::from_secs(10)), charts.connection_timeout);
+        assert_eq!(Some(Duration::from_secs(5)), charts.read_timeout);
+        assert_eq!(None, charts.includes);
+        let diving = conf.upstreams.get("diving").unwrap();
+        assert_eq!(vec!["127.0.0.1:5001".to_string()], diving.addrs);
+        assert_eq!(Some(Duration::from_secs(10)), diving.read_timeout);
+
+        // the raw config is validated with templates
+        let conf = PingapConf::new(data.as_bytes(), false).unwrap();
+        assert_eq!(
+            true,
+            conf.upstreams.get("charts").unwrap().addrs.is_empty()
+        );
+        assert_eq!(true, conf.validate().is_ok());
+    }
+
+    #[test]
+    fn test_config_patch() {
+        let toml_data = include_bytes!("../../conf/pingap.toml");
+        let mut conf =
+            PingapConf::new(toml_data.to_vec().as_slice(), false).unwrap();
+        let version = conf.get_version(CATEGORY_UPSTREAM, "charts").unwrap();
+        assert_eq!(
+            version,
+            conf.clone()
+                .get_version(CATEGORY_UPSTREAM, "charts")
+                .unwrap()
+        );
+        assert_eq!(true, conf.get_version(CATEGORY_UPSTREAM, "abc").is_none());
+
+        conf.patch(
+            CATEGORY_UPSTREAM,
+            "charts",
+            br#"{"addrs":["127.0.0.1:5001"],"remark":null}"#,
+        )
+        .unwrap();
+        let upstream = conf.upstreams.get("charts").unwrap();
+        assert_eq!(vec!["127.0.0.1:5001".to_string()], upstream.addrs);
+        assert_eq!(true, upstream.remark.is_none());
+        assert_ne!(
+            version,
+            conf.get_version(CATEGORY_UPSTREAM, "charts").unwrap()
+        );
+
+        let result = conf.patch(CATEGORY_UPSTREAM, "abc", b"{}");
+        assert_eq!(
+            "Invalid error upstream(abc) is not found",
+            result.err().unwrap().to_string()
+        );
     }
 
     #[test]
@@ -12,12 +12,16 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use super::{convert_config, is_config_file, CATEGORY_ALL};
 use super::{ConfigStorage, Error, LoadConfigOptions, Result};
 use super::{Observer, PingapConf};
 use crate::util;
 use async_trait::async_trait;
-use etcd_client::{Client, ConnectOptions, GetOptions, WatchOptions};
+use etcd_client::{
+    Client, ConnectOptions, GetOptions, Txn, TxnOp, WatchOptions,
+};
 use humantime::parse_duration;
+use std::collections::HashSet;
 use substring::Substring;
 
 pub struct EtcdStorage {
@@ -91,6 +95,39 @@ impl EtcdStorage {
             .await
             .map_err(|e| Error::Etcd { source: e })
     }
+    /// Save all items of config in a transaction, the config keys
+    /// which aren't in the config are deleted, e.g. the removed items.
+    async fn save_all_config(&self, conf: &PingapConf) -> Result<()> {
+        let mut c = self.connect().await?;
+        let mut keys = HashSet::new();
+        let mut ops = vec![];
+        for (path, value) in conf.get_all_toml(self.separation)? {
+            if value.is_empty() {
+                continue;
+            }
+            let key = util::path_join(&self.path, &path);
+            keys.insert(key.clone());
+            ops.push(TxnOp::put(key, value, None));
+        }
+        let arr = c
+            .get(
+                self.path.as_bytes(),
+                Some(GetOptions::new().with_prefix().with_keys_only()),
+            )
+            .await
+            .map_err(|e| Error::Etcd { source: e })?
+            .take_kvs();
+        for item in arr {
+            let key = String::from_utf8_lossy(item.key()).to_string();
+            if is_config_file(&key) && !keys.contains(&key) {
+                ops.push(TxnOp::delete(key, None));
+            }
+        }
+        c.txn(Txn::new().and_then(ops))
+            .await
+            .map_err(|e| Error::Etcd { source: e })?;
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -108,7 +145,12 @@ impl ConfigStorage for EtcdStorage {
             .take_kvs();
         let mut buffer = vec![];
         for item in arr {
-            buffer.extend(item.value());
+            let key = String::from_utf8_lossy(item.key()).to_string();
+            // skip the data which isn't config, e.g. history
+            if !is_config_file(&key) {
+                continue;
+            }
+            buffer.extend(convert_config(&key, item.value())?);
             buffer.push(0x0a);
         }
         PingapConf::new(buffer.as_slice(), replace_include)
@@ -121,6 +163,9 @@ impl ConfigStorage for EtcdStorage {
         name: Option<&str>,
     ) -> Result<()> {
         conf.validate()?;
+        if category == CATEGORY_ALL {
+            return self.save_all_config(conf).await;
+        }
         let (path, toml_value) = if self.separation && name.is_some() {
             conf.get_toml(category, name)?
         } else {
@@ -155,6 +200,7 @@ impl ConfigStorage for EtcdStorage {
             .map_err(|e| Error::Etcd { source: e })?;
         Ok(Observer {
             etcd_watch_stream: Some(stream),
+            ..Default::default()
         })
     }
     async fn save(&self, key: &str, data: &[u8]) -> Result<()> {
@@ -12,15 +12,216 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use super::{convert_config, format_config, is_config_file, CATEGORY_ALL};
 use super::{ConfigStorage, Error, LoadConfigOptions, PingapConf, Result};
 use crate::util;
 use async_trait::async_trait;
+use futures::future::BoxFuture;
 use futures_util::TryFutureExt;
 use glob::glob;
-use std::path::Path;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use tokio::fs;
+use toml::{Table, Value};
 use tracing::debug;
 
+static INCLUDES_KEY: &str = "includes";
+
+/// Merge the items of source into target by category, the item of
+/// source overrides the same one of target.
+fn merge_toml_table(target: &mut Table, source: Table) {
+    for (key, value) in source {
+        match (target.get_mut(&key), value) {
+            (Some(Value::Table(current)), Value::Table(items)) => {
+                current.extend(items);
+            },
+            (_, value) => {
+                target.insert(key, value);
+            },
+        }
+    }
+}
+
+/// Take the include patterns from the top level of config.
+fn take_includes(table: &mut Table) -> Result<Vec<String>> {
+    let Some(value) = table.remove(INCLUDES_KEY) else {
+        return Ok(vec![]);
+    };
+    let Some(values) = value.as_array() else {
+        return Err(Error::Invalid {
+            message: "includes should be an array of file pattern".to_string(),
+        });
+    };
+    Ok(values
+        .iter()
+        .filter_map(|item| item.as_str())
+        .map(|item| item.to_string())
+        .collect())
+}
+
+/// Read the config file, the yaml or json is converted to toml.
+async fn read_config_file(file: &Path) -> Result<Vec<u8>> {
+    let name = file.to_string_lossy().to_string();
+    let buf = fs::read(file).await.map_err(|e| Error::Io {
+        source: e,
+        file: name.clone(),
+    })?;
+    convert_config(&name, &buf)
+}
+
+async fn read_toml_table(file: &Path) -> Result<Table> {
+    let buf = read_config_file(file).await?;
+    toml::from_str(&String::from_utf8_lossy(&buf))
+        .map_err(|e| Error::De { source: e })
+}
+
+/// Load the files matched by the include patterns, the relative pattern
+/// is based on the directory of file. The patterns are merged in order,
+/// and the files of one pattern are merged in alphabetical order.
+async fn load_includes(
+    file: &Path,
+    includes: &[String],
+    visited: &mut HashSet<PathBuf>,
+) -> Result<Table> {
+    let dir = file.parent().unwrap_or(Path::new(""));
+    let mut table = Table::new();
+    for pattern in includes.iter() {
+        let pattern = if Path::new(pattern).is_absolute() {
+            pattern.to_string()
+        } else {
+            dir.join(pattern).to_string_lossy().to_string()
+        };
+        let mut files = vec![];
+        for entry in glob(&pattern).map_err(|e| Error::Pattern {
+            source: e,
+            path: pattern.clone(),
+        })? {
+            files.push(entry.map_err(|e| Error::Glob { source: e })?);
+        }
+        files.sort();
+        for f in files {
+            merge_toml_table(&mut table, load_toml_file(f, visited).await?);
+        }
+    }
+    Ok(table)
+}
+
+/// Load the toml file with its includes, the items of file override
+/// the included ones. The file which is already loaded is skipped.
+fn load_toml_file(
+    file: PathBuf,
+    visited: &mut HashSet<PathBuf>,
+) -> BoxFuture<'_, Result<Table>> {
+    Box::pin(async move {
+        let key = std::fs::canonicalize(&file).unwrap_or(file.clone());
+        if !visited.insert(key) {
+            return Ok(Table::new());
+        }
+        debug!(filename = format!("{file:?}"), "load config");
+        let mut current = read_toml_table(&file).await?;
+        let includes = take_includes(&mut current)?;
+        let mut table = load_includes(&file, &includes, visited).await?;
+        merge_toml_table(&mut table, current);
+        Ok(table)
+    })
+}
+
+fn to_toml_table(conf: &PingapConf) -> Result<Table> {
+    let ping_conf =
+        toml::to_string_pretty(&conf).map_err(|e| Error::Ser { source: e })?;
+    toml::from_str(&ping_conf).map_err(|e| Error::De { source: e })
+}
+
+/// Remove the items which are the same as the included ones,
+/// so they are still managed by the include files.
+async fn remove_included_items(
+    file: &Path,
+    includes: &[String],
+    values: &mut Table,
+) -> Result<()> {
+    let mut visited = HashSet::new();
+    visited.insert(std::fs::canonicalize(file).unwrap_or(file.to_path_buf()));
+    let included = load_includes(file, includes, &mut visited).await?;
+    let data =
+        toml::to_string(&included).map_err(|e| Error::Ser { source: e })?;
+    // convert to the same format as the saved config
+    let included = to_toml_table(&PingapConf::new(data.as_bytes(), false)?)?;
+    for (category, items) in included.iter() {
+        let (Some(Value::Table(current)), Some(items)) =
+            (values.get_mut(category), items.as_table())
+        else {
+            continue;
+        };
+        for (name, value) in items.iter() {
+            if current.get(name) == Some(value) {
+                current.remove(name);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Get the config files of directory, they are sorted by path.
+fn get_config_files(dir: &str) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for ext in ["toml", "yaml", "yml", "json"] {
+        let pattern = format!("{dir}/**/*.{ext}");
+        for entry in glob(&pattern).map_err(|e| Error::Pattern {
+            source: e,
+            path: pattern.clone(),
+        })? {
+            files.push(entry.map_err(|e| Error::Glob { source: e })?);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Get the existing files of the config path in all formats,
+/// e.g. `upstreams.yaml` and `upstreams.json` of `upstreams.toml`.
+fn get_existing_files(filepath: &str) -> Vec<String> {
+    let stem = filepath.rsplit_once('.').map_or(filepath, |(stem, _)| stem);
+    ["toml", "yaml", "yml", "json"]
+        .iter()
+        .map(|ext| format!("{stem}.{ext}"))
+        .filter(|file| Path::new(file).is_file())
+        .collect()
+}
+
+/// Write the config file, it's removed if the value is empty.
+/// The value is written in the format of the existing file, and the
+/// duplicate files of other formats are removed. It returns the path
+/// of the written file.
+async fn write_config_file(filepath: &str, value: &str) -> Result<String> {
+    let mut files = get_existing_files(filepath).into_iter();
+    let target = if value.is_empty() {
+        filepath.to_string()
+    } else {
+        files.next().unwrap_or_else(|| filepath.to_string())
+    };
+    for file in files {
+        fs::remove_file(&file).await.map_err(|e| Error::Io {
+            source: e,
+            file: file.clone(),
+        })?;
+    }
+    if value.is_empty() {
+        return Ok(target);
+    }
+    if let Some(p) = Path::new(&target).parent() {
+        fs::create_dir_all(p).await.map_err(|e| Error::Io {
+            source: e,
+            file: target.clone(),
+        })?;
+    }
+    let value = format_config(&target, value.to_string())?;
+    fs::write(&target, value).await.map_err(|e| Error::Io {
+        source: e,
+        file: target.clone(),
+    })?;
+    Ok(target)
+}
+
 pub struct FileStorage {
     path: String,
     separation: bool,
@@ -46,6 +247,36 @@ impl FileStorage {
             separation,
         })
     }
+    /// Get the file path of key, it's in the directory of config file
+    /// if the config is a single file.
+    fn get_key_path(&self, key: &str) -> String {
+        if is_config_file(&self.path) {
+            let dir = Path::new(&self.path).parent().unwrap_or(Path::new(""));
+            return util::path_join(&dir.to_string_lossy(), key);
+        }
+        util::path_join(&self.path, key)
+    }
+    /// Save all items of config to the directory, the config files
+    /// which aren't in the config are removed, e.g. the removed items.
+    async fn save_all_config(&self, conf: &PingapConf) -> Result<()> {
+        let values = conf.get_all_toml(self.separation)?;
+        let mut files = HashSet::new();
+        for (path, value) in values.iter() {
+            let filepath = util::path_join(&self.path, path);
+            let filepath = write_config_file(&filepath, value).await?;
+            files.insert(PathBuf::from(filepath));
+        }
+        for file in get_config_files(&self.path)? {
+            if files.contains(&file) {
+                continue;
+            }
+            fs::remove_file(&file).await.map_err(|e| Error::Io {
+                source: e,
+                file: file.to_string_lossy().to_string(),
+            })?;
+        }
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -58,7 +289,7 @@ impl ConfigStorage for FileStorage {
             return Ok(PingapConf::default());
         }
         // create dir
-        if !filepath.ends_with(".toml") && !dir.exists() {
+        if !is_config_file(&filepath) && !dir.exists() {
             fs::create_dir_all(&filepath)
                 .map_err(|e| Error::Io {
                     source: e,
@@ -69,28 +300,24 @@ impl ConfigStorage for FileStorage {
 
         let mut data = vec![];
         if dir.is_dir() {
-            for entry in
-                glob(&format!("{filepath}/**/*.toml")).map_err(|e| {
-                    Error::Pattern {
-                        source: e,
-                        path: filepath,
-                    }
-                })?
-            {
-                let f = entry.map_err(|e| Error::Glob { source: e })?;
-                let mut buf = fs::read(&f).await.map_err(|e| Error::Io {
-                    source: e,
-                    file: f.to_string_lossy().to_string(),
-                })?;
+            for f in get_config_files(&filepath)? {
+                let mut buf = read_config_file(&f).await?;
                 debug!(filename = format!("{f:?}"), "load config");
                 data.append(&mut buf);
                 data.push(0x0a);
             }
         } else {
-            let mut buf = fs::read(&filepath).await.map_err(|e| Error::Io {
-                source: e,
-                file: filepath,
-            })?;
+            let mut buf = read_config_file(dir).await?;
+            let mut table: Table =
+                toml::from_str(&String::from_utf8_lossy(&buf))
+                    .map_err(|e| Error::De { source: e })?;
+            if table.contains_key(INCLUDES_KEY) {
+                table = load_toml_file(dir.to_path_buf(), &mut HashSet::new())
+                    .await?;
+                buf = toml::to_string(&table)
+                    .map_err(|e| Error::Ser { source: e })?
+                    .into_bytes();
+            }
             data.append(&mut buf);
         }
         PingapConf::new(data.as_slice(), opts.replace_include)
@@ -105,7 +332,7 @@ impl ConfigStorage for FileStorage {
         let filepath = self.path.clone();
         conf.validate()?;
         let path = Path::new(&filepath);
-        if !path.exists() && path.extension().unwrap_or_default() == "toml" {
+        if !path.exists() && is_config_file(&filepath) {
             fs::File::create(&path).await.map_err(|e| Error::Io {
                 source: e,
                 file: filepath.clone(),
@@ -113,10 +340,14 @@ impl ConfigStorage for FileStorage {
         }
 
         if path.is_file() {
-            let ping_conf = toml::to_string_pretty(&conf)
-                .map_err(|e| Error::Ser { source: e })?;
-            let mut values: toml::Table = toml::from_str(&ping_conf)
-                .map_err(|e| Error::De { source: e })?;
+            let mut values = to_toml_table(conf)?;
+            // keep the includes of config file
+            let includes = take_includes(
+                &mut read_toml_table(path).await.unwrap_or_default(),
+            )?;
+            if !includes.is_empty() {
+                remove_included_items(path, &includes, &mut values).await?;
+            }
             let mut omit_keys = vec![];
             for key in values.keys() {
                 if let Some(value) = values.get(key) {
@@ -128,47 +359,35 @@ impl ConfigStorage for FileStorage {
             for key in omit_keys {
                 values.remove(&key);
             }
+            if !includes.is_empty() {
+                values.insert(
+                    INCLUDES_KEY.to_string(),
+                    Value::Array(
+                        includes.into_iter().map(Value::from).collect(),
+                    ),
+                );
+            }
             let ping_conf = toml::to_string_pretty(&values)
                 .map_err(|e| Error::Ser { source: e })?;
+            let ping_conf = format_config(&filepath, ping_conf)?;
             return fs::write(path, ping_conf).await.map_err(|e| Error::Io {
                 source: e,
                 file: filepath,
             });
         }
+        if category == CATEGORY_ALL {
+            return self.save_all_config(conf).await;
+        }
         let (path, toml_value) = if self.separation && name.is_some() {
             conf.get_toml(category, name)?
         } else {
             conf.get_toml(category, None)?
         };
 
-        let filepath = util::path_join(&filepath, &path);
-        let target_file = Path::new(&filepath);
-        if let Some(p) = Path::new(&target_file).parent() {
-            fs::create_dir_all(p).await.map_err(|e| Error::Io {
-                source: e,
-                file: filepath.clone(),
-            })?;
-        }
-        if toml_value.is_empty() {
-            if target_file.exists() {
-                fs::remove_file(&filepath).await.map_err(|e| Error::Io {
-                    source: e,
-                    file: filepath,
-                })?;
-            }
-        } else {
-            fs::write(&filepath, toml_value)
-                .await
-                .map_err(|e| Error::Io {
-                    source: e,
-                    file: filepath,
-                })?;
-        }
-
-        Ok(())
+        write_config_file(&util::path_join(&filepath, &path), &toml_value).await
     }
     async fn save(&self, key: &str, data: &[u8]) -> Result<()> {
-        let key = util::path_join(&self.path, key);
+        let key = self.get_key_path(key);
         let path = Path::new(&key);
         if let Some(p) = path.parent() {
             fs::create_dir_all(p).await.map_err(|e| Error::Io {
@@ -183,7 +402,7 @@ impl ConfigStorage for FileStorage {
         Ok(())
     }
     async fn load(&self, key: &str) -> Result<Vec<u8>> {
-        let key = util::path_join(&self.path, key);
+        let key = self.get_key_path(key);
         let path = Path::new(&key);
         let buf = fs::read(path).await.map_err(|e| Error::Io {
             source: e,
@@ -253,4 +472,157 @@ mod tests {
             .unwrap();
         assert_eq!(current_conf.hash().unwrap(), conf.hash().unwrap());
     }
+
+    #[tokio::test]
+    async fn test_file_storage_includes() {
+        let dir = format!("/tmp/{}", nanoid!(16));
+        tokio::fs::create_dir_all(format!("{dir}/conf.d"))
+            .await
+            .unwrap();
+        let file = format!("{dir}/pingap.toml");
+        tokio::fs::write(
+            &file,
+            r#"includes = ["conf.d/*.toml"]
+
+[upstreams.charts]
+addrs = ["127.0.0.1:5000"]
+"#,
+        )
+        .await
+        .unwrap();
+        tokio::fs::write(
+            format!("{dir}/conf.d/a.toml"),
+            r#"[upstreams.charts]
+addrs = ["127.0.0.1:5001"]
+
+[upstreams.diving]
+addrs = ["127.0.0.1:5002"]
+"#,
+        )
+        .await
+        .unwrap();
+        tokio::fs::write(
+            format!("{dir}/conf.d/b.toml"),
+            r#"[upstreams.diving]
+addrs = ["127.0.0.1:5003"]
+"#,
+        )
+        .await
+        .unwrap();
+
+        let storage = FileStorage::new(&file).unwrap();
+        let conf = storage
+            .load_config(LoadConfigOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(2, conf.upstreams.len());
+        assert_eq!(
+            vec!["127.0.0.1:5000".to_string()],
+            conf.upstreams.get("charts").unwrap().addrs
+        );
+        assert_eq!(
+            vec!["127.0.0.1:5003".to_string()],
+            conf.upstreams.get("diving").unwrap().addrs
+        );
+
+        // the included items aren't saved to the config file
+        storage
+            .save_config(&conf, CATEGORY_UPSTREAM, None)
+            .await
+            .unwrap();
+        let data = tokio::fs::read_to_string(&file).await.unwrap();
+        assert_eq!(true, data.contains("conf.d/*.toml"));
+        assert_eq!(false, data.contains("127.0.0.1:5003"));
+        let current_conf = storage
+            .load_config(LoadConfigOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(current_conf.hash().unwrap(), conf.hash().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_file_storage_yaml() {
+        let file = format!("/tmp/{}.yaml", nanoid!(16));
+        tokio::fs::write(
+            &file,
+            r#"upstreams:
+  charts:
+    addrs:
+      - "127.0.0.1:5000"
+"#,
+        )
+        .await
+        .unwrap();
+        let storage = FileStorage::new(&file).unwrap();
+        let conf = storage
+            .load_config(LoadConfigOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            vec!["127.0.0.1:5000".to_string()],
+            conf.upstreams.get("charts").unwrap().addrs
+        );
+
+        // the config is saved as yaml
+        storage
+            .save_config(&conf, CATEGORY_UPSTREAM, None)
+            .await
+            .unwrap();
+        let current_conf = storage
+            .load_config(LoadConfigOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(current_conf.hash().unwrap(), conf.hash().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_file_storage_dir_format() {
+        let dir = format!("/tmp/{}", nanoid!(16));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let file = format!("{dir}/upstreams.yaml");
+        tokio::fs::write(
+            &file,
+            r#"upstreams:
+  charts:
+    addrs:
+      - "127.0.0.1:5000"
+"#,
+        )
+        .await
+        .unwrap();
+        // the duplicate file of other format
+        tokio::fs::write(
+            format!("{dir}/upstreams.json"),
+            r#"{"upstreams":{"diving":{"addrs":["127.0.0.1:5001"]}}}"#,
+        )
+        .await
+        .unwrap();
+        let storage = FileStorage::new(&dir).unwrap();
+        let conf = storage
+            .load_config(LoadConfigOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(2, conf.upstreams.len());
+
+        // the config is saved in its original format and path
+        storage
+            .save_config(&conf, CATEGORY_UPSTREAM, None)
+            .await
+            .unwrap();
+        let data = tokio::fs::read_to_string(&file).await.unwrap();
+        assert_eq!(true, data.contains("127.0.0.1:5001"));
+        assert_eq!(
+            false,
+            std::path::Path::new(&format!("{dir}/upstreams.toml")).exists()
+        );
+        assert_eq!(
+            false,
+            std::path::Path::new(&format!("{dir}/upstreams.json")).exists()
+        );
+        let current_conf = storage
+            .load_config(LoadConfigOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(current_conf.hash().unwrap(), conf.hash().unwrap());
+    }
 }
@@ -0,0 +1,167 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    get_config_storage, ConfigStorage, Error, PingapConf, Result, CATEGORY_ALL,
+};
+use crate::util;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+// the keys have no extension, so they aren't loaded as config
+static HISTORY_INDEX_KEY: &str = "pingap-history/index";
+static HISTORY_PREFIX: &str = "pingap-history";
+// the snapshots are saved in the slots by turns
+const MAX_HISTORY_VERSIONS: u64 = 20;
+
+// avoid saving the history concurrently
+static HISTORY_LOCK: Lazy<tokio::sync::Mutex<()>> =
+    Lazy::new(|| tokio::sync::Mutex::new(()));
+
+/// The version of config snapshot.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ConfigVersion {
+    pub id: u64,
+    pub hash: String,
+    pub summary: String,
+    pub created_at: u64,
+}
+
+fn get_snapshot_key(id: u64) -> String {
+    format!("{HISTORY_PREFIX}/{}", id % MAX_HISTORY_VERSIONS)
+}
+
+fn get_storage() -> Result<&'static (dyn ConfigStorage + Sync + Send)> {
+    get_config_storage().ok_or_else(|| Error::Invalid {
+        message: "storage is not inited".to_string(),
+    })
+}
+
+async fn load_versions(
+    storage: &(dyn ConfigStorage + Sync + Send),
+) -> Result<Vec<ConfigVersion>> {
+    // the index doesn't exist before the first snapshot
+    let data = storage.load(HISTORY_INDEX_KEY).await.unwrap_or_default();
+    if data.is_empty() {
+        return Ok(vec![]);
+    }
+    serde_json::from_slice(&data).map_err(|e| Error::Json { source: e })
+}
+
+/// Add the version to the versions, the oldest one is removed
+/// if the count exceeds the limit. It returns none if the hash
+/// is the same as the latest version.
+fn add_version(
+    versions: &mut Vec<ConfigVersion>,
+    hash: &str,
+    summary: &str,
+) -> Option<ConfigVersion> {
+    if versions.last().map(|item| item.hash.as_str()) == Some(hash) {
+        return None;
+    }
+    let version = ConfigVersion {
+        id: versions.last().map(|item| item.id + 1).unwrap_or(1),
+        hash: hash.to_string(),
+        summary: summary.to_string(),
+        created_at: util::now().as_secs(),
+    };
+    versions.push(version.clone());
+    let count = versions.len() as u64;
+    if count > MAX_HISTORY_VERSIONS {
+        versions.drain(..(count - MAX_HISTORY_VERSIONS) as usize);
+    }
+    Some(version)
+}
+
+/// Save the snapshot of config as a new version, it's skipped if the
+/// config is the same as the latest version.
+pub async fn save_history(conf: &PingapConf, summary: &str) -> Result<()> {
+    let storage = get_storage()?;
+    let _guard = HISTORY_LOCK.lock().await;
+    let mut versions = load_versions(storage).await?;
+    let Some(version) = add_version(&mut versions, &conf.hash()?, summary)
+    else {
+        return Ok(());
+    };
+    let data =
+        toml::to_string_pretty(conf).map_err(|e| Error::Ser { source: e })?;
+    storage
+        .save(&get_snapshot_key(version.id), data.as_bytes())
+        .await?;
+    let data =
+        serde_json::to_vec(&versions).map_err(|e| Error::Json { source: e })?;
+    storage.save(HISTORY_INDEX_KEY, &data).await?;
+    info!(id = version.id, summary, "save config history");
+    Ok(())
+}
+
+/// List the versions of config history, the latest one is the last.
+pub async fn list_history() -> Result<Vec<ConfigVersion>> {
+    load_versions(get_storage()?).await
+}
+
+/// Get the config of the version.
+pub async fn get_history(id: u64) -> Result<PingapConf> {
+    let storage = get_storage()?;
+    let versions = load_versions(storage).await?;
+    if !versions.iter().any(|item| item.id == id) {
+        return Err(Error::Invalid {
+            message: format!("config version({id}) is not found"),
+        });
+    }
+    let data = storage.load(&get_snapshot_key(id)).await?;
+    PingapConf::new(&data, false)
+}
+
+/// Roll back the config to the version, the config is validated first,
+/// and then it's written back as a whole, so the items which don't exist
+/// in the version are removed too.
+pub async fn rollback_history(id: u64) -> Result<PingapConf> {
+    let storage = get_storage()?;
+    let conf = get_history(id).await?;
+    conf.validate()?;
+    storage.save_config(&conf, CATEGORY_ALL, None).await?;
+    save_history(&conf, &format!("rollback to version({id})")).await?;
+    Ok(conf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{add_version, get_snapshot_key, MAX_HISTORY_VERSIONS};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_add_version() {
+        let mut versions = vec![];
+        let version = add_version(&mut versions, "A", "init").unwrap();
+        assert_eq!(1, version.id);
+        assert_eq!(true, add_version(&mut versions, "A", "init").is_none());
+        let version = add_version(&mut versions, "B", "update").unwrap();
+        assert_eq!(2, version.id);
+
+        for i in 0..MAX_HISTORY_VERSIONS {
+            add_version(&mut versions, &i.to_string(), "update");
+        }
+        assert_eq!(MAX_HISTORY_VERSIONS as usize, versions.len());
+        assert_eq!(3, versions[0].id);
+        assert_eq!(MAX_HISTORY_VERSIONS + 2, versions.last().unwrap().id);
+    }
+
+    #[test]
+    fn test_get_snapshot_key() {
+        assert_eq!("pingap-history/1", get_snapshot_key(1));
+        assert_eq!("pingap-history/1", get_snapshot_key(21));
+    }
+}
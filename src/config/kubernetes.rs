@@ -0,0 +1,567 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{convert_config, is_config_file, CATEGORY_ALL};
+use super::{ConfigStorage, Error, LoadConfigOptions, Result};
+use super::{Observer, PingapConf};
+use crate::util;
+use async_trait::async_trait;
+use bytes::BytesMut;
+use humantime::parse_duration;
+use reqwest::{Method, StatusCode};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tracing::{debug, info};
+
+pub const KUBERNETES_PROTOCOL: &str = "k8s://";
+
+static SERVICE_ACCOUNT_DIR: &str =
+    "/var/run/secrets/kubernetes.io/serviceaccount";
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ObjectMeta {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    resource_version: String,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ConfigMap {
+    #[serde(default)]
+    metadata: ObjectMeta,
+    #[serde(default)]
+    data: BTreeMap<String, String>,
+    #[serde(default)]
+    binary_data: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+struct ConfigMapList {
+    #[serde(default)]
+    metadata: ObjectMeta,
+    #[serde(default)]
+    items: Vec<ConfigMap>,
+}
+
+#[derive(Deserialize, Debug)]
+struct WatchEvent {
+    #[serde(rename = "type")]
+    category: String,
+    #[serde(default)]
+    object: serde_json::Value,
+}
+
+#[derive(Clone)]
+struct KubernetesClient {
+    client: reqwest::Client,
+    api_server: String,
+    token_file: String,
+}
+
+impl KubernetesClient {
+    /// Create the request with the token of service account,
+    /// the token is read each time because it's rotated by kubelet.
+    fn request(&self, method: Method, path: &str) -> reqwest::RequestBuilder {
+        let url = format!("{}{path}", self.api_server);
+        let req = self.client.request(method, url);
+        match std::fs::read_to_string(&self.token_file) {
+            Ok(token) => req.bearer_auth(token.trim()),
+            Err(_) => req,
+        }
+    }
+}
+
+async fn check_response(resp: reqwest::Response) -> Result<reqwest::Response> {
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(resp);
+    }
+    let message = resp.text().await.unwrap_or_default();
+    Err(Error::Invalid {
+        message: format!("kubernetes api error, status: {status}, {message}"),
+    })
+}
+
+/// Convert the path of config to the key of config map,
+/// e.g. `/servers/test.toml` to `servers.test.toml`.
+fn get_data_key(path: &str) -> String {
+    path.trim_start_matches('/').replace('/', ".")
+}
+
+/// Load the config from the config maps of kubernetes, each item of data
+/// is a toml config, e.g. `basic.toml` and `locations.toml`.
+pub struct KubernetesStorage {
+    client: KubernetesClient,
+    namespace: String,
+    // the name of config map, it's required for saving config
+    name: String,
+    // the label selector of config maps, e.g. `app=pingap`
+    selector: String,
+    separation: bool,
+}
+
+impl KubernetesStorage {
+    /// Create a new kubernetes storage for config, the service account
+    /// of pod is used to access the api server.
+    /// Url: k8s://namespace/name?selector=app=pingap&timeout=10s&api_server=https://127.0.0.1:6443
+    pub fn new(value: &str) -> Result<Self> {
+        let value = value
+            .strip_prefix(KUBERNETES_PROTOCOL)
+            .unwrap_or(value)
+            .to_string();
+        let (path, query) = value.split_once('?').unwrap_or((&value, ""));
+        let (namespace, name) = path.split_once('/').unwrap_or((path, ""));
+        let mut namespace = namespace.trim().to_string();
+        if namespace.is_empty() {
+            namespace = std::fs::read_to_string(format!(
+                "{SERVICE_ACCOUNT_DIR}/namespace"
+            ))
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        }
+
+        let mut selector = "".to_string();
+        let mut timeout = Duration::from_secs(10);
+        let mut api_server = "".to_string();
+        let mut token_file = format!("{SERVICE_ACCOUNT_DIR}/token");
+        let mut ca_file = format!("{SERVICE_ACCOUNT_DIR}/ca.crt");
+        let query = util::convert_query_map(query);
+        let separation = query.contains_key("separation");
+        for (key, value) in query {
+            let value = urlencoding::decode(&value)
+                .map(|value| value.to_string())
+                .unwrap_or(value);
+            match key.as_str() {
+                "selector" => selector = value,
+                "timeout" => {
+                    if let Ok(d) = parse_duration(&value) {
+                        timeout = d;
+                    }
+                },
+                "api_server" => api_server = value,
+                "token_file" => token_file = value,
+                "ca_file" => ca_file = value,
+                _ => {},
+            }
+        }
+        if api_server.is_empty() {
+            let host = std::env::var("KUBERNETES_SERVICE_HOST")
+                .unwrap_or_else(|_| "kubernetes.default.svc".to_string());
+            let port = std::env::var("KUBERNETES_SERVICE_PORT")
+                .unwrap_or_else(|_| "443".to_string());
+            api_server = if host.contains(':') {
+                format!("https://[{host}]:{port}")
+            } else {
+                format!("https://{host}:{port}")
+            };
+        }
+        if namespace.is_empty() {
+            return Err(Error::Invalid {
+                message: "kubernetes namespace is empty".to_string(),
+            });
+        }
+        if name.is_empty() && selector.is_empty() {
+            return Err(Error::Invalid {
+                message: "config map name or selector is required".to_string(),
+            });
+        }
+
+        let mut builder = reqwest::Client::builder().timeout(timeout);
+        if let Ok(buf) = std::fs::read(&ca_file) {
+            let cert = reqwest::Certificate::from_pem(&buf)
+                .map_err(|e| Error::Request { source: e })?;
+            builder = builder.add_root_certificate(cert);
+        }
+        let client =
+            builder.build().map_err(|e| Error::Request { source: e })?;
+
+        Ok(Self {
+            client: KubernetesClient {
+                client,
+                api_server: api_server.trim_end_matches('/').to_string(),
+                token_file,
+            },
+            namespace,
+            name: name.trim().to_string(),
+            selector,
+            separation,
+        })
+    }
+    fn get_list_path(&self) -> String {
+        let mut path =
+            format!("/api/v1/namespaces/{}/configmaps", self.namespace);
+        if !self.name.is_empty() {
+            path = format!(
+                "{path}?fieldSelector={}",
+                urlencoding::encode(&format!("metadata.name={}", self.name))
+            );
+        } else {
+            path = format!(
+                "{path}?labelSelector={}",
+                urlencoding::encode(&self.selector)
+            );
+        }
+        path
+    }
+    fn get_item_path(&self) -> Result<String> {
+        if self.name.is_empty() {
+            return Err(Error::Invalid {
+                message: "config map name is required for saving".to_string(),
+            });
+        }
+        Ok(format!(
+            "/api/v1/namespaces/{}/configmaps/{}",
+            self.namespace, self.name
+        ))
+    }
+    async fn list(&self) -> Result<ConfigMapList> {
+        let resp = self
+            .client
+            .request(Method::GET, &self.get_list_path())
+            .send()
+            .await
+            .map_err(|e| Error::Request { source: e })?;
+        check_response(resp)
+            .await?
+            .json::<ConfigMapList>()
+            .await
+            .map_err(|e| Error::Request { source: e })
+    }
+    /// Save all items of config by one patch, the config keys
+    /// which aren't in the config are removed, e.g. the removed items.
+    async fn save_all_config(&self, conf: &PingapConf) -> Result<()> {
+        let mut data = serde_json::Map::new();
+        for item in self.list().await?.items {
+            if item.metadata.name != self.name {
+                continue;
+            }
+            for key in item.data.keys().filter(|key| is_config_file(key)) {
+                // the null value removes the item of data
+                data.insert(key.clone(), serde_json::Value::Null);
+            }
+        }
+        for (path, value) in conf.get_all_toml(self.separation)? {
+            if !value.is_empty() {
+                data.insert(
+                    get_data_key(&path),
+                    serde_json::Value::String(value),
+                );
+            }
+        }
+        self.patch(json!({ "data": data })).await
+    }
+    /// Update the data of config map by merge patch,
+    /// the config map is created if it doesn't exist.
+    async fn patch(&self, data: serde_json::Value) -> Result<()> {
+        let path = self.get_item_path()?;
+        let resp = self
+            .client
+            .request(Method::PATCH, &path)
+            .header("Content-Type", "application/merge-patch+json")
+            .body(data.to_string())
+            .send()
+            .await
+            .map_err(|e| Error::Request { source: e })?;
+        if resp.status() != StatusCode::NOT_FOUND {
+            check_response(resp).await?;
+            return Ok(());
+        }
+        let mut body = json!({
+            "apiVersion": "v1",
+            "kind": "ConfigMap",
+            "metadata": {
+                "name": self.name,
+                "namespace": self.namespace,
+            },
+        });
+        if let (Some(body), Some(data)) =
+            (body.as_object_mut(), data.as_object())
+        {
+            body.extend(data.clone());
+        }
+        info!(
+            namespace = self.namespace,
+            name = self.name,
+            "create config map"
+        );
+        let resp = self
+            .client
+            .request(
+                Method::POST,
+                &format!("/api/v1/namespaces/{}/configmaps", self.namespace),
+            )
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| Error::Request { source: e })?;
+        check_response(resp).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ConfigStorage for KubernetesStorage {
+    /// Load config from the config maps, the data are sorted by key.
+    async fn load_config(&self, opts: LoadConfigOptions) -> Result<PingapConf> {
+        let list = self.list().await?;
+        let mut buffer = vec![];
+        for item in list.items.iter() {
+            for (key, value) in item.data.iter() {
+                if !is_config_file(key) {
+                    continue;
+                }
+                buffer.extend(convert_config(key, value.as_bytes())?);
+                buffer.push(0x0a);
+            }
+        }
+        PingapConf::new(buffer.as_slice(), opts.replace_include)
+    }
+    /// Save config to the data of config map by category.
+    async fn save_config(
+        &self,
+        conf: &PingapConf,
+        category: &str,
+        name: Option<&str>,
+    ) -> Result<()> {
+        conf.validate()?;
+        if category == CATEGORY_ALL {
+            return self.save_all_config(conf).await;
+        }
+        let (path, toml_value) = if self.separation && name.is_some() {
+            conf.get_toml(category, name)?
+        } else {
+            conf.get_toml(category, None)?
+        };
+        let key = get_data_key(&path);
+        // the null value removes the item of data
+        let value = if toml_value.is_empty() {
+            serde_json::Value::Null
+        } else {
+            serde_json::Value::String(toml_value)
+        };
+        self.patch(json!({ "data": { key: value } })).await
+    }
+    fn support_observer(&self) -> bool {
+        true
+    }
+    async fn observe(&self) -> Result<Observer> {
+        // watch from the current version, so the existing
+        // config maps aren't treated as changes
+        let list = self.list().await?;
+        Ok(Observer {
+            kubernetes_watcher: Some(KubernetesWatcher {
+                client: self.client.clone(),
+                path: self.get_list_path(),
+                resource_version: list.metadata.resource_version,
+                resp: None,
+                buffer: BytesMut::new(),
+                failed: false,
+            }),
+            ..Default::default()
+        })
+    }
+    async fn save(&self, key: &str, data: &[u8]) -> Result<()> {
+        let key = get_data_key(key);
+        self.patch(json!({
+            "binaryData": { key: util::base64_encode(data) }
+        }))
+        .await
+    }
+    async fn load(&self, key: &str) -> Result<Vec<u8>> {
+        let key = get_data_key(key);
+        let list = self.list().await?;
+        for item in list.items.iter() {
+            if let Some(value) = item.binary_data.get(&key) {
+                return util::base64_decode(value)
+                    .map_err(|e| Error::Base64Decode { source: e });
+            }
+            if let Some(value) = item.data.get(&key) {
+                return Ok(value.as_bytes().to_vec());
+            }
+        }
+        Ok(vec![])
+    }
+}
+
+/// Watch the changes of config maps, the watch request is sent again
+/// if it's closed by the api server.
+pub struct KubernetesWatcher {
+    client: KubernetesClient,
+    path: String,
+    resource_version: String,
+    resp: Option<reqwest::Response>,
+    // the incomplete line of events
+    buffer: BytesMut,
+    failed: bool,
+}
+
+/// Parse the events of watch response, each event is a json line.
+/// The incomplete line is kept in the buffer.
+fn parse_watch_events(buffer: &mut BytesMut) -> Vec<WatchEvent> {
+    let mut events = vec![];
+    while let Some(pos) = buffer.iter().position(|c| *c == b'\n') {
+        let line = buffer.split_to(pos + 1);
+        if let Ok(event) = serde_json::from_slice::<WatchEvent>(&line) {
+            events.push(event);
+        }
+    }
+    events
+}
+
+impl KubernetesWatcher {
+    /// Wait for the change of config maps, true means changed.
+    pub async fn watch(&mut self) -> Result<bool> {
+        // avoid the busy loop if the api server is unavailable
+        if self.failed {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            self.failed = false;
+        }
+        if self.resp.is_none() {
+            let path = format!(
+                "{}&watch=true&resourceVersion={}",
+                self.path, self.resource_version
+            );
+            let result = self
+                .client
+                .request(Method::GET, &path)
+                // the watch request is kept by server for a long time
+                .timeout(Duration::from_secs(3600))
+                .send()
+                .await
+                .map_err(|e| Error::Request { source: e });
+            let resp = match result {
+                Ok(resp) => check_response(resp).await,
+                Err(e) => Err(e),
+            };
+            match resp {
+                Ok(resp) => self.resp = Some(resp),
+                Err(e) => {
+                    self.failed = true;
+                    return Err(e);
+                },
+            }
+            self.buffer.clear();
+        }
+        let Some(resp) = self.resp.as_mut() else {
+            return Ok(false);
+        };
+        let chunk = match resp.chunk().await {
+            Ok(Some(chunk)) => chunk,
+            // closed by api server, watch again
+            Ok(None) => {
+                self.resp = None;
+                return Ok(false);
+            },
+            Err(e) => {
+                self.resp = None;
+                self.failed = true;
+                return Err(Error::Request { source: e });
+            },
+        };
+        self.buffer.extend_from_slice(&chunk);
+        let mut changed = false;
+        for event in parse_watch_events(&mut self.buffer) {
+            debug!(category = event.category, "kubernetes watch event");
+            match event.category.as_str() {
+                "ADDED" | "MODIFIED" | "DELETED" => {
+                    changed = true;
+                },
+                // the version is too old, watch from the latest
+                "ERROR" => {
+                    self.resource_version = "".to_string();
+                    self.resp = None;
+                    continue;
+                },
+                _ => {},
+            }
+            if let Some(version) = event
+                .object
+                .get("metadata")
+                .and_then(|value| value.get("resourceVersion"))
+                .and_then(|value| value.as_str())
+            {
+                self.resource_version = version.to_string();
+            }
+        }
+        Ok(changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_data_key, parse_watch_events, KubernetesStorage};
+    use bytes::BytesMut;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_kubernetes_storage() {
+        let storage = KubernetesStorage::new(
+            "k8s://pingap/config?timeout=5s&api_server=https://127.0.0.1:6443/&separation=true",
+        )
+        .unwrap();
+        assert_eq!("pingap", storage.namespace);
+        assert_eq!("config", storage.name);
+        assert_eq!(true, storage.separation);
+        assert_eq!("https://127.0.0.1:6443", storage.client.api_server);
+        assert_eq!(
+            "/api/v1/namespaces/pingap/configmaps?fieldSelector=metadata.name%3Dconfig",
+            storage.get_list_path()
+        );
+
+        let storage = KubernetesStorage::new(
+            "k8s://pingap?selector=app%3Dpingap&api_server=https://127.0.0.1:6443",
+        )
+        .unwrap();
+        assert_eq!(
+            "/api/v1/namespaces/pingap/configmaps?labelSelector=app%3Dpingap",
+            storage.get_list_path()
+        );
+        assert_eq!(
+            "Invalid error config map name is required for saving",
+            storage.get_item_path().err().unwrap().to_string()
+        );
+
+        let result = KubernetesStorage::new("k8s://pingap");
+        assert_eq!(
+            "Invalid error config map name or selector is required",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_get_data_key() {
+        assert_eq!("basic.toml", get_data_key("/basic.toml"));
+        assert_eq!("servers.test.toml", get_data_key("/servers/test.toml"));
+    }
+
+    #[test]
+    fn test_parse_watch_events() {
+        let mut buffer = BytesMut::from(
+            r#"{"type":"MODIFIED","object":{"metadata":{"name":"config","resourceVersion":"10"}}}
+{"type":"BOOKMARK","object":{"metadata":{"resourceVersion":"11"}}}
+{"type":"ADD"#,
+        );
+        let events = parse_watch_events(&mut buffer);
+        assert_eq!(2, events.len());
+        assert_eq!("MODIFIED", events[0].category);
+        assert_eq!("BOOKMARK", events[1].category);
+        assert_eq!(r#"{"type":"ADD"#, std::str::from_utf8(&buffer).unwrap());
+    }
+}
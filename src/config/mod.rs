@@ -14,13 +14,19 @@
 
 use async_trait::async_trait;
 use etcd_client::WatchStream;
-use once_cell::sync::OnceCell;
+use once_cell::sync::{Lazy, OnceCell};
 use snafu::Snafu;
 use std::time::Duration;
 
+mod audit;
 mod common;
 mod etcd;
 mod file;
+mod history;
+mod kubernetes;
+mod nginx;
+mod scheduled;
+mod vault;
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -58,15 +64,26 @@ pub enum Error {
     Regex { source: regex::Error },
     #[snafu(display("Etcd error {source}"))]
     Etcd { source: etcd_client::Error },
+    #[snafu(display("Json error {source}"))]
+    Json { source: serde_json::Error },
+    #[snafu(display("Request error {source}"))]
+    Request { source: reqwest::Error },
+    #[snafu(display("Yaml error {source}"))]
+    Yaml { source: serde_yaml::Error },
 }
 type Result<T, E = Error> = std::result::Result<T, E>;
 
+#[derive(Default)]
 pub struct Observer {
     etcd_watch_stream: Option<WatchStream>,
+    kubernetes_watcher: Option<KubernetesWatcher>,
 }
 
 impl Observer {
     pub async fn watch(&mut self) -> Result<bool> {
+        if let Some(watcher) = self.kubernetes_watcher.as_mut() {
+            return watcher.watch().await;
+        }
         let sleep_time = Duration::from_secs(30);
         // no watch stream, just sleep a moment
         let Some(stream) = self.etcd_watch_stream.as_mut() else {
@@ -82,6 +99,58 @@ impl Observer {
     }
 }
 
+#[derive(Debug, PartialEq)]
+enum ConfigFormat {
+    Toml,
+    Yaml,
+    Json,
+}
+
+fn get_config_format(name: &str) -> Option<ConfigFormat> {
+    let (_, ext) = name.rsplit_once('.')?;
+    match ext.to_lowercase().as_str() {
+        "toml" => Some(ConfigFormat::Toml),
+        "yaml" | "yml" => Some(ConfigFormat::Yaml),
+        "json" => Some(ConfigFormat::Json),
+        _ => None,
+    }
+}
+
+/// Check whether it's the config file of toml, yaml or json.
+pub(crate) fn is_config_file(name: &str) -> bool {
+    get_config_format(name).is_some()
+}
+
+/// Convert the config of yaml or json to toml by the extension of name,
+/// the other config is returned as it is.
+pub(crate) fn convert_config(name: &str, data: &[u8]) -> Result<Vec<u8>> {
+    let table: toml::Table = match get_config_format(name) {
+        Some(ConfigFormat::Yaml) => serde_yaml::from_slice(data)
+            .map_err(|e| Error::Yaml { source: e })?,
+        Some(ConfigFormat::Json) => serde_json::from_slice(data)
+            .map_err(|e| Error::Json { source: e })?,
+        _ => return Ok(data.to_vec()),
+    };
+    let data = toml::to_string(&table).map_err(|e| Error::Ser { source: e })?;
+    Ok(data.into_bytes())
+}
+
+/// Format the toml config to yaml or json by the extension of name.
+pub(crate) fn format_config(name: &str, data: String) -> Result<String> {
+    let format = get_config_format(name);
+    if format.is_none() || format == Some(ConfigFormat::Toml) {
+        return Ok(data);
+    }
+    let table: toml::Table =
+        toml::from_str(&data).map_err(|e| Error::De { source: e })?;
+    if format == Some(ConfigFormat::Yaml) {
+        serde_yaml::to_string(&table).map_err(|e| Error::Yaml { source: e })
+    } else {
+        serde_json::to_string_pretty(&table)
+            .map_err(|e| Error::Json { source: e })
+    }
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct LoadConfigOptions {
     pub replace_include: bool,
@@ -101,9 +170,7 @@ pub trait ConfigStorage {
         false
     }
     async fn observe(&self) -> Result<Observer> {
-        Ok(Observer {
-            etcd_watch_stream: None,
-        })
+        Ok(Observer::default())
     }
     async fn save(&self, key: &str, data: &[u8]) -> Result<()>;
     async fn load(&self, key: &str) -> Result<Vec<u8>>;
@@ -112,6 +179,11 @@ pub trait ConfigStorage {
 static CONFIG_STORAGE: OnceCell<Box<(dyn ConfigStorage + Sync + Send)>> =
     OnceCell::new();
 
+// serialize the load, check and save of config,
+// it avoids losing the update of concurrent modifications
+pub static CONFIG_SAVE_LOCK: Lazy<tokio::sync::Mutex<()>> =
+    Lazy::new(|| tokio::sync::Mutex::new(()));
+
 fn new_config_storage(
     path: &str,
 ) -> Result<Box<(dyn ConfigStorage + Sync + Send)>> {
@@ -119,6 +191,9 @@ fn new_config_storage(
         if path.starts_with(ETCD_PROTOCOL) {
             let storage = EtcdStorage::new(path)?;
             Box::new(storage)
+        } else if path.starts_with(KUBERNETES_PROTOCOL) {
+            let storage = KubernetesStorage::new(path)?;
+            Box::new(storage)
         } else {
             let storage = FileStorage::new(path)?;
             Box::new(storage)
@@ -139,7 +214,13 @@ pub async fn load_config(opts: LoadConfigOptions) -> Result<PingapConf> {
             message: "storage is not inited".to_string(),
         });
     };
-    storage.load_config(opts).await
+    let replace_include = opts.replace_include;
+    let conf = storage.load_config(opts).await?;
+    // the vault secrets are replaced like the includes
+    if replace_include {
+        return vault::replace_vault_secrets(conf).await;
+    }
+    Ok(conf)
 }
 
 pub fn support_observer() -> bool {
@@ -171,6 +252,21 @@ pub async fn save_config(
     };
     storage.save_config(conf, category, name).await
 }
+
+/// Load the config from the path, which isn't the config storage
+/// of current process.
+pub async fn load_config_from_path(
+    path: &str,
+    opts: LoadConfigOptions,
+) -> Result<PingapConf> {
+    let replace_include = opts.replace_include;
+    let conf = new_config_storage(path)?.load_config(opts).await?;
+    if replace_include {
+        return vault::replace_vault_secrets(conf).await;
+    }
+    Ok(conf)
+}
+
 pub async fn sync_to_path(path: &str) -> Result<()> {
     let conf = get_current_config();
     let storage = new_config_storage(path)?;
@@ -206,15 +302,29 @@ pub async fn sync_config(
     Ok(())
 }
 
+pub use audit::{append_audit, query_audit, AuditQuery, AuditRecord};
 pub use common::*;
 pub use etcd::{EtcdStorage, ETCD_PROTOCOL};
 pub use file::FileStorage;
+pub use history::{
+    get_history, list_history, rollback_history, save_history, ConfigVersion,
+};
+pub use kubernetes::{
+    KubernetesStorage, KubernetesWatcher, KUBERNETES_PROTOCOL,
+};
+pub use nginx::{import_nginx, NginxImport};
+pub use scheduled::{
+    add_scheduled_change, cancel_scheduled_change, list_scheduled_changes,
+    new_scheduled_config_service, parse_effective_at, ScheduledChange,
+};
+pub use vault::new_vault_renew_service;
 
 #[cfg(test)]
 mod tests {
     use super::{
-        get_config_storage, load_config, support_observer, sync_to_path,
-        try_init_config_storage, LoadConfigOptions,
+        convert_config, format_config, get_config_storage, is_config_file,
+        load_config, support_observer, sync_to_path, try_init_config_storage,
+        LoadConfigOptions,
     };
     use pretty_assertions::assert_eq;
     use tempfile::TempDir;
@@ -237,4 +347,55 @@ mod tests {
         tokio::fs::write(&file, b"").await.unwrap();
         sync_to_path(file.to_string_lossy().as_ref()).await.unwrap();
     }
+
+    #[test]
+    fn test_convert_config() {
+        assert_eq!(true, is_config_file("pingap.toml"));
+        assert_eq!(true, is_config_file("pingap.YML"));
+        assert_eq!(true, is_config_file("/opt/pingap.json"));
+        assert_eq!(false, is_config_file("/opt/pingap"));
+
+        let data = r#"upstreams:
+  charts:
+    addrs:
+      - "127.0.0.1:5000"
+"#;
+        let toml_data = r#"[upstreams.charts]
+addrs = ["127.0.0.1:5000"]
+"#;
+        let to_table = |data: Vec<u8>| {
+            toml::from_str::<toml::Table>(std::str::from_utf8(&data).unwrap())
+                .unwrap()
+        };
+        let expected = to_table(toml_data.as_bytes().to_vec());
+        assert_eq!(
+            expected,
+            to_table(convert_config("pingap.yaml", data.as_bytes()).unwrap())
+        );
+        assert_eq!(
+            expected,
+            to_table(
+                convert_config(
+                    "pingap.json",
+                    br#"{"upstreams":{"charts":{"addrs":["127.0.0.1:5000"]}}}"#
+                )
+                .unwrap()
+            )
+        );
+        assert_eq!(
+            b"a = 1".to_vec(),
+            convert_config("pingap.toml", b"a = 1").unwrap()
+        );
+
+        // yaml to toml again
+        let data = format_config("pingap.yml", toml_data.to_string()).unwrap();
+        assert_eq!(
+            expected,
+            to_table(convert_config("pingap.yml", data.as_bytes()).unwrap())
+        );
+        assert_eq!(
+            toml_data,
+            format_config("pingap.toml", toml_data.to_string()).unwrap()
+        );
+    }
 }
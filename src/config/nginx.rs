@@ -0,0 +1,1061 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    CertificateConf, Error, LocationConf, PingapConf, PluginConf, Result,
+    ServerConf, UpstreamConf,
+};
+use bytesize::ByteSize;
+use std::collections::HashMap;
+use std::time::Duration;
+use toml::Value;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Semicolon,
+    BlockStart,
+    BlockEnd,
+}
+
+/// The directive of nginx config, e.g. `proxy_pass http://backend;`,
+/// the block is set for `server { ... }`.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Directive {
+    name: String,
+    args: Vec<String>,
+    block: Option<Vec<Directive>>,
+}
+
+impl Directive {
+    fn arg(&self, index: usize) -> &str {
+        self.args
+            .get(index)
+            .map(|item| item.as_str())
+            .unwrap_or_default()
+    }
+    /// Get the value of `key=value` arg.
+    fn named_arg(&self, key: &str) -> Option<&str> {
+        self.args.iter().find_map(|item| {
+            item.strip_prefix(key)
+                .and_then(|value| value.strip_prefix('='))
+        })
+    }
+    fn block(&self) -> &[Directive] {
+        self.block.as_deref().unwrap_or_default()
+    }
+}
+
+impl std::fmt::Display for Directive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        for arg in self.args.iter() {
+            write!(f, " {arg}")?;
+        }
+        Ok(())
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    Error::Invalid {
+        message: format!("nginx config is invalid, {message}"),
+    }
+}
+
+fn tokenize(data: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = data.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '#' => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            },
+            ';' => tokens.push(Token::Semicolon),
+            '{' => tokens.push(Token::BlockStart),
+            '}' => tokens.push(Token::BlockEnd),
+            '"' | '\'' => {
+                let mut value = String::new();
+                let mut closed = false;
+                while let Some(ch) = chars.next() {
+                    if ch == c {
+                        closed = true;
+                        break;
+                    }
+                    if ch == '\\' {
+                        if let Some(next) = chars.next() {
+                            value.push(next);
+                        }
+                        continue;
+                    }
+                    value.push(ch);
+                }
+                if !closed {
+                    return Err(invalid("quote is not closed"));
+                }
+                tokens.push(Token::Word(value));
+            },
+            _ if c.is_whitespace() => {},
+            _ => {
+                let mut value = c.to_string();
+                while let Some(ch) = chars.peek() {
+                    if ch.is_whitespace() || [';', '{', '}'].contains(ch) {
+                        break;
+                    }
+                    value.push(*ch);
+                    chars.next();
+                }
+                tokens.push(Token::Word(value));
+            },
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_block(
+    tokens: &mut impl Iterator<Item = Token>,
+    nested: bool,
+) -> Result<Vec<Directive>> {
+    let mut directives = vec![];
+    let mut words = vec![];
+    loop {
+        let Some(token) = tokens.next() else {
+            if nested {
+                return Err(invalid("\"}\" is missing"));
+            }
+            if !words.is_empty() {
+                return Err(invalid("\";\" is missing"));
+            }
+            return Ok(directives);
+        };
+        match token {
+            Token::Word(word) => words.push(word),
+            Token::BlockEnd => {
+                if !nested || !words.is_empty() {
+                    return Err(invalid("unexpected \"}\""));
+                }
+                return Ok(directives);
+            },
+            Token::Semicolon | Token::BlockStart => {
+                if words.is_empty() {
+                    return Err(invalid("directive name is missing"));
+                }
+                let name = words.remove(0);
+                let block = if token == Token::BlockStart {
+                    Some(parse_block(tokens, true)?)
+                } else {
+                    None
+                };
+                directives.push(Directive {
+                    name,
+                    args: std::mem::take(&mut words),
+                    block,
+                });
+            },
+        }
+    }
+}
+
+fn parse(data: &str) -> Result<Vec<Directive>> {
+    parse_block(&mut tokenize(data)?.into_iter(), false)
+}
+
+/// Convert the name to the name of pingap config,
+/// e.g. `api.example.com` -> `api-example-com`.
+fn sanitize_name(value: &str) -> String {
+    value
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|item| !item.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+fn unique_name<T>(items: &HashMap<String, T>, value: &str) -> String {
+    let name = sanitize_name(value);
+    let name = if name.is_empty() {
+        "default".to_string()
+    } else {
+        name
+    };
+    if !items.contains_key(&name) {
+        return name;
+    }
+    let mut index = 2;
+    while items.contains_key(&format!("{name}-{index}")) {
+        index += 1;
+    }
+    format!("{name}-{index}")
+}
+
+/// Parse the time of nginx, the unit is second if it's not set.
+fn parse_time(value: &str) -> Option<Duration> {
+    if let Ok(value) = value.parse::<u64>() {
+        return Some(Duration::from_secs(value));
+    }
+    humantime::parse_duration(value).ok()
+}
+
+/// Parse the size of nginx, e.g. `512`, `8k`, `10m` and `1g`.
+fn parse_size(value: &str) -> Option<u64> {
+    let value = value.to_lowercase();
+    let (num, unit) = match value.chars().last()? {
+        'k' => (&value[..value.len() - 1], 1024),
+        'm' => (&value[..value.len() - 1], 1024 * 1024),
+        'g' => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        _ => (value.as_str(), 1),
+    };
+    num.parse::<u64>().ok().map(|num| num * unit)
+}
+
+/// Parse the listen of nginx to the addr of server,
+/// e.g. `80` -> `0.0.0.0:80`, `*:8080` -> `0.0.0.0:8080`.
+fn parse_listen(value: &str) -> Option<String> {
+    if value.starts_with("unix:") {
+        return None;
+    }
+    if value.parse::<u16>().is_ok() {
+        return Some(format!("0.0.0.0:{value}"));
+    }
+    if let Some(port) = value.strip_prefix("*:") {
+        return Some(format!("0.0.0.0:{port}"));
+    }
+    if value.contains(':') && !value.ends_with(']') {
+        return Some(value.to_string());
+    }
+    Some(format!("{value}:80"))
+}
+
+/// Convert the server name of nginx to the host of location,
+/// the wildcard name is converted to regex.
+fn convert_server_name(value: &str) -> Option<String> {
+    if value.is_empty() || value == "_" {
+        return None;
+    }
+    if value.starts_with('~') {
+        return Some(value.to_string());
+    }
+    if value.contains('*') {
+        let re = value
+            .split('*')
+            .map(regex::escape)
+            .collect::<Vec<_>>()
+            .join(".+");
+        return Some(format!("~^{re}$"));
+    }
+    // .example.com matches example.com and its subdomains
+    if let Some(domain) = value.strip_prefix('.') {
+        return Some(format!("~^(.+\\.)?{}$", regex::escape(domain)));
+    }
+    Some(value.to_string())
+}
+
+/// Convert the location of nginx to the path of location.
+fn convert_location_path(args: &[String]) -> Option<String> {
+    match args {
+        [path] => Some(path.to_string()),
+        [modifier, path] => match modifier.as_str() {
+            "=" => Some(format!("={path}")),
+            "~" => Some(format!("~{path}")),
+            "~*" => Some(format!("~(?i){path}")),
+            "^~" => Some(path.to_string()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The settings which are inherited from the outer level,
+/// the list settings are replaced if they are set in the current level.
+#[derive(Debug, Default, Clone)]
+struct Context {
+    name: String,
+    hosts: Vec<String>,
+    proxy_set_headers: Vec<String>,
+    add_headers: Vec<String>,
+    limits: Vec<String>,
+    client_max_body_size: Option<ByteSize>,
+    connection_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+}
+
+impl Context {
+    /// New the context of inner level, the list settings are reset
+    /// and they are inherited after the inner level is processed.
+    fn new_inner(&self, name: String) -> Self {
+        Self {
+            name,
+            proxy_set_headers: vec![],
+            add_headers: vec![],
+            limits: vec![],
+            ..self.clone()
+        }
+    }
+    /// Inherit the list settings of outer level if they aren't set.
+    fn inherit(&mut self, outer: &Context) {
+        if self.proxy_set_headers.is_empty() {
+            self.proxy_set_headers.clone_from(&outer.proxy_set_headers);
+        }
+        if self.add_headers.is_empty() {
+            self.add_headers.clone_from(&outer.add_headers);
+        }
+        if self.limits.is_empty() {
+            self.limits.clone_from(&outer.limits);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Importer {
+    conf: PingapConf,
+    // the limit_req_zone, zone -> (max, interval)
+    rate_zones: HashMap<String, (i64, String)>,
+    // the server addr -> server name
+    addrs: HashMap<String, String>,
+    warnings: Vec<String>,
+}
+
+impl Importer {
+    fn warn(&mut self, scope: &str, directive: &Directive) {
+        self.warnings
+            .push(format!("{scope}: \"{directive}\" is not supported"));
+    }
+    fn add_upstream(&mut self, directive: &Directive) {
+        let name = directive.arg(0).to_string();
+        let mut upstream = UpstreamConf::default();
+        for item in directive.block().iter() {
+            match item.name.as_str() {
+                "server" => {
+                    if item.args.iter().any(|arg| arg == "backup") {
+                        self.warn(&format!("upstream({name})"), item);
+                        continue;
+                    }
+                    let addr = item.arg(0);
+                    match item.named_arg("weight") {
+                        Some(weight) => {
+                            upstream.addrs.push(format!("{addr} {weight}"))
+                        },
+                        None => upstream.addrs.push(addr.to_string()),
+                    }
+                },
+                "ip_hash" => upstream.algo = Some("hash:ip".to_string()),
+                "hash" if item.arg(0) == "$request_uri" => {
+                    upstream.algo = Some("hash:uri".to_string())
+                },
+                // the connections are reused by default
+                "keepalive" => {},
+                "keepalive_timeout" => {
+                    upstream.idle_timeout = parse_time(item.arg(0))
+                },
+                _ => self.warn(&format!("upstream({name})"), item),
+            }
+        }
+        self.conf.upstreams.insert(name, upstream);
+    }
+    fn add_rate_zone(&mut self, directive: &Directive) {
+        let Some(zone) = directive.named_arg("zone") else {
+            return;
+        };
+        let zone = zone.split(':').next().unwrap_or_default().to_string();
+        if !directive.arg(0).ends_with("remote_addr") {
+            self.warnings.push(format!(
+                "limit_req_zone({zone}): the key is converted to ip"
+            ));
+        }
+        let rate = directive.named_arg("rate").unwrap_or_default();
+        let (max, interval) = if let Some(max) = rate.strip_suffix("r/m") {
+            (max, "1m")
+        } else {
+            (rate.strip_suffix("r/s").unwrap_or_default(), "1s")
+        };
+        self.rate_zones.insert(
+            zone,
+            (max.parse::<i64>().unwrap_or_default(), interval.to_string()),
+        );
+    }
+    fn add_plugin(&mut self, name: &str, values: Vec<(&str, Value)>) {
+        let mut plugin = PluginConf::new();
+        for (key, value) in values {
+            plugin.insert(key.to_string(), value);
+        }
+        self.conf.plugins.insert(name.to_string(), plugin);
+    }
+    /// Convert the limit_req and limit_conn to limit plugin,
+    /// and return the name of plugin.
+    fn add_limit(&mut self, directive: &Directive) -> Option<String> {
+        if directive.name == "limit_conn" {
+            let max = directive.arg(1).parse::<i64>().ok()?;
+            let name = sanitize_name(&format!(
+                "limit-conn-{}-{max}",
+                directive.arg(0)
+            ));
+            self.add_plugin(
+                &name,
+                vec![
+                    ("category", Value::String("limit".to_string())),
+                    ("type", Value::String("inflight".to_string())),
+                    ("tag", Value::String("ip".to_string())),
+                    ("max", Value::Integer(max)),
+                ],
+            );
+            return Some(name);
+        }
+        let zone = directive.named_arg("zone")?;
+        let (max, interval) = self.rate_zones.get(zone)?.clone();
+        let burst = directive
+            .named_arg("burst")
+            .and_then(|value| value.parse::<i64>().ok());
+        let mut values = vec![
+            ("category", Value::String("limit".to_string())),
+            ("tag", Value::String("ip".to_string())),
+            ("max", Value::Integer(max)),
+            ("interval", Value::String(interval)),
+        ];
+        let name = if let Some(burst) = burst {
+            // the burst requests are allowed by token bucket
+            values.push(("type", Value::String("token_bucket".to_string())));
+            values.push(("burst", Value::Integer(max + burst)));
+            sanitize_name(&format!("limit-req-{zone}-{burst}"))
+        } else {
+            values.push(("type", Value::String("rate".to_string())));
+            sanitize_name(&format!("limit-req-{zone}"))
+        };
+        self.add_plugin(&name, values);
+        Some(name)
+    }
+    /// Update the inherited settings by the directive,
+    /// it returns false if the directive isn't a setting.
+    fn update_context(
+        &mut self,
+        ctx: &mut Context,
+        directive: &Directive,
+    ) -> bool {
+        match directive.name.as_str() {
+            "proxy_set_header" => {
+                ctx.proxy_set_headers.push(format!(
+                    "{}:{}",
+                    directive.arg(0),
+                    directive.arg(1)
+                ));
+            },
+            "add_header" => {
+                ctx.add_headers.push(format!(
+                    "{}:{}",
+                    directive.arg(0),
+                    directive.arg(1)
+                ));
+            },
+            "limit_req" | "limit_conn" => match self.add_limit(directive) {
+                Some(name) => ctx.limits.push(name),
+                None => self.warn(&ctx.name, directive),
+            },
+            "client_max_body_size" => {
+                ctx.client_max_body_size = parse_size(directive.arg(0))
+                    .filter(|size| *size > 0)
+                    .map(ByteSize);
+            },
+            "proxy_connect_timeout" => {
+                ctx.connection_timeout = parse_time(directive.arg(0))
+            },
+            "proxy_read_timeout" => {
+                ctx.read_timeout = parse_time(directive.arg(0))
+            },
+            "proxy_send_timeout" => {
+                ctx.write_timeout = parse_time(directive.arg(0))
+            },
+            _ => return false,
+        }
+        true
+    }
+    /// Get the upstream of proxy_pass, the upstream is created
+    /// if it isn't defined by upstream block. It returns the name
+    /// of upstream and the uri of proxy_pass.
+    fn get_proxy_pass_upstream(
+        &mut self,
+        value: &str,
+    ) -> Option<(String, Option<String>)> {
+        let (tls, rest) = if let Some(rest) = value.strip_prefix("http://") {
+            (false, rest)
+        } else {
+            (true, value.strip_prefix("https://")?)
+        };
+        if rest.contains('$') || rest.starts_with("unix:") {
+            return None;
+        }
+        let (authority, uri) = match rest.find('/') {
+            Some(index) => (&rest[..index], Some(rest[index..].to_string())),
+            None => (rest, None),
+        };
+        let host = authority.split(':').next().unwrap_or_default();
+        if self.conf.upstreams.contains_key(authority) {
+            if tls {
+                if let Some(upstream) = self.conf.upstreams.get_mut(authority) {
+                    upstream.sni = Some(host.to_string());
+                }
+            }
+            return Some((authority.to_string(), uri));
+        }
+        let addr = if authority.contains(':') {
+            authority.to_string()
+        } else if tls {
+            format!("{authority}:443")
+        } else {
+            format!("{authority}:80")
+        };
+        let name = sanitize_name(&addr);
+        let upstream = UpstreamConf {
+            addrs: vec![addr],
+            sni: tls.then(|| host.to_string()),
+            ..Default::default()
+        };
+        self.conf.upstreams.insert(name.clone(), upstream);
+        Some((name, uri))
+    }
+    fn add_location(
+        &mut self,
+        server: &str,
+        inherited: &Context,
+        directive: &Directive,
+        locations: &mut Vec<String>,
+    ) {
+        let Some(path) = convert_location_path(&directive.args) else {
+            self.warn(&inherited.name, directive);
+            return;
+        };
+        let name = unique_name(
+            &self.conf.locations,
+            &format!(
+                "{server}-{}",
+                directive
+                    .args
+                    .last()
+                    .map(String::as_str)
+                    .unwrap_or_default()
+            ),
+        );
+        let mut ctx = inherited.new_inner(format!("location({name})"));
+        let mut location = LocationConf {
+            path: Some(path.clone()),
+            ..Default::default()
+        };
+        if !ctx.hosts.is_empty() {
+            location.host = Some(ctx.hosts.join(","));
+        }
+        let mut proxy_pass = None;
+        let mut rewrites = vec![];
+        let mut nested = vec![];
+        for item in directive.block().iter() {
+            if self.update_context(&mut ctx, item) {
+                continue;
+            }
+            match item.name.as_str() {
+                "proxy_pass" => proxy_pass = Some(item.clone()),
+                "rewrite" => rewrites.push(item.clone()),
+                "location" => nested.push(item.clone()),
+                _ => self.warn(&ctx.name, item),
+            }
+        }
+        ctx.inherit(inherited);
+        if let Some(item) = rewrites.first() {
+            if !["", "last", "break"].contains(&item.arg(2)) {
+                self.warn(&ctx.name, item);
+            } else {
+                location.rewrite =
+                    Some(format!("{} {}", item.arg(0), item.arg(1)));
+            }
+        }
+        for item in rewrites.iter().skip(1) {
+            self.warn(&ctx.name, item);
+        }
+        if let Some(item) = &proxy_pass {
+            match self.get_proxy_pass_upstream(item.arg(0)) {
+                Some((upstream, uri)) => {
+                    if let Some(uri) = uri {
+                        let is_prefix = !path.starts_with(['~', '=']);
+                        if !is_prefix || location.rewrite.is_some() {
+                            self.warn(&ctx.name, item);
+                        } else if uri != path {
+                            location.rewrite = Some(format!(
+                                "^{} {uri}",
+                                regex::escape(&path)
+                            ));
+                        }
+                    }
+                    if let Some(conf) = self.conf.upstreams.get_mut(&upstream) {
+                        conf.connection_timeout =
+                            conf.connection_timeout.or(ctx.connection_timeout);
+                        conf.read_timeout =
+                            conf.read_timeout.or(ctx.read_timeout);
+                        conf.write_timeout =
+                            conf.write_timeout.or(ctx.write_timeout);
+                    }
+                    location.upstream = Some(upstream);
+                },
+                None => self.warn(&ctx.name, item),
+            }
+        }
+        if !ctx.proxy_set_headers.is_empty() {
+            location.proxy_set_headers = Some(ctx.proxy_set_headers.clone());
+        }
+        location.client_max_body_size = ctx.client_max_body_size;
+        let mut plugins = ctx.limits.clone();
+        if !ctx.add_headers.is_empty() {
+            let plugin = format!("{name}-headers");
+            let headers = ctx
+                .add_headers
+                .iter()
+                .map(|item| Value::String(item.to_string()))
+                .collect();
+            self.add_plugin(
+                &plugin,
+                vec![
+                    ("category", Value::String("response_headers".to_string())),
+                    ("add_headers", Value::Array(headers)),
+                ],
+            );
+            plugins.push(plugin);
+        }
+        if !plugins.is_empty() {
+            location.plugins = Some(plugins);
+        }
+        // the location without proxy_pass can't be served by pingap,
+        // but it's still imported for the nested locations
+        if location.upstream.is_some() {
+            self.conf.locations.insert(name.clone(), location);
+            locations.push(name);
+        } else if nested.is_empty() {
+            self.warnings
+                .push(format!("{}: proxy_pass is missing", ctx.name));
+        }
+        for item in nested.iter() {
+            self.add_location(server, &ctx, item, locations);
+        }
+    }
+    fn add_certificate(&mut self, server: &str, cert: &str, key: &str) {
+        let read = |file: &str| {
+            std::fs::read_to_string(file)
+                .ok()
+                .filter(|data| data.contains("-----BEGIN"))
+        };
+        let (Some(tls_cert), Some(tls_key)) = (read(cert), read(key)) else {
+            self.warnings.push(format!(
+                "server({server}): certificate {cert} can't be read"
+            ));
+            return;
+        };
+        let name = unique_name(&self.conf.certificates, server);
+        self.conf.certificates.insert(
+            name,
+            CertificateConf {
+                tls_cert: Some(tls_cert),
+                tls_key: Some(tls_key),
+                ..Default::default()
+            },
+        );
+    }
+    fn add_server(&mut self, inherited: &Context, directive: &Directive) {
+        let server_name = directive
+            .block()
+            .iter()
+            .find(|item| item.name == "server_name")
+            .map(|item| item.arg(0).trim_start_matches(['.', '*', '~']))
+            .filter(|value| !value.is_empty() && *value != "_")
+            .unwrap_or("server")
+            .to_string();
+        let mut ctx = inherited.new_inner(format!("server({server_name})"));
+        let mut addrs = vec![];
+        let mut certificate = (String::new(), String::new());
+        let mut locations = vec![];
+        for item in directive.block().iter() {
+            if self.update_context(&mut ctx, item) {
+                continue;
+            }
+            match item.name.as_str() {
+                "listen" => match parse_listen(item.arg(0)) {
+                    Some(addr) => addrs.push(addr),
+                    None => self.warn(&ctx.name, item),
+                },
+                "server_name" => {
+                    ctx.hosts = item
+                        .args
+                        .iter()
+                        .filter_map(|value| convert_server_name(value))
+                        .collect();
+                },
+                "ssl_certificate" => certificate.0 = item.arg(0).to_string(),
+                "ssl_certificate_key" => {
+                    certificate.1 = item.arg(0).to_string()
+                },
+                "location" => {},
+                _ => self.warn(&ctx.name, item),
+            }
+        }
+        ctx.inherit(inherited);
+        let name = sanitize_name(&server_name);
+        for item in directive.block().iter() {
+            if item.name == "location" {
+                self.add_location(&name, &ctx, item, &mut locations);
+            }
+        }
+        if !certificate.0.is_empty() {
+            self.add_certificate(&name, &certificate.0, &certificate.1);
+        }
+        if addrs.is_empty() {
+            addrs.push("0.0.0.0:80".to_string());
+        }
+        let addr = addrs.join(",");
+        // the server blocks of the same listen are merged,
+        // and they are matched by the host of location
+        if let Some(server) = self
+            .addrs
+            .get(&addr)
+            .and_then(|name| self.conf.servers.get_mut(name))
+        {
+            server
+                .locations
+                .get_or_insert_with(Vec::new)
+                .extend(locations);
+            return;
+        }
+        let name = unique_name(&self.conf.servers, &server_name);
+        self.addrs.insert(addr.clone(), name.clone());
+        self.conf.servers.insert(
+            name,
+            ServerConf {
+                addr,
+                locations: Some(locations),
+                ..Default::default()
+            },
+        );
+    }
+    fn import(&mut self, directives: &[Directive]) {
+        // the http block is flattened
+        let mut items = vec![];
+        for item in directives.iter() {
+            if item.name == "http" {
+                items.extend(item.block().iter());
+            } else if item.name == "events" {
+                continue;
+            } else {
+                items.push(item);
+            }
+        }
+        // the upstreams and zones may be defined after the servers
+        for item in items.iter() {
+            match item.name.as_str() {
+                "upstream" => self.add_upstream(item),
+                "limit_req_zone" => self.add_rate_zone(item),
+                _ => {},
+            }
+        }
+        let mut ctx = Context {
+            name: "http".to_string(),
+            ..Default::default()
+        };
+        for item in items.iter() {
+            if self.update_context(&mut ctx, item) {
+                continue;
+            }
+            match item.name.as_str() {
+                "upstream" | "limit_req_zone" | "limit_conn_zone" => {},
+                "server" => {},
+                _ => self.warn("http", item),
+            }
+        }
+        for item in items.iter() {
+            if item.name == "server" {
+                self.add_server(&ctx, item);
+            }
+        }
+    }
+}
+
+/// The result of nginx import, the directives which can't be
+/// converted are collected as warnings.
+#[derive(Debug, Default)]
+pub struct NginxImport {
+    pub conf: PingapConf,
+    pub warnings: Vec<String>,
+}
+
+/// Import the subset of nginx config(upstream, server, location,
+/// proxy_pass, headers, rewrite and limits) as pingap config.
+pub fn import_nginx(data: &str) -> Result<NginxImport> {
+    let directives = parse(data)?;
+    let mut importer = Importer::default();
+    importer.import(&directives);
+    Ok(NginxImport {
+        conf: importer.conf,
+        warnings: importer.warnings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        convert_location_path, convert_server_name, import_nginx, parse,
+        parse_listen, parse_size, Directive,
+    };
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse() {
+        let directives = parse(
+            r#"
+# comment
+worker_processes 4;
+http {
+    server {
+        add_header X-Name "pingap proxy"; # comment
+        location / {}
+    }
+}
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                Directive {
+                    name: "worker_processes".to_string(),
+                    args: vec!["4".to_string()],
+                    block: None,
+                },
+                Directive {
+                    name: "http".to_string(),
+                    args: vec![],
+                    block: Some(vec![Directive {
+                        name: "server".to_string(),
+                        args: vec![],
+                        block: Some(vec![
+                            Directive {
+                                name: "add_header".to_string(),
+                                args: vec![
+                                    "X-Name".to_string(),
+                                    "pingap proxy".to_string()
+                                ],
+                                block: None,
+                            },
+                            Directive {
+                                name: "location".to_string(),
+                                args: vec!["/".to_string()],
+                                block: Some(vec![]),
+                            },
+                        ]),
+                    }]),
+                },
+            ],
+            directives
+        );
+
+        assert_eq!(
+            "Invalid error nginx config is invalid, \"}\" is missing",
+            parse("http {").unwrap_err().to_string()
+        );
+        assert_eq!(
+            "Invalid error nginx config is invalid, \";\" is missing",
+            parse("worker_processes 4").unwrap_err().to_string()
+        );
+        assert_eq!(
+            "Invalid error nginx config is invalid, unexpected \"}\"",
+            parse("}").unwrap_err().to_string()
+        );
+        assert_eq!(
+            "Invalid error nginx config is invalid, quote is not closed",
+            parse("add_header X-Name \"pingap;")
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn test_convert_values() {
+        assert_eq!(Some(512), parse_size("512"));
+        assert_eq!(Some(10 * 1024 * 1024), parse_size("10m"));
+        assert_eq!(Some(1024 * 1024 * 1024), parse_size("1G"));
+        assert_eq!(None, parse_size("abc"));
+
+        assert_eq!("0.0.0.0:80", parse_listen("80").unwrap());
+        assert_eq!("0.0.0.0:8080", parse_listen("*:8080").unwrap());
+        assert_eq!("127.0.0.1:8080", parse_listen("127.0.0.1:8080").unwrap());
+        assert_eq!("[::]:443", parse_listen("[::]:443").unwrap());
+        assert_eq!("localhost:80", parse_listen("localhost").unwrap());
+        assert_eq!(None, parse_listen("unix:/var/run/nginx.sock"));
+
+        assert_eq!(None, convert_server_name("_"));
+        assert_eq!("pingap.io", convert_server_name("pingap.io").unwrap());
+        assert_eq!(
+            r#"~^.+\.pingap\.io$"#,
+            convert_server_name("*.pingap.io").unwrap()
+        );
+        assert_eq!(
+            r#"~^(.+\.)?pingap\.io$"#,
+            convert_server_name(".pingap.io").unwrap()
+        );
+
+        let path = |args: &[&str]| {
+            let args: Vec<String> =
+                args.iter().map(|item| item.to_string()).collect();
+            convert_location_path(&args)
+        };
+        assert_eq!("/api", path(&["/api"]).unwrap());
+        assert_eq!("=/api", path(&["=", "/api"]).unwrap());
+        assert_eq!("~^/api", path(&["~", "^/api"]).unwrap());
+        assert_eq!("~(?i)^/api", path(&["~*", "^/api"]).unwrap());
+        assert_eq!("/static", path(&["^~", "/static"]).unwrap());
+        assert_eq!(None, path(&["@fallback", "/api"]));
+    }
+
+    #[test]
+    fn test_import_nginx() {
+        let result = import_nginx(
+            r#"
+http {
+    limit_req_zone $binary_remote_addr zone=api:10m rate=10r/s;
+    client_max_body_size 10m;
+    proxy_set_header Host $host;
+
+    upstream backend {
+        server 127.0.0.1:5000 weight=2;
+        server 127.0.0.1:5001;
+        server 127.0.0.1:5002 backup;
+        keepalive 32;
+    }
+
+    server {
+        listen 80;
+        server_name pingap.io www.pingap.io;
+        proxy_connect_timeout 5s;
+        gzip on;
+
+        location /api/ {
+            proxy_pass http://backend/v1/;
+            proxy_set_header X-Real-IP $remote_addr;
+            proxy_read_timeout 30;
+            limit_req zone=api burst=5;
+            add_header X-Frame-Options DENY;
+        }
+        location ~* \.(png|jpg)$ {
+            proxy_pass https://cdn.pingap.io;
+        }
+        location / {
+            rewrite ^/old/(.*)$ /new/$1 break;
+            proxy_pass http://backend;
+        }
+    }
+    server {
+        listen 80;
+        server_name static.pingap.io;
+        location / {
+            proxy_pass http://127.0.0.1:3000;
+            return 404;
+        }
+    }
+}
+"#,
+        )
+        .unwrap();
+        let conf = result.conf;
+
+        assert_eq!(
+            vec![
+                r#"upstream(backend): "server 127.0.0.1:5002 backup" is not supported"#,
+                r#"server(pingap.io): "gzip on" is not supported"#,
+                r#"location(static-pingap-io): "return 404" is not supported"#,
+            ],
+            result.warnings
+        );
+
+        let backend = conf.upstreams.get("backend").unwrap();
+        assert_eq!(
+            vec!["127.0.0.1:5000 2".to_string(), "127.0.0.1:5001".to_string()],
+            backend.addrs
+        );
+        assert_eq!(Some(Duration::from_secs(5)), backend.connection_timeout);
+        assert_eq!(Some(Duration::from_secs(30)), backend.read_timeout);
+        let cdn = conf.upstreams.get("cdn-pingap-io-443").unwrap();
+        assert_eq!(vec!["cdn.pingap.io:443".to_string()], cdn.addrs);
+        assert_eq!("cdn.pingap.io", cdn.sni.clone().unwrap());
+
+        let api = conf.locations.get("pingap-io-api").unwrap();
+        assert_eq!("/api/", api.path.clone().unwrap());
+        assert_eq!("pingap.io,www.pingap.io", api.host.clone().unwrap());
+        assert_eq!("backend", api.upstream.clone().unwrap());
+        assert_eq!(r#"^/api/ /v1/"#, api.rewrite.clone().unwrap());
+        // the headers of outer level are replaced
+        assert_eq!(
+            vec!["X-Real-IP:$remote_addr".to_string()],
+            api.proxy_set_headers.clone().unwrap()
+        );
+        assert_eq!(
+            vec![
+                "limit-req-api-5".to_string(),
+                "pingap-io-api-headers".to_string()
+            ],
+            api.plugins.clone().unwrap()
+        );
+        assert_eq!(
+            10 * 1024 * 1024,
+            api.client_max_body_size.unwrap().as_u64()
+        );
+
+        let image = conf.locations.get("pingap-io-png-jpg").unwrap();
+        assert_eq!(r#"~(?i)\.(png|jpg)$"#, image.path.clone().unwrap());
+        assert_eq!("cdn-pingap-io-443", image.upstream.clone().unwrap());
+
+        let root = conf.locations.get("pingap-io").unwrap();
+        assert_eq!("^/old/(.*)$ /new/$1", root.rewrite.clone().unwrap());
+        assert_eq!(
+            vec!["Host:$host".to_string()],
+            root.proxy_set_headers.clone().unwrap()
+        );
+
+        let limit = conf.plugins.get("limit-req-api-5").unwrap();
+        assert_eq!(
+            r#"burst = 15
+category = "limit"
+interval = "1s"
+max = 10
+tag = "ip"
+type = "token_bucket"
+"#,
+            toml::to_string(limit).unwrap()
+        );
+        let headers = conf.plugins.get("pingap-io-api-headers").unwrap();
+        assert_eq!(
+            r#"add_headers = ["X-Frame-Options:DENY"]
+category = "response_headers"
+"#,
+            toml::to_string(headers).unwrap()
+        );
+
+        // the servers of the same listen are merged
+        assert_eq!(1, conf.servers.len());
+        let server = conf.servers.get("pingap-io").unwrap();
+        assert_eq!("0.0.0.0:80", server.addr);
+        assert_eq!(
+            vec![
+                "pingap-io-api".to_string(),
+                "pingap-io-png-jpg".to_string(),
+                "pingap-io".to_string(),
+                "static-pingap-io".to_string(),
+            ],
+            server.locations.clone().unwrap()
+        );
+        conf.validate().unwrap();
+    }
+}
@@ -0,0 +1,263 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{load_config, save_config, save_history};
+use super::{Error, LoadConfigOptions, Result, CONFIG_SAVE_LOCK};
+use crate::service::SimpleServiceTaskFuture;
+use crate::util;
+use crate::webhook::{
+    send_notification, NotificationCategory, NotificationLevel,
+    SendNotificationParams,
+};
+use nanoid::nanoid;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+use tracing::{error, info};
+
+/// The config change which will be applied at the effective time,
+/// it's staged in memory, so it will be lost if pingap is restarted.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScheduledChange {
+    pub id: String,
+    pub category: String,
+    pub name: String,
+    // the json data of config, the config will be removed if it's none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+    pub effective_at: u64,
+    pub created_at: u64,
+}
+
+impl ScheduledChange {
+    pub fn new(
+        category: &str,
+        name: &str,
+        data: Option<String>,
+        effective_at: u64,
+    ) -> Self {
+        Self {
+            id: nanoid!(8),
+            category: category.to_string(),
+            name: name.to_string(),
+            data,
+            effective_at,
+            created_at: util::now().as_secs(),
+        }
+    }
+    /// Apply the change to config.
+    pub fn apply(&self, conf: &mut super::PingapConf) -> Result<()> {
+        if let Some(data) = &self.data {
+            conf.update(&self.category, &self.name, data.as_bytes())
+        } else {
+            conf.remove(&self.category, &self.name)
+        }
+    }
+}
+
+static SCHEDULED_CHANGES: Lazy<Mutex<Vec<ScheduledChange>>> =
+    Lazy::new(|| Mutex::new(vec![]));
+
+/// Parse the effective time, it supports unix timestamp(seconds)
+/// and rfc3339 datetime, e.g. `2024-10-01T02:00:00Z`.
+pub fn parse_effective_at(value: &str) -> Result<u64> {
+    let value = value.trim();
+    let effective_at = if let Ok(value) = value.parse::<u64>() {
+        value
+    } else {
+        humantime::parse_rfc3339_weak(value)
+            .map_err(|e| Error::Invalid {
+                message: format!("effective_at({value}) is invalid, {e}"),
+            })?
+            .duration_since(UNIX_EPOCH)
+            .map(|value| value.as_secs())
+            .unwrap_or_default()
+    };
+    if effective_at <= util::now().as_secs() {
+        return Err(Error::Invalid {
+            message: format!("effective_at({value}) should be in the future"),
+        });
+    }
+    Ok(effective_at)
+}
+
+/// Stage the config change, it will be applied by scheduled service.
+pub fn add_scheduled_change(change: ScheduledChange) {
+    let mut changes =
+        SCHEDULED_CHANGES.lock().unwrap_or_else(|e| e.into_inner());
+    changes.push(change);
+    changes.sort_by_key(|item| item.effective_at);
+}
+
+/// Get all the staged config changes.
+pub fn list_scheduled_changes() -> Vec<ScheduledChange> {
+    SCHEDULED_CHANGES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+}
+
+/// Cancel the staged config change, it returns false if not found.
+pub fn cancel_scheduled_change(id: &str) -> bool {
+    let mut changes =
+        SCHEDULED_CHANGES.lock().unwrap_or_else(|e| e.into_inner());
+    let count = changes.len();
+    changes.retain(|item| item.id != id);
+    count != changes.len()
+}
+
+fn take_due_changes(now: u64) -> Vec<ScheduledChange> {
+    let mut changes =
+        SCHEDULED_CHANGES.lock().unwrap_or_else(|e| e.into_inner());
+    let (due, pending) =
+        changes.drain(..).partition(|item| item.effective_at <= now);
+    *changes = pending;
+    due
+}
+
+async fn apply_change(change: &ScheduledChange) -> Result<()> {
+    let _guard = CONFIG_SAVE_LOCK.lock().await;
+    let mut conf = load_config(LoadConfigOptions {
+        replace_include: false,
+        admin: true,
+    })
+    .await?;
+    change.apply(&mut conf)?;
+    // validate again, the config may be changed after staged
+    conf.validate()?;
+    save_config(&conf, &change.category, Some(&change.name)).await?;
+    save_history(
+        &conf,
+        &format!("scheduled change of {}({})", change.category, change.name),
+    )
+    .await?;
+    Ok(())
+}
+
+async fn do_scheduled_change(_count: u32) -> Result<bool, String> {
+    for change in take_due_changes(util::now().as_secs()) {
+        let action = if change.data.is_some() {
+            "update"
+        } else {
+            "remove"
+        };
+        let target = format!("{}({})", change.category, change.name);
+        let params = match apply_change(&change).await {
+            Ok(()) => {
+                info!(id = change.id, action, target, "apply scheduled config");
+                SendNotificationParams {
+                    category: NotificationCategory::ScheduledConfig,
+                    level: NotificationLevel::Info,
+                    msg: format!("{action} {target} is applied"),
+                    ..Default::default()
+                }
+            },
+            Err(e) => {
+                error!(
+                    id = change.id,
+                    action,
+                    target,
+                    error = e.to_string(),
+                    "apply scheduled config fail"
+                );
+                SendNotificationParams {
+                    category: NotificationCategory::ScheduledConfigFail,
+                    level: NotificationLevel::Error,
+                    msg: format!("{action} {target} fail, {e}"),
+                    ..Default::default()
+                }
+            },
+        };
+        send_notification(params).await;
+    }
+    Ok(true)
+}
+
+/// Create a service task to apply the staged config changes,
+/// it checks the changes every interval of simple service.
+pub fn new_scheduled_config_service() -> (String, SimpleServiceTaskFuture) {
+    let task: SimpleServiceTaskFuture =
+        Box::new(|count: u32| Box::pin(do_scheduled_change(count)));
+    ("scheduledConfig".to_string(), task)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        add_scheduled_change, cancel_scheduled_change, list_scheduled_changes,
+        parse_effective_at, take_due_changes, ScheduledChange,
+    };
+    use crate::config::PingapConf;
+    use crate::util;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_parse_effective_at() {
+        assert_eq!(4102444800, parse_effective_at("4102444800").unwrap());
+        assert_eq!(
+            4102444800,
+            parse_effective_at("2100-01-01T00:00:00Z").unwrap()
+        );
+        assert_eq!(
+            "Invalid error effective_at(1) should be in the future",
+            parse_effective_at("1").err().unwrap().to_string()
+        );
+        assert_eq!(true, parse_effective_at("tomorrow").is_err());
+    }
+
+    #[test]
+    fn test_scheduled_change() {
+        let now = util::now().as_secs();
+        let change = ScheduledChange::new(
+            "upstream",
+            "charts",
+            Some(r#"{"addrs":["127.0.0.1:5000"]}"#.to_string()),
+            now + 3600,
+        );
+        let id = change.id.clone();
+        add_scheduled_change(change.clone());
+        let remove_change =
+            ScheduledChange::new("upstream", "charts", None, now + 7200);
+        let remove_id = remove_change.id.clone();
+        add_scheduled_change(remove_change);
+        assert_eq!(
+            true,
+            list_scheduled_changes().iter().any(|item| item.id == id)
+        );
+        assert_eq!(
+            false,
+            take_due_changes(now).iter().any(|item| item.id == id)
+        );
+        let due = take_due_changes(now + 3600);
+        assert_eq!(true, due.iter().any(|item| item.id == id));
+        assert_eq!(
+            false,
+            list_scheduled_changes().iter().any(|item| item.id == id)
+        );
+        assert_eq!(false, cancel_scheduled_change(&id));
+        assert_eq!(true, cancel_scheduled_change(&remove_id));
+
+        let mut conf = PingapConf::default();
+        change.apply(&mut conf).unwrap();
+        assert_eq!(
+            vec!["127.0.0.1:5000".to_string()],
+            conf.upstreams.get("charts").unwrap().addrs
+        );
+        ScheduledChange::new("upstream", "charts", None, now + 1)
+            .apply(&mut conf)
+            .unwrap();
+        assert_eq!(true, conf.upstreams.is_empty());
+    }
+}
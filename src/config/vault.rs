@@ -0,0 +1,429 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{Error, PingapConf, Result};
+use crate::service::SimpleServiceTaskFuture;
+use crate::util;
+use http::Method;
+use once_cell::sync::Lazy;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::{error, info};
+
+// the reference of vault secret, e.g. `vault:secret/data/pingap#tls_key`
+static VAULT_PREFIX: &str = "vault:";
+// the secret without lease is fetched again after the ttl(seconds)
+const DEFAULT_SECRET_TTL: u64 = 300;
+
+#[derive(Debug, Clone, Default)]
+struct VaultSecret {
+    data: Map<String, Value>,
+    lease_id: String,
+    renewable: bool,
+    lease_duration: u64,
+    expired_at: u64,
+}
+
+// the cache of secrets, the key is the path of secret
+static VAULT_SECRETS: Lazy<Mutex<HashMap<String, VaultSecret>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Parse the vault reference to path and field,
+/// e.g. `vault:secret/data/pingap#tls_key`.
+fn parse_vault_reference(value: &str) -> Option<(&str, &str)> {
+    let (path, field) = value.strip_prefix(VAULT_PREFIX)?.split_once('#')?;
+    let path = path.trim_matches('/');
+    if path.is_empty() || field.is_empty() {
+        return None;
+    }
+    Some((path, field))
+}
+
+/// The client of vault, the address and token are read from the env
+/// `VAULT_ADDR`, `VAULT_TOKEN` and the optional `VAULT_NAMESPACE`.
+struct VaultClient {
+    client: reqwest::Client,
+    addr: String,
+    token: String,
+    namespace: String,
+}
+
+fn new_vault_client() -> Result<VaultClient> {
+    let addr = std::env::var("VAULT_ADDR").unwrap_or_default();
+    let token = std::env::var("VAULT_TOKEN").unwrap_or_default();
+    if addr.is_empty() || token.is_empty() {
+        return Err(Error::Invalid {
+            message: "VAULT_ADDR and VAULT_TOKEN should be set for vault"
+                .to_string(),
+        });
+    }
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .map_err(|e| Error::Request { source: e })?;
+    Ok(VaultClient {
+        client,
+        addr: addr.trim_end_matches('/').to_string(),
+        token,
+        namespace: std::env::var("VAULT_NAMESPACE").unwrap_or_default(),
+    })
+}
+
+impl VaultClient {
+    async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<Value>,
+    ) -> Result<Value> {
+        let url = format!("{}/v1/{path}", self.addr);
+        let mut req = self
+            .client
+            .request(method, url)
+            .header("X-Vault-Token", &self.token);
+        if !self.namespace.is_empty() {
+            req = req.header("X-Vault-Namespace", &self.namespace);
+        }
+        if let Some(body) = body {
+            req = req.json(&body);
+        }
+        let resp =
+            req.send().await.map_err(|e| Error::Request { source: e })?;
+        let status = resp.status();
+        if !status.is_success() {
+            let message = resp.text().await.unwrap_or_default();
+            return Err(Error::Invalid {
+                message: format!(
+                    "vault api error, status: {status}, {message}"
+                ),
+            });
+        }
+        resp.json().await.map_err(|e| Error::Request { source: e })
+    }
+    async fn read(&self, path: &str) -> Result<VaultSecret> {
+        let value = self.request(Method::GET, path, None).await?;
+        Ok(new_vault_secret(&value, util::now().as_secs()))
+    }
+    /// Renew the lease of secret, it returns the new lease duration.
+    async fn renew_lease(&self, lease_id: &str, increment: u64) -> Result<u64> {
+        let value = self
+            .request(
+                Method::PUT,
+                "sys/leases/renew",
+                Some(serde_json::json!({
+                    "lease_id": lease_id,
+                    "increment": increment,
+                })),
+            )
+            .await?;
+        Ok(value["lease_duration"].as_u64().unwrap_or_default())
+    }
+    /// Renew the token if it's renewable and more than half of ttl passed.
+    async fn renew_token(&self) -> Result<bool> {
+        let value = self
+            .request(Method::GET, "auth/token/lookup-self", None)
+            .await?;
+        let data = &value["data"];
+        let renewable = data["renewable"].as_bool().unwrap_or_default();
+        let ttl = data["ttl"].as_u64().unwrap_or_default();
+        let creation_ttl = data["creation_ttl"].as_u64().unwrap_or_default();
+        if !renewable || ttl == 0 || ttl * 2 > creation_ttl {
+            return Ok(false);
+        }
+        self.request(Method::POST, "auth/token/renew-self", None)
+            .await?;
+        Ok(true)
+    }
+}
+
+/// Create the secret from the response of vault, the data of kv v2
+/// is nested in `data.data`.
+fn new_vault_secret(value: &Value, now: u64) -> VaultSecret {
+    let mut data = value["data"].as_object().cloned().unwrap_or_default();
+    if data.contains_key("metadata") {
+        if let Some(Value::Object(nested)) = data.remove("data") {
+            data = nested;
+        }
+    }
+    let lease_duration = value["lease_duration"].as_u64().unwrap_or_default();
+    let ttl = if lease_duration > 0 {
+        lease_duration
+    } else {
+        DEFAULT_SECRET_TTL
+    };
+    VaultSecret {
+        data,
+        lease_id: value["lease_id"].as_str().unwrap_or_default().to_string(),
+        renewable: value["renewable"].as_bool().unwrap_or_default(),
+        lease_duration,
+        expired_at: now + ttl,
+    }
+}
+
+fn get_secret_field(secret: &VaultSecret, field: &str) -> Option<String> {
+    match secret.data.get(field)? {
+        Value::String(value) => Some(value.clone()),
+        value => Some(value.to_string()),
+    }
+}
+
+/// Get the secret from cache, it's fetched from vault if not exists
+/// or expired.
+async fn get_secret(client: &VaultClient, path: &str) -> Result<VaultSecret> {
+    let now = util::now().as_secs();
+    if let Some(secret) = VAULT_SECRETS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(path)
+        .filter(|item| item.expired_at > now)
+    {
+        return Ok(secret.clone());
+    }
+    let secret = client.read(path).await?;
+    info!(
+        path,
+        lease_duration = secret.lease_duration,
+        "fetch vault secret"
+    );
+    VAULT_SECRETS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(path.to_string(), secret.clone());
+    Ok(secret)
+}
+
+fn collect_vault_references(value: &toml::Value, references: &mut Vec<String>) {
+    match value {
+        toml::Value::String(data) => {
+            if parse_vault_reference(data).is_some()
+                && !references.contains(data)
+            {
+                references.push(data.clone());
+            }
+        },
+        toml::Value::Array(values) => values
+            .iter()
+            .for_each(|item| collect_vault_references(item, references)),
+        toml::Value::Table(values) => values
+            .values()
+            .for_each(|item| collect_vault_references(item, references)),
+        _ => {},
+    }
+}
+
+fn replace_vault_references(
+    value: &mut toml::Value,
+    secrets: &HashMap<String, String>,
+) {
+    match value {
+        toml::Value::String(data) => {
+            if let Some(secret) = secrets.get(data.as_str()) {
+                *data = secret.clone();
+            }
+        },
+        toml::Value::Array(values) => values
+            .iter_mut()
+            .for_each(|item| replace_vault_references(item, secrets)),
+        toml::Value::Table(values) => values
+            .values_mut()
+            .for_each(|item| replace_vault_references(item, secrets)),
+        _ => {},
+    }
+}
+
+/// Replace the vault references of config with the secrets,
+/// the config is returned directly if there is no reference.
+pub async fn replace_vault_secrets(conf: PingapConf) -> Result<PingapConf> {
+    let mut value =
+        toml::Value::try_from(&conf).map_err(|e| Error::Ser { source: e })?;
+    let mut references = vec![];
+    collect_vault_references(&value, &mut references);
+    if references.is_empty() {
+        return Ok(conf);
+    }
+    let client = new_vault_client()?;
+    let mut secrets = HashMap::new();
+    for reference in references {
+        let Some((path, field)) = parse_vault_reference(&reference) else {
+            continue;
+        };
+        let secret = get_secret(&client, path).await?;
+        let data =
+            get_secret_field(&secret, field).ok_or_else(|| Error::Invalid {
+                message: format!(
+                    "field({field}) of vault secret({path}) is not found"
+                ),
+            })?;
+        secrets.insert(reference, data);
+    }
+    replace_vault_references(&mut value, &secrets);
+    let data =
+        toml::to_string_pretty(&value).map_err(|e| Error::Ser { source: e })?;
+    PingapConf::new(data.as_bytes(), false)
+}
+
+async fn do_vault_renew(_count: u32) -> Result<bool, String> {
+    let client = new_vault_client().map_err(|e| e.to_string())?;
+    let mut executed = client.renew_token().await.map_err(|e| e.to_string())?;
+    let now = util::now().as_secs();
+    let secrets: Vec<(String, VaultSecret)> = VAULT_SECRETS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .map(|(path, secret)| (path.clone(), secret.clone()))
+        .collect();
+    for (path, secret) in secrets {
+        // renew the lease if less than one third of duration is left
+        if !secret.renewable
+            || secret.lease_id.is_empty()
+            || secret.expired_at > now + secret.lease_duration / 3
+        {
+            continue;
+        }
+        executed = true;
+        let result = client
+            .renew_lease(&secret.lease_id, secret.lease_duration)
+            .await;
+        let mut secrets =
+            VAULT_SECRETS.lock().unwrap_or_else(|e| e.into_inner());
+        match result {
+            Ok(lease_duration) if lease_duration > 0 => {
+                if let Some(item) = secrets.get_mut(&path) {
+                    item.expired_at = now + lease_duration;
+                }
+            },
+            Ok(_) => {
+                secrets.remove(&path);
+            },
+            Err(e) => {
+                // it will be fetched again at the next config loading
+                error!(error = e.to_string(), path, "renew vault lease fail");
+                secrets.remove(&path);
+            },
+        }
+    }
+    Ok(executed)
+}
+
+/// Create a service task to renew the token and the leases of secrets,
+/// it's none if the vault address isn't set.
+pub fn new_vault_renew_service() -> Option<(String, SimpleServiceTaskFuture)> {
+    if std::env::var("VAULT_ADDR").unwrap_or_default().is_empty() {
+        return None;
+    }
+    let task: SimpleServiceTaskFuture =
+        Box::new(|count: u32| Box::pin(do_vault_renew(count)));
+    Some(("vaultRenew".to_string(), task))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        collect_vault_references, get_secret_field, new_vault_secret,
+        parse_vault_reference, replace_vault_references, DEFAULT_SECRET_TTL,
+    };
+    use pretty_assertions::assert_eq;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_parse_vault_reference() {
+        assert_eq!(
+            Some(("secret/data/pingap", "tls_key")),
+            parse_vault_reference("vault:/secret/data/pingap#tls_key")
+        );
+        assert_eq!(None, parse_vault_reference("vault:secret/data/pingap"));
+        assert_eq!(None, parse_vault_reference("vault:#tls_key"));
+        assert_eq!(None, parse_vault_reference("secret/data/pingap#tls_key"));
+    }
+
+    #[test]
+    fn test_new_vault_secret() {
+        // kv v2
+        let secret = new_vault_secret(
+            &serde_json::json!({
+                "lease_id": "",
+                "renewable": false,
+                "lease_duration": 0,
+                "data": {
+                    "data": {"token": "abc", "port": 3000},
+                    "metadata": {"version": 1}
+                }
+            }),
+            100,
+        );
+        assert_eq!(100 + DEFAULT_SECRET_TTL, secret.expired_at);
+        assert_eq!(Some("abc".to_string()), get_secret_field(&secret, "token"));
+        assert_eq!(Some("3000".to_string()), get_secret_field(&secret, "port"));
+        assert_eq!(None, get_secret_field(&secret, "key"));
+
+        // dynamic secret with lease
+        let secret = new_vault_secret(
+            &serde_json::json!({
+                "lease_id": "database/creds/pingap/1",
+                "renewable": true,
+                "lease_duration": 3600,
+                "data": {"username": "pingap"}
+            }),
+            100,
+        );
+        assert_eq!(true, secret.renewable);
+        assert_eq!(3700, secret.expired_at);
+        assert_eq!(
+            Some("pingap".to_string()),
+            get_secret_field(&secret, "username")
+        );
+    }
+
+    #[test]
+    fn test_replace_vault_references() {
+        let mut value: toml::Value = toml::from_str(
+            r###"
+[plugins.auth]
+authorizations = ["vault:secret/data/pingap#basic", "YWRtaW46MTIzMTIz"]
+
+[certificates.pingap]
+tls_key = "vault:secret/data/pingap#tls_key"
+"###,
+        )
+        .unwrap();
+        let mut references = vec![];
+        collect_vault_references(&value, &mut references);
+        references.sort();
+        assert_eq!(
+            vec![
+                "vault:secret/data/pingap#basic".to_string(),
+                "vault:secret/data/pingap#tls_key".to_string(),
+            ],
+            references
+        );
+
+        let mut secrets = HashMap::new();
+        secrets.insert(references[0].clone(), "basic".to_string());
+        secrets.insert(references[1].clone(), "key".to_string());
+        replace_vault_references(&mut value, &secrets);
+        assert_eq!(
+            "key",
+            value["certificates"]["pingap"]["tls_key"].as_str().unwrap()
+        );
+        assert_eq!(
+            toml::Value::Array(vec![
+                toml::Value::String("basic".to_string()),
+                toml::Value::String("YWRtaW46MTIzMTIz".to_string()),
+            ]),
+            value["plugins"]["auth"]["authorizations"]
+        );
+    }
+}
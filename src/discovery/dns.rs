@@ -158,7 +158,7 @@ impl ServiceDiscovery for Dns {
                         webhook::NotificationCategory::ServiceDiscoverFail,
                     level: webhook::NotificationLevel::Warn,
                     msg: format!("dns discovery {:?}, error: {e}", self.hosts),
-                    remark: None,
+                    ..Default::default()
                 })
                 .await;
                 return Err(e.into());
@@ -226,7 +226,7 @@ impl ServiceDiscovery for Docker {
                         "docker discovery {:?}, error: {e}",
                         self.labels(),
                     ),
-                    remark: None,
+                    ..Default::default()
                 })
                 .await;
                 return Err(e.into());
@@ -197,7 +197,7 @@ pub static HTTP_HEADER_CONTENT_HTML: Lazy<HttpHeader> = Lazy::new(|| {
     )
 });
 
-#[cfg(feature = "full")]
+#[cfg(feature = "metrics")]
 pub static HTTP_HEADER_CONTENT_TEXT: Lazy<HttpHeader> = Lazy::new(|| {
     (
         header::CONTENT_TYPE,
@@ -215,6 +215,9 @@ pub static HTTP_HEADER_TRANSFER_CHUNKED: Lazy<HttpHeader> = Lazy::new(|| {
 pub static HTTP_HEADER_NAME_X_REQUEST_ID: Lazy<HeaderName> =
     Lazy::new(|| HeaderName::from_str("X-Request-Id").unwrap());
 
+pub static HTTP_HEADER_NAME_X_PINGAP_VIA: Lazy<HeaderName> =
+    Lazy::new(|| HeaderName::from_str("X-Pingap-Via").unwrap());
+
 #[cfg(test)]
 mod tests {
     use super::{
@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-#[cfg(feature = "full")]
+#[cfg(feature = "metrics")]
 use super::HTTP_HEADER_CONTENT_TEXT;
 use super::{
     HttpHeader, HTTP_HEADER_CONTENT_HTML, HTTP_HEADER_CONTENT_JSON,
@@ -116,7 +116,7 @@ impl HttpResponse {
         }
     }
 
-    #[cfg(feature = "full")]
+    #[cfg(feature = "metrics")]
     /// Create a text response with `no-cache` cache-control.
     pub fn text(body: Bytes) -> Self {
         Self {
@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+#[cfg(feature = "acme")]
 pub mod acme;
 pub mod cache;
 pub mod certificate;
@@ -21,7 +22,7 @@ pub mod health;
 pub mod http_extra;
 pub mod limit;
 pub mod logger;
-#[cfg(feature = "full")]
+#[cfg(feature = "otel")]
 pub mod otel;
 pub mod plugin;
 pub mod proxy;
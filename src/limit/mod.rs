@@ -12,6 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod rate_limit;
 mod ttl_lru_limit;
 
+pub use rate_limit::{SlidingWindowLimit, TokenBucketLimit};
 pub use ttl_lru_limit::TtlLruLimit;
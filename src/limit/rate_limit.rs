@@ -0,0 +1,183 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::util;
+use ahash::AHashMap;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const SHARDS: usize = 16;
+// the idle keys of shard are removed if its size exceeds it
+const MAX_SHARD_KEYS: usize = 4096;
+
+/// The key value store split into shards to reduce lock contention,
+/// the value is created by default and updated by the function.
+struct ShardedStore<V> {
+    hasher: ahash::RandomState,
+    shards: Vec<Mutex<AHashMap<String, (V, u64)>>>,
+    idle_ms: u64,
+}
+
+impl<V: Default> ShardedStore<V> {
+    fn new(idle: Duration) -> Self {
+        Self {
+            hasher: ahash::RandomState::new(),
+            shards: (0..SHARDS).map(|_| Mutex::new(AHashMap::new())).collect(),
+            idle_ms: idle.as_millis().max(1) as u64,
+        }
+    }
+    fn update<T>(&self, key: &str, f: impl FnOnce(&mut V, u64) -> T) -> T {
+        let mut hasher = self.hasher.build_hasher();
+        key.hash(&mut hasher);
+        let index = hasher.finish() as usize % SHARDS;
+        let now = util::now().as_millis() as u64;
+        let mut shard =
+            self.shards[index].lock().unwrap_or_else(|e| e.into_inner());
+        if shard.len() >= MAX_SHARD_KEYS && !shard.contains_key(key) {
+            let idle_ms = self.idle_ms;
+            shard.retain(|_, (_, last)| now.saturating_sub(*last) < idle_ms);
+        }
+        let entry = shard
+            .entry(key.to_string())
+            .or_insert_with(|| (V::default(), now));
+        entry.1 = now;
+        f(&mut entry.0, now)
+    }
+}
+
+#[derive(Default)]
+struct SlidingWindow {
+    start: u64,
+    previous: u64,
+    current: u64,
+}
+
+/// The sliding window limit, the count is estimated by the weighted
+/// count of previous window and the count of current window, so it
+/// has no burst at the boundary of windows.
+pub struct SlidingWindowLimit {
+    interval: u64,
+    store: ShardedStore<SlidingWindow>,
+}
+
+impl SlidingWindowLimit {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval: interval.as_millis().max(1) as u64,
+            store: ShardedStore::new(interval * 2),
+        }
+    }
+    /// Observe a request of key, and returns the estimated count of
+    /// the sliding window including this request.
+    pub fn observe(&self, key: &str) -> isize {
+        let interval = self.interval;
+        self.store.update(key, |window, now| {
+            let start = now - now % interval;
+            if start != window.start {
+                window.previous = if start - window.start == interval {
+                    window.current
+                } else {
+                    0
+                };
+                window.current = 0;
+                window.start = start;
+            }
+            window.current += 1;
+            let weight = 1.0 - (now - start) as f64 / interval as f64;
+            (window.previous as f64 * weight) as isize + window.current as isize
+        })
+    }
+}
+
+#[derive(Default)]
+struct Bucket {
+    tokens: f64,
+    updated_at: u64,
+}
+
+/// The token bucket limit, the tokens are refilled at the rate of
+/// `max/interval`, and the capacity of bucket is the burst.
+pub struct TokenBucketLimit {
+    // the tokens refilled per millisecond
+    refill: f64,
+    capacity: f64,
+    store: ShardedStore<Bucket>,
+}
+
+impl TokenBucketLimit {
+    pub fn new(max: usize, interval: Duration, burst: usize) -> Self {
+        let interval = interval.as_millis().max(1);
+        let capacity = burst.max(1) as f64;
+        let refill = max as f64 / interval as f64;
+        // the idle bucket is full, so it can be removed
+        let idle = if refill > 0.0 {
+            Duration::from_millis((capacity / refill) as u64 + 1)
+        } else {
+            Duration::from_secs(3600)
+        };
+        Self {
+            refill,
+            capacity,
+            store: ShardedStore::new(idle),
+        }
+    }
+    /// Take a token of key, it returns false if the bucket is empty.
+    pub fn acquire(&self, key: &str) -> bool {
+        let refill = self.refill;
+        let capacity = self.capacity;
+        self.store.update(key, |bucket, now| {
+            if bucket.updated_at == 0 {
+                bucket.tokens = capacity;
+            } else {
+                let elapsed = now.saturating_sub(bucket.updated_at) as f64;
+                bucket.tokens =
+                    (bucket.tokens + elapsed * refill).min(capacity);
+            }
+            bucket.updated_at = now;
+            if bucket.tokens < 1.0 {
+                return false;
+            }
+            bucket.tokens -= 1.0;
+            true
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SlidingWindowLimit, TokenBucketLimit};
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+
+    #[test]
+    fn test_sliding_window_limit() {
+        let limit = SlidingWindowLimit::new(Duration::from_secs(60));
+        assert_eq!(1, limit.observe("a"));
+        assert_eq!(2, limit.observe("a"));
+        assert_eq!(1, limit.observe("b"));
+    }
+
+    #[tokio::test]
+    async fn test_token_bucket_limit() {
+        let limit = TokenBucketLimit::new(10, Duration::from_millis(100), 2);
+        assert_eq!(true, limit.acquire("a"));
+        assert_eq!(true, limit.acquire("a"));
+        assert_eq!(false, limit.acquire("a"));
+        assert_eq!(true, limit.acquire("b"));
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(true, limit.acquire("a"));
+    }
+}
@@ -0,0 +1,218 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::config::get_current_config;
+use crate::util;
+use once_cell::sync::{Lazy, OnceCell};
+use std::backtrace::Backtrace;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+// the count of recent warn and error logs kept for crash report
+const RECENT_LOG_CAPACITY: usize = 100;
+
+static RECENT_LOGS: Lazy<Mutex<VecDeque<String>>> =
+    Lazy::new(|| Mutex::new(VecDeque::with_capacity(RECENT_LOG_CAPACITY)));
+static CRASH_REPORT_DIR: OnceCell<PathBuf> = OnceCell::new();
+
+fn push_recent_log(data: &[u8]) {
+    let line = String::from_utf8_lossy(data).trim_end().to_string();
+    if line.is_empty() {
+        return;
+    }
+    let mut logs = RECENT_LOGS.lock().unwrap_or_else(|e| e.into_inner());
+    if logs.len() >= RECENT_LOG_CAPACITY {
+        logs.pop_front();
+    }
+    logs.push_back(line);
+}
+
+/// The writer keeps the warn and error logs in ring buffer,
+/// and writes all logs to the inner writer.
+pub struct RecentLogWriter<W> {
+    inner: W,
+    recent: bool,
+}
+
+impl<W: Write> Write for RecentLogWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let size = self.inner.write(buf)?;
+        if self.recent {
+            push_recent_log(&buf[..size]);
+        }
+        Ok(size)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub struct RecentLogMakeWriter<M> {
+    inner: M,
+}
+
+impl<M> RecentLogMakeWriter<M> {
+    pub fn new(inner: M) -> Self {
+        Self { inner }
+    }
+}
+
+impl<'a, M: MakeWriter<'a>> MakeWriter<'a> for RecentLogMakeWriter<M> {
+    type Writer = RecentLogWriter<M::Writer>;
+    fn make_writer(&'a self) -> Self::Writer {
+        RecentLogWriter {
+            inner: self.inner.make_writer(),
+            recent: false,
+        }
+    }
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        RecentLogWriter {
+            inner: self.inner.make_writer_for(meta),
+            recent: *meta.level() <= Level::WARN,
+        }
+    }
+}
+
+/// Get the recent warn and error logs.
+pub fn get_recent_logs() -> Vec<String> {
+    RECENT_LOGS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .cloned()
+        .collect()
+}
+
+/// Set the directory of crash report and install the panic hook,
+/// the crash report will be written when panic.
+pub fn set_crash_report_dir(dir: &str) -> io::Result<()> {
+    let dir = PathBuf::from(util::resolve_path(dir));
+    std::fs::create_dir_all(&dir)?;
+    if CRASH_REPORT_DIR.set(dir).is_err() {
+        return Ok(());
+    }
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if let Some(file) = write_crash_report(&info.to_string()) {
+            // use eprintln because the log may be not flushed
+            eprintln!("crash report is written to {}", file.display());
+        }
+        default_hook(info);
+    }));
+    Ok(())
+}
+
+fn new_crash_report(reason: &str) -> String {
+    let config_hash = get_current_config().hash().unwrap_or_default();
+    let logs = get_recent_logs().join("\n");
+    let thread = std::thread::current();
+    format!(
+        r###"name: {}
+version: {}
+rustc: {}
+pid: {}
+thread: {}
+time: {}
+config_hash: {config_hash}
+
+reason:
+{reason}
+
+recent logs:
+{logs}
+
+backtrace:
+{}
+"###,
+        util::get_pkg_name(),
+        util::get_pkg_version(),
+        util::get_rustc_version(),
+        std::process::id(),
+        thread.name().unwrap_or("unnamed"),
+        chrono::Local::now().to_rfc3339(),
+        Backtrace::force_capture(),
+    )
+}
+
+fn write_crash_report_to(dir: &Path, reason: &str) -> io::Result<PathBuf> {
+    let file = dir.join(format!(
+        "crash-{}-{}.log",
+        chrono::Local::now().format("%Y%m%d%H%M%S%3f"),
+        std::process::id()
+    ));
+    std::fs::write(&file, new_crash_report(reason))?;
+    Ok(file)
+}
+
+/// Write the crash report to the directory, it returns none
+/// if the directory isn't set or write fail.
+pub fn write_crash_report(reason: &str) -> Option<PathBuf> {
+    let dir = CRASH_REPORT_DIR.get()?;
+    match write_crash_report_to(dir, reason) {
+        Ok(file) => Some(file),
+        Err(e) => {
+            eprintln!("write crash report fail, {e}");
+            None
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        push_recent_log, write_crash_report_to, RecentLogWriter, RECENT_LOGS,
+        RECENT_LOG_CAPACITY,
+    };
+    use pretty_assertions::assert_eq;
+    use std::io::Write;
+
+    #[test]
+    fn test_crash_report() {
+        for i in 0..RECENT_LOG_CAPACITY + 10 {
+            push_recent_log(format!("error {i}\n").as_bytes());
+        }
+        {
+            let logs = RECENT_LOGS.lock().unwrap();
+            assert_eq!(RECENT_LOG_CAPACITY, logs.len());
+            assert_eq!("error 10", logs.front().unwrap());
+        }
+
+        let mut writer = RecentLogWriter {
+            inner: std::io::sink(),
+            recent: true,
+        };
+        writer.write_all(b"upstream is unhealthy\n").unwrap();
+        let mut writer = RecentLogWriter {
+            inner: std::io::sink(),
+            recent: false,
+        };
+        writer.write_all(b"request is done\n").unwrap();
+        assert_eq!(
+            "upstream is unhealthy",
+            RECENT_LOGS.lock().unwrap().back().unwrap()
+        );
+
+        let dir = std::env::temp_dir();
+        let file = write_crash_report_to(&dir, "panic at test").unwrap();
+        let data = std::fs::read_to_string(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(true, data.contains("reason:\npanic at test"));
+        assert_eq!(true, data.contains("upstream is unhealthy"));
+        assert_eq!(true, data.contains("backtrace:"));
+    }
+}
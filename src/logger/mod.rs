@@ -30,6 +30,10 @@ use tracing::{error, info, Level};
 use tracing_subscriber::fmt::writer::BoxMakeWriter;
 use walkdir::WalkDir;
 
+mod crash;
+
+pub use crash::{get_recent_logs, set_crash_report_dir, write_crash_report};
+
 static GZIP_EXT: &str = "gz";
 static ZSTD_EXT: &str = "zst";
 
@@ -283,6 +287,8 @@ pub fn logger_try_init(
             BoxMakeWriter::new(Mutex::new(w))
         }
     };
+    // keep the recent warn and error logs for crash report
+    let writer = BoxMakeWriter::new(crash::RecentLogMakeWriter::new(writer));
     if params.json {
         builder
             .event_format(tracing_subscriber::fmt::format::json())
@@ -12,17 +12,20 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+#[cfg(feature = "acme")]
 use acme::new_lets_encrypt_service;
-use cache::new_file_storage_clear_service;
+use cache::{
+    new_file_storage_clear_service, new_prefetch_service, new_warm_up_service,
+};
 use certificate::{
     new_certificate_validity_service,
     new_self_signed_certificate_validity_service,
 };
-use clap::Parser;
-use config::ETCD_PROTOCOL;
-use config::{LoadConfigOptions, PingapConf};
+use clap::{Parser, Subcommand};
+use config::{new_scheduled_config_service, LoadConfigOptions, PingapConf};
+use config::{ETCD_PROTOCOL, KUBERNETES_PROTOCOL};
 use crossbeam_channel::Sender;
-#[cfg(feature = "full")]
+#[cfg(feature = "otel")]
 use otel::TracerService;
 use pingora::server;
 use pingora::server::configuration::Opt;
@@ -32,7 +35,7 @@ use service::new_simple_service_task;
 use service::{new_auto_restart_service, new_observer_service};
 use state::{
     get_admin_addr, get_start_time, new_performance_metrics_log_service,
-    set_admin_addr,
+    new_slo_burn_rate_service, set_admin_addr,
 };
 use std::collections::HashMap;
 use std::error::Error;
@@ -42,6 +45,7 @@ use std::sync::Arc;
 use std::time::Duration;
 use tracing::{error, info};
 
+#[cfg(feature = "acme")]
 mod acme;
 mod cache;
 mod certificate;
@@ -51,13 +55,13 @@ mod health;
 mod http_extra;
 mod limit;
 mod logger;
-#[cfg(feature = "full")]
+#[cfg(feature = "otel")]
 mod otel;
 mod plugin;
 mod proxy;
 #[cfg(feature = "pyro")]
 mod pyro;
-#[cfg(feature = "full")]
+#[cfg(feature = "sentry")]
 mod sentry;
 mod service;
 mod state;
@@ -91,6 +95,13 @@ struct Args {
     /// service can start before shutting down the old server process.
     #[arg(short, long)]
     test: bool,
+    /// Test the configuration fully and exit
+    ///
+    /// Beyond parsing, it binds the listeners, loads the certificates,
+    /// compiles the regexes and instantiates the plugins, then prints
+    /// the report as json and exits non-zero if any check fails.
+    #[arg(long)]
+    test_full: bool,
     /// Log file path
     #[arg(long)]
     log: Option<String>,
@@ -115,6 +126,44 @@ struct Args {
     /// Print the template configuration and exit
     #[arg(long)]
     template: bool,
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Check the candidate config and exit
+    ///
+    /// The candidate config is checked fully like `--test-full`, and the
+    /// changes against the config of `--conf` are printed as json.
+    /// It exits non-zero if any check fails, which is useful for CI.
+    Check {
+        /// The candidate config file or directory
+        candidate: String,
+    },
+    /// Import the nginx config, print the pingap config and exit
+    ///
+    /// The server, location, upstream, proxy_pass, headers, rewrite and
+    /// limit directives are converted, the unsupported directives are
+    /// printed as comments for manual migration.
+    ImportNginx {
+        /// The nginx config file
+        file: String,
+    },
+    /// Upgrade the running server to the binary and exit
+    ///
+    /// The new binary is started with the args of this command, the old
+    /// server hands over the listeners only after the new one is ready,
+    /// then it drains the in-flight requests. The report is printed as
+    /// json and sent by webhook, it exits non-zero if the upgrade fails.
+    Upgrade {
+        /// The path of new binary
+        exec_path: String,
+        /// The deadline of upgrade, default is the grace period and
+        /// graceful shutdown timeout of old server with one minute
+        #[arg(long, value_parser = humantime::parse_duration)]
+        timeout: Option<Duration>,
+    },
 }
 
 fn new_server_conf(
@@ -259,7 +308,8 @@ fn parse_arguments() -> Args {
     if !exist_config_argument {
         let conf = get_from_env("conf");
         if !conf.is_empty() {
-            arr.push(format!("-c={conf}").into());
+            // before the subcommand, it's the option of pingap
+            arr.insert(arr.len().min(1), format!("-c={conf}").into());
         }
     }
 
@@ -307,12 +357,49 @@ fn parse_arguments() -> Args {
     args
 }
 
+/// Get the args of new process, which takes over the listeners
+/// of current process.
+fn get_upgrade_args(args: &Args) -> Vec<String> {
+    let conf_path = if args.conf.starts_with(ETCD_PROTOCOL)
+        || args.conf.starts_with(KUBERNETES_PROTOCOL)
+    {
+        args.conf.clone()
+    } else {
+        util::resolve_path(&args.conf)
+    };
+
+    let mut new_args = vec![
+        format!("-c={conf_path}"),
+        "-d".to_string(),
+        "-u".to_string(),
+    ];
+    if let Some(log) = &args.log {
+        new_args.push(format!("--log={log}"));
+    }
+    if let Some(admin) = &args.admin {
+        new_args.push(format!("--admin={admin}"));
+    }
+    if args.autorestart {
+        new_args.push("--autorestart".to_string());
+    }
+    new_args
+}
+
 fn run() -> Result<(), Box<dyn Error>> {
     let args = parse_arguments();
     if args.template {
         println!("{TEMPLATE_CONFIG}");
         return Ok(());
     }
+    if let Some(Commands::ImportNginx { file }) = &args.command {
+        let data = std::fs::read_to_string(file)?;
+        let result = config::import_nginx(&data)?;
+        for warning in result.warnings.iter() {
+            println!("# warning: {warning}");
+        }
+        println!("{}", toml::to_string_pretty(&result.conf)?);
+        return Ok(());
+    }
 
     if let Some(admin) = &args.admin {
         set_admin_addr(admin);
@@ -331,10 +418,39 @@ fn run() -> Result<(), Box<dyn Error>> {
         level: conf.basic.log_level.clone().unwrap_or_default(),
         json: conf.basic.log_format_json.unwrap_or_default(),
     })?;
+    if let Some(dir) = &conf.basic.crash_report_dir {
+        logger::set_crash_report_dir(dir)?;
+    }
     // TODO a better way
     // since the cache will be initialized in validate function
     // so set the current conf first
     config::set_current_config(&conf);
+    if let Some(Commands::Check { candidate }) = &args.command {
+        let candidate = tokio::runtime::Runtime::new()?.block_on(
+            config::load_config_from_path(
+                candidate,
+                LoadConfigOptions {
+                    replace_include: true,
+                    ..Default::default()
+                },
+            ),
+        )?;
+        config::set_current_config(&candidate);
+        let report = proxy::check_candidate(&candidate, &conf);
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        if !report.success {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if args.test_full {
+        let report = proxy::check_full(&conf);
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        if !report.success {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
     conf.validate()?;
 
     // sync config to other storage
@@ -356,12 +472,45 @@ fn run() -> Result<(), Box<dyn Error>> {
         &conf.basic.webhook_notifications.clone().unwrap_or_default(),
     );
 
+    let id_generator = util::IdGenerator::from_str(
+        &basic_conf.id_generator.clone().unwrap_or_default(),
+    )?;
+    util::set_id_generator(
+        id_generator,
+        basic_conf.node_id.unwrap_or_default(),
+    );
+
     // return if test mode
     if args.test {
         info!("Validate config success");
         return Ok(());
     }
 
+    if let Some(Commands::Upgrade { exec_path, timeout }) = &args.command {
+        let timeout = timeout.unwrap_or_else(|| {
+            basic_conf.grace_period.unwrap_or(Duration::from_secs(300))
+                + basic_conf
+                    .graceful_shutdown_timeout
+                    .unwrap_or(Duration::from_secs(5))
+                + Duration::from_secs(60)
+        });
+        let params = state::UpgradeParams {
+            exec_path: util::resolve_path(exec_path).into(),
+            log_level: std::env::var("RUST_LOG").unwrap_or_default(),
+            args: get_upgrade_args(&args),
+            pid_file: basic_conf.get_pid_file(),
+            upgrade_sock: new_server_conf(&args, &conf).upgrade_sock,
+            timeout,
+        };
+        let report =
+            tokio::runtime::Runtime::new()?.block_on(state::upgrade(&params));
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        if !report.success {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     let auto_restart_check_interval = basic_conf
         .auto_restart_check_interval
         .map_or(Duration::from_secs(90), |item| item);
@@ -377,27 +526,7 @@ fn run() -> Result<(), Box<dyn Error>> {
         if let Ok(env) = std::env::var("RUST_LOG") {
             cmd.log_level = env;
         }
-        let conf_path = if args.conf.starts_with(ETCD_PROTOCOL) {
-            args.conf.clone()
-        } else {
-            util::resolve_path(&args.conf)
-        };
-
-        let mut new_args = vec![
-            format!("-c={conf_path}"),
-            "-d".to_string(),
-            "-u".to_string(),
-        ];
-        if let Some(log) = &args.log {
-            new_args.push(format!("--log={log}"));
-        }
-        if let Some(admin) = &args.admin {
-            new_args.push(format!("--admin={admin}"));
-        }
-        if args.autorestart {
-            new_args.push("--autorestart".to_string());
-        }
-        cmd.args = new_args;
+        cmd.args = get_upgrade_args(&args);
         state::set_restart_process_command(cmd);
     }
 
@@ -417,7 +546,7 @@ fn run() -> Result<(), Box<dyn Error>> {
     let server_conf = new_server_conf(&args, &conf);
     info!(server_conf = format!("{server_conf:?}"),);
     my_server.configuration = Arc::new(server_conf);
-    #[cfg(feature = "full")]
+    #[cfg(feature = "sentry")]
     {
         let sentry_dsn = basic_conf.sentry.clone().unwrap_or_default();
         if !sentry_dsn.is_empty() {
@@ -459,13 +588,12 @@ fn run() -> Result<(), Box<dyn Error>> {
         }
     }
 
-    let mut enabled_lets_encrypt = false;
     let mut exits_80_server = false;
     for serve_conf in server_conf_list.iter() {
         if serve_conf.addr.ends_with(":80") {
             exits_80_server = true;
         }
-        #[cfg(feature = "full")]
+        #[cfg(feature = "otel")]
         // add otlp service
         if let Some(otlp_exporter) = &serve_conf.otlp_exporter {
             my_server.add_service(background_service(
@@ -479,34 +607,49 @@ fn run() -> Result<(), Box<dyn Error>> {
         new_certificate_validity_service(),
         new_self_signed_certificate_validity_service(),
         new_performance_metrics_log_service(),
+        new_slo_burn_rate_service(),
+        new_scheduled_config_service(),
     ];
     if let Some(task) = new_file_storage_clear_service() {
         simple_tasks.push(task);
     }
+    if let Some(task) = plugin::new_cache_checkpoint_service() {
+        simple_tasks.push(task);
+    }
+    if let Some(task) = config::new_vault_renew_service() {
+        simple_tasks.push(task);
+    }
     if let Some(compression_task) = compression_task {
         simple_tasks.push(compression_task);
     }
 
-    let mut lets_encrypt_params = vec![];
-    for (name, certificate) in certificates.iter() {
-        let acme = certificate.acme.clone().unwrap_or_default();
-        let domains = certificate.domains.clone().unwrap_or_default();
-        if acme.is_empty() || domains.is_empty() {
-            continue;
-        }
-        enabled_lets_encrypt = true;
-        // only a single instance of acme is running
-        if std::env::var("PINGAP_DISABLE_ACME")
-            .unwrap_or_default()
-            .is_empty()
-        {
-            lets_encrypt_params.push((
+    let lets_encrypt_params: Vec<(String, Vec<String>)> = certificates
+        .iter()
+        .filter_map(|(name, certificate)| {
+            let acme = certificate.acme.clone().unwrap_or_default();
+            let domains = certificate.domains.clone().unwrap_or_default();
+            if acme.is_empty() || domains.is_empty() {
+                return None;
+            }
+            Some((
                 name.to_string(),
                 domains.split(',').map(|item| item.to_string()).collect(),
-            ));
-        }
-    }
+            ))
+        })
+        .collect();
+    #[cfg(not(feature = "acme"))]
     if !lets_encrypt_params.is_empty() {
+        error!("lets encrypt is ignored, it requires the acme feature");
+    }
+    let enabled_lets_encrypt =
+        cfg!(feature = "acme") && !lets_encrypt_params.is_empty();
+    // only a single instance of acme is running
+    #[cfg(feature = "acme")]
+    if enabled_lets_encrypt
+        && std::env::var("PINGAP_DISABLE_ACME")
+            .unwrap_or_default()
+            .is_empty()
+    {
         simple_tasks.push(new_lets_encrypt_service(lets_encrypt_params));
     }
 
@@ -532,9 +675,8 @@ fn run() -> Result<(), Box<dyn Error>> {
     }
 
     for server_conf in server_conf_list.iter() {
-        let listen_80_port = server_conf.addr.ends_with(":80");
         let mut ps = Server::new(server_conf)?;
-        if enabled_lets_encrypt && listen_80_port {
+        if enabled_lets_encrypt && server_conf.addr.ends_with(":80") {
             ps.enable_lets_encrypt();
         }
         if let Some(service) = ps.get_prometheus_push_service() {
@@ -578,6 +720,14 @@ fn run() -> Result<(), Box<dyn Error>> {
         "UpstreamHc",
         new_upstream_health_check_task(Duration::from_secs(10)),
     ));
+    if let Some(warm_up_service) = new_warm_up_service() {
+        my_server
+            .add_service(background_service("CacheWarmUp", warm_up_service));
+    }
+    if let Some(prefetch_service) = new_prefetch_service() {
+        my_server
+            .add_service(background_service("CachePrefetch", prefetch_service));
+    }
 
     #[cfg(feature = "perf")]
     {
@@ -597,6 +747,9 @@ fn run() -> Result<(), Box<dyn Error>> {
 fn main() {
     if let Err(e) = run() {
         println!("{e}");
-        error!(error = e.to_string());
+        let crash_report = logger::write_crash_report(&e.to_string())
+            .map(|file| file.to_string_lossy().to_string())
+            .unwrap_or_default();
+        error!(error = e.to_string(), crash_report);
     }
 }
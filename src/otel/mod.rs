@@ -180,7 +180,7 @@ impl BackgroundService for TracerService {
                 let mut propagators: Vec<
                     Box<dyn TextMapPropagator + Send + Sync>,
                 > = vec![Box::new(TraceContextPropagator::new())];
-                #[cfg(feature = "full")]
+                #[cfg(feature = "otel")]
                 if self.support_jaeger_propagator {
                     propagators.push(Box::new(
                         opentelemetry_jaeger_propagator::Propagator::new(),
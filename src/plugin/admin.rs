@@ -12,58 +12,86 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use super::basic_auth::{
+    get_basic_auth_credential, is_supported_hash, verify_password,
+};
+use super::cache::get_cache_backend;
+use super::cache_purge::purge_cache;
 use super::{
     get_hash_key, get_int_conf, get_step_conf, get_str_conf,
     get_str_slice_conf, Error, Plugin, Result,
 };
+use crate::cache::{warm_up, CacheKeyList};
+use crate::config::{
+    self, get_current_config, save_config, LoadConfigOptions, PluginCategory,
+    PluginConf, PluginStep, CATEGORY_CERTIFICATE,
+};
 use crate::config::{
-    self, get_current_config, save_config, BasicConf, CertificateConf,
-    LoadConfigOptions, LocationConf, PluginCategory, PluginConf, PluginStep,
-    ServerConf, StorageConf, UpstreamConf, CATEGORY_CERTIFICATE,
-    CATEGORY_STORAGE,
+    add_scheduled_change, cancel_scheduled_change, list_scheduled_changes,
+    parse_effective_at, ScheduledChange,
+};
+use crate::config::{append_audit, query_audit, AuditQuery, AuditRecord};
+use crate::config::{
+    get_history, list_history, rollback_history, save_history,
 };
 use crate::config::{
     PingapConf, CATEGORY_LOCATION, CATEGORY_PLUGIN, CATEGORY_SERVER,
-    CATEGORY_UPSTREAM,
+    CATEGORY_UPSTREAM, CONFIG_SAVE_LOCK,
 };
 use crate::http_extra::HttpResponse;
 use crate::limit::TtlLruLimit;
-use crate::proxy::get_certificate_info_list;
+use crate::logger::get_recent_logs;
+use crate::proxy::{
+    get_certificate_info_list, get_upstreams_ewma_stats,
+    get_upstreams_healthy_status, UpstreamHealthyStatus,
+};
+use crate::service::{set_config_modifier, try_hot_reload_config};
 use crate::state::{
     get_process_system_info, get_processing_accepted, get_start_time,
 };
-use crate::state::{restart_now, State};
+use crate::state::{get_traffic_summary, TrafficSummary};
+use crate::state::{restart_now, State, AUTH_USER_VARIABLE};
 use crate::util::{self, base64_decode};
+use ahash::AHashMap;
 use async_trait::async_trait;
 use bytes::Bytes;
 use bytes::{BufMut, BytesMut};
 use flate2::write::GzEncoder;
 use flate2::Compression;
+#[cfg(feature = "admin-ui")]
 use hex::encode;
 use hex::ToHex;
 use http::Method;
 use http::{header, HeaderValue, StatusCode};
 use humantime::parse_duration;
-use pingora::http::RequestHeader;
+use once_cell::sync::Lazy;
+use pingora::http::{RequestHeader, ResponseHeader};
 use pingora::proxy::Session;
 use regex::Regex;
+#[cfg(feature = "admin-ui")]
 use rust_embed::EmbeddedFile;
+#[cfg(feature = "admin-ui")]
 use rust_embed::RustEmbed;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::collections::HashMap;
+#[cfg(feature = "admin-ui")]
 use std::io::Write;
-use std::time::Duration;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use substring::Substring;
-use tracing::{debug, error};
+use tracing::{debug, error, info, warn};
 use urlencoding::decode;
 
+#[cfg(feature = "admin-ui")]
 #[derive(RustEmbed)]
 #[folder = "dist/"]
 struct AdminAsset;
 
+#[cfg(feature = "admin-ui")]
 pub struct EmbeddedStaticFile(pub Option<EmbeddedFile>, pub Duration);
 
+#[cfg(feature = "admin-ui")]
 impl From<EmbeddedStaticFile> for HttpResponse {
     fn from(value: EmbeddedStaticFile) -> Self {
         let Some(file) = value.0 else {
@@ -116,13 +144,127 @@ impl From<EmbeddedStaticFile> for HttpResponse {
     }
 }
 
+/// Get the response of admin ui static file, `/` is `index.html`.
+#[cfg(feature = "admin-ui")]
+fn get_asset_response(path: &str) -> HttpResponse {
+    let mut file = path.substring(1, path.len());
+    if file.is_empty() {
+        file = "index.html";
+    }
+    EmbeddedStaticFile(
+        AdminAsset::get(file),
+        Duration::from_secs(365 * 24 * 3600),
+    )
+    .into()
+}
+
+#[cfg(not(feature = "admin-ui"))]
+fn get_asset_response(_path: &str) -> HttpResponse {
+    HttpResponse::not_found("Admin ui requires the admin-ui feature".into())
+}
+
+static STATIC_FILE_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\.(js|css|png)$").unwrap());
+
+/// The static file of admin ui can be accessed without authentication,
+/// only get or head request outside the api is allowed.
+fn is_static_file(method: &Method, path: &str) -> bool {
+    [Method::GET, Method::HEAD].contains(method)
+        && path != "/api"
+        && !path.starts_with("/api/")
+        && (path.len() <= 1 || STATIC_FILE_REGEX.is_match(path))
+}
+
+// the max count of verified basic authorizations
+const MAX_VERIFIED_AUTHORIZATIONS: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AdminRole {
+    Admin,
+    // only the read methods are allowed
+    ReadOnly,
+    // only the static files of admin ui are allowed
+    Anonymous,
+}
+
+impl From<&str> for AdminRole {
+    fn from(value: &str) -> Self {
+        match value {
+            "read_only" | "readonly" => AdminRole::ReadOnly,
+            _ => AdminRole::Admin,
+        }
+    }
+}
+
+/// The identity of admin request, the scopes are the config categories
+/// which can be accessed, all categories are allowed if it's empty.
+#[derive(Debug, Clone, PartialEq)]
+struct AdminIdentity {
+    name: String,
+    role: AdminRole,
+    scopes: Vec<String>,
+}
+
+impl AdminIdentity {
+    fn admin(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            role: AdminRole::Admin,
+            scopes: vec![],
+        }
+    }
+    fn anonymous() -> Self {
+        Self {
+            name: String::new(),
+            role: AdminRole::Anonymous,
+            scopes: vec![],
+        }
+    }
+    /// Check the identity is permitted to access the path(without api prefix)
+    /// with the method.
+    fn is_permitted(&self, method: &Method, path: &str) -> bool {
+        if self.role == AdminRole::Anonymous {
+            return false;
+        }
+        if self.role == AdminRole::ReadOnly
+            && ![Method::GET, Method::HEAD].contains(method)
+        {
+            return false;
+        }
+        if self.scopes.is_empty() {
+            return true;
+        }
+        // the scoped identity can only access the configs of its scopes
+        let Some(category) = path.strip_prefix("/configs/") else {
+            return false;
+        };
+        let category = category.split('/').next().unwrap_or_default();
+        self.scopes.iter().any(|item| item == category)
+    }
+}
+
+#[derive(Debug)]
+struct AdminUser {
+    // the bcrypt or argon2 hash of password
+    password: String,
+    role: AdminRole,
+}
+
 pub struct AdminServe {
     pub path: String,
     pub authorizations: Vec<(String, String)>,
     pub plugin_step: PluginStep,
+    users: AHashMap<String, AdminUser>,
+    // the sha256 hex of token -> identity
+    tokens: AHashMap<String, AdminIdentity>,
+    // the verified basic authorization -> user
+    verified: Mutex<AHashMap<Vec<u8>, String>>,
     max_age: Duration,
     hash_value: String,
     ip_fail_limit: TtlLruLimit,
+    // the json lines file of audit log, it's kept in memory if empty,
+    // which is neither append-only nor kept after restart
+    audit_log: String,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -150,13 +292,210 @@ struct BasicInfo {
     physical_cpus: usize,
     total_memory: String,
     used_memory: String,
-    enabled_full: bool,
+    // the features enabled at compile time
+    features: Vec<String>,
     enabled_pyroscope: bool,
     fd_count: usize,
     tcp_count: usize,
     tcp6_count: usize,
 }
 
+fn get_basic_info() -> BasicInfo {
+    let current_config = get_current_config();
+    let info = get_process_system_info();
+
+    let (processing, accepted) = get_processing_accepted();
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "pyro")] {
+            let enabled_pyroscope = true;
+        } else {
+            let enabled_pyroscope = false;
+        }
+    }
+
+    BasicInfo {
+        start_time: get_start_time(),
+        version: util::get_pkg_version().to_string(),
+        rustc_version: util::get_rustc_version(),
+        config_hash: config::get_config_hash(),
+        user: current_config.basic.user.clone().unwrap_or_default(),
+        group: current_config.basic.group.clone().unwrap_or_default(),
+        pid: info.pid.to_string(),
+        threads: info.threads,
+        accepted,
+        processing,
+        kernel: info.kernel,
+        memory_mb: info.memory_mb,
+        memory: info.memory,
+        arch: info.arch,
+        cpus: info.cpus,
+        physical_cpus: info.physical_cpus,
+        total_memory: info.total_memory,
+        used_memory: info.used_memory,
+        features: util::get_features()
+            .iter()
+            .map(|item| item.to_string())
+            .collect(),
+        enabled_pyroscope,
+        fd_count: info.fd_count,
+        tcp_count: info.tcp_count,
+        tcp6_count: info.tcp6_count,
+    }
+}
+
+#[derive(Serialize)]
+struct LiveTraffic {
+    #[serde(flatten)]
+    summary: TrafficSummary,
+    processing: i32,
+    upstreams: HashMap<String, UpstreamHealthyStatus>,
+}
+
+fn get_live_traffic(window: u64) -> LiveTraffic {
+    let (processing, _) = get_processing_accepted();
+    LiveTraffic {
+        summary: get_traffic_summary(window),
+        processing,
+        upstreams: get_upstreams_healthy_status(),
+    }
+}
+
+// the max duration of live traffic stream, the client should reconnect
+// after the stream is closed
+static MAX_TRAFFIC_STREAM_DURATION: Duration = Duration::from_secs(3600);
+
+// the response has been sent by the plugin
+static IGNORE_RESPONSE: Lazy<HttpResponse> = Lazy::new(|| HttpResponse {
+    status: StatusCode::from_u16(999).unwrap(),
+    ..Default::default()
+});
+
+/// Push the live traffic to client as server-sent events every second,
+/// until the max duration is reached or the client closes the connection.
+async fn stream_live_traffic(session: &mut Session) -> pingora::Result<()> {
+    let mut resp = ResponseHeader::build(StatusCode::OK, None)?;
+    resp.insert_header(header::CONTENT_TYPE, "text/event-stream")?;
+    resp.insert_header(header::CACHE_CONTROL, "no-cache")?;
+    session.write_response_header(Box::new(resp), false).await?;
+    let started_at = Instant::now();
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    while started_at.elapsed() < MAX_TRAFFIC_STREAM_DURATION {
+        interval.tick().await;
+        let data = serde_json::to_string(&get_live_traffic(1))
+            .map_err(|e| util::new_internal_error(500, e.to_string()))?;
+        session
+            .write_response_body(
+                Some(Bytes::from(format!("data: {data}\n\n"))),
+                false,
+            )
+            .await?;
+    }
+    session.write_response_body(None, true).await?;
+    session.finish_body().await?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct CertificateInventory {
+    domains: Vec<String>,
+    issuer: String,
+    acme: Option<String>,
+    not_before: i64,
+    not_after: i64,
+}
+
+// the config field is redacted if its name contains any of these words
+const SENSITIVE_FIELDS: [&str; 9] = [
+    "key",
+    "secret",
+    "password",
+    "token",
+    "authorization",
+    "webhook",
+    "sentry",
+    "pyroscope",
+    "value",
+];
+
+/// Redact the sensitive fields of config recursively.
+fn redact_toml_value(value: &mut toml::Value) {
+    match value {
+        toml::Value::Table(table) => {
+            for (name, value) in table.iter_mut() {
+                let name = name.to_lowercase();
+                if SENSITIVE_FIELDS.iter().any(|item| name.contains(item)) {
+                    *value = toml::Value::String("***".to_string());
+                } else {
+                    redact_toml_value(value);
+                }
+            }
+        },
+        toml::Value::Array(values) => {
+            values.iter_mut().for_each(redact_toml_value);
+        },
+        _ => {},
+    }
+}
+
+fn append_bundle_file<W: Write>(
+    builder: &mut tar::Builder<W>,
+    name: &str,
+    data: &[u8],
+) -> std::io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(util::now().as_secs());
+    header.set_cksum();
+    builder.append_data(
+        &mut header,
+        format!("{}-debug/{name}", util::get_pkg_name()),
+        data,
+    )
+}
+
+/// Create the diagnostic bundle(tar.gz) for support issue,
+/// the secrets of config and certificates are not included.
+fn new_debug_bundle() -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut builder =
+        tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+
+    let basic_info = serde_json::to_vec_pretty(&get_basic_info())?;
+    append_bundle_file(&mut builder, "basic.json", &basic_info)?;
+
+    let mut conf = toml::Value::try_from(get_current_config().as_ref())?;
+    redact_toml_value(&mut conf);
+    let conf = toml::to_string_pretty(&conf)?;
+    append_bundle_file(&mut builder, "config.toml", conf.as_bytes())?;
+
+    let logs = get_recent_logs().join("\n");
+    append_bundle_file(&mut builder, "recent.log", logs.as_bytes())?;
+
+    let upstreams = serde_json::to_vec_pretty(&get_upstreams_healthy_status())?;
+    append_bundle_file(&mut builder, "upstreams.json", &upstreams)?;
+
+    let certificates: HashMap<String, CertificateInventory> =
+        get_certificate_info_list()
+            .into_iter()
+            .map(|(name, cert)| {
+                (
+                    name,
+                    CertificateInventory {
+                        domains: cert.domains,
+                        issuer: cert.issuer,
+                        acme: cert.acme,
+                        not_before: cert.not_before,
+                        not_after: cert.not_after,
+                    },
+                )
+            })
+            .collect();
+    let certificates = serde_json::to_vec_pretty(&certificates)?;
+    append_bundle_file(&mut builder, "certificates.json", &certificates)?;
+
+    Ok(builder.into_inner()?.finish()?)
+}
+
 #[derive(Serialize, Deserialize)]
 struct TomlJson {
     pub full: String,
@@ -183,6 +522,53 @@ impl TryFrom<&PluginConf> for AdminServe {
                 authorizations.push((user.to_string(), pass.to_string()));
             }
         }
+        let new_invalid_error = |message: String| Error::Invalid {
+            category: PluginCategory::Admin.to_string(),
+            message,
+        };
+        let mut users = AHashMap::new();
+        if let Some(tables) = value.get("users").and_then(|v| v.as_table()) {
+            for (name, item) in tables.iter() {
+                let Some(item) = item.as_table() else {
+                    continue;
+                };
+                let password = get_str_conf(item, "password");
+                if !is_supported_hash(&password) {
+                    return Err(new_invalid_error(format!(
+                        "password of user({name}) should be bcrypt or argon2 hash"
+                    )));
+                }
+                users.insert(
+                    name.to_string(),
+                    AdminUser {
+                        password,
+                        role: get_str_conf(item, "role").as_str().into(),
+                    },
+                );
+            }
+        }
+        let mut tokens = AHashMap::new();
+        if let Some(tables) = value.get("tokens").and_then(|v| v.as_table()) {
+            for (name, item) in tables.iter() {
+                let Some(item) = item.as_table() else {
+                    continue;
+                };
+                let hash = get_str_conf(item, "hash").to_lowercase();
+                if hash.len() != 64 {
+                    return Err(new_invalid_error(format!(
+                        "hash of token({name}) should be sha256 hex"
+                    )));
+                }
+                tokens.insert(
+                    hash,
+                    AdminIdentity {
+                        name: name.to_string(),
+                        role: get_str_conf(item, "role").as_str().into(),
+                        scopes: get_str_slice_conf(item, "scopes"),
+                    },
+                );
+            }
+        }
         let mut ip_fail_limit = get_int_conf(value, "ip_fail_limit");
         if ip_fail_limit <= 0 {
             ip_fail_limit = 10;
@@ -203,12 +589,16 @@ impl TryFrom<&PluginConf> for AdminServe {
             max_age,
             plugin_step: get_step_conf(value),
             path: get_str_conf(value, "path"),
+            audit_log: get_str_conf(value, "audit_log"),
             ip_fail_limit: TtlLruLimit::new(
                 512,
                 Duration::from_secs(5 * 60),
                 ip_fail_limit as usize,
             ),
             authorizations,
+            users,
+            tokens,
+            verified: Mutex::new(AHashMap::new()),
         };
         if ![PluginStep::Request, PluginStep::ProxyUpstream]
             .contains(&params.plugin_step)
@@ -218,6 +608,12 @@ impl TryFrom<&PluginConf> for AdminServe {
                 message: "Admin serve plugin should be executed at request or proxy upstream step".to_string(),
             });
         }
+        if params.audit_log.is_empty() {
+            warn!(
+                path = params.path,
+                "audit log of admin is not set, the records are kept in memory and lost after restart"
+            );
+        }
 
         Ok(params)
     }
@@ -235,6 +631,85 @@ struct AesResp {
     value: String,
 }
 
+#[derive(Deserialize, Debug)]
+struct CacheWarmUpParams {
+    urls: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct CacheKeysResp {
+    backend: String,
+    #[serde(flatten)]
+    keys: CacheKeyList,
+}
+
+/// Get the etag of config item, it's the version for optimistic concurrency.
+fn get_config_etag(
+    conf: &PingapConf,
+    category: &str,
+    name: &str,
+) -> Option<String> {
+    conf.get_version(category, name)
+        .map(|version| format!(r#""{version}""#))
+}
+
+/// Check the `If-Match` header with the etag of config item,
+/// `*` matches any existing item. The config should be loaded and saved
+/// with `CONFIG_SAVE_LOCK`, otherwise the check is racy.
+fn check_if_match(
+    session: &Session,
+    conf: &PingapConf,
+    category: &str,
+    name: &str,
+) -> pingora::Result<()> {
+    let Some(value) =
+        util::get_req_header_value(session.req_header(), "If-Match")
+    else {
+        return Ok(());
+    };
+    let etag = get_config_etag(conf, category, name);
+    let matched = value.split(',').map(|item| item.trim()).any(|item| {
+        if item == "*" {
+            etag.is_some()
+        } else {
+            Some(item) == etag.as_deref()
+        }
+    });
+    if !matched {
+        return Err(util::new_internal_error(
+            412,
+            format!("{category}({name}) is modified, etag doesn't match"),
+        ));
+    }
+    Ok(())
+}
+
+fn new_etag_response(status: StatusCode, etag: Option<String>) -> HttpResponse {
+    let mut resp = HttpResponse {
+        status,
+        ..HttpResponse::no_content()
+    };
+    if let Some(etag) = etag.and_then(|etag| HeaderValue::from_str(&etag).ok())
+    {
+        resp.headers
+            .get_or_insert_with(Vec::new)
+            .push((header::ETAG, etag));
+    }
+    resp
+}
+
+/// Save the snapshot of config to history, the error is returned
+/// because the rollback depends on it.
+async fn save_config_history(
+    conf: &PingapConf,
+    summary: &str,
+) -> pingora::Result<()> {
+    save_history(conf, summary).await.map_err(|e| {
+        error!(error = e.to_string(), "save config history fail");
+        util::new_internal_error(500, e.to_string())
+    })
+}
+
 async fn get_request_body(session: &mut Session) -> pingora::Result<BytesMut> {
     let mut buf = BytesMut::with_capacity(4096);
     while let Some(value) = session.read_request_body().await? {
@@ -250,28 +725,76 @@ impl AdminServe {
 
         Ok(serve)
     }
-    fn auth_validate(&self, req_header: &RequestHeader) -> bool {
-        if self.authorizations.is_empty() {
-            return true;
-        }
-        let path = req_header.uri.path();
-        if path.len() <= 1
-            || Regex::new(r#".(js|css|png)$"#).unwrap().is_match(path)
+    /// Verify the basic authorization of users, the verified
+    /// authorization is cached to avoid hashing the password every time.
+    async fn verify_user(&self, value: &[u8]) -> Option<AdminIdentity> {
+        let cached = self
+            .verified
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(value)
+            .cloned();
+        let name = if let Some(name) = cached {
+            name
+        } else {
+            let (name, password) = get_basic_auth_credential(value)?;
+            let hash = self.users.get(&name)?.password.clone();
+            let valid = tokio::task::spawn_blocking(move || {
+                verify_password(&password, &hash)
+            })
+            .await
+            .unwrap_or_default();
+            if !valid {
+                return None;
+            }
+            let mut verified =
+                self.verified.lock().unwrap_or_else(|e| e.into_inner());
+            if verified.len() >= MAX_VERIFIED_AUTHORIZATIONS {
+                verified.clear();
+            }
+            verified.insert(value.to_vec(), name.clone());
+            name
+        };
+        let user = self.users.get(&name)?;
+        Some(AdminIdentity {
+            name,
+            role: user.role,
+            scopes: vec![],
+        })
+    }
+    /// Authenticate the request by api token, basic authorization of users
+    /// or the signed authorization, it returns none if fails.
+    async fn authenticate(
+        &self,
+        req_header: &RequestHeader,
+    ) -> Option<AdminIdentity> {
+        if self.authorizations.is_empty()
+            && self.users.is_empty()
+            && self.tokens.is_empty()
         {
-            return true;
+            return Some(AdminIdentity::admin(""));
+        }
+        if is_static_file(&req_header.method, req_header.uri.path()) {
+            return Some(AdminIdentity::anonymous());
         }
         let value = util::get_req_header_value(req_header, "Authorization")
             .unwrap_or_default();
         if value.is_empty() {
-            return false;
+            return None;
         }
-        let Some((token, ts)) = value.split_once(':') else {
-            return false;
-        };
+        if let Some(token) = value.strip_prefix("Bearer ") {
+            let hash: String =
+                Sha256::digest(token.trim().as_bytes()).encode_hex();
+            return self.tokens.get(&hash).cloned();
+        }
+        if value.starts_with("Basic ") {
+            return self.verify_user(value.as_bytes()).await;
+        }
+        let (token, ts) = value.split_once(':')?;
         let offset = util::now().as_secs() as i64
             - ts.parse::<i64>().unwrap_or_default();
         if offset.abs() > self.max_age.as_secs() as i64 {
-            return false;
+            return None;
         }
 
         for (user, pass) in self.authorizations.iter() {
@@ -279,10 +802,10 @@ impl AdminServe {
             hasher.update(format!("{user}:{pass}:{ts}").as_bytes());
             let hash256 = hasher.finalize();
             if hash256.encode_hex::<String>() == token {
-                return true;
+                return Some(AdminIdentity::admin(user));
             }
         }
-        false
+        None
     }
     async fn load_config(
         &self,
@@ -334,12 +857,72 @@ impl AdminServe {
         Ok(resp)
     }
 
+    /// Get the config item by name, the etag is its version.
+    async fn get_config_item(
+        &self,
+        category: &str,
+        name: &str,
+    ) -> pingora::Result<HttpResponse> {
+        let conf = self.load_config(false).await?;
+        let Some(value) = conf.get_item(category, name) else {
+            return Err(util::new_internal_error(
+                404,
+                format!("{category}({name}) is not found"),
+            ));
+        };
+        let mut resp = HttpResponse::try_from_json(&value)?;
+        if let Some(etag) = get_config_etag(&conf, category, name)
+            .and_then(|etag| HeaderValue::from_str(&etag).ok())
+        {
+            resp.headers
+                .get_or_insert_with(Vec::new)
+                .push((header::ETAG, etag));
+        }
+        Ok(resp)
+    }
+    /// Stage the config change which will be applied at the effective time,
+    /// it should be valid with the current config.
+    async fn schedule_config(
+        &self,
+        effective_at: &str,
+        category: &str,
+        name: &str,
+        data: Option<String>,
+    ) -> pingora::Result<HttpResponse> {
+        let effective_at = parse_effective_at(effective_at)
+            .map_err(|e| util::new_internal_error(400, e.to_string()))?;
+        let change = ScheduledChange::new(category, name, data, effective_at);
+        let mut conf = self.load_config(false).await?;
+        change
+            .apply(&mut conf)
+            .and_then(|_| conf.validate())
+            .map_err(|e| {
+                error!(error = e.to_string(), "validate scheduled config fail");
+                util::new_internal_error(400, e.to_string())
+            })?;
+        info!(
+            id = change.id,
+            category, name, effective_at, "stage scheduled config"
+        );
+        add_scheduled_change(change.clone());
+        HttpResponse::try_from_json(&change)
+    }
     async fn remove_config(
         &self,
+        session: &Session,
         category: &str,
         name: &str,
     ) -> pingora::Result<HttpResponse> {
+        if let Some(effective_at) =
+            util::get_query_value(session.req_header(), "effective_at")
+        {
+            return self
+                .schedule_config(effective_at, category, name, None)
+                .await;
+        }
+        let guard = CONFIG_SAVE_LOCK.lock().await;
         let mut conf = self.load_config(false).await?;
+        check_if_match(session, &conf, category, name)?;
         conf.remove(category, name).map_err(|e| {
             error!(error = e.to_string(), "validate config fail");
             util::new_internal_error(400, e.to_string())
@@ -350,13 +933,21 @@ impl AdminServe {
                 error!(error = e.to_string(), "save config fail");
                 util::new_internal_error(400, e.to_string())
             })?;
+        // release the lock before reloading
+        drop(guard);
+        try_hot_reload_config().await;
+        save_config_history(&conf, &format!("remove {category}({name})"))
+            .await?;
         Ok(HttpResponse::no_content())
     }
+    /// Update the config by name, the whole config is replaced,
+    /// or it's merged with the json merge patch if patch is true.
     async fn update_config(
         &self,
         session: &mut Session,
         category: &str,
         name: &str,
+        patch: bool,
     ) -> pingora::Result<HttpResponse> {
         if name.is_empty() {
             return Err(util::new_internal_error(
@@ -365,91 +956,49 @@ impl AdminServe {
             ));
         }
         let buf = get_request_body(session).await?;
-        let key = name.to_string();
+        if let Some(effective_at) =
+            util::get_query_value(session.req_header(), "effective_at")
+        {
+            let data = std::str::from_utf8(&buf)
+                .map_err(|e| util::new_internal_error(400, e.to_string()))?
+                .to_string();
+            return self
+                .schedule_config(effective_at, category, name, Some(data))
+                .await;
+        }
+        let guard = CONFIG_SAVE_LOCK.lock().await;
         let mut conf = self.load_config(false).await?;
-        match category {
-            CATEGORY_UPSTREAM => {
-                let upstream: UpstreamConf = serde_json::from_slice(&buf)
-                    .map_err(|e| {
-                        error!(
-                            error = e.to_string(),
-                            "descrialize upstream fail"
-                        );
-                        util::new_internal_error(400, e.to_string())
-                    })?;
-                conf.upstreams.insert(key, upstream);
-            },
-            CATEGORY_LOCATION => {
-                let location: LocationConf = serde_json::from_slice(&buf)
-                    .map_err(|e| {
-                        error!(
-                            error = e.to_string(),
-                            "descrialize location fail"
-                        );
-                        util::new_internal_error(400, e.to_string())
-                    })?;
-                conf.locations.insert(key, location);
-            },
-            CATEGORY_SERVER => {
-                let server: ServerConf =
-                    serde_json::from_slice(&buf).map_err(|e| {
-                        error!(
-                            error = e.to_string(),
-                            "descrialize server fail"
-                        );
-                        util::new_internal_error(400, e.to_string())
-                    })?;
-                conf.servers.insert(key, server);
-            },
-            CATEGORY_PLUGIN => {
-                let plugin: PluginConf =
-                    serde_json::from_slice(&buf).map_err(|e| {
-                        error!(
-                            error = e.to_string(),
-                            "descrialize plugin fail"
-                        );
-                        util::new_internal_error(400, e.to_string())
-                    })?;
-                conf.plugins.insert(key, plugin);
-            },
-            CATEGORY_CERTIFICATE => {
-                let certificate: CertificateConf = serde_json::from_slice(&buf)
-                    .map_err(|e| {
-                        error!(
-                            error = e.to_string(),
-                            "descrialize certificate fail"
-                        );
-                        util::new_internal_error(400, e.to_string())
-                    })?;
-                conf.certificates.insert(key, certificate);
-            },
-            CATEGORY_STORAGE => {
-                let storage: StorageConf = serde_json::from_slice(&buf)
-                    .map_err(|e| {
-                        error!(
-                            error = e.to_string(),
-                            "descrialize storage fail"
-                        );
-                        util::new_internal_error(400, e.to_string())
-                    })?;
-                conf.storages.insert(key, storage);
-            },
-            _ => {
-                let basic_conf: BasicConf = serde_json::from_slice(&buf)
-                    .map_err(|e| {
-                        error!(error = e.to_string(), "descrialize basic fail");
-                        util::new_internal_error(400, e.to_string())
-                    })?;
-                conf.basic = basic_conf;
-            },
+        check_if_match(session, &conf, category, name)?;
+        let exists = conf.get_item(category, name).is_some();
+        let result = if patch {
+            conf.patch(category, name, &buf)
+        } else {
+            conf.update(category, name, &buf)
         };
+        result.map_err(|e| {
+            error!(error = e.to_string(), category, "descrialize config fail");
+            util::new_internal_error(400, e.to_string())
+        })?;
         save_config(&conf, category, Some(name))
             .await
             .map_err(|e| {
                 error!(error = e.to_string(), "save config fail");
                 util::new_internal_error(400, e.to_string())
             })?;
-        Ok(HttpResponse::no_content())
+        // release the lock before reloading
+        drop(guard);
+        try_hot_reload_config().await;
+        save_config_history(&conf, &format!("update {category}({name})"))
+            .await?;
+        let status = if exists {
+            StatusCode::NO_CONTENT
+        } else {
+            StatusCode::CREATED
+        };
+        Ok(new_etag_response(
+            status,
+            get_config_etag(&conf, category, name),
+        ))
     }
     async fn import_config(
         &self,
@@ -461,13 +1010,105 @@ impl AdminServe {
             util::new_internal_error(400, e.to_string())
         })?;
         if let Some(storage) = config::get_config_storage() {
+            let _guard = CONFIG_SAVE_LOCK.lock().await;
             config::sync_config(&conf, storage).await.map_err(|e| {
                 error!(error = e.to_string(), "import config fail");
                 util::new_internal_error(400, e.to_string())
             })?;
+            save_config_history(&conf, "import config").await?;
         }
         Ok(HttpResponse::no_content())
     }
+    /// Get the changes from the current config to the version of history.
+    async fn get_history_changes(
+        &self,
+        id: &str,
+    ) -> pingora::Result<HttpResponse> {
+        let id = id
+            .parse::<u64>()
+            .map_err(|e| util::new_internal_error(400, e.to_string()))?;
+        let conf = get_history(id)
+            .await
+            .map_err(|e| util::new_internal_error(404, e.to_string()))?;
+        let current = self.load_config(false).await?;
+        HttpResponse::try_from_json(&current.changes(&conf))
+    }
+    async fn rollback_config(&self, id: &str) -> pingora::Result<HttpResponse> {
+        let id = id
+            .parse::<u64>()
+            .map_err(|e| util::new_internal_error(400, e.to_string()))?;
+        let guard = CONFIG_SAVE_LOCK.lock().await;
+        rollback_history(id).await.map_err(|e| {
+            error!(error = e.to_string(), id, "rollback config fail");
+            util::new_internal_error(400, e.to_string())
+        })?;
+        drop(guard);
+        info!(id, "rollback config");
+        try_hot_reload_config().await;
+        Ok(HttpResponse::no_content())
+    }
+    /// Query the audit records of admin mutations,
+    /// e.g. `/audit?actor=tree&endpoint=/configs/upstreams&limit=10`.
+    async fn query_audit(
+        &self,
+        session: &Session,
+    ) -> pingora::Result<HttpResponse> {
+        let req_header = session.req_header();
+        let get_number = |key: &str| {
+            util::get_query_value(req_header, key)
+                .and_then(|value| value.parse::<u64>().ok())
+        };
+        let query = AuditQuery {
+            actor: util::get_query_value(req_header, "actor")
+                .map(|value| decode(value).unwrap_or_default().to_string()),
+            endpoint: util::get_query_value(req_header, "endpoint")
+                .map(|value| decode(value).unwrap_or_default().to_string()),
+            since: get_number("since"),
+            until: get_number("until"),
+            limit: get_number("limit").map(|value| value as usize),
+        };
+        let records = query_audit(&self.audit_log, &query)
+            .await
+            .map_err(|e| util::new_internal_error(500, e.to_string()))?;
+        HttpResponse::try_from_json(&records)
+    }
+}
+
+/// Get the status of error, e.g. 404 or 412, otherwise it's 500.
+fn get_error_status(err: &pingora::BError) -> StatusCode {
+    match err.etype() {
+        pingora::ErrorType::HTTPStatus(code) => StatusCode::from_u16(*code)
+            .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Create the json response of error, the status of error is used,
+/// e.g. 404 or 412, otherwise it's 500.
+fn new_error_response(err: pingora::BError) -> HttpResponse {
+    let status = get_error_status(&err);
+    HttpResponse::try_from_json_status(
+        &ErrorResponse {
+            message: err.to_string(),
+        },
+        status,
+    )
+    .unwrap_or(HttpResponse::unknown_error("Json serde fail".into()))
+}
+
+/// Whether the request should be recorded to the audit log,
+/// all mutations are recorded except the aes utility.
+fn is_audited(method: &Method, path: &str) -> bool {
+    ![Method::GET, Method::HEAD, Method::OPTIONS].contains(method)
+        && path != "/aes"
+}
+
+/// Get the path of admin api, the path of plugin and `/api` are removed.
+fn get_api_path<'a>(prefix: &str, path: &'a str) -> &'a str {
+    let path = path
+        .strip_prefix(prefix.trim_end_matches('/'))
+        .unwrap_or(path);
+    path.strip_prefix("/api").unwrap_or(path)
 }
 
 fn get_method_path(session: &Session) -> (Method, String) {
@@ -477,17 +1118,13 @@ fn get_method_path(session: &Session) -> (Method, String) {
     (method, path.to_string())
 }
 
-#[async_trait]
-impl Plugin for AdminServe {
-    #[inline]
-    fn hash_key(&self) -> String {
-        self.hash_value.clone()
-    }
-    async fn handle_request(
+impl AdminServe {
+    /// Serve the admin api and static files.
+    async fn serve(
         &self,
         step: PluginStep,
         session: &mut Session,
-        _ctx: &mut State,
+        ctx: &mut State,
     ) -> pingora::Result<Option<HttpResponse>> {
         if self.plugin_step != step {
             return Ok(None);
@@ -515,12 +1152,18 @@ impl Plugin for AdminServe {
         if let Ok(uri) = new_path.parse::<http::Uri>() {
             header.set_uri(uri);
         }
-        if !self.auth_validate(header) {
+        let Some(identity) = self.authenticate(header).await else {
             self.ip_fail_limit.inc(&ip).await;
             return Ok(Some(HttpResponse {
                 status: StatusCode::UNAUTHORIZED,
                 ..Default::default()
             }));
+        };
+        ctx.set_auth_identity(Some(&identity.name), None);
+        // the anonymous identity can only access the static files
+        if identity.role == AdminRole::Anonymous {
+            let path = session.req_header().uri.path().to_string();
+            return Ok(Some(get_asset_response(&path)));
         }
 
         let (method, mut path) = get_method_path(session);
@@ -528,6 +1171,20 @@ impl Plugin for AdminServe {
         if path.starts_with(api_prefix) {
             path = path.substring(api_prefix.len(), path.len()).to_string();
         }
+        if !identity.is_permitted(&method, &path) {
+            return Ok(Some(HttpResponse {
+                status: StatusCode::FORBIDDEN,
+                body: Bytes::from_static(b"Forbidden, permission denied"),
+                ..Default::default()
+            }));
+        }
+        // the modifier is sent with the notification of config diff
+        if method != Method::GET
+            && !identity.name.is_empty()
+            && (path.starts_with("/configs") || path.starts_with("/history/"))
+        {
+            set_config_modifier(&identity.name);
+        }
         let params: Vec<String> = path
             .split('/')
             .map(|item| decode(item).unwrap_or_default().to_string())
@@ -536,83 +1193,105 @@ impl Plugin for AdminServe {
         if params.len() >= 3 {
             category = &params[2];
         }
+        let name = params
+            .get(3)
+            .map(|item| item.as_str())
+            .filter(|item| !item.is_empty());
         let resp = if path.starts_with("/configs") {
-            match method {
-                Method::POST => {
-                    if category == "import" {
-                        self.import_config(session).await
-                    } else if params.len() < 4 {
-                        Err(pingora::Error::new_str("Url is invalid(no name)"))
-                    } else {
-                        self.update_config(session, category, &params[3]).await
-                    }
+            match (method, name) {
+                (Method::POST, _) if category == "import" => {
+                    self.import_config(session).await
+                },
+                (Method::POST | Method::PUT, Some(name)) => {
+                    self.update_config(session, category, name, false).await
                 },
-                Method::DELETE => {
-                    if params.len() < 4 {
-                        Err(pingora::Error::new_str("Url is invalid(no name)"))
-                    } else {
-                        self.remove_config(category, &params[3]).await
-                    }
+                (Method::PATCH, Some(name)) => {
+                    self.update_config(session, category, name, true).await
                 },
+                (Method::DELETE, Some(name)) => {
+                    self.remove_config(session, category, name).await
+                },
+                (
+                    Method::POST | Method::PUT | Method::PATCH | Method::DELETE,
+                    None,
+                ) => Err(pingora::Error::new_str("Url is invalid(no name)")),
+                (_, Some(name)) => self.get_config_item(category, name).await,
                 _ => self.get_config(category).await,
             }
-            .unwrap_or_else(|err| {
-                HttpResponse::try_from_json_status(
-                    &ErrorResponse {
-                        message: err.to_string(),
-                    },
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                )
-                .unwrap_or(HttpResponse::unknown_error(
-                    "Json serde fail".into(),
-                ))
-            })
-        } else if path == "/basic" {
-            let current_config = get_current_config();
-            let info = get_process_system_info();
-
-            let (processing, accepted) = get_processing_accepted();
-            cfg_if::cfg_if! {
-                if #[cfg(feature = "full")] {
-                    let enabled_full = true;
-                } else {
-                    let enabled_full = false;
-                }
+            .unwrap_or_else(new_error_response)
+        } else if path == "/history" {
+            match list_history().await {
+                Ok(versions) => HttpResponse::try_from_json(&versions),
+                Err(e) => Err(util::new_internal_error(500, e.to_string())),
             }
-            cfg_if::cfg_if! {
-                if #[cfg(feature = "pyro")] {
-                    let enabled_pyroscope = true;
-                } else {
-                    let enabled_pyroscope = false;
-                }
+            .unwrap_or_else(new_error_response)
+        } else if let Some(id) = path.strip_prefix("/history/") {
+            match (id.strip_suffix("/rollback"), method) {
+                (Some(id), Method::POST) => self.rollback_config(id).await,
+                (None, Method::GET) => self.get_history_changes(id).await,
+                _ => Err(util::new_internal_error(
+                    405,
+                    "Method not allowed".to_string(),
+                )),
+            }
+            .unwrap_or_else(new_error_response)
+        } else if path == "/audit" {
+            self.query_audit(session)
+                .await
+                .unwrap_or_else(new_error_response)
+        } else if path == "/scheduled" {
+            HttpResponse::try_from_json(&list_scheduled_changes()).unwrap_or(
+                HttpResponse::unknown_error("Json serde fail".into()),
+            )
+        } else if path.starts_with("/scheduled/") && method == Method::DELETE {
+            let id = path.substring("/scheduled/".len(), path.len());
+            if cancel_scheduled_change(id) {
+                HttpResponse::no_content()
+            } else {
+                HttpResponse::not_found("Scheduled config not found".into())
+            }
+        } else if path == "/traffic" {
+            let window = util::get_query_value(session.req_header(), "window")
+                .and_then(|value| value.parse::<u64>().ok())
+                .unwrap_or(1);
+            HttpResponse::try_from_json(&get_live_traffic(window)).unwrap_or(
+                HttpResponse::unknown_error("Json serde fail".into()),
+            )
+        } else if path == "/traffic/stream" {
+            if let Err(e) = stream_live_traffic(session).await {
+                debug!(error = e.to_string(), "live traffic stream is closed");
+            }
+            IGNORE_RESPONSE.clone()
+        } else if path == "/basic" {
+            HttpResponse::try_from_json(&get_basic_info()).unwrap_or(
+                HttpResponse::unknown_error("Json serde fail".into()),
+            )
+        } else if path == "/debug/bundle" {
+            let data = new_debug_bundle()
+                .map_err(|e| util::new_internal_error(500, e.to_string()))?;
+            let filename = format!(
+                "{}-debug-{}.tar.gz",
+                util::get_pkg_name(),
+                util::now().as_secs()
+            );
+            HttpResponse {
+                status: StatusCode::OK,
+                body: data.into(),
+                headers: Some(vec![
+                    (
+                        header::CONTENT_TYPE,
+                        HeaderValue::from_static("application/gzip"),
+                    ),
+                    (
+                        header::CONTENT_DISPOSITION,
+                        HeaderValue::from_str(&format!(
+                            r#"attachment; filename="{filename}""#
+                        ))
+                        .unwrap_or(HeaderValue::from_static("attachment")),
+                    ),
+                ]),
+                ..Default::default()
             }
-
-            HttpResponse::try_from_json(&BasicInfo {
-                start_time: get_start_time(),
-                version: util::get_pkg_version().to_string(),
-                rustc_version: util::get_rustc_version(),
-                config_hash: config::get_config_hash(),
-                user: current_config.basic.user.clone().unwrap_or_default(),
-                group: current_config.basic.group.clone().unwrap_or_default(),
-                pid: info.pid.to_string(),
-                threads: info.threads,
-                accepted,
-                processing,
-                kernel: info.kernel,
-                memory_mb: info.memory_mb,
-                memory: info.memory,
-                arch: info.arch,
-                cpus: info.cpus,
-                physical_cpus: info.physical_cpus,
-                total_memory: info.total_memory,
-                used_memory: info.used_memory,
-                enabled_full,
-                enabled_pyroscope,
-                fd_count: info.fd_count,
-                tcp_count: info.tcp_count,
-                tcp6_count: info.tcp6_count,
-            })
-            .unwrap_or(HttpResponse::unknown_error("Json serde fail".into()))
         } else if path == "/restart" && method == Method::POST {
             if let Err(e) = restart_now().await {
                 error!("Restart fail: {e}");
@@ -633,6 +1312,124 @@ impl Plugin for AdminServe {
             HttpResponse::try_from_json(&AesResp { value }).unwrap_or(
                 HttpResponse::unknown_error("Json serde fail".into()),
             )
+        } else if path == "/cache/keys" && method == Method::DELETE {
+            let cache = get_cache_backend()
+                .map_err(|e| util::new_internal_error(500, e.to_string()))?;
+            purge_cache(cache, session.req_header(), "").await?
+        } else if path.starts_with("/cache/keys/") && method == Method::DELETE {
+            let hash = path.substring("/cache/keys/".len(), path.len());
+            let cache = get_cache_backend()
+                .map_err(|e| util::new_internal_error(500, e.to_string()))?;
+            cache.remove_by_hash(hash).await?;
+            HttpResponse::no_content()
+        } else if path == "/cache/warm-up" && method == Method::POST {
+            let buf = get_request_body(session).await?;
+            // the urls of config are used if not set
+            let urls = if buf.is_empty() {
+                vec![]
+            } else {
+                serde_json::from_slice::<CacheWarmUpParams>(buf.as_ref())
+                    .map_err(|e| util::new_internal_error(400, e.to_string()))?
+                    .urls
+            };
+            let urls = if urls.is_empty() {
+                get_current_config()
+                    .basic
+                    .cache_warm_up_urls
+                    .clone()
+                    .unwrap_or_default()
+            } else {
+                urls
+            };
+            if urls.is_empty() {
+                return Ok(Some(HttpResponse::bad_request(
+                    "Urls of warm up should be set".into(),
+                )));
+            }
+            tokio::spawn(async move {
+                if let Err(e) = warm_up(urls).await {
+                    error!(error = e, "warm up cache fail");
+                }
+            });
+            HttpResponse {
+                status: StatusCode::ACCEPTED,
+                ..Default::default()
+            }
+        } else if path == "/cache/stats" {
+            let cache = get_cache_backend()
+                .map_err(|e| util::new_internal_error(500, e.to_string()))?;
+            HttpResponse::try_from_json(&cache.summary()).unwrap_or(
+                HttpResponse::unknown_error("Json serde fail".into()),
+            )
+        } else if path == "/cache/lookup" {
+            let req_header = session.req_header();
+            let get_value = |name: &str| {
+                let value =
+                    util::get_query_value(req_header, name).unwrap_or_default();
+                urlencoding::decode(value).unwrap_or_default().to_string()
+            };
+            // the key is generated from url as the cache plugin
+            let mut key = get_value("key");
+            let url = get_value("url");
+            if key.is_empty() && !url.is_empty() {
+                let method = get_value("method");
+                let method = if method.is_empty() {
+                    "GET".to_string()
+                } else {
+                    method.to_uppercase()
+                };
+                key = format!("{}{method}:{url}", get_value("prefix"));
+            }
+            if key.is_empty() {
+                return Ok(Some(HttpResponse::bad_request(
+                    "Key or url should be set".into(),
+                )));
+            }
+            let cache = get_cache_backend()
+                .map_err(|e| util::new_internal_error(500, e.to_string()))?;
+            if let Some(detail) =
+                cache.lookup_key(&key, &get_value("namespace")).await?
+            {
+                HttpResponse::try_from_json(&detail).unwrap_or(
+                    HttpResponse::unknown_error("Json serde fail".into()),
+                )
+            } else {
+                HttpResponse::not_found("Cache key not found".into())
+            }
+        } else if path == "/cache/keys" {
+            let req_header = session.req_header();
+            let pattern = util::get_query_value(req_header, "pattern")
+                .unwrap_or_default()
+                .to_string();
+            let offset = util::get_query_value(req_header, "offset")
+                .and_then(|v| v.parse::<usize>().ok())
+                .unwrap_or_default();
+            let limit = util::get_query_value(req_header, "limit")
+                .and_then(|v| v.parse::<usize>().ok())
+                .unwrap_or(50)
+                .min(500);
+            let cache = get_cache_backend()
+                .map_err(|e| util::new_internal_error(500, e.to_string()))?;
+            HttpResponse::try_from_json(&CacheKeysResp {
+                backend: cache.backend().to_string(),
+                keys: cache.list_keys(&pattern, offset, limit),
+            })
+            .unwrap_or(HttpResponse::unknown_error("Json serde fail".into()))
+        } else if path.starts_with("/cache/keys/") {
+            let hash = path.substring("/cache/keys/".len(), path.len());
+            let cache = get_cache_backend()
+                .map_err(|e| util::new_internal_error(500, e.to_string()))?;
+            if let Some(detail) = cache.inspect_key(hash).await? {
+                HttpResponse::try_from_json(&detail).unwrap_or(
+                    HttpResponse::unknown_error("Json serde fail".into()),
+                )
+            } else {
+                HttpResponse::not_found("Cache key not found".into())
+            }
+        } else if path == "/upstreams/ewma" {
+            HttpResponse::try_from_json(&get_upstreams_ewma_stats()).unwrap_or(
+                HttpResponse::unknown_error("Json serde fail".into()),
+            )
         } else if path == "/certificates" {
             let mut infos = HashMap::new();
             for (name, info) in get_certificate_info_list() {
@@ -642,26 +1439,140 @@ impl Plugin for AdminServe {
                 HttpResponse::unknown_error("Json serde fail".into()),
             )
         } else {
-            let mut file = path.substring(1, path.len());
-            if file.is_empty() {
-                file = "index.html";
-            }
-            EmbeddedStaticFile(
-                AdminAsset::get(file),
-                Duration::from_secs(365 * 24 * 3600),
-            )
-            .into()
+            get_asset_response(&path)
         };
         Ok(Some(resp))
     }
 }
 
+#[async_trait]
+impl Plugin for AdminServe {
+    #[inline]
+    fn hash_key(&self) -> String {
+        self.hash_value.clone()
+    }
+    /// Serve the admin request, the mutations are recorded to the audit
+    /// log, including the ones rejected by authentication or permission.
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut State,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        let (method, path) = get_method_path(session);
+        let api_path = get_api_path(&self.path, &path);
+        if self.plugin_step != step
+            || !path.starts_with(&self.path)
+            || !is_audited(&method, api_path)
+        {
+            return self.serve(step, session, ctx).await;
+        }
+        let ip = util::get_client_ip(session);
+        let mut endpoint = api_path.to_string();
+        if let Some(query) = session.req_header().uri.query() {
+            endpoint = format!("{endpoint}?{query}");
+        }
+        // the config before mutation for the summary of changes,
+        // it is not loaded for the ip which is limited by failures
+        let before = if (api_path.starts_with("/configs")
+            || api_path.starts_with("/history/"))
+            && self.ip_fail_limit.validate(&ip).await
+        {
+            self.load_config(false).await.ok()
+        } else {
+            None
+        };
+        let result = self.serve(step, session, ctx).await;
+        let status = match &result {
+            Ok(resp) => {
+                resp.as_ref().map(|resp| resp.status).unwrap_or_default()
+            },
+            Err(e) => get_error_status(e),
+        };
+        let mut changes = vec![];
+        if let Some(before) = before.filter(|_| status.is_success()) {
+            if let Ok(after) = self.load_config(false).await {
+                changes = before
+                    .changes(&after)
+                    .iter()
+                    .map(|item| item.to_string())
+                    .collect();
+            }
+        }
+        // the actor is empty if the request is not authenticated
+        let actor = ctx
+            .variables
+            .as_ref()
+            .and_then(|variables| {
+                variables.get(&format!("${AUTH_USER_VARIABLE}"))
+            })
+            .cloned()
+            .unwrap_or_default();
+        let record = AuditRecord {
+            created_at: util::now().as_secs(),
+            actor,
+            ip,
+            method: method.to_string(),
+            endpoint,
+            status: status.as_u16(),
+            changes,
+        };
+        append_audit(&self.audit_log, &record).await;
+        result
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{AdminAsset, AdminServe, EmbeddedStaticFile};
-    use crate::{config::PluginConf, http_extra::HttpResponse};
+    use super::{
+        get_api_path, is_audited, is_static_file, redact_toml_value,
+        AdminIdentity, AdminRole, AdminServe,
+    };
+    use crate::config::PluginConf;
+    use http::Method;
+    use pingora::http::RequestHeader;
     use pretty_assertions::assert_eq;
-    use std::time::Duration;
+
+    #[test]
+    fn test_is_audited() {
+        assert_eq!(true, is_audited(&Method::PUT, "/configs/upstreams/a"));
+        assert_eq!(true, is_audited(&Method::POST, "/restart"));
+        assert_eq!(true, is_audited(&Method::DELETE, "/cache/keys"));
+        assert_eq!(false, is_audited(&Method::GET, "/configs/upstreams"));
+        assert_eq!(false, is_audited(&Method::POST, "/aes"));
+    }
+
+    #[test]
+    fn test_is_static_file() {
+        assert_eq!(true, is_static_file(&Method::GET, "/"));
+        assert_eq!(true, is_static_file(&Method::GET, "/assets/index.js"));
+        assert_eq!(true, is_static_file(&Method::HEAD, "/logo.png"));
+        assert_eq!(false, is_static_file(&Method::GET, "/assets/indexjs"));
+        assert_eq!(false, is_static_file(&Method::GET, "/api/configs/a.js"));
+        assert_eq!(
+            false,
+            is_static_file(&Method::PUT, "/configs/upstreams/x.js")
+        );
+        assert_eq!(
+            false,
+            is_static_file(&Method::DELETE, "/api/cache/keys/abc.png")
+        );
+        assert_eq!(
+            false,
+            AdminIdentity::anonymous().is_permitted(&Method::GET, "/configs")
+        );
+    }
+
+    #[test]
+    fn test_get_api_path() {
+        assert_eq!("/configs", get_api_path("/", "/api/configs"));
+        assert_eq!("/configs", get_api_path("/pingap", "/pingap/api/configs"));
+        assert_eq!("/configs", get_api_path("/pingap/", "/pingap/api/configs"));
+        assert_eq!(
+            "/index.html",
+            get_api_path("/pingap", "/pingap/index.html")
+        );
+    }
 
     #[test]
     fn test_admin_params() {
@@ -674,6 +1585,7 @@ mod tests {
         "YWRtaW46MTIzMTIz",
         "cGluZ2FwOjEyMzEyMw=="
     ]
+    audit_log = "/var/log/pingap/audit.log"
     "#,
             )
             .unwrap(),
@@ -690,6 +1602,7 @@ mod tests {
         );
         assert_eq!("request", params.plugin_step.to_string());
         assert_eq!("/", params.path);
+        assert_eq!("/var/log/pingap/audit.log", params.audit_log);
 
         let result = AdminServe::try_from(
             &toml::from_str::<PluginConf>(
@@ -729,8 +1642,13 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "admin-ui")]
     #[test]
     fn test_embedded_static_file() {
+        use super::{AdminAsset, EmbeddedStaticFile};
+        use crate::http_extra::HttpResponse;
+        use std::time::Duration;
+
         let file = AdminAsset::get("index.html").unwrap();
         let resp: HttpResponse =
             EmbeddedStaticFile(Some(file), Duration::from_secs(60)).into();
@@ -746,4 +1664,135 @@ mod tests {
             EmbeddedStaticFile(None, Duration::from_secs(60)).into();
         assert_eq!(404, resp.status.as_u16())
     }
+
+    #[test]
+    fn test_redact_toml_value() {
+        let mut value = toml::from_str::<toml::Value>(
+            r###"
+[basic]
+webhook = "https://hooks.example.com/token"
+threads = 2
+
+[certificates.pingap]
+tls_cert = "cert"
+tls_key = "key"
+
+[plugins.auth]
+category = "basic_auth"
+authorizations = ["cGluZ2FwOjEyMzEyMw=="]
+"###,
+        )
+        .unwrap();
+        redact_toml_value(&mut value);
+        assert_eq!("***", value["basic"]["webhook"].as_str().unwrap());
+        assert_eq!(2, value["basic"]["threads"].as_integer().unwrap());
+        assert_eq!(
+            "cert",
+            value["certificates"]["pingap"]["tls_cert"]
+                .as_str()
+                .unwrap()
+        );
+        assert_eq!(
+            "***",
+            value["certificates"]["pingap"]["tls_key"].as_str().unwrap()
+        );
+        assert_eq!(
+            "***",
+            value["plugins"]["auth"]["authorizations"].as_str().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_admin_users_tokens() {
+        let password = bcrypt::hash("123123", 4).unwrap();
+        // sha256 of "pingap-token"
+        let token_hash = hex::encode(<sha2::Sha256 as sha2::Digest>::digest(
+            b"pingap-token",
+        ));
+        let admin = AdminServe::try_from(
+            &toml::from_str::<PluginConf>(&format!(
+                r#"
+path = "/"
+[users.tree]
+password = "{password}"
+role = "read_only"
+
+[tokens.ci]
+hash = "{token_hash}"
+scopes = ["upstreams"]
+"#
+            ))
+            .unwrap(),
+        )
+        .unwrap();
+
+        let mut req_header =
+            RequestHeader::build("GET", b"/api/basic", None).unwrap();
+        assert_eq!(true, admin.authenticate(&req_header).await.is_none());
+        // the static file is anonymous, the mutation needs authentication
+        let header =
+            RequestHeader::build("GET", b"/assets/index.js", None).unwrap();
+        assert_eq!(
+            AdminRole::Anonymous,
+            admin.authenticate(&header).await.unwrap().role
+        );
+        let header =
+            RequestHeader::build("PUT", b"/api/configs/upstreams/x.js", None)
+                .unwrap();
+        assert_eq!(true, admin.authenticate(&header).await.is_none());
+
+        // dHJlZToxMjMxMjM= is tree:123123
+        req_header
+            .insert_header("Authorization", "Basic dHJlZToxMjMxMjM=")
+            .unwrap();
+        let identity = admin.authenticate(&req_header).await.unwrap();
+        assert_eq!("tree", identity.name);
+        assert_eq!(AdminRole::ReadOnly, identity.role);
+        assert_eq!(true, identity.is_permitted(&Method::GET, "/basic"));
+        assert_eq!(
+            false,
+            identity.is_permitted(&Method::POST, "/configs/upstreams/charts")
+        );
+        // verified by cache
+        assert_eq!(true, admin.authenticate(&req_header).await.is_some());
+
+        req_header
+            .insert_header("Authorization", "Basic dHJlZToxMjM=")
+            .unwrap();
+        assert_eq!(true, admin.authenticate(&req_header).await.is_none());
+
+        req_header
+            .insert_header("Authorization", "Bearer pingap-token")
+            .unwrap();
+        let identity = admin.authenticate(&req_header).await.unwrap();
+        assert_eq!("ci", identity.name);
+        assert_eq!(
+            true,
+            identity.is_permitted(&Method::POST, "/configs/upstreams/charts")
+        );
+        assert_eq!(
+            false,
+            identity.is_permitted(&Method::POST, "/configs/locations/lo")
+        );
+        assert_eq!(false, identity.is_permitted(&Method::GET, "/basic"));
+        assert_eq!(
+            true,
+            AdminIdentity::admin("").is_permitted(&Method::POST, "/restart")
+        );
+
+        let result = AdminServe::try_from(
+            &toml::from_str::<PluginConf>(
+                r#"
+path = "/"
+[users.tree]
+password = "123123"
+"#,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin admin invalid, message: password of user(tree) should be bcrypt or argon2 hash",
+            result.err().unwrap().to_string()
+        );
+    }
 }
@@ -0,0 +1,519 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    get_bool_conf, get_hash_key, get_step_conf, get_str_conf, Error, Plugin,
+    Result,
+};
+use crate::config::{PluginCategory, PluginConf, PluginStep};
+use crate::http_extra::HttpResponse;
+use crate::state::State;
+#[cfg(feature = "metrics")]
+use crate::state::API_KEY_REQUESTS;
+use crate::util;
+use ahash::AHashMap;
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::{HeaderName, StatusCode};
+use humantime::parse_duration;
+use pingora::proxy::Session;
+use pingora_limits::rate::Rate;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+use toml::Table;
+use tracing::{debug, error};
+
+const CONSUMER_VARIABLE: &str = "api_consumer";
+
+struct Tier {
+    // the max requests of interval
+    limit: Option<(Rate, isize)>,
+    // allowed paths, path prefix if it ends with `*`
+    paths: Vec<String>,
+    // the max requests of one day
+    quota: u64,
+}
+
+impl Tier {
+    fn allow_path(&self, path: &str) -> bool {
+        if self.paths.is_empty() {
+            return true;
+        }
+        self.paths.iter().any(|item| {
+            if let Some(prefix) = item.strip_suffix('*') {
+                path.starts_with(prefix)
+            } else {
+                path == item
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Consumer {
+    name: String,
+    tier: String,
+}
+
+pub struct ApiKey {
+    plugin_step: PluginStep,
+    header: Option<HeaderName>,
+    query: Option<String>,
+    // api key -> consumer
+    consumers: AHashMap<String, Consumer>,
+    tiers: AHashMap<String, Tier>,
+    // consumer -> (day, count)
+    usages: Mutex<AHashMap<String, (u64, u64)>>,
+    hide_credentials: bool,
+    hash_value: String,
+}
+
+fn new_invalid_error(message: String) -> Error {
+    Error::Invalid {
+        category: PluginCategory::ApiKey.to_string(),
+        message,
+    }
+}
+
+fn parse_tiers(value: &PluginConf) -> Result<AHashMap<String, Tier>> {
+    let mut tiers = AHashMap::new();
+    let Some(values) = value.get("tiers").and_then(|v| v.as_table()) else {
+        return Ok(tiers);
+    };
+    for (name, item) in values.iter() {
+        let Some(item) = item.as_table() else {
+            continue;
+        };
+        let get_int = |key: &str| {
+            item.get(key)
+                .and_then(|v| v.as_integer())
+                .unwrap_or_default()
+                .max(0)
+        };
+        let max = get_int("max");
+        let limit = if max > 0 {
+            let interval = item
+                .get("interval")
+                .and_then(|v| v.as_str())
+                .unwrap_or_default();
+            let interval = if interval.is_empty() {
+                Duration::from_secs(60)
+            } else {
+                parse_duration(interval)
+                    .map_err(|e| new_invalid_error(e.to_string()))?
+            };
+            Some((Rate::new(interval), max as isize))
+        } else {
+            None
+        };
+        let paths = item
+            .get("paths")
+            .and_then(|v| v.as_array())
+            .map(|arr| {
+                arr.iter()
+                    .filter_map(|v| v.as_str().map(|v| v.to_string()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        tiers.insert(
+            name.to_string(),
+            Tier {
+                limit,
+                paths,
+                quota: get_int("quota") as u64,
+            },
+        );
+    }
+    Ok(tiers)
+}
+
+fn parse_consumers(
+    values: Option<&Table>,
+    consumers: &mut AHashMap<String, Consumer>,
+) {
+    let Some(values) = values else {
+        return;
+    };
+    for (name, item) in values.iter() {
+        let get = |key: &str| {
+            item.get(key)
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .to_string()
+        };
+        let key = get("key");
+        if key.is_empty() {
+            continue;
+        }
+        consumers.insert(
+            key,
+            Consumer {
+                name: name.to_string(),
+                tier: get("tier"),
+            },
+        );
+    }
+}
+
+impl TryFrom<&PluginConf> for ApiKey {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let step = get_step_conf(value);
+
+        let query_name = get_str_conf(value, "query");
+        let header_name = get_str_conf(value, "header");
+        if query_name.is_empty() && header_name.is_empty() {
+            return Err(new_invalid_error(
+                "Header or query of api key should be set".to_string(),
+            ));
+        }
+        let mut query = None;
+        let mut header = None;
+        if !query_name.is_empty() {
+            query = Some(query_name);
+        } else {
+            header = Some(HeaderName::from_str(&header_name).map_err(|e| {
+                new_invalid_error(format!("invalid header name, {e}"))
+            })?);
+        }
+
+        // the consumers of config file are merged with the inline consumers
+        let mut consumers = AHashMap::new();
+        let file = get_str_conf(value, "consumers_file");
+        if !file.is_empty() {
+            let data = std::fs::read_to_string(&file).map_err(|e| {
+                new_invalid_error(format!("read consumers {file} fail, {e}"))
+            })?;
+            let table: Table = toml::from_str(&data)
+                .map_err(|e| new_invalid_error(e.to_string()))?;
+            parse_consumers(
+                table.get("consumers").and_then(|v| v.as_table()),
+                &mut consumers,
+            );
+        }
+        parse_consumers(
+            value.get("consumers").and_then(|v| v.as_table()),
+            &mut consumers,
+        );
+        let tiers = parse_tiers(value)?;
+        for consumer in consumers.values() {
+            if !tiers.contains_key(&consumer.tier) {
+                return Err(new_invalid_error(format!(
+                    "tier({}) of consumer({}) is not found",
+                    consumer.tier, consumer.name
+                )));
+            }
+        }
+        if consumers.is_empty() {
+            return Err(new_invalid_error(
+                "Consumers should not be empty".to_string(),
+            ));
+        }
+
+        let params = Self {
+            hash_value,
+            plugin_step: step,
+            header,
+            query,
+            consumers,
+            tiers,
+            usages: Mutex::new(AHashMap::new()),
+            hide_credentials: get_bool_conf(value, "hide_credentials"),
+        };
+        if ![PluginStep::Request, PluginStep::ProxyUpstream]
+            .contains(&params.plugin_step)
+        {
+            return Err(new_invalid_error("Api key plugin should be executed at request or proxy upstream step".to_string()));
+        }
+        Ok(params)
+    }
+}
+
+impl ApiKey {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new api key plugin");
+        Self::try_from(params)
+    }
+    /// Increase the daily usage of consumer, it returns false
+    /// if the quota is exceeded.
+    fn incr_usage(&self, consumer: &str, quota: u64) -> bool {
+        let day = util::now().as_secs() / (24 * 3600);
+        let mut usages = self.usages.lock().unwrap_or_else(|e| e.into_inner());
+        let usage = usages.entry(consumer.to_string()).or_insert((day, 0));
+        if usage.0 != day {
+            *usage = (day, 0);
+        }
+        if quota > 0 && usage.1 >= quota {
+            return false;
+        }
+        usage.1 += 1;
+        true
+    }
+    /// Validate the consumer of api key, the error response and
+    /// the result label of metrics are returned if it's not allowed.
+    fn validate(
+        &self,
+        consumer: &Consumer,
+        path: &str,
+    ) -> std::result::Result<(), (HttpResponse, &'static str)> {
+        let Some(tier) = self.tiers.get(&consumer.tier) else {
+            return Err((
+                HttpResponse {
+                    status: StatusCode::FORBIDDEN,
+                    body: Bytes::from_static(b"Api key tier is not found"),
+                    ..Default::default()
+                },
+                "forbidden",
+            ));
+        };
+        if !tier.allow_path(path) {
+            return Err((
+                HttpResponse {
+                    status: StatusCode::FORBIDDEN,
+                    body: Bytes::from_static(b"Path is not allowed"),
+                    ..Default::default()
+                },
+                "forbidden",
+            ));
+        }
+        if let Some((rate, max)) = &tier.limit {
+            rate.observe(&consumer.name, 1);
+            if rate.rate(&consumer.name) as isize > *max {
+                return Err((
+                    HttpResponse {
+                        status: StatusCode::TOO_MANY_REQUESTS,
+                        body: Bytes::from_static(b"Too many requests"),
+                        ..Default::default()
+                    },
+                    "rate_limited",
+                ));
+            }
+        }
+        if !self.incr_usage(&consumer.name, tier.quota) {
+            return Err((
+                HttpResponse {
+                    status: StatusCode::TOO_MANY_REQUESTS,
+                    body: Bytes::from_static(b"Daily quota exceeded"),
+                    ..Default::default()
+                },
+                "quota_exceeded",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Plugin for ApiKey {
+    #[inline]
+    fn hash_key(&self) -> String {
+        self.hash_value.clone()
+    }
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut State,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        if step != self.plugin_step {
+            return Ok(None);
+        }
+        let value = if let Some(key) = &self.query {
+            util::get_query_value(session.req_header(), key).unwrap_or_default()
+        } else {
+            self.header
+                .as_ref()
+                .and_then(|v| session.get_header(v))
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default()
+        };
+        if value.is_empty() {
+            return Ok(Some(HttpResponse {
+                status: StatusCode::UNAUTHORIZED,
+                body: Bytes::from_static(b"Api key missing"),
+                ..Default::default()
+            }));
+        }
+        let Some(consumer) = self.consumers.get(value) else {
+            return Ok(Some(HttpResponse {
+                status: StatusCode::UNAUTHORIZED,
+                body: Bytes::from_static(b"Api key is invalid"),
+                ..Default::default()
+            }));
+        };
+        let result = self.validate(consumer, session.req_header().uri.path());
+        #[cfg(feature = "metrics")]
+        API_KEY_REQUESTS
+            .with_label_values(&[
+                &consumer.name,
+                &consumer.tier,
+                result.as_ref().err().map_or("allowed", |(_, label)| *label),
+            ])
+            .inc();
+        if let Err((resp, _)) = result {
+            return Ok(Some(resp));
+        }
+        ctx.add_variable(CONSUMER_VARIABLE, &consumer.name);
+        ctx.set_auth_identity(None, Some(&consumer.name));
+        if self.hide_credentials {
+            if let Some(name) = &self.header {
+                session.req_header_mut().remove_header(name);
+            } else if let Some(name) = &self.query {
+                if let Err(e) = util::remove_query_from_header(
+                    session.req_header_mut(),
+                    name,
+                ) {
+                    error!(error = e.to_string(), "remove query fail");
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ApiKey;
+    use crate::config::{PluginConf, PluginStep};
+    use crate::plugin::Plugin;
+    use crate::state::State;
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use tokio_test::io::Builder;
+
+    fn new_api_key() -> ApiKey {
+        ApiKey::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+header = "X-Api-Key"
+hide_credentials = true
+
+[tiers.free]
+max = 100
+interval = "1m"
+paths = ["/api/*"]
+quota = 2
+
+[tiers.pro]
+
+[consumers.alice]
+key = "alice-key"
+tier = "free"
+
+[consumers.bob]
+key = "bob-key"
+tier = "pro"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_api_key_params() {
+        let params = new_api_key();
+        assert_eq!("x-api-key", params.header.as_ref().unwrap().as_str());
+        assert_eq!(2, params.consumers.len());
+        assert_eq!(2, params.tiers.len());
+        assert_eq!("alice", params.consumers.get("alice-key").unwrap().name);
+        assert_eq!(2, params.tiers.get("free").unwrap().quota);
+        assert_eq!(true, params.tiers.get("pro").unwrap().limit.is_none());
+
+        let result = ApiKey::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+header = "X-Api-Key"
+[consumers.alice]
+key = "alice-key"
+tier = "free"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin api_key invalid, message: tier(free) of consumer(alice) is not found",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_api_key() {
+        let params = new_api_key();
+        for (input_header, status, consumer) in [
+            ("GET /api/users HTTP/1.1\r\n\r\n", Some(401), ""),
+            (
+                "GET /api/users HTTP/1.1\r\nX-Api-Key: unknown\r\n\r\n",
+                Some(401),
+                "",
+            ),
+            (
+                "GET /admin HTTP/1.1\r\nX-Api-Key: alice-key\r\n\r\n",
+                Some(403),
+                "",
+            ),
+            (
+                "GET /api/users HTTP/1.1\r\nX-Api-Key: alice-key\r\n\r\n",
+                None,
+                "alice",
+            ),
+            (
+                "GET /api/books HTTP/1.1\r\nX-Api-Key: alice-key\r\n\r\n",
+                None,
+                "alice",
+            ),
+            // daily quota is 2
+            (
+                "GET /api/books HTTP/1.1\r\nX-Api-Key: alice-key\r\n\r\n",
+                Some(429),
+                "",
+            ),
+            (
+                "GET /admin HTTP/1.1\r\nX-Api-Key: bob-key\r\n\r\n",
+                None,
+                "bob",
+            ),
+        ] {
+            let mock_io = Builder::new().read(input_header.as_bytes()).build();
+            let mut session = Session::new_h1(Box::new(mock_io));
+            session.read_request().await.unwrap();
+            let mut ctx = State::default();
+            let result = params
+                .handle_request(PluginStep::Request, &mut session, &mut ctx)
+                .await
+                .unwrap();
+            assert_eq!(
+                status,
+                result.as_ref().map(|resp| resp.status.as_u16()),
+                "{input_header}"
+            );
+            let value = ctx
+                .variables
+                .as_ref()
+                .and_then(|variables| variables.get("$api_consumer").cloned())
+                .unwrap_or_default();
+            assert_eq!(consumer, value);
+            if result.is_none() {
+                // the api key is removed
+                assert_eq!(
+                    true,
+                    session.req_header().headers.get("X-Api-Key").is_none()
+                );
+            }
+        }
+    }
+}
@@ -19,20 +19,92 @@ use super::{
 use crate::config::{PluginCategory, PluginConf, PluginStep};
 use crate::http_extra::HttpResponse;
 use crate::state::State;
-use crate::util::base64_decode;
+use crate::util::{self, base64_decode};
+use ahash::{AHashMap, AHashSet};
+use arc_swap::ArcSwap;
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
 use async_trait::async_trait;
 use bytes::Bytes;
 use http::HeaderValue;
 use http::StatusCode;
 use humantime::parse_duration;
 use pingora::proxy::Session;
-use std::time::Duration;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 use tokio::time::sleep;
-use tracing::debug;
+use tracing::{debug, error, info};
+
+// the verified authorizations are cleared if the count exceeds it
+const MAX_VERIFIED_AUTHORIZATIONS: usize = 1024;
+
+/// The users with hashed password, the verified authorizations are
+/// cached, so the password is only hashed once for the same credential.
+#[derive(Debug, Default)]
+struct HashedUsers {
+    users: AHashMap<String, String>,
+    verified: Mutex<AHashSet<Vec<u8>>>,
+    // the modified time of htpasswd file
+    modified: Option<SystemTime>,
+}
+
+/// Check the hash is supported, only bcrypt and argon2 are supported.
+pub(crate) fn is_supported_hash(hash: &str) -> bool {
+    ["$2a$", "$2b$", "$2y$", "$argon2"]
+        .iter()
+        .any(|prefix| hash.starts_with(prefix))
+}
+
+/// Parse the users of htpasswd format, every line is `user:hash`.
+fn parse_hashed_users<'a>(
+    lines: impl Iterator<Item = &'a str>,
+) -> Result<AHashMap<String, String>> {
+    let mut users = AHashMap::new();
+    for (index, line) in lines.enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((user, hash)) = line
+            .split_once(':')
+            .filter(|(_, hash)| is_supported_hash(hash))
+        else {
+            return Err(Error::Invalid {
+                category: PluginCategory::BasicAuth.to_string(),
+                message: format!(
+                    "line {}: only bcrypt and argon2 hash are supported",
+                    index + 1
+                ),
+            });
+        };
+        users.insert(user.to_string(), hash.to_string());
+    }
+    Ok(users)
+}
+
+/// Verify the password with bcrypt or argon2 hash.
+pub(crate) fn verify_password(password: &str, hash: &str) -> bool {
+    if hash.starts_with("$argon2") {
+        return PasswordHash::new(hash)
+            .map(|hash| {
+                Argon2::default()
+                    .verify_password(password.as_bytes(), &hash)
+                    .is_ok()
+            })
+            .unwrap_or_default();
+    }
+    bcrypt::verify(password, hash).unwrap_or_default()
+}
 
 pub struct BasicAuth {
     plugin_step: PluginStep,
     authorizations: Vec<Vec<u8>>,
+    // the inline users of `user:hash`
+    users: Vec<String>,
+    htpasswd_file: String,
+    interval: Duration,
+    hashed_users: ArcSwap<HashedUsers>,
+    checked_at: AtomicU64,
     hide_credentials: bool,
     miss_authorization_resp: HttpResponse,
     unauthorized_resp: HttpResponse,
@@ -64,18 +136,38 @@ impl TryFrom<&PluginConf> for BasicAuth {
             })?;
             authorizations.push(format!("Basic {item}").as_bytes().to_vec());
         }
-        if authorizations.is_empty() {
+        let users = get_str_slice_conf(value, "users");
+        let htpasswd_file =
+            util::resolve_path(&get_str_conf(value, "htpasswd_file"));
+        if authorizations.is_empty()
+            && users.is_empty()
+            && htpasswd_file.is_empty()
+        {
             return Err(Error::Invalid {
                 category: PluginCategory::BasicAuth.to_string(),
                 message: "basic authorizations can't be empty".to_string(),
             });
         }
+        let interval = get_str_conf(value, "interval");
+        let interval = if interval.is_empty() {
+            Duration::from_secs(30)
+        } else {
+            parse_duration(&interval).map_err(|e| Error::Invalid {
+                category: PluginCategory::BasicAuth.to_string(),
+                message: e.to_string(),
+            })?
+        };
         let params = Self {
             hash_value,
             plugin_step: step,
             delay,
             hide_credentials: get_bool_conf(value, "hide_credentials"),
             authorizations,
+            users,
+            htpasswd_file,
+            interval,
+            hashed_users: ArcSwap::from_pointee(HashedUsers::default()),
+            checked_at: AtomicU64::new(util::now().as_secs()),
             miss_authorization_resp: HttpResponse {
                 status: StatusCode::UNAUTHORIZED,
                 headers: Some(vec![(
@@ -108,6 +200,17 @@ impl TryFrom<&PluginConf> for BasicAuth {
                     .to_string(),
             });
         }
+        let modified = if params.htpasswd_file.is_empty() {
+            None
+        } else {
+            std::fs::metadata(&params.htpasswd_file)
+                .and_then(|meta| meta.modified())
+                .ok()
+        };
+        let data = params.read_file()?;
+        params
+            .hashed_users
+            .store(Arc::new(params.parse(&data, modified)?));
         Ok(params)
     }
 }
@@ -117,6 +220,147 @@ impl BasicAuth {
         debug!(params = params.to_string(), "new basic auth plugin");
         Self::try_from(params)
     }
+    fn read_file(&self) -> Result<String> {
+        if self.htpasswd_file.is_empty() {
+            return Ok("".to_string());
+        }
+        std::fs::read_to_string(&self.htpasswd_file).map_err(|e| {
+            Error::Invalid {
+                category: PluginCategory::BasicAuth.to_string(),
+                message: format!(
+                    "read htpasswd file {} fail, {e}",
+                    self.htpasswd_file
+                ),
+            }
+        })
+    }
+    /// Parse the users of htpasswd file and the inline users,
+    /// the inline users have higher priority.
+    fn parse(
+        &self,
+        data: &str,
+        modified: Option<SystemTime>,
+    ) -> Result<HashedUsers> {
+        let mut users = parse_hashed_users(data.lines())?;
+        users.extend(parse_hashed_users(
+            self.users.iter().map(|item| item.as_str()),
+        )?);
+        Ok(HashedUsers {
+            users,
+            modified,
+            ..Default::default()
+        })
+    }
+    /// Reload the htpasswd file if it's modified,
+    /// the previous users are kept if the new file is invalid.
+    async fn try_reload(&self) {
+        if self.htpasswd_file.is_empty() {
+            return;
+        }
+        let now = util::now().as_secs();
+        let checked_at = self.checked_at.load(Ordering::Relaxed);
+        if now < checked_at + self.interval.as_secs()
+            || self
+                .checked_at
+                .compare_exchange(
+                    checked_at,
+                    now,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                )
+                .is_err()
+        {
+            return;
+        }
+        let modified = tokio::fs::metadata(&self.htpasswd_file)
+            .await
+            .and_then(|meta| meta.modified())
+            .ok();
+        if modified.is_none() || modified == self.hashed_users.load().modified {
+            return;
+        }
+        let result = match tokio::fs::read_to_string(&self.htpasswd_file).await
+        {
+            Ok(data) => self.parse(&data, modified),
+            Err(e) => Err(Error::Invalid {
+                category: PluginCategory::BasicAuth.to_string(),
+                message: e.to_string(),
+            }),
+        };
+        match result {
+            Ok(hashed_users) => {
+                info!(
+                    file = self.htpasswd_file,
+                    count = hashed_users.users.len(),
+                    "reload htpasswd file"
+                );
+                self.hashed_users.store(Arc::new(hashed_users));
+            },
+            Err(e) => {
+                error!(
+                    file = self.htpasswd_file,
+                    error = e.to_string(),
+                    "reload htpasswd file fail"
+                );
+            },
+        }
+    }
+    /// Verify the authorization with the hashed users, the hash is
+    /// calculated in blocking thread as it's cpu intensive.
+    async fn verify_hashed(&self, value: &[u8]) -> bool {
+        self.try_reload().await;
+        let hashed_users = self.hashed_users.load_full();
+        if hashed_users.users.is_empty() {
+            return false;
+        }
+        if hashed_users
+            .verified
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .contains(value)
+        {
+            return true;
+        }
+        let Some((user, password)) = get_basic_auth_credential(value) else {
+            return false;
+        };
+        let Some(hash) = hashed_users.users.get(&user).cloned() else {
+            return false;
+        };
+        let valid = tokio::task::spawn_blocking(move || {
+            verify_password(&password, &hash)
+        })
+        .await
+        .unwrap_or_default();
+        if valid {
+            let mut verified = hashed_users
+                .verified
+                .lock()
+                .unwrap_or_else(|e| e.into_inner());
+            if verified.len() >= MAX_VERIFIED_AUTHORIZATIONS {
+                verified.clear();
+            }
+            verified.insert(value.to_vec());
+        }
+        valid
+    }
+}
+
+/// Get the user and password of basic authorization,
+/// e.g. `Basic dHJlZTpwaW5nYXA=`.
+pub(crate) fn get_basic_auth_credential(
+    value: &[u8],
+) -> Option<(String, String)> {
+    let value = std::str::from_utf8(value).ok()?;
+    let data = base64_decode(value.strip_prefix("Basic ")?).ok()?;
+    let data = String::from_utf8(data).ok()?;
+    let (user, password) = data.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+/// Get the user of basic authorization, e.g. `Basic dHJlZTpwaW5nYXA=`.
+fn get_basic_auth_user(value: &[u8]) -> Option<String> {
+    get_basic_auth_credential(value).map(|(user, _)| user)
 }
 
 #[async_trait]
@@ -130,7 +374,7 @@ impl Plugin for BasicAuth {
         &self,
         step: PluginStep,
         session: &mut Session,
-        _ctx: &mut State,
+        ctx: &mut State,
     ) -> pingora::Result<Option<HttpResponse>> {
         if step != self.plugin_step {
             return Ok(None);
@@ -139,12 +383,15 @@ impl Plugin for BasicAuth {
         if value.is_empty() {
             return Ok(Some(self.miss_authorization_resp.clone()));
         }
-        if !self.authorizations.contains(&value.to_vec()) {
+        if !self.authorizations.contains(&value.to_vec())
+            && !self.verify_hashed(value).await
+        {
             if let Some(d) = self.delay {
                 sleep(d).await;
             }
             return Ok(Some(self.unauthorized_resp.clone()));
         }
+        ctx.set_auth_identity(get_basic_auth_user(value).as_deref(), None);
         if self.hide_credentials {
             session
                 .req_header_mut()
@@ -156,9 +403,10 @@ impl Plugin for BasicAuth {
 
 #[cfg(test)]
 mod tests {
-    use super::{BasicAuth, Plugin};
+    use super::{parse_hashed_users, verify_password, BasicAuth, Plugin};
     use crate::config::{PluginConf, PluginStep};
     use crate::state::State;
+    use bytes::BytesMut;
     use http::StatusCode;
     use pingora::proxy::Session;
     use pretty_assertions::assert_eq;
@@ -225,6 +473,75 @@ authorizations = [
         );
     }
 
+    #[test]
+    fn test_hashed_users() {
+        let hash = bcrypt::hash("pingap", 4).unwrap();
+        let data = format!("# users\nadmin:{hash}\n");
+        let users = parse_hashed_users(data.lines()).unwrap();
+        assert_eq!(1, users.len());
+        assert_eq!(true, verify_password("pingap", &users["admin"]));
+        assert_eq!(false, verify_password("123123", &users["admin"]));
+
+        let result = parse_hashed_users("admin:123123".lines());
+        assert_eq!(
+            "Plugin basic_auth invalid, message: line 1: only bcrypt and argon2 hash are supported",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_verify_argon2_password() {
+        use argon2::password_hash::{PasswordHasher, SaltString};
+        let salt = SaltString::from_b64("cGluZ2FwLXNhbHQ").unwrap();
+        let hash = argon2::Argon2::default()
+            .hash_password(b"pingap", &salt)
+            .unwrap()
+            .to_string();
+        assert_eq!(true, verify_password("pingap", &hash));
+        assert_eq!(false, verify_password("123123", &hash));
+    }
+
+    #[tokio::test]
+    async fn test_basic_auth_htpasswd() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let file = dir.path().join("htpasswd");
+        let hash = bcrypt::hash("123123", 4).unwrap();
+        std::fs::write(&file, format!("admin:{hash}\n")).unwrap();
+        let auth = BasicAuth::new(
+            &toml::from_str::<PluginConf>(&format!(
+                r###"
+htpasswd_file = "{}"
+"###,
+                file.to_string_lossy()
+            ))
+            .unwrap(),
+        )
+        .unwrap();
+
+        for (authorization, is_none) in [
+            ("YWRtaW46MTIzMTIz", true),
+            // the verified authorization is cached
+            ("YWRtaW46MTIzMTIz", true),
+            ("YWRtaW46MTIzMTIa", false),
+        ] {
+            let input_header = format!(
+                "GET / HTTP/1.1\r\nAuthorization: Basic {authorization}\r\n\r\n"
+            );
+            let mock_io = Builder::new().read(input_header.as_bytes()).build();
+            let mut session = Session::new_h1(Box::new(mock_io));
+            session.read_request().await.unwrap();
+            let result = auth
+                .handle_request(
+                    PluginStep::Request,
+                    &mut session,
+                    &mut State::default(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(is_none, result.is_none());
+        }
+    }
+
     #[tokio::test]
     async fn test_basic_auth() {
         let auth = BasicAuth::new(
@@ -247,12 +564,9 @@ hide_credentials = true
         let mock_io = Builder::new().read(input_header.as_bytes()).build();
         let mut session = Session::new_h1(Box::new(mock_io));
         session.read_request().await.unwrap();
+        let mut ctx = State::default();
         let result = auth
-            .handle_request(
-                PluginStep::Request,
-                &mut session,
-                &mut State::default(),
-            )
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
             .await
             .unwrap();
         assert_eq!(true, result.is_none());
@@ -260,6 +574,10 @@ hide_credentials = true
             false,
             session.req_header().headers.contains_key("Authorization")
         );
+        assert_eq!(
+            b"admin",
+            ctx.append_value(BytesMut::new(), "auth_user").as_ref()
+        );
 
         // auth fail
         let headers = ["Authorization: Basic YWRtaW46MTIzMTIa"].join("\r\n");
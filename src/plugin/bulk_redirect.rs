@@ -0,0 +1,471 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    get_bool_conf, get_hash_key, get_int_conf, get_step_conf, get_str_conf,
+    get_str_slice_conf, Error, Plugin, Result,
+};
+use crate::config::{PluginCategory, PluginConf, PluginStep};
+use crate::http_extra::HttpResponse;
+use crate::state::State;
+use crate::util;
+use ahash::AHashMap;
+use arc_swap::ArcSwap;
+use async_trait::async_trait;
+use http::{header, HeaderValue, StatusCode};
+use humantime::parse_duration;
+use pingora::proxy::Session;
+use regex::{Regex, RegexSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tracing::{debug, error, info};
+
+#[derive(Debug, Clone)]
+struct RedirectRule {
+    target: String,
+    status: StatusCode,
+}
+
+/// The redirect map, the exact rules are looked up by hash map,
+/// and the regex rules are matched by regex set in one pass.
+#[derive(Debug, Default)]
+struct RedirectMap {
+    exact: AHashMap<String, RedirectRule>,
+    regex_set: Option<RegexSet>,
+    regexes: Vec<(Regex, RedirectRule)>,
+    // the modified time of redirect file
+    modified: Option<SystemTime>,
+}
+
+impl RedirectMap {
+    /// Get the redirect location and status of path,
+    /// the exact rule has higher priority than the regex rule.
+    fn get(&self, path: &str) -> Option<(String, StatusCode)> {
+        if let Some(rule) = self.exact.get(path) {
+            return Some((rule.target.clone(), rule.status));
+        }
+        let regex_set = self.regex_set.as_ref()?;
+        let index = regex_set.matches(path).iter().next()?;
+        let (regex, rule) = self.regexes.get(index)?;
+        Some((
+            regex.replace(path, rule.target.as_str()).to_string(),
+            rule.status,
+        ))
+    }
+}
+
+/// Parse the redirect rules, every line is `source target [status]`,
+/// the source starts with `~` is a regex, e.g. `~^/blog/(\d+)$ /posts/$1 302`.
+fn parse_redirect_map<'a>(
+    lines: impl Iterator<Item = &'a str>,
+    default_status: StatusCode,
+) -> Result<RedirectMap> {
+    let new_invalid_error = |line: usize, message: String| Error::Invalid {
+        category: PluginCategory::BulkRedirect.to_string(),
+        message: format!("line {line}: {message}"),
+    };
+    let mut redirect_map = RedirectMap::default();
+    let mut patterns = vec![];
+    for (index, line) in lines.enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let items: Vec<&str> = line.split_whitespace().collect();
+        if items.len() < 2 || items.len() > 3 {
+            return Err(new_invalid_error(
+                index + 1,
+                format!("redirect({line}) is invalid"),
+            ));
+        }
+        let status = if let Some(status) = items.get(2) {
+            status
+                .parse::<u16>()
+                .ok()
+                .and_then(|value| StatusCode::from_u16(value).ok())
+                .filter(|value| value.is_redirection())
+                .ok_or_else(|| {
+                    new_invalid_error(
+                        index + 1,
+                        format!("status({status}) is invalid"),
+                    )
+                })?
+        } else {
+            default_status
+        };
+        let rule = RedirectRule {
+            target: items[1].to_string(),
+            status,
+        };
+        if let Some(pattern) = items[0].strip_prefix('~') {
+            let regex = Regex::new(pattern)
+                .map_err(|e| new_invalid_error(index + 1, e.to_string()))?;
+            patterns.push(pattern.to_string());
+            redirect_map.regexes.push((regex, rule));
+        } else {
+            // the first rule of the same source is used
+            redirect_map
+                .exact
+                .entry(items[0].to_string())
+                .or_insert(rule);
+        }
+    }
+    if !patterns.is_empty() {
+        redirect_map.regex_set =
+            Some(RegexSet::new(patterns).map_err(|e| Error::Invalid {
+                category: PluginCategory::BulkRedirect.to_string(),
+                message: e.to_string(),
+            })?);
+    }
+    Ok(redirect_map)
+}
+
+/// Redirect the request by the rules of redirect file,
+/// the file is reloaded if it's modified.
+pub struct BulkRedirect {
+    plugin_step: PluginStep,
+    file: String,
+    redirects: Vec<String>,
+    status: StatusCode,
+    // append the query of request if the target doesn't have query
+    keep_query: bool,
+    interval: Duration,
+    redirect_map: ArcSwap<RedirectMap>,
+    checked_at: AtomicU64,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for BulkRedirect {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let step = get_step_conf(value);
+
+        let status = get_int_conf(value, "status");
+        let status = if status == 0 {
+            StatusCode::MOVED_PERMANENTLY
+        } else {
+            StatusCode::from_u16(status as u16)
+                .ok()
+                .filter(|value| value.is_redirection())
+                .ok_or_else(|| Error::Invalid {
+                    category: PluginCategory::BulkRedirect.to_string(),
+                    message: format!("status({status}) is invalid"),
+                })?
+        };
+        let interval = get_str_conf(value, "interval");
+        let interval = if interval.is_empty() {
+            Duration::from_secs(30)
+        } else {
+            parse_duration(&interval).map_err(|e| Error::Invalid {
+                category: PluginCategory::BulkRedirect.to_string(),
+                message: e.to_string(),
+            })?
+        };
+
+        let params = Self {
+            hash_value,
+            plugin_step: step,
+            file: util::resolve_path(&get_str_conf(value, "file")),
+            redirects: get_str_slice_conf(value, "redirects"),
+            status,
+            keep_query: get_bool_conf(value, "keep_query"),
+            interval,
+            redirect_map: ArcSwap::from_pointee(RedirectMap::default()),
+            checked_at: AtomicU64::new(util::now().as_secs()),
+        };
+        if params.plugin_step != PluginStep::Request {
+            return Err(Error::Invalid {
+                category: PluginCategory::BulkRedirect.to_string(),
+                message:
+                    "Bulk redirect plugin should be executed at request step"
+                        .to_string(),
+            });
+        }
+        let modified = if params.file.is_empty() {
+            None
+        } else {
+            std::fs::metadata(&params.file)
+                .and_then(|meta| meta.modified())
+                .ok()
+        };
+        let data = params.read_file()?;
+        params
+            .redirect_map
+            .store(Arc::new(params.parse(&data, modified)?));
+
+        Ok(params)
+    }
+}
+
+impl BulkRedirect {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new bulk redirect plugin");
+        Self::try_from(params)
+    }
+    fn read_file(&self) -> Result<String> {
+        if self.file.is_empty() {
+            return Ok("".to_string());
+        }
+        std::fs::read_to_string(&self.file).map_err(|e| Error::Invalid {
+            category: PluginCategory::BulkRedirect.to_string(),
+            message: format!("read redirect file {} fail, {e}", self.file),
+        })
+    }
+    /// Parse the rules of file and the inline redirects,
+    /// the inline redirects have higher priority.
+    fn parse(
+        &self,
+        data: &str,
+        modified: Option<SystemTime>,
+    ) -> Result<RedirectMap> {
+        let mut redirect_map = parse_redirect_map(
+            self.redirects
+                .iter()
+                .map(|item| item.as_str())
+                .chain(data.lines()),
+            self.status,
+        )?;
+        redirect_map.modified = modified;
+        Ok(redirect_map)
+    }
+    /// Reload the redirect file if it's modified,
+    /// the previous rules are kept if the new file is invalid.
+    async fn try_reload(&self) {
+        if self.file.is_empty() {
+            return;
+        }
+        let now = util::now().as_secs();
+        let checked_at = self.checked_at.load(Ordering::Relaxed);
+        if now < checked_at + self.interval.as_secs()
+            || self
+                .checked_at
+                .compare_exchange(
+                    checked_at,
+                    now,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                )
+                .is_err()
+        {
+            return;
+        }
+        let modified = tokio::fs::metadata(&self.file)
+            .await
+            .and_then(|meta| meta.modified())
+            .ok();
+        if modified.is_none() || modified == self.redirect_map.load().modified {
+            return;
+        }
+        let result = match tokio::fs::read_to_string(&self.file).await {
+            Ok(data) => self.parse(&data, modified),
+            Err(e) => Err(Error::Invalid {
+                category: PluginCategory::BulkRedirect.to_string(),
+                message: e.to_string(),
+            }),
+        };
+        match result {
+            Ok(redirect_map) => {
+                info!(
+                    file = self.file,
+                    count =
+                        redirect_map.exact.len() + redirect_map.regexes.len(),
+                    "reload bulk redirect file"
+                );
+                self.redirect_map.store(Arc::new(redirect_map));
+            },
+            Err(e) => {
+                error!(
+                    file = self.file,
+                    error = e.to_string(),
+                    "reload bulk redirect file fail"
+                );
+            },
+        }
+    }
+    fn get_location(
+        &self,
+        path: &str,
+        query: Option<&str>,
+    ) -> Option<HttpResponse> {
+        let (mut location, status) = self.redirect_map.load().get(path)?;
+        if let Some(query) = query.filter(|_| self.keep_query) {
+            if !location.contains('?') {
+                location = format!("{location}?{query}");
+            }
+        }
+        let location = HeaderValue::from_str(&location).ok()?;
+        Some(HttpResponse {
+            status,
+            headers: Some(vec![(header::LOCATION, location)]),
+            ..Default::default()
+        })
+    }
+}
+
+#[async_trait]
+impl Plugin for BulkRedirect {
+    #[inline]
+    fn hash_key(&self) -> String {
+        self.hash_value.clone()
+    }
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        _ctx: &mut State,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        if step != self.plugin_step {
+            return Ok(None);
+        }
+        self.try_reload().await;
+        let uri = &session.req_header().uri;
+        Ok(self.get_location(uri.path(), uri.query()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BulkRedirect;
+    use crate::config::{PluginConf, PluginStep};
+    use crate::plugin::Plugin;
+    use crate::state::State;
+    use http::StatusCode;
+    use nanoid::nanoid;
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use tokio_test::io::Builder;
+
+    #[test]
+    fn test_bulk_redirect_params() {
+        let result = BulkRedirect::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+redirects = ["/old"]
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin bulk_redirect invalid, message: line 1: redirect(/old) is invalid",
+            result.err().unwrap().to_string()
+        );
+
+        let result = BulkRedirect::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+redirects = ["/old /new 200"]
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin bulk_redirect invalid, message: line 1: status(200) is invalid",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_bulk_redirect_location() {
+        let file = format!("/tmp/{}.txt", nanoid!(16));
+        std::fs::write(
+            &file,
+            r###"# migrate the old site
+/about.html /about
+/blog/index.html https://blog.example.com/ 302
+~^/blog/(\d+)\.html$ /posts/$1
+"###,
+        )
+        .unwrap();
+        let params = BulkRedirect::new(
+            &toml::from_str::<PluginConf>(&format!(
+                r###"
+file = "{file}"
+keep_query = true
+redirects = ["/about.html /about-us 308"]
+"###
+            ))
+            .unwrap(),
+        )
+        .unwrap();
+        std::fs::remove_file(&file).unwrap();
+
+        let resp = params.get_location("/about.html", None).unwrap();
+        assert_eq!(StatusCode::PERMANENT_REDIRECT, resp.status);
+        assert_eq!(
+            r#"Some([("location", "/about-us")])"#,
+            format!("{:?}", resp.headers)
+        );
+
+        let resp = params
+            .get_location("/blog/index.html", Some("a=1"))
+            .unwrap();
+        assert_eq!(StatusCode::FOUND, resp.status);
+        assert_eq!(
+            r#"Some([("location", "https://blog.example.com/?a=1")])"#,
+            format!("{:?}", resp.headers)
+        );
+
+        let resp = params.get_location("/blog/123.html", None).unwrap();
+        assert_eq!(StatusCode::MOVED_PERMANENTLY, resp.status);
+        assert_eq!(
+            r#"Some([("location", "/posts/123")])"#,
+            format!("{:?}", resp.headers)
+        );
+
+        assert_eq!(true, params.get_location("/blog/abc.html", None).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_bulk_redirect() {
+        let params = BulkRedirect::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+redirects = ["/old /new"]
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        let mock_io = Builder::new().read(b"GET /old HTTP/1.1\r\n\r\n").build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let resp = params
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(StatusCode::MOVED_PERMANENTLY, resp.status);
+
+        let mock_io = Builder::new().read(b"GET /new HTTP/1.1\r\n\r\n").build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let result = params
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_none());
+    }
+}
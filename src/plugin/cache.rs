@@ -13,16 +13,23 @@
 // limitations under the License.
 
 use super::{
-    get_bool_conf, get_hash_key, get_step_conf, get_str_conf,
-    get_str_slice_conf, Error, Plugin, Result,
+    get_bool_conf, get_hash_key, get_int_conf, get_step_conf, get_str_conf,
+    get_str_slice_conf, read_request_body, Error, Plugin, Result,
+};
+use crate::cache::{
+    is_memcached_url, is_prefetch_request, is_redis_url, is_s3_url,
+    new_file_cache, new_memcached_cache, new_redis_cache, new_s3_cache,
+    new_tiered_cache, new_tiny_ufo_cache, split_memory_size, CacheAdmission,
+    HttpCache, HTTP_HEADER_PREFETCH, NOT_ADMITTED,
 };
-use crate::cache::{new_file_cache, new_tiny_ufo_cache, HttpCache};
 use crate::config::{
     get_current_config, PluginCategory, PluginConf, PluginStep,
 };
 use crate::http_extra::HttpResponse;
+use crate::service::SimpleServiceTaskFuture;
 use crate::state::{get_cache_key, State};
 use crate::util;
+use ahash::AHashMap;
 use async_trait::async_trait;
 use bytes::{BufMut, Bytes, BytesMut};
 use bytesize::ByteSize;
@@ -36,17 +43,27 @@ use pingora::cache::eviction::EvictionManager;
 use pingora::cache::key::CacheHashKey;
 use pingora::cache::lock::CacheLock;
 use pingora::cache::predictor::{CacheablePredictor, Predictor};
+use pingora::http::RequestHeader;
 use pingora::proxy::Session;
+use sha2::{Digest, Sha256};
 use std::str::FromStr;
+use std::sync::Mutex;
 use std::time::Duration;
 use tracing::{debug, error};
 
 // meomory limit size
 const MAX_MEMORY_SIZE: usize = 100 * 1024 * 1024;
+// the post body is buffered by pingora retry buffer(64kb)
+const MAX_POST_BODY_SIZE: usize = 64 * 1024;
 static CACHE_BACKEND: OnceCell<HttpCache> = OnceCell::new();
 static PREDICTOR: OnceCell<Predictor<32>> = OnceCell::new();
 static EVICTION_MANAGER: OnceCell<Manager> = OnceCell::new();
-static CACHE_LOCK_ONE_SECOND: OnceCell<CacheLock> = OnceCell::new();
+// the cache locks of different timeouts
+static CACHE_LOCKS: Lazy<Mutex<AHashMap<u64, &'static CacheLock>>> =
+    Lazy::new(|| Mutex::new(AHashMap::new()));
+// the eviction managers of cache plugins which have their own size
+static EVICTION_MANAGERS: Lazy<Mutex<AHashMap<String, &'static Manager>>> =
+    Lazy::new(|| Mutex::new(AHashMap::new()));
 
 pub struct Cache {
     plugin_step: PluginStep,
@@ -56,15 +73,68 @@ pub struct Cache {
     http_cache: &'static HttpCache,
     max_file_size: usize,
     max_ttl: Option<Duration>,
+    // the ttl of response which doesn't set the freshness
+    ttl: Option<Duration>,
+    // the ttls of negative responses(404, 410, 451 and 5xx),
+    // they are cached even if upstream doesn't allow
+    negative_ttls: Option<Vec<(u16, Duration)>>,
+    // the admission policy by the hits of key in the window
+    admission: Option<CacheAdmission>,
+    // the response larger than it is admitted by the probability of size
+    admission_size: Option<usize>,
+    // serve the stale response while revalidating in background
+    stale_while_revalidate: Option<Duration>,
+    // serve the stale response if upstream fails
+    stale_if_error: Option<Duration>,
     namespace: Option<String>,
     headers: Option<Vec<String>>,
+    // the varied request headers which are used to store variants
+    vary: Option<Vec<String>>,
+    // the template of cache key, method and uri are used if not set
+    key_template: Option<CacheKeyTemplate>,
+    // prefetch the hot cache in the duration before expired
+    prefetch: Option<Duration>,
+    prefetch_min_hits: u32,
     check_cache_control: bool,
     purge_ip_rules: util::IpRules,
     skip: Option<Regex>,
+    // cache the response of post request,
+    // the digest of body will be used as cache key
+    cache_post: bool,
+    max_post_body_size: usize,
     hash_value: String,
 }
 
-fn get_cache_backend() -> Result<&'static HttpCache> {
+/// Parse the ttls of negative responses, e.g. `404:30s`,
+/// only 404, 410, 451 and 5xx are allowed.
+fn parse_negative_ttls(values: &[String]) -> Result<Vec<(u16, Duration)>> {
+    let mut ttls = vec![];
+    for value in values.iter() {
+        let new_invalid_error = |message: String| Error::Invalid {
+            category: PluginCategory::Cache.to_string(),
+            message,
+        };
+        let Some((status, ttl)) = value.split_once(':') else {
+            return Err(new_invalid_error(format!(
+                "negative ttl({value}) is invalid"
+            )));
+        };
+        let status = status.trim().parse::<u16>().map_err(|e| {
+            new_invalid_error(format!("negative ttl({value}) is invalid, {e}"))
+        })?;
+        if ![404, 410, 451].contains(&status) && !(500..600).contains(&status) {
+            return Err(new_invalid_error(format!(
+                "status({status}) of negative ttl is not allowed"
+            )));
+        }
+        let ttl = parse_duration(ttl.trim())
+            .map_err(|e| new_invalid_error(e.to_string()))?;
+        ttls.push((status, ttl));
+    }
+    Ok(ttls)
+}
+
+pub(crate) fn get_cache_backend() -> Result<&'static HttpCache> {
     // get global cache backend
     CACHE_BACKEND.get_or_try_init(|| {
         let basic_conf = &get_current_config().basic;
@@ -73,12 +143,31 @@ fn get_cache_backend() -> Result<&'static HttpCache> {
         } else {
             MAX_MEMORY_SIZE
         };
-        // file cache
-        let cache = if let Some(dir) = &basic_conf.cache_directory {
-            new_file_cache(dir.as_str()).map_err(|e| Error::Invalid {
+        let mut cache = if let Some(dir) = &basic_conf.cache_directory {
+            let (url, memory_size) = split_memory_size(dir);
+            let cache = if is_redis_url(&url) {
+                // redis cache
+                new_redis_cache(&url)
+            } else if is_memcached_url(&url) {
+                // memcached cache
+                new_memcached_cache(&url)
+            } else if is_s3_url(&url) {
+                // s3 cache
+                new_s3_cache(&url)
+            } else {
+                // file cache
+                new_file_cache(&url)
+            }
+            .map_err(|e| Error::Invalid {
                 category: "cache_backend".to_string(),
                 message: e.to_string(),
-            })?
+            })?;
+            // the memory cache is used for hot objects
+            if memory_size > 0 {
+                new_tiered_cache(cache, memory_size)
+            } else {
+                cache
+            }
         } else {
             // max memory
             let max_memory = if let Some(value) = memory_stats() {
@@ -89,10 +178,46 @@ fn get_cache_backend() -> Result<&'static HttpCache> {
             // tiny ufo cache
             new_tiny_ufo_cache(size.min(max_memory))
         };
+        cache.compression_level =
+            basic_conf.cache_compression_level.unwrap_or_default();
         Ok(cache)
     })
 }
 
+/// Get the eviction manager of the cache plugin which has its own size,
+/// so the objects of other locations won't be evicted by it.
+fn get_sized_eviction_manager(key: &str, size: usize) -> &'static Manager {
+    let mut managers =
+        EVICTION_MANAGERS.lock().unwrap_or_else(|e| e.into_inner());
+    let manager = managers.entry(key.to_string()).or_insert_with(|| {
+        // the manager is kept until the config of plugin is changed
+        Box::leak(Box::new(Manager::new(size)))
+    });
+    manager
+}
+
+async fn do_cache_checkpoint(count: u32) -> Result<bool, String> {
+    // save the checkpoint every 5 minutes
+    if count % 5 != 0 {
+        return Ok(false);
+    }
+    let cache = get_cache_backend().map_err(|e| e.to_string())?;
+    cache.checkpoint().await.map_err(|e| e.to_string())
+}
+
+/// Create the service which saves the index of file cache periodically,
+/// the cache is reopened from the checkpoint after restart.
+pub fn new_cache_checkpoint_service(
+) -> Option<(String, SimpleServiceTaskFuture)> {
+    let dir = get_current_config().basic.cache_directory.clone()?;
+    if is_redis_url(&dir) || is_memcached_url(&dir) || is_s3_url(&dir) {
+        return None;
+    }
+    let task: SimpleServiceTaskFuture =
+        Box::new(|count: u32| Box::pin(do_cache_checkpoint(count)));
+    Some(("cacheCheckpoint".to_string(), task))
+}
+
 fn get_eviction_manager() -> &'static Manager {
     EVICTION_MANAGER.get_or_init(|| {
         let size = if let Some(cache_max_size) =
@@ -106,26 +231,153 @@ fn get_eviction_manager() -> &'static Manager {
     })
 }
 
+/// Get the cache lock of timeout, the locks are shared by the cache
+/// plugins which have the same timeout.
 fn get_cache_lock(lock: Duration) -> Option<&'static CacheLock> {
-    match lock.as_secs() {
-        1 => Some(
-            CACHE_LOCK_ONE_SECOND
-                .get_or_init(|| CacheLock::new(Duration::from_secs(1))),
-        ),
-        2 => Some(
-            CACHE_LOCK_ONE_SECOND
-                .get_or_init(|| CacheLock::new(Duration::from_secs(2))),
-        ),
-        3 => Some(
-            CACHE_LOCK_ONE_SECOND
-                .get_or_init(|| CacheLock::new(Duration::from_secs(3))),
-        ),
-        _ => None,
+    if lock.is_zero() {
+        return None;
     }
+    let mut locks = CACHE_LOCKS.lock().unwrap_or_else(|e| e.into_inner());
+    let lock = locks.entry(lock.as_millis() as u64).or_insert_with(|| {
+        // the count of locks is limited by the config of cache plugins
+        Box::leak(Box::new(CacheLock::new(lock)))
+    });
+    Some(*lock)
 }
 
 fn get_predictor() -> &'static (dyn CacheablePredictor + Sync) {
-    PREDICTOR.get_or_init(|| Predictor::new(128, None))
+    // the response which isn't admitted can be cached later
+    PREDICTOR.get_or_init(|| {
+        Predictor::new(128, Some(|reason| reason == NOT_ADMITTED))
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum KeyTag {
+    Text(String),
+    Scheme,
+    Host,
+    Method,
+    Path,
+    Query,
+    Header(String),
+    Cookie(String),
+}
+
+/// The template of cache key, e.g. `{method}:{host}{path}?{query}`,
+/// the host is lowercased and the query params are sorted.
+#[derive(Debug, Default)]
+struct CacheKeyTemplate {
+    tags: Vec<KeyTag>,
+    // only these query params are used if it's not empty
+    query_include: Vec<String>,
+    query_exclude: Vec<String>,
+}
+
+impl CacheKeyTemplate {
+    fn new(
+        template: &str,
+        query_include: Vec<String>,
+        query_exclude: Vec<String>,
+    ) -> Result<Self> {
+        let mut tags = vec![];
+        let mut value = template;
+        while let Some(start) = value.find('{') {
+            let Some(end) = value[start..].find('}') else {
+                break;
+            };
+            if start > 0 {
+                tags.push(KeyTag::Text(value[..start].to_string()));
+            }
+            let name = &value[start + 1..start + end];
+            let tag = match name {
+                "scheme" => KeyTag::Scheme,
+                "host" => KeyTag::Host,
+                "method" => KeyTag::Method,
+                "path" => KeyTag::Path,
+                "query" => KeyTag::Query,
+                _ => {
+                    if let Some(header) = name.strip_prefix("header:") {
+                        KeyTag::Header(header.trim().to_lowercase())
+                    } else if let Some(cookie) = name.strip_prefix("cookie:") {
+                        KeyTag::Cookie(cookie.trim().to_string())
+                    } else {
+                        return Err(Error::Invalid {
+                            category: PluginCategory::Cache.to_string(),
+                            message: format!("Invalid cache key tag: {name}"),
+                        });
+                    }
+                },
+            };
+            tags.push(tag);
+            value = &value[start + end + 1..];
+        }
+        if !value.is_empty() {
+            tags.push(KeyTag::Text(value.to_string()));
+        }
+        Ok(Self {
+            tags,
+            query_include,
+            query_exclude,
+        })
+    }
+    /// Normalize the query, the params are filtered and sorted,
+    /// so the same resource has the same cache key.
+    fn normalize_query(&self, query: &str) -> String {
+        let mut params: Vec<&str> = query
+            .split('&')
+            .filter(|item| {
+                if item.is_empty() {
+                    return false;
+                }
+                let name = item.split_once('=').map_or(*item, |(k, _)| k);
+                if !self.query_include.is_empty() {
+                    return self.query_include.iter().any(|v| v == name);
+                }
+                !self.query_exclude.iter().any(|v| v == name)
+            })
+            .collect();
+        params.sort_unstable();
+        params.join("&")
+    }
+    fn render(&self, ctx: &State, method: &str, req: &RequestHeader) -> String {
+        let mut key = String::with_capacity(64);
+        for tag in self.tags.iter() {
+            match tag {
+                KeyTag::Text(value) => key.push_str(value),
+                KeyTag::Scheme => {
+                    if ctx.tls_version.is_some() {
+                        key.push_str("https");
+                    } else {
+                        key.push_str("http");
+                    }
+                },
+                KeyTag::Host => {
+                    if let Some(host) = util::get_host(req) {
+                        key.push_str(&host.to_lowercase());
+                    }
+                },
+                KeyTag::Method => key.push_str(method),
+                KeyTag::Path => key.push_str(req.uri.path()),
+                KeyTag::Query => {
+                    if let Some(query) = req.uri.query() {
+                        key.push_str(&self.normalize_query(query));
+                    }
+                },
+                KeyTag::Header(name) => {
+                    if let Some(value) = util::get_req_header_value(req, name) {
+                        key.push_str(value);
+                    }
+                },
+                KeyTag::Cookie(name) => {
+                    if let Some(value) = util::get_cookie_value(req, name) {
+                        key.push_str(value);
+                    }
+                },
+            }
+        }
+        key
+    }
 }
 
 impl TryFrom<&PluginConf> for Cache {
@@ -133,7 +385,18 @@ impl TryFrom<&PluginConf> for Cache {
     fn try_from(value: &PluginConf) -> Result<Self> {
         let hash_value = get_hash_key(value);
         let cache = get_cache_backend()?;
-        let eviction = if value.contains_key("eviction") {
+        let eviction_size = get_str_conf(value, "eviction_size");
+        let eviction = if !eviction_size.is_empty() {
+            let size = ByteSize::from_str(&eviction_size).map_err(|e| {
+                Error::Invalid {
+                    category: PluginCategory::Cache.to_string(),
+                    message: e.to_string(),
+                }
+            })?;
+            let eviction =
+                get_sized_eviction_manager(&hash_value, size.as_u64() as usize);
+            Some(eviction as &'static (dyn EvictionManager + Sync))
+        } else if value.contains_key("eviction") {
             let eviction = get_eviction_manager();
             Some(eviction as &'static (dyn EvictionManager + Sync))
         } else {
@@ -196,6 +459,12 @@ impl TryFrom<&PluginConf> for Cache {
             Some(headers)
         };
 
+        let vary: Vec<String> = get_str_slice_conf(value, "vary")
+            .iter()
+            .map(|item| item.trim().to_lowercase())
+            .collect();
+        let vary = if vary.is_empty() { None } else { Some(vary) };
+
         let predictor = if value.contains_key("predictor") {
             Some(get_predictor())
         } else {
@@ -215,6 +484,81 @@ impl TryFrom<&PluginConf> for Cache {
             })?)
         };
 
+        let get_duration_conf = |key: &str| -> Result<Option<Duration>> {
+            let value = get_str_conf(value, key);
+            if value.is_empty() {
+                return Ok(None);
+            }
+            let d = parse_duration(&value).map_err(|e| Error::Invalid {
+                category: PluginCategory::Cache.to_string(),
+                message: e.to_string(),
+            })?;
+            Ok(Some(d))
+        };
+        let stale_while_revalidate =
+            get_duration_conf("stale_while_revalidate")?;
+        let stale_if_error = get_duration_conf("stale_if_error")?;
+        let key_template = get_str_conf(value, "key");
+        let key_template = if key_template.is_empty() {
+            None
+        } else {
+            Some(CacheKeyTemplate::new(
+                &key_template,
+                get_str_slice_conf(value, "key_query_include"),
+                get_str_slice_conf(value, "key_query_exclude"),
+            )?)
+        };
+        let prefetch = get_duration_conf("prefetch")?;
+        let prefetch_min_hits = get_int_conf(value, "prefetch_min_hits");
+        let mut ttl = get_duration_conf("ttl")?;
+        // the default ttl can't be greater than max ttl
+        if let (Some(d), Some(max_ttl)) = (ttl, max_ttl) {
+            ttl = Some(d.min(max_ttl));
+        }
+
+        let negative_ttls =
+            parse_negative_ttls(&get_str_slice_conf(value, "negative_ttls"))?;
+        let negative_ttls = if negative_ttls.is_empty() {
+            None
+        } else {
+            Some(negative_ttls)
+        };
+
+        let admission_min_hits = get_int_conf(value, "admission_min_hits");
+        let admission = if admission_min_hits > 1 {
+            let window = get_duration_conf("admission_window")?
+                .unwrap_or(Duration::from_secs(60));
+            Some(CacheAdmission::new(admission_min_hits as isize, window))
+        } else {
+            None
+        };
+        let admission_size = get_str_conf(value, "admission_size");
+        let admission_size = if admission_size.is_empty() {
+            None
+        } else {
+            let size = ByteSize::from_str(&admission_size).map_err(|e| {
+                Error::Invalid {
+                    category: PluginCategory::Cache.to_string(),
+                    message: e.to_string(),
+                }
+            })?;
+            Some(size.as_u64() as usize)
+        };
+
+        let max_post_body_size = get_str_conf(value, "max_post_body_size");
+        let max_post_body_size = if !max_post_body_size.is_empty() {
+            let size =
+                ByteSize::from_str(&max_post_body_size).map_err(|e| {
+                    Error::Invalid {
+                        category: PluginCategory::Cache.to_string(),
+                        message: e.to_string(),
+                    }
+                })?;
+            (size.as_u64() as usize).min(MAX_POST_BODY_SIZE)
+        } else {
+            16 * 1024
+        };
+
         let params = Self {
             hash_value,
             http_cache: cache,
@@ -223,12 +567,24 @@ impl TryFrom<&PluginConf> for Cache {
             predictor,
             lock: get_cache_lock(lock),
             max_ttl,
+            ttl,
+            negative_ttls,
+            admission,
+            admission_size,
+            stale_while_revalidate,
+            stale_if_error,
             max_file_size: max_file_size.as_u64() as usize,
             namespace,
             headers,
+            vary,
+            key_template,
+            prefetch,
+            prefetch_min_hits: prefetch_min_hits.max(1) as u32,
             purge_ip_rules,
             check_cache_control: get_bool_conf(value, "check_cache_control"),
             skip,
+            cache_post: get_bool_conf(value, "cache_post"),
+            max_post_body_size,
         };
         if params.plugin_step != PluginStep::Request {
             return Err(Error::Invalid {
@@ -248,7 +604,7 @@ impl Cache {
     }
 }
 
-static METHOD_PURGE: Lazy<Method> =
+pub(crate) static METHOD_PURGE: Lazy<Method> =
     Lazy::new(|| Method::from_bytes(b"PURGE").unwrap());
 
 #[async_trait]
@@ -267,11 +623,24 @@ impl Plugin for Cache {
         if step != self.plugin_step {
             return Ok(None);
         }
-        // cache only support get or head
+        // cache only support get or head,
+        // post is supported if cache post is enabled
         let req_header = session.req_header();
-        let method = &req_header.method;
-        if ![Method::GET, Method::HEAD, METHOD_PURGE.to_owned()]
-            .contains(method)
+        let method = req_header.method.clone();
+        let is_post = method == Method::POST;
+        if is_post {
+            if !self.cache_post {
+                return Ok(None);
+            }
+            // the body should be read for cache key,
+            // so only the request with small content length is cacheable
+            let content_length =
+                util::get_content_length(req_header).unwrap_or_default();
+            if content_length == 0 || content_length > self.max_post_body_size {
+                return Ok(None);
+            }
+        } else if ![Method::GET, Method::HEAD, METHOD_PURGE.to_owned()]
+            .contains(&method)
         {
             return Ok(None);
         }
@@ -294,11 +663,37 @@ impl Plugin for Cache {
                 }
             }
         }
+        if is_post {
+            let body = read_request_body(session, ctx, self.max_post_body_size)
+                .await?;
+            let mut hasher = Sha256::new();
+            hasher.update(&body);
+            keys.put(hex::encode(hasher.finalize()).as_bytes());
+            keys.put(&b":"[..]);
+        }
         if !keys.is_empty() {
             let prefix =
                 std::str::from_utf8(&keys).unwrap_or_default().to_string();
             debug!("Cache prefix: {prefix}");
-            ctx.cache_prefix = Some(prefix);
+            // the prefix may be set by other plugins(e.g. client hints)
+            ctx.cache_prefix = if let Some(cache_prefix) = &ctx.cache_prefix {
+                Some(format!("{cache_prefix}{prefix}"))
+            } else {
+                Some(prefix)
+            };
+        }
+        if let Some(key_template) = &self.key_template {
+            // the cache of purge request is the cache of get request
+            let method = if method == METHOD_PURGE.to_owned() {
+                Method::GET
+            } else {
+                method.clone()
+            };
+            ctx.cache_key = Some(key_template.render(
+                ctx,
+                method.as_str(),
+                session.req_header(),
+            ));
         }
         if method == METHOD_PURGE.to_owned() {
             let found = match self
@@ -326,7 +721,6 @@ impl Plugin for Cache {
                 &session.req_header().uri,
             );
             self.http_cache
-                .cached
                 .remove(&key.combined(), key.namespace())
                 .await?;
             return Ok(Some(HttpResponse::no_content()));
@@ -334,7 +728,25 @@ impl Plugin for Cache {
 
         // max age of cache control
         ctx.cache_max_ttl = self.max_ttl;
+        ctx.cache_ttl = self.ttl;
+        ctx.cache_negative_ttls.clone_from(&self.negative_ttls);
+        ctx.cache_admission_size = self.admission_size;
+        if let Some(admission) = &self.admission {
+            let key =
+                get_cache_key(ctx, method.as_str(), &session.req_header().uri);
+            ctx.cache_not_admitted = !admission.observe(&key.combined());
+        }
+        ctx.cache_stale_while_revalidate = self.stale_while_revalidate;
+        ctx.cache_stale_if_error = self.stale_if_error;
         ctx.check_cache_control = self.check_cache_control;
+        ctx.cache_vary.clone_from(&self.vary);
+        ctx.cache_prefetch = self.prefetch;
+        ctx.cache_prefetch_min_hits = self.prefetch_min_hits;
+        if is_prefetch_request(session.req_header()) {
+            ctx.cache_prefetching = true;
+            // the token shouldn't be sent to upstream
+            session.req_header_mut().remove_header(HTTP_HEADER_PREFETCH);
+        }
 
         session.cache.enable(
             self.http_cache,
@@ -357,10 +769,11 @@ impl Plugin for Cache {
 
 #[cfg(test)]
 mod tests {
-    use super::Cache;
+    use super::{Cache, CacheKeyTemplate};
     use crate::config::{PluginConf, PluginStep};
     use crate::plugin::Plugin;
     use crate::state::State;
+    use pingora::http::RequestHeader;
     use pingora::proxy::Session;
     use pretty_assertions::assert_eq;
     use tokio_test::io::Builder;
@@ -376,20 +789,132 @@ lock = "2s"
 max_file_size = "100kb"
 predictor = true
 max_ttl = "1m"
+stale_while_revalidate = "10s"
+stale_if_error = "1h"
+vary = ["Accept-Encoding", "accept-language"]
+ttl = "5m"
+prefetch = "30s"
+prefetch_min_hits = 3
 "###,
             )
             .unwrap(),
         )
         .unwrap();
+        assert_eq!(10, params.stale_while_revalidate.unwrap().as_secs());
+        assert_eq!(3600, params.stale_if_error.unwrap().as_secs());
         assert_eq!(true, params.eviction.is_some());
         assert_eq!(
             r#"Some(["Accept-Encoding"])"#,
             format!("{:?}", params.headers)
         );
         assert_eq!(true, params.lock.is_some());
+        assert_eq!(
+            true,
+            std::ptr::eq(
+                params.lock.unwrap(),
+                super::get_cache_lock(std::time::Duration::from_secs(2))
+                    .unwrap()
+            )
+        );
+        assert_eq!(
+            true,
+            super::get_cache_lock(std::time::Duration::from_secs(10)).is_some()
+        );
+        assert_eq!(
+            true,
+            super::get_cache_lock(std::time::Duration::ZERO).is_none()
+        );
         assert_eq!(100 * 1000, params.max_file_size);
         assert_eq!(60, params.max_ttl.unwrap().as_secs());
+        // the ttl is limited by max ttl
+        assert_eq!(60, params.ttl.unwrap().as_secs());
+        assert_eq!(30, params.prefetch.unwrap().as_secs());
+        assert_eq!(3, params.prefetch_min_hits);
         assert_eq!(true, params.predictor.is_some());
+        assert_eq!(
+            Some(vec![
+                "accept-encoding".to_string(),
+                "accept-language".to_string()
+            ]),
+            params.vary
+        );
+    }
+    #[test]
+    fn test_cache_admission() {
+        let conf = toml::from_str::<PluginConf>(
+            r###"
+admission_min_hits = 2
+admission_window = "30s"
+admission_size = "1mb"
+"###,
+        )
+        .unwrap();
+        let params = Cache::try_from(&conf).unwrap();
+        assert_eq!(Some(1_000_000), params.admission_size);
+        let admission = params.admission.unwrap();
+        assert_eq!(false, admission.observe("GET:/"));
+        assert_eq!(true, admission.observe("GET:/"));
+
+        let conf = toml::from_str::<PluginConf>(
+            r###"
+admission_min_hits = 1
+"###,
+        )
+        .unwrap();
+        let params = Cache::try_from(&conf).unwrap();
+        assert_eq!(true, params.admission.is_none());
+    }
+    #[test]
+    fn test_parse_negative_ttls() {
+        let ttls = super::parse_negative_ttls(&[
+            "404:30s".to_string(),
+            "503 : 5s".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(
+            vec![
+                (404, std::time::Duration::from_secs(30)),
+                (503, std::time::Duration::from_secs(5))
+            ],
+            ttls
+        );
+        assert_eq!(
+            "Plugin cache invalid, message: status(200) of negative ttl is not allowed",
+            super::parse_negative_ttls(&["200:30s".to_string()])
+                .err()
+                .unwrap()
+                .to_string()
+        );
+        assert_eq!(
+            "Plugin cache invalid, message: negative ttl(404) is invalid",
+            super::parse_negative_ttls(&["404".to_string()])
+                .err()
+                .unwrap()
+                .to_string()
+        );
+    }
+    #[test]
+    fn test_cache_eviction_size() {
+        let conf = toml::from_str::<PluginConf>(
+            r###"
+eviction_size = "10mb"
+ttl = "30s"
+"###,
+        )
+        .unwrap();
+        let params = Cache::try_from(&conf).unwrap();
+        assert_eq!(30, params.ttl.unwrap().as_secs());
+        let eviction = params.eviction.unwrap();
+        let manager = super::get_sized_eviction_manager(&params.hash_value, 0);
+        // the eviction manager isn't shared with other plugins
+        assert_eq!(false, std::ptr::eq(manager, super::get_eviction_manager()));
+        assert_eq!(
+            true,
+            std::ptr::eq(
+                eviction as *const _ as *const (),
+                manager as *const _ as *const ()
+            )
+        );
     }
     #[tokio::test]
     async fn test_cache() {
@@ -441,4 +966,75 @@ max_ttl = "1m"
             .await
             .unwrap();
     }
+    #[test]
+    fn test_cache_key_template() {
+        let template = CacheKeyTemplate::new(
+            "{scheme}://{host}{path}?{query}:{header:X-Device}:{cookie:lang}",
+            vec![],
+            vec!["utm_source".to_string()],
+        )
+        .unwrap();
+        let mut req =
+            RequestHeader::build("GET", b"/books?b=2&utm_source=x&a=1", None)
+                .unwrap();
+        req.insert_header("Host", "Pingap.IO").unwrap();
+        req.insert_header("X-Device", "mobile").unwrap();
+        req.insert_header("Cookie", "uid=1; lang=en").unwrap();
+        assert_eq!(
+            "http://pingap.io/books?a=1&b=2:mobile:en",
+            template.render(&State::default(), "GET", &req)
+        );
+
+        let template = CacheKeyTemplate::new(
+            "{method}:{path}?{query}",
+            vec!["a".to_string()],
+            vec![],
+        )
+        .unwrap();
+        assert_eq!(
+            "GET:/books?a=1",
+            template.render(&State::default(), "GET", &req)
+        );
+
+        assert_eq!(
+            "Plugin cache invalid, message: Invalid cache key tag: uri",
+            CacheKeyTemplate::new("{uri}", vec![], vec![])
+                .err()
+                .unwrap()
+                .to_string()
+        );
+    }
+    #[tokio::test]
+    async fn test_cache_post() {
+        let cache = Cache::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+cache_post = true
+max_post_body_size = "1kb"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(1000, cache.max_post_body_size);
+
+        let body = r#"{"keyword":"pingap"}"#;
+        let input_header = format!(
+            "POST /search HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let mut ctx = State::default();
+        cache
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(
+            "af501b996663c24cb75477f3b7bb5cf90d130a94d1602e811f70d850ca608dfd:",
+            ctx.cache_prefix.unwrap_or_default()
+        );
+        assert_eq!(true, session.cache.enabled());
+    }
 }
@@ -0,0 +1,423 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::cache::{get_cache_backend, METHOD_PURGE};
+use super::{
+    get_hash_key, get_step_conf, get_str_conf, get_str_slice_conf, Error,
+    Plugin, Result,
+};
+use crate::cache::{Error as CacheError, HttpCache};
+use crate::config::{PluginCategory, PluginConf, PluginStep};
+use crate::http_extra::HttpResponse;
+use crate::state::State;
+use crate::util;
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::{header, StatusCode};
+use pingora::cache::key::CacheHashKey;
+use pingora::cache::CacheKey;
+use pingora::http::RequestHeader;
+use pingora::proxy::Session;
+use serde::Serialize;
+use tracing::{debug, info};
+
+const DEFAULT_PURGE_PATH: &str = "/-/purge";
+
+#[derive(Serialize, Debug)]
+struct PurgeResp {
+    purged: usize,
+}
+
+pub struct CachePurge {
+    plugin_step: PluginStep,
+    path: String,
+    namespace: String,
+    authorization: String,
+    ip_rules: Option<util::IpRules>,
+    http_cache: &'static HttpCache,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for CachePurge {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let step = get_step_conf(value);
+
+        let mut path = get_str_conf(value, "path");
+        if path.is_empty() {
+            path = DEFAULT_PURGE_PATH.to_string();
+        }
+        let ip_list = get_str_slice_conf(value, "ip_list");
+        let ip_rules = if ip_list.is_empty() {
+            None
+        } else {
+            Some(util::IpRules::new(&ip_list))
+        };
+
+        let params = Self {
+            hash_value,
+            plugin_step: step,
+            path,
+            namespace: get_str_conf(value, "namespace"),
+            authorization: get_str_conf(value, "authorization"),
+            ip_rules,
+            http_cache: get_cache_backend()?,
+        };
+        if params.authorization.is_empty() && params.ip_rules.is_none() {
+            return Err(Error::Invalid {
+                category: PluginCategory::CachePurge.to_string(),
+                message: "Authorization or ip list should be set".to_string(),
+            });
+        }
+        if params.plugin_step != PluginStep::Request {
+            return Err(Error::Invalid {
+                category: PluginCategory::CachePurge.to_string(),
+                message:
+                    "Cache purge plugin should be executed at request step"
+                        .to_string(),
+            });
+        }
+        Ok(params)
+    }
+}
+
+impl CachePurge {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new cache purge plugin");
+        Self::try_from(params)
+    }
+    /// Check the authorization token and client ip,
+    /// the error response is returned if the request is not allowed.
+    fn validate(&self, session: &Session) -> Option<HttpResponse> {
+        if !self.authorization.is_empty() {
+            let value = session
+                .get_header(header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default();
+            let token = value.strip_prefix("Bearer ").unwrap_or(value).trim();
+            if token != self.authorization {
+                return Some(HttpResponse {
+                    status: StatusCode::UNAUTHORIZED,
+                    body: Bytes::from_static(b"Authorization is invalid"),
+                    ..Default::default()
+                });
+            }
+        }
+        if let Some(ip_rules) = &self.ip_rules {
+            let ip = util::get_client_ip(session);
+            match ip_rules.matched(&ip) {
+                Ok(true) => {},
+                Ok(false) => {
+                    return Some(HttpResponse {
+                        status: StatusCode::FORBIDDEN,
+                        body: Bytes::from_static(
+                            b"Forbidden, ip is not allowed",
+                        ),
+                        ..Default::default()
+                    })
+                },
+                Err(e) => {
+                    return Some(HttpResponse::bad_request(
+                        e.to_string().into(),
+                    ))
+                },
+            }
+        }
+        None
+    }
+}
+
+/// Purge the caches by exact key, key prefix, wildcard pattern, tag
+/// or namespace of query, it's used by cache purge plugin and admin api.
+pub(crate) async fn purge_cache(
+    http_cache: &HttpCache,
+    req_header: &RequestHeader,
+    default_namespace: &str,
+) -> pingora::Result<HttpResponse> {
+    let get_value = |name: &str| {
+        let value = util::get_query_value(req_header, name).unwrap_or_default();
+        urlencoding::decode(value).unwrap_or_default().to_string()
+    };
+    let key = get_value("key");
+    let prefix = get_value("prefix");
+    let pattern = get_value("pattern");
+    let tag = get_value("tag");
+    let namespace = get_value("namespace");
+    let result = if !key.is_empty() {
+        let namespace = if namespace.is_empty() {
+            default_namespace
+        } else {
+            namespace.as_str()
+        };
+        let hash = CacheKey::new(namespace, key.clone(), "").combined();
+        http_cache.remove(&hash, namespace).await.map(usize::from)
+    } else if !prefix.is_empty() {
+        http_cache.remove_by_prefix(&prefix).await
+    } else if !pattern.is_empty() {
+        http_cache.remove_by_pattern(&pattern).await
+    } else if !tag.is_empty() {
+        http_cache.remove_by_tag(&tag).await
+    } else if !namespace.is_empty() {
+        http_cache.remove_by_namespace(&namespace).await
+    } else {
+        return Ok(HttpResponse::bad_request(Bytes::from_static(
+            b"Key, prefix, pattern, tag or namespace should be set",
+        )));
+    };
+    let purged = match result {
+        Ok(purged) => purged,
+        Err(CacheError::Invalid { message }) => {
+            return Ok(HttpResponse::bad_request(message.into()));
+        },
+        Err(e) => return Err(e.into()),
+    };
+    info!(key, prefix, pattern, tag, namespace, purged, "purge cache");
+    HttpResponse::try_from_json(&PurgeResp { purged })
+}
+
+#[async_trait]
+impl Plugin for CachePurge {
+    #[inline]
+    fn hash_key(&self) -> String {
+        self.hash_value.clone()
+    }
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut State,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        if step != self.plugin_step {
+            return Ok(None);
+        }
+        let is_purge_method = session.req_header().method == *METHOD_PURGE;
+        if !is_purge_method && session.req_header().uri.path() != self.path {
+            return Ok(None);
+        }
+        if let Some(resp) = self.validate(session) {
+            return Ok(Some(resp));
+        }
+        if !is_purge_method {
+            return Ok(Some(
+                purge_cache(
+                    self.http_cache,
+                    session.req_header(),
+                    &self.namespace,
+                )
+                .await?,
+            ));
+        }
+        // purge the cache of request url
+        let namespace = ctx.cache_namespace.as_ref().unwrap_or(&self.namespace);
+        let prefix = ctx.cache_prefix.as_deref().unwrap_or_default();
+        let key = format!("{prefix}GET:{}", session.req_header().uri);
+        let hash = CacheKey::new(namespace.as_str(), key, "").combined();
+        self.http_cache.remove(&hash, namespace).await?;
+        Ok(Some(HttpResponse::no_content()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CachePurge;
+    use crate::config::{PluginConf, PluginStep};
+    use crate::plugin::Plugin;
+    use crate::state::State;
+    use pingora::cache::key::CacheHashKey;
+    use pingora::cache::CacheKey;
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use tokio_test::io::Builder;
+
+    fn new_cache_purge() -> CachePurge {
+        CachePurge::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+authorization = "pingap"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    }
+
+    async fn new_session(input_header: &str) -> Session {
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        session
+    }
+
+    #[test]
+    fn test_cache_purge_params() {
+        let params = new_cache_purge();
+        assert_eq!("/-/purge", params.path);
+        assert_eq!("pingap", params.authorization);
+        assert_eq!(true, params.ip_rules.is_none());
+
+        let result = CachePurge::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+path = "/purge"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin cache_purge invalid, message: Authorization or ip list should be set",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cache_purge() {
+        let params = new_cache_purge();
+        let keys = &params.http_cache.keys;
+        for (index, key) in
+            ["GET:/api/users", "GET:/api/books", "GET:/static/logo.png"]
+                .iter()
+                .enumerate()
+        {
+            let hash = CacheKey::new("", key.to_string(), "").combined();
+            let tags = if index == 2 {
+                vec!["static".to_string()]
+            } else {
+                vec![]
+            };
+            keys.add(&hash, key, "", 100, tags);
+        }
+
+        // not purge request
+        let mut session = new_session("GET /api/users HTTP/1.1\r\n\r\n").await;
+        let result = params
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_none());
+
+        // unauthorized
+        let mut session =
+            new_session("POST /-/purge?prefix=GET:/api/ HTTP/1.1\r\n\r\n")
+                .await;
+        let resp = params
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(401, resp.status.as_u16());
+
+        // the keys are indexed, but the storage is empty
+        let mut session = new_session(
+            "POST /-/purge?prefix=GET:/api/ HTTP/1.1\r\nAuthorization: Bearer pingap\r\n\r\n",
+        )
+        .await;
+        let resp = params
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(200, resp.status.as_u16());
+        assert_eq!(r#"{"purged":0}"#, std::str::from_utf8(&resp.body).unwrap());
+        assert_eq!(0, keys.list("GET:/api/", 0, 10).total);
+        assert_eq!(1, keys.list("GET:/static/", 0, 10).total);
+
+        let mut session = new_session(
+            "POST /-/purge?tag=static HTTP/1.1\r\nAuthorization: pingap\r\n\r\n",
+        )
+        .await;
+        let resp = params
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(200, resp.status.as_u16());
+        assert_eq!(0, keys.list("GET:/static/", 0, 10).total);
+
+        let mut session = new_session(
+            "POST /-/purge HTTP/1.1\r\nAuthorization: pingap\r\n\r\n",
+        )
+        .await;
+        let resp = params
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(400, resp.status.as_u16());
+
+        let mut session = new_session(
+            "PURGE /api/users HTTP/1.1\r\nAuthorization: pingap\r\n\r\n",
+        )
+        .await;
+        let resp = params
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(204, resp.status.as_u16());
+
+        for (namespace, key) in
+            [("", "GET:/images/a.png"), ("pingap", "GET:/images/b.png")]
+        {
+            let hash = CacheKey::new(namespace, key.to_string(), "").combined();
+            keys.add(&hash, key, namespace, 100, vec![]);
+        }
+        for (query, status, total) in [
+            ("pattern=GET:/images/%5B", 400, 2),
+            ("pattern=GET:/*/a.png", 200, 1),
+            ("namespace=pingap", 200, 0),
+        ] {
+            let mut session = new_session(&format!(
+                "POST /-/purge?{query} HTTP/1.1\r\nAuthorization: pingap\r\n\r\n"
+            ))
+            .await;
+            let resp = params
+                .handle_request(
+                    PluginStep::Request,
+                    &mut session,
+                    &mut State::default(),
+                )
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(status, resp.status.as_u16());
+            assert_eq!(total, keys.list("GET:/images/", 0, 10).total);
+        }
+    }
+}
@@ -0,0 +1,516 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    get_hash_key, get_local_addr, get_step_conf, get_str_conf, Error, Plugin,
+    Result,
+};
+use crate::config::{PluginCategory, PluginConf, PluginStep};
+use crate::http_extra::HttpResponse;
+use crate::state::State;
+use crate::util;
+use async_trait::async_trait;
+use bytesize::ByteSize;
+use http::{header, HeaderName, HeaderValue, Method, StatusCode};
+use humantime::parse_duration;
+use nanoid::nanoid;
+use once_cell::sync::Lazy;
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::debug;
+
+static HTTP_HEADER_SLICE: &str = "x-pingap-slice";
+
+// the token of slice request, it's generated for each process,
+// so the range of slice can't be set by the client
+static SLICE_TOKEN: Lazy<String> = Lazy::new(|| nanoid!(32));
+
+static IGNORE_RESPONSE: Lazy<HttpResponse> = Lazy::new(|| HttpResponse {
+    status: StatusCode::from_u16(999).unwrap(),
+    ..Default::default()
+});
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum ByteRange {
+    // bytes=start-
+    From(u64),
+    // bytes=start-end
+    Between(u64, u64),
+    // bytes=-length
+    Suffix(u64),
+}
+
+/// Parse the range header, only the single range is supported.
+fn parse_range(value: &str) -> Option<ByteRange> {
+    let value = value.trim().strip_prefix("bytes=")?;
+    if value.contains(',') {
+        return None;
+    }
+    let (start, end) = value.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+    if start.is_empty() {
+        return end.parse::<u64>().ok().map(ByteRange::Suffix);
+    }
+    let start = start.parse::<u64>().ok()?;
+    if end.is_empty() {
+        return Some(ByteRange::From(start));
+    }
+    let end = end.parse::<u64>().ok()?;
+    if start > end {
+        return None;
+    }
+    Some(ByteRange::Between(start, end))
+}
+
+/// Get the range(inclusive) of object, none means the range
+/// is not satisfiable.
+fn get_satisfiable_range(
+    range: Option<ByteRange>,
+    total: u64,
+) -> Option<(u64, u64)> {
+    if total == 0 {
+        return None;
+    }
+    match range {
+        None => Some((0, total - 1)),
+        Some(ByteRange::From(start)) => {
+            (start < total).then_some((start, total - 1))
+        },
+        Some(ByteRange::Between(start, end)) => {
+            (start < total).then_some((start, end.min(total - 1)))
+        },
+        Some(ByteRange::Suffix(length)) => {
+            (length > 0).then_some((total - length.min(total), total - 1))
+        },
+    }
+}
+
+/// Get the total size of object from content range,
+/// e.g. `bytes 0-1023/10240`.
+fn get_total_size(value: &str) -> Option<u64> {
+    value.rsplit_once('/')?.1.trim().parse::<u64>().ok()
+}
+
+/// Split the large object into fixed-size slices, each slice is requested
+/// through the listener of server and cached independently by the cache
+/// plugin, the range response of client is assembled from the slices.
+pub struct CacheSlice {
+    plugin_step: PluginStep,
+    size: u64,
+    // the plain http listener for slice requests,
+    // the listener of current request is used if it's empty
+    addr: String,
+    client: reqwest::Client,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for CacheSlice {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let step = get_step_conf(value);
+        let new_invalid_error = |message: String| Error::Invalid {
+            category: PluginCategory::CacheSlice.to_string(),
+            message,
+        };
+        let size = get_str_conf(value, "size");
+        let size = if size.is_empty() {
+            ByteSize::mb(1).as_u64()
+        } else {
+            ByteSize::from_str(&size)
+                .map_err(|e| new_invalid_error(e.to_string()))?
+                .as_u64()
+        };
+        let timeout = get_str_conf(value, "timeout");
+        let timeout = if timeout.is_empty() {
+            Duration::from_secs(30)
+        } else {
+            parse_duration(&timeout)
+                .map_err(|e| new_invalid_error(e.to_string()))?
+        };
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .map_err(|e| new_invalid_error(e.to_string()))?;
+
+        let params = Self {
+            hash_value,
+            plugin_step: step,
+            size,
+            addr: get_str_conf(value, "addr"),
+            client,
+        };
+        if params.size < ByteSize::kb(64).as_u64() {
+            return Err(new_invalid_error(
+                "Slice size should be at least 64kb".to_string(),
+            ));
+        }
+        if params.plugin_step != PluginStep::Request {
+            return Err(new_invalid_error(
+                "Cache slice plugin should be executed at request step"
+                    .to_string(),
+            ));
+        }
+        Ok(params)
+    }
+}
+
+impl CacheSlice {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new cache slice plugin");
+        Self::try_from(params)
+    }
+    async fn fetch(
+        &self,
+        url: &str,
+        headers: &reqwest::header::HeaderMap,
+        index: u64,
+    ) -> pingora::Result<reqwest::Response> {
+        self.client
+            .get(url)
+            .headers(headers.clone())
+            .header(
+                HTTP_HEADER_SLICE,
+                format!("{}:{index}", SLICE_TOKEN.as_str()),
+            )
+            .send()
+            .await
+            .map_err(|e| util::new_internal_error(502, e.to_string()))
+    }
+    /// Send the response of upstream to client without slicing,
+    /// it's used if upstream doesn't support range request.
+    async fn send_directly(
+        &self,
+        session: &mut Session,
+        mut resp: reqwest::Response,
+    ) -> pingora::Result<StatusCode> {
+        let status = StatusCode::from_u16(resp.status().as_u16())
+            .unwrap_or(StatusCode::BAD_GATEWAY);
+        let mut header = ResponseHeader::build(status, None)?;
+        for (name, value) in resp.headers().iter() {
+            if ["connection", "transfer-encoding"].contains(&name.as_str()) {
+                continue;
+            }
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_ref()),
+                HeaderValue::from_bytes(value.as_bytes()),
+            ) {
+                header.append_header(name, value)?;
+            }
+        }
+        session
+            .write_response_header(Box::new(header), false)
+            .await?;
+        while let Some(chunk) = resp
+            .chunk()
+            .await
+            .map_err(|e| util::new_internal_error(502, e.to_string()))?
+        {
+            session.write_response_body(Some(chunk), false).await?;
+        }
+        session.write_response_body(None, true).await?;
+        session.finish_body().await?;
+        Ok(status)
+    }
+}
+
+#[async_trait]
+impl Plugin for CacheSlice {
+    #[inline]
+    fn hash_key(&self) -> String {
+        self.hash_value.clone()
+    }
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut State,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        if step != self.plugin_step {
+            return Ok(None);
+        }
+        let req = session.req_header_mut();
+        if let Some(value) = req.remove_header(HTTP_HEADER_SLICE) {
+            let index = value
+                .to_str()
+                .ok()
+                .and_then(|value| value.strip_prefix(SLICE_TOKEN.as_str()))
+                .and_then(|value| value.strip_prefix(':'))
+                .and_then(|value| value.parse::<u64>().ok());
+            // the slice request is proxied as usual,
+            // the range of slice is set to upstream request
+            if let Some(index) = index {
+                let start = index * self.size;
+                ctx.cache_slice = Some((start, start + self.size - 1));
+                req.remove_header(&header::RANGE);
+                req.remove_header(&header::IF_RANGE);
+                return Ok(None);
+            }
+        }
+        if req.method != Method::GET
+            || req.headers.contains_key(header::IF_RANGE)
+        {
+            return Ok(None);
+        }
+        let range = match req.headers.get(header::RANGE) {
+            Some(value) => {
+                let Some(range) =
+                    parse_range(value.to_str().unwrap_or_default())
+                else {
+                    return Ok(None);
+                };
+                Some(range)
+            },
+            None => None,
+        };
+        let Some(addr) = get_local_addr(session, &self.addr) else {
+            return Ok(None);
+        };
+        let req = session.req_header();
+        let path = req
+            .uri
+            .path_and_query()
+            .map(|value| value.to_string())
+            .unwrap_or_else(|| "/".to_string());
+        let url = format!("http://{addr}{path}");
+        let mut headers = reqwest::header::HeaderMap::new();
+        for (name, value) in req.headers.iter() {
+            if [
+                header::CONNECTION,
+                header::CONTENT_LENGTH,
+                header::RANGE,
+                header::IF_NONE_MATCH,
+                header::IF_MODIFIED_SINCE,
+            ]
+            .contains(name)
+            {
+                continue;
+            }
+            if let (Ok(name), Ok(value)) = (
+                reqwest::header::HeaderName::from_bytes(name.as_ref()),
+                reqwest::header::HeaderValue::from_bytes(value.as_bytes()),
+            ) {
+                headers.append(name, value);
+            }
+        }
+
+        // the total size of object is got from the first slice
+        let first_index = match range {
+            Some(ByteRange::From(start))
+            | Some(ByteRange::Between(start, _)) => start / self.size,
+            _ => 0,
+        };
+        let first = self.fetch(&url, &headers, first_index).await?;
+        let total = first
+            .headers()
+            .get(header::CONTENT_RANGE.as_str())
+            .and_then(|value| value.to_str().ok())
+            .and_then(get_total_size);
+        let Some(total) = total.filter(|_| first.status().is_success()) else {
+            ctx.status = Some(self.send_directly(session, first).await?);
+            return Ok(Some(IGNORE_RESPONSE.clone()));
+        };
+        let Some((start, end)) = get_satisfiable_range(range, total) else {
+            let content_range = format!("bytes */{total}");
+            return Ok(Some(HttpResponse {
+                status: StatusCode::RANGE_NOT_SATISFIABLE,
+                headers: Some(vec![(
+                    header::CONTENT_RANGE,
+                    HeaderValue::from_str(&content_range).map_err(|e| {
+                        util::new_internal_error(500, e.to_string())
+                    })?,
+                )]),
+                ..Default::default()
+            }));
+        };
+
+        let status = if range.is_some() {
+            StatusCode::PARTIAL_CONTENT
+        } else {
+            StatusCode::OK
+        };
+        let mut resp_header = ResponseHeader::build(status, None)?;
+        for (name, value) in first.headers().iter() {
+            if [
+                "connection",
+                "content-length",
+                "content-range",
+                "transfer-encoding",
+                "accept-ranges",
+            ]
+            .contains(&name.as_str())
+            {
+                continue;
+            }
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_ref()),
+                HeaderValue::from_bytes(value.as_bytes()),
+            ) {
+                resp_header.append_header(name, value)?;
+            }
+        }
+        resp_header.insert_header(header::ACCEPT_RANGES, "bytes")?;
+        resp_header.insert_header(
+            header::CONTENT_LENGTH,
+            (end - start + 1).to_string(),
+        )?;
+        if range.is_some() {
+            resp_header.insert_header(
+                header::CONTENT_RANGE,
+                format!("bytes {start}-{end}/{total}"),
+            )?;
+        }
+        session
+            .write_response_header(Box::new(resp_header), false)
+            .await?;
+        ctx.status = Some(status);
+
+        let mut first = Some(first);
+        for index in start / self.size..=end / self.size {
+            let resp = match first.take() {
+                Some(resp) if index == first_index => resp,
+                _ => self.fetch(&url, &headers, index).await?,
+            };
+            if !resp.status().is_success() {
+                return Err(util::new_internal_error(
+                    502,
+                    format!(
+                        "fetch slice {index} fail, status: {}",
+                        resp.status().as_u16()
+                    ),
+                ));
+            }
+            let data = resp
+                .bytes()
+                .await
+                .map_err(|e| util::new_internal_error(502, e.to_string()))?;
+            let offset = index * self.size;
+            let from = (start.max(offset) - offset) as usize;
+            let to = (end.min(offset + self.size - 1) - offset) as usize;
+            // the object is changed or the slice is truncated
+            if to >= data.len() {
+                return Err(util::new_internal_error(
+                    502,
+                    format!("slice {index} is incomplete"),
+                ));
+            }
+            session
+                .write_response_body(Some(data.slice(from..=to)), false)
+                .await?;
+        }
+        session.write_response_body(None, true).await?;
+        session.finish_body().await?;
+        Ok(Some(IGNORE_RESPONSE.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        get_satisfiable_range, get_total_size, parse_range, ByteRange,
+        CacheSlice,
+    };
+    use crate::config::PluginConf;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_cache_slice_params() {
+        let slice = CacheSlice::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+size = "2mb"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(2_000_000, slice.size);
+
+        let result = CacheSlice::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+size = "1kb"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin cache_slice invalid, message: Slice size should be at least 64kb",
+            result.err().unwrap().to_string()
+        );
+
+        let result = CacheSlice::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+step = "response"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin cache_slice invalid, message: Cache slice plugin should be executed at request step",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(Some(ByteRange::From(100)), parse_range("bytes=100-"));
+        assert_eq!(
+            Some(ByteRange::Between(0, 1023)),
+            parse_range("bytes=0-1023")
+        );
+        assert_eq!(Some(ByteRange::Suffix(500)), parse_range("bytes=-500"));
+        assert_eq!(None, parse_range("bytes=0-1,5-10"));
+        assert_eq!(None, parse_range("bytes=10-1"));
+        assert_eq!(None, parse_range("items=0-1"));
+    }
+
+    #[test]
+    fn test_get_satisfiable_range() {
+        assert_eq!(Some((0, 999)), get_satisfiable_range(None, 1000));
+        assert_eq!(
+            Some((100, 999)),
+            get_satisfiable_range(Some(ByteRange::From(100)), 1000)
+        );
+        assert_eq!(
+            Some((100, 999)),
+            get_satisfiable_range(Some(ByteRange::Between(100, 2000)), 1000)
+        );
+        assert_eq!(
+            Some((900, 999)),
+            get_satisfiable_range(Some(ByteRange::Suffix(100)), 1000)
+        );
+        assert_eq!(
+            Some((0, 999)),
+            get_satisfiable_range(Some(ByteRange::Suffix(2000)), 1000)
+        );
+        assert_eq!(
+            None,
+            get_satisfiable_range(Some(ByteRange::From(1000)), 1000)
+        );
+        assert_eq!(
+            None,
+            get_satisfiable_range(Some(ByteRange::Suffix(0)), 1000)
+        );
+        assert_eq!(None, get_satisfiable_range(None, 0));
+
+        assert_eq!(Some(10240), get_total_size("bytes 0-1023/10240"));
+        assert_eq!(None, get_total_size("bytes 0-1023/*"));
+    }
+}
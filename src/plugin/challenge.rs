@@ -0,0 +1,666 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    get_hash_key, get_int_conf, get_step_conf, get_str_conf, Error, Plugin,
+    Result,
+};
+use crate::config::{PluginCategory, PluginConf, PluginStep};
+use crate::http_extra::{
+    HttpResponse, HTTP_HEADER_CONTENT_HTML, HTTP_HEADER_NO_STORE,
+};
+use crate::limit::TtlLruLimit;
+use crate::state::State;
+use crate::util;
+use async_trait::async_trait;
+use bytes::Bytes;
+use cookie::{Cookie, SameSite};
+use http::{header, HeaderValue, StatusCode};
+use humantime::parse_duration;
+use nanoid::nanoid;
+use pingora::proxy::Session;
+use pingora_limits::rate::Rate;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use strum::EnumString;
+use tracing::{debug, error};
+
+// the challenge should be solved in 5 minutes
+const CHALLENGE_TTL: u64 = 5 * 60;
+// the max count of consumed challenges which are recorded to avoid replay
+const MAX_CONSUMED_CHALLENGES: usize = 100_000;
+
+#[derive(PartialEq, Debug, Clone, Copy, EnumString)]
+#[strum(serialize_all = "snake_case")]
+enum ChallengeProvider {
+    Pow,
+    Turnstile,
+    Hcaptcha,
+}
+
+pub struct Challenge {
+    plugin_step: PluginStep,
+    provider: ChallengeProvider,
+    site_key: String,
+    secret_key: String,
+    // secret for signing challenge and clearance cookie
+    secret: String,
+    // leading zero bits of pow
+    difficulty: u32,
+    // challenge the client when the request count exceeds the threshold,
+    // zero means always challenge the client without clearance
+    threshold: isize,
+    rate: Rate,
+    cookie: String,
+    ttl: Duration,
+    verify_path: String,
+    // the consumed pow challenges, each challenge can be used only once
+    consumed: TtlLruLimit,
+    client: reqwest::Client,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for Challenge {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let step = get_step_conf(value);
+        let provider = get_str_conf(value, "provider");
+        let provider = if provider.is_empty() {
+            ChallengeProvider::Pow
+        } else {
+            provider.parse::<ChallengeProvider>().map_err(|e| {
+                Error::Invalid {
+                    category: PluginCategory::Challenge.to_string(),
+                    message: e.to_string(),
+                }
+            })?
+        };
+        let parse_duration_conf = |key: &str, default_value: Duration| {
+            let value = get_str_conf(value, key);
+            if value.is_empty() {
+                return Ok(default_value);
+            }
+            parse_duration(&value).map_err(|e| Error::Invalid {
+                category: PluginCategory::Challenge.to_string(),
+                message: e.to_string(),
+            })
+        };
+        let interval =
+            parse_duration_conf("interval", Duration::from_secs(10))?;
+        let ttl = parse_duration_conf("ttl", Duration::from_secs(3600))?;
+        let mut difficulty = get_int_conf(value, "difficulty") as u32;
+        if difficulty == 0 {
+            difficulty = 16;
+        }
+        let mut cookie = get_str_conf(value, "cookie");
+        if cookie.is_empty() {
+            cookie = "pingap_clearance".to_string();
+        }
+        let mut verify_path = get_str_conf(value, "verify_path");
+        if verify_path.is_empty() {
+            verify_path = "/.pingap/challenge".to_string();
+        }
+
+        let params = Self {
+            hash_value,
+            plugin_step: step,
+            provider,
+            site_key: get_str_conf(value, "site_key"),
+            secret_key: get_str_conf(value, "secret_key"),
+            secret: get_str_conf(value, "secret"),
+            difficulty: difficulty.min(32),
+            threshold: get_int_conf(value, "threshold") as isize,
+            rate: Rate::new(interval),
+            cookie,
+            ttl,
+            verify_path,
+            consumed: TtlLruLimit::new(
+                MAX_CONSUMED_CHALLENGES,
+                Duration::from_secs(CHALLENGE_TTL),
+                1,
+            ),
+            client: reqwest::Client::new(),
+        };
+        if params.secret.is_empty() {
+            return Err(Error::Invalid {
+                category: PluginCategory::Challenge.to_string(),
+                message: "Secret is not allowed empty".to_string(),
+            });
+        }
+        if params.provider != ChallengeProvider::Pow
+            && (params.site_key.is_empty() || params.secret_key.is_empty())
+        {
+            return Err(Error::Invalid {
+                category: PluginCategory::Challenge.to_string(),
+                message: "Site key and secret key are required for captcha"
+                    .to_string(),
+            });
+        }
+        if params.plugin_step != PluginStep::Request {
+            return Err(Error::Invalid {
+                category: PluginCategory::Challenge.to_string(),
+                message: "Challenge plugin should be executed at request step"
+                    .to_string(),
+            });
+        }
+        Ok(params)
+    }
+}
+
+fn sign(secret: &str, value: &str) -> String {
+    hex::encode(hmac_sha256::HMAC::mac(value.as_bytes(), secret.as_bytes()))
+}
+
+/// Verify the signature of value, it's compared in constant time
+/// to avoid leaking the expected signature by timing.
+fn verify(secret: &str, value: &str, signature: &str) -> bool {
+    let expected = sign(secret, value);
+    if expected.len() != signature.len() {
+        return false;
+    }
+    expected
+        .bytes()
+        .zip(signature.bytes())
+        .fold(0, |acc, (a, b)| acc | (a ^ b))
+        == 0
+}
+
+fn leading_zero_bits(buf: &[u8]) -> u32 {
+    let mut count = 0;
+    for b in buf.iter() {
+        if *b == 0 {
+            count += 8;
+            continue;
+        }
+        count += b.leading_zeros();
+        break;
+    }
+    count
+}
+
+/// Only relative path is allowed for redirect after challenge,
+/// `//host` and `/\host` are rejected because browsers treat them
+/// as the url of other host, and the control chars(e.g. tab) are
+/// rejected because browsers strip them.
+fn get_redirect(value: &str) -> String {
+    let value = urlencoding::decode(value).unwrap_or_default();
+    let mut chars = value.chars();
+    if chars.next() == Some('/')
+        && !matches!(chars.next(), Some('/' | '\\'))
+        && !value.chars().any(|c| c.is_control())
+    {
+        value.to_string()
+    } else {
+        "/".to_string()
+    }
+}
+
+#[derive(Deserialize)]
+struct SiteVerifyResult {
+    success: bool,
+}
+
+impl Challenge {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new challenge plugin");
+        Self::try_from(params)
+    }
+    /// Clearance value: {expired_at}.{signature}, it's bound to client ip.
+    fn new_clearance(&self, ip: &str) -> String {
+        let expired_at = util::now().as_secs() + self.ttl.as_secs();
+        let prefix = format!("{expired_at:x}");
+        let signature = sign(&self.secret, &format!("{prefix}.{ip}"));
+        format!("{prefix}.{signature}")
+    }
+    fn validate_clearance(&self, ip: &str, value: &str) -> bool {
+        let Some((prefix, signature)) = value.split_once('.') else {
+            return false;
+        };
+        let expired_at = u64::from_str_radix(prefix, 16).unwrap_or_default();
+        if expired_at < util::now().as_secs() {
+            return false;
+        }
+        verify(&self.secret, &format!("{prefix}.{ip}"), signature)
+    }
+    /// Challenge value: {created_at}.{random}.{signature}
+    fn new_pow_challenge(&self, ip: &str) -> String {
+        let prefix = format!("{:x}.{}", util::now().as_secs(), nanoid!(8));
+        let signature = sign(&self.secret, &format!("{prefix}.{ip}"));
+        format!("{prefix}.{signature}")
+    }
+    /// Validate the pow challenge, the challenge is consumed after it's
+    /// solved, so it can't be replayed in its ttl.
+    async fn validate_pow(
+        &self,
+        ip: &str,
+        challenge: &str,
+        nonce: &str,
+    ) -> bool {
+        let arr: Vec<&str> = challenge.split('.').collect();
+        if arr.len() != 3 {
+            return false;
+        }
+        let created_at = u64::from_str_radix(arr[0], 16).unwrap_or_default();
+        if created_at.saturating_add(CHALLENGE_TTL) < util::now().as_secs() {
+            return false;
+        }
+        if !verify(&self.secret, &format!("{}.{}.{ip}", arr[0], arr[1]), arr[2])
+        {
+            return false;
+        }
+        let mut hasher = Sha256::new();
+        hasher.update(challenge.as_bytes());
+        hasher.update(nonce.as_bytes());
+        if leading_zero_bits(&hasher.finalize()) < self.difficulty {
+            return false;
+        }
+        if !self.consumed.validate(challenge).await {
+            return false;
+        }
+        self.consumed.inc(challenge).await;
+        true
+    }
+    async fn validate_captcha(&self, ip: &str, token: &str) -> bool {
+        let url = match self.provider {
+            ChallengeProvider::Turnstile => {
+                "https://challenges.cloudflare.com/turnstile/v0/siteverify"
+            },
+            _ => "https://api.hcaptcha.com/siteverify",
+        };
+        let result = self
+            .client
+            .post(url)
+            .form(&[
+                ("secret", self.secret_key.as_str()),
+                ("response", token),
+                ("remoteip", ip),
+            ])
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await;
+        match result {
+            Ok(res) => res
+                .json::<SiteVerifyResult>()
+                .await
+                .map(|item| item.success)
+                .unwrap_or_default(),
+            Err(e) => {
+                error!(error = e.to_string(), "verify captcha fail");
+                false
+            },
+        }
+    }
+    fn get_challenge_html(&self, ip: &str, redirect: &str) -> String {
+        let redirect = urlencoding::encode(redirect);
+        let verify_path = &self.verify_path;
+        let body = match self.provider {
+            ChallengeProvider::Pow => {
+                let challenge = self.new_pow_challenge(ip);
+                let difficulty = self.difficulty;
+                format!(
+                    r###"<p>Checking your browser, please wait...</p>
+<script>
+(async () => {{
+  const challenge = "{challenge}";
+  const encoder = new TextEncoder();
+  const zeroBits = (buf) => {{
+    let count = 0;
+    for (const b of new Uint8Array(buf)) {{
+      if (b === 0) {{ count += 8; continue; }}
+      count += Math.clz32(b) - 24;
+      break;
+    }}
+    return count;
+  }};
+  for (let nonce = 0; ; nonce++) {{
+    const buf = await crypto.subtle.digest("SHA-256", encoder.encode(challenge + nonce));
+    if (zeroBits(buf) >= {difficulty}) {{
+      location.href = "{verify_path}?challenge=" + challenge + "&nonce=" + nonce + "&redirect={redirect}";
+      return;
+    }}
+  }}
+}})();
+</script>"###
+                )
+            },
+            ChallengeProvider::Turnstile => self.get_captcha_html(
+                "https://challenges.cloudflare.com/turnstile/v0/api.js",
+                "cf-turnstile",
+                &redirect,
+            ),
+            ChallengeProvider::Hcaptcha => self.get_captcha_html(
+                "https://js.hcaptcha.com/1/api.js",
+                "h-captcha",
+                &redirect,
+            ),
+        };
+        format!(
+            r###"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>Just a moment...</title></head>
+<body>
+{body}
+</body>
+</html>"###
+        )
+    }
+    fn get_captcha_html(
+        &self,
+        script: &str,
+        class: &str,
+        redirect: &str,
+    ) -> String {
+        let site_key = &self.site_key;
+        let verify_path = &self.verify_path;
+        format!(
+            r###"<script src="{script}" async defer></script>
+<div class="{class}" data-sitekey="{site_key}" data-callback="onChallengeSuccess"></div>
+<script>
+function onChallengeSuccess(token) {{
+  location.href = "{verify_path}?token=" + encodeURIComponent(token) + "&redirect={redirect}";
+}}
+</script>"###
+        )
+    }
+    async fn verify(
+        &self,
+        session: &Session,
+        ip: &str,
+    ) -> pingora::Result<HttpResponse> {
+        let req_header = session.req_header();
+        let redirect = get_redirect(
+            util::get_query_value(req_header, "redirect").unwrap_or_default(),
+        );
+        let passed = match self.provider {
+            ChallengeProvider::Pow => {
+                self.validate_pow(
+                    ip,
+                    util::get_query_value(req_header, "challenge")
+                        .unwrap_or_default(),
+                    util::get_query_value(req_header, "nonce")
+                        .unwrap_or_default(),
+                )
+                .await
+            },
+            _ => {
+                let token = urlencoding::decode(
+                    util::get_query_value(req_header, "token")
+                        .unwrap_or_default(),
+                )
+                .unwrap_or_default()
+                .to_string();
+                self.validate_captcha(ip, &token).await
+            },
+        };
+        if !passed {
+            return Ok(HttpResponse {
+                status: StatusCode::FORBIDDEN,
+                headers: Some(vec![HTTP_HEADER_NO_STORE.clone()]),
+                body: Bytes::from("Challenge is failed"),
+                ..Default::default()
+            });
+        }
+        let c = Cookie::build((&self.cookie, self.new_clearance(ip)))
+            .path("/")
+            .http_only(true)
+            .same_site(SameSite::Lax)
+            .max_age(cookie::time::Duration::seconds(self.ttl.as_secs() as i64))
+            .build();
+        let set_cookie = HeaderValue::from_str(&c.to_string())
+            .map_err(|e| util::new_internal_error(500, e.to_string()))?;
+        let location = HeaderValue::from_str(&redirect)
+            .map_err(|e| util::new_internal_error(400, e.to_string()))?;
+        Ok(HttpResponse {
+            status: StatusCode::FOUND,
+            headers: Some(vec![
+                HTTP_HEADER_NO_STORE.clone(),
+                (header::SET_COOKIE, set_cookie),
+                (header::LOCATION, location),
+            ]),
+            ..Default::default()
+        })
+    }
+}
+
+#[async_trait]
+impl Plugin for Challenge {
+    #[inline]
+    fn hash_key(&self) -> String {
+        self.hash_value.clone()
+    }
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut State,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        if step != self.plugin_step {
+            return Ok(None);
+        }
+        let ip = if let Some(ip) = &ctx.client_ip {
+            ip.to_string()
+        } else {
+            let ip = util::get_client_ip(session);
+            ctx.client_ip = Some(ip.clone());
+            ip
+        };
+        if session.req_header().uri.path() == self.verify_path {
+            return Ok(Some(self.verify(session, &ip).await?));
+        }
+        if let Some(value) =
+            util::get_cookie_value(session.req_header(), &self.cookie)
+        {
+            if self.validate_clearance(&ip, value) {
+                return Ok(None);
+            }
+        }
+        if self.threshold > 0 && self.rate.observe(&ip, 1) <= self.threshold {
+            return Ok(None);
+        }
+        let redirect = session
+            .req_header()
+            .uri
+            .path_and_query()
+            .map(|item| item.to_string())
+            .unwrap_or_else(|| "/".to_string());
+        Ok(Some(HttpResponse {
+            status: StatusCode::FORBIDDEN,
+            headers: Some(vec![
+                HTTP_HEADER_CONTENT_HTML.clone(),
+                HTTP_HEADER_NO_STORE.clone(),
+            ]),
+            body: Bytes::from(self.get_challenge_html(&ip, &redirect)),
+            ..Default::default()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_redirect, leading_zero_bits, sign, verify, Challenge};
+    use crate::config::{PluginConf, PluginStep};
+    use crate::plugin::Plugin;
+    use crate::state::State;
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use sha2::{Digest, Sha256};
+    use tokio_test::io::Builder;
+
+    #[test]
+    fn test_challenge_params() {
+        let params = Challenge::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+secret = "pingap"
+threshold = 10
+difficulty = 8
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(10, params.threshold);
+        assert_eq!(8, params.difficulty);
+        assert_eq!("pingap_clearance", params.cookie);
+        assert_eq!("/.pingap/challenge", params.verify_path);
+
+        let result = Challenge::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+secret = "pingap"
+provider = "turnstile"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin challenge invalid, message: Site key and secret key are required for captcha",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_challenge_utils() {
+        assert_eq!(16, leading_zero_bits(&[0, 0, 255]));
+        assert_eq!(11, leading_zero_bits(&[0, 16, 0]));
+        assert_eq!("/users?a=1", get_redirect("%2Fusers%3Fa%3D1"));
+        assert_eq!("/", get_redirect("https://github.com"));
+        assert_eq!("/", get_redirect("//github.com"));
+        assert_eq!("/", get_redirect("/\\github.com"));
+        assert_eq!("/", get_redirect("%2F%5Cgithub.com"));
+        assert_eq!("/", get_redirect("%2F%09%2Fgithub.com"));
+        assert_eq!("/", get_redirect(""));
+
+        let signature = sign("pingap", "abc");
+        assert_eq!(64, signature.len());
+        assert_eq!(true, verify("pingap", "abc", &signature));
+        assert_eq!(false, verify("pingap", "abd", &signature));
+        assert_eq!(false, verify("pingap", "abc", &signature[1..]));
+    }
+
+    #[tokio::test]
+    async fn test_challenge_pow() {
+        let params = Challenge::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+secret = "pingap"
+difficulty = 8
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let ip = "127.0.0.1";
+        let challenge = params.new_pow_challenge(ip);
+        let mut nonce = 0;
+        loop {
+            let mut hasher = Sha256::new();
+            hasher.update(challenge.as_bytes());
+            hasher.update(nonce.to_string().as_bytes());
+            if leading_zero_bits(&hasher.finalize()) >= 8 {
+                break;
+            }
+            nonce += 1;
+        }
+        assert_eq!(
+            false,
+            params
+                .validate_pow("127.0.0.2", &challenge, &nonce.to_string())
+                .await
+        );
+        assert_eq!(
+            true,
+            params
+                .validate_pow(ip, &challenge, &nonce.to_string())
+                .await
+        );
+        // the challenge can't be replayed
+        assert_eq!(
+            false,
+            params
+                .validate_pow(ip, &challenge, &nonce.to_string())
+                .await
+        );
+
+        let clearance = params.new_clearance(ip);
+        assert_eq!(true, params.validate_clearance(ip, &clearance));
+        assert_eq!(false, params.validate_clearance("127.0.0.2", &clearance));
+
+        // the created time is overflowed
+        let challenge = format!("{:x}.abc.def", u64::MAX);
+        assert_eq!(false, params.validate_pow(ip, &challenge, "0").await);
+    }
+
+    #[tokio::test]
+    async fn test_challenge() {
+        let params = Challenge::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+secret = "pingap"
+threshold = 1
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        let input_header =
+            "GET /vicanso/pingap HTTP/1.1\r\nX-Forwarded-For: 1.1.1.1\r\n\r\n";
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let result = params
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_none());
+
+        // exceed the threshold
+        let result = params
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(403, result.status.as_u16());
+
+        // with clearance
+        let input_header = format!(
+            "GET /vicanso/pingap HTTP/1.1\r\nX-Forwarded-For: 1.1.1.1\r\nCookie: pingap_clearance={}\r\n\r\n",
+            params.new_clearance("1.1.1.1")
+        );
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let result = params
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_none());
+    }
+}
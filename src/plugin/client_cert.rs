@@ -0,0 +1,332 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    get_bool_conf, get_hash_key, get_step_conf, get_str_slice_conf, Error,
+    Plugin, Result,
+};
+use crate::config::{PluginCategory, PluginConf, PluginStep};
+use crate::http_extra::HttpResponse;
+use crate::state::State;
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::StatusCode;
+use pingora::http::RequestHeader;
+use pingora::protocols::tls::SslDigest;
+use pingora::proxy::Session;
+use tracing::debug;
+
+const HEADER_FINGERPRINT: &str = "X-Client-Cert-Fingerprint";
+const HEADER_ORGANIZATION: &str = "X-Client-Cert-Organization";
+const HEADER_SERIAL: &str = "X-Client-Cert-Serial";
+
+/// Authorize the request by the attributes of client certificate,
+/// the listener should set `tls_client_ca` to enable mtls,
+/// so the issuer of certificate is verified by the tls handshake.
+pub struct ClientCert {
+    plugin_step: PluginStep,
+    // sha256 fingerprints of allowed certificates
+    fingerprints: Vec<String>,
+    // patterns of subject organization, e.g. `pingap*`
+    organizations: Vec<String>,
+    serial_numbers: Vec<String>,
+    forward_headers: bool,
+    hash_value: String,
+}
+
+/// Normalize the hex value, e.g. `AB:CD:01` -> `abcd01`.
+fn normalize_hex(value: &str) -> String {
+    value.replace(':', "").trim().to_lowercase()
+}
+
+/// Check the value matches pattern, the `*` prefix or suffix is wildcard.
+fn matched_pattern(pattern: &str, value: &str) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+    if let Some(suffix) = pattern.strip_prefix('*') {
+        return value.ends_with(suffix);
+    }
+    if let Some(prefix) = pattern.strip_suffix('*') {
+        return value.starts_with(prefix);
+    }
+    pattern == value
+}
+
+impl TryFrom<&PluginConf> for ClientCert {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let step = get_step_conf(value);
+
+        let params = Self {
+            hash_value,
+            plugin_step: step,
+            fingerprints: get_str_slice_conf(value, "fingerprints")
+                .iter()
+                .map(|item| normalize_hex(item))
+                .collect(),
+            organizations: get_str_slice_conf(value, "organizations"),
+            serial_numbers: get_str_slice_conf(value, "serial_numbers")
+                .iter()
+                .map(|item| normalize_hex(item))
+                .collect(),
+            forward_headers: get_bool_conf(value, "forward_headers"),
+        };
+        if params.plugin_step != PluginStep::Request {
+            return Err(Error::Invalid {
+                category: PluginCategory::ClientCert.to_string(),
+                message:
+                    "Client cert plugin should be executed at request step"
+                        .to_string(),
+            });
+        }
+        Ok(params)
+    }
+}
+
+impl ClientCert {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new client cert plugin");
+        Self::try_from(params)
+    }
+    /// Authorize the client certificate, all the configured rules
+    /// should be matched, the error response is returned if it's not allowed.
+    fn authorize(
+        &self,
+        ssl_digest: Option<&SslDigest>,
+    ) -> std::result::Result<(), HttpResponse> {
+        let Some(ssl_digest) =
+            ssl_digest.filter(|digest| !digest.cert_digest.is_empty())
+        else {
+            return Err(HttpResponse {
+                status: StatusCode::UNAUTHORIZED,
+                body: Bytes::from_static(b"Client certificate is required"),
+                ..Default::default()
+            });
+        };
+        let forbidden = || HttpResponse {
+            status: StatusCode::FORBIDDEN,
+            body: Bytes::from_static(b"Client certificate is not allowed"),
+            ..Default::default()
+        };
+        if !self.fingerprints.is_empty() {
+            let fingerprint = hex::encode(&ssl_digest.cert_digest);
+            if !self.fingerprints.contains(&fingerprint) {
+                return Err(forbidden());
+            }
+        }
+        if !self.organizations.is_empty() {
+            let organization =
+                ssl_digest.organization.as_deref().unwrap_or_default();
+            if !self
+                .organizations
+                .iter()
+                .any(|item| matched_pattern(item, organization))
+            {
+                return Err(forbidden());
+            }
+        }
+        if !self.serial_numbers.is_empty() {
+            let serial_number = normalize_hex(
+                ssl_digest.serial_number.as_deref().unwrap_or_default(),
+            );
+            if !self.serial_numbers.contains(&serial_number) {
+                return Err(forbidden());
+            }
+        }
+        Ok(())
+    }
+    /// Forward the details of client certificate to upstream,
+    /// the headers from client are always removed.
+    fn forward(
+        &self,
+        req_header: &mut RequestHeader,
+        ssl_digest: Option<&SslDigest>,
+    ) -> pingora::Result<()> {
+        for name in [HEADER_FINGERPRINT, HEADER_ORGANIZATION, HEADER_SERIAL] {
+            req_header.remove_header(name);
+        }
+        let Some(ssl_digest) = ssl_digest else {
+            return Ok(());
+        };
+        if !self.forward_headers || ssl_digest.cert_digest.is_empty() {
+            return Ok(());
+        }
+        req_header.insert_header(
+            HEADER_FINGERPRINT,
+            hex::encode(&ssl_digest.cert_digest),
+        )?;
+        if let Some(organization) = &ssl_digest.organization {
+            req_header.insert_header(HEADER_ORGANIZATION, organization)?;
+        }
+        if let Some(serial_number) = &ssl_digest.serial_number {
+            req_header.insert_header(HEADER_SERIAL, serial_number)?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Plugin for ClientCert {
+    #[inline]
+    fn hash_key(&self) -> String {
+        self.hash_value.clone()
+    }
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut State,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        if step != self.plugin_step {
+            return Ok(None);
+        }
+        let ssl_digest = session
+            .digest()
+            .and_then(|digest| digest.ssl_digest.clone());
+        if let Err(resp) = self.authorize(ssl_digest.as_deref()) {
+            return Ok(Some(resp));
+        }
+        if let Some(ssl_digest) = &ssl_digest {
+            // the fingerprint of certificate is used as client id
+            ctx.set_auth_identity(
+                None,
+                Some(&hex::encode(&ssl_digest.cert_digest)),
+            );
+        }
+        self.forward(session.req_header_mut(), ssl_digest.as_deref())?;
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ClientCert;
+    use crate::config::PluginConf;
+    use pingora::http::RequestHeader;
+    use pingora::protocols::tls::SslDigest;
+    use pretty_assertions::assert_eq;
+
+    fn new_ssl_digest() -> SslDigest {
+        SslDigest {
+            cipher: "TLS_AES_128_GCM_SHA256",
+            version: "TLSv1.3",
+            organization: Some("pingap-team".to_string()),
+            serial_number: Some("01AB".to_string()),
+            cert_digest: vec![0xab, 0xcd, 0x01],
+        }
+    }
+
+    #[test]
+    fn test_client_cert_params() {
+        let params = ClientCert::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+fingerprints = ["AB:CD:01"]
+organizations = ["pingap*"]
+serial_numbers = ["01:ab"]
+forward_headers = true
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(vec!["abcd01".to_string()], params.fingerprints);
+        assert_eq!(vec!["pingap*".to_string()], params.organizations);
+        assert_eq!(vec!["01ab".to_string()], params.serial_numbers);
+        assert_eq!(true, params.forward_headers);
+
+        let result = ClientCert::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+step = "response"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin client_cert invalid, message: Client cert plugin should be executed at request step",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_client_cert_authorize() {
+        let params = ClientCert::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+fingerprints = ["AB:CD:01"]
+organizations = ["pingap*"]
+forward_headers = true
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let ssl_digest = new_ssl_digest();
+        assert_eq!(true, params.authorize(Some(&ssl_digest)).is_ok());
+
+        let resp = params.authorize(None).err().unwrap();
+        assert_eq!(401, resp.status.as_u16());
+
+        let mut ssl_digest = new_ssl_digest();
+        ssl_digest.organization = Some("unknown".to_string());
+        let resp = params.authorize(Some(&ssl_digest)).err().unwrap();
+        assert_eq!(403, resp.status.as_u16());
+
+        let mut ssl_digest = new_ssl_digest();
+        ssl_digest.cert_digest = vec![0x01];
+        let resp = params.authorize(Some(&ssl_digest)).err().unwrap();
+        assert_eq!(403, resp.status.as_u16());
+
+        let mut req_header = RequestHeader::build("GET", b"/", None).unwrap();
+        req_header
+            .insert_header("X-Client-Cert-Fingerprint", "fake")
+            .unwrap();
+        params
+            .forward(&mut req_header, Some(&new_ssl_digest()))
+            .unwrap();
+        assert_eq!(
+            "abcd01",
+            req_header.headers.get("X-Client-Cert-Fingerprint").unwrap()
+        );
+        assert_eq!(
+            "pingap-team",
+            req_header
+                .headers
+                .get("X-Client-Cert-Organization")
+                .unwrap()
+        );
+        assert_eq!(
+            "01AB",
+            req_header.headers.get("X-Client-Cert-Serial").unwrap()
+        );
+
+        // the spoofed header is removed
+        let mut req_header = RequestHeader::build("GET", b"/", None).unwrap();
+        req_header
+            .insert_header("X-Client-Cert-Fingerprint", "fake")
+            .unwrap();
+        params.forward(&mut req_header, None).unwrap();
+        assert_eq!(
+            true,
+            req_header
+                .headers
+                .get("X-Client-Cert-Fingerprint")
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,312 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    get_bool_conf, get_hash_key, get_step_conf, get_str_slice_conf, Error,
+    Plugin, Result,
+};
+use crate::config::{PluginCategory, PluginConf, PluginStep};
+use crate::http_extra::HttpResponse;
+use crate::state::State;
+use async_trait::async_trait;
+use http::header;
+use http::{HeaderName, HeaderValue};
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use std::str::FromStr;
+use tracing::debug;
+
+// the legacy client hints, some image backends only support them
+static LEGACY_HINTS: [(&str, &str); 3] = [
+    ("sec-ch-dpr", "dpr"),
+    ("sec-ch-width", "width"),
+    ("sec-ch-viewport-width", "viewport-width"),
+];
+
+pub struct ClientHints {
+    plugin_step: PluginStep,
+    hints: Vec<HeaderName>,
+    accept_ch: HeaderValue,
+    critical: bool,
+    cache_key: bool,
+    legacy: bool,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for ClientHints {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let step = get_step_conf(value);
+
+        let mut hints = vec![];
+        for item in get_str_slice_conf(value, "hints").iter() {
+            let name = HeaderName::from_str(item.trim()).map_err(|e| {
+                Error::Invalid {
+                    category: PluginCategory::ClientHints.to_string(),
+                    message: e.to_string(),
+                }
+            })?;
+            hints.push(name);
+        }
+        if hints.is_empty() {
+            return Err(Error::Invalid {
+                category: PluginCategory::ClientHints.to_string(),
+                message: "Hints are not allowed empty".to_string(),
+            });
+        }
+        let accept_ch = hints
+            .iter()
+            .map(|item| item.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let accept_ch =
+            HeaderValue::from_str(&accept_ch).map_err(|e| Error::Invalid {
+                category: PluginCategory::ClientHints.to_string(),
+                message: e.to_string(),
+            })?;
+
+        let params = Self {
+            hash_value,
+            plugin_step: step,
+            hints,
+            accept_ch,
+            critical: get_bool_conf(value, "critical"),
+            cache_key: get_bool_conf(value, "cache_key"),
+            legacy: get_bool_conf(value, "legacy"),
+        };
+        if params.plugin_step != PluginStep::Request {
+            return Err(Error::Invalid {
+                category: PluginCategory::ClientHints.to_string(),
+                message:
+                    "Client hints plugin should be executed at request step"
+                        .to_string(),
+            });
+        }
+        Ok(params)
+    }
+}
+
+impl ClientHints {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new client hints plugin");
+        Self::try_from(params)
+    }
+    /// Get the cache prefix of the selected hints,
+    /// the empty value is also added to avoid the key conflict.
+    fn get_cache_prefix(&self, session: &Session) -> String {
+        let mut prefix = String::new();
+        for name in self.hints.iter() {
+            let value = session
+                .req_header()
+                .headers
+                .get(name)
+                .map(|v| v.to_str().unwrap_or_default())
+                .unwrap_or_default();
+            prefix.push_str(value.trim());
+            prefix.push(':');
+        }
+        prefix
+    }
+}
+
+#[async_trait]
+impl Plugin for ClientHints {
+    #[inline]
+    fn hash_key(&self) -> String {
+        self.hash_value.clone()
+    }
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut State,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        if step != self.plugin_step {
+            return Ok(None);
+        }
+        if self.legacy {
+            for (name, legacy_name) in LEGACY_HINTS.iter() {
+                let req_header = session.req_header();
+                if req_header.headers.contains_key(*legacy_name) {
+                    continue;
+                }
+                if let Some(value) = req_header.headers.get(*name).cloned() {
+                    let _ = session
+                        .req_header_mut()
+                        .insert_header(*legacy_name, value);
+                }
+            }
+        }
+        if self.cache_key {
+            let prefix = self.get_cache_prefix(session);
+            ctx.cache_prefix = if let Some(cache_prefix) = &ctx.cache_prefix {
+                Some(format!("{cache_prefix}{prefix}"))
+            } else {
+                Some(prefix)
+            };
+        }
+        Ok(None)
+    }
+    #[inline]
+    async fn handle_response(
+        &self,
+        step: PluginStep,
+        _session: &mut Session,
+        _ctx: &mut State,
+        upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<()> {
+        if step != PluginStep::Response {
+            return Ok(());
+        }
+        let _ = upstream_response
+            .insert_header("Accept-CH", self.accept_ch.clone());
+        if self.critical {
+            let _ = upstream_response
+                .insert_header("Critical-CH", self.accept_ch.clone());
+        }
+        // the response varies by the hints
+        if self.cache_key {
+            for name in self.hints.iter() {
+                let _ = upstream_response
+                    .append_header(header::VARY, name.as_str());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ClientHints;
+    use crate::config::{PluginConf, PluginStep};
+    use crate::plugin::Plugin;
+    use crate::state::State;
+    use pingora::http::ResponseHeader;
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use tokio_test::io::Builder;
+
+    #[test]
+    fn test_client_hints_params() {
+        let params = ClientHints::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+hints = ["Sec-CH-DPR", "Sec-CH-Width"]
+cache_key = true
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(2, params.hints.len());
+        assert_eq!(
+            "sec-ch-dpr, sec-ch-width",
+            params.accept_ch.to_str().unwrap()
+        );
+        assert_eq!(true, params.cache_key);
+
+        let result = ClientHints::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+cache_key = true
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin client_hints invalid, message: Hints are not allowed empty",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_client_hints() {
+        let params = ClientHints::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+hints = ["Sec-CH-DPR", "Sec-CH-Width"]
+cache_key = true
+critical = true
+legacy = true
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        let headers = ["Sec-CH-DPR: 2", "Sec-CH-Width: 800"].join("\r\n");
+        let input_header =
+            format!("GET /images/logo.png HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let mut ctx = State {
+            cache_prefix: Some("gzip:".to_string()),
+            ..Default::default()
+        };
+        let result = params
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_none());
+        assert_eq!("gzip:2:800:", ctx.cache_prefix.clone().unwrap());
+        assert_eq!(
+            "2",
+            session
+                .req_header()
+                .headers
+                .get("DPR")
+                .unwrap()
+                .to_str()
+                .unwrap()
+        );
+        assert_eq!(
+            "800",
+            session
+                .req_header()
+                .headers
+                .get("Width")
+                .unwrap()
+                .to_str()
+                .unwrap()
+        );
+
+        let mut upstream_response = ResponseHeader::build(200, None).unwrap();
+        params
+            .handle_response(
+                PluginStep::Response,
+                &mut session,
+                &mut ctx,
+                &mut upstream_response,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            "sec-ch-dpr, sec-ch-width",
+            upstream_response
+                .headers
+                .get("Accept-CH")
+                .unwrap()
+                .to_str()
+                .unwrap()
+        );
+        assert_eq!(
+            true,
+            upstream_response.headers.get("Critical-CH").is_some()
+        );
+        assert_eq!(2, upstream_response.headers.get_all("Vary").iter().count());
+    }
+}
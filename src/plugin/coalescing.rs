@@ -0,0 +1,488 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{get_hash_key, get_step_conf, get_str_conf, Error, Plugin, Result};
+use crate::config::{PluginCategory, PluginConf, PluginStep};
+use crate::http_extra::{HttpHeader, HttpResponse};
+use crate::state::{get_cache_key, ModifyResponseBody, State};
+use ahash::AHashMap;
+use async_trait::async_trait;
+use bytes::Bytes;
+use bytesize::ByteSize;
+use http::{header, Method, StatusCode};
+use humantime::parse_duration;
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::debug;
+
+const COALESCING_VARIABLE: &str = "coalescing_key";
+
+// hop by hop headers should not be shared to other requests
+const SKIP_HEADERS: [header::HeaderName; 4] = [
+    header::CONNECTION,
+    header::CONTENT_LENGTH,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+];
+
+#[derive(Debug)]
+struct CoalescedResponse {
+    status: StatusCode,
+    headers: Vec<HttpHeader>,
+    body: Bytes,
+}
+
+type InflightRequests = Arc<Mutex<AHashMap<String, Arc<InflightRequest>>>>;
+
+struct InflightRequest {
+    // the response is sent to waiters after the leader request is done,
+    // the waiters get `None` if the sender is dropped without response.
+    sender: watch::Sender<Option<Arc<CoalescedResponse>>>,
+    // the status and headers of shareable upstream response
+    header: Mutex<Option<(StatusCode, Vec<HttpHeader>)>>,
+}
+
+/// The body handler of leader request, it shares the response
+/// to waiters and removes the in-flight request when dropped.
+struct CoalescingBody {
+    key: String,
+    max_body_size: usize,
+    inflight: Arc<InflightRequest>,
+    requests: InflightRequests,
+}
+
+impl ModifyResponseBody for CoalescingBody {
+    fn handle(&self, data: Bytes) -> Bytes {
+        let header = self
+            .inflight
+            .header
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+        if let Some((status, headers)) = header {
+            if data.len() <= self.max_body_size {
+                self.inflight.sender.send_replace(Some(Arc::new(
+                    CoalescedResponse {
+                        status,
+                        headers,
+                        body: data.clone(),
+                    },
+                )));
+            }
+        }
+        data
+    }
+}
+
+impl Drop for CoalescingBody {
+    fn drop(&mut self) {
+        let mut requests =
+            self.requests.lock().unwrap_or_else(|e| e.into_inner());
+        // the key may be used by a new leader
+        if requests
+            .get(&self.key)
+            .map(|item| Arc::ptr_eq(item, &self.inflight))
+            .unwrap_or_default()
+        {
+            requests.remove(&self.key);
+        }
+    }
+}
+
+pub struct Coalescing {
+    plugin_step: PluginStep,
+    // max waiting time of the identical requests
+    timeout: Duration,
+    // the response is not shared if its body is too large
+    max_body_size: usize,
+    requests: InflightRequests,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for Coalescing {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let step = get_step_conf(value);
+
+        let timeout = get_str_conf(value, "timeout");
+        let timeout = if !timeout.is_empty() {
+            parse_duration(&timeout).map_err(|e| Error::Invalid {
+                category: PluginCategory::Coalescing.to_string(),
+                message: e.to_string(),
+            })?
+        } else {
+            Duration::from_secs(5)
+        };
+        let max_body_size = get_str_conf(value, "max_body_size");
+        let max_body_size = if !max_body_size.is_empty() {
+            ByteSize::from_str(&max_body_size)
+                .map_err(|e| Error::Invalid {
+                    category: PluginCategory::Coalescing.to_string(),
+                    message: e.to_string(),
+                })?
+                .as_u64() as usize
+        } else {
+            1024 * 1024
+        };
+
+        let params = Self {
+            hash_value,
+            plugin_step: step,
+            timeout,
+            max_body_size,
+            requests: Arc::new(Mutex::new(AHashMap::new())),
+        };
+        if ![PluginStep::Request, PluginStep::ProxyUpstream]
+            .contains(&params.plugin_step)
+        {
+            return Err(Error::Invalid {
+                category: PluginCategory::Coalescing.to_string(),
+                message: "Coalescing plugin should be executed at request or proxy upstream step".to_string(),
+            });
+        }
+        Ok(params)
+    }
+}
+
+impl Coalescing {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new coalescing plugin");
+        Self::try_from(params)
+    }
+}
+
+/// Get the coalescing key of request, the personalized request
+/// (with authorization or cookie) is not coalesced.
+fn get_coalescing_key(session: &Session, ctx: &State) -> Option<String> {
+    let req_header = session.req_header();
+    if req_header.method != Method::GET
+        || req_header.headers.contains_key(header::AUTHORIZATION)
+        || req_header.headers.contains_key(header::COOKIE)
+    {
+        return None;
+    }
+    let key = get_cache_key(ctx, "GET", &req_header.uri);
+    // the upstream response may be encoded by accept encoding
+    let accept_encoding = req_header
+        .headers
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    Some(format!(
+        "{}:{}{accept_encoding}",
+        key.namespace(),
+        key.primary_key()
+    ))
+}
+
+/// The response can be shared if it is not private for the request.
+fn is_shareable(upstream_response: &ResponseHeader) -> bool {
+    if upstream_response.headers.contains_key(header::SET_COOKIE) {
+        return false;
+    }
+    let cache_control = upstream_response
+        .headers
+        .get(header::CACHE_CONTROL)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    !cache_control.contains("private")
+}
+
+#[async_trait]
+impl Plugin for Coalescing {
+    #[inline]
+    fn hash_key(&self) -> String {
+        self.hash_value.clone()
+    }
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut State,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        if step != self.plugin_step {
+            return Ok(None);
+        }
+        let Some(key) = get_coalescing_key(session, ctx) else {
+            return Ok(None);
+        };
+        let mut receiver = {
+            let mut requests =
+                self.requests.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(inflight) = requests.get(&key) {
+                inflight.sender.subscribe()
+            } else {
+                // the first request is the leader, it will be proxied
+                // to upstream and the response will be shared
+                let (sender, _) = watch::channel(None);
+                let inflight = Arc::new(InflightRequest {
+                    sender,
+                    header: Mutex::new(None),
+                });
+                requests.insert(key.clone(), inflight.clone());
+                ctx.modify_response_body = Some(Box::new(CoalescingBody {
+                    key: key.clone(),
+                    max_body_size: self.max_body_size,
+                    inflight,
+                    requests: self.requests.clone(),
+                }));
+                ctx.add_variable(COALESCING_VARIABLE, &key);
+                return Ok(None);
+            }
+        };
+        // proxy to upstream if the leader request fails or timeout
+        let result = tokio::time::timeout(
+            self.timeout,
+            receiver.wait_for(|value| value.is_some()),
+        )
+        .await;
+        let Ok(Ok(value)) = result else {
+            return Ok(None);
+        };
+        let Some(resp) = value.as_ref() else {
+            return Ok(None);
+        };
+        Ok(Some(HttpResponse {
+            status: resp.status,
+            headers: Some(resp.headers.clone()),
+            body: resp.body.clone(),
+            ..Default::default()
+        }))
+    }
+    #[inline]
+    async fn handle_response(
+        &self,
+        step: PluginStep,
+        _session: &mut Session,
+        ctx: &mut State,
+        upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<()> {
+        if step != PluginStep::Response {
+            return Ok(());
+        }
+        let Some(key) = ctx.variables.as_ref().and_then(|variables| {
+            variables.get(&format!("${COALESCING_VARIABLE}")).cloned()
+        }) else {
+            return Ok(());
+        };
+        let inflight = self
+            .requests
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&key)
+            .cloned();
+        let Some(inflight) = inflight else {
+            return Ok(());
+        };
+        let content_length = upstream_response
+            .headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or_default();
+        if !is_shareable(upstream_response)
+            || content_length > self.max_body_size
+        {
+            // drop the body handler to wake up the waiters,
+            // they will be proxied to upstream
+            ctx.modify_response_body = None;
+            return Ok(());
+        }
+        let headers = upstream_response
+            .headers
+            .iter()
+            .filter(|(name, _)| !SKIP_HEADERS.contains(name))
+            .map(|(name, value)| (name.to_owned(), value.to_owned()))
+            .collect();
+        *inflight.header.lock().unwrap_or_else(|e| e.into_inner()) =
+            Some((upstream_response.status, headers));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Coalescing;
+    use crate::config::{PluginConf, PluginStep};
+    use crate::plugin::Plugin;
+    use crate::state::State;
+    use bytes::Bytes;
+    use pingora::http::ResponseHeader;
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use std::sync::Arc;
+    use tokio_test::io::Builder;
+
+    async fn new_session(input_header: &str) -> Session {
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        session
+    }
+
+    fn new_coalescing() -> Arc<Coalescing> {
+        Arc::new(
+            Coalescing::new(
+                &toml::from_str::<PluginConf>(
+                    r###"
+step = "proxy_upstream"
+timeout = "1s"
+max_body_size = "10kb"
+"###,
+                )
+                .unwrap(),
+            )
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_coalescing_params() {
+        let params = new_coalescing();
+        assert_eq!(PluginStep::ProxyUpstream, params.plugin_step);
+        assert_eq!(1, params.timeout.as_secs());
+        assert_eq!(10_000, params.max_body_size);
+
+        let result = Coalescing::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+step = "response"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin coalescing invalid, message: Coalescing plugin should be executed at request or proxy upstream step",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_coalescing() {
+        let params = new_coalescing();
+        let input_header = "GET /vicanso/pingap?size=1 HTTP/1.1\r\n\r\n";
+
+        // the leader request
+        let mut session = new_session(input_header).await;
+        let mut ctx = State::default();
+        let result = params
+            .handle_request(PluginStep::ProxyUpstream, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_none());
+        assert_eq!(true, ctx.modify_response_body.is_some());
+
+        // the waiter request
+        let waiter = tokio::spawn({
+            let params = params.clone();
+            async move {
+                let mut session = new_session(input_header).await;
+                params
+                    .handle_request(
+                        PluginStep::ProxyUpstream,
+                        &mut session,
+                        &mut State::default(),
+                    )
+                    .await
+                    .unwrap()
+            }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+
+        let mut upstream_response = ResponseHeader::build(200, None).unwrap();
+        upstream_response
+            .insert_header("Content-Type", "application/json")
+            .unwrap();
+        upstream_response
+            .insert_header("Content-Length", "2")
+            .unwrap();
+        params
+            .handle_response(
+                PluginStep::Response,
+                &mut session,
+                &mut ctx,
+                &mut upstream_response,
+            )
+            .await
+            .unwrap();
+        let body = ctx
+            .modify_response_body
+            .as_ref()
+            .unwrap()
+            .handle(Bytes::from_static(b"{}"));
+        assert_eq!(b"{}", body.as_ref());
+
+        let resp = waiter.await.unwrap().unwrap();
+        assert_eq!(200, resp.status.as_u16());
+        assert_eq!(b"{}", resp.body.as_ref());
+        assert_eq!(
+            r#"Some([("content-type", "application/json")])"#,
+            format!("{:?}", resp.headers)
+        );
+
+        // the in-flight request is removed after the leader is done
+        drop(ctx);
+        assert_eq!(true, params.requests.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_coalescing_leader_fail() {
+        let params = new_coalescing();
+        let input_header = "GET /vicanso/pingap HTTP/1.1\r\n\r\n";
+
+        let mut session = new_session(input_header).await;
+        let mut ctx = State::default();
+        params
+            .handle_request(PluginStep::ProxyUpstream, &mut session, &mut ctx)
+            .await
+            .unwrap();
+
+        let waiter = tokio::spawn({
+            let params = params.clone();
+            async move {
+                let mut session = new_session(input_header).await;
+                params
+                    .handle_request(
+                        PluginStep::ProxyUpstream,
+                        &mut session,
+                        &mut State::default(),
+                    )
+                    .await
+                    .unwrap()
+            }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        // the leader request fails without response
+        drop(ctx);
+        assert_eq!(true, waiter.await.unwrap().is_none());
+
+        // the personalized request is not coalesced
+        let mut session = new_session(
+            "GET /vicanso/pingap HTTP/1.1\r\nCookie: uid=1\r\n\r\n",
+        )
+        .await;
+        let mut ctx = State::default();
+        params
+            .handle_request(PluginStep::ProxyUpstream, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(true, ctx.modify_response_body.is_none());
+    }
+}
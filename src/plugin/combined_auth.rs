@@ -179,7 +179,7 @@ impl Plugin for CombinedAuth {
         &self,
         step: PluginStep,
         session: &mut Session,
-        _ctx: &mut State,
+        ctx: &mut State,
     ) -> pingora::Result<Option<HttpResponse>> {
         if step != self.plugin_step {
             return Ok(None);
@@ -192,6 +192,10 @@ impl Plugin for CombinedAuth {
                 ..Default::default()
             }));
         }
+        ctx.set_auth_identity(
+            None,
+            util::get_query_value(session.req_header(), "app_id"),
+        );
 
         Ok(None)
     }
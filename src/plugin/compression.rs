@@ -12,14 +12,21 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{get_bool_conf, get_hash_key, get_int_conf, Error, Plugin, Result};
-use crate::config::{PluginConf, PluginStep};
+use super::{
+    get_bool_conf, get_hash_key, get_int_conf, get_str_conf,
+    get_str_slice_conf, Error, Plugin, Result,
+};
+use crate::config::{PluginCategory, PluginConf, PluginStep};
 use crate::http_extra::HttpResponse;
 use crate::state::State;
 use async_trait::async_trait;
+use bytesize::ByteSize;
+use http::header;
+use pingora::http::ResponseHeader;
 use pingora::modules::http::compression::ResponseCompression;
 use pingora::protocols::http::compression::Algorithm;
 use pingora::proxy::Session;
+use std::str::FromStr;
 use tracing::debug;
 
 const ZSTD: &str = "zstd";
@@ -32,10 +39,33 @@ pub struct Compression {
     zstd_level: u32,
     support_compression: bool,
     decompression: Option<bool>,
+    // the content types of response to be compressed, e.g. `text/*`,
+    // all types are compressed if it's empty
+    content_types: Vec<String>,
+    // the response is not compressed if its content length is less than it
+    min_length: usize,
     plugin_step: PluginStep,
     hash_value: String,
 }
 
+/// Check the content type matches the patterns, the pattern ends with
+/// `/*` matches all sub types, e.g. `text/*`.
+fn is_matched_content_type(patterns: &[String], content_type: &str) -> bool {
+    let content_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+    patterns.iter().any(|pattern| {
+        if let Some(prefix) = pattern.strip_suffix('*') {
+            content_type.starts_with(prefix)
+        } else {
+            &content_type == pattern
+        }
+    })
+}
+
 impl TryFrom<&PluginConf> for Compression {
     type Error = Error;
     fn try_from(value: &PluginConf) -> Result<Self> {
@@ -48,6 +78,17 @@ impl TryFrom<&PluginConf> for Compression {
         let br_level = get_int_conf(value, "br_level") as u32;
         let zstd_level = get_int_conf(value, "zstd_level") as u32;
         let support_compression = gzip_level + br_level + zstd_level > 0;
+        let min_length = get_str_conf(value, "min_length");
+        let min_length = if min_length.is_empty() {
+            0
+        } else {
+            ByteSize::from_str(&min_length)
+                .map_err(|e| Error::Invalid {
+                    category: PluginCategory::Compression.to_string(),
+                    message: e.to_string(),
+                })?
+                .as_u64() as usize
+        };
 
         let params = Self {
             hash_value,
@@ -55,6 +96,11 @@ impl TryFrom<&PluginConf> for Compression {
             br_level,
             zstd_level,
             decompression,
+            content_types: get_str_slice_conf(value, "content_types")
+                .iter()
+                .map(|item| item.trim().to_lowercase())
+                .collect(),
+            min_length,
             support_compression,
             plugin_step: PluginStep::EarlyRequest,
         };
@@ -68,6 +114,31 @@ impl Compression {
         debug!(params = params.to_string(), "new compression plugin");
         Self::try_from(params)
     }
+    /// Check the response matches the content types and min length,
+    /// the response without content length is compressible.
+    fn is_compressible(&self, resp: &ResponseHeader) -> bool {
+        if !self.content_types.is_empty() {
+            let content_type = resp
+                .headers
+                .get(header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default();
+            if !is_matched_content_type(&self.content_types, content_type) {
+                return false;
+            }
+        }
+        if self.min_length > 0 {
+            let content_length = resp
+                .headers
+                .get(header::CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<usize>().ok());
+            if let Some(content_length) = content_length {
+                return content_length >= self.min_length;
+            }
+        }
+        true
+    }
 }
 
 #[async_trait]
@@ -134,13 +205,39 @@ impl Plugin for Compression {
         }
         Ok(None)
     }
+    #[inline]
+    async fn handle_response(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        _ctx: &mut State,
+        upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<()> {
+        if step != PluginStep::Response
+            || (self.content_types.is_empty() && self.min_length == 0)
+        {
+            return Ok(());
+        }
+        if self.is_compressible(upstream_response) {
+            return Ok(());
+        }
+        // disable the compression of this response
+        if let Some(c) = session
+            .downstream_modules_ctx
+            .get_mut::<ResponseCompression>()
+        {
+            c.adjust_level(0);
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Compression;
+    use super::{is_matched_content_type, Compression};
     use crate::state::State;
     use crate::{config::PluginConf, config::PluginStep, plugin::Plugin};
+    use pingora::http::ResponseHeader;
     use pingora::modules::http::compression::{
         ResponseCompression, ResponseCompressionBuilder,
     };
@@ -169,6 +266,40 @@ zstd_level = 6
         assert_eq!(6, params.zstd_level);
     }
 
+    #[test]
+    fn test_compressible() {
+        let params = Compression::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+gzip_level = 9
+content_types = ["text/*", "application/json"]
+min_length = "1kb"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(1000, params.min_length);
+        let patterns = &params.content_types;
+        assert_eq!(
+            true,
+            is_matched_content_type(patterns, "text/html; charset=utf-8")
+        );
+        assert_eq!(true, is_matched_content_type(patterns, "Application/JSON"));
+        assert_eq!(false, is_matched_content_type(patterns, "image/png"));
+
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header("Content-Type", "application/json")
+            .unwrap();
+        assert_eq!(true, params.is_compressible(&resp));
+        resp.insert_header("Content-Length", "100").unwrap();
+        assert_eq!(false, params.is_compressible(&resp));
+        resp.insert_header("Content-Length", "2048").unwrap();
+        assert_eq!(true, params.is_compressible(&resp));
+        resp.insert_header("Content-Type", "image/png").unwrap();
+        assert_eq!(false, params.is_compressible(&resp));
+    }
+
     #[tokio::test]
     async fn test_compression() {
         let compression = Compression::new(
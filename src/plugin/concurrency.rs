@@ -0,0 +1,285 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    get_hash_key, get_int_conf, get_step_conf, get_str_conf, Error, Plugin,
+    Result,
+};
+use crate::config::{PluginCategory, PluginConf, PluginStep};
+use crate::http_extra::HttpResponse;
+use crate::state::State;
+use ahash::AHashMap;
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::{header, HeaderValue, StatusCode};
+use humantime::parse_duration;
+use pingora::proxy::Session;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tracing::debug;
+
+#[derive(PartialEq, Debug)]
+pub enum ConcurrencyScope {
+    Location,
+    Upstream,
+}
+
+struct ConcurrencyQueue {
+    semaphore: Arc<Semaphore>,
+    // the count of waiting requests
+    waiting: AtomicUsize,
+}
+
+pub struct Concurrency {
+    plugin_step: PluginStep,
+    scope: ConcurrencyScope,
+    max: usize,
+    // max waiting requests of the queue
+    queue: usize,
+    timeout: Duration,
+    retry_after: HeaderValue,
+    queues: Mutex<AHashMap<String, Arc<ConcurrencyQueue>>>,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for Concurrency {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let step = get_step_conf(value);
+
+        let scope = match get_str_conf(value, "scope").as_str() {
+            "upstream" => ConcurrencyScope::Upstream,
+            _ => ConcurrencyScope::Location,
+        };
+        let timeout = get_str_conf(value, "timeout");
+        let timeout = if !timeout.is_empty() {
+            parse_duration(&timeout).map_err(|e| Error::Invalid {
+                category: PluginCategory::Concurrency.to_string(),
+                message: e.to_string(),
+            })?
+        } else {
+            Duration::from_secs(10)
+        };
+        let retry_after = get_int_conf(value, "retry_after").max(1);
+
+        let params = Self {
+            hash_value,
+            plugin_step: step,
+            scope,
+            max: get_int_conf(value, "max").max(0) as usize,
+            queue: get_int_conf(value, "queue").max(0) as usize,
+            timeout,
+            retry_after: HeaderValue::from(retry_after),
+            queues: Mutex::new(AHashMap::new()),
+        };
+        if params.max == 0 {
+            return Err(Error::Invalid {
+                category: PluginCategory::Concurrency.to_string(),
+                message: "Max should be greater than 0".to_string(),
+            });
+        }
+        if ![PluginStep::Request, PluginStep::ProxyUpstream]
+            .contains(&params.plugin_step)
+        {
+            return Err(Error::Invalid {
+                category: PluginCategory::Concurrency.to_string(),
+                message: "Concurrency plugin should be executed at request or proxy upstream step".to_string(),
+            });
+        }
+        Ok(params)
+    }
+}
+
+impl Concurrency {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new concurrency plugin");
+        Self::try_from(params)
+    }
+    fn get_queue(&self, ctx: &State) -> Arc<ConcurrencyQueue> {
+        let key = ctx
+            .location
+            .as_ref()
+            .map(|location| match self.scope {
+                ConcurrencyScope::Upstream => location.upstream.clone(),
+                _ => location.name.clone(),
+            })
+            .unwrap_or_default();
+        let mut queues = self.queues.lock().unwrap_or_else(|e| e.into_inner());
+        queues
+            .entry(key)
+            .or_insert_with(|| {
+                Arc::new(ConcurrencyQueue {
+                    semaphore: Arc::new(Semaphore::new(self.max)),
+                    waiting: AtomicUsize::new(0),
+                })
+            })
+            .clone()
+    }
+    fn new_unavailable_response(&self, message: &str) -> HttpResponse {
+        HttpResponse {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            headers: Some(vec![(
+                header::RETRY_AFTER,
+                self.retry_after.clone(),
+            )]),
+            body: Bytes::from(message.to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+#[async_trait]
+impl Plugin for Concurrency {
+    #[inline]
+    fn hash_key(&self) -> String {
+        self.hash_value.clone()
+    }
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        _session: &mut Session,
+        ctx: &mut State,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        if step != self.plugin_step {
+            return Ok(None);
+        }
+        let queue = self.get_queue(ctx);
+        // acquire the permit directly if not over limit
+        if let Ok(permit) = queue.semaphore.clone().try_acquire_owned() {
+            ctx.concurrency_permit = Some(permit);
+            return Ok(None);
+        }
+        if queue.waiting.fetch_add(1, Ordering::Relaxed) >= self.queue {
+            queue.waiting.fetch_sub(1, Ordering::Relaxed);
+            return Ok(Some(
+                self.new_unavailable_response("Concurrency queue is full"),
+            ));
+        }
+        let result = tokio::time::timeout(
+            self.timeout,
+            queue.semaphore.clone().acquire_owned(),
+        )
+        .await;
+        queue.waiting.fetch_sub(1, Ordering::Relaxed);
+        match result {
+            Ok(Ok(permit)) => {
+                ctx.concurrency_permit = Some(permit);
+                Ok(None)
+            },
+            _ => Ok(Some(
+                self.new_unavailable_response("Concurrency queue timeout"),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Concurrency, ConcurrencyScope};
+    use crate::config::{PluginConf, PluginStep};
+    use crate::plugin::Plugin;
+    use crate::state::State;
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use tokio_test::io::Builder;
+
+    #[test]
+    fn test_concurrency_params() {
+        let params = Concurrency::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+scope = "upstream"
+max = 10
+queue = 100
+timeout = "3s"
+retry_after = 5
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(ConcurrencyScope::Upstream, params.scope);
+        assert_eq!(10, params.max);
+        assert_eq!(100, params.queue);
+        assert_eq!(3, params.timeout.as_secs());
+        assert_eq!("5", params.retry_after.to_str().unwrap());
+
+        let result = Concurrency::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+queue = 100
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin concurrency invalid, message: Max should be greater than 0",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_concurrency() {
+        let params = Concurrency::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+max = 1
+queue = 1
+timeout = "50ms"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let input_header = "GET /vicanso/pingap HTTP/1.1\r\n\r\n";
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+
+        let mut ctx = State::default();
+        let result = params
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_none());
+        assert_eq!(true, ctx.concurrency_permit.is_some());
+
+        // wait in queue until timeout
+        let result = params
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(503, result.unwrap().status.as_u16());
+
+        // the permit is released
+        ctx.concurrency_permit = None;
+        let result = params
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_none());
+    }
+}
@@ -0,0 +1,422 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{get_str_conf, get_str_slice_conf, Error, Plugin, Result};
+use crate::config::{PluginConf, PluginStep};
+use crate::http_extra::HttpResponse;
+use crate::state::State;
+use crate::util;
+use ahash::AHashMap;
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::{HeaderName, Method};
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use regex::Regex;
+use std::str::FromStr;
+use std::sync::Arc;
+
+const MATCH_CONF: &str = "match";
+
+#[derive(Debug)]
+enum HeaderMatcher {
+    Exists,
+    Equal(String),
+    Regex(Regex),
+}
+
+/// The match condition of plugin, the plugin is only executed for the
+/// request matches all conditions, e.g.
+/// ```toml
+/// [plugins.auth.match]
+/// methods = ["POST", "PUT"]
+/// path = "^/api"
+/// path_prefix = "/api"
+/// headers = ["X-Debug", "X-Env:prod", "User-Agent:~curl"]
+/// ip_list = ["192.168.0.0/16"]
+/// ```
+#[derive(Debug)]
+pub struct Condition {
+    methods: Vec<Method>,
+    path: Option<Regex>,
+    path_prefix: String,
+    headers: Vec<(HeaderName, HeaderMatcher)>,
+    ip_rules: Option<util::IpRules>,
+}
+
+fn new_invalid_error(message: String) -> Error {
+    Error::Invalid {
+        category: MATCH_CONF.to_string(),
+        message,
+    }
+}
+
+impl Condition {
+    /// Create the condition from `match` block of plugin config,
+    /// it returns none if the block is not set.
+    pub fn new(conf: &PluginConf) -> Result<Option<Self>> {
+        let Some(value) = conf.get(MATCH_CONF) else {
+            return Ok(None);
+        };
+        let Some(value) = value.as_table() else {
+            return Err(new_invalid_error(
+                "match should be a table".to_string(),
+            ));
+        };
+        let mut methods = vec![];
+        for item in get_str_slice_conf(value, "methods").iter() {
+            let method =
+                Method::from_str(&item.to_uppercase()).map_err(|e| {
+                    new_invalid_error(format!("method({item}) is invalid, {e}"))
+                })?;
+            methods.push(method);
+        }
+        let path = get_str_conf(value, "path");
+        let path = if path.is_empty() {
+            None
+        } else {
+            Some(Regex::new(&path).map_err(|e| {
+                new_invalid_error(format!("path({path}) is invalid, {e}"))
+            })?)
+        };
+        let mut headers = vec![];
+        for item in get_str_slice_conf(value, "headers").iter() {
+            let (name, value) = item
+                .split_once(':')
+                .map(|(name, value)| (name.trim(), Some(value.trim())))
+                .unwrap_or((item.trim(), None));
+            let name = HeaderName::from_str(name).map_err(|e| {
+                new_invalid_error(format!("header({item}) is invalid, {e}"))
+            })?;
+            let matcher = match value {
+                None => HeaderMatcher::Exists,
+                Some(value) => {
+                    if let Some(reg) = value.strip_prefix('~') {
+                        HeaderMatcher::Regex(Regex::new(reg).map_err(|e| {
+                            new_invalid_error(format!(
+                                "header({item}) is invalid, {e}"
+                            ))
+                        })?)
+                    } else {
+                        HeaderMatcher::Equal(value.to_string())
+                    }
+                },
+            };
+            headers.push((name, matcher));
+        }
+        let ip_list = get_str_slice_conf(value, "ip_list");
+        let ip_rules = if ip_list.is_empty() {
+            None
+        } else {
+            Some(util::IpRules::new(&ip_list))
+        };
+        Ok(Some(Self {
+            methods,
+            path,
+            path_prefix: get_str_conf(value, "path_prefix"),
+            headers,
+            ip_rules,
+        }))
+    }
+    /// Check the request matches all conditions.
+    pub fn matched(&self, session: &Session, ctx: &mut State) -> bool {
+        let req_header = session.req_header();
+        if !self.methods.is_empty()
+            && !self.methods.contains(&req_header.method)
+        {
+            return false;
+        }
+        let path = req_header.uri.path();
+        if !self.path_prefix.is_empty() && !path.starts_with(&self.path_prefix)
+        {
+            return false;
+        }
+        if let Some(reg) = &self.path {
+            if !reg.is_match(path) {
+                return false;
+            }
+        }
+        for (name, matcher) in self.headers.iter() {
+            let Some(value) = req_header.headers.get(name) else {
+                return false;
+            };
+            let value = value.to_str().unwrap_or_default();
+            let matched = match matcher {
+                HeaderMatcher::Exists => true,
+                HeaderMatcher::Equal(expected) => value == expected,
+                HeaderMatcher::Regex(reg) => reg.is_match(value),
+            };
+            if !matched {
+                return false;
+            }
+        }
+        if let Some(ip_rules) = &self.ip_rules {
+            let ip = if let Some(ip) = &ctx.client_ip {
+                ip.to_string()
+            } else {
+                let ip = util::get_client_ip(session);
+                ctx.client_ip = Some(ip.clone());
+                ip
+            };
+            if !ip_rules.matched(&ip).unwrap_or_default() {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// The plugin is only executed if the request matches the condition.
+pub struct ConditionalPlugin {
+    name: String,
+    condition: Condition,
+    plugin: Arc<dyn Plugin>,
+}
+
+impl ConditionalPlugin {
+    pub fn new(
+        name: &str,
+        condition: Condition,
+        plugin: Arc<dyn Plugin>,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            condition,
+            plugin,
+        }
+    }
+    /// Check the condition once per step of request, the result is reused
+    /// by the body chunks of the step. It's checked again in the later step,
+    /// because the request may be rewritten, e.g. the path is rewritten
+    /// after the early request step.
+    #[inline]
+    fn matched(
+        &self,
+        step: PluginStep,
+        session: &Session,
+        ctx: &mut State,
+    ) -> bool {
+        let key = format!("{}:{step}", self.name);
+        if let Some(matched) = ctx
+            .plugin_conditions
+            .as_ref()
+            .and_then(|conditions| conditions.get(&key))
+        {
+            return *matched;
+        }
+        let matched = self.condition.matched(session, ctx);
+        ctx.plugin_conditions
+            .get_or_insert_with(AHashMap::new)
+            .insert(key, matched);
+        matched
+    }
+}
+
+#[async_trait]
+impl Plugin for ConditionalPlugin {
+    #[inline]
+    fn hash_key(&self) -> String {
+        self.plugin.hash_key()
+    }
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut State,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        if !self.matched(step, session, ctx) {
+            return Ok(None);
+        }
+        self.plugin.handle_request(step, session, ctx).await
+    }
+    #[inline]
+    async fn handle_response(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut State,
+        upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<()> {
+        if !self.matched(step, session, ctx) {
+            return Ok(());
+        }
+        self.plugin
+            .handle_response(step, session, ctx, upstream_response)
+            .await
+    }
+    #[inline]
+    fn handle_response_body(
+        &self,
+        session: &mut Session,
+        ctx: &mut State,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+    ) -> pingora::Result<()> {
+        // the response body is handled in the response step
+        if !self.matched(PluginStep::Response, session, ctx) {
+            return Ok(());
+        }
+        self.plugin
+            .handle_response_body(session, ctx, body, end_of_stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Condition, ConditionalPlugin};
+    use crate::config::{PluginConf, PluginStep};
+    use crate::plugin::ping::Ping;
+    use crate::state::State;
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use std::sync::Arc;
+    use tokio_test::io::Builder;
+
+    async fn new_session(
+        method: &str,
+        path: &str,
+        headers: &[&str],
+    ) -> Session {
+        let headers = headers.join("\r\n");
+        let input_header =
+            format!("{method} {path} HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        session
+    }
+
+    #[test]
+    fn test_condition_params() {
+        let conf = toml::from_str::<PluginConf>(
+            r###"
+category = "stats"
+"###,
+        )
+        .unwrap();
+        assert_eq!(true, Condition::new(&conf).unwrap().is_none());
+
+        let conf = toml::from_str::<PluginConf>(
+            r###"
+[match]
+path = "^/api/(["
+"###,
+        )
+        .unwrap();
+        assert_eq!(true, Condition::new(&conf).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_condition() {
+        let conf = toml::from_str::<PluginConf>(
+            r###"
+category = "stats"
+[match]
+methods = ["post"]
+path_prefix = "/api"
+headers = ["X-Env:prod", "User-Agent:~curl"]
+ip_list = ["192.168.0.0/16"]
+"###,
+        )
+        .unwrap();
+        let condition = Condition::new(&conf).unwrap().unwrap();
+
+        let headers = [
+            "X-Env: prod",
+            "User-Agent: curl/8.0",
+            "X-Forwarded-For: 192.168.1.1",
+        ];
+        let session = new_session("POST", "/api/users", &headers).await;
+        assert_eq!(true, condition.matched(&session, &mut State::default()));
+
+        let session = new_session("GET", "/api/users", &headers).await;
+        assert_eq!(false, condition.matched(&session, &mut State::default()));
+
+        let session = new_session("POST", "/users", &headers).await;
+        assert_eq!(false, condition.matched(&session, &mut State::default()));
+
+        let session = new_session(
+            "POST",
+            "/api/users",
+            &["X-Env: dev", "User-Agent: curl/8.0"],
+        )
+        .await;
+        assert_eq!(false, condition.matched(&session, &mut State::default()));
+
+        let session = new_session(
+            "POST",
+            "/api/users",
+            &[
+                "X-Env: prod",
+                "User-Agent: curl/8.0",
+                "X-Forwarded-For: 10.0.0.1",
+            ],
+        )
+        .await;
+        assert_eq!(false, condition.matched(&session, &mut State::default()));
+    }
+
+    #[tokio::test]
+    async fn test_conditional_plugin() {
+        let conf = toml::from_str::<PluginConf>(
+            r###"
+category = "ping"
+path = "/ping"
+[match]
+path_prefix = "/api"
+"###,
+        )
+        .unwrap();
+        let plugin = ConditionalPlugin::new(
+            "ping",
+            Condition::new(&conf).unwrap().unwrap(),
+            Arc::new(Ping::new(&conf).unwrap()),
+        );
+        let mut ctx = State::default();
+        let session = new_session("GET", "/api/users", &[]).await;
+        assert_eq!(
+            true,
+            plugin.matched(PluginStep::EarlyRequest, &session, &mut ctx)
+        );
+        assert_eq!(
+            Some(&true),
+            ctx.plugin_conditions
+                .as_ref()
+                .unwrap()
+                .get("ping:early_request")
+        );
+
+        // the result is reused by the same step of request
+        let session = new_session("GET", "/users", &[]).await;
+        assert_eq!(
+            true,
+            plugin.matched(PluginStep::EarlyRequest, &session, &mut ctx)
+        );
+        // the request is checked again in the later step,
+        // e.g. the path is rewritten
+        assert_eq!(
+            false,
+            plugin.matched(PluginStep::Request, &session, &mut ctx)
+        );
+        assert_eq!(
+            false,
+            plugin.matched(
+                PluginStep::Request,
+                &session,
+                &mut State::default()
+            )
+        );
+    }
+}
@@ -0,0 +1,324 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    get_bool_conf, get_hash_key, get_step_conf, get_str_conf,
+    get_str_slice_conf, Error, Plugin, Result,
+};
+use crate::config::{PluginCategory, PluginConf, PluginStep};
+use crate::state::State;
+use async_trait::async_trait;
+use http::header;
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use tracing::debug;
+
+/// Rewrite the attributes of `Set-Cookie` from upstream,
+/// it's useful for proxying legacy apps under a new domain.
+pub struct CookieRewrite {
+    plugin_step: PluginStep,
+    // (from, to), `*` matches any domain,
+    // and the domain attribute is removed if `to` is empty
+    domains: Vec<(String, String)>,
+    // (from prefix, to prefix) of path attribute
+    paths: Vec<(String, String)>,
+    // (from prefix, to prefix) of cookie name
+    name_prefixes: Vec<(String, String)>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<String>,
+    hash_value: String,
+}
+
+fn parse_pairs(value: &PluginConf, key: &str) -> Vec<(String, String)> {
+    get_str_slice_conf(value, key)
+        .iter()
+        .filter_map(|item| {
+            item.split_once(':')
+                .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+        })
+        .collect()
+}
+
+impl TryFrom<&PluginConf> for CookieRewrite {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let step = get_step_conf(value);
+
+        let same_site = get_str_conf(value, "same_site");
+        let same_site = match same_site.to_lowercase().as_str() {
+            "" => None,
+            "strict" => Some("Strict".to_string()),
+            "lax" => Some("Lax".to_string()),
+            "none" => Some("None".to_string()),
+            _ => {
+                return Err(Error::Invalid {
+                    category: PluginCategory::CookieRewrite.to_string(),
+                    message: format!("same site({same_site}) is invalid"),
+                })
+            },
+        };
+
+        let params = Self {
+            hash_value,
+            plugin_step: step,
+            domains: parse_pairs(value, "domains"),
+            paths: parse_pairs(value, "paths"),
+            name_prefixes: parse_pairs(value, "name_prefixes"),
+            // the secure is required by `SameSite=None`
+            secure: get_bool_conf(value, "secure")
+                || same_site.as_deref() == Some("None"),
+            http_only: get_bool_conf(value, "http_only"),
+            same_site,
+        };
+        if params.plugin_step != PluginStep::Response {
+            return Err(Error::Invalid {
+                category: PluginCategory::CookieRewrite.to_string(),
+                message:
+                    "Cookie rewrite plugin should be executed at response step"
+                        .to_string(),
+            });
+        }
+        Ok(params)
+    }
+}
+
+impl CookieRewrite {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new cookie rewrite plugin");
+        Self::try_from(params)
+    }
+    /// Rewrite the attributes of set cookie value.
+    fn rewrite(&self, value: &str) -> String {
+        let mut items = value.split(';').map(|item| item.trim());
+        let mut cookie = items.next().unwrap_or_default().to_string();
+        for (from, to) in self.name_prefixes.iter() {
+            if let Some(value) = cookie.strip_prefix(from.as_str()) {
+                cookie = format!("{to}{value}");
+                break;
+            }
+        }
+        let mut attrs = vec![cookie];
+        let (mut secure, mut http_only) = (false, false);
+        for item in items {
+            if item.is_empty() {
+                continue;
+            }
+            let (name, value) = item
+                .split_once('=')
+                .map(|(k, v)| (k.trim(), v.trim()))
+                .unwrap_or((item, ""));
+            match name.to_lowercase().as_str() {
+                "domain" => {
+                    let domain = value.trim_start_matches('.');
+                    match self.domains.iter().find(|(from, _)| {
+                        from == "*" || from.trim_start_matches('.') == domain
+                    }) {
+                        Some((_, to)) if to.is_empty() => continue,
+                        Some((_, to)) => attrs.push(format!("Domain={to}")),
+                        None => attrs.push(item.to_string()),
+                    }
+                },
+                "path" => {
+                    match self
+                        .paths
+                        .iter()
+                        .find(|(from, _)| value.starts_with(from.as_str()))
+                    {
+                        Some((from, to)) => {
+                            let rest =
+                                value.get(from.len()..).unwrap_or_default();
+                            let path = format!("{to}{rest}").replace("//", "/");
+                            if path.is_empty() {
+                                attrs.push("Path=/".to_string());
+                            } else {
+                                attrs.push(format!("Path={path}"));
+                            }
+                        },
+                        None => attrs.push(item.to_string()),
+                    }
+                },
+                "samesite" if self.same_site.is_some() => continue,
+                "secure" => {
+                    secure = true;
+                    attrs.push(item.to_string());
+                },
+                "httponly" => {
+                    http_only = true;
+                    attrs.push(item.to_string());
+                },
+                _ => attrs.push(item.to_string()),
+            }
+        }
+        if self.secure && !secure {
+            attrs.push("Secure".to_string());
+        }
+        if self.http_only && !http_only {
+            attrs.push("HttpOnly".to_string());
+        }
+        if let Some(same_site) = &self.same_site {
+            attrs.push(format!("SameSite={same_site}"));
+        }
+        attrs.join("; ")
+    }
+}
+
+#[async_trait]
+impl Plugin for CookieRewrite {
+    #[inline]
+    fn hash_key(&self) -> String {
+        self.hash_value.clone()
+    }
+    #[inline]
+    async fn handle_response(
+        &self,
+        step: PluginStep,
+        _session: &mut Session,
+        _ctx: &mut State,
+        upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<()> {
+        if step != self.plugin_step {
+            return Ok(());
+        }
+        let values: Vec<String> = upstream_response
+            .headers
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .map(|value| self.rewrite(value))
+            .collect();
+        if values.is_empty() {
+            return Ok(());
+        }
+        upstream_response.remove_header(&header::SET_COOKIE);
+        for value in values {
+            upstream_response.append_header(header::SET_COOKIE, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CookieRewrite;
+    use crate::config::{PluginConf, PluginStep};
+    use crate::plugin::Plugin;
+    use crate::state::State;
+    use pingora::http::ResponseHeader;
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use tokio_test::io::Builder;
+
+    fn new_cookie_rewrite() -> CookieRewrite {
+        CookieRewrite::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+step = "response"
+domains = ["legacy.com:example.com", "old.com:"]
+paths = ["/legacy:/app"]
+name_prefixes = ["legacy_:app_"]
+http_only = true
+same_site = "none"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_cookie_rewrite_params() {
+        let params = new_cookie_rewrite();
+        assert_eq!(
+            vec![
+                ("legacy.com".to_string(), "example.com".to_string()),
+                ("old.com".to_string(), "".to_string())
+            ],
+            params.domains
+        );
+        assert_eq!(true, params.secure);
+        assert_eq!(Some("None".to_string()), params.same_site);
+
+        let result = CookieRewrite::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+step = "response"
+same_site = "unknown"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin cookie_rewrite invalid, message: same site(unknown) is invalid",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_cookie_rewrite_value() {
+        let params = new_cookie_rewrite();
+        assert_eq!(
+            "app_session=abc; Domain=example.com; Path=/app/users; Max-Age=60; HttpOnly; Secure; SameSite=None",
+            params.rewrite(
+                "legacy_session=abc; Domain=.legacy.com; Path=/legacy/users; Max-Age=60; HttpOnly; SameSite=Lax"
+            )
+        );
+        assert_eq!(
+            "uid=1; Path=/app; Secure; HttpOnly; SameSite=None",
+            params.rewrite("uid=1; Domain=old.com; Path=/legacy; Secure")
+        );
+        assert_eq!(
+            "uid=1; Domain=other.com; Path=/; Secure; HttpOnly; SameSite=None",
+            params.rewrite("uid=1; Domain=other.com; Path=/")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cookie_rewrite() {
+        let params = new_cookie_rewrite();
+        let mock_io = Builder::new().read(b"GET / HTTP/1.1\r\n\r\n").build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let mut upstream_response = ResponseHeader::build(200, None).unwrap();
+        upstream_response
+            .append_header("Set-Cookie", "legacy_a=1; Path=/legacy")
+            .unwrap();
+        upstream_response
+            .append_header("Set-Cookie", "b=2; Domain=legacy.com")
+            .unwrap();
+        params
+            .handle_response(
+                PluginStep::Response,
+                &mut session,
+                &mut State::default(),
+                &mut upstream_response,
+            )
+            .await
+            .unwrap();
+        let values: Vec<&str> = upstream_response
+            .headers
+            .get_all("Set-Cookie")
+            .iter()
+            .map(|value| value.to_str().unwrap())
+            .collect();
+        assert_eq!(
+            vec![
+                "app_a=1; Path=/app; Secure; HttpOnly; SameSite=None",
+                "b=2; Domain=example.com; Secure; HttpOnly; SameSite=None",
+            ],
+            values
+        );
+    }
+}
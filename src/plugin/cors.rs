@@ -13,8 +13,8 @@
 // limitations under the License.
 
 use super::{
-    get_bool_conf, get_hash_key, get_step_conf, get_str_conf, Error, Plugin,
-    Result,
+    get_bool_conf, get_hash_key, get_step_conf, get_str_conf,
+    get_str_slice_conf, Error, Plugin, Result,
 };
 use crate::config::{PluginCategory, PluginConf, PluginStep};
 use crate::http_extra::{convert_header_value, HttpHeader, HttpResponse};
@@ -29,14 +29,123 @@ use regex::Regex;
 use std::time::Duration;
 use tracing::debug;
 
+#[derive(Debug)]
+enum OriginMatcher {
+    Exact(String),
+    // the prefix and suffix of `*`, e.g. `https://*.pingap.io`
+    Wildcard(String, String),
+    Regex(Regex),
+}
+
+impl OriginMatcher {
+    fn matched(&self, origin: &str) -> bool {
+        match self {
+            OriginMatcher::Exact(value) => value.eq_ignore_ascii_case(origin),
+            OriginMatcher::Wildcard(prefix, suffix) => {
+                origin.len() > prefix.len() + suffix.len()
+                    && origin.starts_with(prefix.as_str())
+                    && origin.ends_with(suffix.as_str())
+            },
+            OriginMatcher::Regex(reg) => reg.is_match(origin),
+        }
+    }
+}
+
+/// The cors policy of path, the allow methods and headers of it
+/// override the default values.
+struct CorsRoute {
+    path: Regex,
+    headers: Vec<HttpHeader>,
+}
+
 pub struct Cors {
     plugin_step: PluginStep,
     path: Option<Regex>,
     allow_origin: HeaderValue,
+    // the allowed origins, the matched origin is used as allow origin,
+    // it starts with `~` is a regex, and `*` is a wildcard
+    allow_origins: Vec<OriginMatcher>,
+    allow_credentials: bool,
+    allow_private_network: bool,
     headers: Vec<HttpHeader>,
+    routes: Vec<CorsRoute>,
     hash_value: String,
 }
 
+fn new_invalid_error(message: String) -> Error {
+    Error::Invalid {
+        category: PluginCategory::Cors.to_string(),
+        message,
+    }
+}
+
+fn format_header_value(value: &str) -> Result<HeaderValue> {
+    HeaderValue::from_str(value).map_err(|e| new_invalid_error(e.to_string()))
+}
+
+fn parse_allow_origins(values: &[String]) -> Result<Vec<OriginMatcher>> {
+    let mut allow_origins = vec![];
+    for item in values.iter() {
+        let item = item.trim();
+        let matcher = if let Some(value) = item.strip_prefix('~') {
+            OriginMatcher::Regex(
+                Regex::new(value)
+                    .map_err(|e| new_invalid_error(e.to_string()))?,
+            )
+        } else if let Some((prefix, suffix)) = item.split_once('*') {
+            OriginMatcher::Wildcard(prefix.to_string(), suffix.to_string())
+        } else {
+            OriginMatcher::Exact(item.to_string())
+        };
+        allow_origins.push(matcher);
+    }
+    Ok(allow_origins)
+}
+
+/// Parse the cors routes, e.g.
+/// ```toml
+/// [plugins.cors.routes.admin]
+/// path = "^/api/admin"
+/// allow_methods = "GET, POST"
+/// allow_headers = "Content-Type, X-Token"
+/// ```
+fn parse_routes(
+    value: &PluginConf,
+    headers: &[HttpHeader],
+) -> Result<Vec<CorsRoute>> {
+    let mut routes = vec![];
+    let Some(values) = value.get("routes").and_then(|v| v.as_table()) else {
+        return Ok(routes);
+    };
+    for (name, item) in values.iter() {
+        let get = |key: &str| {
+            item.get(key)
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .to_string()
+        };
+        let path = Regex::new(&get("path")).map_err(|e| {
+            new_invalid_error(format!("path of route {name} is invalid, {e}"))
+        })?;
+        let mut route_headers = headers.to_vec();
+        for (name, value) in [
+            (header::ACCESS_CONTROL_ALLOW_METHODS, get("allow_methods")),
+            (header::ACCESS_CONTROL_ALLOW_HEADERS, get("allow_headers")),
+        ] {
+            if value.is_empty() {
+                continue;
+            }
+            route_headers.retain(|(key, _)| *key != name);
+            route_headers.push((name, format_header_value(&value)?));
+        }
+        routes.push(CorsRoute {
+            path,
+            headers: route_headers,
+        });
+    }
+    Ok(routes)
+}
+
 impl TryFrom<&PluginConf> for Cors {
     type Error = Error;
     fn try_from(value: &PluginConf) -> Result<Self> {
@@ -74,13 +183,6 @@ impl TryFrom<&PluginConf> for Cors {
                 ["GET", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"].join(", ");
         };
 
-        let format_header_value = |value: &str| -> Result<HeaderValue> {
-            HeaderValue::from_str(value).map_err(|e| Error::Invalid {
-                category: PluginCategory::Cors.to_string(),
-                message: e.to_string(),
-            })
-        };
-
         let mut headers = vec![(
             header::ACCESS_CONTROL_ALLOW_METHODS,
             format_header_value(&allow_methods)?,
@@ -117,12 +219,23 @@ impl TryFrom<&PluginConf> for Cors {
             ));
         }
 
+        let routes = parse_routes(value, &headers)?;
         let cors = Self {
             hash_value,
             plugin_step: step,
             path,
             allow_origin: format_header_value(&allow_origin)?,
+            allow_origins: parse_allow_origins(&get_str_slice_conf(
+                value,
+                "allow_origins",
+            ))?,
+            allow_credentials,
+            allow_private_network: get_bool_conf(
+                value,
+                "allow_private_network",
+            ),
             headers,
+            routes,
         };
         if cors.plugin_step != PluginStep::Request {
             return Err(Error::Invalid {
@@ -141,19 +254,65 @@ impl Cors {
         debug!(params = params.to_string(), "new cors plugin");
         Self::try_from(params)
     }
+    /// Get the cors headers of request, it returns none if the origin
+    /// is not allowed.
     fn get_headers(
         &self,
         session: &mut Session,
         ctx: &mut State,
-    ) -> Result<Vec<HttpHeader>> {
-        let origin = convert_header_value(&self.allow_origin, session, ctx)
-            .ok_or(Error::Invalid {
-                category: PluginCategory::Cors.to_string(),
-                message: "Allow origin is invalid".to_string(),
-            })?;
-        let mut headers = self.headers.clone();
+    ) -> Result<Option<Vec<HttpHeader>>> {
+        let request_origin = session
+            .get_header(header::ORIGIN)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let mut vary_origin = false;
+        let origin = if !self.allow_origins.is_empty() {
+            if !self
+                .allow_origins
+                .iter()
+                .any(|item| item.matched(&request_origin))
+            {
+                return Ok(None);
+            }
+            vary_origin = true;
+            format_header_value(&request_origin)?
+        } else if self.allow_credentials
+            && self.allow_origin == "*"
+            && !request_origin.is_empty()
+        {
+            // wildcard origin is not allowed with credentials
+            vary_origin = true;
+            format_header_value(&request_origin)?
+        } else {
+            convert_header_value(&self.allow_origin, session, ctx).ok_or(
+                new_invalid_error("Allow origin is invalid".to_string()),
+            )?
+        };
+        let path = session.req_header().uri.path();
+        let mut headers = self
+            .routes
+            .iter()
+            .find(|route| route.path.is_match(path))
+            .map(|route| route.headers.clone())
+            .unwrap_or_else(|| self.headers.clone());
+        if self.allow_private_network
+            && session
+                .get_header("Access-Control-Request-Private-Network")
+                .is_some()
+        {
+            headers.push((
+                http::HeaderName::from_static(
+                    "access-control-allow-private-network",
+                ),
+                HeaderValue::from_static("true"),
+            ));
+        }
         headers.push((header::ACCESS_CONTROL_ALLOW_ORIGIN, origin));
-        Ok(headers)
+        if vary_origin {
+            headers.push((header::VARY, HeaderValue::from_static("Origin")));
+        }
+        Ok(Some(headers))
     }
 }
 
@@ -183,8 +342,9 @@ impl Plugin for Cors {
             let headers = self
                 .get_headers(session, ctx)
                 .map_err(|e| util::new_internal_error(400, e.to_string()))?;
+            // the preflight of disallowed origin has no cors headers
             let mut resp = HttpResponse::no_content();
-            resp.headers = Some(headers);
+            resp.headers = headers;
             return Ok(Some(resp));
         }
         Ok(None)
@@ -209,11 +369,18 @@ impl Plugin for Cors {
             return Ok(());
         }
 
-        let headers = self
+        let Some(headers) = self
             .get_headers(session, ctx)
-            .map_err(|e| util::new_internal_error(400, e.to_string()))?;
+            .map_err(|e| util::new_internal_error(400, e.to_string()))?
+        else {
+            return Ok(());
+        };
         for (name, value) in &headers {
-            let _ = upstream_response.insert_header(name, value);
+            if *name == header::VARY {
+                let _ = upstream_response.append_header(name, value);
+            } else {
+                let _ = upstream_response.insert_header(name, value);
+            }
         }
         Ok(())
     }
@@ -312,4 +479,79 @@ max_age = "60m"
             format!("{:?}", header.headers)
         );
     }
+
+    #[tokio::test]
+    async fn test_cors_allow_origins() {
+        let cors = Cors::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+allow_origins = ["https://pingap.io", "https://*.pingap.io", "~^http://localhost:\\d+$"]
+allow_private_network = true
+max_age = "10m"
+[routes.admin]
+path = "^/api/admin"
+allow_methods = "GET"
+allow_headers = "X-Token"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        let new_session = |origin: &str, path: &str| {
+            let input_header = format!(
+                "OPTIONS {path} HTTP/1.1\r\nOrigin: {origin}\r\nAccess-Control-Request-Method: GET\r\nAccess-Control-Request-Private-Network: true\r\n\r\n"
+            );
+            let mock_io = Builder::new().read(input_header.as_bytes()).build();
+            Session::new_h1(Box::new(mock_io))
+        };
+
+        let mut session = new_session("https://api.pingap.io", "/api/admin");
+        session.read_request().await.unwrap();
+        let resp = cors
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            r#"[("access-control-max-age", "600"), ("access-control-allow-methods", "GET"), ("access-control-allow-headers", "X-Token"), ("access-control-allow-private-network", "true"), ("access-control-allow-origin", "https://api.pingap.io"), ("vary", "Origin")]"#,
+            format!("{:?}", resp.headers.unwrap())
+        );
+
+        let mut session = new_session("http://localhost:3000", "/api/users");
+        session.read_request().await.unwrap();
+        let resp = cors
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            true,
+            format!("{:?}", resp.headers.unwrap()).contains(
+                r#"("access-control-allow-origin", "http://localhost:3000")"#
+            )
+        );
+
+        let mut session = new_session("https://pingap.io.evil.com", "/");
+        session.read_request().await.unwrap();
+        let resp = cors
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(204, resp.status.as_u16());
+        assert_eq!(true, resp.headers.is_none());
+    }
 }
@@ -0,0 +1,342 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    get_hash_key, get_step_conf, get_str_conf, get_str_slice_conf, Error,
+    Plugin, Result,
+};
+use crate::config::{PluginCategory, PluginConf, PluginStep};
+use crate::state::{ModifyResponseBody, State};
+use crate::util;
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::header;
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use tracing::debug;
+
+const CSP_NONCE_VARIABLE: &str = "csp_nonce";
+const NONCE_TAGS: [&[u8]; 2] = [b"<script", b"<style"];
+
+/// Generate the nonce of each request, add it to the directives of
+/// `Content-Security-Policy` header and the inline script and style tags
+/// of html response, so the strict csp can be used behind the proxy.
+pub struct CspNonce {
+    plugin_step: PluginStep,
+    // the policy is used if the upstream response has no csp header
+    policy: String,
+    directives: Vec<String>,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for CspNonce {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let step = get_step_conf(value);
+
+        let mut directives: Vec<String> =
+            get_str_slice_conf(value, "directives")
+                .iter()
+                .map(|item| item.to_lowercase())
+                .collect();
+        if directives.is_empty() {
+            directives =
+                vec!["script-src".to_string(), "style-src".to_string()];
+        }
+        let params = Self {
+            hash_value,
+            plugin_step: step,
+            policy: get_str_conf(value, "policy"),
+            directives,
+        };
+        if params.plugin_step != PluginStep::Response {
+            return Err(Error::Invalid {
+                category: PluginCategory::CspNonce.to_string(),
+                message: "Csp nonce plugin should be executed at response step"
+                    .to_string(),
+            });
+        }
+        Ok(params)
+    }
+}
+
+/// Add the nonce source to the directives of policy, the missing directive
+/// is added with the sources of `default-src` if it exists.
+fn add_nonce_to_policy(
+    policy: &str,
+    directives: &[String],
+    nonce: &str,
+) -> String {
+    let mut items: Vec<String> = policy
+        .split(';')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect();
+    let directive_name = |item: &str| -> String {
+        item.split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_lowercase()
+    };
+    let default_sources = items
+        .iter()
+        .find(|item| directive_name(item) == "default-src")
+        .map(|item| item["default-src".len()..].trim().to_string());
+    let source = format!("'nonce-{nonce}'");
+    for name in directives.iter() {
+        if let Some(item) =
+            items.iter_mut().find(|item| &directive_name(item) == name)
+        {
+            item.push(' ');
+            item.push_str(&source);
+        } else if let Some(sources) = &default_sources {
+            items.push(format!("{name} {sources} {source}"));
+        }
+    }
+    items.join("; ")
+}
+
+struct CspNonceBody {
+    nonce: String,
+}
+
+impl CspNonceBody {
+    /// Get the end position of tag name if the data starts with
+    /// script or style tag.
+    fn match_tag(data: &[u8]) -> Option<usize> {
+        for tag in NONCE_TAGS {
+            if data.len() <= tag.len()
+                || !data[..tag.len()].eq_ignore_ascii_case(tag)
+            {
+                continue;
+            }
+            let c = data[tag.len()];
+            if c.is_ascii_whitespace() || c == b'>' || c == b'/' {
+                return Some(tag.len());
+            }
+        }
+        None
+    }
+}
+
+impl ModifyResponseBody for CspNonceBody {
+    fn handle(&self, data: Bytes) -> Bytes {
+        let attr = format!(r#" nonce="{}""#, self.nonce);
+        let mut buf = Vec::with_capacity(data.len() + 256);
+        let mut start = 0;
+        let mut index = 0;
+        while let Some(offset) = data[index..].iter().position(|c| *c == b'<') {
+            index += offset;
+            let Some(tag_size) = Self::match_tag(&data[index..]) else {
+                index += 1;
+                continue;
+            };
+            let end = data[index..]
+                .iter()
+                .position(|c| *c == b'>')
+                .map(|pos| index + pos)
+                .unwrap_or(data.len());
+            let has_nonce = data[index..end]
+                .to_ascii_lowercase()
+                .windows(6)
+                .any(|item| item == b"nonce=");
+            if !has_nonce {
+                buf.extend_from_slice(&data[start..index + tag_size]);
+                buf.extend_from_slice(attr.as_bytes());
+                start = index + tag_size;
+            }
+            index = end;
+        }
+        if start == 0 {
+            return data;
+        }
+        buf.extend_from_slice(&data[start..]);
+        Bytes::from(buf)
+    }
+}
+
+impl CspNonce {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new csp nonce plugin");
+        Self::try_from(params)
+    }
+}
+
+#[async_trait]
+impl Plugin for CspNonce {
+    #[inline]
+    fn hash_key(&self) -> String {
+        self.hash_value.clone()
+    }
+    #[inline]
+    async fn handle_response(
+        &self,
+        step: PluginStep,
+        _session: &mut Session,
+        ctx: &mut State,
+        upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<()> {
+        if step != self.plugin_step {
+            return Ok(());
+        }
+        let headers = &upstream_response.headers;
+        let is_html = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_lowercase().starts_with("text/html"))
+            .unwrap_or_default();
+        // the compressed html can't be rewritten,
+        // the inline tags will be blocked if the policy is changed
+        if !is_html || headers.contains_key(header::CONTENT_ENCODING) {
+            return Ok(());
+        }
+        let policy = headers
+            .get(header::CONTENT_SECURITY_POLICY)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string())
+            .unwrap_or_else(|| self.policy.clone());
+        if policy.is_empty() {
+            return Ok(());
+        }
+        let nonce = util::base64_encode(rand::random::<[u8; 16]>());
+        let policy = add_nonce_to_policy(&policy, &self.directives, &nonce);
+        upstream_response
+            .insert_header(header::CONTENT_SECURITY_POLICY, policy)?;
+        ctx.add_variable(CSP_NONCE_VARIABLE, &nonce);
+
+        // the body will be changed
+        upstream_response.remove_header(&header::CONTENT_LENGTH);
+        let _ = upstream_response
+            .insert_header(header::TRANSFER_ENCODING, "Chunked");
+        ctx.modify_response_body = Some(Box::new(CspNonceBody { nonce }));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{add_nonce_to_policy, CspNonce, CspNonceBody};
+    use crate::config::{PluginConf, PluginStep};
+    use crate::plugin::Plugin;
+    use crate::state::{ModifyResponseBody, State};
+    use bytes::Bytes;
+    use pingora::http::ResponseHeader;
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use tokio_test::io::Builder;
+
+    #[test]
+    fn test_csp_nonce_params() {
+        let result = CspNonce::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+policy = "script-src 'self'"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin csp_nonce invalid, message: Csp nonce plugin should be executed at response step",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_add_nonce_to_policy() {
+        let directives =
+            vec!["script-src".to_string(), "style-src".to_string()];
+        assert_eq!(
+            "script-src 'self' 'nonce-abc'; img-src *",
+            add_nonce_to_policy(
+                "script-src 'self'; img-src *",
+                &directives,
+                "abc"
+            )
+        );
+        assert_eq!(
+            "default-src 'self' https://pingap.io; script-src 'self' https://pingap.io 'nonce-abc'; style-src 'self' https://pingap.io 'nonce-abc'",
+            add_nonce_to_policy(
+                "default-src 'self' https://pingap.io;",
+                &directives,
+                "abc"
+            )
+        );
+    }
+
+    #[test]
+    fn test_csp_nonce_body() {
+        let body = CspNonceBody {
+            nonce: "abc".to_string(),
+        };
+        let data = Bytes::from_static(
+            br#"<html><head><STYLE>a{}</STYLE><script nonce="x">1</script><scripts></scripts></head><body><script src="/a.js"></script></body></html>"#,
+        );
+        assert_eq!(
+            r#"<html><head><STYLE nonce="abc">a{}</STYLE><script nonce="x">1</script><scripts></scripts></head><body><script nonce="abc" src="/a.js"></script></body></html>"#,
+            std::str::from_utf8(&body.handle(data)).unwrap()
+        );
+
+        let data = Bytes::from_static(b"<html></html>");
+        assert_eq!(data.clone(), body.handle(data));
+    }
+
+    #[tokio::test]
+    async fn test_csp_nonce() {
+        let params = CspNonce::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+step = "response"
+policy = "script-src 'self'"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let input_header = "GET / HTTP/1.1\r\n\r\n";
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+
+        let mut ctx = State::default();
+        let mut upstream_response = ResponseHeader::build(200, None).unwrap();
+        upstream_response
+            .insert_header("Content-Type", "text/html; charset=utf-8")
+            .unwrap();
+        params
+            .handle_response(
+                PluginStep::Response,
+                &mut session,
+                &mut ctx,
+                &mut upstream_response,
+            )
+            .await
+            .unwrap();
+        let nonce = ctx
+            .variables
+            .as_ref()
+            .and_then(|variables| variables.get("$csp_nonce").cloned())
+            .unwrap();
+        assert_eq!(
+            format!("script-src 'self' 'nonce-{nonce}'"),
+            upstream_response
+                .headers
+                .get("Content-Security-Policy")
+                .unwrap()
+                .to_str()
+                .unwrap()
+        );
+        assert_eq!(true, ctx.modify_response_body.is_some());
+    }
+}
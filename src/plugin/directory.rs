@@ -122,6 +122,8 @@ pub struct Directory {
     headers: Option<Vec<HttpHeader>>,
     // support download
     download: bool,
+    // serve the pre-compressed file(.br, .zst, .gz) if it exists
+    precompressed: bool,
     hash_value: String,
 }
 
@@ -138,6 +140,25 @@ async fn get_data(
     Ok((meta, f))
 }
 
+/// Get the pre-compressed file of accept encoding, the order is br, zstd, gzip.
+async fn get_precompressed_data(
+    file: &Path,
+    accept_encoding: &str,
+) -> Option<(std::fs::Metadata, fs::File, &'static str)> {
+    for (encoding, ext) in [("br", "br"), ("zstd", "zst"), ("gzip", "gz")] {
+        if !accept_encoding.contains(encoding) {
+            continue;
+        }
+        let mut name = file.as_os_str().to_os_string();
+        name.push(".");
+        name.push(ext);
+        if let Ok((meta, f)) = get_data(&PathBuf::from(name)).await {
+            return Some((meta, f, encoding));
+        }
+    }
+    None
+}
+
 fn get_cacheable_and_headers_from_meta(
     file: &PathBuf,
     meta: &Metadata,
@@ -229,6 +250,7 @@ impl TryFrom<&PluginConf> for Directory {
             cache_private,
             plugin_step: step,
             download: get_bool_conf(value, "download"),
+            precompressed: get_bool_conf(value, "precompressed"),
             headers: Some(headers),
         };
         if ![PluginStep::Request, PluginStep::ProxyUpstream]
@@ -334,14 +356,42 @@ impl Plugin for Directory {
 
         // Content-Disposition: attachment; filename="example.pdf"
 
-        let resp = match get_data(&file).await {
+        let accept_encoding = session
+            .req_header()
+            .headers
+            .get(header::ACCEPT_ENCODING)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        let precompressed = if self.precompressed && !accept_encoding.is_empty()
+        {
+            get_precompressed_data(&file, accept_encoding).await
+        } else {
+            None
+        };
+        let (result, encoding) = match precompressed {
+            Some((meta, f, encoding)) => (Ok((meta, f)), Some(encoding)),
+            None => (get_data(&file).await, None),
+        };
+
+        let resp = match result {
             Ok((meta, mut f)) => {
+                // the content type is got from the original file
                 let (cacheable, size, mut headers) =
                     get_cacheable_and_headers_from_meta(
                         &file,
                         &meta,
                         &self.charset,
                     );
+                if let Some(encoding) = encoding {
+                    headers.push((
+                        header::CONTENT_ENCODING,
+                        HeaderValue::from_static(encoding),
+                    ));
+                    headers.push((
+                        header::VARY,
+                        HeaderValue::from_static("Accept-Encoding"),
+                    ));
+                }
                 if self.download {
                     if let Ok(value) = HeaderValue::from_str(&format!(
                         r###"attachment; filename="{}""###,
@@ -399,7 +449,10 @@ impl Plugin for Directory {
 
 #[cfg(test)]
 mod tests {
-    use super::{get_cacheable_and_headers_from_meta, get_data, Directory};
+    use super::{
+        get_cacheable_and_headers_from_meta, get_data, get_precompressed_data,
+        Directory,
+    };
     use crate::state::State;
     use crate::{config::PluginConf, config::PluginStep, plugin::Plugin};
     use pingora::proxy::Session;
@@ -557,4 +610,17 @@ download = true
             )
         );
     }
+
+    #[tokio::test]
+    async fn test_get_precompressed_data() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let file = dir.path().join("app.js");
+        std::fs::write(&file, b"console.log(1)").unwrap();
+        std::fs::write(dir.path().join("app.js.gz"), b"gzip").unwrap();
+
+        let (_, _, encoding) =
+            get_precompressed_data(&file, "gzip, br").await.unwrap();
+        assert_eq!("gzip", encoding);
+        assert_eq!(true, get_precompressed_data(&file, "br").await.is_none());
+    }
 }
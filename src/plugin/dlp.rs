@@ -0,0 +1,498 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    get_hash_key, get_step_conf, get_str_conf, get_str_slice_conf, Error,
+    Plugin, Result,
+};
+use crate::config::{PluginCategory, PluginConf, PluginStep};
+#[cfg(feature = "metrics")]
+use crate::state::DLP_MATCHES;
+use crate::state::{ModifyResponseBody, State};
+use async_trait::async_trait;
+use bytes::Bytes;
+use bytesize::ByteSize;
+use http::header;
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use regex::bytes::Regex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+const CREDIT_CARD: &str = "credit_card";
+const SSN: &str = "ssn";
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum DlpAction {
+    Mask,
+    Block,
+}
+
+impl DlpAction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            DlpAction::Mask => "mask",
+            DlpAction::Block => "block",
+        }
+    }
+}
+
+struct DlpPattern {
+    name: String,
+    regex: Regex,
+    // validate the matched value to reduce false positives
+    validate: Option<fn(&[u8]) -> bool>,
+    matched: AtomicU64,
+}
+
+/// Luhn checksum of credit card number, the separators are ignored.
+fn luhn_valid(value: &[u8]) -> bool {
+    let digits: Vec<u32> = value
+        .iter()
+        .filter(|c| c.is_ascii_digit())
+        .map(|c| (c - b'0') as u32)
+        .collect();
+    if digits.len() < 13 || digits.len() > 19 {
+        return false;
+    }
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(index, &digit)| {
+            if index % 2 == 1 {
+                let value = digit * 2;
+                if value > 9 {
+                    value - 9
+                } else {
+                    value
+                }
+            } else {
+                digit
+            }
+        })
+        .sum();
+    sum % 10 == 0
+}
+
+/// The area, group and serial of ssn can't be all zero,
+/// and the area can't be 666 or start with 9.
+fn ssn_valid(value: &[u8]) -> bool {
+    let value = std::str::from_utf8(value).unwrap_or_default();
+    let parts: Vec<&str> = value.split('-').collect();
+    if parts.len() != 3 {
+        return false;
+    }
+    !(parts[0] == "000"
+        || parts[0] == "666"
+        || parts[0].starts_with('9')
+        || parts[1] == "00"
+        || parts[2] == "0000")
+}
+
+fn new_pattern(
+    name: &str,
+    value: &str,
+    validate: Option<fn(&[u8]) -> bool>,
+) -> Result<DlpPattern> {
+    let regex = Regex::new(value).map_err(|e| Error::Invalid {
+        category: PluginCategory::Dlp.to_string(),
+        message: format!("pattern({name}) is invalid, {e}"),
+    })?;
+    Ok(DlpPattern {
+        name: name.to_string(),
+        regex,
+        validate,
+        matched: AtomicU64::new(0),
+    })
+}
+
+/// Scan the response body with data loss prevention patterns,
+/// the matched data is masked or the whole body is blocked.
+/// The body is buffered before scanning, so the data split by chunks
+/// can be matched.
+pub struct Dlp {
+    plugin_step: PluginStep,
+    patterns: Arc<Vec<DlpPattern>>,
+    action: DlpAction,
+    block_message: Bytes,
+    // the prefix of response content type
+    content_types: Vec<String>,
+    // the response body larger than it is not scanned
+    max_body_size: usize,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for Dlp {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let step = get_step_conf(value);
+
+        let mut patterns = vec![];
+        for name in get_str_slice_conf(value, "patterns") {
+            let pattern = match name.as_str() {
+                CREDIT_CARD => new_pattern(
+                    &name,
+                    r"\b\d(?:[ -]?\d){12,18}\b",
+                    Some(luhn_valid),
+                )?,
+                SSN => new_pattern(
+                    &name,
+                    r"\b\d{3}-\d{2}-\d{4}\b",
+                    Some(ssn_valid),
+                )?,
+                _ => {
+                    return Err(Error::Invalid {
+                        category: PluginCategory::Dlp.to_string(),
+                        message: format!("pattern({name}) is not supported"),
+                    })
+                },
+            };
+            patterns.push(pattern);
+        }
+        // custom regex, e.g. `token:sk_live_[0-9a-zA-Z]{24}`
+        for item in get_str_slice_conf(value, "regexes") {
+            let Some((name, regex)) = item.split_once(':') else {
+                return Err(Error::Invalid {
+                    category: PluginCategory::Dlp.to_string(),
+                    message: format!("regex({item}) should be name:regex"),
+                });
+            };
+            patterns.push(new_pattern(name.trim(), regex.trim(), None)?);
+        }
+        if patterns.is_empty() {
+            return Err(Error::Invalid {
+                category: PluginCategory::Dlp.to_string(),
+                message: "Patterns and regexes should not be empty".to_string(),
+            });
+        }
+        let action = match get_str_conf(value, "action").as_str() {
+            "block" => DlpAction::Block,
+            _ => DlpAction::Mask,
+        };
+        let mut block_message = get_str_conf(value, "block_message");
+        if block_message.is_empty() {
+            block_message =
+                "Response is blocked by data loss prevention".to_string();
+        }
+        let mut content_types: Vec<String> =
+            get_str_slice_conf(value, "content_types")
+                .iter()
+                .map(|item| item.to_lowercase())
+                .collect();
+        if content_types.is_empty() {
+            content_types = [
+                "text/",
+                "application/json",
+                "application/xml",
+                "application/javascript",
+            ]
+            .iter()
+            .map(|item| item.to_string())
+            .collect();
+        }
+        let max_body_size = get_str_conf(value, "max_body_size");
+        let max_body_size = if max_body_size.is_empty() {
+            ByteSize::mb(1)
+        } else {
+            max_body_size
+                .parse::<ByteSize>()
+                .map_err(|e| Error::Invalid {
+                    category: PluginCategory::Dlp.to_string(),
+                    message: format!("max body size is invalid, {e}"),
+                })?
+        };
+
+        let params = Self {
+            hash_value,
+            plugin_step: step,
+            patterns: Arc::new(patterns),
+            action,
+            block_message: Bytes::from(block_message),
+            content_types,
+            max_body_size: max_body_size.as_u64() as usize,
+        };
+        if params.plugin_step != PluginStep::Response {
+            return Err(Error::Invalid {
+                category: PluginCategory::Dlp.to_string(),
+                message: "Dlp plugin should be executed at response step"
+                    .to_string(),
+            });
+        }
+        Ok(params)
+    }
+}
+
+impl Dlp {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new dlp plugin");
+        Self::try_from(params)
+    }
+    /// Check the response should be scanned, the compressed response
+    /// and the large response are ignored.
+    fn should_scan(&self, upstream_response: &ResponseHeader) -> bool {
+        let headers = &upstream_response.headers;
+        if headers.contains_key(header::CONTENT_ENCODING) {
+            return false;
+        }
+        let content_length = headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or_default();
+        if content_length > self.max_body_size {
+            return false;
+        }
+        let content_type = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_lowercase();
+        self.content_types
+            .iter()
+            .any(|item| content_type.starts_with(item))
+    }
+}
+
+struct DlpBody {
+    patterns: Arc<Vec<DlpPattern>>,
+    action: DlpAction,
+    block_message: Bytes,
+}
+
+impl DlpBody {
+    /// Get the matched ranges of data, the count of each pattern is added.
+    fn find_matches(&self, data: &[u8]) -> Vec<(usize, usize)> {
+        let mut ranges = vec![];
+        for pattern in self.patterns.iter() {
+            let mut count = 0;
+            for m in pattern.regex.find_iter(data) {
+                if let Some(validate) = pattern.validate {
+                    if !validate(m.as_bytes()) {
+                        continue;
+                    }
+                }
+                count += 1;
+                ranges.push((m.start(), m.end()));
+            }
+            if count == 0 {
+                continue;
+            }
+            pattern.matched.fetch_add(count, Ordering::Relaxed);
+            #[cfg(feature = "metrics")]
+            DLP_MATCHES
+                .with_label_values(&[&pattern.name, self.action.as_str()])
+                .inc_by(count);
+            warn!(
+                pattern = pattern.name,
+                count,
+                total = pattern.matched.load(Ordering::Relaxed),
+                action = self.action.as_str(),
+                "sensitive data is found in response"
+            );
+        }
+        ranges
+    }
+}
+
+impl ModifyResponseBody for DlpBody {
+    fn handle(&self, data: Bytes) -> Bytes {
+        let ranges = self.find_matches(&data);
+        if ranges.is_empty() {
+            return data;
+        }
+        if self.action == DlpAction::Block {
+            return self.block_message.clone();
+        }
+        let mut buf = data.to_vec();
+        for (start, end) in ranges {
+            for c in buf[start..end].iter_mut() {
+                // keep the separators for readability
+                if c.is_ascii_alphanumeric() {
+                    *c = b'*';
+                }
+            }
+        }
+        Bytes::from(buf)
+    }
+}
+
+#[async_trait]
+impl Plugin for Dlp {
+    #[inline]
+    fn hash_key(&self) -> String {
+        self.hash_value.clone()
+    }
+    #[inline]
+    async fn handle_response(
+        &self,
+        step: PluginStep,
+        _session: &mut Session,
+        ctx: &mut State,
+        upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<()> {
+        if step != self.plugin_step || !self.should_scan(upstream_response) {
+            return Ok(());
+        }
+        // the body may be changed
+        upstream_response.remove_header(&header::CONTENT_LENGTH);
+        let _ = upstream_response
+            .insert_header(header::TRANSFER_ENCODING, "Chunked");
+        ctx.modify_response_body = Some(Box::new(DlpBody {
+            patterns: self.patterns.clone(),
+            action: self.action,
+            block_message: self.block_message.clone(),
+        }));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{luhn_valid, ssn_valid, Dlp, DlpBody};
+    use crate::config::PluginConf;
+    use crate::state::ModifyResponseBody;
+    use bytes::Bytes;
+    use pingora::http::ResponseHeader;
+    use pretty_assertions::assert_eq;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn test_dlp_params() {
+        let result = Dlp::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+step = "response"
+patterns = ["phone"]
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin dlp invalid, message: pattern(phone) is not supported",
+            result.err().unwrap().to_string()
+        );
+
+        let result = Dlp::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+step = "response"
+regexes = ["token"]
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin dlp invalid, message: regex(token) should be name:regex",
+            result.err().unwrap().to_string()
+        );
+
+        let result = Dlp::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+patterns = ["ssn"]
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin dlp invalid, message: Dlp plugin should be executed at response step",
+            result.err().unwrap().to_string()
+        );
+
+        let params = Dlp::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+step = "response"
+patterns = ["credit_card", "ssn"]
+max_body_size = "1kb"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let mut upstream_response = ResponseHeader::build(200, None).unwrap();
+        upstream_response
+            .insert_header("Content-Type", "application/json")
+            .unwrap();
+        assert_eq!(true, params.should_scan(&upstream_response));
+        upstream_response
+            .insert_header("Content-Length", "2048")
+            .unwrap();
+        assert_eq!(false, params.should_scan(&upstream_response));
+        upstream_response.remove_header("Content-Length");
+        upstream_response
+            .insert_header("Content-Encoding", "gzip")
+            .unwrap();
+        assert_eq!(false, params.should_scan(&upstream_response));
+    }
+
+    #[test]
+    fn test_dlp_validate() {
+        assert_eq!(true, luhn_valid(b"4111 1111 1111 1111"));
+        assert_eq!(false, luhn_valid(b"4111 1111 1111 1112"));
+        assert_eq!(true, ssn_valid(b"123-45-6789"));
+        assert_eq!(false, ssn_valid(b"666-45-6789"));
+        assert_eq!(false, ssn_valid(b"123-00-6789"));
+    }
+
+    #[test]
+    fn test_dlp_body() {
+        let params = Dlp::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+step = "response"
+patterns = ["credit_card", "ssn"]
+regexes = ["token:sk_live_[0-9a-zA-Z]{8}"]
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let body = DlpBody {
+            patterns: params.patterns.clone(),
+            action: params.action,
+            block_message: params.block_message.clone(),
+        };
+        let data = Bytes::from_static(
+            br#"{"card":"4111-1111-1111-1111","order":"1234567890123","ssn":"123-45-6789","token":"sk_live_abcd1234"}"#,
+        );
+        assert_eq!(
+            r#"{"card":"****-****-****-****","order":"1234567890123","ssn":"***-**-****","token":"**_****_********"}"#,
+            std::str::from_utf8(&body.handle(data.clone())).unwrap()
+        );
+        assert_eq!(
+            vec![1, 1, 1],
+            params
+                .patterns
+                .iter()
+                .map(|item| item.matched.load(Ordering::Relaxed))
+                .collect::<Vec<_>>()
+        );
+
+        let body = DlpBody {
+            patterns: params.patterns.clone(),
+            action: super::DlpAction::Block,
+            block_message: params.block_message.clone(),
+        };
+        assert_eq!(
+            b"Response is blocked by data loss prevention",
+            body.handle(data).as_ref()
+        );
+        let data = Bytes::from_static(b"hello world");
+        assert_eq!(data.clone(), body.handle(data));
+    }
+}
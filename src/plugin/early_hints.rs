@@ -0,0 +1,275 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    get_bool_conf, get_hash_key, get_int_conf, get_step_conf,
+    get_str_slice_conf, Error, Plugin, Result,
+};
+use crate::config::{PluginCategory, PluginConf, PluginStep};
+use crate::http_extra::HttpResponse;
+use crate::state::State;
+use ahash::AHashMap;
+use async_trait::async_trait;
+use http::{header, HeaderValue, Method, StatusCode, Version};
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use std::sync::Mutex;
+use tracing::debug;
+
+/// Send `103 Early Hints` with the link headers before the upstream responds,
+/// the links can be configured or learned from the previous response.
+pub struct EarlyHints {
+    plugin_step: PluginStep,
+    links: Vec<HeaderValue>,
+    // learn the preload and preconnect links from upstream response
+    learn: bool,
+    max_paths: usize,
+    learned_links: Mutex<AHashMap<String, Vec<HeaderValue>>>,
+    hash_value: String,
+}
+
+/// Check the link header is preload or preconnect.
+fn is_hint_link(value: &HeaderValue) -> bool {
+    let value = value.to_str().unwrap_or_default().to_lowercase();
+    value.contains("rel=preload")
+        || value.contains("rel=preconnect")
+        || value.contains("rel=\"preload\"")
+        || value.contains("rel=\"preconnect\"")
+}
+
+impl TryFrom<&PluginConf> for EarlyHints {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let step = get_step_conf(value);
+
+        let mut links = vec![];
+        for link in get_str_slice_conf(value, "links") {
+            let link =
+                HeaderValue::from_str(&link).map_err(|e| Error::Invalid {
+                    category: PluginCategory::EarlyHints.to_string(),
+                    message: format!("link({link}) is invalid, {e}"),
+                })?;
+            links.push(link);
+        }
+        let learn = get_bool_conf(value, "learn");
+        if links.is_empty() && !learn {
+            return Err(Error::Invalid {
+                category: PluginCategory::EarlyHints.to_string(),
+                message: "Links should not be empty if learn is disabled"
+                    .to_string(),
+            });
+        }
+        let max_paths = get_int_conf(value, "max_paths");
+
+        let params = Self {
+            hash_value,
+            plugin_step: step,
+            links,
+            learn,
+            max_paths: if max_paths <= 0 {
+                1000
+            } else {
+                max_paths as usize
+            },
+            learned_links: Mutex::new(AHashMap::new()),
+        };
+        if params.plugin_step != PluginStep::Request {
+            return Err(Error::Invalid {
+                category: PluginCategory::EarlyHints.to_string(),
+                message:
+                    "Early hints plugin should be executed at request step"
+                        .to_string(),
+            });
+        }
+        Ok(params)
+    }
+}
+
+impl EarlyHints {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new early hints plugin");
+        Self::try_from(params)
+    }
+    /// Get the links of path, the configured links are in front of
+    /// the learned links.
+    fn get_links(&self, path: &str) -> Vec<HeaderValue> {
+        let mut links = self.links.clone();
+        if self.learn {
+            let learned_links =
+                self.learned_links.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(values) = learned_links.get(path) {
+                for value in values {
+                    if !links.contains(value) {
+                        links.push(value.clone());
+                    }
+                }
+            }
+        }
+        links
+    }
+    /// Learn the hint links of upstream response.
+    fn learn_links(&self, path: &str, upstream_response: &ResponseHeader) {
+        if !self.learn || !upstream_response.status.is_success() {
+            return;
+        }
+        let links: Vec<HeaderValue> = upstream_response
+            .headers
+            .get_all(header::LINK)
+            .iter()
+            .filter(|value| is_hint_link(value))
+            .cloned()
+            .collect();
+        let mut learned_links =
+            self.learned_links.lock().unwrap_or_else(|e| e.into_inner());
+        if links.is_empty() {
+            learned_links.remove(path);
+            return;
+        }
+        // only the paths which are learned can be updated if it's full
+        if learned_links.len() >= self.max_paths
+            && !learned_links.contains_key(path)
+        {
+            return;
+        }
+        learned_links.insert(path.to_string(), links);
+    }
+}
+
+#[async_trait]
+impl Plugin for EarlyHints {
+    #[inline]
+    fn hash_key(&self) -> String {
+        self.hash_value.clone()
+    }
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        _ctx: &mut State,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        if step != self.plugin_step {
+            return Ok(None);
+        }
+        let req_header = session.req_header();
+        // the informational response is only supported by http/1.1
+        if req_header.method != Method::GET
+            || req_header.version != Version::HTTP_11
+        {
+            return Ok(None);
+        }
+        let links = self.get_links(req_header.uri.path());
+        if links.is_empty() {
+            return Ok(None);
+        }
+        let mut hints = ResponseHeader::build(StatusCode::EARLY_HINTS, None)?;
+        for link in links {
+            hints.append_header(header::LINK, link)?;
+        }
+        session
+            .write_response_header(Box::new(hints), false)
+            .await?;
+        Ok(None)
+    }
+    #[inline]
+    async fn handle_response(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        _ctx: &mut State,
+        upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<()> {
+        if step != PluginStep::Response {
+            return Ok(());
+        }
+        self.learn_links(session.req_header().uri.path(), upstream_response);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EarlyHints;
+    use crate::config::PluginConf;
+    use pingora::http::ResponseHeader;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_early_hints_params() {
+        let result = EarlyHints::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+links = []
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin early_hints invalid, message: Links should not be empty if learn is disabled",
+            result.err().unwrap().to_string()
+        );
+
+        let result = EarlyHints::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+step = "response"
+learn = true
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin early_hints invalid, message: Early hints plugin should be executed at request step",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_early_hints_links() {
+        let params = EarlyHints::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+links = ["</style.css>; rel=preload; as=style"]
+learn = true
+max_paths = 1
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(1, params.get_links("/").len());
+
+        let mut upstream_response = ResponseHeader::build(200, None).unwrap();
+        upstream_response
+            .append_header("Link", "</app.js>; rel=preload; as=script")
+            .unwrap();
+        upstream_response
+            .append_header("Link", "</next>; rel=next")
+            .unwrap();
+        params.learn_links("/", &upstream_response);
+        assert_eq!(
+            r#"["</style.css>; rel=preload; as=style", "</app.js>; rel=preload; as=script"]"#,
+            format!("{:?}", params.get_links("/"))
+        );
+
+        // the max paths is 1
+        params.learn_links("/about", &upstream_response);
+        assert_eq!(1, params.get_links("/about").len());
+
+        // the links are removed if the response has no hint link
+        params.learn_links("/", &ResponseHeader::build(200, None).unwrap());
+        assert_eq!(1, params.get_links("/").len());
+    }
+}
@@ -0,0 +1,548 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    get_hash_key, get_int_conf, get_local_addr, get_step_conf, get_str_conf,
+    Error, Plugin, Result,
+};
+use crate::config::{PluginCategory, PluginConf, PluginStep};
+use crate::http_extra::HttpResponse;
+use crate::state::State;
+use crate::util;
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::{header, HeaderName, HeaderValue, Method, StatusCode};
+use humantime::parse_duration;
+use nanoid::nanoid;
+use once_cell::sync::Lazy;
+use pingora::proxy::Session;
+use std::time::Duration;
+use tracing::{debug, error};
+use url::Url;
+
+static HTTP_HEADER_ESI: &str = "x-pingap-esi";
+
+// the token of fragment request, it's generated for each process,
+// so the esi processing can't be skipped by the client
+static ESI_TOKEN: Lazy<String> = Lazy::new(|| nanoid!(32));
+
+const ESI_INCLUDE_TAG: &str = "<esi:include";
+const ESI_REMOVE_TAG: &str = "<esi:remove>";
+const ESI_REMOVE_END_TAG: &str = "</esi:remove>";
+const ESI_COMMENT_TAG: &str = "<!--esi";
+
+#[derive(Debug, PartialEq)]
+enum EsiSegment {
+    Text(String),
+    Include {
+        src: String,
+        alt: Option<String>,
+        // the error of fragment is ignored
+        continue_on_error: bool,
+    },
+}
+
+/// Get the value of tag's attribute, both single and double quotes
+/// are supported.
+fn get_attr(tag: &str, name: &str) -> Option<String> {
+    let mut rest = tag;
+    let key = format!("{name}=");
+    while let Some(pos) = rest.find(&key) {
+        let before = rest[..pos].chars().last().unwrap_or_default();
+        rest = &rest[pos + key.len()..];
+        if !before.is_ascii_whitespace() {
+            continue;
+        }
+        let quote = rest.chars().next()?;
+        if quote != '"' && quote != '\'' {
+            return None;
+        }
+        let end = rest[1..].find(quote)?;
+        return Some(rest[1..end + 1].replace("&amp;", "&"));
+    }
+    None
+}
+
+fn push_text(segments: &mut Vec<EsiSegment>, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    if let Some(EsiSegment::Text(value)) = segments.last_mut() {
+        value.push_str(text);
+    } else {
+        segments.push(EsiSegment::Text(text.to_string()));
+    }
+}
+
+/// Parse the html to segments of text and include, the `<esi:remove>`
+/// block is dropped and the content of `<!--esi -->` is parsed.
+fn parse_esi(data: &str) -> Vec<EsiSegment> {
+    let mut segments = vec![];
+    let mut rest = data;
+    loop {
+        let found = [ESI_INCLUDE_TAG, ESI_REMOVE_TAG, ESI_COMMENT_TAG]
+            .iter()
+            .filter_map(|tag| rest.find(tag).map(|pos| (pos, *tag)))
+            .min_by_key(|(pos, _)| *pos);
+        let Some((pos, tag)) = found else {
+            push_text(&mut segments, rest);
+            break;
+        };
+        push_text(&mut segments, &rest[..pos]);
+        rest = &rest[pos..];
+        match tag {
+            ESI_INCLUDE_TAG => {
+                let Some(end) = rest.find('>') else {
+                    push_text(&mut segments, rest);
+                    break;
+                };
+                let value = &rest[..end + 1];
+                rest = &rest[end + 1..];
+                if !value.ends_with("/>") {
+                    rest = rest
+                        .trim_start()
+                        .strip_prefix("</esi:include>")
+                        .unwrap_or(rest);
+                }
+                if let Some(src) = get_attr(value, "src") {
+                    segments.push(EsiSegment::Include {
+                        src,
+                        alt: get_attr(value, "alt"),
+                        continue_on_error: get_attr(value, "onerror")
+                            .map(|value| value == "continue")
+                            .unwrap_or_default(),
+                    });
+                }
+            },
+            ESI_REMOVE_TAG => {
+                rest = rest
+                    .find(ESI_REMOVE_END_TAG)
+                    .map(|end| &rest[end + ESI_REMOVE_END_TAG.len()..])
+                    .unwrap_or_default();
+            },
+            _ => {
+                let content = &rest[ESI_COMMENT_TAG.len()..];
+                let (inner, next) =
+                    content.split_once("-->").unwrap_or((content, ""));
+                for segment in parse_esi(inner) {
+                    match segment {
+                        EsiSegment::Text(text) => {
+                            push_text(&mut segments, &text)
+                        },
+                        _ => segments.push(segment),
+                    }
+                }
+                rest = next;
+            },
+        }
+    }
+    segments
+}
+
+/// Get the path of fragment, only the relative path and the url
+/// of the same host are allowed.
+fn get_fragment_path(src: &str, host: &str) -> Option<String> {
+    if src.starts_with('/') && !src.starts_with("//") {
+        return Some(src.to_string());
+    }
+    let info = Url::parse(src).ok()?;
+    let mut fragment_host = info.host_str()?.to_string();
+    if let Some(port) = info.port() {
+        fragment_host = format!("{fragment_host}:{port}");
+    }
+    if fragment_host != host {
+        return None;
+    }
+    Some(info[url::Position::BeforePath..].to_string())
+}
+
+/// Check whether the response may contain esi tags, the upstream can
+/// set `Surrogate-Control: content="ESI/1.0"` or return html.
+fn is_esi_response(headers: &reqwest::header::HeaderMap) -> bool {
+    let get_value = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_lowercase()
+    };
+    get_value("surrogate-control").contains("esi/1.0")
+        || get_value(header::CONTENT_TYPE.as_str()).starts_with("text/html")
+}
+
+/// Process the Edge Side Includes of html, the page and fragments are
+/// fetched through the listener of server, so each of them can be cached
+/// with its own ttl by the cache plugin of location.
+pub struct Esi {
+    plugin_step: PluginStep,
+    // the plain http listener for fragment requests,
+    // the listener of current request is used if it's empty
+    addr: String,
+    max_includes: usize,
+    client: reqwest::Client,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for Esi {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let step = get_step_conf(value);
+        let timeout = get_str_conf(value, "timeout");
+        let timeout = if timeout.is_empty() {
+            Duration::from_secs(10)
+        } else {
+            parse_duration(&timeout).map_err(|e| Error::Invalid {
+                category: PluginCategory::Esi.to_string(),
+                message: e.to_string(),
+            })?
+        };
+        let mut max_includes = get_int_conf(value, "max_includes") as usize;
+        if max_includes == 0 {
+            max_includes = 20;
+        }
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .map_err(|e| Error::Invalid {
+                category: PluginCategory::Esi.to_string(),
+                message: e.to_string(),
+            })?;
+
+        let params = Self {
+            hash_value,
+            plugin_step: step,
+            addr: get_str_conf(value, "addr"),
+            max_includes,
+            client,
+        };
+        if params.plugin_step != PluginStep::Request {
+            return Err(Error::Invalid {
+                category: PluginCategory::Esi.to_string(),
+                message: "Esi plugin should be executed at request step"
+                    .to_string(),
+            });
+        }
+        Ok(params)
+    }
+}
+
+impl Esi {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new esi plugin");
+        Self::try_from(params)
+    }
+    async fn fetch(
+        &self,
+        url: &str,
+        headers: &reqwest::header::HeaderMap,
+    ) -> std::result::Result<Bytes, String> {
+        let resp = self
+            .client
+            .get(url)
+            .headers(headers.clone())
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        let status = resp.status();
+        if !status.is_success() {
+            return Err(format!("status: {}", status.as_u16()));
+        }
+        resp.bytes().await.map_err(|e| e.to_string())
+    }
+    /// Fetch the fragment, the alt source is used if the src fails.
+    async fn fetch_fragment(
+        &self,
+        addr: &str,
+        host: &str,
+        headers: &reqwest::header::HeaderMap,
+        segment: &EsiSegment,
+    ) -> std::result::Result<Bytes, String> {
+        let EsiSegment::Include {
+            src,
+            alt,
+            continue_on_error,
+        } = segment
+        else {
+            return Ok(Bytes::new());
+        };
+        let mut sources = vec![src];
+        if let Some(alt) = alt {
+            sources.push(alt);
+        }
+        let mut message = "".to_string();
+        for src in sources {
+            let Some(path) = get_fragment_path(src, host) else {
+                message = format!("fragment({src}) is not allowed");
+                continue;
+            };
+            match self.fetch(&format!("http://{addr}{path}"), headers).await {
+                Ok(body) => return Ok(body),
+                Err(e) => {
+                    message = e;
+                },
+            }
+        }
+        error!(src, error = message, "fetch esi fragment fail");
+        if *continue_on_error {
+            return Ok(Bytes::new());
+        }
+        Err(message)
+    }
+}
+
+#[async_trait]
+impl Plugin for Esi {
+    #[inline]
+    fn hash_key(&self) -> String {
+        self.hash_value.clone()
+    }
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        _ctx: &mut State,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        if step != self.plugin_step {
+            return Ok(None);
+        }
+        let req = session.req_header_mut();
+        if let Some(value) = req.remove_header(HTTP_HEADER_ESI) {
+            // the page or fragment request of esi is proxied as usual
+            if value.as_bytes() == ESI_TOKEN.as_bytes() {
+                return Ok(None);
+            }
+        }
+        if req.method != Method::GET {
+            return Ok(None);
+        }
+        let Some(addr) = get_local_addr(session, &self.addr) else {
+            return Ok(None);
+        };
+        let req = session.req_header();
+        let host = util::get_host(req).unwrap_or_default().to_string();
+        let path = req
+            .uri
+            .path_and_query()
+            .map(|value| value.to_string())
+            .unwrap_or_else(|| "/".to_string());
+
+        let mut headers = reqwest::header::HeaderMap::new();
+        for (name, value) in req.headers.iter() {
+            if [
+                header::CONNECTION,
+                header::CONTENT_LENGTH,
+                header::ACCEPT_ENCODING,
+                header::IF_NONE_MATCH,
+                header::IF_MODIFIED_SINCE,
+                header::RANGE,
+            ]
+            .contains(name)
+            {
+                continue;
+            }
+            if let (Ok(name), Ok(value)) = (
+                reqwest::header::HeaderName::from_bytes(name.as_ref()),
+                reqwest::header::HeaderValue::from_bytes(value.as_bytes()),
+            ) {
+                headers.append(name, value);
+            }
+        }
+        if let Ok(value) =
+            reqwest::header::HeaderValue::from_str(ESI_TOKEN.as_str())
+        {
+            headers.insert(HTTP_HEADER_ESI, value);
+        }
+
+        let resp = self
+            .client
+            .get(format!("http://{addr}{path}"))
+            .headers(headers.clone())
+            .send()
+            .await
+            .map_err(|e| util::new_internal_error(502, e.to_string()))?;
+        let status = StatusCode::from_u16(resp.status().as_u16())
+            .unwrap_or(StatusCode::BAD_GATEWAY);
+        let esi_enabled = is_esi_response(resp.headers());
+        let mut resp_headers = vec![];
+        for (name, value) in resp.headers().iter() {
+            if ["connection", "content-length", "transfer-encoding"]
+                .contains(&name.as_str())
+            {
+                continue;
+            }
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_ref()),
+                HeaderValue::from_bytes(value.as_bytes()),
+            ) {
+                resp_headers.push((name, value));
+            }
+        }
+        let body = resp
+            .bytes()
+            .await
+            .map_err(|e| util::new_internal_error(502, e.to_string()))?;
+        let data = std::str::from_utf8(&body).unwrap_or_default();
+        if !esi_enabled || !status.is_success() || !data.contains("<esi:") {
+            return Ok(Some(HttpResponse {
+                status,
+                body,
+                headers: Some(resp_headers),
+                ..Default::default()
+            }));
+        }
+
+        let segments = parse_esi(data);
+        let fragments = futures::future::join_all(
+            segments
+                .iter()
+                .filter(|item| matches!(item, EsiSegment::Include { .. }))
+                .take(self.max_includes)
+                .map(|item| self.fetch_fragment(&addr, &host, &headers, item)),
+        )
+        .await;
+        let mut fragments = fragments.into_iter();
+        let mut buf = Vec::with_capacity(body.len() * 2);
+        for segment in segments.iter() {
+            match segment {
+                EsiSegment::Text(text) => {
+                    buf.extend_from_slice(text.as_bytes())
+                },
+                _ => {
+                    // the includes over the limit are ignored
+                    let Some(result) = fragments.next() else {
+                        continue;
+                    };
+                    let fragment =
+                        result.map_err(|e| util::new_internal_error(502, e))?;
+                    buf.extend_from_slice(&fragment);
+                },
+            }
+        }
+        // the assembled page may be personalized, so it's not cached
+        // by the client, the cache control of page is ignored
+        let resp_headers = resp_headers
+            .into_iter()
+            .filter(|(name, _)| {
+                ![
+                    header::CACHE_CONTROL,
+                    header::ETAG,
+                    header::LAST_MODIFIED,
+                    header::EXPIRES,
+                ]
+                .contains(name)
+                    && name.as_str() != "surrogate-control"
+            })
+            .collect();
+        Ok(Some(HttpResponse {
+            status,
+            body: Bytes::from(buf),
+            headers: Some(resp_headers),
+            ..Default::default()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_attr, get_fragment_path, parse_esi, Esi, EsiSegment};
+    use crate::config::PluginConf;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_esi_params() {
+        let result = Esi::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+step = "response"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin esi invalid, message: Esi plugin should be executed at request step",
+            result.err().unwrap().to_string()
+        );
+
+        let esi = Esi::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+addr = "127.0.0.1:6188"
+timeout = "5s"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!("127.0.0.1:6188", esi.addr);
+        assert_eq!(20, esi.max_includes);
+    }
+
+    #[test]
+    fn test_get_attr() {
+        let tag = r#"<esi:include src="/header?a=1&amp;b=2" alt='/alt' onerror="continue"/>"#;
+        assert_eq!(Some("/header?a=1&b=2".to_string()), get_attr(tag, "src"));
+        assert_eq!(Some("/alt".to_string()), get_attr(tag, "alt"));
+        assert_eq!(Some("continue".to_string()), get_attr(tag, "onerror"));
+        assert_eq!(None, get_attr(tag, "rc"));
+        assert_eq!(None, get_attr(r#"<esi:include src=/a />"#, "src"));
+    }
+
+    #[test]
+    fn test_parse_esi() {
+        let data = r#"<html><esi:include src="/header" /><esi:remove><a href="/header">header</a></esi:remove><!--esi <p>Hi</p><esi:include src="/user" alt="/guest" onerror="continue"></esi:include>--><p>body</p></html>"#;
+        assert_eq!(
+            vec![
+                EsiSegment::Text("<html>".to_string()),
+                EsiSegment::Include {
+                    src: "/header".to_string(),
+                    alt: None,
+                    continue_on_error: false,
+                },
+                EsiSegment::Text(" <p>Hi</p>".to_string()),
+                EsiSegment::Include {
+                    src: "/user".to_string(),
+                    alt: Some("/guest".to_string()),
+                    continue_on_error: true,
+                },
+                EsiSegment::Text("<p>body</p></html>".to_string()),
+            ],
+            parse_esi(data)
+        );
+
+        assert_eq!(
+            vec![EsiSegment::Text("<p>esi:include</p>".to_string())],
+            parse_esi("<p>esi:include</p>")
+        );
+    }
+
+    #[test]
+    fn test_get_fragment_path() {
+        assert_eq!(
+            Some("/header?a=1".to_string()),
+            get_fragment_path("/header?a=1", "pingap.io")
+        );
+        assert_eq!(
+            Some("/header".to_string()),
+            get_fragment_path("https://pingap.io/header", "pingap.io")
+        );
+        assert_eq!(None, get_fragment_path("//github.com/header", "pingap.io"));
+        assert_eq!(
+            None,
+            get_fragment_path("https://github.com/header", "pingap.io")
+        );
+    }
+}
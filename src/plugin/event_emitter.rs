@@ -0,0 +1,410 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    get_hash_key, get_step_conf, get_str_conf, get_str_slice_conf, Error,
+    Plugin, Result,
+};
+use crate::config::{PluginCategory, PluginConf, PluginStep};
+use crate::state::State;
+use crate::util;
+use async_trait::async_trait;
+use http::HeaderName;
+use humantime::parse_duration;
+use once_cell::sync::OnceCell;
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use serde_json::{json, Map, Value};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{debug, error, warn};
+
+// the max events waiting to be sent, the new event is dropped if it's full
+const MAX_PENDING_EVENTS: usize = 1024;
+
+pub struct EventEmitter {
+    plugin_step: PluginStep,
+    url: String,
+    // kafka topic, the event will be sent to kafka rest proxy
+    topic: String,
+    status_list: Vec<u16>,
+    // response header conditions, (name, value)
+    headers: Vec<(HeaderName, Option<String>)>,
+    timeout: Duration,
+    client: reqwest::Client,
+    // the events are sent one by one by a single worker,
+    // it's created when the first event is emitted
+    sender: OnceCell<mpsc::Sender<Value>>,
+    // the count of events dropped because the queue is full
+    dropped: AtomicU64,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for EventEmitter {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let step = get_step_conf(value);
+
+        let mut status_list = vec![];
+        if let Some(arr) = value.get("status").and_then(|v| v.as_array()) {
+            for item in arr.iter() {
+                if let Some(status) = item.as_integer() {
+                    status_list.push(status as u16);
+                }
+            }
+        }
+        let mut headers = vec![];
+        for item in get_str_slice_conf(value, "headers").iter() {
+            let (name, value) =
+                if let Some((name, value)) = item.split_once(':') {
+                    (name.trim(), Some(value.trim().to_string()))
+                } else {
+                    (item.trim(), None)
+                };
+            let name =
+                HeaderName::from_str(name).map_err(|e| Error::Invalid {
+                    category: PluginCategory::EventEmitter.to_string(),
+                    message: e.to_string(),
+                })?;
+            headers.push((name, value));
+        }
+        let timeout = get_str_conf(value, "timeout");
+        let timeout = if timeout.is_empty() {
+            Duration::from_secs(10)
+        } else {
+            parse_duration(&timeout).map_err(|e| Error::Invalid {
+                category: PluginCategory::EventEmitter.to_string(),
+                message: e.to_string(),
+            })?
+        };
+
+        let params = Self {
+            hash_value,
+            plugin_step: step,
+            url: get_str_conf(value, "url"),
+            topic: get_str_conf(value, "topic"),
+            status_list,
+            headers,
+            timeout,
+            client: reqwest::Client::new(),
+            sender: OnceCell::new(),
+            dropped: AtomicU64::new(0),
+        };
+        if params.url.is_empty() {
+            return Err(Error::Invalid {
+                category: PluginCategory::EventEmitter.to_string(),
+                message: "Url is not allowed empty".to_string(),
+            });
+        }
+        if params.status_list.is_empty() && params.headers.is_empty() {
+            return Err(Error::Invalid {
+                category: PluginCategory::EventEmitter.to_string(),
+                message: "Status and headers are not allowed both empty"
+                    .to_string(),
+            });
+        }
+        if params.plugin_step != PluginStep::Response {
+            return Err(Error::Invalid {
+                category: PluginCategory::EventEmitter.to_string(),
+                message:
+                    "Event emitter plugin should be executed at response step"
+                        .to_string(),
+            });
+        }
+        Ok(params)
+    }
+}
+
+impl EventEmitter {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new event emitter plugin");
+        Self::try_from(params)
+    }
+    /// The response matches all conditions,
+    /// status(any of the list) and headers(any of the list).
+    fn matched(&self, upstream_response: &ResponseHeader) -> bool {
+        if !self.status_list.is_empty()
+            && !self
+                .status_list
+                .contains(&upstream_response.status.as_u16())
+        {
+            return false;
+        }
+        if self.headers.is_empty() {
+            return true;
+        }
+        self.headers.iter().any(|(name, value)| {
+            let Some(header_value) = upstream_response.headers.get(name) else {
+                return false;
+            };
+            if let Some(value) = value {
+                header_value.as_bytes() == value.as_bytes()
+            } else {
+                true
+            }
+        })
+    }
+    /// Emit the event to the queue, it's sent by the worker in background,
+    /// so the response is not blocked. The event is dropped if the queue
+    /// is full, it avoids piling up if the sink is slow.
+    fn emit(&self, event: Value) {
+        let sender = self.sender.get_or_init(|| {
+            let (sender, mut receiver) = mpsc::channel(MAX_PENDING_EVENTS);
+            let client = self.client.clone();
+            let url = self.url.clone();
+            let topic = self.topic.clone();
+            let timeout = self.timeout;
+            // the worker exits after the plugin is dropped
+            tokio::spawn(async move {
+                while let Some(event) = receiver.recv().await {
+                    send_event(&client, &url, &topic, event, timeout).await;
+                }
+            });
+            sender
+        });
+        if sender.try_send(event).is_err() {
+            #[cfg(feature = "metrics")]
+            crate::state::EVENT_EMITTER_DROPPED.inc();
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            warn!(url = self.url, dropped, "event queue is full, drop event");
+        }
+    }
+    fn new_event(
+        &self,
+        session: &Session,
+        ctx: &State,
+        upstream_response: &ResponseHeader,
+    ) -> Value {
+        let req_header = session.req_header();
+        let mut headers = Map::new();
+        for (name, _) in self.headers.iter() {
+            if let Some(value) = upstream_response.headers.get(name) {
+                headers.insert(
+                    name.to_string(),
+                    Value::String(
+                        value.to_str().unwrap_or_default().to_string(),
+                    ),
+                );
+            }
+        }
+        let client_ip = if let Some(ip) = &ctx.client_ip {
+            ip.to_string()
+        } else {
+            util::get_client_ip(session)
+        };
+        json!({
+            "method": req_header.method.as_str(),
+            "host": util::get_host(req_header).unwrap_or_default(),
+            "uri": req_header.uri.to_string(),
+            "status": upstream_response.status.as_u16(),
+            "headers": headers,
+            "client_ip": client_ip,
+            "request_id": ctx.request_id.clone().unwrap_or_default(),
+            "location": ctx
+                .location
+                .as_ref()
+                .map(|item| item.name.clone())
+                .unwrap_or_default(),
+            "created_at": util::now().as_millis() as u64,
+        })
+    }
+}
+
+async fn send_event(
+    client: &reqwest::Client,
+    url: &str,
+    topic: &str,
+    event: Value,
+    timeout: Duration,
+) {
+    let req = if topic.is_empty() {
+        client.post(url).json(&event)
+    } else {
+        // kafka rest proxy(v2)
+        client
+            .post(format!("{}/topics/{topic}", url.trim_end_matches('/')))
+            .header("Content-Type", "application/vnd.kafka.json.v2+json")
+            .body(
+                json!({
+                    "records": [
+                        {
+                            "value": event
+                        }
+                    ]
+                })
+                .to_string(),
+            )
+    };
+    match req.timeout(timeout).send().await {
+        Ok(res) => {
+            if res.status().as_u16() >= 400 {
+                error!(status = res.status().to_string(), "emit event fail");
+            }
+        },
+        Err(e) => {
+            error!(error = e.to_string(), "emit event fail");
+        },
+    };
+}
+
+#[async_trait]
+impl Plugin for EventEmitter {
+    #[inline]
+    fn hash_key(&self) -> String {
+        self.hash_value.clone()
+    }
+    #[inline]
+    async fn handle_response(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut State,
+        upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<()> {
+        if step != self.plugin_step || !self.matched(upstream_response) {
+            return Ok(());
+        }
+        self.emit(self.new_event(session, ctx, upstream_response));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EventEmitter, MAX_PENDING_EVENTS};
+    use crate::config::PluginConf;
+    use crate::state::State;
+    use pingora::http::ResponseHeader;
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+    use std::sync::atomic::Ordering;
+    use tokio_test::io::Builder;
+
+    #[test]
+    fn test_event_emitter_params() {
+        let params = EventEmitter::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+step = "response"
+url = "http://127.0.0.1:8082"
+topic = "payment"
+status = [402]
+headers = ["X-Payment-Required", "X-Plan:free"]
+timeout = "3s"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!("payment", params.topic);
+        assert_eq!(vec![402], params.status_list);
+        assert_eq!(2, params.headers.len());
+        assert_eq!(3, params.timeout.as_secs());
+
+        let result = EventEmitter::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+step = "response"
+url = "http://127.0.0.1:8082"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin event_emitter invalid, message: Status and headers are not allowed both empty",
+            result.err().unwrap().to_string()
+        );
+
+        let result = EventEmitter::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+url = "http://127.0.0.1:8082"
+status = [402]
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin event_emitter invalid, message: Event emitter plugin should be executed at response step",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_event_emitter() {
+        let params = EventEmitter::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+step = "response"
+url = "http://127.0.0.1:8082"
+status = [402]
+headers = ["X-Plan:free"]
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let mut upstream_response = ResponseHeader::build(402, None).unwrap();
+        assert_eq!(false, params.matched(&upstream_response));
+        upstream_response.insert_header("X-Plan", "free").unwrap();
+        assert_eq!(true, params.matched(&upstream_response));
+
+        let upstream_response = ResponseHeader::build(200, None).unwrap();
+        assert_eq!(false, params.matched(&upstream_response));
+
+        let headers = ["Host: github.com"].join("\r\n");
+        let input_header =
+            format!("GET /vicanso/pingap?size=1 HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let mut upstream_response = ResponseHeader::build(402, None).unwrap();
+        upstream_response.insert_header("X-Plan", "free").unwrap();
+        let event = params.new_event(
+            &session,
+            &State {
+                request_id: Some("123".to_string()),
+                ..Default::default()
+            },
+            &upstream_response,
+        );
+        assert_eq!("github.com", event["host"].as_str().unwrap());
+        assert_eq!(402, event["status"].as_u64().unwrap());
+        assert_eq!("123", event["request_id"].as_str().unwrap());
+        assert_eq!("free", event["headers"]["x-plan"].as_str().unwrap());
+    }
+    #[tokio::test]
+    async fn test_event_emitter_queue() {
+        let params = EventEmitter::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+step = "response"
+url = "http://127.0.0.1:8082"
+status = [402]
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        // the worker doesn't run until yield
+        for index in 0..MAX_PENDING_EVENTS + 2 {
+            params.emit(json!({ "index": index }));
+        }
+        assert_eq!(2, params.dropped.load(Ordering::Relaxed));
+    }
+}
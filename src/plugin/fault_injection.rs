@@ -0,0 +1,431 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{get_hash_key, get_step_conf, get_str_conf, Error, Plugin, Result};
+use crate::config::{PluginCategory, PluginConf, PluginStep};
+use crate::http_extra::HttpResponse;
+use crate::state::{ModifyResponseBody, State};
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use http::{header, HeaderName, StatusCode};
+use humantime::parse_duration;
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::debug;
+
+const FAULT_VARIABLE: &str = "fault_injected";
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ResponseFault {
+    Truncate,
+    Corrupt,
+}
+
+pub struct FaultInjection {
+    plugin_step: PluginStep,
+    // the fraction of matched requests, 0.0 - 1.0
+    fraction: f64,
+    // the request header should be matched, (name, value)
+    header: Option<(HeaderName, Option<String>)>,
+    delay: Option<Duration>,
+    max_delay: Option<Duration>,
+    abort_status: Vec<StatusCode>,
+    response_fault: Option<ResponseFault>,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for FaultInjection {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let step = get_step_conf(value);
+
+        let fraction = value
+            .get("fraction")
+            .and_then(|v| v.as_float())
+            .unwrap_or(1.0);
+        let header = get_str_conf(value, "header");
+        let header = if header.is_empty() {
+            None
+        } else {
+            let (name, value) =
+                if let Some((name, value)) = header.split_once(':') {
+                    (name.trim(), Some(value.trim().to_string()))
+                } else {
+                    (header.trim(), None)
+                };
+            let name =
+                HeaderName::from_str(name).map_err(|e| Error::Invalid {
+                    category: PluginCategory::FaultInjection.to_string(),
+                    message: e.to_string(),
+                })?;
+            Some((name, value))
+        };
+        let parse = |key: &str| -> Result<Option<Duration>> {
+            let value = get_str_conf(value, key);
+            if value.is_empty() {
+                return Ok(None);
+            }
+            let d = parse_duration(&value).map_err(|e| Error::Invalid {
+                category: PluginCategory::FaultInjection.to_string(),
+                message: e.to_string(),
+            })?;
+            Ok(Some(d))
+        };
+        let delay = parse("delay")?;
+        let max_delay = parse("max_delay")?;
+        let mut abort_status = vec![];
+        if let Some(arr) = value.get("abort_status").and_then(|v| v.as_array())
+        {
+            for item in arr.iter() {
+                let status = item.as_integer().unwrap_or_default() as u16;
+                let status = StatusCode::from_u16(status).map_err(|e| {
+                    Error::Invalid {
+                        category: PluginCategory::FaultInjection.to_string(),
+                        message: e.to_string(),
+                    }
+                })?;
+                abort_status.push(status);
+            }
+        }
+        let response_fault =
+            match get_str_conf(value, "response_fault").as_str() {
+                "truncate" => Some(ResponseFault::Truncate),
+                "corrupt" => Some(ResponseFault::Corrupt),
+                _ => None,
+            };
+
+        let params = Self {
+            hash_value,
+            plugin_step: step,
+            fraction,
+            header,
+            delay,
+            max_delay,
+            abort_status,
+            response_fault,
+        };
+        if !(0.0..=1.0).contains(&params.fraction) {
+            return Err(Error::Invalid {
+                category: PluginCategory::FaultInjection.to_string(),
+                message: "Fraction should be between 0 and 1".to_string(),
+            });
+        }
+        if params.delay.is_none()
+            && params.abort_status.is_empty()
+            && params.response_fault.is_none()
+        {
+            return Err(Error::Invalid {
+                category: PluginCategory::FaultInjection.to_string(),
+                message: "Delay, abort status and response fault are not allowed all empty".to_string(),
+            });
+        }
+        if params.plugin_step != PluginStep::Request {
+            return Err(Error::Invalid {
+                category: PluginCategory::FaultInjection.to_string(),
+                message:
+                    "Fault injection plugin should be executed at request step"
+                        .to_string(),
+            });
+        }
+        Ok(params)
+    }
+}
+
+impl FaultInjection {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new fault injection plugin");
+        Self::try_from(params)
+    }
+    /// Whether the request is matched by header and fraction.
+    fn matched(&self, session: &Session) -> bool {
+        if let Some((name, value)) = &self.header {
+            let Some(header_value) = session.req_header().headers.get(name)
+            else {
+                return false;
+            };
+            if let Some(value) = value {
+                if header_value.as_bytes() != value.as_bytes() {
+                    return false;
+                }
+            }
+        }
+        self.fraction >= 1.0 || rand::random::<f64>() < self.fraction
+    }
+    /// Get the delay duration, it's random between delay and max delay.
+    fn get_delay(&self) -> Option<Duration> {
+        let delay = self.delay?;
+        match self.max_delay {
+            Some(max_delay) if max_delay > delay => {
+                let offset = (max_delay - delay).as_millis() as f64
+                    * rand::random::<f64>();
+                Some(delay + Duration::from_millis(offset as u64))
+            },
+            _ => Some(delay),
+        }
+    }
+}
+
+struct FaultBody {
+    fault: ResponseFault,
+}
+
+impl ModifyResponseBody for FaultBody {
+    fn handle(&self, data: Bytes) -> Bytes {
+        match self.fault {
+            ResponseFault::Truncate => data.slice(0..data.len() / 2),
+            ResponseFault::Corrupt => {
+                if data.is_empty() {
+                    return data;
+                }
+                let mut buf = BytesMut::from(data.as_ref());
+                // flip about 1% bytes of the body
+                let count = (buf.len() / 100).max(1);
+                for _ in 0..count {
+                    let index = rand::random::<usize>() % buf.len();
+                    buf[index] = !buf[index];
+                }
+                buf.freeze()
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl Plugin for FaultInjection {
+    #[inline]
+    fn hash_key(&self) -> String {
+        self.hash_value.clone()
+    }
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut State,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        if step != self.plugin_step || !self.matched(session) {
+            return Ok(None);
+        }
+        if let Some(delay) = self.get_delay() {
+            sleep(delay).await;
+        }
+        if !self.abort_status.is_empty() {
+            let index = rand::random::<usize>() % self.abort_status.len();
+            let status = self.abort_status[index];
+            return Ok(Some(HttpResponse {
+                status,
+                body: Bytes::from(format!(
+                    "Fault injection, status: {}",
+                    status.as_u16()
+                )),
+                ..Default::default()
+            }));
+        }
+        if let Some(fault) = self.response_fault {
+            let value = match fault {
+                ResponseFault::Truncate => "truncate",
+                ResponseFault::Corrupt => "corrupt",
+            };
+            ctx.add_variable(FAULT_VARIABLE, value);
+        }
+        Ok(None)
+    }
+    #[inline]
+    async fn handle_response(
+        &self,
+        step: PluginStep,
+        _session: &mut Session,
+        ctx: &mut State,
+        upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<()> {
+        if step != PluginStep::Response {
+            return Ok(());
+        }
+        let Some(fault) = self.response_fault else {
+            return Ok(());
+        };
+        let injected = ctx
+            .variables
+            .as_ref()
+            .map(|variables| {
+                variables.contains_key(&format!("${FAULT_VARIABLE}"))
+            })
+            .unwrap_or_default();
+        if !injected {
+            return Ok(());
+        }
+        upstream_response.remove_header(&header::CONTENT_LENGTH);
+        let _ = upstream_response
+            .insert_header(header::TRANSFER_ENCODING, "Chunked");
+        ctx.modify_response_body = Some(Box::new(FaultBody { fault }));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FaultBody, FaultInjection, ResponseFault};
+    use crate::config::{PluginConf, PluginStep};
+    use crate::plugin::Plugin;
+    use crate::state::{ModifyResponseBody, State};
+    use bytes::Bytes;
+    use pingora::http::ResponseHeader;
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use tokio_test::io::Builder;
+
+    #[test]
+    fn test_fault_injection_params() {
+        let params = FaultInjection::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+fraction = 0.1
+header = "X-Chaos:on"
+delay = "100ms"
+max_delay = "1s"
+abort_status = [500, 503]
+response_fault = "truncate"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(0.1, params.fraction);
+        assert_eq!("x-chaos", params.header.as_ref().unwrap().0.as_str());
+        assert_eq!(100, params.delay.unwrap().as_millis());
+        assert_eq!(2, params.abort_status.len());
+        assert_eq!(Some(ResponseFault::Truncate), params.response_fault);
+
+        let result = FaultInjection::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+fraction = 0.1
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin fault_injection invalid, message: Delay, abort status and response fault are not allowed all empty",
+            result.err().unwrap().to_string()
+        );
+
+        let result = FaultInjection::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+fraction = 2.0
+delay = "1s"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin fault_injection invalid, message: Fraction should be between 0 and 1",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fault_injection() {
+        let params = FaultInjection::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+header = "X-Chaos"
+abort_status = [503]
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        let input_header = "GET /vicanso/pingap HTTP/1.1\r\n\r\n";
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let result = params
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_none());
+
+        let input_header = "GET /vicanso/pingap HTTP/1.1\r\nX-Chaos: 1\r\n\r\n";
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let result = params
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(503, result.unwrap().status.as_u16());
+
+        let params = FaultInjection::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+response_fault = "truncate"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let mut ctx = State::default();
+        let result = params
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_none());
+        let mut upstream_response = ResponseHeader::build(200, None).unwrap();
+        upstream_response
+            .insert_header("Content-Length", "12")
+            .unwrap();
+        params
+            .handle_response(
+                PluginStep::Response,
+                &mut session,
+                &mut ctx,
+                &mut upstream_response,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            true,
+            upstream_response.headers.get("Content-Length").is_none()
+        );
+        let data = ctx
+            .modify_response_body
+            .unwrap()
+            .handle(Bytes::from_static(b"Hello World!"));
+        assert_eq!(b"Hello ", data.as_ref());
+    }
+
+    #[test]
+    fn test_fault_body() {
+        let body = FaultBody {
+            fault: ResponseFault::Corrupt,
+        };
+        let data = body.handle(Bytes::from_static(b"Hello World!"));
+        assert_eq!(12, data.len());
+        assert_ne!(b"Hello World!", data.as_ref());
+    }
+}
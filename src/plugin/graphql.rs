@@ -0,0 +1,1025 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    get_bool_conf, get_hash_key, get_int_conf, get_step_conf, get_str_conf,
+    read_request_body, Error, Plugin, Result, MAX_REQUEST_BODY_SIZE,
+};
+use crate::cache::{CacheObject, HttpCache};
+use crate::config::{PluginCategory, PluginConf, PluginStep};
+use crate::http_extra::HttpResponse;
+use crate::state::State;
+use crate::util;
+use async_trait::async_trait;
+use bytes::Bytes;
+use bytesize::ByteSize;
+use http::{header, Method};
+use pingora::proxy::Session;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::str::FromStr;
+use tracing::debug;
+
+const PERSISTED_QUERY_PREFIX: &str = "graphql_apq_";
+// the max nesting of selection sets for parsing,
+// it avoids stack overflow of malicious query
+const MAX_NESTING: usize = 256;
+const VALIDATION_FAILED: &str = "GRAPHQL_VALIDATION_FAILED";
+
+pub struct Graphql {
+    plugin_step: PluginStep,
+    path: String,
+    persisted_queries: bool,
+    max_body_size: usize,
+    // max depth of query, 0 means unlimited
+    max_depth: usize,
+    // max complexity(count of fields) of query, 0 means unlimited
+    max_complexity: usize,
+    // max operations of batching request, 0 means unlimited
+    max_batch: usize,
+    block_introspection: bool,
+    http_cache: &'static HttpCache,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for Graphql {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let step = get_step_conf(value);
+        let mut path = get_str_conf(value, "path");
+        if path.is_empty() {
+            path = "/graphql".to_string();
+        }
+        // persisted queries is enabled by default
+        let persisted_queries = !value.contains_key("persisted_queries")
+            || get_bool_conf(value, "persisted_queries");
+
+        let max_body_size = get_str_conf(value, "max_body_size");
+        let max_body_size = if max_body_size.is_empty() {
+            MAX_REQUEST_BODY_SIZE
+        } else {
+            let size = ByteSize::from_str(&max_body_size).map_err(|e| {
+                Error::Invalid {
+                    category: PluginCategory::Graphql.to_string(),
+                    message: e.to_string(),
+                }
+            })?;
+            (size.as_u64() as usize).min(MAX_REQUEST_BODY_SIZE)
+        };
+
+        let params = Self {
+            hash_value,
+            plugin_step: step,
+            path,
+            persisted_queries,
+            max_body_size,
+            max_depth: get_int_conf(value, "max_depth").max(0) as usize,
+            max_complexity: get_int_conf(value, "max_complexity").max(0)
+                as usize,
+            max_batch: get_int_conf(value, "max_batch").max(0) as usize,
+            block_introspection: get_bool_conf(value, "block_introspection"),
+            http_cache: super::cache::get_cache_backend()?,
+        };
+        if params.plugin_step != PluginStep::Request {
+            return Err(Error::Invalid {
+                category: PluginCategory::Graphql.to_string(),
+                message: "Graphql plugin should be executed at request step"
+                    .to_string(),
+            });
+        }
+        Ok(params)
+    }
+}
+
+impl Graphql {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new graphql plugin");
+        Self::try_from(params)
+    }
+    fn protection_enabled(&self) -> bool {
+        self.max_depth > 0
+            || self.max_complexity > 0
+            || self.max_batch > 0
+            || self.block_introspection
+    }
+    /// Validate the query by depth, complexity and introspection,
+    /// the error message is returned if the query is not allowed.
+    fn validate_query(&self, query: &str) -> Option<String> {
+        if !self.protection_enabled() {
+            return None;
+        }
+        let Some(analysis) = analyze_query(query) else {
+            return Some(format!(
+                "Query nesting exceeds the limit {MAX_NESTING}"
+            ));
+        };
+        if self.max_depth > 0 && analysis.depth > self.max_depth {
+            return Some(format!(
+                "Query depth {} exceeds the max depth {}",
+                analysis.depth, self.max_depth
+            ));
+        }
+        if self.max_complexity > 0 && analysis.complexity > self.max_complexity
+        {
+            return Some(format!(
+                "Query complexity {} exceeds the max complexity {}",
+                analysis.complexity, self.max_complexity
+            ));
+        }
+        if self.block_introspection && analysis.introspection {
+            return Some("GraphQL introspection is not allowed".to_string());
+        }
+        None
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Name(String),
+    Punct(char),
+    Spread,
+    // string, number and others
+    Other,
+}
+
+/// Tokenize the graphql query, the string values and comments are ignored.
+fn tokenize(query: &str) -> Vec<Token> {
+    let chars: Vec<char> = query.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '#' => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            },
+            '"' => {
+                // block string
+                if chars[i..].starts_with(&['"', '"', '"']) {
+                    i += 3;
+                    while i < chars.len()
+                        && !chars[i..].starts_with(&['"', '"', '"'])
+                    {
+                        i += 1;
+                    }
+                    i += 3;
+                } else {
+                    i += 1;
+                    while i < chars.len() && chars[i] != '"' {
+                        if chars[i] == '\\' {
+                            i += 1;
+                        }
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                tokens.push(Token::Other);
+            },
+            '.' => {
+                if chars[i..].starts_with(&['.', '.', '.']) {
+                    tokens.push(Token::Spread);
+                    i += 3;
+                } else {
+                    i += 1;
+                }
+            },
+            '{' | '}' | '(' | ')' | ':' | '@' | '$' | '[' | ']' | '=' | '!' => {
+                tokens.push(Token::Punct(c));
+                i += 1;
+            },
+            _ if c.is_ascii_alphabetic() || c == '_' => {
+                let start = i;
+                while i < chars.len()
+                    && (chars[i].is_ascii_alphanumeric() || chars[i] == '_')
+                {
+                    i += 1;
+                }
+                tokens.push(Token::Name(chars[start..i].iter().collect()));
+            },
+            _ if c.is_ascii_digit() || c == '-' => {
+                while i < chars.len()
+                    && (chars[i].is_ascii_alphanumeric()
+                        || ['-', '.', '+'].contains(&chars[i]))
+                {
+                    i += 1;
+                }
+                tokens.push(Token::Other);
+            },
+            _ => {
+                // whitespace, comma and others
+                i += 1;
+            },
+        }
+    }
+    tokens
+}
+
+#[derive(Debug)]
+enum Selection {
+    Field {
+        name: String,
+        children: Vec<Selection>,
+    },
+    // fragment spread
+    Spread(String),
+    InlineFragment(Vec<Selection>),
+}
+
+struct QueryParser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl QueryParser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+    fn is_punct(&self, c: char) -> bool {
+        self.peek() == Some(&Token::Punct(c))
+    }
+    /// Skip the balanced tokens, e.g. arguments `(...)`.
+    fn skip_balanced(&mut self, open: char, close: char) {
+        let mut count = 0;
+        while let Some(token) = self.peek() {
+            if token == &Token::Punct(open) {
+                count += 1;
+            } else if token == &Token::Punct(close) {
+                count -= 1;
+            }
+            self.pos += 1;
+            if count == 0 {
+                break;
+            }
+        }
+    }
+    fn skip_directives(&mut self) {
+        while self.is_punct('@') {
+            // @ and name
+            self.pos += 2;
+            if self.is_punct('(') {
+                self.skip_balanced('(', ')');
+            }
+        }
+    }
+    /// Parse the selection set, the current token should be `{`.
+    fn parse_selection_set(
+        &mut self,
+        nesting: usize,
+    ) -> Option<Vec<Selection>> {
+        if nesting > MAX_NESTING {
+            return None;
+        }
+        let mut selections = vec![];
+        // skip {
+        self.pos += 1;
+        while let Some(token) = self.peek().cloned() {
+            match token {
+                Token::Punct('}') => {
+                    self.pos += 1;
+                    break;
+                },
+                Token::Spread => {
+                    self.pos += 1;
+                    match self.peek().cloned() {
+                        Some(Token::Name(name)) if name != "on" => {
+                            self.pos += 1;
+                            self.skip_directives();
+                            selections.push(Selection::Spread(name));
+                        },
+                        _ => {
+                            // inline fragment: ... on Type @directive { }
+                            if matches!(self.peek(), Some(Token::Name(_))) {
+                                self.pos += 2;
+                            }
+                            self.skip_directives();
+                            if self.is_punct('{') {
+                                let children =
+                                    self.parse_selection_set(nesting + 1)?;
+                                selections
+                                    .push(Selection::InlineFragment(children));
+                            }
+                        },
+                    }
+                },
+                Token::Name(mut name) => {
+                    self.pos += 1;
+                    // alias: name
+                    if self.is_punct(':') {
+                        self.pos += 1;
+                        if let Some(Token::Name(value)) = self.peek().cloned() {
+                            name = value;
+                            self.pos += 1;
+                        }
+                    }
+                    if self.is_punct('(') {
+                        self.skip_balanced('(', ')');
+                    }
+                    self.skip_directives();
+                    let children = if self.is_punct('{') {
+                        self.parse_selection_set(nesting + 1)?
+                    } else {
+                        vec![]
+                    };
+                    selections.push(Selection::Field { name, children });
+                },
+                _ => {
+                    self.pos += 1;
+                },
+            }
+        }
+        Some(selections)
+    }
+    /// Parse the document, returns the operations and fragments.
+    #[allow(clippy::type_complexity)]
+    fn parse(
+        &mut self,
+    ) -> Option<(Vec<Vec<Selection>>, HashMap<String, Vec<Selection>>)> {
+        let mut operations = vec![];
+        let mut fragments = HashMap::new();
+        while let Some(token) = self.peek().cloned() {
+            match token {
+                Token::Punct('{') => {
+                    operations.push(self.parse_selection_set(0)?);
+                },
+                Token::Name(name) if name == "fragment" => {
+                    self.pos += 1;
+                    let fragment_name = match self.peek() {
+                        Some(Token::Name(value)) => value.clone(),
+                        _ => String::new(),
+                    };
+                    // skip to selection set
+                    while self.peek().is_some() && !self.is_punct('{') {
+                        self.pos += 1;
+                    }
+                    if self.peek().is_some() {
+                        fragments.insert(
+                            fragment_name,
+                            self.parse_selection_set(0)?,
+                        );
+                    }
+                },
+                Token::Name(_) => {
+                    // operation: query name($id: ID) @directive { }
+                    self.pos += 1;
+                    while let Some(token) = self.peek() {
+                        match token {
+                            Token::Punct('(') => self.skip_balanced('(', ')'),
+                            Token::Punct('{') => break,
+                            _ => self.pos += 1,
+                        }
+                    }
+                },
+                _ => {
+                    self.pos += 1;
+                },
+            }
+        }
+        Some((operations, fragments))
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct QueryAnalysis {
+    depth: usize,
+    complexity: usize,
+    introspection: bool,
+}
+
+impl QueryAnalysis {
+    fn merge(&mut self, other: &QueryAnalysis) {
+        self.depth = self.depth.max(other.depth);
+        self.complexity = self.complexity.saturating_add(other.complexity);
+        self.introspection = self.introspection || other.introspection;
+    }
+}
+
+fn measure_selections(
+    selections: &[Selection],
+    fragments: &HashMap<String, Vec<Selection>>,
+    measured: &mut HashMap<String, QueryAnalysis>,
+    visiting: &mut Vec<String>,
+) -> QueryAnalysis {
+    let mut analysis = QueryAnalysis::default();
+    for selection in selections.iter() {
+        match selection {
+            Selection::Field { name, children } => {
+                let mut child =
+                    measure_selections(children, fragments, measured, visiting);
+                child.depth += 1;
+                child.complexity = child.complexity.saturating_add(1);
+                if name == "__schema" || name == "__type" {
+                    child.introspection = true;
+                }
+                analysis.merge(&child);
+            },
+            Selection::InlineFragment(children) => {
+                let child =
+                    measure_selections(children, fragments, measured, visiting);
+                analysis.merge(&child);
+            },
+            Selection::Spread(name) => {
+                // the fragment is measured or in cycle
+                if let Some(child) = measured.get(name) {
+                    analysis.merge(child);
+                    continue;
+                }
+                if visiting.contains(name) {
+                    continue;
+                }
+                let Some(children) = fragments.get(name) else {
+                    continue;
+                };
+                visiting.push(name.clone());
+                let child =
+                    measure_selections(children, fragments, measured, visiting);
+                visiting.pop();
+                measured.insert(name.clone(), child);
+                analysis.merge(&child);
+            },
+        }
+    }
+    analysis
+}
+
+/// Analyze the depth, complexity and introspection of graphql query,
+/// it returns `None` if the nesting of query is over limit.
+fn analyze_query(query: &str) -> Option<QueryAnalysis> {
+    let mut parser = QueryParser {
+        tokens: tokenize(query),
+        pos: 0,
+    };
+    let (operations, fragments) = parser.parse()?;
+    let mut measured = HashMap::new();
+    let mut analysis = QueryAnalysis::default();
+    for operation in operations.iter() {
+        let value = measure_selections(
+            operation,
+            &fragments,
+            &mut measured,
+            &mut vec![],
+        );
+        analysis.merge(&value);
+    }
+    Some(analysis)
+}
+
+/// Create a graphql error response, the status of graphql error is `200`.
+fn new_graphql_error(
+    message: &str,
+    code: &str,
+) -> pingora::Result<HttpResponse> {
+    HttpResponse::try_from_json(&json!({
+        "errors": [
+            {
+                "message": message,
+                "extensions": {
+                    "code": code
+                }
+            }
+        ]
+    }))
+}
+
+fn get_persisted_query_hash(extensions: &Value) -> Option<String> {
+    extensions
+        .get("persistedQuery")
+        .and_then(|value| value.get("sha256Hash"))
+        .and_then(|value| value.as_str())
+        .map(|value| value.to_lowercase())
+}
+
+fn sha256_hex(query: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(query.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+enum PersistedQuery {
+    // no persisted query extensions
+    None,
+    // the query is loaded from cache store
+    Loaded(String),
+    // the query and hash are registered to cache store
+    Registered,
+    NotFound,
+    HashMismatch,
+}
+
+impl Graphql {
+    async fn resolve_persisted_query(
+        &self,
+        extensions: Option<&Value>,
+        query: Option<&str>,
+    ) -> pingora::Result<PersistedQuery> {
+        let Some(hash) = extensions.and_then(get_persisted_query_hash) else {
+            return Ok(PersistedQuery::None);
+        };
+        let key = format!("{PERSISTED_QUERY_PREFIX}{hash}");
+        if let Some(query) = query.filter(|value| !value.is_empty()) {
+            if sha256_hex(query) != hash {
+                return Ok(PersistedQuery::HashMismatch);
+            }
+            let obj = CacheObject {
+                meta: (vec![], vec![]),
+                body: Bytes::from(query.to_string()),
+                ..Default::default()
+            };
+            let weight = (obj.body.len() / crate::cache::PAGE_SIZE + 1) as u16;
+            self.http_cache.cached.put(&key, "", obj, weight).await?;
+            return Ok(PersistedQuery::Registered);
+        }
+        let Some(obj) = self.http_cache.cached.get(&key, "").await? else {
+            return Ok(PersistedQuery::NotFound);
+        };
+        Ok(PersistedQuery::Loaded(
+            String::from_utf8_lossy(&obj.body).to_string(),
+        ))
+    }
+    async fn handle_get_request(
+        &self,
+        session: &mut Session,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        if self.persisted_queries {
+            if let Some(resp) =
+                self.resolve_get_persisted_query(session).await?
+            {
+                return Ok(Some(resp));
+            }
+        }
+        let query =
+            util::get_query_value(session.req_header(), "query").map(|value| {
+                urlencoding::decode(value).unwrap_or_default().to_string()
+            });
+        if let Some(message) =
+            query.and_then(|query| self.validate_query(&query))
+        {
+            return Ok(Some(new_graphql_error(&message, VALIDATION_FAILED)?));
+        }
+        Ok(None)
+    }
+    async fn resolve_get_persisted_query(
+        &self,
+        session: &mut Session,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        let req_header = session.req_header();
+        let Some(extensions) = util::get_query_value(req_header, "extensions")
+        else {
+            return Ok(None);
+        };
+        let Ok(extensions) = serde_json::from_str::<Value>(
+            &urlencoding::decode(extensions).unwrap_or_default(),
+        ) else {
+            return Ok(Some(HttpResponse::bad_request(
+                "extensions of graphql is invalid".into(),
+            )));
+        };
+        let query = util::get_query_value(req_header, "query").map(|value| {
+            urlencoding::decode(value).unwrap_or_default().to_string()
+        });
+        match self
+            .resolve_persisted_query(Some(&extensions), query.as_deref())
+            .await?
+        {
+            PersistedQuery::Loaded(query) => {
+                let uri = &session.req_header().uri;
+                let mut path_and_query = format!(
+                    "{}?query={}",
+                    uri.path(),
+                    urlencoding::encode(&query)
+                );
+                if let Some(value) = uri.query() {
+                    path_and_query.push('&');
+                    path_and_query.push_str(value);
+                }
+                if let Ok(uri) = path_and_query.parse::<http::Uri>() {
+                    session.req_header_mut().set_uri(uri);
+                }
+                Ok(None)
+            },
+            PersistedQuery::NotFound => Ok(Some(new_graphql_error(
+                "PersistedQueryNotFound",
+                "PERSISTED_QUERY_NOT_FOUND",
+            )?)),
+            PersistedQuery::HashMismatch => Ok(Some(new_graphql_error(
+                "provided sha does not match query",
+                "BAD_USER_INPUT",
+            )?)),
+            _ => Ok(None),
+        }
+    }
+    async fn handle_post_request(
+        &self,
+        session: &mut Session,
+        ctx: &mut State,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        let body = read_request_body(session, ctx, self.max_body_size).await?;
+        let Ok(mut data) = serde_json::from_slice::<Value>(&body) else {
+            // not json body, forward it to upstream
+            return Ok(None);
+        };
+        let mut modified = false;
+        // batching requests
+        let operations: Vec<&mut Value> = match &mut data {
+            Value::Array(arr) => arr.iter_mut().collect(),
+            value => vec![value],
+        };
+        if self.max_batch > 0 && operations.len() > self.max_batch {
+            return Ok(Some(new_graphql_error(
+                &format!(
+                    "Batch size {} exceeds the max batch size {}",
+                    operations.len(),
+                    self.max_batch
+                ),
+                VALIDATION_FAILED,
+            )?));
+        }
+        for operation in operations {
+            let query = operation
+                .get("query")
+                .and_then(|value| value.as_str())
+                .map(|value| value.to_string());
+            if !self.persisted_queries {
+                if let Some(message) =
+                    query.and_then(|query| self.validate_query(&query))
+                {
+                    return Ok(Some(new_graphql_error(
+                        &message,
+                        VALIDATION_FAILED,
+                    )?));
+                }
+                continue;
+            }
+            match self
+                .resolve_persisted_query(
+                    operation.get("extensions"),
+                    query.as_deref(),
+                )
+                .await?
+            {
+                PersistedQuery::Loaded(query) => {
+                    if let Some(message) = self.validate_query(&query) {
+                        return Ok(Some(new_graphql_error(
+                            &message,
+                            VALIDATION_FAILED,
+                        )?));
+                    }
+                    if let Some(obj) = operation.as_object_mut() {
+                        obj.insert("query".to_string(), Value::String(query));
+                        modified = true;
+                    }
+                },
+                PersistedQuery::NotFound => {
+                    return Ok(Some(new_graphql_error(
+                        "PersistedQueryNotFound",
+                        "PERSISTED_QUERY_NOT_FOUND",
+                    )?));
+                },
+                PersistedQuery::HashMismatch => {
+                    return Ok(Some(new_graphql_error(
+                        "provided sha does not match query",
+                        "BAD_USER_INPUT",
+                    )?));
+                },
+                _ => {
+                    if let Some(message) =
+                        query.and_then(|query| self.validate_query(&query))
+                    {
+                        return Ok(Some(new_graphql_error(
+                            &message,
+                            VALIDATION_FAILED,
+                        )?));
+                    }
+                },
+            };
+        }
+        if modified {
+            let buf = serde_json::to_vec(&data)
+                .map_err(|e| util::new_internal_error(500, e.to_string()))?;
+            session
+                .req_header_mut()
+                .insert_header(header::CONTENT_LENGTH, buf.len().to_string())?;
+            ctx.request_body = Some(Bytes::from(buf));
+        }
+        Ok(None)
+    }
+}
+
+#[async_trait]
+impl Plugin for Graphql {
+    #[inline]
+    fn hash_key(&self) -> String {
+        self.hash_value.clone()
+    }
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut State,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        if step != self.plugin_step {
+            return Ok(None);
+        }
+        if (!self.persisted_queries && !self.protection_enabled())
+            || session.req_header().uri.path() != self.path
+        {
+            return Ok(None);
+        }
+        match session.req_header().method {
+            Method::GET => self.handle_get_request(session).await,
+            Method::POST => self.handle_post_request(session, ctx).await,
+            _ => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        analyze_query, sha256_hex, Graphql, QueryAnalysis,
+        PERSISTED_QUERY_PREFIX,
+    };
+    use crate::config::{PluginConf, PluginStep};
+    use crate::plugin::Plugin;
+    use crate::state::State;
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use tokio_test::io::Builder;
+
+    #[test]
+    fn test_graphql_params() {
+        let params = Graphql::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+max_body_size = "10kb"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!("/graphql", params.path);
+        assert_eq!(true, params.persisted_queries);
+        assert_eq!(10 * 1000, params.max_body_size);
+
+        let result = Graphql::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+step = "response"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin graphql invalid, message: Graphql plugin should be executed at request step",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_graphql_persisted_query() {
+        let graphql = Graphql::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+path = "/graphql"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let query = "{ user { id } }";
+        let hash = sha256_hex(query);
+
+        // hash only, not found
+        let body = format!(
+            r#"{{"extensions":{{"persistedQuery":{{"version":1,"sha256Hash":"{hash}"}}}}}}"#
+        );
+        let input_header = format!(
+            "POST /graphql HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let mut state = State::default();
+        let resp = graphql
+            .handle_request(PluginStep::Request, &mut session, &mut state)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            true,
+            String::from_utf8_lossy(&resp.body)
+                .contains("PERSISTED_QUERY_NOT_FOUND")
+        );
+
+        // register query
+        let body = format!(
+            r#"{{"query":"{query}","extensions":{{"persistedQuery":{{"version":1,"sha256Hash":"{hash}"}}}}}}"#
+        );
+        let input_header = format!(
+            "POST /graphql HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let mut state = State::default();
+        let result = graphql
+            .handle_request(PluginStep::Request, &mut session, &mut state)
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_none());
+        assert_eq!(true, state.request_body.is_none());
+        assert_eq!(
+            true,
+            graphql
+                .http_cache
+                .cached
+                .get(&format!("{PERSISTED_QUERY_PREFIX}{hash}"), "")
+                .await
+                .unwrap()
+                .is_some()
+        );
+
+        // hash only, load query from cache store
+        let body = format!(
+            r#"{{"extensions":{{"persistedQuery":{{"version":1,"sha256Hash":"{hash}"}}}}}}"#
+        );
+        let input_header = format!(
+            "POST /graphql HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let mut state = State::default();
+        let result = graphql
+            .handle_request(PluginStep::Request, &mut session, &mut state)
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_none());
+        let body = state.request_body.unwrap();
+        assert_eq!(true, String::from_utf8_lossy(&body).contains(query));
+
+        // hash mismatch
+        let body = r#"{"query":"{ id }","extensions":{"persistedQuery":{"version":1,"sha256Hash":"abc"}}}"#;
+        let input_header = format!(
+            "POST /graphql HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let mut state = State::default();
+        let resp = graphql
+            .handle_request(PluginStep::Request, &mut session, &mut state)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            true,
+            String::from_utf8_lossy(&resp.body)
+                .contains("provided sha does not match query")
+        );
+    }
+
+    #[test]
+    fn test_analyze_query() {
+        let analysis = analyze_query(
+            r#"
+# get user
+query GetUser($id: ID!) {
+  user(id: $id, name: "a { b } c") {
+    id
+    nickname: name
+    friends(first: 10) @include(if: true) {
+      ...UserFields
+      ... on Admin {
+        role
+      }
+    }
+  }
+}
+fragment UserFields on User {
+  id
+  avatar { url }
+}
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            QueryAnalysis {
+                depth: 4,
+                complexity: 8,
+                introspection: false,
+            },
+            analysis
+        );
+
+        let analysis =
+            analyze_query("{ __schema { types { name } } }").unwrap();
+        assert_eq!(true, analysis.introspection);
+        let analysis = analyze_query("{ user { __typename } }").unwrap();
+        assert_eq!(false, analysis.introspection);
+
+        // fragment cycle
+        let analysis = analyze_query(
+            "{ ...A } fragment A on Query { a { ...B } } fragment B on A { b { ...A } }",
+        )
+        .unwrap();
+        assert_eq!(2, analysis.depth);
+
+        let query = "{ a ".repeat(300) + &"}".repeat(300);
+        assert_eq!(true, analyze_query(&query).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_graphql_protection() {
+        let graphql = Graphql::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+persisted_queries = false
+max_depth = 3
+max_complexity = 10
+max_batch = 2
+block_introspection = true
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(None, graphql.validate_query("{ user { id name } }"));
+        assert_eq!(
+            "Query depth 4 exceeds the max depth 3",
+            graphql.validate_query("{ a { b { c { d } } } }").unwrap()
+        );
+        assert_eq!(
+            "Query complexity 11 exceeds the max complexity 10",
+            graphql.validate_query("{ a b c d e f g h i j k }").unwrap()
+        );
+        assert_eq!(
+            "GraphQL introspection is not allowed",
+            graphql
+                .validate_query("{ __type(name: \"User\") { name } }")
+                .unwrap()
+        );
+
+        let body = r#"[{"query":"{ a }"},{"query":"{ b }"},{"query":"{ c }"}]"#;
+        let input_header = format!(
+            "POST /graphql HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let resp = graphql
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            true,
+            String::from_utf8_lossy(&resp.body)
+                .contains("Batch size 3 exceeds the max batch size 2")
+        );
+
+        let input_header =
+            "GET /graphql?query=%7B%20a%20%7B%20b%20%7B%20c%20%7B%20d%20%7D%20%7D%20%7D%20%7D HTTP/1.1\r\n\r\n";
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let resp = graphql
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            true,
+            String::from_utf8_lossy(&resp.body)
+                .contains("GRAPHQL_VALIDATION_FAILED")
+        );
+    }
+}
@@ -0,0 +1,423 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{get_int_conf, get_str_conf, Error, Plugin, Result};
+use crate::config::{PluginConf, PluginStep};
+use crate::http_extra::HttpResponse;
+use crate::state::State;
+use crate::util;
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures_util::FutureExt;
+use humantime::parse_duration;
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use pingora::ErrorType;
+use std::any::Any;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, warn};
+
+const FAILURE_CONF: &str = "failure";
+
+/// The failure policy of plugin, e.g.
+/// ```toml
+/// [plugins.auth.failure]
+/// mode = "open"
+/// max_failures = 5
+/// disabled_duration = "30s"
+/// ```
+/// The failure is the panic or the error which is not a client error,
+/// the request continues without the plugin if mode is `open`.
+#[derive(Debug)]
+pub struct FailurePolicy {
+    fail_open: bool,
+    // the plugin is disabled after consecutive failures, zero means never
+    max_failures: u32,
+    disabled_duration: Duration,
+}
+
+impl FailurePolicy {
+    /// Create the policy from `failure` block of plugin config,
+    /// it returns none if the block is not set.
+    pub fn new(conf: &PluginConf) -> Result<Option<Self>> {
+        let Some(value) = conf.get(FAILURE_CONF) else {
+            return Ok(None);
+        };
+        let Some(value) = value.as_table() else {
+            return Err(Error::Invalid {
+                category: FAILURE_CONF.to_string(),
+                message: "failure should be a table".to_string(),
+            });
+        };
+        let disabled_duration = get_str_conf(value, "disabled_duration");
+        let disabled_duration = if disabled_duration.is_empty() {
+            Duration::from_secs(30)
+        } else {
+            parse_duration(&disabled_duration).map_err(|e| {
+                Error::ParseDuration {
+                    category: FAILURE_CONF.to_string(),
+                    source: e,
+                }
+            })?
+        };
+        Ok(Some(Self {
+            fail_open: get_str_conf(value, "mode") == "open",
+            max_failures: get_int_conf(value, "max_failures").max(0) as u32,
+            disabled_duration,
+        }))
+    }
+}
+
+/// The plugin is guarded by the failure policy, the panic of plugin is
+/// caught, and the plugin is disabled after consecutive failures. The
+/// disabled plugin is skipped if fail open, otherwise the request fails
+/// without running it.
+pub struct GuardedPlugin {
+    name: String,
+    policy: FailurePolicy,
+    plugin: Arc<dyn Plugin>,
+    failures: AtomicU32,
+    // the plugin is disabled until the timestamp(ms)
+    disabled_until: AtomicU64,
+}
+
+fn get_panic_message(e: &(dyn Any + Send)) -> String {
+    if let Some(message) = e.downcast_ref::<&str>() {
+        return message.to_string();
+    }
+    if let Some(message) = e.downcast_ref::<String>() {
+        return message.clone();
+    }
+    "unknown panic".to_string()
+}
+
+// the client error(4xx) is the result of plugin, e.g. rate limit
+fn is_failure(e: &pingora::Error) -> bool {
+    !matches!(e.etype(), ErrorType::HTTPStatus(status) if *status < 500)
+}
+
+impl GuardedPlugin {
+    pub fn new(
+        name: &str,
+        policy: FailurePolicy,
+        plugin: Arc<dyn Plugin>,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            policy,
+            plugin,
+            failures: AtomicU32::new(0),
+            disabled_until: AtomicU64::new(0),
+        }
+    }
+    fn is_disabled(&self) -> bool {
+        self.disabled_until.load(Ordering::Relaxed)
+            > util::now().as_millis() as u64
+    }
+    /// Check the plugin is runnable, it returns error if the plugin is
+    /// disabled and fail closed.
+    fn check_disabled(&self) -> pingora::Result<bool> {
+        if !self.is_disabled() {
+            return Ok(false);
+        }
+        if self.policy.fail_open {
+            return Ok(true);
+        }
+        Err(util::new_internal_error(
+            503,
+            format!("Plugin {} is disabled", self.name),
+        ))
+    }
+    fn on_success(&self) {
+        if self.failures.load(Ordering::Relaxed) != 0 {
+            self.failures.store(0, Ordering::Relaxed);
+        }
+    }
+    /// Record the failure, the plugin is disabled if the consecutive
+    /// failures reach the max failures.
+    fn on_failure(&self, kind: &str, message: &str) {
+        #[cfg(feature = "metrics")]
+        crate::state::PLUGIN_FAILURES
+            .with_label_values(&[&self.name, kind])
+            .inc();
+        let failures = self.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        error!(plugin = self.name, kind, message, failures, "plugin fails");
+        if self.policy.max_failures > 0 && failures >= self.policy.max_failures
+        {
+            self.failures.store(0, Ordering::Relaxed);
+            let until = util::now() + self.policy.disabled_duration;
+            self.disabled_until
+                .store(until.as_millis() as u64, Ordering::Relaxed);
+            warn!(
+                plugin = self.name,
+                duration = format!("{:?}", self.policy.disabled_duration),
+                "plugin is disabled"
+            );
+        }
+    }
+    /// Handle the result of plugin, the failure is ignored if fail open.
+    fn handle_result<T: Default>(
+        &self,
+        result: std::result::Result<pingora::Result<T>, Box<dyn Any + Send>>,
+    ) -> pingora::Result<T> {
+        match result {
+            Ok(Ok(value)) => {
+                self.on_success();
+                Ok(value)
+            },
+            Ok(Err(e)) => {
+                if !is_failure(&e) {
+                    self.on_success();
+                    return Err(e);
+                }
+                self.on_failure("error", &e.to_string());
+                if self.policy.fail_open {
+                    Ok(T::default())
+                } else {
+                    Err(e)
+                }
+            },
+            Err(e) => {
+                let message = get_panic_message(e.as_ref());
+                self.on_failure("panic", &message);
+                if self.policy.fail_open {
+                    Ok(T::default())
+                } else {
+                    Err(util::new_internal_error(
+                        500,
+                        format!("Plugin {} panics", self.name),
+                    ))
+                }
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl Plugin for GuardedPlugin {
+    #[inline]
+    fn hash_key(&self) -> String {
+        self.plugin.hash_key()
+    }
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut State,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        if self.check_disabled()? {
+            return Ok(None);
+        }
+        let result =
+            AssertUnwindSafe(self.plugin.handle_request(step, session, ctx))
+                .catch_unwind()
+                .await;
+        self.handle_result(result)
+    }
+    async fn handle_response(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut State,
+        upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<()> {
+        if self.check_disabled()? {
+            return Ok(());
+        }
+        let result = AssertUnwindSafe(self.plugin.handle_response(
+            step,
+            session,
+            ctx,
+            upstream_response,
+        ))
+        .catch_unwind()
+        .await;
+        self.handle_result(result)
+    }
+    fn handle_response_body(
+        &self,
+        session: &mut Session,
+        ctx: &mut State,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+    ) -> pingora::Result<()> {
+        if self.check_disabled()? {
+            return Ok(());
+        }
+        let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            self.plugin
+                .handle_response_body(session, ctx, body, end_of_stream)
+        }));
+        self.handle_result(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FailurePolicy, GuardedPlugin};
+    use crate::config::{PluginConf, PluginStep};
+    use crate::http_extra::HttpResponse;
+    use crate::plugin::Plugin;
+    use crate::state::State;
+    use crate::util;
+    use async_trait::async_trait;
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use tokio_test::io::Builder;
+
+    struct PanicPlugin {
+        count: AtomicU32,
+    }
+
+    #[async_trait]
+    impl Plugin for PanicPlugin {
+        async fn handle_request(
+            &self,
+            _step: PluginStep,
+            session: &mut Session,
+            _ctx: &mut State,
+        ) -> pingora::Result<Option<HttpResponse>> {
+            self.count.fetch_add(1, Ordering::Relaxed);
+            match session.req_header().uri.path() {
+                "/panic" => panic!("plugin panics"),
+                "/limit" => Err(util::new_internal_error(
+                    429,
+                    "Too many requests".to_string(),
+                )),
+                _ => Ok(None),
+            }
+        }
+    }
+
+    async fn new_session(path: &str) -> Session {
+        let input_header = format!("GET {path} HTTP/1.1\r\n\r\n");
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        session
+    }
+
+    fn new_guarded_plugin(mode: &str) -> (GuardedPlugin, Arc<PanicPlugin>) {
+        let conf = toml::from_str::<PluginConf>(&format!(
+            r#"
+[failure]
+mode = "{mode}"
+max_failures = 2
+disabled_duration = "1m"
+"#
+        ))
+        .unwrap();
+        let policy = FailurePolicy::new(&conf).unwrap().unwrap();
+        let plugin = Arc::new(PanicPlugin {
+            count: AtomicU32::new(0),
+        });
+        (GuardedPlugin::new("panic", policy, plugin.clone()), plugin)
+    }
+
+    #[test]
+    fn test_failure_policy() {
+        let conf = toml::from_str::<PluginConf>(
+            r###"
+category = "stats"
+"###,
+        )
+        .unwrap();
+        assert_eq!(true, FailurePolicy::new(&conf).unwrap().is_none());
+
+        let conf = toml::from_str::<PluginConf>(
+            r###"
+[failure]
+disabled_duration = "1x"
+"###,
+        )
+        .unwrap();
+        assert_eq!(true, FailurePolicy::new(&conf).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_guarded_plugin() {
+        let (guarded, plugin) = new_guarded_plugin("open");
+        // the client error is not failure
+        let mut session = new_session("/limit").await;
+        let result = guarded
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await;
+        assert_eq!(true, result.is_err());
+
+        for _ in 0..2 {
+            let mut session = new_session("/panic").await;
+            let result = guarded
+                .handle_request(
+                    PluginStep::Request,
+                    &mut session,
+                    &mut State::default(),
+                )
+                .await;
+            assert_eq!(true, result.unwrap().is_none());
+        }
+        assert_eq!(3, plugin.count.load(Ordering::Relaxed));
+        // the plugin is disabled and skipped
+        let mut session = new_session("/").await;
+        let result = guarded
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await;
+        assert_eq!(true, result.unwrap().is_none());
+        assert_eq!(3, plugin.count.load(Ordering::Relaxed));
+
+        let (guarded, plugin) = new_guarded_plugin("closed");
+        let mut session = new_session("/panic").await;
+        let result = guarded
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await;
+        assert_eq!(
+            " HTTPStatus context: Plugin panic panics cause:  InternalError",
+            result.err().unwrap().to_string()
+        );
+        let mut session = new_session("/panic").await;
+        let _ = guarded
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await;
+        let mut session = new_session("/").await;
+        let result = guarded
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await;
+        assert_eq!(true, result.is_err());
+        assert_eq!(2, plugin.count.load(Ordering::Relaxed));
+    }
+}
@@ -13,8 +13,8 @@
 // limitations under the License.
 
 use super::{
-    get_hash_key, get_step_conf, get_str_conf, get_str_slice_conf, Error,
-    Plugin, Result,
+    get_hash_key, get_int_conf, get_step_conf, get_str_conf,
+    get_str_slice_conf, Error, Plugin, Result,
 };
 use crate::config::{PluginCategory, PluginConf, PluginStep};
 use crate::http_extra::HttpResponse;
@@ -30,6 +30,9 @@ pub struct IpRestriction {
     plugin_step: PluginStep,
     ip_rules: util::IpRules,
     restriction_category: String,
+    // the count of proxies in front of pingap
+    trusted_hops: usize,
+    trusted_proxies: Option<util::IpRules>,
     forbidden_resp: HttpResponse,
     hash_value: String,
 }
@@ -46,11 +49,19 @@ impl TryFrom<&PluginConf> for IpRestriction {
         if message.is_empty() {
             message = "Request is forbidden".to_string();
         }
+        let trusted_proxies = get_str_slice_conf(value, "trusted_proxies");
+        let trusted_proxies = if trusted_proxies.is_empty() {
+            None
+        } else {
+            Some(util::IpRules::new(&trusted_proxies))
+        };
         let params = Self {
             hash_value,
             plugin_step: step,
             ip_rules,
             restriction_category: get_str_conf(value, "type"),
+            trusted_hops: get_int_conf(value, "trusted_hops") as usize,
+            trusted_proxies,
             forbidden_resp: HttpResponse {
                 status: StatusCode::FORBIDDEN,
                 body: Bytes::from(message),
@@ -74,6 +85,26 @@ impl IpRestriction {
         debug!(params = params.to_string(), "new ip restriction plugin");
         Self::try_from(params)
     }
+    /// Gets the client ip, if trusted hops or trusted proxies is set,
+    /// it's derived from the forwarded chain instead of the first ip
+    /// of X-Forwarded-For, which can be forged by client.
+    fn get_client_ip(&self, session: &Session, ctx: &mut State) -> String {
+        if self.trusted_hops > 0 || self.trusted_proxies.is_some() {
+            let chain =
+                util::get_forwarded_chain(session, ctx.remote_addr.as_deref());
+            return util::get_trusted_client_ip(
+                &chain,
+                self.trusted_hops,
+                self.trusted_proxies.as_ref(),
+            );
+        }
+        if let Some(ip) = &ctx.client_ip {
+            return ip.to_string();
+        }
+        let ip = util::get_client_ip(session);
+        ctx.client_ip = Some(ip.clone());
+        ip
+    }
 }
 
 #[async_trait]
@@ -92,13 +123,7 @@ impl Plugin for IpRestriction {
         if step != self.plugin_step {
             return Ok(None);
         }
-        let ip = if let Some(ip) = &ctx.client_ip {
-            ip.to_string()
-        } else {
-            let ip = util::get_client_ip(session);
-            ctx.client_ip = Some(ip.clone());
-            ip
-        };
+        let ip = self.get_client_ip(session, ctx);
 
         let found = match self.ip_rules.matched(&ip) {
             Ok(matched) => matched,
@@ -287,4 +312,84 @@ ip_list = [
             .unwrap();
         assert_eq!(true, result.is_none());
     }
+
+    #[tokio::test]
+    async fn test_ip_limit_trusted_proxies() {
+        let deny = IpRestriction::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+type = "deny"
+ip_list = ["1.1.1.0/24"]
+trusted_proxies = ["10.0.0.0/8"]
+    "###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let new_session = |headers: &'static str| async move {
+            let input_header =
+                format!("GET /vicanso/pingap HTTP/1.1\r\n{headers}\r\n\r\n");
+            let mock_io = Builder::new().read(input_header.as_bytes()).build();
+            let mut session = Session::new_h1(Box::new(mock_io));
+            session.read_request().await.unwrap();
+            session
+        };
+
+        // the forged ip is ignored
+        let mut session =
+            new_session("X-Forwarded-For: 2.1.1.2, 1.1.1.2, 10.0.0.2").await;
+        let result = deny
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State {
+                    remote_addr: Some("10.0.0.1".to_string()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_some());
+
+        let mut session =
+            new_session("X-Forwarded-For: 1.1.1.2, 2.1.1.2, 10.0.0.2").await;
+        let result = deny
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State {
+                    remote_addr: Some("10.0.0.1".to_string()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_none());
+
+        let deny = IpRestriction::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+type = "deny"
+ip_list = ["1.1.1.0/24"]
+trusted_hops = 1
+    "###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let mut session =
+            new_session(r#"Forwarded: for=2.1.1.2, for="1.1.1.2:8080""#).await;
+        let result = deny
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State {
+                    remote_addr: Some("10.0.0.1".to_string()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_some());
+    }
 }
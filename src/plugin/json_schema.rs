@@ -0,0 +1,424 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    get_hash_key, get_step_conf, get_str_conf, read_request_body, Error,
+    Plugin, Result,
+};
+use crate::config::{PluginCategory, PluginConf, PluginStep};
+use crate::http_extra::HttpResponse;
+use crate::state::State;
+use async_trait::async_trait;
+use bytesize::ByteSize;
+use http::{Method, StatusCode};
+use jsonschema::JSONSchema;
+use pingora::proxy::Session;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::str::FromStr;
+use tracing::debug;
+
+// the retry buffer of pingora is limited to 64kb
+const MAX_BODY_SIZE: usize = 64 * 1024;
+// max count of errors in response
+const MAX_ERRORS: usize = 10;
+
+struct SchemaRule {
+    method: Option<Method>,
+    // exact path, or path prefix if it ends with `*`
+    path: String,
+    body: Option<JSONSchema>,
+    query: Option<JSONSchema>,
+}
+
+impl SchemaRule {
+    fn matched(&self, method: &Method, path: &str) -> bool {
+        if let Some(value) = &self.method {
+            if value != method {
+                return false;
+            }
+        }
+        if let Some(prefix) = self.path.strip_suffix('*') {
+            path.starts_with(prefix)
+        } else {
+            path == self.path
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct SchemaValidationError {
+    category: String,
+    path: String,
+    message: String,
+}
+
+#[derive(Serialize, Debug)]
+struct SchemaValidationResp {
+    message: String,
+    errors: Vec<SchemaValidationError>,
+}
+
+pub struct JsonSchema {
+    plugin_step: PluginStep,
+    rules: Vec<SchemaRule>,
+    max_body_size: usize,
+    hash_value: String,
+}
+
+/// Compile the json schema, the value can be json string or file path.
+fn compile_schema(value: &str) -> Result<JSONSchema> {
+    let data = if value.trim_start().starts_with('{') {
+        value.to_string()
+    } else {
+        std::fs::read_to_string(value).map_err(|e| Error::Invalid {
+            category: PluginCategory::JsonSchema.to_string(),
+            message: format!("read schema {value} fail, {e}"),
+        })?
+    };
+    let schema: Value =
+        serde_json::from_str(&data).map_err(|e| Error::Invalid {
+            category: PluginCategory::JsonSchema.to_string(),
+            message: e.to_string(),
+        })?;
+    JSONSchema::compile(&schema).map_err(|e| Error::Invalid {
+        category: PluginCategory::JsonSchema.to_string(),
+        message: e.to_string(),
+    })
+}
+
+impl TryFrom<&PluginConf> for JsonSchema {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let step = get_step_conf(value);
+
+        let mut rules = vec![];
+        if let Some(arr) = value.get("schemas").and_then(|v| v.as_array()) {
+            for item in arr.iter() {
+                let Some(item) = item.as_table() else {
+                    continue;
+                };
+                let get = |key: &str| {
+                    item.get(key)
+                        .and_then(|v| v.as_str())
+                        .unwrap_or_default()
+                        .to_string()
+                };
+                let method = get("method");
+                let method = if method.is_empty() {
+                    None
+                } else {
+                    Some(Method::from_str(&method.to_uppercase()).map_err(
+                        |e| Error::Invalid {
+                            category: PluginCategory::JsonSchema.to_string(),
+                            message: e.to_string(),
+                        },
+                    )?)
+                };
+                let body = get("body");
+                let query = get("query");
+                rules.push(SchemaRule {
+                    method,
+                    path: get("path"),
+                    body: if body.is_empty() {
+                        None
+                    } else {
+                        Some(compile_schema(&body)?)
+                    },
+                    query: if query.is_empty() {
+                        None
+                    } else {
+                        Some(compile_schema(&query)?)
+                    },
+                });
+            }
+        }
+        let max_body_size = get_str_conf(value, "max_body_size");
+        let max_body_size = if max_body_size.is_empty() {
+            MAX_BODY_SIZE
+        } else {
+            let size = ByteSize::from_str(&max_body_size).map_err(|e| {
+                Error::Invalid {
+                    category: PluginCategory::JsonSchema.to_string(),
+                    message: e.to_string(),
+                }
+            })?;
+            (size.as_u64() as usize).min(MAX_BODY_SIZE)
+        };
+
+        let params = Self {
+            hash_value,
+            plugin_step: step,
+            rules,
+            max_body_size,
+        };
+        if params.rules.is_empty() {
+            return Err(Error::Invalid {
+                category: PluginCategory::JsonSchema.to_string(),
+                message: "Schemas are not allowed empty".to_string(),
+            });
+        }
+        if params.plugin_step != PluginStep::Request {
+            return Err(Error::Invalid {
+                category: PluginCategory::JsonSchema.to_string(),
+                message:
+                    "Json schema plugin should be executed at request step"
+                        .to_string(),
+            });
+        }
+        Ok(params)
+    }
+}
+
+/// Validate the value by schema, the errors are appended to list.
+fn validate(
+    schema: &JSONSchema,
+    category: &str,
+    value: &Value,
+    errors: &mut Vec<SchemaValidationError>,
+) {
+    if let Err(result) = schema.validate(value) {
+        for e in result.take(MAX_ERRORS) {
+            errors.push(SchemaValidationError {
+                category: category.to_string(),
+                path: e.instance_path.to_string(),
+                message: e.to_string(),
+            });
+        }
+    }
+}
+
+/// Convert the query of uri to json object, all values are string.
+fn get_query_object(query: Option<&str>) -> Value {
+    let mut obj = Map::new();
+    for item in query.unwrap_or_default().split('&') {
+        if item.is_empty() {
+            continue;
+        }
+        let (key, value) = item.split_once('=').unwrap_or((item, ""));
+        let key = urlencoding::decode(key).unwrap_or_default().to_string();
+        let value = urlencoding::decode(value).unwrap_or_default().to_string();
+        obj.insert(key, Value::String(value));
+    }
+    Value::Object(obj)
+}
+
+fn new_bad_request(
+    message: &str,
+    errors: Vec<SchemaValidationError>,
+) -> pingora::Result<HttpResponse> {
+    HttpResponse::try_from_json_status(
+        &SchemaValidationResp {
+            message: message.to_string(),
+            errors,
+        },
+        StatusCode::BAD_REQUEST,
+    )
+}
+
+impl JsonSchema {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new json schema plugin");
+        Self::try_from(params)
+    }
+}
+
+#[async_trait]
+impl Plugin for JsonSchema {
+    #[inline]
+    fn hash_key(&self) -> String {
+        self.hash_value.clone()
+    }
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut State,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        if step != self.plugin_step {
+            return Ok(None);
+        }
+        let req_header = session.req_header();
+        let Some(rule) = self.rules.iter().find(|item| {
+            item.matched(&req_header.method, req_header.uri.path())
+        }) else {
+            return Ok(None);
+        };
+        let mut errors = vec![];
+        if let Some(schema) = &rule.query {
+            let value = get_query_object(req_header.uri.query());
+            validate(schema, "query", &value, &mut errors);
+        }
+        if let Some(schema) = &rule.body {
+            let body =
+                read_request_body(session, ctx, self.max_body_size).await?;
+            let Ok(value) = serde_json::from_slice::<Value>(&body) else {
+                return Ok(Some(new_bad_request(
+                    "Request body is not valid json",
+                    errors,
+                )?));
+            };
+            validate(schema, "body", &value, &mut errors);
+        }
+        if !errors.is_empty() {
+            return Ok(Some(new_bad_request(
+                "Request validation fail",
+                errors,
+            )?));
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_query_object, JsonSchema};
+    use crate::config::{PluginConf, PluginStep};
+    use crate::plugin::Plugin;
+    use crate::state::State;
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+    use tokio_test::io::Builder;
+
+    fn new_json_schema() -> JsonSchema {
+        JsonSchema::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+[[schemas]]
+method = "POST"
+path = "/api/users"
+body = '{"type":"object","required":["name"],"properties":{"name":{"type":"string","minLength":2},"age":{"type":"integer"}}}'
+
+[[schemas]]
+method = "GET"
+path = "/api/books*"
+query = '{"type":"object","required":["page"],"properties":{"page":{"type":"string","pattern":"^[0-9]+$"}}}'
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_json_schema_params() {
+        let params = new_json_schema();
+        assert_eq!(2, params.rules.len());
+        assert_eq!(true, params.rules[0].body.is_some());
+        assert_eq!(true, params.rules[1].query.is_some());
+
+        let result = JsonSchema::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+[[schemas]]
+path = "/api/users"
+body = '{"type":"unknown"}'
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(true, result.is_err());
+
+        let result = JsonSchema::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+max_body_size = "10kb"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin json_schema invalid, message: Schemas are not allowed empty",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_get_query_object() {
+        assert_eq!(
+            json!({
+                "page": "1",
+                "name": "pin gap",
+            }),
+            get_query_object(Some("page=1&name=pin%20gap"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_json_schema() {
+        let params = new_json_schema();
+
+        let body = r#"{"name":"pingap","age":1}"#;
+        let input_header = format!(
+            "POST /api/users HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let result = params
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_none());
+
+        let body = r#"{"name":"p","age":"1"}"#;
+        let input_header = format!(
+            "POST /api/users HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let resp = params
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(400, resp.status.as_u16());
+        let data: serde_json::Value =
+            serde_json::from_slice(&resp.body).unwrap();
+        assert_eq!(2, data["errors"].as_array().unwrap().len());
+
+        let input_header = "GET /api/books/1?page=a HTTP/1.1\r\n\r\n";
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let resp = params
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(400, resp.status.as_u16());
+        assert_eq!(
+            true,
+            String::from_utf8_lossy(&resp.body).contains(r#""path":"/page""#)
+        );
+    }
+}
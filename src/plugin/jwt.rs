@@ -138,7 +138,7 @@ impl Plugin for JwtAuth {
         &self,
         step: PluginStep,
         session: &mut Session,
-        _ctx: &mut State,
+        ctx: &mut State,
     ) -> pingora::Result<Option<HttpResponse>> {
         if step != self.plugin_step {
             return Ok(None);
@@ -210,6 +210,11 @@ impl Plugin for JwtAuth {
                 return Ok(Some(resp));
             }
         }
+        let get_claim = |key: &str| value.get(key).and_then(|v| v.as_str());
+        ctx.set_auth_identity(
+            get_claim("sub"),
+            get_claim("client_id").or_else(|| get_claim("azp")),
+        );
 
         Ok(None)
     }
@@ -20,21 +20,35 @@ use crate::config::{PluginCategory, PluginConf, PluginStep};
 use crate::http_extra::HttpResponse;
 use crate::state::State;
 use crate::util;
+use ahash::AHashMap;
 use async_trait::async_trait;
 use bytes::Bytes;
 use http::{HeaderName, StatusCode};
 use humantime::parse_duration;
 use pingora::proxy::Session;
+use sha2::{Digest, Sha256};
 use std::str::FromStr;
 use std::time::Duration;
 use tokio::time::sleep;
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
+
+/// The named key for rotation, the expired key is rejected,
+/// and the deprecated key is still accepted with a warning log.
+#[derive(Debug, Clone, PartialEq)]
+struct NamedKey {
+    name: String,
+    // the expired timestamp(seconds)
+    expired_at: Option<u64>,
+    deprecated: bool,
+}
 
 pub struct KeyAuth {
     plugin_step: PluginStep,
     header: Option<HeaderName>,
     query: Option<String>,
     keys: Vec<Vec<u8>>,
+    // key -> named key
+    named_keys: AHashMap<Vec<u8>, NamedKey>,
     delay: Option<Duration>,
     miss_authorization_resp: HttpResponse,
     unauthorized_resp: HttpResponse,
@@ -42,6 +56,56 @@ pub struct KeyAuth {
     hash_value: String,
 }
 
+/// Parse the named keys, e.g.
+/// ```toml
+/// [plugins.keyAuth.named_keys.mobile]
+/// key = "123"
+/// expired_at = "2025-01-01T00:00:00Z"
+/// deprecated = true
+/// ```
+fn parse_named_keys(value: &PluginConf) -> Result<AHashMap<Vec<u8>, NamedKey>> {
+    let mut named_keys = AHashMap::new();
+    let Some(values) = value.get("named_keys").and_then(|v| v.as_table())
+    else {
+        return Ok(named_keys);
+    };
+    for (name, item) in values.iter() {
+        let key = item.get("key").and_then(|v| v.as_str()).unwrap_or_default();
+        if key.is_empty() {
+            return Err(Error::Invalid {
+                category: PluginCategory::KeyAuth.to_string(),
+                message: format!("key of {name} can't be empty"),
+            });
+        }
+        let expired_at = item
+            .get("expired_at")
+            .and_then(|v| v.as_str())
+            .filter(|v| !v.is_empty());
+        let expired_at = if let Some(expired_at) = expired_at {
+            let value = chrono::DateTime::parse_from_rfc3339(expired_at)
+                .map_err(|e| Error::Invalid {
+                    category: PluginCategory::KeyAuth.to_string(),
+                    message: format!("expired_at of {name} is invalid, {e}"),
+                })?;
+            Some(value.timestamp().max(0) as u64)
+        } else {
+            None
+        };
+        named_keys.insert(
+            key.as_bytes().to_vec(),
+            NamedKey {
+                name: name.to_string(),
+                expired_at,
+                deprecated: item
+                    .get("deprecated")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or_default(),
+            },
+        );
+    }
+    Ok(named_keys)
+}
+
 impl TryFrom<&PluginConf> for KeyAuth {
     type Error = Error;
     fn try_from(value: &PluginConf) -> Result<Self> {
@@ -83,7 +147,8 @@ impl TryFrom<&PluginConf> for KeyAuth {
             .iter()
             .map(|item| item.as_bytes().to_vec())
             .collect();
-        if keys.is_empty() {
+        let named_keys = parse_named_keys(value)?;
+        if keys.is_empty() && named_keys.is_empty() {
             return Err(Error::Invalid {
                 category: PluginCategory::KeyAuth.to_string(),
                 message: "auth keys can't be empty".to_string(),
@@ -92,6 +157,7 @@ impl TryFrom<&PluginConf> for KeyAuth {
         let params = Self {
             hash_value,
             keys,
+            named_keys,
             hide_credentials: get_bool_conf(value, "hide_credentials"),
             plugin_step: step,
             query,
@@ -138,7 +204,7 @@ impl Plugin for KeyAuth {
         &self,
         step: PluginStep,
         session: &mut Session,
-        _ctx: &mut State,
+        ctx: &mut State,
     ) -> pingora::Result<Option<HttpResponse>> {
         if step != self.plugin_step {
             return Ok(None);
@@ -156,12 +222,32 @@ impl Plugin for KeyAuth {
         if value.is_empty() {
             return Ok(Some(self.miss_authorization_resp.clone()));
         }
-        if !self.keys.contains(&value.to_vec()) {
+        let value = value.to_vec();
+        let named_key = self.named_keys.get(&value).filter(|item| {
+            item.expired_at
+                .map(|expired_at| expired_at > util::now().as_secs())
+                .unwrap_or(true)
+        });
+        if named_key.is_none() && !self.keys.contains(&value) {
             if let Some(d) = self.delay {
                 sleep(d).await;
             }
             return Ok(Some(self.unauthorized_resp.clone()));
         }
+        if let Some(named_key) = named_key {
+            if named_key.deprecated {
+                warn!(
+                    name = named_key.name,
+                    client_ip = util::get_client_ip(session),
+                    "deprecated auth key is used"
+                );
+            }
+            ctx.set_auth_identity(None, Some(&named_key.name));
+        } else {
+            // the digest of key is used as client id to avoid leaking the key
+            let client_id = hex::encode(&Sha256::digest(&value)[..8]);
+            ctx.set_auth_identity(None, Some(&client_id));
+        }
         if self.hide_credentials {
             if let Some(name) = &self.header {
                 session.req_header_mut().remove_header(name);
@@ -180,7 +266,7 @@ impl Plugin for KeyAuth {
 
 #[cfg(test)]
 mod tests {
-    use super::KeyAuth;
+    use super::{KeyAuth, NamedKey};
     use crate::state::State;
     use crate::{config::PluginConf, config::PluginStep, plugin::Plugin};
     use pingora::proxy::Session;
@@ -368,4 +454,67 @@ hide_credentials = true
             session.req_header().uri.to_string()
         );
     }
+
+    #[tokio::test]
+    async fn test_named_key_auth() {
+        let auth = KeyAuth::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+header = "X-User"
+[named_keys.mobile]
+key = "123"
+expired_at = "2000-01-01T00:00:00Z"
+[named_keys.web]
+key = "456"
+deprecated = true
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            Some(&NamedKey {
+                name: "web".to_string(),
+                expired_at: None,
+                deprecated: true,
+            }),
+            auth.named_keys.get(b"456".as_slice())
+        );
+
+        for (key, is_none) in [("123", false), ("456", true)] {
+            let input_header =
+                format!("GET / HTTP/1.1\r\nX-User: {key}\r\n\r\n");
+            let mock_io = Builder::new().read(input_header.as_bytes()).build();
+            let mut session = Session::new_h1(Box::new(mock_io));
+            session.read_request().await.unwrap();
+            let mut ctx = State::default();
+            let result = auth
+                .handle_request(PluginStep::Request, &mut session, &mut ctx)
+                .await
+                .unwrap();
+            assert_eq!(is_none, result.is_none());
+            if is_none {
+                assert_eq!(
+                    Some("web"),
+                    ctx.variables
+                        .as_ref()
+                        .and_then(|v| v.get("$auth_client_id"))
+                        .map(|v| v.as_str())
+                );
+            }
+        }
+
+        let result = KeyAuth::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+header = "X-User"
+[named_keys.mobile]
+key = "123"
+expired_at = "2000-01-01"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(true, result.is_err());
+    }
 }
@@ -13,11 +13,12 @@
 // limitations under the License.
 
 use super::{
-    get_hash_key, get_int_conf, get_step_conf, get_str_conf, Error, Plugin,
-    Result,
+    get_hash_key, get_int_conf, get_step_conf, get_str_conf,
+    get_str_slice_conf, Error, Plugin, Result,
 };
 use crate::config::{PluginCategory, PluginConf, PluginStep};
 use crate::http_extra::HttpResponse;
+use crate::limit::{SlidingWindowLimit, TokenBucketLimit};
 use crate::state::State;
 use crate::util;
 use async_trait::async_trait;
@@ -35,14 +36,34 @@ pub enum LimitTag {
     RequestHeader,
     Cookie,
     Query,
+    Variable,
+    Path,
+}
+
+impl From<&str> for LimitTag {
+    fn from(value: &str) -> Self {
+        match value {
+            "cookie" => LimitTag::Cookie,
+            "header" => LimitTag::RequestHeader,
+            "query" => LimitTag::Query,
+            "variable" => LimitTag::Variable,
+            "path" => LimitTag::Path,
+            _ => LimitTag::Ip,
+        }
+    }
 }
 
 pub struct Limiter {
     tag: LimitTag,
     max: isize,
     key: String,
+    // the composite key, e.g. ["ip", "path", "header:X-User"],
+    // the tag and key are ignored if it's set
+    keys: Vec<(LimitTag, String)>,
     inflight: Option<Inflight>,
     rate: Option<Rate>,
+    sliding_window: Option<SlidingWindowLimit>,
+    token_bucket: Option<TokenBucketLimit>,
     plugin_step: PluginStep,
     hash_value: String,
 }
@@ -53,12 +74,15 @@ impl TryFrom<&PluginConf> for Limiter {
         let hash_value = get_hash_key(value);
         let step = get_step_conf(value);
 
-        let tag = match get_str_conf(value, "tag").as_str() {
-            "cookie" => LimitTag::Cookie,
-            "header" => LimitTag::RequestHeader,
-            "query" => LimitTag::Query,
-            _ => LimitTag::Ip,
-        };
+        let tag = LimitTag::from(get_str_conf(value, "tag").as_str());
+        let keys = get_str_slice_conf(value, "keys")
+            .iter()
+            .map(|item| {
+                let (tag, key) = item.split_once(':').unwrap_or((item, ""));
+                (LimitTag::from(tag.trim()), key.trim().to_string())
+            })
+            .collect();
+        let max = get_int_conf(value, "max");
         let interval = get_str_conf(value, "interval");
         let interval = if !interval.is_empty() {
             parse_duration(&interval).map_err(|e| Error::Invalid {
@@ -70,19 +94,35 @@ impl TryFrom<&PluginConf> for Limiter {
         };
         let mut inflight = None;
         let mut rate = None;
-        if get_str_conf(value, "type") == "inflight" {
-            inflight = Some(Inflight::new());
-        } else {
-            rate = Some(Rate::new(interval));
-        }
+        let mut sliding_window = None;
+        let mut token_bucket = None;
+        match get_str_conf(value, "type").as_str() {
+            "inflight" => inflight = Some(Inflight::new()),
+            "sliding_window" => {
+                sliding_window = Some(SlidingWindowLimit::new(interval))
+            },
+            "token_bucket" => {
+                // the capacity of bucket, max is used if it's not set
+                let burst = get_int_conf(value, "burst").max(max);
+                token_bucket = Some(TokenBucketLimit::new(
+                    max.max(0) as usize,
+                    interval,
+                    burst.max(0) as usize,
+                ));
+            },
+            _ => rate = Some(Rate::new(interval)),
+        };
 
         let params = Self {
             hash_value,
             tag,
             key: get_str_conf(value, "key"),
-            max: get_int_conf(value, "max") as isize,
+            keys,
+            max: max as isize,
             inflight,
             rate,
+            sliding_window,
+            token_bucket,
             plugin_step: step,
         };
         if ![PluginStep::Request, PluginStep::ProxyUpstream]
@@ -102,34 +142,74 @@ impl Limiter {
         debug!(params = params.to_string(), "new limit plugin");
         Self::try_from(params)
     }
-    /// Increment `key` by 1. If value gt max, an error will be return.
-    /// Otherwise returns a Guard. It may set the client ip to context.
-    pub fn incr(&self, session: &Session, ctx: &mut State) -> Result<()> {
-        let key = match self.tag {
-            LimitTag::Query => {
-                util::get_query_value(session.req_header(), &self.key)
-                    .unwrap_or_default()
-                    .to_string()
-            },
+    /// Get the value of tag from request. It may set the client ip to context.
+    fn get_tag_value(
+        tag: &LimitTag,
+        key: &str,
+        session: &Session,
+        ctx: &mut State,
+    ) -> String {
+        match tag {
+            LimitTag::Query => util::get_query_value(session.req_header(), key)
+                .unwrap_or_default()
+                .to_string(),
             LimitTag::RequestHeader => {
-                util::get_req_header_value(session.req_header(), &self.key)
+                util::get_req_header_value(session.req_header(), key)
                     .unwrap_or_default()
                     .to_string()
             },
             LimitTag::Cookie => {
-                util::get_cookie_value(session.req_header(), &self.key)
+                util::get_cookie_value(session.req_header(), key)
                     .unwrap_or_default()
                     .to_string()
             },
+            LimitTag::Variable => ctx
+                .variables
+                .as_ref()
+                .and_then(|variables| variables.get(&format!("${key}")))
+                .cloned()
+                .unwrap_or_default(),
+            LimitTag::Path => session.req_header().uri.path().to_string(),
             _ => {
                 let client_ip = util::get_client_ip(session);
                 ctx.client_ip = Some(client_ip.clone());
                 client_ip
             },
-        };
+        }
+    }
+    /// Get the limit key of request, the composite key is joined by `:`,
+    /// and it's empty if any part of composite key is empty.
+    fn get_key(&self, session: &Session, ctx: &mut State) -> String {
+        if self.keys.is_empty() {
+            return Self::get_tag_value(&self.tag, &self.key, session, ctx);
+        }
+        let mut values = Vec::with_capacity(self.keys.len());
+        for (tag, key) in self.keys.iter() {
+            let value = Self::get_tag_value(tag, key, session, ctx);
+            if value.is_empty() {
+                return "".to_string();
+            }
+            values.push(value);
+        }
+        values.join(":")
+    }
+    /// Increment `key` by 1. If value gt max, an error will be return.
+    /// Otherwise returns a Guard. It may set the client ip to context.
+    pub fn incr(&self, session: &Session, ctx: &mut State) -> Result<()> {
+        let key = self.get_key(session, ctx);
         if key.is_empty() {
             return Ok(());
         }
+        if let Some(token_bucket) = &self.token_bucket {
+            if !token_bucket.acquire(&key) {
+                return Err(Error::Exceed {
+                    category: PluginCategory::Limit.to_string(),
+                    max: self.max,
+                    value: self.max + 1,
+                });
+            }
+            return Ok(());
+        }
         let value = if let Some(rate) = &self.rate {
             rate.observe(&key, 1);
             let value = rate.rate(&key);
@@ -138,6 +218,8 @@ impl Limiter {
             let (guard, value) = inflight.incr(&key, 1);
             ctx.guard = Some(guard);
             value
+        } else if let Some(sliding_window) = &self.sliding_window {
+            sliding_window.observe(&key)
         } else {
             0
         };
@@ -317,6 +399,34 @@ max = 10
         assert_eq!(true, ctx.guard.is_some());
     }
     #[tokio::test]
+    async fn test_new_variable_limiter() {
+        let limiter = Limiter::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+type = "inflight"
+tag = "variable"
+key = "operation_id"
+max = 10
+    "###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(LimitTag::Variable, limiter.tag);
+        let mut ctx = State {
+            ..Default::default()
+        };
+        let session = new_session().await;
+
+        // no variable, skip limit
+        limiter.incr(&session, &mut ctx).unwrap();
+        assert_eq!(true, ctx.guard.is_none());
+
+        ctx.add_variable("operation_id", "getUser");
+        limiter.incr(&session, &mut ctx).unwrap();
+        assert_eq!(true, ctx.guard.is_some());
+    }
+    #[tokio::test]
     async fn test_new_ip_limiter() {
         let limiter = Limiter::new(
             &toml::from_str::<PluginConf>(
@@ -390,6 +500,73 @@ max = 1
         assert_eq!(true, result.is_none());
     }
 
+    #[tokio::test]
+    async fn test_composite_key_limiter() {
+        let limiter = Limiter::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+type = "sliding_window"
+keys = ["ip", "path", "header:X-Uuid", "cookie:deviceId"]
+max = 1
+interval = "1m"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(4, limiter.keys.len());
+        assert_eq!(true, limiter.sliding_window.is_some());
+        let mut ctx = State::default();
+        let session = new_session().await;
+        assert_eq!(
+            "1.1.1.1:/vicanso/pingap:138q71:abc",
+            limiter.get_key(&session, &mut ctx)
+        );
+
+        limiter.incr(&session, &mut ctx).unwrap();
+        assert_eq!(
+            "Plugin limit, exceed limit 2/1",
+            limiter.incr(&session, &mut ctx).err().unwrap().to_string()
+        );
+
+        let limiter = Limiter::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+keys = ["ip", "header:X-User"]
+max = 1
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        // skip limit if any part of key is empty
+        assert_eq!("", limiter.get_key(&session, &mut ctx));
+    }
+
+    #[tokio::test]
+    async fn test_token_bucket_limit() {
+        let limiter = Limiter::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+type = "token_bucket"
+max = 1
+burst = 2
+interval = "1m"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let mut ctx = State::default();
+        let session = new_session().await;
+        limiter.incr(&session, &mut ctx).unwrap();
+        limiter.incr(&session, &mut ctx).unwrap();
+        assert_eq!(
+            "Plugin limit, exceed limit 2/1",
+            limiter.incr(&session, &mut ctx).err().unwrap().to_string()
+        );
+    }
+
     #[tokio::test]
     async fn test_rate_limit() {
         let limiter = Limiter::new(
@@ -0,0 +1,254 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    get_hash_key, get_step_conf, get_str_slice_conf, Error, Plugin, Result,
+};
+use crate::config::{PluginCategory, PluginConf, PluginStep};
+use crate::http_extra::convert_header_value;
+use crate::state::State;
+use async_trait::async_trait;
+use http::{header, HeaderValue};
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use tracing::debug;
+
+/// Inject the `Link` headers into the response,
+/// the variable in braces is replaced, e.g. `</{$http_x-version}/app.js>`.
+pub struct LinkHeader {
+    plugin_step: PluginStep,
+    links: Vec<String>,
+    // the prefix of response content type, all types are matched if empty
+    content_types: Vec<String>,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for LinkHeader {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let step = get_step_conf(value);
+
+        let links = get_str_slice_conf(value, "links");
+        for link in links.iter() {
+            if !link.trim_start().starts_with('<') {
+                return Err(Error::Invalid {
+                    category: PluginCategory::LinkHeader.to_string(),
+                    message: format!("link({link}) should start with <"),
+                });
+            }
+        }
+        let params = Self {
+            hash_value,
+            plugin_step: step,
+            links,
+            content_types: get_str_slice_conf(value, "content_types")
+                .iter()
+                .map(|item| item.to_lowercase())
+                .collect(),
+        };
+        if params.plugin_step != PluginStep::Response {
+            return Err(Error::Invalid {
+                category: PluginCategory::LinkHeader.to_string(),
+                message:
+                    "Link header plugin should be executed at response step"
+                        .to_string(),
+            });
+        }
+        Ok(params)
+    }
+}
+
+/// Replace the variables of link, the variable which can't be resolved
+/// is replaced with empty string.
+fn format_link(
+    link: &str,
+    resolve: impl Fn(&str) -> Option<HeaderValue>,
+) -> String {
+    let mut result = String::with_capacity(link.len());
+    let mut rest = link;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+        result.push_str(&rest[..start]);
+        let tag = &rest[start + 1..start + end];
+        if let Some(value) = resolve(tag) {
+            result.push_str(value.to_str().unwrap_or_default());
+        }
+        rest = &rest[start + end + 1..];
+    }
+    result.push_str(rest);
+    result
+}
+
+impl LinkHeader {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new link header plugin");
+        Self::try_from(params)
+    }
+    fn matched_content_type(&self, upstream_response: &ResponseHeader) -> bool {
+        if self.content_types.is_empty() {
+            return true;
+        }
+        let content_type = upstream_response
+            .headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_lowercase();
+        self.content_types
+            .iter()
+            .any(|item| content_type.starts_with(item))
+    }
+}
+
+#[async_trait]
+impl Plugin for LinkHeader {
+    #[inline]
+    fn hash_key(&self) -> String {
+        self.hash_value.clone()
+    }
+    #[inline]
+    async fn handle_response(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut State,
+        upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<()> {
+        if step != self.plugin_step
+            || !upstream_response.status.is_success()
+            || !self.matched_content_type(upstream_response)
+        {
+            return Ok(());
+        }
+        let (session, ctx) = (&*session, &*ctx);
+        for link in self.links.iter() {
+            let value = format_link(link, |tag| {
+                convert_header_value(
+                    &HeaderValue::from_str(tag).ok()?,
+                    session,
+                    ctx,
+                )
+            });
+            upstream_response.append_header(header::LINK, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_link, LinkHeader};
+    use crate::config::{PluginConf, PluginStep};
+    use crate::plugin::Plugin;
+    use crate::state::State;
+    use http::HeaderValue;
+    use pingora::http::ResponseHeader;
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use tokio_test::io::Builder;
+
+    #[test]
+    fn test_format_link() {
+        let resolve = |tag: &str| {
+            if tag == "$host" {
+                Some(HeaderValue::from_static("pingap.io"))
+            } else {
+                None
+            }
+        };
+        assert_eq!(
+            "<https://pingap.io/app.js>; rel=preload; as=script",
+            format_link(
+                "<https://{$host}/app.js>; rel=preload; as=script",
+                resolve
+            )
+        );
+        assert_eq!(
+            "</app.js>; rel=preload",
+            format_link("</{:unknown}app.js>; rel=preload", resolve)
+        );
+        assert_eq!("</app{.js>", format_link("</app{.js>", resolve));
+    }
+
+    #[tokio::test]
+    async fn test_link_header() {
+        let result = LinkHeader::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+step = "response"
+links = ["/app.js"]
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin link_header invalid, message: link(/app.js) should start with <",
+            result.err().unwrap().to_string()
+        );
+
+        let params = LinkHeader::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+step = "response"
+links = ["</{$http_x-version}/app.js>; rel=preload; as=script"]
+content_types = ["text/html"]
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        let headers = ["X-Version: v1"].join("\r\n");
+        let input_header = format!("GET / HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+
+        let mut upstream_response = ResponseHeader::build(200, None).unwrap();
+        upstream_response
+            .insert_header("Content-Type", "text/html; charset=utf-8")
+            .unwrap();
+        params
+            .handle_response(
+                PluginStep::Response,
+                &mut session,
+                &mut State::default(),
+                &mut upstream_response,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            "</v1/app.js>; rel=preload; as=script",
+            upstream_response.headers.get("Link").unwrap()
+        );
+
+        let mut upstream_response = ResponseHeader::build(200, None).unwrap();
+        upstream_response
+            .insert_header("Content-Type", "application/json")
+            .unwrap();
+        params
+            .handle_response(
+                PluginStep::Response,
+                &mut session,
+                &mut State::default(),
+                &mut upstream_response,
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, upstream_response.headers.get("Link").is_none());
+    }
+}
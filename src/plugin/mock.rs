@@ -17,22 +17,183 @@ use crate::config::{PluginCategory, PluginConf, PluginStep};
 use crate::http_extra::{convert_headers, HttpResponse};
 use crate::plugin::{get_hash_key, get_int_conf, get_str_slice_conf};
 use crate::state::State;
+use crate::util;
 use async_trait::async_trait;
-use http::StatusCode;
+use bytes::Bytes;
+use http::{HeaderValue, Method, StatusCode};
 use humantime::parse_duration;
 use pingora::proxy::Session;
+use rand::Rng;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
 use tokio::time::sleep;
 use tracing::debug;
 
+/// The variant of mock response, it's used for the request which matches
+/// the methods, or the requests of sequence.
+struct MockVariant {
+    methods: Vec<Method>,
+    // the count of requests served by the variant in sequence mode
+    count: u64,
+    resp: HttpResponse,
+}
+
 pub struct MockResponse {
     pub path: String,
     pub plugin_step: PluginStep,
     pub resp: HttpResponse,
-    pub delay: Option<Duration>,
+    // the delay is random in the range
+    pub delay: Option<(Duration, Duration)>,
+    variants: Vec<MockVariant>,
+    sequence: Vec<MockVariant>,
+    // the count of requests for sequence mode
+    requests: AtomicU64,
     hash_value: String,
 }
 
+fn new_invalid_error(message: String) -> Error {
+    Error::Invalid {
+        category: PluginCategory::Mock.to_string(),
+        message,
+    }
+}
+
+fn parse_delay(value: &str) -> Result<Option<(Duration, Duration)>> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    let (min, max) = value.split_once('-').unwrap_or((value, value));
+    let min = parse_duration(min.trim())
+        .map_err(|e| new_invalid_error(e.to_string()))?;
+    let max = parse_duration(max.trim())
+        .map_err(|e| new_invalid_error(e.to_string()))?;
+    if min > max {
+        return Err(new_invalid_error(format!("delay({value}) is invalid")));
+    }
+    Ok(Some((min, max)))
+}
+
+fn new_mock_variant(value: &PluginConf) -> Result<MockVariant> {
+    let mut methods = vec![];
+    for item in get_str_slice_conf(value, "methods").iter() {
+        let method = Method::from_str(&item.to_uppercase()).map_err(|e| {
+            new_invalid_error(format!("method({item}) is invalid, {e}"))
+        })?;
+        methods.push(method);
+    }
+    let status = get_int_conf(value, "status") as u16;
+    let headers = get_str_slice_conf(value, "headers");
+    let mut resp = HttpResponse {
+        status: StatusCode::OK,
+        body: get_str_conf(value, "data").into(),
+        ..Default::default()
+    };
+    if status > 0 {
+        resp.status = StatusCode::from_u16(status).unwrap_or(StatusCode::OK);
+    }
+    if !headers.is_empty() {
+        if let Ok(headers) = convert_headers(&headers) {
+            resp.headers = Some(headers);
+        }
+    }
+    Ok(MockVariant {
+        methods,
+        count: get_int_conf(value, "count").max(1) as u64,
+        resp,
+    })
+}
+
+fn new_mock_variants(
+    value: &PluginConf,
+    key: &str,
+) -> Result<Vec<MockVariant>> {
+    let mut variants = vec![];
+    if let Some(arr) = value.get(key).and_then(|v| v.as_array()) {
+        for item in arr.iter() {
+            let Some(item) = item.as_table() else {
+                return Err(new_invalid_error(format!(
+                    "{key} should be array of table"
+                )));
+            };
+            variants.push(new_mock_variant(item)?);
+        }
+    }
+    Ok(variants)
+}
+
+/// Gets the value of template variable, the supported variables:
+/// `method`, `path`, `uri`, `host`, `remote_addr`, `request_id`,
+/// `timestamp`, `query.<name>` and `header.<name>`.
+fn get_template_value(name: &str, session: &Session, ctx: &State) -> String {
+    let req_header = session.req_header();
+    match name {
+        "method" => req_header.method.to_string(),
+        "path" => req_header.uri.path().to_string(),
+        "uri" => req_header.uri.to_string(),
+        "host" => util::get_host(req_header).unwrap_or_default().to_string(),
+        "remote_addr" => ctx.remote_addr.clone().unwrap_or_default(),
+        "request_id" => ctx.request_id.clone().unwrap_or_default(),
+        "timestamp" => util::now().as_secs().to_string(),
+        _ => {
+            if let Some(key) = name.strip_prefix("query.") {
+                util::get_query_value(req_header, key)
+                    .unwrap_or_default()
+                    .to_string()
+            } else if let Some(key) = name.strip_prefix("header.") {
+                util::get_req_header_value(req_header, key)
+                    .unwrap_or_default()
+                    .to_string()
+            } else {
+                "".to_string()
+            }
+        },
+    }
+}
+
+/// Renders the template, the variable is in the format of `{{name}}`.
+fn render_template(template: &str, session: &Session, ctx: &State) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        result.push_str(&rest[..start]);
+        let name = rest[start + 2..start + end].trim();
+        result.push_str(&get_template_value(name, session, ctx));
+        rest = &rest[start + end + 2..];
+    }
+    result.push_str(rest);
+    result
+}
+
+fn render_response(
+    resp: &HttpResponse,
+    session: &Session,
+    ctx: &State,
+) -> HttpResponse {
+    let mut resp = resp.clone();
+    let body = std::str::from_utf8(&resp.body).unwrap_or_default();
+    if body.contains("{{") {
+        resp.body = Bytes::from(render_template(body, session, ctx));
+    }
+    if let Some(headers) = resp.headers.as_mut() {
+        for (_, value) in headers.iter_mut() {
+            let template = value.to_str().unwrap_or_default();
+            if !template.contains("{{") {
+                continue;
+            }
+            if let Ok(v) =
+                HeaderValue::from_str(&render_template(template, session, ctx))
+            {
+                *value = v;
+            }
+        }
+    }
+    resp
+}
+
 impl MockResponse {
     /// Creates a new mock response upstream, which will return a mock data.
     pub fn new(params: &PluginConf) -> Result<Self> {
@@ -47,34 +208,8 @@ impl MockResponse {
         }
 
         let path = get_str_conf(params, "path");
-        let status = get_int_conf(params, "status") as u16;
-        let headers = get_str_slice_conf(params, "headers");
-        let data = get_str_conf(params, "data");
-        let delay = get_str_conf(params, "delay");
-        let delay = if !delay.is_empty() {
-            let d = parse_duration(&delay).map_err(|e| Error::Invalid {
-                category: PluginCategory::Mock.to_string(),
-                message: e.to_string(),
-            })?;
-            Some(d)
-        } else {
-            None
-        };
-
-        let mut resp = HttpResponse {
-            status: StatusCode::OK,
-            body: data.into(),
-            ..Default::default()
-        };
-        if status > 0 {
-            resp.status =
-                StatusCode::from_u16(status).unwrap_or(StatusCode::OK);
-        }
-        if !headers.is_empty() {
-            if let Ok(headers) = convert_headers(&headers) {
-                resp.headers = Some(headers);
-            }
-        }
+        let delay = parse_delay(&get_str_conf(params, "delay"))?;
+        let resp = new_mock_variant(params)?.resp;
 
         Ok(MockResponse {
             hash_value,
@@ -82,8 +217,33 @@ impl MockResponse {
             plugin_step: step,
             path,
             delay,
+            variants: new_mock_variants(params, "variants")?,
+            sequence: new_mock_variants(params, "sequence")?,
+            requests: AtomicU64::new(0),
         })
     }
+    /// Gets the response of request, the sequence is checked first,
+    /// then the variant matches the method, otherwise the default response.
+    fn get_response(&self, method: &Method) -> &HttpResponse {
+        if !self.sequence.is_empty() {
+            let mut index = self.requests.fetch_add(1, Ordering::Relaxed);
+            for item in self.sequence.iter() {
+                if index < item.count {
+                    return &item.resp;
+                }
+                index -= item.count;
+            }
+            // the last one is used after the sequence is finished
+            if let Some(item) = self.sequence.last() {
+                return &item.resp;
+            }
+        }
+        self.variants
+            .iter()
+            .find(|item| item.methods.contains(method))
+            .map(|item| &item.resp)
+            .unwrap_or(&self.resp)
+    }
 }
 
 #[async_trait]
@@ -98,7 +258,7 @@ impl Plugin for MockResponse {
         &self,
         step: PluginStep,
         session: &mut Session,
-        _ctx: &mut State,
+        ctx: &mut State,
     ) -> pingora::Result<Option<HttpResponse>> {
         if step != self.plugin_step {
             return Ok(None);
@@ -107,10 +267,16 @@ impl Plugin for MockResponse {
         {
             return Ok(None);
         }
-        if let Some(d) = self.delay {
+        if let Some((min, max)) = self.delay {
+            let d = if min < max {
+                rand::thread_rng().gen_range(min..=max)
+            } else {
+                min
+            };
             sleep(d).await;
         }
-        Ok(Some(self.resp.clone()))
+        let resp = self.get_response(&session.req_header().method);
+        Ok(Some(render_response(resp, session, ctx)))
     }
 }
 
@@ -226,4 +392,94 @@ data = "{\"message\":\"Mock Service Unavailable\"}"
             .unwrap();
         assert_eq!(true, result.is_none());
     }
+
+    #[tokio::test]
+    async fn test_mock_template_sequence() {
+        let mock = MockResponse::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+delay = "1ms-5ms"
+headers = ["X-Path: {{path}}"]
+data = "{{method}} {{query.id}} {{header.X-User}}"
+
+[[variants]]
+methods = ["POST"]
+status = 201
+data = "created"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!("Some((1ms, 5ms))", format!("{:?}", mock.delay));
+        let new_session = |method: &'static str| async move {
+            let input_header = format!(
+                "{method} /users?id=1 HTTP/1.1\r\nX-User: pingap\r\n\r\n"
+            );
+            let mock_io = Builder::new().read(input_header.as_bytes()).build();
+            let mut session = Session::new_h1(Box::new(mock_io));
+            session.read_request().await.unwrap();
+            session
+        };
+
+        let mut session = new_session("GET").await;
+        let resp = mock
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(Bytes::from_static(b"GET 1 pingap"), resp.body);
+        assert_eq!(
+            r###"Some([("x-path", "/users")])"###,
+            format!("{:?}", resp.headers)
+        );
+
+        let mut session = new_session("POST").await;
+        let resp = mock
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, resp.status);
+        assert_eq!(Bytes::from_static(b"created"), resp.body);
+
+        let mock = MockResponse::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+[[sequence]]
+count = 2
+status = 503
+
+[[sequence]]
+status = 200
+data = "ok"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let mut status_list = vec![];
+        for _ in 0..4 {
+            let mut session = new_session("GET").await;
+            let resp = mock
+                .handle_request(
+                    PluginStep::Request,
+                    &mut session,
+                    &mut State::default(),
+                )
+                .await
+                .unwrap()
+                .unwrap();
+            status_list.push(resp.status.as_u16());
+        }
+        assert_eq!(vec![503, 503, 200, 200], status_list);
+    }
 }
@@ -20,36 +20,74 @@ use crate::util::{self, base64_encode};
 use ahash::AHashMap;
 use arc_swap::ArcSwap;
 use async_trait::async_trait;
+use bytes::{BufMut, Bytes, BytesMut};
 use once_cell::sync::Lazy;
 use pingora::http::ResponseHeader;
 use pingora::proxy::Session;
 use snafu::Snafu;
 use std::collections::HashMap;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use tracing::info;
 
 mod accept_encoding;
 mod admin;
+mod api_key;
 mod basic_auth;
+mod bulk_redirect;
 mod cache;
+mod cache_purge;
+mod cache_slice;
+mod challenge;
+mod client_cert;
+mod client_hints;
+mod coalescing;
 mod combined_auth;
 mod compression;
+mod concurrency;
+mod condition;
+mod cookie_rewrite;
 mod cors;
+mod csp_nonce;
 mod csrf;
 mod directory;
+#[cfg(feature = "waf")]
+mod dlp;
+mod early_hints;
+mod esi;
+mod event_emitter;
+mod fault_injection;
+mod graphql;
+mod guard;
 mod ip_restriction;
+#[cfg(feature = "waf")]
+mod json_schema;
 mod jwt;
 mod key_auth;
 mod limit;
+mod link_header;
 mod mock;
+mod open_api;
 mod ping;
+mod ready;
 mod redirect;
 mod referer_restriction;
 mod request_id;
 mod response_headers;
+mod robots;
+mod session;
 mod stats;
+mod tarpit;
 mod ua_restriction;
+#[cfg(feature = "wasm")]
+mod wasm;
+mod websocket_policy;
+mod well_known;
+
+pub use cache::new_cache_checkpoint_service;
+#[cfg(feature = "wasm")]
+pub use wasm::WasmInstance;
 
 pub static ADMIN_SERVER_PLUGIN: Lazy<String> =
     Lazy::new(|| uuid::Uuid::now_v7().to_string());
@@ -98,6 +136,12 @@ pub fn parse_admin_plugin(
     remark = "Admin serve"
     "#,
     );
+    let mut conf = toml::from_str::<PluginConf>(&data).unwrap();
+    // the audit log file of admin mutations
+    if let Some(value) = query.get("audit_log") {
+        let file = urlencoding::decode(value).unwrap_or_default();
+        conf.insert("audit_log".to_string(), toml::Value::String(file.into()));
+    }
     Ok((
         ServerConf {
             name: "pingap:admin".to_string(),
@@ -106,7 +150,7 @@ pub fn parse_admin_plugin(
             ..Default::default()
         },
         ADMIN_SERVER_PLUGIN.clone(),
-        toml::from_str::<PluginConf>(&data).unwrap(),
+        conf,
     ))
 }
 
@@ -138,6 +182,24 @@ pub enum Error {
 }
 type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// Get the address of plain http listener for the internal requests,
+/// the configured address is used first, otherwise the listener of
+/// current request is used if it isn't tls.
+pub(crate) fn get_local_addr(session: &Session, addr: &str) -> Option<String> {
+    if !addr.is_empty() {
+        return Some(addr.to_string());
+    }
+    if session
+        .digest()
+        .map(|digest| digest.ssl_digest.is_some())
+        .unwrap_or_default()
+    {
+        return None;
+    }
+    let addr = session.server_addr()?.as_inet()?;
+    Some(addr.to_string())
+}
+
 pub(crate) fn get_hash_key(conf: &PluginConf) -> String {
     let mut keys: Vec<String> =
         conf.keys().map(|item| item.to_string()).collect();
@@ -177,6 +239,17 @@ pub trait Plugin: Sync + Send {
     ) -> pingora::Result<()> {
         Ok(())
     }
+    /// Handle the chunk of response body, the body can be modified or
+    /// taken, and end of stream is true for the last chunk.
+    fn handle_response_body(
+        &self,
+        _session: &mut Session,
+        _ctx: &mut State,
+        _body: &mut Option<Bytes>,
+        _end_of_stream: bool,
+    ) -> pingora::Result<()> {
+        Ok(())
+    }
 }
 
 pub fn get_builtin_proxy_plugins() -> Vec<(String, PluginConf)> {
@@ -246,10 +319,94 @@ remark = "Adjust the accept encoding order and choose one econding"
 type Plugins = AHashMap<String, Arc<dyn Plugin>>;
 static PLUGINS: Lazy<ArcSwap<Plugins>> =
     Lazy::new(|| ArcSwap::from_pointee(AHashMap::new()));
+static PLUGIN_PRIORITIES: Lazy<ArcSwap<AHashMap<String, i64>>> =
+    Lazy::new(|| ArcSwap::from_pointee(AHashMap::new()));
+// it's increased after the plugins are reloaded
+static PLUGINS_VERSION: AtomicU64 = AtomicU64::new(0);
+
+const DEFAULT_PRIORITY: i64 = 500;
+
+/// Get the default priority of plugin category, the plugin with lower
+/// priority is executed first:
+/// - 100: request id
+/// - 200: ip, user agent and referer restriction, tarpit
+/// - 300: limit and concurrency
+/// - 400: authentication and csrf
+/// - 500: others
+///
+/// The plugins with the same priority are executed in the order of location.
+pub fn get_default_priority(category: &PluginCategory) -> i64 {
+    match category {
+        PluginCategory::RequestId => 100,
+        PluginCategory::IpRestriction
+        | PluginCategory::UaRestriction
+        | PluginCategory::RefererRestriction
+        | PluginCategory::Tarpit => 200,
+        PluginCategory::Limit | PluginCategory::Concurrency => 300,
+        PluginCategory::BasicAuth
+        | PluginCategory::KeyAuth
+        | PluginCategory::Jwt
+        | PluginCategory::ApiKey
+        | PluginCategory::CombinedAuth
+        | PluginCategory::ClientCert
+        | PluginCategory::Csrf => 400,
+        _ => DEFAULT_PRIORITY,
+    }
+}
+
+/// Get the priority of plugin, the `priority` of config is used if it
+/// is set, otherwise the default priority of category is used.
+fn get_priority_conf(conf: &PluginConf) -> i64 {
+    if let Some(priority) = conf.get("priority").and_then(|v| v.as_integer()) {
+        return priority;
+    }
+    let category = PluginCategory::from_str(&get_str_conf(conf, "category"))
+        .unwrap_or_default();
+    get_default_priority(&category)
+}
+
+/// The factory of plugin, it's used by the crate which embeds pingap
+/// to add its own plugin without changing the builtin categories.
+pub trait PluginFactory: Sync + Send {
+    /// Create the plugin from config, the config is the same as builtin
+    /// plugin, and the category is the registered name.
+    fn new_plugin(&self, conf: &PluginConf) -> Result<Arc<dyn Plugin>>;
+}
+
+static PLUGIN_FACTORIES: Lazy<
+    ArcSwap<AHashMap<String, Arc<dyn PluginFactory>>>,
+> = Lazy::new(|| ArcSwap::from_pointee(AHashMap::new()));
+
+/// Register the plugin factory of category, the factory with the same
+/// name will be replaced. The builtin categories can't be overridden,
+/// it returns an error if the name is one of them.
+pub fn register_factory(
+    name: &str,
+    factory: Box<dyn PluginFactory>,
+) -> Result<()> {
+    if name.is_empty() || PluginCategory::from_str(name).is_ok() {
+        return Err(Error::Invalid {
+            category: name.to_string(),
+            message: "Plugin factory can not use builtin category".to_string(),
+        });
+    }
+    let factory: Arc<dyn PluginFactory> = Arc::from(factory);
+    PLUGIN_FACTORIES.rcu(|factories| {
+        let mut factories = AHashMap::clone(factories);
+        factories.insert(name.to_string(), factory.clone());
+        factories
+    });
+    Ok(())
+}
+
+fn get_factory(name: &str) -> Option<Arc<dyn PluginFactory>> {
+    PLUGIN_FACTORIES.load().get(name).cloned()
+}
+
 pub fn parse_plugins(confs: Vec<(String, PluginConf)>) -> Result<Plugins> {
     let mut plguins: Plugins = AHashMap::new();
-    for (name, conf) in confs.iter() {
-        let name = name.to_string();
+    for (key, conf) in confs.iter() {
+        let name = key.to_string();
         let category = conf.get("category");
         if category.is_none() {
             return Err(Error::Invalid {
@@ -257,10 +414,17 @@ pub fn parse_plugins(confs: Vec<(String, PluginConf)>) -> Result<Plugins> {
                 message: "Category can not be empty".to_string(),
             });
         }
-        let category = PluginCategory::from_str(
-            category.unwrap().as_str().unwrap_or_default(),
-        )
-        .unwrap_or_default();
+        let category = category.unwrap().as_str().unwrap_or_default();
+        let category = match PluginCategory::from_str(category) {
+            Ok(category) => category,
+            Err(_) => {
+                if let Some(factory) = get_factory(category) {
+                    plguins.insert(name, factory.new_plugin(conf)?);
+                    continue;
+                }
+                PluginCategory::default()
+            },
+        };
         match category {
             PluginCategory::Limit => {
                 let l = limit::Limiter::new(conf)?;
@@ -347,8 +511,164 @@ pub fn parse_plugins(confs: Vec<(String, PluginConf)>) -> Result<Plugins> {
                     accept_encoding::AcceptEncoding::new(conf)?;
                 plguins.insert(name.clone(), Arc::new(accept_encoding));
             },
+            PluginCategory::Graphql => {
+                let g = graphql::Graphql::new(conf)?;
+                plguins.insert(name, Arc::new(g));
+            },
+            PluginCategory::Session => {
+                let s = session::SessionCookie::new(conf)?;
+                plguins.insert(name, Arc::new(s));
+            },
+            PluginCategory::EventEmitter => {
+                let e = event_emitter::EventEmitter::new(conf)?;
+                plguins.insert(name, Arc::new(e));
+            },
+            PluginCategory::ClientHints => {
+                let c = client_hints::ClientHints::new(conf)?;
+                plguins.insert(name, Arc::new(c));
+            },
+            PluginCategory::Concurrency => {
+                let c = concurrency::Concurrency::new(conf)?;
+                plguins.insert(name, Arc::new(c));
+            },
+            PluginCategory::FaultInjection => {
+                let f = fault_injection::FaultInjection::new(conf)?;
+                plguins.insert(name, Arc::new(f));
+            },
+            #[cfg(feature = "waf")]
+            PluginCategory::JsonSchema => {
+                let j = json_schema::JsonSchema::new(conf)?;
+                plguins.insert(name, Arc::new(j));
+            },
+            #[cfg(not(feature = "waf"))]
+            PluginCategory::JsonSchema => {
+                return Err(Error::Invalid {
+                    category: PluginCategory::JsonSchema.to_string(),
+                    message: "Json schema plugin requires the waf feature"
+                        .to_string(),
+                });
+            },
+            PluginCategory::Coalescing => {
+                let c = coalescing::Coalescing::new(conf)?;
+                plguins.insert(name, Arc::new(c));
+            },
+            PluginCategory::CacheSlice => {
+                let c = cache_slice::CacheSlice::new(conf)?;
+                plguins.insert(name, Arc::new(c));
+            },
+            PluginCategory::CachePurge => {
+                let c = cache_purge::CachePurge::new(conf)?;
+                plguins.insert(name, Arc::new(c));
+            },
+            PluginCategory::WellKnown => {
+                let w = well_known::WellKnown::new(conf)?;
+                plguins.insert(name, Arc::new(w));
+            },
+            PluginCategory::OpenApi => {
+                let o = open_api::OpenApi::new(conf)?;
+                plguins.insert(name, Arc::new(o));
+            },
+            PluginCategory::ClientCert => {
+                let c = client_cert::ClientCert::new(conf)?;
+                plguins.insert(name, Arc::new(c));
+            },
+            PluginCategory::ApiKey => {
+                let a = api_key::ApiKey::new(conf)?;
+                plguins.insert(name, Arc::new(a));
+            },
+            PluginCategory::CookieRewrite => {
+                let c = cookie_rewrite::CookieRewrite::new(conf)?;
+                plguins.insert(name, Arc::new(c));
+            },
+            PluginCategory::BulkRedirect => {
+                let b = bulk_redirect::BulkRedirect::new(conf)?;
+                plguins.insert(name, Arc::new(b));
+            },
+            PluginCategory::EarlyHints => {
+                let e = early_hints::EarlyHints::new(conf)?;
+                plguins.insert(name, Arc::new(e));
+            },
+            PluginCategory::LinkHeader => {
+                let l = link_header::LinkHeader::new(conf)?;
+                plguins.insert(name, Arc::new(l));
+            },
+            PluginCategory::Tarpit => {
+                let t = tarpit::Tarpit::new(conf)?;
+                plguins.insert(name, Arc::new(t));
+            },
+            PluginCategory::Ready => {
+                let r = ready::Ready::new(conf)?;
+                plguins.insert(name, Arc::new(r));
+            },
+            #[cfg(feature = "waf")]
+            PluginCategory::Dlp => {
+                let d = dlp::Dlp::new(conf)?;
+                plguins.insert(name, Arc::new(d));
+            },
+            #[cfg(not(feature = "waf"))]
+            PluginCategory::Dlp => {
+                return Err(Error::Invalid {
+                    category: PluginCategory::Dlp.to_string(),
+                    message: "Dlp plugin requires the waf feature".to_string(),
+                });
+            },
+            PluginCategory::WebsocketPolicy => {
+                let w = websocket_policy::WebsocketPolicy::new(conf)?;
+                plguins.insert(name, Arc::new(w));
+            },
+            PluginCategory::Robots => {
+                let r = robots::Robots::new(conf)?;
+                plguins.insert(name, Arc::new(r));
+            },
+            PluginCategory::CspNonce => {
+                let c = csp_nonce::CspNonce::new(conf)?;
+                plguins.insert(name, Arc::new(c));
+            },
+            PluginCategory::Challenge => {
+                let c = challenge::Challenge::new(conf)?;
+                plguins.insert(name, Arc::new(c));
+            },
+            PluginCategory::Esi => {
+                let e = esi::Esi::new(conf)?;
+                plguins.insert(name, Arc::new(e));
+            },
+            #[cfg(feature = "wasm")]
+            PluginCategory::Wasm => {
+                let w = wasm::Wasm::new(conf)?;
+                plguins.insert(name, Arc::new(w));
+            },
+            #[cfg(not(feature = "wasm"))]
+            PluginCategory::Wasm => {
+                return Err(Error::Invalid {
+                    category: PluginCategory::Wasm.to_string(),
+                    message: "Wasm plugin requires the wasm feature"
+                        .to_string(),
+                });
+            },
         };
     }
+    // wrap the plugin if it has match condition
+    for (key, conf) in confs.iter() {
+        if let Some(condition) = condition::Condition::new(conf)? {
+            if let Some(plugin) = plguins.remove(key) {
+                plguins.insert(
+                    key.to_string(),
+                    Arc::new(condition::ConditionalPlugin::new(
+                        key, condition, plugin,
+                    )),
+                );
+            }
+        }
+        // the failure of plugin is guarded by policy
+        if let Some(policy) = guard::FailurePolicy::new(conf)? {
+            if let Some(plugin) = plguins.remove(key) {
+                plguins.insert(
+                    key.to_string(),
+                    Arc::new(guard::GuardedPlugin::new(key, policy, plugin)),
+                );
+            }
+        }
+    }
 
     Ok(plguins)
 }
@@ -369,6 +689,11 @@ pub fn try_init_plugins(
 
     plugin_confs.extend(get_builtin_proxy_plugins());
 
+    let priorities = plugin_confs
+        .iter()
+        .map(|(name, conf)| (name.to_string(), get_priority_conf(conf)))
+        .collect();
+
     let mut updated_plugins = vec![];
     let mut plugins = AHashMap::new();
     let plugin_confs: Vec<(String, PluginConf)> = plugin_confs
@@ -401,6 +726,8 @@ pub fn try_init_plugins(
         .collect();
     plugins.extend(parse_plugins(plugin_confs)?);
     PLUGINS.store(Arc::new(plugins));
+    PLUGIN_PRIORITIES.store(Arc::new(priorities));
+    PLUGINS_VERSION.fetch_add(1, Ordering::Release);
 
     Ok(updated_plugins)
 }
@@ -409,6 +736,60 @@ pub fn get_plugin(name: &str) -> Option<Arc<dyn Plugin>> {
     PLUGINS.load().get(name).cloned()
 }
 
+/// The plugins sorted by priority, it's outdated after the plugins
+/// are reloaded and should be sorted again.
+#[derive(Default)]
+pub struct SortedPlugins {
+    version: u64,
+    pub plugins: Vec<(String, Arc<dyn Plugin>)>,
+}
+
+impl std::fmt::Debug for SortedPlugins {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<&str> =
+            self.plugins.iter().map(|(name, _)| name.as_str()).collect();
+        f.debug_struct("SortedPlugins")
+            .field("version", &self.version)
+            .field("plugins", &names)
+            .finish()
+    }
+}
+
+impl SortedPlugins {
+    /// Whether the plugins are reloaded after sorting.
+    pub fn is_outdated(&self) -> bool {
+        self.version != PLUGINS_VERSION.load(Ordering::Acquire)
+    }
+}
+
+/// Get the plugins of names sorted by priority, the sort is stable,
+/// so the plugins with the same priority keep the order of names.
+pub fn get_sorted_plugins(names: &[String]) -> SortedPlugins {
+    // load the version first, the plugins will be sorted again
+    // if they are reloaded after this
+    let version = PLUGINS_VERSION.load(Ordering::Acquire);
+    let plugins = PLUGINS.load();
+    let priorities = PLUGIN_PRIORITIES.load();
+    let mut items: Vec<(i64, String, Arc<dyn Plugin>)> = names
+        .iter()
+        .filter_map(|name| {
+            plugins.get(name).map(|plugin| {
+                let priority =
+                    priorities.get(name).copied().unwrap_or(DEFAULT_PRIORITY);
+                (priority, name.to_string(), plugin.clone())
+            })
+        })
+        .collect();
+    items.sort_by_key(|(priority, _, _)| *priority);
+    SortedPlugins {
+        version,
+        plugins: items
+            .into_iter()
+            .map(|(_, name, plugin)| (name, plugin))
+            .collect(),
+    }
+}
+
 pub(crate) fn get_str_conf(value: &PluginConf, key: &str) -> String {
     if let Some(value) = value.get(key) {
         value.as_str().unwrap_or_default().to_string()
@@ -445,6 +826,51 @@ pub(crate) fn get_str_slice_conf(value: &PluginConf, key: &str) -> Vec<String> {
     vec![]
 }
 
+// the retry buffer of pingora is limited to 64kb, the body larger than it
+// can't be sent to upstream after it is read
+pub(crate) const MAX_REQUEST_BODY_SIZE: usize = 64 * 1024;
+
+/// Read the whole request body of session, the retry buffering is enabled
+/// for every request which reads the body, so it can still be sent to
+/// upstream after it is read. The reading stops with 413 error if the body
+/// is larger than the limit, which is 64kb at most because of the retry
+/// buffer. The body is kept in the state, so it's read only once by the
+/// plugins of request.
+pub(crate) async fn read_request_body(
+    session: &mut Session,
+    ctx: &mut State,
+    limit: usize,
+) -> pingora::Result<Bytes> {
+    let limit = if limit == 0 {
+        MAX_REQUEST_BODY_SIZE
+    } else {
+        limit.min(MAX_REQUEST_BODY_SIZE)
+    };
+    let new_too_large_error = || {
+        util::new_internal_error(
+            413,
+            format!("Request body is too large, limit: {limit}"),
+        )
+    };
+    if let Some(body) = &ctx.request_body {
+        if body.len() > limit {
+            return Err(new_too_large_error());
+        }
+        return Ok(body.clone());
+    }
+    session.enable_retry_buffering();
+    let mut buf = BytesMut::new();
+    while let Some(value) = session.read_request_body().await? {
+        if buf.len() + value.len() > limit {
+            return Err(new_too_large_error());
+        }
+        buf.put(value.as_ref());
+    }
+    let body = buf.freeze();
+    ctx.request_body = Some(body.clone());
+    Ok(body)
+}
+
 pub(crate) fn get_step_conf(value: &PluginConf) -> PluginStep {
     PluginStep::from_str(get_str_conf(value, "step").as_str())
         .unwrap_or_default()
@@ -488,3 +914,87 @@ remove_headers = [
     ]);
     try_init_plugins(&plugins).unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        get_priority_conf, get_str_conf, parse_plugins, register_factory,
+        Plugin, PluginFactory, Result,
+    };
+    use crate::config::PluginConf;
+    use async_trait::async_trait;
+    use pretty_assertions::assert_eq;
+    use std::sync::Arc;
+
+    struct TestPlugin {
+        hash_value: String,
+    }
+    #[async_trait]
+    impl Plugin for TestPlugin {
+        fn hash_key(&self) -> String {
+            self.hash_value.clone()
+        }
+    }
+
+    struct TestPluginFactory {}
+    impl PluginFactory for TestPluginFactory {
+        fn new_plugin(&self, conf: &PluginConf) -> Result<Arc<dyn Plugin>> {
+            Ok(Arc::new(TestPlugin {
+                hash_value: get_str_conf(conf, "value"),
+            }))
+        }
+    }
+
+    #[test]
+    fn test_register_factory() {
+        assert_eq!(
+            "Plugin mock invalid, message: Plugin factory can not use builtin category",
+            register_factory("mock", Box::new(TestPluginFactory {}))
+                .err()
+                .unwrap()
+                .to_string()
+        );
+        register_factory("test_factory", Box::new(TestPluginFactory {}))
+            .unwrap();
+        let plugins = parse_plugins(vec![(
+            "test".to_string(),
+            toml::from_str::<PluginConf>(
+                r###"
+category = "test_factory"
+value = "abc"
+"###,
+            )
+            .unwrap(),
+        )])
+        .unwrap();
+        assert_eq!("abc", plugins.get("test").unwrap().hash_key());
+    }
+
+    #[test]
+    fn test_get_priority_conf() {
+        let conf = toml::from_str::<PluginConf>(
+            r###"
+category = "ip_restriction"
+"###,
+        )
+        .unwrap();
+        assert_eq!(200, get_priority_conf(&conf));
+
+        let conf = toml::from_str::<PluginConf>(
+            r###"
+category = "basic_auth"
+priority = 10
+"###,
+        )
+        .unwrap();
+        assert_eq!(10, get_priority_conf(&conf));
+
+        let conf = toml::from_str::<PluginConf>(
+            r###"
+category = "mock"
+"###,
+        )
+        .unwrap();
+        assert_eq!(500, get_priority_conf(&conf));
+    }
+}
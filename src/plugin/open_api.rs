@@ -0,0 +1,524 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{get_hash_key, get_step_conf, get_str_conf, Error, Plugin, Result};
+use crate::config::{PluginCategory, PluginConf, PluginStep};
+use crate::http_extra::HttpResponse;
+use crate::state::State;
+use crate::util;
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::{header, HeaderValue, Method, StatusCode};
+use pingora::http::RequestHeader;
+use pingora::proxy::Session;
+use serde_json::Value;
+use std::str::FromStr;
+use tracing::debug;
+
+const METHODS: [&str; 8] = [
+    "get", "put", "post", "delete", "options", "head", "patch", "trace",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+struct Parameter {
+    name: String,
+    // query, header, path or cookie
+    location: String,
+    required: bool,
+}
+
+#[derive(Debug)]
+struct Operation {
+    method: Method,
+    operation_id: String,
+    parameters: Vec<Parameter>,
+    body_required: bool,
+}
+
+#[derive(Debug)]
+struct Route {
+    // the template segment(`{id}`) is none
+    segments: Vec<Option<String>>,
+    templated: usize,
+    operations: Vec<Operation>,
+}
+
+impl Route {
+    fn matched(&self, path: &str) -> bool {
+        let mut count = 0;
+        for (index, value) in
+            path.trim_start_matches('/').split('/').enumerate()
+        {
+            let Some(segment) = self.segments.get(index) else {
+                return false;
+            };
+            match segment {
+                Some(segment) => {
+                    if segment != value {
+                        return false;
+                    }
+                },
+                None => {
+                    if value.is_empty() {
+                        return false;
+                    }
+                },
+            }
+            count += 1;
+        }
+        count == self.segments.len()
+    }
+    fn get_operation(&self, method: &Method) -> Option<&Operation> {
+        let found = self.operations.iter().find(|op| op.method == method);
+        // the head request can be handled by get operation
+        if found.is_none() && method == Method::HEAD {
+            return self.get_operation(&Method::GET);
+        }
+        found
+    }
+}
+
+pub struct OpenApi {
+    plugin_step: PluginStep,
+    base_path: String,
+    variable: String,
+    routes: Vec<Route>,
+    hash_value: String,
+}
+
+/// Resolve the local reference of spec, e.g. `#/components/parameters/id`.
+fn resolve_ref<'a>(spec: &'a Value, value: &'a Value) -> &'a Value {
+    if let Some(reference) = value.get("$ref").and_then(|v| v.as_str()) {
+        if let Some(value) = reference
+            .strip_prefix('#')
+            .and_then(|pointer| spec.pointer(pointer))
+        {
+            return value;
+        }
+    }
+    value
+}
+
+fn parse_parameters(spec: &Value, value: Option<&Value>) -> Vec<Parameter> {
+    let Some(arr) = value.and_then(|v| v.as_array()) else {
+        return vec![];
+    };
+    arr.iter()
+        .filter_map(|item| {
+            let item = resolve_ref(spec, item);
+            let name = item.get("name")?.as_str()?.to_string();
+            let location = item.get("in")?.as_str()?.to_string();
+            // path parameter is always required
+            let required = location == "path"
+                || item
+                    .get("required")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or_default();
+            Some(Parameter {
+                name,
+                location,
+                required,
+            })
+        })
+        .collect()
+}
+
+/// Parse the routes of open api spec, the routes without template
+/// segment will be matched first.
+fn parse_routes(spec: &Value) -> Result<Vec<Route>> {
+    let Some(paths) = spec.get("paths").and_then(|v| v.as_object()) else {
+        return Err(Error::Invalid {
+            category: PluginCategory::OpenApi.to_string(),
+            message: "Paths of open api spec is not found".to_string(),
+        });
+    };
+    let mut routes = vec![];
+    for (path, item) in paths.iter() {
+        let item = resolve_ref(spec, item);
+        let common_parameters = parse_parameters(spec, item.get("parameters"));
+        let mut operations = vec![];
+        for method in METHODS {
+            let Some(op) = item.get(method) else {
+                continue;
+            };
+            let mut parameters = common_parameters.clone();
+            // the operation parameter overrides the path item parameter
+            for param in parse_parameters(spec, op.get("parameters")) {
+                parameters.retain(|item| {
+                    item.name != param.name || item.location != param.location
+                });
+                parameters.push(param);
+            }
+            let body_required = op
+                .get("requestBody")
+                .map(|body| resolve_ref(spec, body))
+                .and_then(|body| body.get("required"))
+                .and_then(|v| v.as_bool())
+                .unwrap_or_default();
+            operations.push(Operation {
+                method: Method::from_str(&method.to_uppercase())
+                    .unwrap_or_default(),
+                operation_id: op
+                    .get("operationId")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default()
+                    .to_string(),
+                parameters,
+                body_required,
+            });
+        }
+        let segments: Vec<Option<String>> = path
+            .trim_start_matches('/')
+            .split('/')
+            .map(|item| {
+                if item.starts_with('{') && item.ends_with('}') {
+                    None
+                } else {
+                    Some(item.to_string())
+                }
+            })
+            .collect();
+        routes.push(Route {
+            templated: segments.iter().filter(|item| item.is_none()).count(),
+            segments,
+            operations,
+        });
+    }
+    routes.sort_by_key(|item| item.templated);
+    Ok(routes)
+}
+
+/// Get the path of first server url as base path,
+/// e.g. `https://example.com/api/v1` or `/api/v1`.
+fn get_server_base_path(spec: &Value) -> String {
+    let url = spec
+        .pointer("/servers/0/url")
+        .and_then(|v| v.as_str())
+        .unwrap_or_default();
+    let path = if let Some((_, value)) = url.split_once("://") {
+        value
+            .find('/')
+            .map(|index| &value[index..])
+            .unwrap_or_default()
+    } else {
+        url
+    };
+    path.trim_end_matches('/').to_string()
+}
+
+impl TryFrom<&PluginConf> for OpenApi {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let step = get_step_conf(value);
+
+        // the spec can be json string or file path
+        let spec = get_str_conf(value, "spec");
+        let data = if spec.trim_start().starts_with('{') {
+            spec
+        } else {
+            std::fs::read_to_string(&spec).map_err(|e| Error::Invalid {
+                category: PluginCategory::OpenApi.to_string(),
+                message: format!("read spec {spec} fail, {e}"),
+            })?
+        };
+        let spec: Value =
+            serde_json::from_str(&data).map_err(|e| Error::Invalid {
+                category: PluginCategory::OpenApi.to_string(),
+                message: e.to_string(),
+            })?;
+        let mut base_path = get_str_conf(value, "base_path");
+        if base_path.is_empty() {
+            base_path = get_server_base_path(&spec);
+        }
+
+        let params = Self {
+            hash_value,
+            plugin_step: step,
+            base_path: base_path.trim_end_matches('/').to_string(),
+            variable: get_str_conf(value, "variable"),
+            routes: parse_routes(&spec)?,
+        };
+        if params.plugin_step != PluginStep::Request {
+            return Err(Error::Invalid {
+                category: PluginCategory::OpenApi.to_string(),
+                message: "Open api plugin should be executed at request step"
+                    .to_string(),
+            });
+        }
+        Ok(params)
+    }
+}
+
+impl OpenApi {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new open api plugin");
+        Self::try_from(params)
+    }
+    /// Find the operation of request, the error response is returned
+    /// if the request doesn't match any operation of spec.
+    fn validate(
+        &self,
+        req_header: &RequestHeader,
+    ) -> std::result::Result<&Operation, HttpResponse> {
+        let path = req_header.uri.path();
+        let Some(path) = path
+            .strip_prefix(&self.base_path)
+            .filter(|value| value.is_empty() || value.starts_with('/'))
+        else {
+            return Err(HttpResponse::not_found(Bytes::from_static(
+                b"No operation matched",
+            )));
+        };
+        let Some(route) = self.routes.iter().find(|item| item.matched(path))
+        else {
+            return Err(HttpResponse::not_found(Bytes::from_static(
+                b"No operation matched",
+            )));
+        };
+        let Some(operation) = route.get_operation(&req_header.method) else {
+            let allow = route
+                .operations
+                .iter()
+                .map(|op| op.method.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            return Err(HttpResponse {
+                status: StatusCode::METHOD_NOT_ALLOWED,
+                body: Bytes::from_static(b"Method is not allowed"),
+                headers: HeaderValue::from_str(&allow)
+                    .ok()
+                    .map(|value| vec![(header::ALLOW, value)]),
+                ..Default::default()
+            });
+        };
+        for param in operation.parameters.iter() {
+            if !param.required {
+                continue;
+            }
+            let exists = match param.location.as_str() {
+                "query" => {
+                    util::get_query_value(req_header, &param.name).is_some()
+                },
+                "header" => req_header.headers.contains_key(&param.name),
+                "cookie" => {
+                    util::get_cookie_value(req_header, &param.name).is_some()
+                },
+                // path parameter is checked by route
+                _ => true,
+            };
+            if !exists {
+                return Err(HttpResponse::bad_request(
+                    format!(
+                        "Missing required {} parameter: {}",
+                        param.location, param.name
+                    )
+                    .into(),
+                ));
+            }
+        }
+        if operation.body_required {
+            let has_body = req_header
+                .headers
+                .contains_key(header::TRANSFER_ENCODING)
+                || util::get_content_length(req_header).unwrap_or_default() > 0;
+            if !has_body {
+                return Err(HttpResponse::bad_request(Bytes::from_static(
+                    b"Request body is required",
+                )));
+            }
+        }
+        Ok(operation)
+    }
+}
+
+#[async_trait]
+impl Plugin for OpenApi {
+    #[inline]
+    fn hash_key(&self) -> String {
+        self.hash_value.clone()
+    }
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut State,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        if step != self.plugin_step {
+            return Ok(None);
+        }
+        match self.validate(session.req_header()) {
+            Ok(operation) => {
+                if !self.variable.is_empty()
+                    && !operation.operation_id.is_empty()
+                {
+                    ctx.add_variable(&self.variable, &operation.operation_id);
+                }
+                Ok(None)
+            },
+            Err(resp) => Ok(Some(resp)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OpenApi;
+    use crate::config::{PluginConf, PluginStep};
+    use crate::plugin::Plugin;
+    use crate::state::State;
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use tokio_test::io::Builder;
+
+    const SPEC: &str = r###"{
+  "openapi": "3.0.0",
+  "servers": [{"url": "https://example.com/api"}],
+  "components": {
+    "parameters": {
+      "token": {"name": "X-Token", "in": "header", "required": true}
+    }
+  },
+  "paths": {
+    "/users/{id}": {
+      "parameters": [{"name": "id", "in": "path"}],
+      "get": {
+        "operationId": "getUser",
+        "parameters": [{"$ref": "#/components/parameters/token"}]
+      },
+      "delete": {"operationId": "deleteUser"}
+    },
+    "/users/me": {
+      "get": {"operationId": "getMe"}
+    },
+    "/users": {
+      "get": {
+        "operationId": "listUsers",
+        "parameters": [{"name": "page", "in": "query", "required": true}]
+      },
+      "post": {
+        "operationId": "createUser",
+        "requestBody": {"required": true}
+      }
+    }
+  }
+}"###;
+
+    fn new_open_api() -> OpenApi {
+        let mut conf = toml::from_str::<PluginConf>(
+            r###"
+variable = "operation_id"
+"###,
+        )
+        .unwrap();
+        conf.insert("spec".to_string(), toml::Value::String(SPEC.to_string()));
+        OpenApi::new(&conf).unwrap()
+    }
+
+    #[test]
+    fn test_open_api_params() {
+        let params = new_open_api();
+        assert_eq!("/api", params.base_path);
+        assert_eq!("operation_id", params.variable);
+        assert_eq!(3, params.routes.len());
+        // the route without template is matched first
+        assert_eq!(0, params.routes[0].templated);
+        assert_eq!(1, params.routes[2].templated);
+
+        let result = OpenApi::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+spec = "{}"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin open_api invalid, message: Paths of open api spec is not found",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_open_api() {
+        let params = new_open_api();
+
+        for (input_header, status, operation_id) in [
+            ("GET /api/users/me HTTP/1.1\r\n\r\n", None, "getMe"),
+            (
+                "GET /api/users/123 HTTP/1.1\r\nX-Token: pingap\r\n\r\n",
+                None,
+                "getUser",
+            ),
+            (
+                "HEAD /api/users/123 HTTP/1.1\r\nX-Token: pingap\r\n\r\n",
+                None,
+                "getUser",
+            ),
+            ("GET /api/users?page=1 HTTP/1.1\r\n\r\n", None, "listUsers"),
+            (
+                "POST /api/users HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}",
+                None,
+                "createUser",
+            ),
+            ("GET /api/users/123 HTTP/1.1\r\n\r\n", Some(400), ""),
+            ("GET /api/users HTTP/1.1\r\n\r\n", Some(400), ""),
+            ("POST /api/users HTTP/1.1\r\n\r\n", Some(400), ""),
+            ("PUT /api/users/123 HTTP/1.1\r\n\r\n", Some(405), ""),
+            ("GET /api/books HTTP/1.1\r\n\r\n", Some(404), ""),
+            ("GET /api/users/123/books HTTP/1.1\r\n\r\n", Some(404), ""),
+            ("GET /users/me HTTP/1.1\r\n\r\n", Some(404), ""),
+        ] {
+            let mock_io = Builder::new().read(input_header.as_bytes()).build();
+            let mut session = Session::new_h1(Box::new(mock_io));
+            session.read_request().await.unwrap();
+            let mut ctx = State::default();
+            let result = params
+                .handle_request(PluginStep::Request, &mut session, &mut ctx)
+                .await
+                .unwrap();
+            assert_eq!(
+                status,
+                result.as_ref().map(|resp| resp.status.as_u16()),
+                "{input_header}"
+            );
+            let value = ctx
+                .variables
+                .as_ref()
+                .and_then(|variables| variables.get("$operation_id").cloned())
+                .unwrap_or_default();
+            assert_eq!(operation_id, value);
+        }
+
+        let mock_io = Builder::new()
+            .read(b"PUT /api/users/123 HTTP/1.1\r\n\r\n")
+            .build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let resp = params
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            r#"Some([("allow", "GET, DELETE")])"#,
+            format!("{:?}", resp.headers)
+        );
+    }
+}
@@ -16,12 +16,19 @@ use super::{get_step_conf, get_str_conf, Error, Plugin, Result};
 use crate::config::{PluginCategory, PluginConf, PluginStep};
 use crate::http_extra::HttpResponse;
 use crate::plugin::get_hash_key;
+use crate::proxy::{
+    get_upstreams_checked_at, get_upstreams_healthy_status,
+    UpstreamHealthyStatus,
+};
 use crate::state::State;
+use crate::util;
 use async_trait::async_trait;
 use bytes::Bytes;
 use http::StatusCode;
 use once_cell::sync::Lazy;
 use pingora::proxy::Session;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
 use tracing::debug;
 
 pub struct Ping {
@@ -35,6 +42,41 @@ static PONG_RESPONSE: Lazy<HttpResponse> = Lazy::new(|| HttpResponse {
     ..Default::default()
 });
 
+#[derive(Serialize, Debug)]
+struct UpstreamSummary {
+    #[serde(flatten)]
+    status: UpstreamHealthyStatus,
+    // the timestamp(seconds) of last health check
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checked_at: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+struct PingDetail {
+    status: &'static str,
+    upstreams: BTreeMap<String, UpstreamSummary>,
+}
+
+/// Get the summary of upstreams, the status is degraded if any upstream
+/// has no healthy backend.
+fn get_ping_detail(
+    statuses: HashMap<String, UpstreamHealthyStatus>,
+    checked_at_list: &HashMap<String, u64>,
+) -> PingDetail {
+    let upstreams: BTreeMap<String, UpstreamSummary> = statuses
+        .into_iter()
+        .map(|(name, status)| {
+            let checked_at = checked_at_list.get(&name).copied();
+            (name, UpstreamSummary { status, checked_at })
+        })
+        .collect();
+    let degraded = upstreams.values().any(|item| item.status.healthy == 0);
+    PingDetail {
+        status: if degraded { "degraded" } else { "ok" },
+        upstreams,
+    }
+}
+
 impl Ping {
     pub fn new(params: &PluginConf) -> Result<Self> {
         debug!(params = params.to_string(), "new ping plugin");
@@ -71,20 +113,38 @@ impl Plugin for Ping {
         if step != self.plugin_step {
             return Ok(None);
         }
-        if session.req_header().uri.path() == self.path {
-            return Ok(Some(PONG_RESPONSE.clone()));
+        let req_header = session.req_header();
+        if req_header.uri.path() != self.path {
+            return Ok(None);
         }
-        Ok(None)
+        // the detail of upstreams is returned for readiness probe
+        if util::get_query_value(req_header, "detail") == Some("true") {
+            let detail = get_ping_detail(
+                get_upstreams_healthy_status(),
+                &get_upstreams_checked_at(),
+            );
+            let status = if detail.status == "ok" {
+                StatusCode::OK
+            } else {
+                StatusCode::SERVICE_UNAVAILABLE
+            };
+            return Ok(Some(HttpResponse::try_from_json_status(
+                &detail, status,
+            )?));
+        }
+        Ok(Some(PONG_RESPONSE.clone()))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Ping;
+    use super::{get_ping_detail, Ping};
+    use crate::proxy::UpstreamHealthyStatus;
     use crate::state::State;
     use crate::{config::PluginConf, config::PluginStep, plugin::Plugin};
     use pingora::proxy::Session;
     use pretty_assertions::assert_eq;
+    use std::collections::HashMap;
     use tokio_test::io::Builder;
 
     #[tokio::test]
@@ -134,4 +194,36 @@ path = "/ping"
             result.err().unwrap().to_string()
         );
     }
+
+    #[test]
+    fn test_ping_detail() {
+        let mut statuses = HashMap::new();
+        statuses.insert(
+            "charts".to_string(),
+            UpstreamHealthyStatus {
+                healthy: 1,
+                total: 2,
+                unhealthy_backends: vec!["127.0.0.1:5001".to_string()],
+            },
+        );
+        let mut checked_at_list = HashMap::new();
+        checked_at_list.insert("charts".to_string(), 1700000000);
+        let detail = get_ping_detail(statuses.clone(), &checked_at_list);
+        assert_eq!(
+            r#"{"status":"ok","upstreams":{"charts":{"healthy":1,"total":2,"unhealthy_backends":["127.0.0.1:5001"],"checked_at":1700000000}}}"#,
+            serde_json::to_string(&detail).unwrap()
+        );
+
+        statuses.insert(
+            "api".to_string(),
+            UpstreamHealthyStatus {
+                healthy: 0,
+                total: 1,
+                unhealthy_backends: vec!["127.0.0.1:6001".to_string()],
+            },
+        );
+        let detail = get_ping_detail(statuses, &checked_at_list);
+        assert_eq!("degraded", detail.status);
+        assert_eq!(true, detail.upstreams["api"].checked_at.is_none());
+    }
 }
@@ -0,0 +1,331 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::cache::get_cache_backend;
+use super::{
+    get_hash_key, get_step_conf, get_str_conf, get_str_slice_conf, Error,
+    Plugin, Result,
+};
+use crate::cache::is_warming_up;
+use crate::certificate::Certificate;
+use crate::config::{
+    get_current_config, PluginCategory, PluginConf, PluginStep,
+};
+use crate::http_extra::HttpResponse;
+use crate::proxy::{
+    get_certificate_info_list, get_upstreams_healthy_status,
+    UpstreamHealthyStatus,
+};
+use crate::service::get_config_reload_error;
+use crate::state::State;
+use crate::util;
+use async_trait::async_trait;
+use http::StatusCode;
+use pingora::proxy::Session;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use tracing::debug;
+
+const CHECK_UPSTREAM: &str = "upstream";
+const CHECK_CACHE: &str = "cache";
+const CHECK_CERTIFICATE: &str = "certificate";
+const CHECK_CONFIG: &str = "config";
+
+/// Aggregate the health of upstream, cache, certificate and config,
+/// it responds 503 if any check fails, which is useful for the probe of
+/// load balancer.
+pub struct Ready {
+    path: String,
+    checks: Vec<String>,
+    // the upstreams to check, all upstreams are checked if empty
+    upstreams: Vec<String>,
+    plugin_step: PluginStep,
+    hash_value: String,
+}
+
+#[derive(Serialize, Debug)]
+struct ReadyCheck {
+    healthy: bool,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    message: String,
+}
+
+impl ReadyCheck {
+    fn new(message: String) -> Self {
+        Self {
+            healthy: message.is_empty(),
+            message,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct ReadyResp {
+    status: &'static str,
+    checks: BTreeMap<String, ReadyCheck>,
+}
+
+impl TryFrom<&PluginConf> for Ready {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let step = get_step_conf(value);
+        let all_checks =
+            [CHECK_UPSTREAM, CHECK_CACHE, CHECK_CERTIFICATE, CHECK_CONFIG];
+        let mut checks = get_str_slice_conf(value, "checks");
+        if checks.is_empty() {
+            checks = all_checks.iter().map(|item| item.to_string()).collect();
+        }
+        for check in checks.iter() {
+            if !all_checks.contains(&check.as_str()) {
+                return Err(Error::Invalid {
+                    category: PluginCategory::Ready.to_string(),
+                    message: format!("check({check}) is not supported"),
+                });
+            }
+        }
+        let mut path = get_str_conf(value, "path");
+        if path.is_empty() {
+            path = "/ready".to_string();
+        }
+        let params = Self {
+            hash_value,
+            path,
+            checks,
+            upstreams: get_str_slice_conf(value, "upstreams"),
+            plugin_step: step,
+        };
+        if params.plugin_step != PluginStep::Request {
+            return Err(Error::Invalid {
+                category: PluginCategory::Ready.to_string(),
+                message: "Ready plugin should be executed at request step"
+                    .to_string(),
+            });
+        }
+        Ok(params)
+    }
+}
+
+/// Check the upstreams have at least one healthy backend.
+fn check_upstreams(
+    statuses: &HashMap<String, UpstreamHealthyStatus>,
+    names: &[String],
+) -> ReadyCheck {
+    let mut messages = vec![];
+    if names.is_empty() {
+        let mut unhealthy: Vec<&String> = statuses
+            .iter()
+            .filter(|(_, status)| status.healthy == 0)
+            .map(|(name, _)| name)
+            .collect();
+        unhealthy.sort();
+        for name in unhealthy {
+            messages.push(format!("{name} has no healthy backend"));
+        }
+    } else {
+        for name in names.iter() {
+            match statuses.get(name) {
+                Some(status) if status.healthy > 0 => {},
+                Some(_) => {
+                    messages.push(format!("{name} has no healthy backend"))
+                },
+                None => messages.push(format!("{name} is not found")),
+            }
+        }
+    }
+    ReadyCheck::new(messages.join("; "))
+}
+
+/// Check the certificates are not expired.
+fn check_certificates(
+    certificates: &[(String, Certificate)],
+    now: i64,
+) -> ReadyCheck {
+    let mut names: Vec<&String> = certificates
+        .iter()
+        .filter(|(_, cert)| cert.not_before > now || cert.not_after < now)
+        .map(|(name, _)| name)
+        .collect();
+    names.sort();
+    let message = names
+        .iter()
+        .map(|name| format!("{name} is expired or not yet valid"))
+        .collect::<Vec<_>>()
+        .join("; ");
+    ReadyCheck::new(message)
+}
+
+/// Check the cache storage is available, the memory cache is always
+/// available and the directory of file cache should exist.
+/// It isn't ready if the cache is warming up.
+async fn check_cache() -> ReadyCheck {
+    if is_warming_up() {
+        return ReadyCheck::new("cache is warming up".to_string());
+    }
+    if get_current_config().basic.cache_directory.is_none() {
+        return ReadyCheck::new(String::new());
+    }
+    let cache = match get_cache_backend() {
+        Ok(cache) => cache,
+        Err(e) => return ReadyCheck::new(e.to_string()),
+    };
+    let Some(dir) = &cache.directory else {
+        return ReadyCheck::new(String::new());
+    };
+    match tokio::fs::metadata(dir).await {
+        Ok(meta) if meta.is_dir() => ReadyCheck::new(String::new()),
+        Ok(_) => ReadyCheck::new(format!("{dir} is not a directory")),
+        Err(e) => ReadyCheck::new(format!("{dir} is unavailable, {e}")),
+    }
+}
+
+impl Ready {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new ready plugin");
+        Self::try_from(params)
+    }
+    async fn run_checks(&self) -> ReadyResp {
+        let mut checks = BTreeMap::new();
+        for check in self.checks.iter() {
+            let result = match check.as_str() {
+                CHECK_UPSTREAM => check_upstreams(
+                    &get_upstreams_healthy_status(),
+                    &self.upstreams,
+                ),
+                CHECK_CACHE => check_cache().await,
+                CHECK_CERTIFICATE => check_certificates(
+                    &get_certificate_info_list(),
+                    util::now().as_secs() as i64,
+                ),
+                _ => ReadyCheck::new(
+                    get_config_reload_error().unwrap_or_default(),
+                ),
+            };
+            checks.insert(check.to_string(), result);
+        }
+        let degraded = checks.values().any(|item| !item.healthy);
+        ReadyResp {
+            status: if degraded { "degraded" } else { "ok" },
+            checks,
+        }
+    }
+}
+
+#[async_trait]
+impl Plugin for Ready {
+    #[inline]
+    fn hash_key(&self) -> String {
+        self.hash_value.clone()
+    }
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        _ctx: &mut State,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        if step != self.plugin_step
+            || session.req_header().uri.path() != self.path
+        {
+            return Ok(None);
+        }
+        let resp = self.run_checks().await;
+        let status = if resp.status == "ok" {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        Ok(Some(HttpResponse::try_from_json_status(&resp, status)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_certificates, check_upstreams, Ready};
+    use crate::certificate::Certificate;
+    use crate::config::PluginConf;
+    use crate::proxy::UpstreamHealthyStatus;
+    use pretty_assertions::assert_eq;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_ready_params() {
+        let params = Ready::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+checks = ["upstream", "config"]
+upstreams = ["charts"]
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!("/ready", params.path);
+        assert_eq!(r#"["upstream", "config"]"#, format!("{:?}", params.checks));
+
+        let result = Ready::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+checks = ["disk"]
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin ready invalid, message: check(disk) is not supported",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_ready_checks() {
+        let mut statuses = HashMap::new();
+        statuses.insert(
+            "charts".to_string(),
+            UpstreamHealthyStatus {
+                healthy: 1,
+                total: 2,
+                unhealthy_backends: vec!["127.0.0.1:5001".to_string()],
+            },
+        );
+        statuses.insert(
+            "diving".to_string(),
+            UpstreamHealthyStatus {
+                healthy: 0,
+                total: 1,
+                unhealthy_backends: vec!["127.0.0.1:5002".to_string()],
+            },
+        );
+        let result = check_upstreams(&statuses, &["charts".to_string()]);
+        assert_eq!(true, result.healthy);
+        let result = check_upstreams(&statuses, &[]);
+        assert_eq!(false, result.healthy);
+        assert_eq!("diving has no healthy backend", result.message);
+        let result = check_upstreams(&statuses, &["unknown".to_string()]);
+        assert_eq!("unknown is not found", result.message);
+
+        let certificates = vec![(
+            "pingap".to_string(),
+            Certificate {
+                not_before: 100,
+                not_after: 200,
+                ..Default::default()
+            },
+        )];
+        assert_eq!(true, check_certificates(&certificates, 150).healthy);
+        let result = check_certificates(&certificates, 300);
+        assert_eq!(false, result.healthy);
+        assert_eq!("pingap is expired or not yet valid", result.message);
+    }
+}
@@ -13,7 +13,8 @@
 // limitations under the License.
 
 use super::{
-    get_bool_conf, get_step_conf, get_str_conf, Error, Plugin, Result,
+    get_bool_conf, get_int_conf, get_step_conf, get_str_conf,
+    get_str_slice_conf, Error, Plugin, Result,
 };
 use crate::config::{PluginCategory, PluginConf, PluginStep};
 use crate::http_extra::convert_headers;
@@ -24,15 +25,74 @@ use crate::util;
 use async_trait::async_trait;
 use http::StatusCode;
 use pingora::proxy::Session;
+use regex::Regex;
 use tracing::debug;
 
+/// The regex redirect rule, the capture groups of source can be
+/// used in target, e.g. `^/old/(.*)$ /new/$1 301`.
+#[derive(Debug)]
+struct RedirectRule {
+    source: Regex,
+    target: String,
+    status: Option<StatusCode>,
+}
+
 pub struct Redirect {
     prefix: String,
     http_to_https: bool,
+    rules: Vec<RedirectRule>,
+    status: StatusCode,
+    // drop the query of request instead of merging to target
+    drop_query: bool,
     plugin_step: PluginStep,
     hash_value: String,
 }
 
+fn new_invalid_error(message: String) -> Error {
+    Error::Invalid {
+        category: PluginCategory::Redirect.to_string(),
+        message,
+    }
+}
+
+fn parse_status(value: i64) -> Result<StatusCode> {
+    StatusCode::from_u16(value as u16)
+        .ok()
+        .filter(|status| [301, 302, 307, 308].contains(&status.as_u16()))
+        .ok_or_else(|| new_invalid_error(format!("status({value}) is invalid")))
+}
+
+fn parse_rules(values: &[String]) -> Result<Vec<RedirectRule>> {
+    let mut rules = vec![];
+    for item in values.iter() {
+        let items: Vec<&str> = item.split_whitespace().collect();
+        if items.len() < 2 || items.len() > 3 {
+            return Err(new_invalid_error(format!("rule({item}) is invalid")));
+        }
+        let status = if let Some(status) = items.get(2) {
+            Some(parse_status(status.parse::<i64>().unwrap_or_default())?)
+        } else {
+            None
+        };
+        rules.push(RedirectRule {
+            source: Regex::new(items[0])
+                .map_err(|e| new_invalid_error(e.to_string()))?,
+            target: items[1].to_string(),
+            status,
+        });
+    }
+    Ok(rules)
+}
+
+/// Merge the query of request to the location.
+fn merge_query(location: String, query: Option<&str>) -> String {
+    match query.filter(|value| !value.is_empty()) {
+        Some(query) if location.contains('?') => format!("{location}&{query}"),
+        Some(query) => format!("{location}?{query}"),
+        None => location,
+    }
+}
+
 impl Redirect {
     pub fn new(params: &PluginConf) -> Result<Self> {
         debug!(params = params.to_string(), "new redirect plugin");
@@ -52,10 +112,19 @@ impl Redirect {
         } else if !prefix.starts_with("/") {
             prefix = format!("/{prefix}");
         }
+        let status = get_int_conf(params, "status");
+        let status = if status == 0 {
+            StatusCode::TEMPORARY_REDIRECT
+        } else {
+            parse_status(status)?
+        };
         Ok(Self {
             hash_value,
             prefix,
             http_to_https: get_bool_conf(params, "http_to_https"),
+            rules: parse_rules(&get_str_slice_conf(params, "rules"))?,
+            status,
+            drop_query: get_bool_conf(params, "drop_query"),
             plugin_step: step,
         })
     }
@@ -77,6 +146,32 @@ impl Plugin for Redirect {
         if step != self.plugin_step {
             return Ok(None);
         }
+        let uri = &session.req_header().uri;
+        let path = uri.path();
+        if let Some(rule) =
+            self.rules.iter().find(|rule| rule.source.is_match(path))
+        {
+            let mut location =
+                rule.source.replace(path, rule.target.as_str()).to_string();
+            if !self.drop_query {
+                location = merge_query(location, uri.query());
+            }
+            return Ok(Some(HttpResponse {
+                status: rule.status.unwrap_or(self.status),
+                headers: Some(
+                    convert_headers(&[format!("Location: {location}")])
+                        .unwrap_or_default(),
+                ),
+                ..Default::default()
+            }));
+        }
+        // only regex rules are used
+        if !self.rules.is_empty()
+            && !self.http_to_https
+            && self.prefix.is_empty()
+        {
+            return Ok(None);
+        }
         let schema_match = ctx.tls_version.is_some() == self.http_to_https;
         if schema_match
             && session.req_header().uri.path().starts_with(&self.prefix)
@@ -92,7 +187,7 @@ impl Plugin for Redirect {
             session.req_header().uri
         );
         Ok(Some(HttpResponse {
-            status: StatusCode::TEMPORARY_REDIRECT,
+            status: self.status,
             headers: Some(convert_headers(&[location]).unwrap_or_default()),
             ..Default::default()
         }))
@@ -158,4 +253,80 @@ prefix = "/api"
             params.err().unwrap().to_string()
         );
     }
+
+    #[tokio::test]
+    async fn test_redirect_rules() {
+        let redirect = Redirect::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+rules = [
+    "^/old/(.*)$ /new/$1 301",
+    "^/docs/(?P<name>[^/]+)$ https://docs.pingap.io/$name?from=pingap",
+]
+status = 308
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        for (path, status, location) in [
+            (
+                "/old/users?id=1",
+                StatusCode::MOVED_PERMANENTLY,
+                "/new/users?id=1",
+            ),
+            (
+                "/docs/plugin?lang=en",
+                StatusCode::PERMANENT_REDIRECT,
+                "https://docs.pingap.io/plugin?from=pingap&lang=en",
+            ),
+        ] {
+            let input_header = format!("GET {path} HTTP/1.1\r\n\r\n");
+            let mock_io = Builder::new().read(input_header.as_bytes()).build();
+            let mut session = Session::new_h1(Box::new(mock_io));
+            session.read_request().await.unwrap();
+            let resp = redirect
+                .handle_request(
+                    PluginStep::Request,
+                    &mut session,
+                    &mut State::default(),
+                )
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(status, resp.status);
+            assert_eq!(
+                format!(r###"Some([("location", "{location}")])"###),
+                format!("{:?}", resp.headers)
+            );
+        }
+
+        let input_header = "GET /users HTTP/1.1\r\n\r\n";
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let result = redirect
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_none());
+
+        let result = Redirect::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+status = 200
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin redirect invalid, message: status(200) is invalid",
+            result.err().unwrap().to_string()
+        );
+    }
 }
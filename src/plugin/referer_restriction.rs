@@ -13,28 +13,50 @@
 // limitations under the License.
 
 use super::{
-    get_hash_key, get_step_conf, get_str_conf, get_str_slice_conf, Error,
-    Plugin, Result,
+    get_bool_conf, get_hash_key, get_int_conf, get_step_conf, get_str_conf,
+    get_str_slice_conf, Error, Plugin, Result,
 };
 use crate::config::{PluginCategory, PluginConf, PluginStep};
-use crate::http_extra::HttpResponse;
+use crate::http_extra::{convert_headers, HttpResponse, HTTP_HEADER_NO_STORE};
 use crate::state::State;
+use crate::util;
 use async_trait::async_trait;
 use bytes::Bytes;
 use http::StatusCode;
 use pingora::proxy::Session;
+use regex::Regex;
 use substring::Substring;
-use tracing::debug;
+use tracing::{debug, warn};
+
+// The transparent gif of 1x1 pixel
+static TRANSPARENT_GIF: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00,
+    0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00,
+    0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
 
 pub struct RefererRestriction {
     plugin_step: PluginStep,
     referer_list: Vec<String>,
     prefix_referer_list: Vec<String>,
+    // the referer with wildcard in the middle, e.g. `img.*.com`
+    wildcard_referer_list: Vec<Regex>,
     restriction_category: String,
+    // only log the violation without blocking the request
+    report_only: bool,
     forbidden_resp: HttpResponse,
     hash_value: String,
 }
 
+fn new_wildcard_regex(value: &str) -> Result<Regex> {
+    let reg = regex::escape(value).replace("\\*", "[^.]+");
+    Regex::new(&format!("^{reg}$")).map_err(|e| Error::Invalid {
+        category: PluginCategory::RefererRestriction.to_string(),
+        message: e.to_string(),
+    })
+}
+
 impl TryFrom<&PluginConf> for RefererRestriction {
     type Error = Error;
     fn try_from(value: &PluginConf) -> Result<Self> {
@@ -42,10 +64,13 @@ impl TryFrom<&PluginConf> for RefererRestriction {
         let step = get_step_conf(value);
         let mut referer_list = vec![];
         let mut prefix_referer_list = vec![];
+        let mut wildcard_referer_list = vec![];
         for item in get_str_slice_conf(value, "referer_list").iter() {
-            if item.starts_with('*') {
+            if item.starts_with('*') && item.matches('*').count() == 1 {
                 prefix_referer_list
                     .push(item.substring(1, item.len()).to_string());
+            } else if item.contains('*') {
+                wildcard_referer_list.push(new_wildcard_regex(item)?);
             } else {
                 referer_list.push(item.to_string());
             }
@@ -55,17 +80,57 @@ impl TryFrom<&PluginConf> for RefererRestriction {
         if message.is_empty() {
             message = "Request is forbidden".to_string();
         }
+        let redirect = get_str_conf(value, "redirect");
+        let forbidden_resp = if get_bool_conf(value, "image") {
+            // the hotlink image is replaced by a transparent pixel
+            HttpResponse {
+                status: StatusCode::OK,
+                body: Bytes::from_static(TRANSPARENT_GIF),
+                headers: Some(vec![
+                    HTTP_HEADER_NO_STORE.clone(),
+                    (http::header::CONTENT_TYPE, "image/gif".parse().unwrap()),
+                ]),
+                ..Default::default()
+            }
+        } else if !redirect.is_empty() {
+            let headers = convert_headers(&[format!("Location: {redirect}")])
+                .map_err(|e| Error::Invalid {
+                category: PluginCategory::RefererRestriction.to_string(),
+                message: e.to_string(),
+            })?;
+            HttpResponse {
+                status: StatusCode::FOUND,
+                headers: Some(headers),
+                ..Default::default()
+            }
+        } else {
+            let status = get_int_conf(value, "status");
+            let status = if status > 0 {
+                StatusCode::from_u16(status as u16).map_err(|e| {
+                    Error::Invalid {
+                        category: PluginCategory::RefererRestriction
+                            .to_string(),
+                        message: e.to_string(),
+                    }
+                })?
+            } else {
+                StatusCode::FORBIDDEN
+            };
+            HttpResponse {
+                status,
+                body: Bytes::from(message),
+                ..Default::default()
+            }
+        };
         let params = Self {
             hash_value,
             plugin_step: step,
             prefix_referer_list,
+            wildcard_referer_list,
             referer_list,
             restriction_category: get_str_conf(value, "type"),
-            forbidden_resp: HttpResponse {
-                status: StatusCode::FORBIDDEN,
-                body: Bytes::from(message),
-                ..Default::default()
-            },
+            report_only: get_str_conf(value, "mode") == "report",
+            forbidden_resp,
         };
         if ![PluginStep::Request, PluginStep::ProxyUpstream]
             .contains(&params.plugin_step)
@@ -101,14 +166,15 @@ impl Plugin for RefererRestriction {
         &self,
         step: PluginStep,
         session: &mut Session,
-        _ctx: &mut State,
+        ctx: &mut State,
     ) -> pingora::Result<Option<HttpResponse>> {
         if step != self.plugin_step {
             return Ok(None);
         }
         let mut found = false;
+        let mut referer = "".to_string();
         if let Some(value) = session.get_header(http::header::REFERER) {
-            let referer = value.to_str().unwrap_or_default().to_string();
+            referer = value.to_str().unwrap_or_default().to_string();
             let host = if let Ok(info) = url::Url::parse(&referer) {
                 info.host_str().unwrap_or_default().to_string()
             } else {
@@ -120,7 +186,11 @@ impl Plugin for RefererRestriction {
                 found = self
                     .prefix_referer_list
                     .iter()
-                    .any(|item| host.ends_with(item));
+                    .any(|item| host.ends_with(item))
+                    || self
+                        .wildcard_referer_list
+                        .iter()
+                        .any(|item| item.is_match(&host));
             }
         }
         let allow = if self.restriction_category == "deny" {
@@ -129,6 +199,20 @@ impl Plugin for RefererRestriction {
             found
         };
         if !allow {
+            if self.report_only {
+                let ip = if let Some(ip) = &ctx.client_ip {
+                    ip.to_string()
+                } else {
+                    util::get_client_ip(session)
+                };
+                warn!(
+                    referer,
+                    ip,
+                    path = session.req_header().uri.path(),
+                    "referer restriction violation is reported"
+                );
+                return Ok(None);
+            }
             return Ok(Some(self.forbidden_resp.clone()));
         }
         return Ok(None);
@@ -250,4 +334,100 @@ type = "deny"
         assert_eq!(true, result.is_some());
         assert_eq!(StatusCode::FORBIDDEN, result.unwrap().status);
     }
+
+    #[tokio::test]
+    async fn test_referer_restriction_wildcard() {
+        let new_session = |referer: &'static str| async move {
+            let input_header = format!(
+                "GET /images/a.png HTTP/1.1\r\nReferer: {referer}\r\n\r\n"
+            );
+            let mock_io = Builder::new().read(input_header.as_bytes()).build();
+            let mut session = Session::new_h1(Box::new(mock_io));
+            session.read_request().await.unwrap();
+            session
+        };
+        let allow = RefererRestriction::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+referer_list = ["img.*.pingap.io"]
+type = "allow"
+image = true
+    "###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let mut session = new_session("https://img.cdn.pingap.io/").await;
+        let result = allow
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_none());
+
+        let mut session = new_session("https://img.a.b.pingap.io/").await;
+        let result = allow
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(StatusCode::OK, result.status);
+        assert_eq!(43, result.body.len());
+
+        let redirect = RefererRestriction::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+referer_list = ["pingap.io"]
+type = "allow"
+redirect = "https://pingap.io/hotlink.png"
+    "###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let mut session = new_session("https://github.com/").await;
+        let result = redirect
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(StatusCode::FOUND, result.status);
+        assert_eq!(
+            r#"Some([("location", "https://pingap.io/hotlink.png")])"#,
+            format!("{:?}", result.headers)
+        );
+
+        let report = RefererRestriction::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+referer_list = ["pingap.io"]
+type = "allow"
+mode = "report"
+    "###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let mut session = new_session("https://github.com/").await;
+        let result = report
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_none());
+    }
 }
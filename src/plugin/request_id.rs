@@ -13,26 +13,32 @@
 // limitations under the License.
 
 use super::{
-    get_hash_key, get_int_conf, get_step_conf, get_str_conf, Error, Plugin,
-    Result,
+    get_hash_key, get_int_conf, get_step_conf, get_str_conf,
+    get_str_slice_conf, Error, Plugin, Result,
 };
 use crate::config::{PluginCategory, PluginConf, PluginStep};
 use crate::http_extra::HttpResponse;
 use crate::http_extra::HTTP_HEADER_NAME_X_REQUEST_ID;
 use crate::state::State;
+use crate::util;
 use async_trait::async_trait;
 use http::HeaderName;
 use nanoid::nanoid;
 use pingora::proxy::Session;
+use regex::Regex;
 use std::str::FromStr;
 use tracing::debug;
-use uuid::Uuid;
 
 pub struct RequestId {
     plugin_step: PluginStep,
     algorithm: String,
     header_name: Option<HeaderName>,
     size: usize,
+    // the incoming id is only reused if the connection is from
+    // the trusted proxies, all are trusted if it's not set
+    trusted_proxies: Option<util::IpRules>,
+    // the format of incoming id
+    id_format: Option<Regex>,
     hash_value: String,
 }
 
@@ -53,12 +59,30 @@ impl TryFrom<&PluginConf> for RequestId {
             })?)
         };
 
+        let trusted_proxies = get_str_slice_conf(value, "trusted_proxies");
+        let trusted_proxies = if trusted_proxies.is_empty() {
+            None
+        } else {
+            Some(util::IpRules::new(&trusted_proxies))
+        };
+        let id_format = get_str_conf(value, "id_format");
+        let id_format = if id_format.is_empty() {
+            None
+        } else {
+            Some(Regex::new(&id_format).map_err(|e| Error::Invalid {
+                category: PluginCategory::RequestId.to_string(),
+                message: e.to_string(),
+            })?)
+        };
+
         let params = Self {
             hash_value,
             plugin_step: step,
             algorithm: get_str_conf(value, "algorithm"),
             size: get_int_conf(value, "size") as usize,
             header_name,
+            trusted_proxies,
+            id_format,
         };
         if ![PluginStep::Request, PluginStep::ProxyUpstream]
             .contains(&params.plugin_step)
@@ -77,6 +101,29 @@ impl RequestId {
         debug!(params = params.to_string(), "new request id plugin");
         Self::try_from(params)
     }
+    /// Check the incoming id can be reused, the connection should be
+    /// from trusted proxies and the id should match the format.
+    fn is_trusted(&self, session: &Session, ctx: &State, id: &str) -> bool {
+        if id.is_empty() {
+            return false;
+        }
+        if let Some(id_format) = &self.id_format {
+            if !id_format.is_match(id) {
+                return false;
+            }
+        }
+        let Some(trusted_proxies) = &self.trusted_proxies else {
+            return true;
+        };
+        let remote_addr = if let Some(addr) = &ctx.remote_addr {
+            addr.to_string()
+        } else if let Some((addr, _)) = util::get_remote_addr(session) {
+            addr
+        } else {
+            return false;
+        };
+        trusted_proxies.matched(&remote_addr).unwrap_or_default()
+    }
 }
 
 #[async_trait]
@@ -101,15 +148,18 @@ impl Plugin for RequestId {
             HTTP_HEADER_NAME_X_REQUEST_ID.clone()
         };
         if let Some(id) = session.get_header(&key) {
-            ctx.request_id = Some(id.to_str().unwrap_or_default().to_string());
-            return Ok(None);
+            let id = id.to_str().unwrap_or_default();
+            if self.is_trusted(session, ctx, id) {
+                ctx.request_id = Some(id.to_string());
+                return Ok(None);
+            }
         }
         let id = match self.algorithm.as_str() {
             "nanoid" => {
                 let size = self.size;
                 nanoid!(size)
             },
-            _ => Uuid::now_v7().to_string(),
+            _ => util::new_request_id(),
         };
         ctx.request_id = Some(id.clone());
         let _ = session.req_header_mut().insert_header(key, &id);
@@ -213,4 +263,49 @@ size = 10
         assert_eq!(true, result.is_none());
         assert_eq!(10, state.request_id.unwrap_or_default().len());
     }
+
+    #[tokio::test]
+    async fn test_trusted_request_id() {
+        let id = RequestId::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+trusted_proxies = ["10.0.0.0/8"]
+id_format = "^[a-zA-Z0-9-]{3,64}$"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        for (remote_addr, request_id, trusted) in [
+            ("10.0.0.1", "abc-123", true),
+            ("192.168.1.1", "abc-123", false),
+            ("10.0.0.1", "abc 123", false),
+        ] {
+            let input_header =
+                format!("GET / HTTP/1.1\r\nX-Request-Id: {request_id}\r\n\r\n");
+            let mock_io = Builder::new().read(input_header.as_bytes()).build();
+            let mut session = Session::new_h1(Box::new(mock_io));
+            session.read_request().await.unwrap();
+
+            let mut state = State {
+                remote_addr: Some(remote_addr.to_string()),
+                ..Default::default()
+            };
+            id.handle_request(PluginStep::Request, &mut session, &mut state)
+                .await
+                .unwrap();
+            let value = state.request_id.unwrap_or_default();
+            assert_eq!(trusted, value == request_id);
+            // the header is replaced by the new id
+            assert_eq!(
+                value,
+                session
+                    .get_header("X-Request-Id")
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+            );
+        }
+    }
 }
@@ -0,0 +1,369 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    get_hash_key, get_step_conf, get_str_conf, get_str_slice_conf, Error,
+    Plugin, Result,
+};
+use crate::config::{PluginCategory, PluginConf, PluginStep};
+use crate::http_extra::HttpResponse;
+use crate::state::State;
+use crate::util;
+use async_trait::async_trait;
+use http::{header, HeaderName, HeaderValue, Method, StatusCode};
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use regex::Regex;
+use tracing::debug;
+
+const ROBOTS_PATH: &str = "/robots.txt";
+const NOINDEX: &str = "noindex, nofollow";
+const DIRECTIVES: [&str; 5] =
+    ["User-agent", "Allow", "Disallow", "Sitemap", "Crawl-delay"];
+
+static X_ROBOTS_TAG: HeaderName = HeaderName::from_static("x-robots-tag");
+
+/// Serve the robots.txt generated by the rules of host, and add
+/// `X-Robots-Tag` header to the responses of matching path.
+/// The hosts of noindex are never indexed, e.g. staging hosts.
+pub struct Robots {
+    plugin_step: PluginStep,
+    // (host pattern, directive line)
+    rules: Vec<(String, String)>,
+    noindex_hosts: Vec<String>,
+    x_robots_tag: Option<HeaderValue>,
+    path: Option<Regex>,
+    hash_value: String,
+}
+
+/// Check the host is matched, the pattern supports `*` prefix
+/// for matching all sub domains.
+fn is_host_matched(pattern: &str, host: &str) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+    if let Some(suffix) = pattern.strip_prefix('*') {
+        return host.ends_with(suffix);
+    }
+    pattern == host
+}
+
+/// Parse the rule as (host pattern, directive line),
+/// e.g. `*.pingap.io Disallow: /admin`.
+fn parse_rule(value: &str) -> Result<(String, String)> {
+    let invalid = |message: String| Error::Invalid {
+        category: PluginCategory::Robots.to_string(),
+        message,
+    };
+    let (host, line) = value
+        .trim()
+        .split_once(' ')
+        .ok_or_else(|| invalid(format!("rule({value}) is invalid")))?;
+    let line = line.trim();
+    let (name, directive) = line
+        .split_once(':')
+        .ok_or_else(|| invalid(format!("rule({value}) is invalid")))?;
+    let name = name.trim();
+    let Some(name) = DIRECTIVES
+        .iter()
+        .find(|item| item.eq_ignore_ascii_case(name))
+    else {
+        return Err(invalid(format!("directive({name}) is not supported")));
+    };
+    Ok((host.to_string(), format!("{name}: {}", directive.trim())))
+}
+
+impl TryFrom<&PluginConf> for Robots {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let step = get_step_conf(value);
+
+        let mut rules = vec![];
+        for item in get_str_slice_conf(value, "rules").iter() {
+            rules.push(parse_rule(item)?);
+        }
+        let x_robots_tag = get_str_conf(value, "x_robots_tag");
+        let x_robots_tag = if x_robots_tag.is_empty() {
+            None
+        } else {
+            Some(HeaderValue::from_str(&x_robots_tag).map_err(|e| {
+                Error::Invalid {
+                    category: PluginCategory::Robots.to_string(),
+                    message: e.to_string(),
+                }
+            })?)
+        };
+        let path = get_str_conf(value, "path");
+        let path = if path.is_empty() {
+            None
+        } else {
+            Some(Regex::new(&path).map_err(|e| Error::Invalid {
+                category: PluginCategory::Robots.to_string(),
+                message: e.to_string(),
+            })?)
+        };
+
+        let params = Self {
+            hash_value,
+            plugin_step: step,
+            rules,
+            noindex_hosts: get_str_slice_conf(value, "noindex_hosts"),
+            x_robots_tag,
+            path,
+        };
+        if params.rules.is_empty()
+            && params.noindex_hosts.is_empty()
+            && params.x_robots_tag.is_none()
+        {
+            return Err(Error::Invalid {
+                category: PluginCategory::Robots.to_string(),
+                message: "Rules, noindex hosts or x robots tag should be set"
+                    .to_string(),
+            });
+        }
+        if params.plugin_step != PluginStep::Request {
+            return Err(Error::Invalid {
+                category: PluginCategory::Robots.to_string(),
+                message: "Robots plugin should be executed at request step"
+                    .to_string(),
+            });
+        }
+        Ok(params)
+    }
+}
+
+impl Robots {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new robots plugin");
+        Self::try_from(params)
+    }
+    fn is_noindex(&self, host: &str) -> bool {
+        self.noindex_hosts
+            .iter()
+            .any(|pattern| is_host_matched(pattern, host))
+    }
+    /// Generate the robots.txt of host, it returns none if no rule
+    /// matches the host, then the robots.txt of upstream is used.
+    fn generate(&self, host: &str) -> Option<String> {
+        if self.is_noindex(host) {
+            return Some("User-agent: *\nDisallow: /\n".to_string());
+        }
+        let mut lines: Vec<&str> = self
+            .rules
+            .iter()
+            .filter(|(pattern, _)| is_host_matched(pattern, host))
+            .map(|(_, line)| line.as_str())
+            .collect();
+        if lines.is_empty() {
+            return None;
+        }
+        if !lines.iter().any(|line| line.starts_with("User-agent:")) {
+            lines.insert(0, "User-agent: *");
+        }
+        Some(lines.join("\n") + "\n")
+    }
+}
+
+#[async_trait]
+impl Plugin for Robots {
+    #[inline]
+    fn hash_key(&self) -> String {
+        self.hash_value.clone()
+    }
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        _ctx: &mut State,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        if step != self.plugin_step {
+            return Ok(None);
+        }
+        let req_header = session.req_header();
+        if req_header.uri.path() != ROBOTS_PATH
+            || ![Method::GET, Method::HEAD].contains(&req_header.method)
+        {
+            return Ok(None);
+        }
+        let host = util::get_host(req_header).unwrap_or_default();
+        let Some(body) = self.generate(host) else {
+            return Ok(None);
+        };
+        Ok(Some(HttpResponse {
+            status: StatusCode::OK,
+            body: body.into(),
+            max_age: Some(3600),
+            headers: Some(vec![(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("text/plain; charset=utf-8"),
+            )]),
+            ..Default::default()
+        }))
+    }
+    async fn handle_response(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        _ctx: &mut State,
+        upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<()> {
+        if step != PluginStep::Response {
+            return Ok(());
+        }
+        let req_header = session.req_header();
+        let host = util::get_host(req_header).unwrap_or_default();
+        if self.is_noindex(host) {
+            let _ =
+                upstream_response.insert_header(X_ROBOTS_TAG.clone(), NOINDEX);
+            return Ok(());
+        }
+        let Some(value) = &self.x_robots_tag else {
+            return Ok(());
+        };
+        if let Some(reg) = &self.path {
+            if !reg.is_match(req_header.uri.path()) {
+                return Ok(());
+            }
+        }
+        let _ = upstream_response.insert_header(X_ROBOTS_TAG.clone(), value);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Robots;
+    use crate::config::{PluginConf, PluginStep};
+    use crate::plugin::Plugin;
+    use crate::state::State;
+    use pingora::http::ResponseHeader;
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use tokio_test::io::Builder;
+
+    fn new_robots() -> Robots {
+        Robots::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+rules = [
+    "pingap.io Disallow: /admin",
+    "* Sitemap: https://pingap.io/sitemap.xml",
+]
+noindex_hosts = ["*.staging.pingap.io"]
+x_robots_tag = "noarchive"
+path = "^/private"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_robots_params() {
+        let result = Robots::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+rules = ["* Noindex: /"]
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin robots invalid, message: directive(Noindex) is not supported",
+            result.err().unwrap().to_string()
+        );
+
+        let robots = new_robots();
+        assert_eq!(
+            Some(
+                "User-agent: *\nDisallow: /admin\nSitemap: https://pingap.io/sitemap.xml\n"
+                    .to_string()
+            ),
+            robots.generate("pingap.io")
+        );
+        assert_eq!(
+            Some("User-agent: *\nDisallow: /\n".to_string()),
+            robots.generate("www.staging.pingap.io")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_robots() {
+        let robots = new_robots();
+
+        let headers = ["Host: pingap.io"].join("\r\n");
+        let input_header =
+            format!("GET /robots.txt HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let result = robots
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            "User-agent: *\nDisallow: /admin\nSitemap: https://pingap.io/sitemap.xml\n",
+            std::str::from_utf8(&result.body).unwrap()
+        );
+
+        let headers = ["Host: www.staging.pingap.io"].join("\r\n");
+        let input_header = format!("GET /api HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let mut upstream_response = ResponseHeader::build(200, None).unwrap();
+        robots
+            .handle_response(
+                PluginStep::Response,
+                &mut session,
+                &mut State::default(),
+                &mut upstream_response,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            "noindex, nofollow",
+            upstream_response.headers.get("X-Robots-Tag").unwrap()
+        );
+
+        let headers = ["Host: pingap.io"].join("\r\n");
+        let input_header =
+            format!("GET /private/1 HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let mut upstream_response = ResponseHeader::build(200, None).unwrap();
+        robots
+            .handle_response(
+                PluginStep::Response,
+                &mut session,
+                &mut State::default(),
+                &mut upstream_response,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            "noarchive",
+            upstream_response.headers.get("X-Robots-Tag").unwrap()
+        );
+    }
+}
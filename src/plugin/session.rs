@@ -0,0 +1,472 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    get_bool_conf, get_hash_key, get_step_conf, get_str_conf,
+    get_str_slice_conf, Error, Plugin, Result,
+};
+use crate::config::{PluginCategory, PluginConf, PluginStep};
+use crate::http_extra::{HttpResponse, HTTP_HEADER_NO_STORE};
+use crate::state::State;
+use crate::util;
+use aes_gcm_siv::{
+    aead::{Aead, KeyInit},
+    Aes256GcmSiv, Nonce,
+};
+use async_trait::async_trait;
+use cookie::{Cookie, SameSite};
+use http::{header, HeaderName, HeaderValue, StatusCode};
+use humantime::parse_duration;
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::debug;
+
+const NONCE_SIZE: usize = 12;
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct SessionData {
+    // expired at(unix seconds)
+    exp: u64,
+    data: HashMap<String, String>,
+}
+
+pub struct SessionCookie {
+    plugin_step: PluginStep,
+    name: String,
+    // the first cipher is used for encryption,
+    // the others are only used for decryption(key rotation)
+    ciphers: Vec<Aes256GcmSiv>,
+    ttl: Duration,
+    secure: bool,
+    login_path: String,
+    logout_path: String,
+    header: Option<HeaderName>,
+    hash_value: String,
+}
+
+fn new_cipher(secret: &str) -> Result<Aes256GcmSiv> {
+    let mut hasher = Sha256::new();
+    hasher.update(secret.as_bytes());
+    Aes256GcmSiv::new_from_slice(&hasher.finalize()).map_err(|e| {
+        Error::Invalid {
+            category: PluginCategory::Session.to_string(),
+            message: e.to_string(),
+        }
+    })
+}
+
+impl TryFrom<&PluginConf> for SessionCookie {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let step = get_step_conf(value);
+
+        let mut ciphers = vec![];
+        for secret in get_str_slice_conf(value, "secrets").iter() {
+            if secret.is_empty() {
+                continue;
+            }
+            ciphers.push(new_cipher(secret)?);
+        }
+        let ttl = get_str_conf(value, "ttl");
+        let ttl = if ttl.is_empty() {
+            Duration::from_secs(24 * 3600)
+        } else {
+            parse_duration(&ttl).map_err(|e| Error::Invalid {
+                category: PluginCategory::Session.to_string(),
+                message: e.to_string(),
+            })?
+        };
+        let mut name = get_str_conf(value, "name");
+        if name.is_empty() {
+            name = "pingap_session".to_string();
+        }
+        let header = get_str_conf(value, "header");
+        let header = if header.is_empty() {
+            None
+        } else {
+            Some(HeaderName::from_str(&header).map_err(|e| Error::Invalid {
+                category: PluginCategory::Session.to_string(),
+                message: e.to_string(),
+            })?)
+        };
+
+        let params = Self {
+            hash_value,
+            plugin_step: step,
+            name,
+            ciphers,
+            ttl,
+            secure: get_bool_conf(value, "secure"),
+            login_path: get_str_conf(value, "login_path"),
+            logout_path: get_str_conf(value, "logout_path"),
+            header,
+        };
+        if params.ciphers.is_empty() {
+            return Err(Error::Invalid {
+                category: PluginCategory::Session.to_string(),
+                message: "Secrets are not allowed empty".to_string(),
+            });
+        }
+        if params.plugin_step != PluginStep::Request {
+            return Err(Error::Invalid {
+                category: PluginCategory::Session.to_string(),
+                message: "Session plugin should be executed at request step"
+                    .to_string(),
+            });
+        }
+        Ok(params)
+    }
+}
+
+impl SessionCookie {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new session cookie plugin");
+        Self::try_from(params)
+    }
+    /// Encrypt the session data, a random nonce is generated
+    /// and prepended to the cipher text.
+    fn encrypt(&self, data: &SessionData) -> pingora::Result<String> {
+        let buf = serde_json::to_vec(data)
+            .map_err(|e| util::new_internal_error(500, e.to_string()))?;
+        let nonce: [u8; NONCE_SIZE] = rand::random();
+        let ciphertext = self.ciphers[0]
+            .encrypt(Nonce::from_slice(&nonce), buf.as_ref())
+            .map_err(|e| util::new_internal_error(500, e.to_string()))?;
+        let mut value = nonce.to_vec();
+        value.extend(ciphertext);
+        Ok(util::base64_encode(value))
+    }
+    /// Decrypt the session data with all secrets,
+    /// returns none if the value is invalid or expired.
+    fn decrypt(&self, value: &str) -> Option<SessionData> {
+        let buf = util::base64_decode(value).ok()?;
+        if buf.len() <= NONCE_SIZE {
+            return None;
+        }
+        let (nonce, ciphertext) = buf.split_at(NONCE_SIZE);
+        let plaintext = self.ciphers.iter().find_map(|cipher| {
+            cipher.decrypt(Nonce::from_slice(nonce), ciphertext).ok()
+        })?;
+        let data: SessionData = serde_json::from_slice(&plaintext).ok()?;
+        if data.exp < util::now().as_secs() {
+            return None;
+        }
+        Some(data)
+    }
+    fn new_set_cookie(&self, value: &str, max_age: i64) -> HeaderValue {
+        let c = Cookie::build((&self.name, value))
+            .path("/")
+            .http_only(true)
+            .secure(self.secure)
+            .same_site(SameSite::Lax)
+            .max_age(cookie::time::Duration::seconds(max_age))
+            .build();
+        // cookie value is base64 string, it should be valid
+        HeaderValue::from_str(&c.to_string())
+            .unwrap_or_else(|_| HeaderValue::from_static(""))
+    }
+}
+
+#[async_trait]
+impl Plugin for SessionCookie {
+    #[inline]
+    fn hash_key(&self) -> String {
+        self.hash_value.clone()
+    }
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut State,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        if step != self.plugin_step {
+            return Ok(None);
+        }
+        // the session header can only be set by pingap
+        if let Some(header) = &self.header {
+            session.req_header_mut().remove_header(header);
+        }
+        if !self.logout_path.is_empty()
+            && session.req_header().uri.path() == self.logout_path
+        {
+            return Ok(Some(HttpResponse {
+                status: StatusCode::NO_CONTENT,
+                headers: Some(vec![
+                    HTTP_HEADER_NO_STORE.clone(),
+                    (header::SET_COOKIE, self.new_set_cookie("", 0)),
+                ]),
+                ..Default::default()
+            }));
+        }
+        let Some(data) =
+            util::get_cookie_value(session.req_header(), &self.name)
+                .and_then(|value| self.decrypt(value))
+        else {
+            return Ok(None);
+        };
+        for (key, value) in data.data.iter() {
+            ctx.add_variable(&format!("session_{key}"), value);
+        }
+        if let Some(header) = &self.header {
+            if let Ok(value) = serde_json::to_string(&data.data) {
+                let _ = session
+                    .req_header_mut()
+                    .insert_header(header.clone(), value);
+            }
+        }
+        ctx.session = Some(data.data);
+        Ok(None)
+    }
+    #[inline]
+    async fn handle_response(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut State,
+        upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<()> {
+        if step != PluginStep::Response {
+            return Ok(());
+        }
+        // the upstream of login path returns the session data by header
+        if let Some(header) = &self.header {
+            if !self.login_path.is_empty()
+                && session.req_header().uri.path() == self.login_path
+            {
+                if let Some(data) =
+                    upstream_response.headers.get(header).and_then(|value| {
+                        serde_json::from_slice::<HashMap<String, String>>(
+                            value.as_bytes(),
+                        )
+                        .ok()
+                    })
+                {
+                    ctx.session = Some(data);
+                    ctx.session_updated = true;
+                }
+            }
+            upstream_response.remove_header(header);
+        }
+        if !ctx.session_updated {
+            return Ok(());
+        }
+        let value = self.encrypt(&SessionData {
+            exp: util::now().as_secs() + self.ttl.as_secs(),
+            data: ctx.session.clone().unwrap_or_default(),
+        })?;
+        upstream_response.append_header(
+            header::SET_COOKIE,
+            self.new_set_cookie(&value, self.ttl.as_secs() as i64),
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SessionCookie, SessionData};
+    use crate::config::{PluginConf, PluginStep};
+    use crate::plugin::Plugin;
+    use crate::state::State;
+    use crate::util;
+    use pingora::http::ResponseHeader;
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use tokio_test::io::Builder;
+
+    #[test]
+    fn test_session_cookie_params() {
+        let params = SessionCookie::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+secrets = ["abc", "def"]
+ttl = "1h"
+login_path = "/login"
+logout_path = "/logout"
+header = "X-Session"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!("pingap_session", params.name);
+        assert_eq!(2, params.ciphers.len());
+        assert_eq!(3600, params.ttl.as_secs());
+        assert_eq!("x-session", params.header.unwrap().to_string());
+
+        let result = SessionCookie::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+secrets = []
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin session invalid, message: Secrets are not allowed empty",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_session_cookie_rotation() {
+        let old = SessionCookie::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+secrets = ["old"]
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let current = SessionCookie::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+secrets = ["new", "old"]
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let mut data = HashMap::new();
+        data.insert("user".to_string(), "tree".to_string());
+        let value = old
+            .encrypt(&SessionData {
+                exp: util::now().as_secs() + 60,
+                data: data.clone(),
+            })
+            .unwrap();
+        // encrypted by old secret
+        assert_eq!(data, current.decrypt(&value).unwrap().data);
+
+        let value = current
+            .encrypt(&SessionData {
+                exp: util::now().as_secs() + 60,
+                data: data.clone(),
+            })
+            .unwrap();
+        assert_eq!(true, old.decrypt(&value).is_none());
+
+        // expired
+        let value = current
+            .encrypt(&SessionData {
+                exp: util::now().as_secs() - 1,
+                data,
+            })
+            .unwrap();
+        assert_eq!(true, current.decrypt(&value).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_session_cookie() {
+        let plugin = SessionCookie::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+secrets = ["abc"]
+login_path = "/login"
+logout_path = "/logout"
+header = "X-Session"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        // login
+        let input_header = "POST /login HTTP/1.1\r\nX-Session: {}\r\n\r\n";
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let mut state = State::default();
+        let result = plugin
+            .handle_request(PluginStep::Request, &mut session, &mut state)
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_none());
+        assert_eq!(true, session.get_header("X-Session").is_none());
+
+        let mut upstream_response = ResponseHeader::build(200, None).unwrap();
+        upstream_response
+            .insert_header("X-Session", r#"{"user":"tree"}"#)
+            .unwrap();
+        plugin
+            .handle_response(
+                PluginStep::Response,
+                &mut session,
+                &mut state,
+                &mut upstream_response,
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, upstream_response.headers.get("X-Session").is_none());
+        let set_cookie = upstream_response
+            .headers
+            .get("Set-Cookie")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert_eq!(true, set_cookie.starts_with("pingap_session="));
+        let cookie = set_cookie.split(';').next().unwrap();
+
+        // request with session cookie
+        let input_header =
+            format!("GET /users/me HTTP/1.1\r\nCookie: {cookie}\r\n\r\n");
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let mut state = State::default();
+        let result = plugin
+            .handle_request(PluginStep::Request, &mut session, &mut state)
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_none());
+        assert_eq!(
+            "tree",
+            state.session.unwrap().get("user").unwrap().as_str()
+        );
+        assert_eq!(
+            r#"{"user":"tree"}"#,
+            session.get_header("X-Session").unwrap().to_str().unwrap()
+        );
+
+        // logout
+        let input_header = "GET /logout HTTP/1.1\r\n\r\n";
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let mut state = State::default();
+        let resp = plugin
+            .handle_request(PluginStep::Request, &mut session, &mut state)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(204, resp.status.as_u16());
+        assert_eq!(
+            true,
+            resp.headers.unwrap()[1]
+                .1
+                .to_str()
+                .unwrap()
+                .contains("Max-Age=0")
+        );
+    }
+}
@@ -13,8 +13,13 @@
 // limitations under the License.
 
 use super::{get_hash_key, get_step_conf, get_str_conf, Error, Plugin, Result};
-use crate::config::{PluginCategory, PluginConf, PluginStep};
+use crate::config::{
+    get_current_config, PluginCategory, PluginConf, PluginStep,
+};
 use crate::http_extra::HttpResponse;
+use crate::proxy::{
+    get_locations_stats, get_upstreams_stats, LocationStats, UpstreamStats,
+};
 use crate::state::{
     get_hostname, get_process_system_info, get_processing_accepted,
     get_start_time, State,
@@ -24,6 +29,7 @@ use async_trait::async_trait;
 use bytes::Bytes;
 use pingora::proxy::Session;
 use serde::Serialize;
+use std::collections::HashMap;
 use std::time::Duration;
 use tracing::debug;
 
@@ -49,6 +55,15 @@ struct ServerStats {
     fd_count: usize,
     tcp_count: usize,
     tcp6_count: usize,
+    // the features enabled at compile time
+    features: Vec<&'static str>,
+    // the subsystems enabled by config
+    subsystems: Vec<String>,
+    // the stats of locations and upstreams, only for `detail=true`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    locations: Option<HashMap<String, LocationStats>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upstreams: Option<HashMap<String, UpstreamStats>>,
 }
 pub struct Stats {
     path: String,
@@ -103,6 +118,12 @@ impl Plugin for Stats {
             return Ok(None);
         }
         if session.req_header().uri.path() == self.path {
+            let detail = util::convert_query_map(
+                session.req_header().uri.query().unwrap_or_default(),
+            )
+            .get("detail")
+            .map(|value| value == "true")
+            .unwrap_or_default();
             let uptime: humantime::Duration =
                 Duration::from_secs(util::now().as_secs() - get_start_time())
                     .into();
@@ -129,6 +150,10 @@ impl Plugin for Stats {
                 fd_count: info.fd_count,
                 tcp_count: info.tcp_count,
                 tcp6_count: info.tcp6_count,
+                features: util::get_features(),
+                subsystems: get_current_config().get_enabled_subsystems(),
+                locations: detail.then(get_locations_stats),
+                upstreams: detail.then(get_upstreams_stats),
             })
             .unwrap_or_else(|e| {
                 HttpResponse::unknown_error(Bytes::from(e.to_string()))
@@ -222,5 +247,30 @@ mod tests {
             .await
             .unwrap();
         assert_eq!(true, result.is_some());
+        let body = result.unwrap().body;
+        assert_eq!(
+            false,
+            std::str::from_utf8(&body)
+                .unwrap()
+                .contains("\"locations\"")
+        );
+
+        let input_header = "GET /stats?detail=true HTTP/1.1\r\n\r\n";
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+
+        let result = stats
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap();
+        let body = result.unwrap().body;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert_eq!(true, body.contains("\"locations\""));
+        assert_eq!(true, body.contains("\"upstreams\""));
     }
 }
@@ -0,0 +1,326 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    get_hash_key, get_int_conf, get_step_conf, get_str_conf,
+    get_str_slice_conf, Error, Plugin, Result,
+};
+use crate::config::{PluginCategory, PluginConf, PluginStep};
+use crate::http_extra::HttpResponse;
+use crate::state::State;
+use crate::util;
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::{header, StatusCode};
+use humantime::parse_duration;
+use once_cell::sync::Lazy;
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use pingora_limits::rate::Rate;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+/// Respond the flagged clients extremely slowly, one byte is sent
+/// after each delay to waste the time of scanner and bot.
+pub struct Tarpit {
+    plugin_step: PluginStep,
+    ip_rules: util::IpRules,
+    // the client is flagged if its requests exceed max in the interval
+    max: isize,
+    rate: Option<Rate>,
+    delay: Duration,
+    duration: Duration,
+    // the max count of the tarpit responses at the same time
+    max_connections: usize,
+    connections: AtomicUsize,
+    hash_value: String,
+}
+
+static IGNORE_RESPONSE: Lazy<HttpResponse> = Lazy::new(|| HttpResponse {
+    status: StatusCode::from_u16(999).unwrap(),
+    ..Default::default()
+});
+
+fn get_duration_conf(
+    value: &PluginConf,
+    key: &str,
+    default_value: Duration,
+) -> Result<Duration> {
+    let value = get_str_conf(value, key);
+    if value.is_empty() {
+        return Ok(default_value);
+    }
+    parse_duration(&value).map_err(|e| Error::Invalid {
+        category: PluginCategory::Tarpit.to_string(),
+        message: format!("{key}({value}) is invalid, {e}"),
+    })
+}
+
+impl TryFrom<&PluginConf> for Tarpit {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let step = get_step_conf(value);
+
+        let ip_list = get_str_slice_conf(value, "ip_list");
+        let max = get_int_conf(value, "max") as isize;
+        if ip_list.is_empty() && max <= 0 {
+            return Err(Error::Invalid {
+                category: PluginCategory::Tarpit.to_string(),
+                message: "Ip list or max should be set".to_string(),
+            });
+        }
+        let interval =
+            get_duration_conf(value, "interval", Duration::from_secs(10))?;
+        let max_connections = get_int_conf(value, "max_connections");
+
+        let params = Self {
+            hash_value,
+            plugin_step: step,
+            ip_rules: util::IpRules::new(&ip_list),
+            max,
+            rate: if max > 0 {
+                Some(Rate::new(interval))
+            } else {
+                None
+            },
+            delay: get_duration_conf(value, "delay", Duration::from_secs(10))?
+                .max(Duration::from_millis(100)),
+            duration: get_duration_conf(
+                value,
+                "duration",
+                Duration::from_secs(5 * 60),
+            )?,
+            max_connections: if max_connections <= 0 {
+                100
+            } else {
+                max_connections as usize
+            },
+            connections: AtomicUsize::new(0),
+        };
+        if ![PluginStep::Request, PluginStep::ProxyUpstream]
+            .contains(&params.plugin_step)
+        {
+            return Err(Error::Invalid {
+                category: PluginCategory::Tarpit.to_string(),
+                message: "Tarpit plugin should be executed at request or proxy upstream step".to_string(),
+            });
+        }
+        Ok(params)
+    }
+}
+
+struct ConnectionGuard<'a>(&'a AtomicUsize);
+
+impl Drop for ConnectionGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Tarpit {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new tarpit plugin");
+        Self::try_from(params)
+    }
+    /// Check the client is flagged by ip list or request rate.
+    fn is_flagged(&self, ip: &String) -> bool {
+        if self.ip_rules.matched(ip).unwrap_or_default() {
+            return true;
+        }
+        if let Some(rate) = &self.rate {
+            return rate.observe(ip, 1) > self.max;
+        }
+        false
+    }
+    /// Drip feed the response until the duration is reached
+    /// or the client closes the connection.
+    async fn drip_feed(&self, session: &mut Session) -> pingora::Result<()> {
+        let mut resp = ResponseHeader::build(StatusCode::OK, None)?;
+        resp.insert_header(header::CONTENT_TYPE, "text/html; charset=utf-8")?;
+        resp.insert_header(header::CACHE_CONTROL, "private, no-store")?;
+        session.write_response_header(Box::new(resp), false).await?;
+        let started_at = Instant::now();
+        while started_at.elapsed() < self.duration {
+            tokio::time::sleep(self.delay).await;
+            session
+                .write_response_body(Some(Bytes::from_static(b" ")), false)
+                .await?;
+        }
+        session.write_response_body(None, true).await?;
+        session.finish_body().await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Plugin for Tarpit {
+    #[inline]
+    fn hash_key(&self) -> String {
+        self.hash_value.clone()
+    }
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut State,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        if step != self.plugin_step {
+            return Ok(None);
+        }
+        let ip = if let Some(ip) = &ctx.client_ip {
+            ip.to_string()
+        } else {
+            let ip = util::get_client_ip(session);
+            ctx.client_ip = Some(ip.clone());
+            ip
+        };
+        if !self.is_flagged(&ip) {
+            return Ok(None);
+        }
+        // reject directly to keep the resources if too many tarpit responses
+        if self.connections.fetch_add(1, Ordering::Relaxed)
+            >= self.max_connections
+        {
+            self.connections.fetch_sub(1, Ordering::Relaxed);
+            return Ok(Some(HttpResponse {
+                status: StatusCode::TOO_MANY_REQUESTS,
+                ..Default::default()
+            }));
+        }
+        let _guard = ConnectionGuard(&self.connections);
+        info!(ip, "client is tarpitted");
+        ctx.status = Some(StatusCode::OK);
+        // the client may close the connection, it's expected
+        if let Err(e) = self.drip_feed(session).await {
+            debug!(ip, error = e.to_string(), "tarpit is closed");
+        }
+        Ok(Some(IGNORE_RESPONSE.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Tarpit;
+    use crate::config::{PluginConf, PluginStep};
+    use crate::plugin::Plugin;
+    use crate::state::State;
+    use http::StatusCode;
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+    use tokio_test::io::Builder;
+
+    #[test]
+    fn test_tarpit_params() {
+        let result = Tarpit::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+delay = "1s"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin tarpit invalid, message: Ip list or max should be set",
+            result.err().unwrap().to_string()
+        );
+
+        let result = Tarpit::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+ip_list = ["1.1.1.1"]
+delay = "1x"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin tarpit invalid, message: delay(1x) is invalid, unknown time unit \"x\", supported units: ns, us, ms, sec, min, hours, days, weeks, months, years (and few variations)",
+            result.err().unwrap().to_string()
+        );
+
+        let params = Tarpit::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+ip_list = ["1.1.1.0/24"]
+max = 2
+delay = "30s"
+duration = "10m"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(Duration::from_secs(30), params.delay);
+        assert_eq!(Duration::from_secs(600), params.duration);
+        assert_eq!(100, params.max_connections);
+
+        assert_eq!(true, params.is_flagged(&"1.1.1.2".to_string()));
+        let ip = "2.1.1.1".to_string();
+        assert_eq!(false, params.is_flagged(&ip));
+        assert_eq!(false, params.is_flagged(&ip));
+        assert_eq!(true, params.is_flagged(&ip));
+    }
+
+    #[tokio::test]
+    async fn test_tarpit() {
+        let params = Tarpit::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+ip_list = ["1.1.1.1"]
+max_connections = 1
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        let headers = ["X-Forwarded-For: 2.1.1.1"].join("\r\n");
+        let input_header = format!("GET / HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let result = params
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_none());
+
+        // too many tarpit responses
+        params.connections.store(1, Ordering::Relaxed);
+        let headers = ["X-Forwarded-For: 1.1.1.1"].join("\r\n");
+        let input_header = format!("GET / HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let result = params
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, result.unwrap().status);
+        assert_eq!(1, params.connections.load(Ordering::Relaxed));
+    }
+}
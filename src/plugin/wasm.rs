@@ -0,0 +1,788 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{get_hash_key, get_int_conf, get_step_conf, get_str_conf};
+use super::{Error, Plugin, Result};
+use crate::config::{PluginCategory, PluginConf, PluginStep};
+use crate::http_extra::{convert_headers, HttpResponse};
+use crate::state::State;
+#[cfg(feature = "metrics")]
+use crate::state::{WASM_CALLS, WASM_CALL_TIME};
+use crate::util;
+use ahash::AHashMap;
+use async_trait::async_trait;
+use bytes::Bytes;
+use bytesize::ByteSize;
+use http::{header, HeaderMap, StatusCode};
+use humantime::parse_duration;
+use once_cell::sync::Lazy;
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::{debug, error, info};
+use wasmtime::{
+    AsContext, Caller, Config, Engine, Instance, Linker, Memory, Module, Store,
+    StoreLimits, StoreLimitsBuilder,
+};
+
+const ON_REQUEST: &str = "on_request";
+const ON_RESPONSE: &str = "on_response";
+const ON_RESPONSE_BODY: &str = "on_response_body";
+
+// the default fuel of each call, it limits the instructions of guest
+const DEFAULT_FUEL: u64 = 10_000_000;
+// the max size of output read from guest memory
+const MAX_OUTPUT_SIZE: usize = 8 * 1024 * 1024;
+// the message of log is truncated if it's too long
+const MAX_LOG_SIZE: usize = 4 * 1024;
+// the default max memory of each instance
+const DEFAULT_MAX_MEMORY: usize = 32 * 1024 * 1024;
+// the default wall-clock timeout of each call
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(100);
+// the epoch is increased by the interval, it's the precision of timeout
+const EPOCH_INTERVAL: Duration = Duration::from_millis(10);
+
+// the engine is shared by all modules, so only one thread is needed
+// to increase the epoch
+static ENGINE: Lazy<std::result::Result<Engine, String>> = Lazy::new(|| {
+    let mut config = Config::new();
+    config.consume_fuel(true).epoch_interruption(true);
+    let engine = Engine::new(&config).map_err(|e| e.to_string())?;
+    let ticker = engine.clone();
+    std::thread::Builder::new()
+        .name("pingap-wasm-epoch".to_string())
+        .spawn(move || loop {
+            std::thread::sleep(EPOCH_INTERVAL);
+            ticker.increment_epoch();
+        })
+        .map_err(|e| e.to_string())?;
+    Ok(engine)
+});
+
+/// The input of `on_request` and `on_response`.
+#[derive(Serialize, Debug, Default)]
+struct WasmInput {
+    #[serde(skip_serializing_if = "String::is_empty")]
+    method: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    uri: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<u16>,
+    headers: BTreeMap<String, String>,
+    variables: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct WasmResponse {
+    status: Option<u16>,
+    #[serde(default)]
+    headers: Vec<String>,
+    #[serde(default)]
+    body: String,
+}
+
+/// The output of `on_request` and `on_response`, the response is only
+/// supported by `on_request`.
+#[derive(Deserialize, Debug, Default)]
+struct WasmOutput {
+    #[serde(default)]
+    set_headers: BTreeMap<String, String>,
+    #[serde(default)]
+    remove_headers: Vec<String>,
+    #[serde(default)]
+    variables: BTreeMap<String, String>,
+    response: Option<WasmResponse>,
+}
+
+/// The instance of module for one request, it's kept in the state of
+/// request and reused by all hooks of the request.
+pub struct WasmInstance {
+    store: Store<StoreLimits>,
+    instance: Instance,
+}
+
+#[derive(Clone)]
+struct WasmRuntime {
+    // the file name of module, it's the label of metrics
+    name: String,
+    engine: Engine,
+    module: Module,
+    linker: Linker<StoreLimits>,
+    fuel: u64,
+    max_memory: usize,
+    // the ticks of epoch before the call is interrupted
+    epoch_deadline: u64,
+}
+
+/// The plugin runs the WebAssembly module of a simple ABI, the data between
+/// host and guest is exchanged by the linear memory of guest:
+/// - `alloc(len: i32) -> i32`: allocates the memory for the input.
+/// - `on_request(ptr: i32, len: i32) -> i64`: the input and output are json.
+/// - `on_response(ptr: i32, len: i32) -> i64`: the input and output are json.
+/// - `on_response_body(ptr: i32, len: i32) -> i64`: the input is the chunk
+///   of body, and the output replaces it.
+///
+/// The result is `ptr << 32 | len` of the output, zero means nothing to do.
+/// The host function `pingap.log(ptr: i32, len: i32)` writes the log.
+///
+/// Each call is limited by the fuel(instructions), the max memory of
+/// instance and the wall-clock timeout, the guest is trapped if any of
+/// them is exceeded. `on_request` and `on_response` run on the blocking
+/// thread pool, `on_response_body` runs in place.
+pub struct Wasm {
+    plugin_step: PluginStep,
+    runtime: WasmRuntime,
+    // the exports of module
+    exports: Vec<String>,
+    hash_value: String,
+}
+
+fn new_wasm_error(message: String) -> Error {
+    Error::Invalid {
+        category: PluginCategory::Wasm.to_string(),
+        message,
+    }
+}
+
+/// Read the data of guest memory, the range is checked before allocating
+/// the buffer because the pointer and length are controlled by guest.
+fn read_memory(
+    memory: &Memory,
+    store: impl AsContext,
+    ptr: usize,
+    len: usize,
+) -> wasmtime::Result<Vec<u8>> {
+    if len > MAX_OUTPUT_SIZE {
+        return Err(wasmtime::Error::msg(format!(
+            "output of wasm is too large, size: {len}"
+        )));
+    }
+    let data = memory.data(&store);
+    let Some(buf) = ptr.checked_add(len).and_then(|end| data.get(ptr..end))
+    else {
+        return Err(wasmtime::Error::msg(format!(
+            "output of wasm is out of memory bounds, ptr: {ptr}, len: {len}"
+        )));
+    };
+    Ok(buf.to_vec())
+}
+
+#[cfg(feature = "metrics")]
+fn get_result_label<T>(result: &wasmtime::Result<T>) -> &'static str {
+    let Err(e) = result else {
+        return "success";
+    };
+    match e.downcast_ref::<wasmtime::Trap>() {
+        Some(wasmtime::Trap::OutOfFuel) => "out_of_fuel",
+        Some(wasmtime::Trap::Interrupt) => "timeout",
+        _ => "error",
+    }
+}
+
+fn get_headers(headers: &HeaderMap) -> BTreeMap<String, String> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            (
+                name.to_string(),
+                value.to_str().unwrap_or_default().to_string(),
+            )
+        })
+        .collect()
+}
+
+fn get_variables(ctx: &State) -> BTreeMap<String, String> {
+    ctx.variables
+        .as_ref()
+        .map(|variables| {
+            variables
+                .iter()
+                .map(|(key, value)| {
+                    (key.trim_start_matches('$').to_string(), value.clone())
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+impl TryFrom<&PluginConf> for Wasm {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let step = get_step_conf(value);
+        let engine = ENGINE.as_ref().map_err(|e| new_wasm_error(e.clone()))?;
+        let path = util::resolve_path(&get_str_conf(value, "path"));
+        if path.is_empty() {
+            return Err(new_wasm_error(
+                "path of wasm module is empty".to_string(),
+            ));
+        }
+        let module = Module::from_file(engine, &path)
+            .map_err(|e| new_wasm_error(e.to_string()))?;
+        let exports: Vec<String> = module
+            .exports()
+            .map(|item| item.name().to_string())
+            .collect();
+        for name in ["memory", "alloc"] {
+            if !exports.iter().any(|item| item == name) {
+                return Err(new_wasm_error(format!(
+                    "{name} is not exported by wasm module"
+                )));
+            }
+        }
+
+        let mut linker = Linker::new(engine);
+        linker
+            .func_wrap(
+                "pingap",
+                "log",
+                |mut caller: Caller<'_, StoreLimits>, ptr: i32, len: i32| {
+                    let Some(memory) = caller
+                        .get_export("memory")
+                        .and_then(|item| item.into_memory())
+                    else {
+                        return;
+                    };
+                    let len = (len as u32 as usize).min(MAX_LOG_SIZE);
+                    if let Ok(buf) =
+                        read_memory(&memory, &caller, ptr as u32 as usize, len)
+                    {
+                        info!(
+                            message = String::from_utf8_lossy(&buf).to_string(),
+                            "wasm log"
+                        );
+                    }
+                },
+            )
+            .map_err(|e| new_wasm_error(e.to_string()))?;
+
+        let fuel = get_int_conf(value, "fuel");
+        let max_memory = get_str_conf(value, "max_memory");
+        let max_memory = if max_memory.is_empty() {
+            DEFAULT_MAX_MEMORY
+        } else {
+            ByteSize::from_str(&max_memory)
+                .map_err(new_wasm_error)?
+                .as_u64() as usize
+        };
+        let timeout = get_str_conf(value, "timeout");
+        let timeout = if timeout.is_empty() {
+            DEFAULT_TIMEOUT
+        } else {
+            parse_duration(&timeout)
+                .map_err(|e| new_wasm_error(e.to_string()))?
+        };
+        let name = Path::new(&path)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let params = Self {
+            hash_value,
+            plugin_step: step,
+            runtime: WasmRuntime {
+                name,
+                engine: engine.clone(),
+                module,
+                linker,
+                fuel: if fuel > 0 { fuel as u64 } else { DEFAULT_FUEL },
+                max_memory,
+                epoch_deadline: (timeout.as_millis()
+                    / EPOCH_INTERVAL.as_millis())
+                .max(1) as u64,
+            },
+            exports,
+        };
+        if ![PluginStep::Request, PluginStep::ProxyUpstream]
+            .contains(&params.plugin_step)
+        {
+            return Err(new_wasm_error(
+                "Wasm plugin should be executed at request or proxy upstream step"
+                    .to_string(),
+            ));
+        }
+        Ok(params)
+    }
+}
+
+impl WasmRuntime {
+    fn instantiate(&self) -> wasmtime::Result<WasmInstance> {
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(self.max_memory)
+            .instances(1)
+            .trap_on_grow_failure(true)
+            .build();
+        let mut store = Store::new(&self.engine, limits);
+        store.limiter(|limits| limits);
+        // the start function of module is also limited
+        store.set_fuel(self.fuel)?;
+        store.set_epoch_deadline(self.epoch_deadline);
+        let instance = self.linker.instantiate(&mut store, &self.module)?;
+        Ok(WasmInstance { store, instance })
+    }
+    /// Call the function of module with the instance of request, a new
+    /// instance is created if it's none. The instance is only returned
+    /// if the call succeeds, because the state of guest may be broken.
+    fn call(
+        &self,
+        instance: Option<WasmInstance>,
+        name: &str,
+        input: &[u8],
+    ) -> Result<(WasmInstance, Option<Vec<u8>>)> {
+        #[cfg(feature = "metrics")]
+        let started_at = std::time::Instant::now();
+        let result = self.do_call(instance, name, input);
+        #[cfg(feature = "metrics")]
+        {
+            WASM_CALL_TIME
+                .with_label_values(&[&self.name, name])
+                .observe(started_at.elapsed().as_secs_f64());
+            WASM_CALLS
+                .with_label_values(&[
+                    &self.name,
+                    name,
+                    get_result_label(&result),
+                ])
+                .inc();
+        }
+        result.map_err(|e| new_wasm_error(e.to_string()))
+    }
+    fn do_call(
+        &self,
+        instance: Option<WasmInstance>,
+        name: &str,
+        input: &[u8],
+    ) -> wasmtime::Result<(WasmInstance, Option<Vec<u8>>)> {
+        let mut current = if let Some(instance) = instance {
+            instance
+        } else {
+            self.instantiate()?
+        };
+        let WasmInstance { store, instance } = &mut current;
+        store.set_fuel(self.fuel)?;
+        store.set_epoch_deadline(self.epoch_deadline);
+        let Some(memory) = instance.get_memory(&mut *store, "memory") else {
+            return Err(wasmtime::Error::msg("memory is not exported"));
+        };
+        let alloc =
+            instance.get_typed_func::<i32, i32>(&mut *store, "alloc")?;
+        let func =
+            instance.get_typed_func::<(i32, i32), i64>(&mut *store, name)?;
+
+        let len = input.len() as i32;
+        let ptr = alloc.call(&mut *store, len)?;
+        memory.write(&mut *store, ptr as u32 as usize, input)?;
+        let result = func.call(&mut *store, (ptr, len))?;
+        if result == 0 {
+            return Ok((current, None));
+        }
+        let output = read_memory(
+            &memory,
+            &*store,
+            (result as u64 >> 32) as usize,
+            (result as u64 & 0xffff_ffff) as usize,
+        )?;
+        Ok((current, Some(output)))
+    }
+}
+
+impl Wasm {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new wasm plugin");
+        Self::try_from(params)
+    }
+    fn has_export(&self, name: &str) -> bool {
+        self.exports.iter().any(|item| item == name)
+    }
+    fn take_instance(&self, ctx: &mut State) -> Option<WasmInstance> {
+        ctx.wasm_instances
+            .as_mut()
+            .and_then(|instances| instances.remove(&self.hash_value))
+    }
+    fn put_instance(&self, ctx: &mut State, instance: WasmInstance) {
+        ctx.wasm_instances
+            .get_or_insert_with(AHashMap::new)
+            .insert(self.hash_value.clone(), instance);
+    }
+    fn call(
+        &self,
+        ctx: &mut State,
+        name: &str,
+        input: &[u8],
+    ) -> Result<Option<Vec<u8>>> {
+        let instance = self.take_instance(ctx);
+        let (instance, output) = self.runtime.call(instance, name, input)?;
+        self.put_instance(ctx, instance);
+        Ok(output)
+    }
+    /// Call the function of module on the blocking thread pool,
+    /// so the guest doesn't block the async worker.
+    async fn call_blocking(
+        &self,
+        ctx: &mut State,
+        name: &'static str,
+        input: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>> {
+        let runtime = self.runtime.clone();
+        let instance = self.take_instance(ctx);
+        let (instance, output) = tokio::task::spawn_blocking(move || {
+            runtime.call(instance, name, &input)
+        })
+        .await
+        .map_err(|e| new_wasm_error(e.to_string()))??;
+        self.put_instance(ctx, instance);
+        Ok(output)
+    }
+    async fn call_json(
+        &self,
+        ctx: &mut State,
+        name: &'static str,
+        input: &WasmInput,
+    ) -> Result<Option<WasmOutput>> {
+        let input = serde_json::to_vec(input)
+            .map_err(|e| new_wasm_error(e.to_string()))?;
+        let Some(output) = self.call_blocking(ctx, name, input).await? else {
+            return Ok(None);
+        };
+        let output = serde_json::from_slice(&output)
+            .map_err(|e| new_wasm_error(e.to_string()))?;
+        Ok(Some(output))
+    }
+}
+
+fn new_internal_error(e: Error) -> pingora::BError {
+    error!(error = e.to_string(), "run wasm fail");
+    util::new_internal_error(500, e.to_string())
+}
+
+#[async_trait]
+impl Plugin for Wasm {
+    #[inline]
+    fn hash_key(&self) -> String {
+        self.hash_value.clone()
+    }
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut State,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        if step != self.plugin_step || !self.has_export(ON_REQUEST) {
+            return Ok(None);
+        }
+        let req_header = session.req_header();
+        let input = WasmInput {
+            method: req_header.method.to_string(),
+            uri: req_header.uri.to_string(),
+            headers: get_headers(&req_header.headers),
+            variables: get_variables(ctx),
+            ..Default::default()
+        };
+        let Some(output) = self
+            .call_json(ctx, ON_REQUEST, &input)
+            .await
+            .map_err(new_internal_error)?
+        else {
+            return Ok(None);
+        };
+        for (key, value) in output.variables.iter() {
+            ctx.add_variable(key, value);
+        }
+        if let Some(resp) = output.response {
+            let headers = convert_headers(&resp.headers)
+                .map_err(|e| util::new_internal_error(500, e.to_string()))?;
+            return Ok(Some(HttpResponse {
+                status: resp
+                    .status
+                    .and_then(|status| StatusCode::from_u16(status).ok())
+                    .unwrap_or(StatusCode::OK),
+                body: Bytes::from(resp.body),
+                headers: (!headers.is_empty()).then_some(headers),
+                ..Default::default()
+            }));
+        }
+        let req_header = session.req_header_mut();
+        for name in output.remove_headers.iter() {
+            req_header.remove_header(name);
+        }
+        for (name, value) in output.set_headers.into_iter() {
+            req_header.insert_header(name, value)?;
+        }
+        Ok(None)
+    }
+    async fn handle_response(
+        &self,
+        _step: PluginStep,
+        _session: &mut Session,
+        ctx: &mut State,
+        upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<()> {
+        if self.has_export(ON_RESPONSE_BODY) {
+            // the body will be replaced by module
+            upstream_response.remove_header(&header::CONTENT_LENGTH);
+            let _ = upstream_response
+                .insert_header(header::TRANSFER_ENCODING, "Chunked");
+        }
+        if !self.has_export(ON_RESPONSE) {
+            return Ok(());
+        }
+        let input = WasmInput {
+            status: Some(upstream_response.status.as_u16()),
+            headers: get_headers(&upstream_response.headers),
+            variables: get_variables(ctx),
+            ..Default::default()
+        };
+        let Some(output) = self
+            .call_json(ctx, ON_RESPONSE, &input)
+            .await
+            .map_err(new_internal_error)?
+        else {
+            return Ok(());
+        };
+        for name in output.remove_headers.iter() {
+            upstream_response.remove_header(name);
+        }
+        for (name, value) in output.set_headers.into_iter() {
+            upstream_response.insert_header(name, value)?;
+        }
+        Ok(())
+    }
+    fn handle_response_body(
+        &self,
+        _session: &mut Session,
+        ctx: &mut State,
+        body: &mut Option<Bytes>,
+        _end_of_stream: bool,
+    ) -> pingora::Result<()> {
+        if !self.has_export(ON_RESPONSE_BODY) {
+            return Ok(());
+        }
+        let Some(data) = body.as_ref() else {
+            return Ok(());
+        };
+        if let Some(output) = self
+            .call(ctx, ON_RESPONSE_BODY, data)
+            .map_err(new_internal_error)?
+        {
+            *body = Some(Bytes::from(output));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Wasm;
+    use crate::state::State;
+    use crate::{config::PluginConf, config::PluginStep, plugin::Plugin};
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use std::io::Write;
+    use tokio_test::io::Builder;
+
+    fn new_wasm_from_wat(wat: &str, mut conf: PluginConf) -> Wasm {
+        let mut file =
+            tempfile::Builder::new().suffix(".wat").tempfile().unwrap();
+        file.write_all(wat.as_bytes()).unwrap();
+        conf.insert(
+            "path".to_string(),
+            toml::Value::String(file.path().to_string_lossy().to_string()),
+        );
+        Wasm::new(&conf).unwrap()
+    }
+
+    fn new_wasm(output: &str) -> Wasm {
+        let wat = format!(
+            r#"(module
+  (import "pingap" "log" (func $log (param i32 i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 1024) "{}")
+  (func (export "alloc") (param i32) (result i32) i32.const 0)
+  (func (export "on_request") (param i32 i32) (result i64)
+    i32.const 1024
+    i32.const {}
+    call $log
+    i64.const 4398046511104
+    i64.const {}
+    i64.or))"#,
+            output.replace('"', "\\\""),
+            output.len(),
+            output.len(),
+        );
+        new_wasm_from_wat(&wat, PluginConf::new())
+    }
+
+    async fn new_session() -> Session {
+        let input_header =
+            "GET /vicanso/pingap HTTP/1.1\r\nHost: pingap.io\r\n\r\n";
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        session
+    }
+
+    #[tokio::test]
+    async fn test_wasm() {
+        let wasm = new_wasm(
+            r#"{"set_headers":{"x-wasm":"pingap"},"variables":{"wasm":"1"}}"#,
+        );
+        let mut session = new_session().await;
+        let mut ctx = State::default();
+        let result = wasm
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_none());
+        assert_eq!(
+            "pingap",
+            session.get_header("x-wasm").unwrap().to_str().unwrap()
+        );
+        assert_eq!("1", ctx.variables.as_ref().unwrap().get("$wasm").unwrap());
+        // the instance is kept for the other hooks of request
+        assert_eq!(1, ctx.wasm_instances.as_ref().unwrap().len());
+
+        let wasm = new_wasm(r#"{"response":{"status":403,"body":"blocked"}}"#);
+        let mut session = new_session().await;
+        let resp = wasm
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(403, resp.status.as_u16());
+        assert_eq!(b"blocked", resp.body.as_ref());
+    }
+
+    #[tokio::test]
+    async fn test_wasm_out_of_bounds() {
+        // the length of output is larger than the memory of guest
+        let wasm = new_wasm_from_wat(
+            r#"(module
+  (memory (export "memory") 1)
+  (func (export "alloc") (param i32) (result i32) i32.const 0)
+  (func (export "on_request") (param i32 i32) (result i64)
+    i64.const 131072))"#,
+            PluginConf::new(),
+        );
+        let mut session = new_session().await;
+        let mut ctx = State::default();
+        let result = wasm
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await;
+        assert_eq!(
+            true,
+            result
+                .err()
+                .unwrap()
+                .to_string()
+                .contains("out of memory bounds")
+        );
+        // the instance is dropped if the call fails
+        assert_eq!(true, ctx.wasm_instances.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_wasm_fuel() {
+        let mut conf = PluginConf::new();
+        conf.insert("fuel".to_string(), toml::Value::Integer(1000));
+        let wasm = new_wasm_from_wat(
+            r#"(module
+  (memory (export "memory") 1)
+  (func (export "alloc") (param i32) (result i32) i32.const 0)
+  (func (export "on_request") (param i32 i32) (result i64)
+    (loop $forever (br $forever))
+    i64.const 0))"#,
+            conf,
+        );
+        let mut session = new_session().await;
+        let result = wasm
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await;
+        assert_eq!(true, result.is_err());
+
+        let result = Wasm::new(&PluginConf::new());
+        assert_eq!(
+            "Plugin wasm invalid, message: path of wasm module is empty",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wasm_limits() {
+        // the fuel is enough, the call is interrupted by timeout
+        let mut conf = PluginConf::new();
+        conf.insert("fuel".to_string(), toml::Value::Integer(i64::MAX));
+        conf.insert(
+            "timeout".to_string(),
+            toml::Value::String("50ms".to_string()),
+        );
+        let wasm = new_wasm_from_wat(
+            r#"(module
+  (memory (export "memory") 1)
+  (func (export "alloc") (param i32) (result i32) i32.const 0)
+  (func (export "on_request") (param i32 i32) (result i64)
+    (loop $forever (br $forever))
+    i64.const 0))"#,
+            conf,
+        );
+        let mut session = new_session().await;
+        let started_at = std::time::Instant::now();
+        let result = wasm
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await;
+        assert_eq!(true, result.is_err());
+        assert_eq!(true, started_at.elapsed().as_secs() < 5);
+
+        // grow 100 pages(6.4MB) of memory
+        let mut conf = PluginConf::new();
+        conf.insert(
+            "max_memory".to_string(),
+            toml::Value::String("1MB".to_string()),
+        );
+        let wasm = new_wasm_from_wat(
+            r#"(module
+  (memory (export "memory") 1)
+  (func (export "alloc") (param i32) (result i32) i32.const 0)
+  (func (export "on_request") (param i32 i32) (result i64)
+    i32.const 100
+    memory.grow
+    drop
+    i64.const 0))"#,
+            conf,
+        );
+        let mut session = new_session().await;
+        let result = wasm
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await;
+        assert_eq!(true, result.is_err());
+    }
+}
@@ -0,0 +1,486 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    get_hash_key, get_step_conf, get_str_conf, get_str_slice_conf, Error,
+    Plugin, Result,
+};
+use crate::config::{PluginCategory, PluginConf, PluginStep};
+use crate::http_extra::HttpResponse;
+use crate::state::{State, WebSocketGuard};
+use async_trait::async_trait;
+use bytes::{BufMut, Bytes, BytesMut};
+use bytesize::ByteSize;
+use http::{header, StatusCode};
+use humantime::parse_duration;
+use pingora::proxy::Session;
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+// the status codes of websocket close frame
+const CLOSE_GOING_AWAY: u16 = 1001;
+const CLOSE_MESSAGE_TOO_BIG: u16 = 1009;
+
+/// Enforce the policy of websocket connection, the connection is closed
+/// with close frame if the message is too big or the lifetime is reached.
+pub struct WebsocketPolicy {
+    plugin_step: PluginStep,
+    max_message_size: Option<u64>,
+    max_lifetime: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    // the origin supports wildcard, e.g. *.pingap.io
+    allowed_origins: Vec<String>,
+    hash_value: String,
+}
+
+fn get_duration_conf(
+    value: &PluginConf,
+    key: &str,
+) -> Result<Option<Duration>> {
+    let value = get_str_conf(value, key);
+    if value.is_empty() {
+        return Ok(None);
+    }
+    let d = parse_duration(&value).map_err(|e| Error::Invalid {
+        category: PluginCategory::WebsocketPolicy.to_string(),
+        message: format!("{key}({value}) is invalid, {e}"),
+    })?;
+    Ok(Some(d))
+}
+
+impl TryFrom<&PluginConf> for WebsocketPolicy {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let step = get_step_conf(value);
+
+        let max_message_size = get_str_conf(value, "max_message_size");
+        let max_message_size = if max_message_size.is_empty() {
+            None
+        } else {
+            let size = max_message_size.parse::<ByteSize>().map_err(|e| {
+                Error::Invalid {
+                    category: PluginCategory::WebsocketPolicy.to_string(),
+                    message: format!("max message size is invalid, {e}"),
+                }
+            })?;
+            Some(size.as_u64())
+        };
+
+        let params = Self {
+            hash_value,
+            plugin_step: step,
+            max_message_size,
+            max_lifetime: get_duration_conf(value, "max_lifetime")?,
+            idle_timeout: get_duration_conf(value, "idle_timeout")?,
+            allowed_origins: get_str_slice_conf(value, "allowed_origins"),
+        };
+        if params.plugin_step != PluginStep::Request {
+            return Err(Error::Invalid {
+                category: PluginCategory::WebsocketPolicy.to_string(),
+                message:
+                    "Websocket policy plugin should be executed at request step"
+                        .to_string(),
+            });
+        }
+        Ok(params)
+    }
+}
+
+/// Check the origin is allowed, the pattern supports `*` prefix
+/// for matching all sub domains.
+fn is_origin_allowed(patterns: &[String], origin: &str) -> bool {
+    let host = origin
+        .split_once("://")
+        .map(|(_, host)| host)
+        .unwrap_or(origin);
+    patterns.iter().any(|pattern| {
+        if pattern == "*" || pattern == origin {
+            return true;
+        }
+        if let Some(suffix) = pattern.strip_prefix('*') {
+            return host.ends_with(suffix);
+        }
+        pattern == host
+    })
+}
+
+/// Build the close frame with status code, the frame sent to
+/// upstream should be masked.
+fn new_close_frame(code: u16, masked: bool) -> Bytes {
+    let payload = code.to_be_bytes();
+    let mut buf = BytesMut::with_capacity(8);
+    // fin + close opcode
+    buf.put_u8(0x88);
+    if masked {
+        let mask: [u8; 4] = rand::random();
+        buf.put_u8(0x80 | payload.len() as u8);
+        buf.put_slice(&mask);
+        for (index, value) in payload.iter().enumerate() {
+            buf.put_u8(value ^ mask[index % 4]);
+        }
+    } else {
+        buf.put_u8(payload.len() as u8);
+        buf.put_slice(&payload);
+    }
+    buf.freeze()
+}
+
+/// The parser of websocket frames in one direction.
+#[derive(Default)]
+struct FrameParser {
+    // the incomplete frame header of last data
+    pending: BytesMut,
+    // the remaining payload size of current frame
+    remaining: u64,
+    // the size of current fragmented message
+    message_size: u64,
+    // all data is dropped after closed
+    closed: bool,
+}
+
+impl FrameParser {
+    /// Parse the frames of data, it returns the forwarded data and the
+    /// close code if the policy is violated. The data is truncated before
+    /// the violated frame, and the incomplete frame header is kept until
+    /// the next data is received.
+    fn parse(
+        &mut self,
+        data: Bytes,
+        max_message_size: Option<u64>,
+        expired: bool,
+    ) -> (Bytes, Option<u16>) {
+        if self.closed {
+            return (Bytes::new(), None);
+        }
+        let mut buf = std::mem::take(&mut self.pending);
+        buf.extend_from_slice(&data);
+        let size = buf.len();
+        let mut pos = 0;
+        let mut violation = None;
+        while pos < size {
+            if self.remaining > 0 {
+                let n = self.remaining.min((size - pos) as u64);
+                self.remaining -= n;
+                pos += n as usize;
+                continue;
+            }
+            if expired {
+                violation = Some(CLOSE_GOING_AWAY);
+                break;
+            }
+            if size - pos < 2 {
+                break;
+            }
+            let (b0, b1) = (buf[pos], buf[pos + 1]);
+            let mut header_size = 2;
+            header_size += match b1 & 0x7f {
+                126 => 2,
+                127 => 8,
+                _ => 0,
+            };
+            if b1 & 0x80 != 0 {
+                header_size += 4;
+            }
+            if size - pos < header_size {
+                break;
+            }
+            let payload_size = match b1 & 0x7f {
+                126 => u16::from_be_bytes([buf[pos + 2], buf[pos + 3]]) as u64,
+                127 => {
+                    let mut value = [0; 8];
+                    value.copy_from_slice(&buf[pos + 2..pos + 10]);
+                    u64::from_be_bytes(value)
+                },
+                value => value as u64,
+            };
+            // text, binary and continuation frame
+            if b0 & 0x0f < 8 {
+                self.message_size += payload_size;
+                if let Some(max) = max_message_size {
+                    if self.message_size > max {
+                        violation = Some(CLOSE_MESSAGE_TOO_BIG);
+                        break;
+                    }
+                }
+                if b0 & 0x80 != 0 {
+                    self.message_size = 0;
+                }
+            }
+            self.remaining = payload_size;
+            pos += header_size;
+        }
+        if violation.is_some() {
+            self.closed = true;
+            buf.truncate(pos);
+        } else {
+            self.pending = buf.split_off(pos);
+        }
+        (buf.freeze(), violation)
+    }
+}
+
+struct PolicyGuard {
+    max_message_size: Option<u64>,
+    max_lifetime: Option<Duration>,
+    started_at: Instant,
+    client: FrameParser,
+    upstream: FrameParser,
+}
+
+impl WebSocketGuard for PolicyGuard {
+    /// The violated side is closed by a close frame sent to its peer,
+    /// the close frame replied by the peer will be forwarded to it.
+    fn inspect(&mut self, data: Bytes, from_client: bool) -> Bytes {
+        let expired = self
+            .max_lifetime
+            .map(|max| self.started_at.elapsed() > max)
+            .unwrap_or_default();
+        let parser = if from_client {
+            &mut self.client
+        } else {
+            &mut self.upstream
+        };
+        let (data, violation) =
+            parser.parse(data, self.max_message_size, expired);
+        let Some(code) = violation else {
+            return data;
+        };
+        info!(code, from_client, "websocket policy is violated");
+        let mut buf = BytesMut::from(data.as_ref());
+        buf.extend_from_slice(&new_close_frame(code, from_client));
+        buf.freeze()
+    }
+}
+
+impl WebsocketPolicy {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new websocket policy plugin");
+        Self::try_from(params)
+    }
+}
+
+#[async_trait]
+impl Plugin for WebsocketPolicy {
+    #[inline]
+    fn hash_key(&self) -> String {
+        self.hash_value.clone()
+    }
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut State,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        if step != self.plugin_step {
+            return Ok(None);
+        }
+        let is_websocket = session
+            .get_header(header::UPGRADE)
+            .map(|value| value.as_bytes().eq_ignore_ascii_case(b"websocket"))
+            .unwrap_or_default();
+        if !is_websocket {
+            return Ok(None);
+        }
+        if !self.allowed_origins.is_empty() {
+            let origin = session
+                .get_header(header::ORIGIN)
+                .map(|value| value.to_str().unwrap_or_default())
+                .unwrap_or_default();
+            if !is_origin_allowed(&self.allowed_origins, origin) {
+                return Ok(Some(HttpResponse {
+                    status: StatusCode::FORBIDDEN,
+                    body: Bytes::from_static(b"Origin is not allowed"),
+                    ..Default::default()
+                }));
+            }
+        }
+        if let Some(idle_timeout) = self.idle_timeout {
+            session.set_read_timeout(idle_timeout);
+        }
+        if self.max_message_size.is_some() || self.max_lifetime.is_some() {
+            ctx.websocket_guard = Some(Box::new(PolicyGuard {
+                max_message_size: self.max_message_size,
+                max_lifetime: self.max_lifetime,
+                started_at: Instant::now(),
+                client: FrameParser::default(),
+                upstream: FrameParser::default(),
+            }));
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        is_origin_allowed, new_close_frame, FrameParser, WebsocketPolicy,
+        CLOSE_GOING_AWAY, CLOSE_MESSAGE_TOO_BIG,
+    };
+    use crate::config::{PluginConf, PluginStep};
+    use crate::plugin::Plugin;
+    use crate::state::State;
+    use bytes::Bytes;
+    use http::StatusCode;
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+    use tokio_test::io::Builder;
+
+    #[test]
+    fn test_websocket_policy_params() {
+        let params = WebsocketPolicy::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+max_message_size = "1kb"
+max_lifetime = "1h"
+idle_timeout = "60s"
+allowed_origins = ["https://pingap.io", "*.pingap.io"]
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(Some(1000), params.max_message_size);
+        assert_eq!(Some(Duration::from_secs(3600)), params.max_lifetime);
+        assert_eq!(Some(Duration::from_secs(60)), params.idle_timeout);
+
+        let result = WebsocketPolicy::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+step = "response"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin websocket_policy invalid, message: Websocket policy plugin should be executed at request step",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_is_origin_allowed() {
+        let patterns = vec![
+            "https://pingap.io".to_string(),
+            "*.npmtrend.com".to_string(),
+        ];
+        assert_eq!(true, is_origin_allowed(&patterns, "https://pingap.io"));
+        assert_eq!(
+            true,
+            is_origin_allowed(&patterns, "https://www.npmtrend.com")
+        );
+        assert_eq!(false, is_origin_allowed(&patterns, "http://pingap.io"));
+        assert_eq!(false, is_origin_allowed(&patterns, ""));
+    }
+
+    #[test]
+    fn test_close_frame() {
+        assert_eq!(
+            b"\x88\x02\x03\xf1".to_vec(),
+            new_close_frame(CLOSE_MESSAGE_TOO_BIG, false).to_vec()
+        );
+        let frame = new_close_frame(CLOSE_MESSAGE_TOO_BIG, true);
+        assert_eq!(8, frame.len());
+        assert_eq!(0x82, frame[1]);
+        let code = [frame[6] ^ frame[2], frame[7] ^ frame[3]];
+        assert_eq!(CLOSE_MESSAGE_TOO_BIG, u16::from_be_bytes(code));
+    }
+
+    #[test]
+    fn test_frame_parser() {
+        let mut parser = FrameParser::default();
+        // text frame with 5 bytes
+        let (data, violation) =
+            parser.parse(Bytes::from_static(b"\x81\x05hello"), Some(10), false);
+        assert_eq!(b"\x81\x05hello".to_vec(), data.to_vec());
+        assert_eq!(None, violation);
+
+        // incomplete header is kept
+        let (data, violation) =
+            parser.parse(Bytes::from_static(b"\x01"), Some(10), false);
+        assert_eq!(0, data.len());
+        assert_eq!(None, violation);
+        let (data, _) =
+            parser.parse(Bytes::from_static(b"\x04abcd"), Some(10), false);
+        assert_eq!(b"\x01\x04abcd".to_vec(), data.to_vec());
+
+        // the fragmented message is too big
+        let (data, violation) = parser.parse(
+            Bytes::from_static(b"\x80\x07abcdefg"),
+            Some(10),
+            false,
+        );
+        assert_eq!(0, data.len());
+        assert_eq!(Some(CLOSE_MESSAGE_TOO_BIG), violation);
+        // the data is dropped after closed
+        let (data, violation) =
+            parser.parse(Bytes::from_static(b"\x81\x01a"), Some(10), false);
+        assert_eq!(0, data.len());
+        assert_eq!(None, violation);
+
+        // the payload of current frame is forwarded before expired
+        let mut parser = FrameParser::default();
+        parser.parse(Bytes::from_static(b"\x82\x04ab"), None, false);
+        let (data, violation) =
+            parser.parse(Bytes::from_static(b"cd\x81\x01a"), None, true);
+        assert_eq!(b"cd".to_vec(), data.to_vec());
+        assert_eq!(Some(CLOSE_GOING_AWAY), violation);
+    }
+
+    #[tokio::test]
+    async fn test_websocket_policy() {
+        let params = WebsocketPolicy::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+max_message_size = "1kb"
+allowed_origins = ["*.pingap.io"]
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        let headers =
+            ["Upgrade: websocket", "Origin: https://npmtrend.com"].join("\r\n");
+        let input_header = format!("GET / HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let result = params
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, result.unwrap().status);
+
+        let headers = ["Upgrade: websocket", "Origin: https://www.pingap.io"]
+            .join("\r\n");
+        let input_header = format!("GET / HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let mut ctx = State::default();
+        let result = params
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_none());
+        assert_eq!(true, ctx.websocket_guard.is_some());
+    }
+}
@@ -0,0 +1,273 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{get_hash_key, get_step_conf, get_str_conf, Error, Plugin, Result};
+use crate::config::{PluginCategory, PluginConf, PluginStep};
+use crate::http_extra::HttpResponse;
+use crate::state::State;
+use crate::util;
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::{header, HeaderValue, Method, StatusCode};
+use humantime::parse_duration;
+use pingora::proxy::Session;
+use std::path::Path;
+use std::time::Duration;
+use tracing::debug;
+
+const ROBOTS_PATH: &str = "/robots.txt";
+const SECURITY_PATH: &str = "/.well-known/security.txt";
+const FAVICON_PATH: &str = "/favicon.ico";
+
+pub struct WellKnown {
+    plugin_step: PluginStep,
+    robots: Option<HttpResponse>,
+    security: Option<HttpResponse>,
+    favicon: Option<HttpResponse>,
+    hash_value: String,
+}
+
+fn new_response(
+    body: Bytes,
+    content_type: &'static str,
+    max_age: u32,
+) -> HttpResponse {
+    HttpResponse {
+        status: StatusCode::OK,
+        body,
+        max_age: Some(max_age),
+        headers: Some(vec![(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(content_type),
+        )]),
+        ..Default::default()
+    }
+}
+
+/// Load the favicon from file, or base64 data if the file is not exists.
+fn load_favicon(value: &str) -> Result<(Bytes, &'static str)> {
+    let path = Path::new(value);
+    if path.is_file() {
+        let data = std::fs::read(path).map_err(|e| Error::Invalid {
+            category: PluginCategory::WellKnown.to_string(),
+            message: format!("read favicon {value} fail, {e}"),
+        })?;
+        let content_type = match path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default()
+        {
+            "png" => "image/png",
+            "svg" => "image/svg+xml",
+            "gif" => "image/gif",
+            _ => "image/x-icon",
+        };
+        return Ok((data.into(), content_type));
+    }
+    let data = util::base64_decode(value).map_err(|e| Error::Invalid {
+        category: PluginCategory::WellKnown.to_string(),
+        message: format!("favicon should be file or base64 data, {e}"),
+    })?;
+    Ok((data.into(), "image/x-icon"))
+}
+
+impl TryFrom<&PluginConf> for WellKnown {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let step = get_step_conf(value);
+
+        let max_age = get_str_conf(value, "max_age");
+        let max_age = if !max_age.is_empty() {
+            parse_duration(&max_age).map_err(|e| Error::Invalid {
+                category: PluginCategory::WellKnown.to_string(),
+                message: e.to_string(),
+            })?
+        } else {
+            Duration::from_secs(24 * 3600)
+        };
+        let max_age = max_age.as_secs() as u32;
+
+        let robots = get_str_conf(value, "robots");
+        let security = get_str_conf(value, "security");
+        let favicon = get_str_conf(value, "favicon");
+        let favicon = if favicon.is_empty() {
+            None
+        } else {
+            let (data, content_type) = load_favicon(&favicon)?;
+            Some(new_response(data, content_type, max_age))
+        };
+        let text_response = |value: String| {
+            if value.is_empty() {
+                None
+            } else {
+                Some(new_response(
+                    value.into(),
+                    "text/plain; charset=utf-8",
+                    max_age,
+                ))
+            }
+        };
+
+        let params = Self {
+            hash_value,
+            plugin_step: step,
+            robots: text_response(robots),
+            security: text_response(security),
+            favicon,
+        };
+        if params.robots.is_none()
+            && params.security.is_none()
+            && params.favicon.is_none()
+        {
+            return Err(Error::Invalid {
+                category: PluginCategory::WellKnown.to_string(),
+                message: "Robots, security or favicon should be set"
+                    .to_string(),
+            });
+        }
+        if params.plugin_step != PluginStep::Request {
+            return Err(Error::Invalid {
+                category: PluginCategory::WellKnown.to_string(),
+                message: "Well known plugin should be executed at request step"
+                    .to_string(),
+            });
+        }
+        Ok(params)
+    }
+}
+
+impl WellKnown {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new well known plugin");
+        Self::try_from(params)
+    }
+}
+
+#[async_trait]
+impl Plugin for WellKnown {
+    #[inline]
+    fn hash_key(&self) -> String {
+        self.hash_value.clone()
+    }
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        _ctx: &mut State,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        if step != self.plugin_step {
+            return Ok(None);
+        }
+        let req_header = session.req_header();
+        if ![Method::GET, Method::HEAD].contains(&req_header.method) {
+            return Ok(None);
+        }
+        let resp = match req_header.uri.path() {
+            ROBOTS_PATH => &self.robots,
+            SECURITY_PATH => &self.security,
+            FAVICON_PATH => &self.favicon,
+            _ => return Ok(None),
+        };
+        Ok(resp.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WellKnown;
+    use crate::config::{PluginConf, PluginStep};
+    use crate::plugin::Plugin;
+    use crate::state::State;
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use tokio_test::io::Builder;
+
+    #[test]
+    fn test_well_known_params() {
+        let params = WellKnown::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+robots = "User-agent: *\nDisallow: /admin"
+favicon = "AAABAAEAAQEAAAEAIAAwAAAAFgAAACgAAAABAAAAAgAAAAEAIAAAAAAACAAAAAAAAAAAAAAAAAAAAAAAAAD/AAD/AAAAAA=="
+max_age = "1h"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(true, params.robots.is_some());
+        assert_eq!(true, params.security.is_none());
+        let favicon = params.favicon.unwrap();
+        assert_eq!(70, favicon.body.len());
+        assert_eq!(Some(3600), favicon.max_age);
+
+        let result = WellKnown::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+max_age = "1h"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin well_known invalid, message: Robots, security or favicon should be set",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_well_known() {
+        let params = WellKnown::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+robots = "User-agent: *\nDisallow: /admin"
+security = "Contact: mailto:security@example.com"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        for (path, body) in [
+            ("/robots.txt", Some("User-agent: *\nDisallow: /admin")),
+            (
+                "/.well-known/security.txt",
+                Some("Contact: mailto:security@example.com"),
+            ),
+            ("/favicon.ico", None),
+            ("/", None),
+        ] {
+            let input_header = format!("GET {path} HTTP/1.1\r\n\r\n");
+            let mock_io = Builder::new().read(input_header.as_bytes()).build();
+            let mut session = Session::new_h1(Box::new(mock_io));
+            session.read_request().await.unwrap();
+            let result = params
+                .handle_request(
+                    PluginStep::Request,
+                    &mut session,
+                    &mut State::default(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(
+                body,
+                result
+                    .as_ref()
+                    .map(|resp| std::str::from_utf8(&resp.body).unwrap())
+            );
+        }
+    }
+}
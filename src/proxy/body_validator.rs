@@ -0,0 +1,201 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::util;
+use http::header;
+use pingora::http::ResponseHeader;
+use sha2::{Digest, Sha256, Sha512};
+
+enum BodyHasher {
+    Sha256(Sha256),
+    Sha512(Sha512),
+}
+
+impl BodyHasher {
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            BodyHasher::Sha256(hasher) => hasher.update(data),
+            BodyHasher::Sha512(hasher) => hasher.update(data),
+        }
+    }
+    fn finalize(self) -> Vec<u8> {
+        match self {
+            BodyHasher::Sha256(hasher) => hasher.finalize().to_vec(),
+            BodyHasher::Sha512(hasher) => hasher.finalize().to_vec(),
+        }
+    }
+}
+
+/// Validate the streamed upstream body by content length and digest header,
+/// the mismatch body should not be sent to client as a complete response.
+pub struct BodyValidator {
+    content_length: Option<usize>,
+    received: usize,
+    // the expected digest and hasher
+    digest: Option<(Vec<u8>, BodyHasher)>,
+}
+
+/// Parse the digest header value, it supports
+/// `sha-256=:base64:`(Content-Digest, Repr-Digest) and `SHA-256=base64`(Digest).
+fn parse_digest(value: &str) -> Option<(Vec<u8>, BodyHasher)> {
+    for item in value.split(',') {
+        let Some((algorithm, value)) = item.trim().split_once('=') else {
+            continue;
+        };
+        let value = value.trim().trim_matches(':');
+        let hasher = match algorithm.trim().to_lowercase().as_str() {
+            "sha-256" => BodyHasher::Sha256(Sha256::new()),
+            "sha-512" => BodyHasher::Sha512(Sha512::new()),
+            _ => continue,
+        };
+        if let Ok(expected) = util::base64_decode(value) {
+            return Some((expected, hasher));
+        }
+    }
+    None
+}
+
+impl BodyValidator {
+    /// Create a body validator from upstream response header,
+    /// it returns `None` if there is nothing to validate.
+    pub fn new(
+        header: &ResponseHeader,
+        validate_content_length: bool,
+        validate_digest: bool,
+    ) -> Option<Self> {
+        let status = header.status.as_u16();
+        // no body
+        if status < 200 || status == 204 || status == 304 {
+            return None;
+        }
+        let get_value = |name: &str| {
+            header
+                .headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string())
+        };
+        let content_length = if validate_content_length
+            && !header.headers.contains_key(header::TRANSFER_ENCODING)
+        {
+            get_value(header::CONTENT_LENGTH.as_str())
+                .and_then(|value| value.trim().parse::<usize>().ok())
+        } else {
+            None
+        };
+        let digest = if validate_digest {
+            let mut value = get_value("content-digest");
+            // the representation digest is the same as content digest
+            // if the response is not encoded
+            if value.is_none()
+                && !header.headers.contains_key(header::CONTENT_ENCODING)
+            {
+                value = get_value("repr-digest");
+            }
+            value
+                .or_else(|| get_value("digest"))
+                .and_then(|value| parse_digest(&value))
+        } else {
+            None
+        };
+        if content_length.is_none() && digest.is_none() {
+            return None;
+        }
+        Some(Self {
+            content_length,
+            received: 0,
+            digest,
+        })
+    }
+    /// Update the validator with the body chunk.
+    #[inline]
+    pub fn update(&mut self, data: &[u8]) {
+        self.received += data.len();
+        if let Some((_, hasher)) = self.digest.as_mut() {
+            hasher.update(data);
+        }
+    }
+    /// Validate the body after end of stream, the error message
+    /// will be returned if the length or digest is mismatched.
+    pub fn finish(&mut self) -> Result<(), String> {
+        if let Some(content_length) = self.content_length {
+            if content_length != self.received {
+                return Err(format!(
+                    "Upstream body length mismatch, content-length: {content_length}, received: {}",
+                    self.received
+                ));
+            }
+        }
+        if let Some((expected, hasher)) = self.digest.take() {
+            if hasher.finalize() != expected {
+                return Err("Upstream body digest mismatch".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BodyValidator;
+    use crate::util;
+    use pingora::http::ResponseHeader;
+    use pretty_assertions::assert_eq;
+    use sha2::{Digest, Sha256};
+
+    #[test]
+    fn test_body_validator() {
+        let mut header = ResponseHeader::build(200, None).unwrap();
+        assert_eq!(true, BodyValidator::new(&header, true, true).is_none());
+
+        header.insert_header("Content-Length", "12").unwrap();
+        let mut validator = BodyValidator::new(&header, true, false).unwrap();
+        validator.update(b"Hello ");
+        assert_eq!(
+            "Upstream body length mismatch, content-length: 12, received: 6",
+            validator.finish().err().unwrap()
+        );
+        let mut validator = BodyValidator::new(&header, true, false).unwrap();
+        validator.update(b"Hello ");
+        validator.update(b"World!");
+        assert_eq!(true, validator.finish().is_ok());
+
+        let digest = util::base64_encode(Sha256::digest(b"Hello World!"));
+        let mut header = ResponseHeader::build(200, None).unwrap();
+        header
+            .insert_header("Content-Digest", format!("sha-256=:{digest}:"))
+            .unwrap();
+        let mut validator = BodyValidator::new(&header, false, true).unwrap();
+        validator.update(b"Hello World!");
+        assert_eq!(true, validator.finish().is_ok());
+        let mut validator = BodyValidator::new(&header, false, true).unwrap();
+        validator.update(b"Hello World?");
+        assert_eq!(
+            "Upstream body digest mismatch",
+            validator.finish().err().unwrap()
+        );
+
+        let mut header = ResponseHeader::build(200, None).unwrap();
+        header
+            .insert_header("Digest", format!("SHA-256={digest}"))
+            .unwrap();
+        let mut validator = BodyValidator::new(&header, false, true).unwrap();
+        validator.update(b"Hello World!");
+        assert_eq!(true, validator.finish().is_ok());
+
+        let mut header = ResponseHeader::build(304, None).unwrap();
+        header.insert_header("Content-Length", "12").unwrap();
+        assert_eq!(true, BodyValidator::new(&header, true, true).is_none());
+    }
+}
@@ -0,0 +1,300 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::dynamic_certificate::validate_certificates;
+use super::upstream::Upstream;
+use super::Location;
+use crate::config::{
+    ConfigChange, PingapConf, UpstreamConf, CATEGORY_UPSTREAM,
+};
+use crate::discovery::{format_addrs, is_dns_discovery, is_static_discovery};
+use crate::plugin::parse_plugins;
+use serde::Serialize;
+use std::net::TcpListener;
+use std::time::Duration;
+use tokio::net::{lookup_host, TcpStream};
+
+/// The item of full check, the message is empty if it passes.
+#[derive(Debug, Serialize)]
+pub struct CheckItem {
+    pub category: &'static str,
+    pub name: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CheckReport {
+    pub success: bool,
+    pub items: Vec<CheckItem>,
+    // the changes against the current config
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub changes: Vec<ConfigChange>,
+}
+
+fn new_item<E: ToString>(
+    category: &'static str,
+    name: &str,
+    result: Result<(), E>,
+) -> CheckItem {
+    CheckItem {
+        category,
+        name: name.to_string(),
+        message: result.err().map(|e| e.to_string()).unwrap_or_default(),
+    }
+}
+
+/// Check the config fully, all errors are collected instead of returning
+/// the first one. Beyond parsing, the listeners are bound, the certificates
+/// are loaded, and the upstreams, locations and plugins are instantiated.
+pub fn check_full(conf: &PingapConf) -> CheckReport {
+    check(conf, &[])
+}
+
+/// Check the candidate config fully and get the changes against the
+/// current config. The listeners of current config are not bound again,
+/// because they may be held by the running server.
+pub fn check_candidate(conf: &PingapConf, current: &PingapConf) -> CheckReport {
+    let addrs: Vec<&str> = current
+        .servers
+        .values()
+        .flat_map(|item| item.addr.split(','))
+        .map(|addr| addr.trim())
+        .collect();
+    let mut report = check(conf, &addrs);
+    report.changes = current.changes(conf);
+    report
+}
+
+/// Check the staged config before it's applied, beyond the candidate check,
+/// the addresses of created or updated upstreams are resolved, and at least
+/// one of them should be connectable.
+pub async fn check_staged(
+    conf: &PingapConf,
+    current: &PingapConf,
+) -> CheckReport {
+    let mut report = check_candidate(conf, current);
+    let names: Vec<String> = report
+        .changes
+        .iter()
+        .filter(|item| {
+            item.category == CATEGORY_UPSTREAM && item.action != "remove"
+        })
+        .map(|item| item.name.clone())
+        .collect();
+    for name in names {
+        if let Some(up) = conf.upstreams.get(&name) {
+            let result = check_connectivity(up).await;
+            report.items.push(new_item("connectivity", &name, result));
+        }
+    }
+    report.success = report.items.iter().all(|item| item.message.is_empty());
+    report
+}
+
+/// Check whether one of the upstream addresses is connectable,
+/// the upstream of docker or transparent discovery is skipped.
+async fn check_connectivity(conf: &UpstreamConf) -> Result<(), String> {
+    let discovery = conf.guess_discovery();
+    if !is_static_discovery(&discovery) && !is_dns_discovery(&discovery) {
+        return Ok(());
+    }
+    let tls = conf
+        .sni
+        .as_ref()
+        .map(|item| !item.is_empty())
+        .unwrap_or_default();
+    let timeout = conf.connection_timeout.unwrap_or(Duration::from_secs(3));
+    let mut errors = vec![];
+    for (host, port, _) in format_addrs(&conf.addrs, tls) {
+        let addr = format!("{host}:{port}");
+        let addrs = match lookup_host(&addr).await {
+            Ok(addrs) => addrs,
+            Err(e) => {
+                errors.push(format!("resolve {addr} fail, {e}"));
+                continue;
+            },
+        };
+        for item in addrs {
+            match tokio::time::timeout(timeout, TcpStream::connect(item)).await
+            {
+                Ok(Ok(_)) => return Ok(()),
+                Ok(Err(e)) => errors.push(format!("connect {item} fail, {e}")),
+                Err(_) => errors.push(format!("connect {item} timeout")),
+            }
+        }
+    }
+    if errors.is_empty() {
+        return Ok(());
+    }
+    Err(errors.join("; "))
+}
+
+fn check(conf: &PingapConf, skip_addrs: &[&str]) -> CheckReport {
+    let mut items = vec![new_item("config", "pingap", conf.validate())];
+
+    let mut names: Vec<&String> = conf.upstreams.keys().collect();
+    names.sort();
+    for name in names {
+        let result = Upstream::new(name, &conf.upstreams[name]).map(|_| ());
+        items.push(new_item("upstream", name, result));
+    }
+
+    let mut names: Vec<&String> = conf.locations.keys().collect();
+    names.sort();
+    for name in names {
+        let result = Location::new(name, &conf.locations[name]).map(|_| ());
+        items.push(new_item("location", name, result));
+    }
+
+    let mut names: Vec<&String> = conf.plugins.keys().collect();
+    names.sort();
+    for name in names {
+        let result =
+            parse_plugins(vec![(name.to_string(), conf.plugins[name].clone())])
+                .map(|_| ());
+        items.push(new_item("plugin", name, result));
+    }
+
+    let errors = validate_certificates(&conf.certificates);
+    let mut names: Vec<&String> = conf.certificates.keys().collect();
+    names.sort();
+    for name in names {
+        let message = errors
+            .iter()
+            .find(|(item, _)| item == name)
+            .map(|(_, message)| message.to_string())
+            .unwrap_or_default();
+        items.push(CheckItem {
+            category: "certificate",
+            name: name.to_string(),
+            message,
+        });
+    }
+
+    let mut names: Vec<&String> = conf.servers.keys().collect();
+    names.sort();
+    for name in names {
+        for addr in conf.servers[name].addr.split(',') {
+            if skip_addrs.contains(&addr.trim()) {
+                continue;
+            }
+            // the listener is closed after dropped
+            let result = TcpListener::bind(addr.trim()).map(|_| ());
+            items.push(new_item("server", &format!("{name}({addr})"), result));
+        }
+    }
+
+    CheckReport {
+        success: items.iter().all(|item| item.message.is_empty()),
+        items,
+        changes: vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_candidate, check_full, check_staged};
+    use crate::config::{ConfigChange, PingapConf};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_check_full() {
+        let conf = PingapConf::new(
+            r###"
+[upstreams.charts]
+addrs = ["127.0.0.1:5000"]
+
+[locations.lo]
+upstream = "charts"
+path = "~/api/(["
+
+[servers.test]
+addr = "127.0.0.1:0"
+locations = ["lo"]
+"###
+            .as_bytes(),
+            false,
+        )
+        .unwrap();
+        let report = check_full(&conf);
+        assert_eq!(false, report.success);
+        let failed: Vec<String> = report
+            .items
+            .iter()
+            .filter(|item| !item.message.is_empty())
+            .map(|item| format!("{}:{}", item.category, item.name))
+            .collect();
+        assert_eq!(vec!["location:lo".to_string()], failed);
+    }
+
+    #[test]
+    fn test_check_candidate() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let current = PingapConf::new(
+            format!(
+                r###"
+[upstreams.charts]
+addrs = ["127.0.0.1:5000"]
+
+[locations.lo]
+upstream = "charts"
+
+[servers.test]
+addr = "{addr}"
+locations = ["lo"]
+"###
+            )
+            .as_bytes(),
+            false,
+        )
+        .unwrap();
+        let mut conf = current.clone();
+        conf.upstreams.get_mut("charts").unwrap().addrs =
+            vec!["127.0.0.1:5001".to_string()];
+
+        // the listener of running server is skipped
+        let report = check_candidate(&conf, &current);
+        assert_eq!(true, report.success);
+        assert_eq!(1, report.changes.len());
+        assert_eq!("modify", report.changes[0].action);
+        assert_eq!("charts", report.changes[0].name);
+        assert_eq!(false, check_full(&conf).success);
+    }
+
+    #[tokio::test]
+    async fn test_check_staged() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let current = PingapConf::new(
+            r###"
+[upstreams.charts]
+addrs = ["127.0.0.1:5000"]
+"###
+            .as_bytes(),
+            false,
+        )
+        .unwrap();
+        let mut conf = current.clone();
+        conf.upstreams.get_mut("charts").unwrap().addrs = vec![addr];
+        assert_eq!(true, check_staged(&conf, &current).await.success);
+
+        // the listener is closed
+        drop(listener);
+        let report = check_staged(&conf, &current).await;
+        assert_eq!(false, report.success);
+        assert_eq!("connectivity", report.items.last().unwrap().category);
+    }
+}
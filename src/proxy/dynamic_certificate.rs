@@ -22,7 +22,7 @@ use once_cell::sync::Lazy;
 use pingora::listeners::tls::TlsSettings;
 use pingora::tls::ext;
 use pingora::tls::pkey::{PKey, Private};
-use pingora::tls::ssl::{NameType, SslRef};
+use pingora::tls::ssl::{NameType, SslRef, SslVerifyMode};
 use pingora::tls::x509::X509;
 use snafu::Snafu;
 use std::collections::HashMap;
@@ -78,6 +78,15 @@ fn parse_certificates(
     (dynamic_certs, errors)
 }
 
+/// Validate the certificates can be loaded for tls,
+/// it returns the errors of certificates.
+pub fn validate_certificates(
+    certificate_configs: &HashMap<String, CertificateConf>,
+) -> Vec<(String, String)> {
+    let (_, errors) = parse_certificates(certificate_configs);
+    errors
+}
+
 /// Try update certificates, which use for global tls callback
 pub fn try_update_certificates(
     certificate_configs: &HashMap<String, CertificateConf>,
@@ -124,6 +133,7 @@ pub struct TlsSettingParams {
     pub ciphersuites: Option<String>,
     pub tls_min_version: Option<String>,
     pub tls_max_version: Option<String>,
+    pub tls_client_ca: Option<String>,
 }
 
 #[inline]
@@ -203,6 +213,28 @@ impl GlobalCertificate {
             );
         }
 
+        // mtls, the client certificate should be issued by the ca
+        if params.tls_client_ca.is_some() {
+            let data = util::convert_certificate_bytes(&params.tls_client_ca)
+                .unwrap_or_default();
+            let certs =
+                X509::stack_from_pem(&data).map_err(|e| Error::Invalid {
+                    category: "tls_client_ca".to_string(),
+                    message: e.to_string(),
+                })?;
+            for cert in certs {
+                if let Err(e) = tls_settings.add_client_ca(&cert) {
+                    error!(error = e.to_string(), name, "add client ca fail");
+                }
+                if let Err(e) = tls_settings.cert_store_mut().add_cert(cert) {
+                    error!(error = e.to_string(), name, "add client ca fail");
+                }
+            }
+            tls_settings.set_verify(
+                SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT,
+            );
+        }
+
         // tls_settings.set_min_proto_version(version)
         if let Some(min_version) = tls_settings.min_proto_version() {
             info!(name, min_version = format!("{min_version:?}"), "tls proto");
@@ -353,6 +385,7 @@ aqcrKJfS+xaKWxXPiNlpBMG5
                 ),
                 tls_min_version: Some("tlsv1.1".to_string()),
                 tls_max_version: Some("tlsv1.3".to_string()),
+                tls_client_ca: None,
             })
             .unwrap();
         assert_eq!(true, tls_settings.min_proto_version().is_some());
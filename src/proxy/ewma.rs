@@ -0,0 +1,223 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::util;
+use ahash::AHashMap;
+use rand::Rng;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+#[derive(Debug, Default)]
+struct PeerEwmaValue {
+    // the ewma of response time(ms)
+    latency: f64,
+    // the last updated time(ms)
+    updated_at: u64,
+}
+
+#[derive(Debug, Default)]
+struct PeerEwma {
+    value: Mutex<PeerEwmaValue>,
+    // the count of outstanding requests
+    pending: AtomicU32,
+}
+
+impl PeerEwma {
+    /// Get the decayed latency of peer.
+    fn latency(&self, decay: f64, now: u64) -> f64 {
+        let value = self.value.lock().unwrap_or_else(|e| e.into_inner());
+        let elapsed = now.saturating_sub(value.updated_at) as f64;
+        value.latency * (-elapsed / decay).exp()
+    }
+    /// Observe the response time, the peak value is used directly
+    /// and the lower value is smoothed by the elapsed time.
+    fn observe(&self, decay: f64, now: u64, latency: f64) {
+        let mut value = self.value.lock().unwrap_or_else(|e| e.into_inner());
+        if latency > value.latency {
+            value.latency = latency;
+        } else {
+            let elapsed = now.saturating_sub(value.updated_at) as f64;
+            let weight = (-elapsed / decay).exp();
+            value.latency = value.latency * weight + latency * (1.0 - weight);
+        }
+        value.updated_at = now;
+    }
+    /// The cost of peer, the latency is weighted by outstanding requests.
+    fn cost(&self, decay: f64, now: u64) -> f64 {
+        let pending = self.pending.load(Ordering::Relaxed) as f64;
+        // add one to avoid zero cost of the peer without sample
+        (self.latency(decay, now) + 1.0) * (pending + 1.0)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerEwmaStat {
+    pub latency: f64,
+    pub pending: u32,
+    pub cost: f64,
+}
+
+/// Peak ewma selection, the peer with the lowest cost of
+/// two random peers is chosen(power of two choices).
+#[derive(Debug)]
+pub struct PeakEwma {
+    // the decay time(ms) of ewma
+    decay: f64,
+    peers: RwLock<AHashMap<String, Arc<PeerEwma>>>,
+}
+
+impl PeakEwma {
+    pub fn new(decay: Duration) -> Self {
+        Self {
+            decay: (decay.as_millis() as f64).max(1.0),
+            peers: RwLock::new(AHashMap::new()),
+        }
+    }
+    fn get_peer(&self, addr: &str) -> Arc<PeerEwma> {
+        if let Some(peer) = self
+            .peers
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(addr)
+        {
+            return peer.clone();
+        }
+        self.peers
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(addr.to_string())
+            .or_default()
+            .clone()
+    }
+    /// Select the address from candidates and add the outstanding
+    /// request of it.
+    pub fn select<'a>(&self, candidates: &[&'a str]) -> Option<&'a str> {
+        let addr = match candidates.len() {
+            0 => return None,
+            1 => candidates[0],
+            count => {
+                let mut rng = rand::thread_rng();
+                let first = rng.gen_range(0..count);
+                let mut second = rng.gen_range(0..count - 1);
+                if second >= first {
+                    second += 1;
+                }
+                let now = util::now().as_millis() as u64;
+                let (a, b) = (candidates[first], candidates[second]);
+                if self.get_peer(a).cost(self.decay, now)
+                    <= self.get_peer(b).cost(self.decay, now)
+                {
+                    a
+                } else {
+                    b
+                }
+            },
+        };
+        self.get_peer(addr).pending.fetch_add(1, Ordering::Relaxed);
+        Some(addr)
+    }
+    /// Complete the outstanding request of address,
+    /// the response time is observed if it exists.
+    pub fn completed(&self, addr: &str, latency: Option<u64>) {
+        let Some(peer) = self
+            .peers
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(addr)
+            .cloned()
+        else {
+            return;
+        };
+        let _ = peer.pending.fetch_update(
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+            |value| value.checked_sub(1),
+        );
+        if let Some(latency) = latency {
+            let now = util::now().as_millis() as u64;
+            peer.observe(self.decay, now, latency as f64);
+        }
+    }
+    /// Remove the peers which are not in the address list,
+    /// the backends may be changed by service discovery.
+    pub fn retain(&self, addrs: &[&str]) {
+        self.peers
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|addr, _| addrs.contains(&addr.as_str()));
+    }
+    /// Get the ewma stats of all peers.
+    pub fn stats(&self) -> AHashMap<String, PeerEwmaStat> {
+        let now = util::now().as_millis() as u64;
+        self.peers
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|(addr, peer)| {
+                (
+                    addr.to_string(),
+                    PeerEwmaStat {
+                        latency: peer.latency(self.decay, now),
+                        pending: peer.pending.load(Ordering::Relaxed),
+                        cost: peer.cost(self.decay, now),
+                    },
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PeakEwma;
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+
+    #[test]
+    fn test_peak_ewma() {
+        let ewma = PeakEwma::new(Duration::from_secs(10));
+        assert_eq!(None, ewma.select(&[]));
+        assert_eq!(Some("a"), ewma.select(&["a"]));
+        ewma.completed("a", Some(100));
+
+        // the peer with lower latency is chosen
+        let candidates = ["a", "b"];
+        assert_eq!(Some("b"), ewma.select(&candidates));
+        ewma.completed("b", Some(10));
+        for _ in 0..10 {
+            assert_eq!(Some("b"), ewma.select(&candidates));
+            ewma.completed("b", Some(10));
+        }
+
+        // the outstanding requests increase the cost
+        for _ in 0..20 {
+            ewma.select(&["b"]);
+        }
+        assert_eq!(Some("a"), ewma.select(&candidates));
+
+        let stats = ewma.stats();
+        assert_eq!(1, stats.get("a").unwrap().pending);
+        assert_eq!(20, stats.get("b").unwrap().pending);
+        assert_eq!(true, stats.get("a").unwrap().latency > 99.0);
+
+        // the peak latency is used directly
+        ewma.completed("b", Some(500));
+        assert_eq!(true, ewma.stats().get("b").unwrap().latency > 499.0);
+
+        ewma.retain(&["a"]);
+        assert_eq!(1, ewma.stats().len());
+    }
+}
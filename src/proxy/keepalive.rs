@@ -0,0 +1,121 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::util;
+use ahash::AHashMap;
+use rand::Rng;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// the stale connections are removed if the count exceeds it
+const MAX_TRACKED_CONNECTIONS: usize = 4096;
+
+pub const CLOSE_BY_REQUESTS: &str = "requests";
+pub const CLOSE_BY_ROLLOUT: &str = "rollout";
+
+/// The keep-alive policy of downstream connections, the connection is
+/// closed if its requests exceed the limit, or it is chosen by the
+/// percentage of rollout for rebalancing behind the l4 load balancer.
+#[derive(Debug)]
+pub struct KeepalivePolicy {
+    max_requests: Option<u32>,
+    pub idle_timeout: Option<Duration>,
+    close_percentage: u8,
+    // connection id --> (request count, last seen seconds)
+    requests: Mutex<AHashMap<usize, (u32, u64)>>,
+}
+
+impl KeepalivePolicy {
+    pub fn new(
+        max_requests: Option<u32>,
+        idle_timeout: Option<Duration>,
+        close_percentage: Option<u8>,
+    ) -> Option<Self> {
+        let close_percentage = close_percentage.unwrap_or_default().min(100);
+        if max_requests.is_none()
+            && idle_timeout.is_none()
+            && close_percentage == 0
+        {
+            return None;
+        }
+        Some(Self {
+            max_requests: max_requests.filter(|value| *value > 0),
+            idle_timeout,
+            close_percentage,
+            requests: Mutex::new(AHashMap::new()),
+        })
+    }
+    /// Get the stale seconds of tracked connection.
+    fn stale_secs(&self) -> u64 {
+        self.idle_timeout
+            .unwrap_or(Duration::from_secs(60))
+            .as_secs()
+            .max(1)
+            * 2
+    }
+    /// Add the request count of connection, it returns the policy if
+    /// the connection should be closed after the response.
+    pub fn should_close(&self, connection_id: usize) -> Option<&'static str> {
+        if let Some(max) = self.max_requests {
+            let now = util::now().as_secs();
+            let mut requests =
+                self.requests.lock().unwrap_or_else(|e| e.into_inner());
+            if requests.len() >= MAX_TRACKED_CONNECTIONS {
+                let stale_secs = self.stale_secs();
+                requests.retain(|_, (_, last_seen)| {
+                    now.saturating_sub(*last_seen) < stale_secs
+                });
+            }
+            let entry = requests.entry(connection_id).or_insert((0, now));
+            entry.0 += 1;
+            entry.1 = now;
+            if entry.0 >= max {
+                requests.remove(&connection_id);
+                return Some(CLOSE_BY_REQUESTS);
+            }
+        }
+        if self.close_percentage > 0
+            && rand::thread_rng().gen_range(0..100) < self.close_percentage
+        {
+            self.requests
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .remove(&connection_id);
+            return Some(CLOSE_BY_ROLLOUT);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KeepalivePolicy, CLOSE_BY_REQUESTS, CLOSE_BY_ROLLOUT};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_keepalive_policy() {
+        assert_eq!(true, KeepalivePolicy::new(None, None, Some(0)).is_none());
+
+        let policy = KeepalivePolicy::new(Some(3), None, None).unwrap();
+        assert_eq!(None, policy.should_close(1));
+        assert_eq!(None, policy.should_close(2));
+        assert_eq!(None, policy.should_close(1));
+        assert_eq!(Some(CLOSE_BY_REQUESTS), policy.should_close(1));
+        // the count is reset after closed
+        assert_eq!(None, policy.should_close(1));
+
+        let policy = KeepalivePolicy::new(None, None, Some(100)).unwrap();
+        assert_eq!(Some(CLOSE_BY_ROLLOUT), policy.should_close(1));
+    }
+}
@@ -14,15 +14,17 @@
 
 use crate::config::{LocationConf, PluginStep};
 use crate::http_extra::{convert_header_value, convert_headers, HttpHeader};
-use crate::plugin::get_plugin;
-use crate::state::State;
+use crate::plugin::{get_sorted_plugins, SortedPlugins};
+use crate::state::{parse_slo_target, Slo, State};
 use crate::util::{self, get_content_length};
 use ahash::AHashMap;
 use arc_swap::ArcSwap;
+use bytes::Bytes;
 use once_cell::sync::Lazy;
 use pingora::http::{RequestHeader, ResponseHeader};
 use pingora::proxy::Session;
 use regex::Regex;
+use serde::Serialize;
 use snafu::{ResultExt, Snafu};
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
@@ -150,11 +152,14 @@ pub struct Location {
     proxy_add_headers: Option<Vec<HttpHeader>>,
     proxy_set_headers: Option<Vec<HttpHeader>>,
     plugins: Option<Vec<String>>,
+    // the plugins are only sorted again after they are reloaded
+    sorted_plugins: ArcSwap<SortedPlugins>,
     accepted: AtomicU64,
     processing: AtomicI32,
     max_processing: i32,
     grpc_web: bool,
     client_max_body_size: usize,
+    pub slo: Option<Slo>,
 }
 
 fn format_headers(
@@ -199,6 +204,16 @@ impl Location {
         }
 
         let path = conf.path.clone().unwrap_or_default();
+        let slo = if let Some(value) = &conf.slo_target {
+            let target = parse_slo_target(value)
+                .map_err(|message| Error::Invalid { message })?;
+            Some(Slo {
+                target,
+                latency: conf.slo_latency,
+            })
+        } else {
+            None
+        };
 
         let location = Location {
             name: name.to_string(),
@@ -209,6 +224,9 @@ impl Location {
             upstream,
             reg_rewrite,
             plugins: conf.plugins.clone(),
+            sorted_plugins: ArcSwap::from_pointee(get_sorted_plugins(
+                conf.plugins.as_deref().unwrap_or_default(),
+            )),
             accepted: AtomicU64::new(0),
             processing: AtomicI32::new(0),
             max_processing: conf.max_processing.unwrap_or_default(),
@@ -219,6 +237,7 @@ impl Location {
                 .client_max_body_size
                 .unwrap_or_default()
                 .as_u64() as usize,
+            slo,
         };
         debug!("create a new location, {location:?}");
 
@@ -386,6 +405,18 @@ impl Location {
             }
         }
     }
+    /// Get the plugins of location sorted by priority.
+    #[inline]
+    fn get_sorted_plugins(&self) -> Option<Arc<SortedPlugins>> {
+        let plugins = self.plugins.as_ref()?;
+        let sorted_plugins = self.sorted_plugins.load_full();
+        if !sorted_plugins.is_outdated() {
+            return Some(sorted_plugins);
+        }
+        let sorted_plugins = Arc::new(get_sorted_plugins(plugins));
+        self.sorted_plugins.store(sorted_plugins.clone());
+        Some(sorted_plugins)
+    }
     /// Run request plugins, if return Ok(true), the request will be done.
     #[inline]
     pub async fn handle_request_plugin(
@@ -394,22 +425,24 @@ impl Location {
         session: &mut Session,
         ctx: &mut State,
     ) -> pingora::Result<bool> {
-        let Some(plugins) = self.plugins.as_ref() else {
+        let Some(sorted_plugins) = self.get_sorted_plugins() else {
             return Ok(false);
         };
 
-        for name in plugins.iter() {
-            if let Some(plugin) = get_plugin(name) {
-                debug!(name, step = step.to_string(), "handle request plugin");
-                let result = plugin.handle_request(step, session, ctx).await?;
-                if let Some(resp) = result {
-                    // ignore http response status >= 900
-                    if resp.status.as_u16() < 900 {
-                        ctx.status = Some(resp.status);
-                        resp.send(session).await?;
-                    }
-                    return Ok(true);
+        for (name, plugin) in sorted_plugins.plugins.iter() {
+            debug!(
+                name = name.as_str(),
+                step = step.to_string(),
+                "handle request plugin"
+            );
+            let result = plugin.handle_request(step, session, ctx).await?;
+            if let Some(resp) = result {
+                // ignore http response status >= 900
+                if resp.status.as_u16() < 900 {
+                    ctx.status = Some(resp.status);
+                    resp.send(session).await?;
                 }
+                return Ok(true);
             }
         }
         Ok(false)
@@ -423,16 +456,39 @@ impl Location {
         ctx: &mut State,
         upstream_response: &mut ResponseHeader,
     ) -> pingora::Result<()> {
-        let Some(plugins) = self.plugins.as_ref() else {
+        let Some(sorted_plugins) = self.get_sorted_plugins() else {
             return Ok(());
         };
-        for name in plugins.iter() {
-            if let Some(plugin) = get_plugin(name) {
-                debug!(name, step = step.to_string(), "handle response plugin");
-                plugin
-                    .handle_response(step, session, ctx, upstream_response)
-                    .await?;
-            }
+        for (name, plugin) in sorted_plugins.plugins.iter() {
+            debug!(
+                name = name.as_str(),
+                step = step.to_string(),
+                "handle response plugin"
+            );
+            plugin
+                .handle_response(step, session, ctx, upstream_response)
+                .await?;
+        }
+        Ok(())
+    }
+    /// Run response body plugins for each chunk of body.
+    #[inline]
+    pub fn handle_response_body_plugin(
+        &self,
+        session: &mut Session,
+        ctx: &mut State,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+    ) -> pingora::Result<()> {
+        let Some(sorted_plugins) = self.get_sorted_plugins() else {
+            return Ok(());
+        };
+        for (name, plugin) in sorted_plugins.plugins.iter() {
+            debug!(
+                name = name.as_str(),
+                end_of_stream, "handle response body plugin"
+            );
+            plugin.handle_response_body(session, ctx, body, end_of_stream)?;
         }
         Ok(())
     }
@@ -449,6 +505,29 @@ pub fn get_location(name: &str) -> Option<Arc<Location>> {
     LOCATION_MAP.load().get(name).cloned()
 }
 
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LocationStats {
+    pub processing: i32,
+    pub accepted: u64,
+}
+
+/// Get the processing and accepted count of all locations.
+pub fn get_locations_stats() -> HashMap<String, LocationStats> {
+    LOCATION_MAP
+        .load()
+        .iter()
+        .map(|(name, lo)| {
+            (
+                name.to_string(),
+                LocationStats {
+                    processing: lo.processing.load(Ordering::Relaxed),
+                    accepted: lo.accepted.load(Ordering::Relaxed),
+                },
+            )
+        })
+        .collect()
+}
+
 pub fn try_init_locations(
     confs: &HashMap<String, LocationConf>,
 ) -> Result<Vec<String>> {
@@ -475,6 +554,7 @@ mod tests {
     use crate::config::{LocationConf, PluginStep};
     use crate::plugin::initialize_test_plugins;
     use crate::state::State;
+    use bytes::Bytes;
     use bytesize::ByteSize;
     use http::Method;
     use pingora::http::{RequestHeader, ResponseHeader};
@@ -780,5 +860,16 @@ mod tests {
             r###"{"x-service": "1", "x-service": "2", "x-server": "abc", "x-response-id": "123"}"###,
             format!("{:?}", upstream_response.headers)
         );
+
+        // the body is not changed by plugins without body hook
+        let mut body = Some(Bytes::from_static(b"pingap"));
+        lo.handle_response_body_plugin(
+            &mut session,
+            &mut State::default(),
+            &mut body,
+            true,
+        )
+        .unwrap();
+        assert_eq!(b"pingap", body.unwrap().as_ref());
     }
 }
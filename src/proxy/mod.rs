@@ -12,7 +12,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod body_validator;
+mod check;
 mod dynamic_certificate;
+mod ewma;
+mod keepalive;
 mod location;
 mod logger;
 mod server;
@@ -23,13 +27,18 @@ mod upstream;
 #[allow(unused_imports)]
 pub use location::Location;
 
+pub use body_validator::BodyValidator;
+pub use check::{check_candidate, check_full, check_staged};
 pub use dynamic_certificate::{
     get_certificate_info_list, try_update_certificates,
 };
-pub use location::try_init_locations;
+pub use location::{get_locations_stats, try_init_locations, LocationStats};
 pub use logger::Parser;
 pub use server::*;
 pub use server_conf::ServerConf;
 pub use upstream::{
+    get_upstreams_checked_at, get_upstreams_ewma_stats,
+    get_upstreams_healthy_status, get_upstreams_stats,
     new_upstream_health_check_task, try_init_upstreams, try_update_upstreams,
+    UpstreamHealthyStatus, UpstreamStats,
 };
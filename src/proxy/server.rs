@@ -12,24 +12,35 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use super::body_validator::BodyValidator;
 use super::dynamic_certificate::{GlobalCertificate, TlsSettingParams};
+use super::keepalive::KeepalivePolicy;
 use super::logger::Parser;
 use super::upstream::get_upstream;
 use super::ServerConf;
+#[cfg(feature = "acme")]
 use crate::acme::handle_lets_encrypt;
+use crate::cache::{
+    add_prefetch, get_cache_variance, is_size_admitted, is_vary_cacheable,
+    is_zstd_accepted, PrefetchItem, ACCEPT_ZSTD_TAG, NOT_ADMITTED,
+};
 use crate::config;
 use crate::config::PluginStep;
-use crate::http_extra::{HttpResponse, HTTP_HEADER_NAME_X_REQUEST_ID};
-#[cfg(feature = "full")]
+use crate::http_extra::{
+    HttpResponse, HTTP_HEADER_NAME_X_PINGAP_VIA, HTTP_HEADER_NAME_X_REQUEST_ID,
+};
+#[cfg(feature = "otel")]
 use crate::otel;
 use crate::plugin::{get_plugin, ADMIN_SERVER_PLUGIN};
 use crate::proxy::location::get_location;
 use crate::service::SimpleServiceTaskFuture;
-#[cfg(feature = "full")]
+#[cfg(feature = "otel")]
 use crate::state::OtelTracer;
-use crate::state::{accept_request, end_request};
-use crate::state::{get_cache_key, CompressionStat, State};
-#[cfg(feature = "full")]
+#[cfg(feature = "metrics")]
+use crate::state::DOWNSTREAM_CONNECTION_CLOSED;
+use crate::state::{accept_request, end_request, record_slo, record_traffic};
+use crate::state::{get_cache_key, get_hostname, CompressionStat, State};
+#[cfg(feature = "metrics")]
 use crate::state::{new_prometheus, new_prometheus_push_service, Prometheus};
 use crate::util;
 use ahash::AHashMap;
@@ -38,21 +49,23 @@ use async_trait::async_trait;
 use bytes::{Bytes, BytesMut};
 use http::StatusCode;
 use once_cell::sync::Lazy;
-#[cfg(feature = "full")]
+#[cfg(feature = "otel")]
 use opentelemetry::{
     global,
     trace::{Span, SpanKind, Tracer},
     KeyValue,
 };
-#[cfg(feature = "full")]
+#[cfg(feature = "otel")]
 use opentelemetry_http::HeaderExtractor;
 use pingora::apps::HttpServerOptions;
 use pingora::cache::cache_control::CacheControl;
 use pingora::cache::cache_control::DirectiveValue;
 use pingora::cache::cache_control::InterpretCacheControl;
 use pingora::cache::filters::resp_cacheable;
+use pingora::cache::key::{CacheHashKey, HashBinary};
 use pingora::cache::{
-    CacheKey, CacheMetaDefaults, NoCacheReason, RespCacheable,
+    CacheKey, CacheMeta, CacheMetaDefaults, CachePhase, NoCacheReason,
+    RespCacheable,
 };
 use pingora::http::{RequestHeader, ResponseHeader};
 use pingora::listeners::TcpSocketOptions;
@@ -70,11 +83,12 @@ use pingora::server::configuration;
 use pingora::services::listening::Service;
 use pingora::upstreams::peer::{HttpPeer, Peer};
 use scopeguard::defer;
+use sha2::{Digest as _, Sha256};
 use snafu::Snafu;
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 use tracing::{debug, error, info};
 
 #[derive(Debug, Snafu)]
@@ -134,6 +148,45 @@ fn get_server_locations(name: &str) -> Option<Arc<Vec<String>>> {
     LOCATION_MAP.load().get(name).cloned()
 }
 
+/// Get the tcp socket options of listen addr, the ipv6 only flag
+/// is only set for ipv6 addr, and `[::]` is dual-stack by default.
+fn get_tcp_socket_options(
+    addr: &str,
+    tcp_socket_options: Option<TcpSocketOptions>,
+    ipv6_only: Option<bool>,
+) -> Option<TcpSocketOptions> {
+    let is_ipv6 = addr
+        .parse::<std::net::SocketAddr>()
+        .map(|addr| addr.is_ipv6())
+        .unwrap_or_default();
+    if !is_ipv6 {
+        return tcp_socket_options;
+    }
+    let mut opts = tcp_socket_options.unwrap_or_default();
+    opts.ipv6_only = Some(ipv6_only.unwrap_or_default());
+    Some(opts)
+}
+
+/// Get the marker of server, it is appended to `X-Pingap-Via` header
+/// of upstream request. The hostname is hashed to avoid leaking.
+fn get_via_marker(hostname: &str, name: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{hostname}/{name}").as_bytes());
+    let hash = hex::encode(hasher.finalize());
+    format!("pingap-{}", &hash[..12])
+}
+
+/// Check whether the request has been proxied by this server.
+fn is_loop_detected(header: &RequestHeader, via_marker: &str) -> bool {
+    header
+        .headers
+        .get_all(&*HTTP_HEADER_NAME_X_PINGAP_VIA)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|value| value.trim() == via_marker)
+}
+
 pub struct Server {
     name: String,
     admin: bool,
@@ -147,16 +200,21 @@ pub struct Server {
     tls_ciphersuites: Option<String>,
     tls_min_version: Option<String>,
     tls_max_version: Option<String>,
+    tls_client_ca: Option<String>,
     enabled_h2: bool,
     lets_encrypt_enabled: bool,
     global_certificates: bool,
     tcp_socket_options: Option<TcpSocketOptions>,
-    #[cfg(feature = "full")]
+    ipv6_only: Option<bool>,
+    // the marker of server for loop detection
+    via_marker: String,
+    keepalive: Option<KeepalivePolicy>,
+    #[cfg(feature = "metrics")]
     prometheus: Option<Arc<Prometheus>>,
     prometheus_push_mode: bool,
-    #[cfg(feature = "full")]
+    #[cfg(feature = "metrics")]
     prometheus_metrics: String,
-    #[cfg(feature = "full")]
+    #[cfg(feature = "otel")]
     enabled_otel: bool,
     modules: Option<Vec<String>>,
 }
@@ -190,7 +248,7 @@ impl Server {
             };
         let prometheus_metrics =
             conf.prometheus_metrics.clone().unwrap_or_default();
-        #[cfg(feature = "full")]
+        #[cfg(feature = "metrics")]
         let prometheus = if prometheus_metrics.is_empty() {
             None
         } else {
@@ -212,17 +270,25 @@ impl Server {
             tls_ciphersuites: conf.tls_ciphersuites.clone(),
             tls_min_version: conf.tls_min_version.clone(),
             tls_max_version: conf.tls_max_version.clone(),
+            tls_client_ca: conf.tls_client_ca.clone(),
             threads: conf.threads,
             lets_encrypt_enabled: false,
             global_certificates: conf.global_certificates,
             enabled_h2: conf.enabled_h2,
             tcp_socket_options,
+            ipv6_only: conf.ipv6_only,
+            via_marker: get_via_marker(get_hostname(), &conf.name),
+            keepalive: KeepalivePolicy::new(
+                conf.keepalive_requests,
+                conf.keepalive_timeout,
+                conf.keepalive_close_percentage,
+            ),
             prometheus_push_mode: prometheus_metrics.contains("://"),
-            #[cfg(feature = "full")]
+            #[cfg(feature = "otel")]
             enabled_otel: conf.otlp_exporter.is_some(),
-            #[cfg(feature = "full")]
+            #[cfg(feature = "metrics")]
             prometheus_metrics,
-            #[cfg(feature = "full")]
+            #[cfg(feature = "metrics")]
             prometheus,
             modules: conf.modules.clone(),
         };
@@ -240,7 +306,7 @@ impl Server {
             return None;
         }
         cfg_if::cfg_if! {
-            if #[cfg(feature = "full")] {
+            if #[cfg(feature = "metrics")] {
                 let Some(prometheus) = &self.prometheus else {
                     return None;
                 };
@@ -271,6 +337,7 @@ impl Server {
     ) -> Result<ServerServices> {
         let addr = self.addr.clone();
         let tcp_socket_options = self.tcp_socket_options.clone();
+        let ipv6_only = self.ipv6_only;
 
         let name = self.name.clone();
         let mut dynamic_cert = None;
@@ -306,6 +373,7 @@ impl Server {
         let ciphersuites = self.tls_ciphersuites.clone();
         let tls_min_version = self.tls_min_version.clone();
         let tls_max_version = self.tls_max_version.clone();
+        let tls_client_ca = self.tls_client_ca.clone();
         let mut lb = http_proxy_service(conf, self);
         // use h2c if not tls and enable http2
         if !is_tls && enabled_h2 {
@@ -318,6 +386,11 @@ impl Server {
         lb.threads = threads;
         // support listen multi address
         for addr in addr.split(',') {
+            let tcp_socket_options = get_tcp_socket_options(
+                addr,
+                tcp_socket_options.clone(),
+                ipv6_only,
+            );
             // tls
             if let Some(dynamic_cert) = &dynamic_cert {
                 let tls_settings = dynamic_cert
@@ -328,6 +401,7 @@ impl Server {
                         ciphersuites: ciphersuites.clone(),
                         tls_min_version: tls_min_version.clone(),
                         tls_max_version: tls_max_version.clone(),
+                        tls_client_ca: tls_client_ca.clone(),
                     })
                     .map_err(|e| Error::Common {
                         category: "tls".to_string(),
@@ -338,8 +412,8 @@ impl Server {
                     tcp_socket_options.clone(),
                     tls_settings,
                 );
-            } else if let Some(opt) = &tcp_socket_options {
-                lb.add_tcp_with_settings(addr, opt.clone());
+            } else if let Some(opt) = tcp_socket_options {
+                lb.add_tcp_with_settings(addr, opt);
             } else {
                 lb.add_tcp(addr);
             }
@@ -363,6 +437,49 @@ impl Server {
         }
         Ok(false)
     }
+    /// Apply the keep-alive policy of downstream http1 connection,
+    /// the connection is closed after the response if it is chosen.
+    fn apply_keepalive_policy(
+        &self,
+        session: &mut Session,
+        ctx: &State,
+        upstream_response: &mut ResponseHeader,
+    ) {
+        let Some(keepalive) = &self.keepalive else {
+            return;
+        };
+        if session.is_http2() {
+            return;
+        }
+        // the connection of client will be closed
+        let req_header = session.req_header();
+        if req_header.version != http::Version::HTTP_11
+            || req_header
+                .headers
+                .get(http::header::CONNECTION)
+                .map(|value| value.as_bytes().eq_ignore_ascii_case(b"close"))
+                .unwrap_or_default()
+        {
+            return;
+        }
+        let Some(policy) = keepalive.should_close(ctx.connection_id) else {
+            if let Some(idle_timeout) = keepalive.idle_timeout {
+                session.set_keepalive(Some(idle_timeout.as_secs()));
+            }
+            return;
+        };
+        debug!(
+            connection_id = ctx.connection_id,
+            policy, "downstream connection will be closed"
+        );
+        session.set_keepalive(None);
+        let _ =
+            upstream_response.insert_header(http::header::CONNECTION, "close");
+        #[cfg(feature = "metrics")]
+        DOWNSTREAM_CONNECTION_CLOSED
+            .with_label_values(&[&self.name, policy])
+            .inc();
+    }
 }
 
 #[derive(Debug, Default)]
@@ -376,6 +493,100 @@ struct DigestDeailt {
 }
 
 #[inline]
+/// Get the cache status of cache phase, it's used for access log and metrics.
+fn get_cache_status(phase: CachePhase) -> Option<&'static str> {
+    match phase {
+        CachePhase::Hit => Some("hit"),
+        CachePhase::Miss => Some("miss"),
+        CachePhase::Stale => Some("stale"),
+        CachePhase::Expired => Some("expired"),
+        CachePhase::Revalidated | CachePhase::RevalidatedNoCache(_) => {
+            Some("revalidated")
+        },
+        CachePhase::Bypass => Some("bypass"),
+        _ => None,
+    }
+}
+
+/// Set the conditional headers from the stale cache for revalidation,
+/// the upstream responds 304 without body if the cache isn't modified,
+/// then the ttl and headers of cache are refreshed.
+fn set_revalidate_headers(
+    req: &mut RequestHeader,
+    meta: &CacheMeta,
+) -> pingora::Result<()> {
+    for (name, conditional) in [
+        (http::header::ETAG, http::header::IF_NONE_MATCH),
+        (http::header::LAST_MODIFIED, http::header::IF_MODIFIED_SINCE),
+    ] {
+        // the conditional header of client is replaced,
+        // it should be based on the cache
+        if let Some(value) = meta.headers().get(&name) {
+            req.insert_header(conditional, value.clone())?;
+        } else {
+            req.remove_header(&conditional);
+        }
+    }
+    Ok(())
+}
+
+/// Create the prefetch item of cache, the request is sent to the listener
+/// of server, so only the plain http request is supported.
+fn new_prefetch_item(
+    session: &Session,
+    fresh_until: SystemTime,
+    min_hits: u32,
+) -> Option<PrefetchItem> {
+    let req = session.req_header();
+    if req.method != http::Method::GET {
+        return None;
+    }
+    let digest = session.digest()?;
+    if digest.ssl_digest.is_some() {
+        return None;
+    }
+    let addr = session.server_addr()?.as_inet()?;
+    let path = req.uri.path_and_query()?;
+    let headers = req
+        .headers
+        .iter()
+        .filter(|(name, _)| {
+            ![
+                http::header::CONNECTION,
+                http::header::CONTENT_LENGTH,
+                http::header::COOKIE,
+                http::header::AUTHORIZATION,
+                http::header::IF_NONE_MATCH,
+                http::header::IF_MODIFIED_SINCE,
+            ]
+            .contains(name)
+        })
+        .map(|(name, value)| {
+            (
+                name.to_string(),
+                value.to_str().unwrap_or_default().to_string(),
+            )
+        })
+        .collect();
+    Some(PrefetchItem::new(
+        format!("http://{addr}{path}"),
+        headers,
+        fresh_until,
+        min_hits,
+    ))
+}
+
+/// Get the value of Cache-Status header(RFC 9211).
+fn get_cache_status_value(status: &str) -> String {
+    match status {
+        "hit" => "pingap; hit".to_string(),
+        "miss" => "pingap; fwd=uri-miss".to_string(),
+        "stale" => "pingap; hit; detail=stale".to_string(),
+        "revalidated" | "expired" => "pingap; fwd=stale".to_string(),
+        _ => format!("pingap; fwd={status}"),
+    }
+}
+
 fn get_digest_detail(digest: &Digest) -> DigestDeailt {
     let get_established = |value: Option<&Option<TimingDigest>>| -> u64 {
         value
@@ -450,12 +661,12 @@ impl ProxyHttp for Server {
         debug!("--> early request filter");
         defer!(debug!("<-- early request filter"););
 
-        if let Some(stream) = session.stream() {
-            ctx.connection_id = stream.id() as usize;
-        }
+        let stream_id = session.stream().map(|stream| stream.id() as usize);
         // get digest of timing and tls
+        let mut tcp_established = 0;
         if let Some(digest) = session.digest() {
             let digest_detail = get_digest_detail(digest);
+            tcp_established = digest_detail.tcp_established;
             ctx.connection_time = digest_detail.connection_time;
             ctx.connection_reused = digest_detail.connection_reused;
 
@@ -471,6 +682,10 @@ impl ProxyHttp for Server {
             ctx.tls_cipher = digest_detail.tls_cipher;
             ctx.tls_version = digest_detail.tls_version;
         };
+        if let Some(stream_id) = stream_id {
+            ctx.connection_id =
+                util::new_connection_id(stream_id, tcp_established);
+        }
         accept_request();
 
         ctx.processing = self.processing.fetch_add(1, Ordering::Relaxed) + 1;
@@ -493,7 +708,7 @@ impl ProxyHttp for Server {
 
         // enable open telemtery
 
-        #[cfg(feature = "full")]
+        #[cfg(feature = "otel")]
         if self.enabled_otel {
             if let Some(tracer) = otel::new_tracer(&self.name) {
                 let cx = global::get_text_map_propagator(|propagator| {
@@ -532,7 +747,7 @@ impl ProxyHttp for Server {
             }
         }
         // set perometheus stats
-        #[cfg(feature = "full")]
+        #[cfg(feature = "metrics")]
         if let Some(prom) = &self.prometheus {
             let location_name =
                 ctx.location.as_ref().map_or("", |item| &item.name);
@@ -590,16 +805,33 @@ impl ProxyHttp for Server {
         }
         // only enable for http 80
         if self.lets_encrypt_enabled {
-            let done = handle_lets_encrypt(session, ctx).await?;
-            if done {
+            #[cfg(feature = "acme")]
+            if handle_lets_encrypt(session, ctx).await? {
                 return Ok(true);
             }
         }
 
+        // the request is proxied to this server again
+        if is_loop_detected(session.req_header(), &self.via_marker) {
+            error!(
+                server = self.name,
+                marker = self.via_marker,
+                "proxy loop detected"
+            );
+            let resp = HttpResponse {
+                status: StatusCode::LOOP_DETECTED,
+                body: Bytes::from_static(b"Loop detected"),
+                ..Default::default()
+            };
+            ctx.status = Some(resp.status);
+            resp.send(session).await?;
+            return Ok(true);
+        }
+
         let header = session.req_header_mut();
 
         // prometheus pull metric
-        #[cfg(feature = "full")]
+        #[cfg(feature = "metrics")]
         if !self.prometheus_push_mode
             && self.prometheus.is_some()
             && header.uri.path() == self.prometheus_metrics
@@ -672,7 +904,7 @@ impl ProxyHttp for Server {
             location_name.clone_from(&location.name);
             if let Some(up) = get_upstream(&location.upstream) {
                 ctx.upstream_connected = up.connected();
-                #[cfg(feature = "full")]
+                #[cfg(feature = "otel")]
                 if let Some(tracer) = &ctx.otel_tracer {
                     let name = format!("upstream.{}", &location.upstream);
                     let mut span = tracer.new_upstream_span(&name);
@@ -682,7 +914,15 @@ impl ProxyHttp for Server {
                     ));
                     ctx.upstream_span = Some(span);
                 }
-                up.new_http_peer(session, ctx)
+                if !ctx.upstream_address.is_empty() {
+                    // the request is retried, release the previous peer
+                    up.peer_completed(&ctx.upstream_address, None);
+                }
+                let peer = up.new_http_peer(session, ctx);
+                if let Some(peer) = &peer {
+                    ctx.upstream_address = peer.address().to_string();
+                }
+                peer
             } else {
                 None
             }
@@ -757,13 +997,36 @@ impl ProxyHttp for Server {
         if let Some(location) = &ctx.location {
             location.set_append_proxy_headers(session, ctx, upstream_response);
         }
+        upstream_response.append_header(
+            HTTP_HEADER_NAME_X_PINGAP_VIA.clone(),
+            &self.via_marker,
+        )?;
+        // revalidate the expired cache
+        if session.cache.enabled()
+            && matches!(
+                session.cache.phase(),
+                CachePhase::Stale | CachePhase::Expired
+            )
+        {
+            if let Some(meta) = session.cache.maybe_cache_meta() {
+                set_revalidate_headers(upstream_response, meta)?;
+            }
+        }
+        // only the range of slice is requested from upstream
+        if let Some((start, end)) = ctx.cache_slice {
+            upstream_response.remove_header(&http::header::IF_RANGE);
+            upstream_response.insert_header(
+                http::header::RANGE,
+                format!("bytes={start}-{end}"),
+            )?;
+        }
         Ok(())
     }
     async fn request_body_filter(
         &self,
         _session: &mut Session,
         body: &mut Option<Bytes>,
-        _end_of_stream: bool,
+        end_of_stream: bool,
         ctx: &mut Self::CTX,
     ) -> pingora::Result<()>
     where
@@ -771,8 +1034,20 @@ impl ProxyHttp for Server {
     {
         debug!("--> request body filter");
         defer!(debug!("<-- request body filter"););
+        // the body has been read(and maybe modified) by plugin
+        if end_of_stream {
+            if let Some(buf) = ctx.request_body.take() {
+                *body = Some(buf);
+            }
+        }
+        if let Some(guard) = ctx.websocket_guard.as_mut() {
+            if let Some(buf) = body.take() {
+                *body = Some(guard.inspect(buf, true));
+            }
+        }
         if let Some(buf) = body {
             ctx.payload_size += buf.len();
+            ctx.upstream_bytes_sent += buf.len();
             if let Some(location) = &ctx.location {
                 location.client_body_size_limit(ctx).map_err(|e| {
                     util::new_internal_error(413, e.to_string())
@@ -788,15 +1063,64 @@ impl ProxyHttp for Server {
     ) -> pingora::Result<CacheKey> {
         debug!("--> cache key callback");
         defer!(debug!("<-- cache key callback"););
-        let key = get_cache_key(
+        let mut key = get_cache_key(
             ctx,
             session.req_header().method.as_ref(),
             &session.req_header().uri,
         );
+        // the user tag isn't a part of cache hash,
+        // it's used to pass through the compressed body on hit
+        if is_zstd_accepted(&session.req_header().headers) {
+            key.user_tag = ACCEPT_ZSTD_TAG.to_string();
+        }
         debug!(key = format!("{key:?}"), "cache key callback");
         Ok(key)
     }
 
+    async fn cache_hit_filter(
+        &self,
+        session: &Session,
+        meta: &CacheMeta,
+        ctx: &mut Self::CTX,
+    ) -> pingora::Result<bool> {
+        // the cache is forced to expire by prefetch request
+        if ctx.cache_prefetching {
+            return Ok(true);
+        }
+        if let Some(prefetch) = ctx.cache_prefetch {
+            let fresh_until = meta.fresh_until();
+            let remaining = fresh_until
+                .duration_since(SystemTime::now())
+                .unwrap_or_default();
+            // the hot cache will be expired soon
+            if !remaining.is_zero() && remaining <= prefetch {
+                if let Some(item) = new_prefetch_item(
+                    session,
+                    fresh_until,
+                    ctx.cache_prefetch_min_hits,
+                ) {
+                    let key = get_cache_key(
+                        ctx,
+                        session.req_header().method.as_ref(),
+                        &session.req_header().uri,
+                    );
+                    add_prefetch(&key.combined(), item);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    fn cache_vary_filter(
+        &self,
+        meta: &CacheMeta,
+        ctx: &mut Self::CTX,
+        req: &RequestHeader,
+    ) -> Option<HashBinary> {
+        let vary = ctx.cache_vary.as_ref()?;
+        get_cache_variance(meta.headers(), &req.headers, vary)
+    }
+
     fn response_cache_filter(
         &self,
         _session: &Session,
@@ -805,6 +1129,53 @@ impl ProxyHttp for Server {
     ) -> pingora::Result<RespCacheable> {
         debug!("--> response cache filter");
         defer!(debug!("<-- response cache filter"););
+        // the response isn't admitted to storage now,
+        // it may be cached by the following requests
+        let size_admitted = ctx.cache_admission_size.map_or(true, |max_size| {
+            resp.headers
+                .get(http::header::CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<usize>().ok())
+                .map_or(true, |size| is_size_admitted(size, max_size))
+        });
+        if ctx.cache_not_admitted || !size_admitted {
+            return Ok(RespCacheable::Uncacheable(NoCacheReason::Custom(
+                NOT_ADMITTED,
+            )));
+        }
+        // the response varied by other headers can't be cached
+        if let Some(vary) = &ctx.cache_vary {
+            if !is_vary_cacheable(&resp.headers, vary) {
+                return Ok(RespCacheable::Uncacheable(
+                    NoCacheReason::OriginNotCache,
+                ));
+            }
+        }
+        // the negative response is cached with its own short ttl,
+        // only no-store and private of upstream are respected
+        let status = resp.status.as_u16();
+        if let Some((_, ttl)) = ctx
+            .cache_negative_ttls
+            .as_ref()
+            .and_then(|ttls| ttls.iter().find(|(code, _)| *code == status))
+        {
+            let uncacheable = CacheControl::from_resp_headers(resp)
+                .map(|c| c.no_store() || c.private())
+                .unwrap_or_default();
+            if uncacheable {
+                return Ok(RespCacheable::Uncacheable(
+                    NoCacheReason::OriginNotCache,
+                ));
+            }
+            let now = SystemTime::now();
+            return Ok(RespCacheable::Cacheable(CacheMeta::new(
+                now + *ttl,
+                now,
+                0,
+                0,
+                resp.clone(),
+            )));
+        }
         if ctx.check_cache_control
             && resp.headers.get("Cache-Control").is_none()
         {
@@ -827,6 +1198,23 @@ impl ProxyHttp for Server {
                     ));
                 }
             }
+            // force the stale durations
+            for (key, value) in [
+                ("stale-while-revalidate", ctx.cache_stale_while_revalidate),
+                ("stale-if-error", ctx.cache_stale_if_error),
+            ] {
+                if let Some(d) = value {
+                    c.directives.insert(
+                        key.to_string(),
+                        Some(DirectiveValue(
+                            itoa::Buffer::new()
+                                .format(d.as_secs())
+                                .as_bytes()
+                                .to_vec(),
+                        )),
+                    );
+                }
+            }
             // adjust cache ttl
             if let Some(d) = ctx.cache_max_ttl {
                 if c.fresh_sec().unwrap_or_default() > d.as_secs() as u32 {
@@ -844,12 +1232,55 @@ impl ProxyHttp for Server {
             }
         }
 
-        Ok(resp_cacheable(
-            cc.as_ref(),
-            resp.clone(),
-            false,
-            &META_DEFAULTS,
-        ))
+        // the stale durations are used as defaults without cache control
+        let defaults = if ctx.cache_stale_while_revalidate.is_some()
+            || ctx.cache_stale_if_error.is_some()
+        {
+            let get_secs = |value: Option<Duration>| {
+                value.map(|d| d.as_secs() as u32).unwrap_or(1)
+            };
+            CacheMetaDefaults::new(
+                |_| Some(1),
+                get_secs(ctx.cache_stale_while_revalidate),
+                get_secs(ctx.cache_stale_if_error),
+            )
+        } else {
+            META_DEFAULTS
+        };
+
+        let cacheable =
+            resp_cacheable(cc.as_ref(), resp.clone(), false, &defaults);
+        // the default ttl is used if upstream doesn't set the freshness
+        if let (Some(ttl), RespCacheable::Cacheable(meta)) =
+            (ctx.cache_ttl, &cacheable)
+        {
+            let fresh_sec = cc.as_ref().and_then(|c| c.fresh_sec());
+            if fresh_sec.is_none()
+                && resp.headers.get(http::header::EXPIRES).is_none()
+            {
+                let now = SystemTime::now();
+                return Ok(RespCacheable::Cacheable(CacheMeta::new(
+                    now + ttl,
+                    now,
+                    meta.stale_while_revalidate_sec(),
+                    meta.stale_if_error_sec(),
+                    meta.response_header().clone(),
+                )));
+            }
+        }
+        Ok(cacheable)
+    }
+
+    fn should_serve_stale(
+        &self,
+        _session: &mut Session,
+        _ctx: &mut Self::CTX,
+        error: Option<&pingora::Error>,
+    ) -> bool {
+        // it's only called if the stale is allowed by cache meta,
+        // serve stale while revalidating without error,
+        // or the upstream fails(including timeout)
+        error.map_or(true, |e| e.esource() == &pingora::ErrorSource::Upstream)
     }
 
     async fn response_filter(
@@ -881,6 +1312,20 @@ impl ProxyHttp for Server {
                     .insert_header("X-Cache-Lock", format!("{ms}ms"));
                 ctx.cache_lock_time = Some(ms);
             }
+            ctx.cache_status = get_cache_status(session.cache.phase());
+            if let Some(status) = ctx.cache_status {
+                let _ = upstream_response.insert_header(
+                    "Cache-Status",
+                    get_cache_status_value(status),
+                );
+                let _ = upstream_response.append_header(
+                    "Server-Timing",
+                    format!(
+                        "cache;desc={status};dur={}",
+                        ctx.cache_lookup_time.unwrap_or_default()
+                    ),
+                );
+            }
         }
 
         if let Some(location) = &ctx.location {
@@ -894,6 +1339,7 @@ impl ProxyHttp for Server {
                 )
                 .await?;
         }
+        self.apply_keepalive_policy(session, ctx, upstream_response);
 
         Ok(())
     }
@@ -915,23 +1361,63 @@ impl ProxyHttp for Server {
             let _ = upstream_response
                 .insert_header(HTTP_HEADER_NAME_X_REQUEST_ID.clone(), id);
         }
+        // the partial content of slice is cached as the whole object,
+        // the content range is kept for the total size
+        if ctx.cache_slice.is_some()
+            && upstream_response.status == StatusCode::PARTIAL_CONTENT
+        {
+            let _ = upstream_response.set_status(StatusCode::OK);
+        }
         ctx.upstream_processing_time =
             util::get_latency(&ctx.upstream_processing_time);
+        if let Some(up) = ctx
+            .location
+            .as_ref()
+            .and_then(|location| get_upstream(&location.upstream))
+        {
+            let (validate_content_length, validate_digest) =
+                up.body_validation();
+            if validate_content_length || validate_digest {
+                ctx.upstream_body_validator = BodyValidator::new(
+                    upstream_response,
+                    validate_content_length,
+                    validate_digest,
+                );
+            }
+        }
     }
 
     fn upstream_response_body_filter(
         &self,
         _session: &mut Session,
-        _body: &mut Option<bytes::Bytes>,
+        body: &mut Option<bytes::Bytes>,
         end_of_stream: bool,
         ctx: &mut Self::CTX,
     ) -> pingora::Result<()> {
         debug!("--> upstream response body filter");
         defer!(debug!("<-- upstream response body filter"););
+        if let Some(data) = body {
+            ctx.upstream_bytes_received += data.len();
+        }
+        if let Some(validator) = ctx.upstream_body_validator.as_mut() {
+            if let Some(data) = body {
+                validator.update(data);
+            }
+            if end_of_stream {
+                if let Err(message) = validator.finish() {
+                    error!(
+                        upstream = ctx.upstream_address,
+                        message, "upstream body validation fail"
+                    );
+                    // abort the response instead of sending a truncated body
+                    return Err(util::new_internal_error(502, message));
+                }
+            }
+        }
         if end_of_stream {
             ctx.upstream_response_time =
                 util::get_latency(&ctx.upstream_response_time);
-            #[cfg(feature = "full")]
+            #[cfg(feature = "otel")]
             if let Some(ref mut span) = ctx.upstream_span.as_mut() {
                 span.set_attributes([
                     KeyValue::new(
@@ -970,7 +1456,7 @@ impl ProxyHttp for Server {
 
     fn response_body_filter(
         &self,
-        _session: &mut Session,
+        session: &mut Session,
         body: &mut Option<Bytes>,
         end_of_stream: bool,
         ctx: &mut Self::CTX,
@@ -980,6 +1466,11 @@ impl ProxyHttp for Server {
     {
         debug!("--> response body filter");
         defer!(debug!("<-- response body filter"););
+        if let Some(guard) = ctx.websocket_guard.as_mut() {
+            if let Some(buf) = body.take() {
+                *body = Some(guard.inspect(buf, false));
+            }
+        }
         // set modify response body
         if let Some(modify) = &ctx.modify_response_body {
             if let Some(ref mut buf) = ctx.response_body {
@@ -1002,6 +1493,14 @@ impl ProxyHttp for Server {
                 }
             }
         }
+        if let Some(location) = &ctx.location {
+            location.clone().handle_response_body_plugin(
+                session,
+                ctx,
+                body,
+                end_of_stream,
+            )?;
+        }
 
         Ok(None)
     }
@@ -1105,6 +1604,10 @@ impl ProxyHttp for Server {
             location.sub_processing();
             if let Some(up) = get_upstream(&location.upstream) {
                 ctx.upstream_processing = Some(up.completed());
+                up.peer_completed(
+                    &ctx.upstream_address,
+                    ctx.get_upstream_response_time(),
+                );
             }
         }
         if ctx.status.is_none() {
@@ -1112,7 +1615,23 @@ impl ProxyHttp for Server {
                 ctx.status = Some(header.status);
             }
         }
-        #[cfg(feature = "full")]
+        record_traffic(
+            ctx.status.map(|status| status.as_u16()).unwrap_or_default(),
+            util::now().as_millis() as u64 - ctx.created_at,
+        );
+        if let Some(location) = &ctx.location {
+            if let Some(slo) = &location.slo {
+                record_slo(
+                    &location.name,
+                    slo,
+                    ctx.status
+                        .map(|status| status.as_u16())
+                        .unwrap_or_default(),
+                    util::now().as_millis() as u64 - ctx.created_at,
+                );
+            }
+        }
+        #[cfg(feature = "otel")]
         // enable open telemetry and proxy upstream fail
         if let Some(ref mut span) = ctx.upstream_span.as_mut() {
             span.end();
@@ -1131,12 +1650,12 @@ impl ProxyHttp for Server {
                 }
             }
         }
-        #[cfg(feature = "full")]
+        #[cfg(feature = "metrics")]
         if let Some(prom) = &self.prometheus {
             prom.after(session, ctx);
         }
 
-        #[cfg(feature = "full")]
+        #[cfg(feature = "otel")]
         // open telemetry
         if let Some(ref mut tracer) = ctx.otel_tracer.as_mut() {
             let ip = if let Some(ip) = &ctx.client_ip {
@@ -1170,13 +1689,19 @@ impl ProxyHttp for Server {
 mod tests {
     use super::Server;
     use crate::config::{LocationConf, PingapConf};
-    use crate::proxy::server::get_digest_detail;
+    use crate::proxy::server::{
+        get_cache_status, get_cache_status_value, get_digest_detail,
+        get_tcp_socket_options, get_via_marker, is_loop_detected,
+        set_revalidate_headers,
+    };
     use crate::proxy::{
         try_init_locations, try_init_server_locations, try_init_upstreams,
         Location, ServerConf,
     };
     use crate::state::State;
-    use pingora::http::ResponseHeader;
+    use pingora::cache::{CacheMeta, CachePhase, RespCacheable};
+    use pingora::http::{RequestHeader, ResponseHeader};
+    use pingora::listeners::TcpSocketOptions;
     use pingora::protocols::tls::SslDigest;
     use pingora::protocols::{Digest, TimingDigest};
     use pingora::proxy::{ProxyHttp, Session};
@@ -1187,6 +1712,58 @@ mod tests {
     use std::time::{Duration, SystemTime};
     use tokio_test::io::Builder;
 
+    #[test]
+    fn test_get_cache_status() {
+        assert_eq!(Some("hit"), get_cache_status(CachePhase::Hit));
+        assert_eq!(Some("miss"), get_cache_status(CachePhase::Miss));
+        assert_eq!(Some("stale"), get_cache_status(CachePhase::Stale));
+        assert_eq!(None, get_cache_status(CachePhase::Uninit));
+
+        assert_eq!("pingap; hit", get_cache_status_value("hit"));
+        assert_eq!("pingap; fwd=uri-miss", get_cache_status_value("miss"));
+        assert_eq!("pingap; fwd=bypass", get_cache_status_value("bypass"));
+    }
+
+    #[test]
+    fn test_loop_detection() {
+        let marker = get_via_marker("pingap.local", "web");
+        assert_eq!(19, marker.len());
+        assert_eq!(true, marker.starts_with("pingap-"));
+        assert_ne!(marker, get_via_marker("pingap.local", "api"));
+
+        let mut header = RequestHeader::build("GET", b"/", None).unwrap();
+        assert_eq!(false, is_loop_detected(&header, &marker));
+        header
+            .append_header("X-Pingap-Via", "pingap-000000000000")
+            .unwrap();
+        assert_eq!(false, is_loop_detected(&header, &marker));
+        header
+            .insert_header(
+                "X-Pingap-Via",
+                format!("pingap-000000000000, {marker}"),
+            )
+            .unwrap();
+        assert_eq!(true, is_loop_detected(&header, &marker));
+    }
+
+    #[test]
+    fn test_get_tcp_socket_options() {
+        assert_eq!(
+            true,
+            get_tcp_socket_options("127.0.0.1:3000", None, Some(true))
+                .is_none()
+        );
+        let opts = get_tcp_socket_options("[::]:3000", None, None).unwrap();
+        assert_eq!(Some(false), opts.ipv6_only);
+
+        let mut opts = TcpSocketOptions::default();
+        opts.tcp_fastopen = Some(10);
+        let opts = get_tcp_socket_options("[::]:3000", Some(opts), Some(true))
+            .unwrap();
+        assert_eq!(Some(true), opts.ipv6_only);
+        assert_eq!(Some(10), opts.tcp_fastopen);
+    }
+
     #[test]
     fn test_get_digest_detail() {
         let digest = Digest {
@@ -1293,6 +1870,18 @@ mod tests {
             r#"Ok(CacheKey { namespace: "pingap", primary: "ss:GET:/vicanso/pingap?size=1", primary_bin_override: None, variance: None, user_tag: "" })"#,
             format!("{key:?}")
         );
+
+        let key = server.cache_key_callback(
+            &session,
+            &mut State {
+                cache_slice: Some((0, 1023)),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            r#"Ok(CacheKey { namespace: "", primary: "GET:/vicanso/pingap?size=1:slice:0-1023", primary_bin_override: None, variance: None, user_tag: "" })"#,
+            format!("{key:?}")
+        );
     }
 
     #[tokio::test]
@@ -1373,5 +1962,146 @@ mod tests {
             )
             .unwrap();
         assert_eq!(false, result.is_cacheable());
+
+        let mut upstream_response =
+            ResponseHeader::build_no_case(200, None).unwrap();
+        upstream_response
+            .append_header(
+                "Cache-Control",
+                "max-age=100, stale-while-revalidate=5",
+            )
+            .unwrap();
+        let result = server
+            .response_cache_filter(
+                &session,
+                &upstream_response,
+                &mut State {
+                    cache_stale_while_revalidate: Some(Duration::from_secs(30)),
+                    cache_stale_if_error: Some(Duration::from_secs(60)),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let RespCacheable::Cacheable(meta) = result else {
+            panic!("response should be cacheable");
+        };
+        assert_eq!(30, meta.stale_while_revalidate_sec());
+        assert_eq!(60, meta.stale_if_error_sec());
+
+        // the default ttl without cache control
+        let upstream_response =
+            ResponseHeader::build_no_case(200, None).unwrap();
+        let result = server
+            .response_cache_filter(
+                &session,
+                &upstream_response,
+                &mut State {
+                    cache_ttl: Some(Duration::from_secs(300)),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let RespCacheable::Cacheable(meta) = result else {
+            panic!("response should be cacheable");
+        };
+        assert_eq!(true, meta.fresh_sec() > 290);
+
+        // the negative response is cached with its own ttl
+        let mut upstream_response =
+            ResponseHeader::build_no_case(404, None).unwrap();
+        upstream_response
+            .append_header("Cache-Control", "max-age=3600")
+            .unwrap();
+        let mut ctx = State {
+            check_cache_control: true,
+            cache_negative_ttls: Some(vec![
+                (404, Duration::from_secs(30)),
+                (503, Duration::from_secs(5)),
+            ]),
+            ..Default::default()
+        };
+        let result = server
+            .response_cache_filter(&session, &upstream_response, &mut ctx)
+            .unwrap();
+        let RespCacheable::Cacheable(meta) = result else {
+            panic!("response should be cacheable");
+        };
+        assert_eq!(true, meta.fresh_sec() <= 30);
+        assert_eq!(0, meta.stale_while_revalidate_sec());
+
+        let upstream_response =
+            ResponseHeader::build_no_case(503, None).unwrap();
+        let result = server
+            .response_cache_filter(&session, &upstream_response, &mut ctx)
+            .unwrap();
+        assert_eq!(true, result.is_cacheable());
+
+        let mut upstream_response =
+            ResponseHeader::build_no_case(404, None).unwrap();
+        upstream_response
+            .append_header("Cache-Control", "no-store")
+            .unwrap();
+        let result = server
+            .response_cache_filter(&session, &upstream_response, &mut ctx)
+            .unwrap();
+        assert_eq!(false, result.is_cacheable());
+
+        // the response isn't admitted
+        let mut upstream_response =
+            ResponseHeader::build_no_case(200, None).unwrap();
+        upstream_response
+            .append_header("Cache-Control", "max-age=3600")
+            .unwrap();
+        upstream_response
+            .append_header("Content-Length", "4096")
+            .unwrap();
+        let result = server
+            .response_cache_filter(
+                &session,
+                &upstream_response,
+                &mut State {
+                    cache_not_admitted: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(false, result.is_cacheable());
+        let result = server
+            .response_cache_filter(
+                &session,
+                &upstream_response,
+                &mut State {
+                    cache_admission_size: Some(4096),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(true, result.is_cacheable());
+
+        // 502 isn't configured, no cache control is not cacheable
+        let upstream_response =
+            ResponseHeader::build_no_case(502, None).unwrap();
+        let result = server
+            .response_cache_filter(&session, &upstream_response, &mut ctx)
+            .unwrap();
+        assert_eq!(false, result.is_cacheable());
+    }
+
+    #[test]
+    fn test_set_revalidate_headers() {
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header("ETag", r#""abc""#).unwrap();
+        let meta =
+            CacheMeta::new(SystemTime::now(), SystemTime::now(), 0, 0, resp);
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        req.insert_header("If-None-Match", r#""client""#).unwrap();
+        req.insert_header("If-Modified-Since", "Wed, 21 Oct 2015 07:28:00 GMT")
+            .unwrap();
+        set_revalidate_headers(&mut req, &meta).unwrap();
+        assert_eq!(
+            r#""abc""#,
+            req.headers.get("If-None-Match").unwrap().to_str().unwrap()
+        );
+        assert_eq!(true, req.headers.get("If-Modified-Since").is_none());
     }
 }
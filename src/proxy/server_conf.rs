@@ -15,6 +15,7 @@
 use crate::config::PingapConf;
 use pingora::protocols::l4::ext::TcpKeepalive;
 use std::fmt;
+use std::time::Duration;
 
 static ERROR_TEMPLATE: &str = include_str!("../../error.html");
 
@@ -29,10 +30,15 @@ pub struct ServerConf {
     pub tls_ciphersuites: Option<String>,
     pub tls_min_version: Option<String>,
     pub tls_max_version: Option<String>,
+    pub tls_client_ca: Option<String>,
     pub threads: Option<usize>,
     pub error_template: String,
     pub tcp_keepalive: Option<TcpKeepalive>,
     pub tcp_fastopen: Option<usize>,
+    pub ipv6_only: Option<bool>,
+    pub keepalive_requests: Option<u32>,
+    pub keepalive_timeout: Option<Duration>,
+    pub keepalive_close_percentage: Option<u8>,
     pub global_certificates: bool,
     pub enabled_h2: bool,
     pub prometheus_metrics: Option<String>,
@@ -96,6 +102,7 @@ impl From<PingapConf> for Vec<ServerConf> {
                 tls_ciphersuites: item.tls_ciphersuites.clone(),
                 tls_min_version: item.tls_min_version.clone(),
                 tls_max_version: item.tls_max_version.clone(),
+                tls_client_ca: item.tls_client_ca.clone(),
                 addr: item.addr,
                 access_log: item.access_log,
                 locations: item.locations.unwrap_or_default(),
@@ -106,6 +113,10 @@ impl From<PingapConf> for Vec<ServerConf> {
                 enabled_h2: item.enabled_h2.unwrap_or_default(),
                 tcp_keepalive,
                 tcp_fastopen: item.tcp_fastopen,
+                ipv6_only: item.ipv6_only,
+                keepalive_requests: item.keepalive_requests,
+                keepalive_timeout: item.keepalive_timeout,
+                keepalive_close_percentage: item.keepalive_close_percentage,
                 prometheus_metrics: item.prometheus_metrics,
                 otlp_exporter: item.otlp_exporter.clone(),
                 modules: item.modules.clone(),
@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use super::ewma::{PeakEwma, PeerEwmaStat};
 use crate::config::UpstreamConf;
 use crate::discovery::{
     is_dns_discovery, is_docker_discovery, is_static_discovery,
@@ -34,9 +35,10 @@ use pingora::protocols::l4::ext::TcpKeepalive;
 use pingora::protocols::ALPN;
 use pingora::proxy::Session;
 use pingora::upstreams::peer::{HttpPeer, Tracer, Tracing};
+use serde::Serialize;
 use snafu::Snafu;
 use std::collections::HashMap;
-use std::sync::atomic::{AtomicI32, AtomicU32, Ordering};
+use std::sync::atomic::{AtomicI32, AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 use tracing::{debug, error};
@@ -51,6 +53,8 @@ type Result<T, E = Error> = std::result::Result<T, E>;
 enum SelectionLb {
     RoundRobin(Arc<LoadBalancer<RoundRobin>>),
     Consistent(Arc<LoadBalancer<Consistent>>),
+    // the backends are updated and checked by round robin load balancer
+    PeakEwma(Arc<LoadBalancer<RoundRobin>>, Arc<PeakEwma>),
     Transparent,
 }
 
@@ -99,9 +103,13 @@ pub struct Upstream {
     tcp_keepalive: Option<TcpKeepalive>,
     tcp_recv_buf: Option<usize>,
     tcp_fast_open: Option<bool>,
+    validate_content_length: bool,
+    validate_digest: bool,
     peer_tracer: Option<UpstreamPeerTracer>,
     tracer: Option<Tracer>,
     processing: AtomicI32,
+    // the timestamp(seconds) of last health check, zero if not checked
+    checked_at: AtomicU64,
 }
 
 fn new_backends(
@@ -224,6 +232,32 @@ fn new_load_balancer(
             lb.health_check_frequency = Some(health_check_frequency);
             SelectionLb::Consistent(Arc::new(lb))
         },
+        "ewma" => {
+            let decay = if algo_params.len() > 1 {
+                humantime::parse_duration(algo_params[1]).map_err(|e| {
+                    Error::Common {
+                        category: "new_upstream".to_string(),
+                        message: format!(
+                            "Ewma decay({}) is invalid, {e}",
+                            algo_params[1]
+                        ),
+                    }
+                })?
+            } else {
+                Duration::from_secs(10)
+            };
+            let mut lb = LoadBalancer::<RoundRobin>::from_backends(backends);
+            if is_static_discovery(&discovery) {
+                lb.update()
+                    .now_or_never()
+                    .expect("static should not block")
+                    .expect("static should not error");
+            }
+            lb.set_health_check(hc);
+            lb.update_frequency = conf.update_frequency;
+            lb.health_check_frequency = Some(health_check_frequency);
+            SelectionLb::PeakEwma(Arc::new(lb), Arc::new(PeakEwma::new(decay)))
+        },
         _ => {
             let mut lb = LoadBalancer::<RoundRobin>::from_backends(backends);
             if is_static_discovery(&discovery) {
@@ -298,9 +332,14 @@ impl Upstream {
             tcp_recv_buf: conf.tcp_recv_buf.map(|item| item.as_u64() as usize),
             tcp_keepalive,
             tcp_fast_open: conf.tcp_fast_open,
+            validate_content_length: conf
+                .validate_content_length
+                .unwrap_or_default(),
+            validate_digest: conf.validate_digest.unwrap_or_default(),
             peer_tracer,
             tracer,
             processing: AtomicI32::new(0),
+            checked_at: AtomicU64::new(0),
         };
         debug!(name = up.name, "new upstream: {up:?}");
         Ok(up)
@@ -320,6 +359,23 @@ impl Upstream {
                     get_hash_value(&self.hash, &self.hash_key, session, ctx);
                 lb.select(value.as_bytes(), 256)
             },
+            SelectionLb::PeakEwma(lb, ewma) => {
+                let backends = lb.backends();
+                let items = backends.get_backend();
+                let addrs: Vec<String> = items
+                    .iter()
+                    .filter(|backend| backends.ready(backend))
+                    .map(|backend| backend.addr.to_string())
+                    .collect();
+                let candidates: Vec<&str> =
+                    addrs.iter().map(|addr| addr.as_str()).collect();
+                ewma.select(&candidates).and_then(|addr| {
+                    items
+                        .iter()
+                        .find(|backend| backend.addr.to_string() == addr)
+                        .cloned()
+                })
+            },
             SelectionLb::Transparent => None,
         };
         self.processing.fetch_add(1, Ordering::Relaxed);
@@ -360,6 +416,13 @@ impl Upstream {
         })
     }
 
+    /// Get the body validation options of upstream,
+    /// (validate content length, validate digest).
+    #[inline]
+    pub fn body_validation(&self) -> (bool, bool) {
+        (self.validate_content_length, self.validate_digest)
+    }
+
     /// Get the connected count of upstream
     #[inline]
     pub fn connected(&self) -> Option<u32> {
@@ -371,7 +434,9 @@ impl Upstream {
     #[inline]
     pub fn as_round_robin(&self) -> Option<Arc<LoadBalancer<RoundRobin>>> {
         match &self.lb {
-            SelectionLb::RoundRobin(lb) => Some(lb.clone()),
+            SelectionLb::RoundRobin(lb) | SelectionLb::PeakEwma(lb, _) => {
+                Some(lb.clone())
+            },
             _ => None,
         }
     }
@@ -386,6 +451,38 @@ impl Upstream {
     pub fn completed(&self) -> i32 {
         self.processing.fetch_add(-1, Ordering::Relaxed)
     }
+    /// Complete the request of peer for peak ewma selection,
+    /// the response time is observed if it exists.
+    #[inline]
+    pub fn peer_completed(&self, addr: &str, response_time: Option<u64>) {
+        if let SelectionLb::PeakEwma(_, ewma) = &self.lb {
+            ewma.completed(addr, response_time);
+        }
+    }
+    /// Remove the ewma of peers which are not in the backends,
+    /// it should be called after the backends are updated.
+    fn retain_ewma_peers(&self) {
+        if let SelectionLb::PeakEwma(lb, ewma) = &self.lb {
+            let addrs: Vec<String> = lb
+                .backends()
+                .get_backend()
+                .iter()
+                .map(|backend| backend.addr.to_string())
+                .collect();
+            let addrs: Vec<&str> =
+                addrs.iter().map(|addr| addr.as_str()).collect();
+            ewma.retain(&addrs);
+        }
+    }
+    /// Get the ewma stats of peers, it returns none if the
+    /// selection algorithm isn't peak ewma.
+    pub fn ewma_stats(&self) -> Option<AHashMap<String, PeerEwmaStat>> {
+        if let SelectionLb::PeakEwma(_, ewma) = &self.lb {
+            Some(ewma.stats())
+        } else {
+            None
+        }
+    }
 }
 
 type Upstreams = AHashMap<String, Arc<Upstream>>;
@@ -417,6 +514,95 @@ fn new_ahash_upstreams(
     Ok((upstreams, updated_upstreams))
 }
 
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UpstreamHealthyStatus {
+    pub healthy: u32,
+    pub total: u32,
+    pub unhealthy_backends: Vec<String>,
+}
+
+/// Get the healthy status of all upstreams,
+/// the transparent upstream is ignored.
+pub fn get_upstreams_healthy_status() -> HashMap<String, UpstreamHealthyStatus>
+{
+    let mut statuses = HashMap::new();
+    for (name, up) in UPSTREAM_MAP.load().iter() {
+        let backends = match &up.lb {
+            SelectionLb::RoundRobin(lb) | SelectionLb::PeakEwma(lb, _) => {
+                lb.backends()
+            },
+            SelectionLb::Consistent(lb) => lb.backends(),
+            SelectionLb::Transparent => continue,
+        };
+        let mut status = UpstreamHealthyStatus::default();
+        for backend in backends.get_backend().iter() {
+            status.total += 1;
+            if backends.ready(backend) {
+                status.healthy += 1;
+            } else {
+                status.unhealthy_backends.push(backend.addr.to_string());
+            }
+        }
+        statuses.insert(name.to_string(), status);
+    }
+    statuses
+}
+
+/// Get the timestamp(seconds) of last health check of all upstreams,
+/// the upstream which has not been checked is ignored.
+pub fn get_upstreams_checked_at() -> HashMap<String, u64> {
+    UPSTREAM_MAP
+        .load()
+        .iter()
+        .filter_map(|(name, up)| {
+            let checked_at = up.checked_at.load(Ordering::Relaxed);
+            (checked_at > 0).then(|| (name.to_string(), checked_at))
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UpstreamStats {
+    pub processing: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connected: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub healthy_status: Option<UpstreamHealthyStatus>,
+}
+
+/// Get the processing, connected count and healthy status of all upstreams,
+/// the connected count is only available if the tracer is enabled.
+pub fn get_upstreams_stats() -> HashMap<String, UpstreamStats> {
+    let mut statuses = get_upstreams_healthy_status();
+    UPSTREAM_MAP
+        .load()
+        .iter()
+        .map(|(name, up)| {
+            (
+                name.to_string(),
+                UpstreamStats {
+                    processing: up.processing.load(Ordering::Relaxed),
+                    connected: up.connected(),
+                    healthy_status: statuses.remove(name),
+                },
+            )
+        })
+        .collect()
+}
+
+/// Get the peak ewma stats of upstreams,
+/// only the upstream using ewma algorithm is returned.
+pub fn get_upstreams_ewma_stats(
+) -> HashMap<String, AHashMap<String, PeerEwmaStat>> {
+    let mut stats = HashMap::new();
+    for (name, up) in UPSTREAM_MAP.load().iter() {
+        if let Some(value) = up.ewma_stats() {
+            stats.insert(name.to_string(), value);
+        }
+    }
+    stats
+}
+
 pub fn try_init_upstreams(confs: &HashMap<String, UpstreamConf>) -> Result<()> {
     let (upstreams, _) = new_ahash_upstreams(confs)?;
     UPSTREAM_MAP.store(Arc::new(upstreams));
@@ -441,6 +627,8 @@ async fn run_health_check(up: &Arc<Upstream>) -> Result<()> {
             .run_health_check(lb.parallel_health_check)
             .await;
     }
+    up.checked_at
+        .store(util::now().as_secs(), Ordering::Relaxed);
     Ok(())
 }
 
@@ -536,6 +724,7 @@ impl ServiceTask for HealthCheckTask {
                             name, "update backends fail"
                         )
                     } else {
+                        up.retain_ewma_peers();
                         debug!(name, "update backend success",);
                     }
                 }
@@ -554,6 +743,8 @@ impl ServiceTask for HealthCheckTask {
                         .run_health_check(lb.parallel_health_check)
                         .await;
                 }
+                up.checked_at
+                    .store(util::now().as_secs(), Ordering::Relaxed);
                 debug!(name, "health check is done",);
             })
         });
@@ -665,6 +856,34 @@ mod tests {
             format!("{:?}", up.tcp_keepalive)
         );
         assert_eq!("Some(1024)", format!("{:?}", up.tcp_recv_buf));
+
+        let result = Upstream::new(
+            "charts",
+            &UpstreamConf {
+                addrs: vec!["192.168.1.1".to_string()],
+                algo: Some("ewma:abc".to_string()),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            "Common error, category: new_upstream, Ewma decay(abc) is invalid, expected number at 0",
+            result.err().unwrap().to_string()
+        );
+
+        let up = Upstream::new(
+            "charts",
+            &UpstreamConf {
+                addrs: vec![
+                    "192.168.1.1:8001".to_string(),
+                    "192.168.1.2:8001".to_string(),
+                ],
+                algo: Some("ewma:5s".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(true, up.as_round_robin().is_some());
+        assert_eq!(0, up.ewma_stats().unwrap().len());
     }
     #[tokio::test]
     async fn test_get_hash_key_value() {
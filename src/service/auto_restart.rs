@@ -13,21 +13,129 @@
 // limitations under the License.
 
 use crate::config::{
-    get_config_storage, get_current_config, load_config, set_current_config,
-    LoadConfigOptions, PingapConf, CATEGORY_CERTIFICATE, CATEGORY_LOCATION,
-    CATEGORY_PLUGIN, CATEGORY_UPSTREAM,
+    get_config_storage, get_current_config, load_config, save_history,
+    set_current_config, ConfigChange, LoadConfigOptions, PingapConf,
+    CATEGORY_ALL, CATEGORY_CERTIFICATE, CATEGORY_LOCATION, CATEGORY_PLUGIN,
+    CATEGORY_UPSTREAM, CONFIG_SAVE_LOCK,
 };
 use crate::service::{CommonServiceTask, ServiceTask};
-use crate::state::restart;
+use crate::state::{get_traffic_summary, restart, TrafficSummary};
 use crate::{plugin, proxy, webhook};
 use async_trait::async_trait;
+use once_cell::sync::Lazy;
 use pingora::server::ShutdownWatch;
 use pingora::services::background::BackgroundService;
-use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use tokio::time::interval;
 use tracing::{debug, error, info};
 
+#[derive(Default)]
+struct ConfigReloadError {
+    // load or validate config fail
+    load: Option<String>,
+    // hot reload upstream, location, plugin or server fail
+    reload: Option<String>,
+}
+
+// it's enabled by auto restart or auto reload
+static HOT_RELOAD_ENABLED: AtomicBool = AtomicBool::new(false);
+
+// avoid reloading the config concurrently
+static CONFIG_RELOAD_LOCK: Lazy<tokio::sync::Mutex<()>> =
+    Lazy::new(|| tokio::sync::Mutex::new(()));
+
+// the generation is increased after each config is applied
+static CONFIG_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+// the hash of config which is rolled back,
+// it isn't applied again until the config is changed
+static ROLLBACK_CONFIG_HASH: Lazy<Mutex<String>> =
+    Lazy::new(|| Mutex::new(String::new()));
+
+// the config of storage which is applied, the includes and env
+// placeholders are kept, it's written back to storage on rollback
+static APPLIED_STORAGE_CONFIG: Lazy<Mutex<Option<PingapConf>>> =
+    Lazy::new(|| Mutex::new(None));
+
+// the min count of requests to judge the error rate
+const ROLLBACK_MIN_REQUESTS: u64 = 10;
+const ROLLBACK_SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+
+// the modifier of config, e.g. the user of admin
+static CONFIG_MODIFIER: Lazy<Mutex<Option<String>>> =
+    Lazy::new(|| Mutex::new(None));
+
+static CONFIG_RELOAD_ERROR: Lazy<Mutex<ConfigReloadError>> =
+    Lazy::new(|| Mutex::new(ConfigReloadError::default()));
+
+/// Get the error of last config reload, it's none if success.
+pub fn get_config_reload_error() -> Option<String> {
+    let reload_error = CONFIG_RELOAD_ERROR
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    reload_error.load.clone().or(reload_error.reload.clone())
+}
+
+fn set_config_reload_error(load: Option<String>, reload: Option<String>) {
+    let mut reload_error = CONFIG_RELOAD_ERROR
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    reload_error.load = load;
+    if reload.is_some() {
+        reload_error.reload = reload;
+    }
+}
+
+fn clear_config_reload_error() {
+    *CONFIG_RELOAD_ERROR
+        .lock()
+        .unwrap_or_else(|e| e.into_inner()) = ConfigReloadError::default();
+}
+
+fn is_rolled_back_config(hash: &str) -> bool {
+    *ROLLBACK_CONFIG_HASH
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        == hash
+}
+
+/// Set the applied config of storage, it returns the previous one.
+fn replace_applied_storage_config(
+    conf: Option<PingapConf>,
+) -> Option<PingapConf> {
+    std::mem::replace(
+        &mut *APPLIED_STORAGE_CONFIG
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()),
+        conf,
+    )
+}
+
+/// Write the previous config back to storage, so the rolled back config
+/// isn't applied again after restart.
+async fn write_back_config(
+    conf: &PingapConf,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(storage) = get_config_storage() else {
+        return Err("storage is not inited".into());
+    };
+    let _guard = CONFIG_SAVE_LOCK.lock().await;
+    storage.save_config(conf, CATEGORY_ALL, None).await?;
+    save_history(conf, "rollback by error rate").await?;
+    Ok(())
+}
+
+/// Get the ratio of 5xx responses in the traffic summary,
+/// it's none if the requests are too few to judge.
+fn get_error_rate(summary: &TrafficSummary) -> Option<f64> {
+    if summary.requests < ROLLBACK_MIN_REQUESTS {
+        return None;
+    }
+    Some(summary.status_5xx as f64 / summary.requests as f64)
+}
+
 async fn diff_and_update_config(
     hot_reload_only: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
@@ -37,6 +145,25 @@ async fn diff_and_update_config(
     })
     .await?;
     new_config.validate()?;
+    let hash = new_config.hash()?;
+    // the config has been rolled back, wait for the next change
+    if is_rolled_back_config(&hash) {
+        return Ok(());
+    }
+    // the config of storage is loaded only if rollback is enabled,
+    // it's written back to storage when the config is rolled back
+    let storage_config = if new_config.basic.rollback_window.is_some() {
+        Some(
+            load_config(LoadConfigOptions {
+                replace_include: false,
+                admin: true,
+            })
+            .await?,
+        )
+    } else {
+        None
+    };
+    set_config_reload_error(None, None);
     let current_config: PingapConf = get_current_config().as_ref().clone();
 
     let (updated_category_list, original_diff_result) =
@@ -48,9 +175,185 @@ async fn diff_and_update_config(
     );
     // no update config
     if original_diff_result.is_empty() {
+        replace_applied_storage_config(storage_config);
         return Ok(());
     }
 
+    if new_config.basic.staged_apply.unwrap_or_default() {
+        let report = proxy::check_staged(&new_config, &current_config).await;
+        if !report.success {
+            let message = report
+                .items
+                .iter()
+                .filter(|item| !item.message.is_empty())
+                .map(|item| {
+                    format!(
+                        "{}({}): {}",
+                        item.category, item.name, item.message
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join("; ");
+            return Err(format!("staged config check fail, {message}").into());
+        }
+    }
+
+    // the error rate before the config is applied
+    let baseline = get_error_rate(&get_traffic_summary(60)).unwrap_or_default();
+    let previous_storage_config =
+        replace_applied_storage_config(storage_config);
+    let generation = apply_config(
+        current_config.clone(),
+        &new_config,
+        updated_category_list,
+        original_diff_result,
+        hot_reload_only,
+    )
+    .await;
+    if let Some(window) = new_config.basic.rollback_window {
+        let max_error_rate =
+            new_config.basic.rollback_error_rate.unwrap_or(0.1);
+        tokio::spawn(observe_applied_config(
+            current_config,
+            ObserveParams {
+                hash,
+                generation,
+                window,
+                max_error_rate,
+                baseline,
+                previous_storage_config,
+            },
+        ));
+    }
+    Ok(())
+}
+
+struct ObserveParams {
+    // the hash of applied config
+    hash: String,
+    // the generation of applied config
+    generation: u64,
+    window: Duration,
+    max_error_rate: f64,
+    // the error rate before the config is applied
+    baseline: f64,
+    // the previous config of storage, it's none if it isn't recorded
+    previous_storage_config: Option<PingapConf>,
+}
+
+/// Observe the error rate in the window after config is applied, the
+/// previous config is hot reloaded and written back to storage if the
+/// error rate exceeds the max and the baseline. It's stopped if the
+/// config is changed again.
+async fn observe_applied_config(previous: PingapConf, params: ObserveParams) {
+    let started_at = Instant::now();
+    let sample_interval = ROLLBACK_SAMPLE_INTERVAL.min(params.window);
+    while started_at.elapsed() < params.window {
+        tokio::time::sleep(sample_interval).await;
+        if CONFIG_GENERATION.load(Ordering::Relaxed) != params.generation {
+            return;
+        }
+        let summary = get_traffic_summary(sample_interval.as_secs());
+        let Some(error_rate) = get_error_rate(&summary) else {
+            continue;
+        };
+        if error_rate <= params.max_error_rate || error_rate <= params.baseline
+        {
+            continue;
+        }
+        let _guard = CONFIG_RELOAD_LOCK.lock().await;
+        if CONFIG_GENERATION.load(Ordering::Relaxed) != params.generation {
+            return;
+        }
+        *ROLLBACK_CONFIG_HASH
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = params.hash.clone();
+        let current_config: PingapConf = get_current_config().as_ref().clone();
+        let (updated_category_list, diff_result) =
+            current_config.diff(&previous);
+        apply_config(
+            current_config,
+            &previous,
+            updated_category_list,
+            diff_result,
+            true,
+        )
+        .await;
+        // write back, otherwise the rolled back config is applied
+        // after restart, e.g. the config which can't be hot reloaded
+        if let Some(conf) = &params.previous_storage_config {
+            match write_back_config(conf).await {
+                Ok(()) => {
+                    replace_applied_storage_config(Some(conf.clone()));
+                },
+                Err(e) => {
+                    error!(error = e.to_string(), "write back config fail");
+                },
+            }
+        } else {
+            error!("previous config of storage isn't recorded");
+        }
+        let message = format!(
+            "error rate({error_rate:.3}) exceeds {}, config is rolled back",
+            params.max_error_rate
+        );
+        error!(
+            error_rate,
+            baseline = params.baseline,
+            "config is rolled back"
+        );
+        set_config_reload_error(None, Some(message.clone()));
+        webhook::send_notification(webhook::SendNotificationParams {
+            category: webhook::NotificationCategory::RollbackConfig,
+            level: webhook::NotificationLevel::Error,
+            msg: message,
+            ..Default::default()
+        })
+        .await;
+        return;
+    }
+}
+
+/// Set the modifier of config, it's sent with the notification
+/// of next config diff.
+pub fn set_config_modifier(name: &str) {
+    *CONFIG_MODIFIER.lock().unwrap_or_else(|e| e.into_inner()) =
+        Some(name.to_string());
+}
+
+/// Send the notification of config diff, the message is the summary of
+/// changes, and the changes are sent as structured data.
+async fn send_diff_notification(changes: Vec<ConfigChange>) {
+    let modifier = CONFIG_MODIFIER
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .take();
+    let msg = changes
+        .iter()
+        .map(|item| item.to_string())
+        .collect::<Vec<String>>()
+        .join("\n");
+    webhook::send_notification(webhook::SendNotificationParams {
+        category: webhook::NotificationCategory::DiffConfig,
+        msg,
+        remark: modifier.map(|name| format!("changed by {name}")),
+        changes,
+        ..Default::default()
+    })
+    .await;
+}
+
+/// Apply the new config, the hot reload config is applied first, and the
+/// process is restarted if other config is updated and it's not hot reload
+/// only. It returns the generation of applied config.
+async fn apply_config(
+    current_config: PingapConf,
+    new_config: &PingapConf,
+    updated_category_list: Vec<String>,
+    original_diff_result: Vec<String>,
+    hot_reload_only: bool,
+) -> u64 {
+    let generation = CONFIG_GENERATION.fetch_add(1, Ordering::Relaxed) + 1;
     let mut reload_fail_messages = vec![];
     let mut hot_realod_config = current_config.clone();
     {
@@ -205,7 +508,7 @@ async fn diff_and_update_config(
                         webhook::NotificationCategory::ParseCertificateFail,
                     level: webhook::NotificationLevel::Error,
                     msg: errors,
-                    remark: None,
+                    ..Default::default()
                 })
                 .await;
             }
@@ -242,6 +545,11 @@ async fn diff_and_update_config(
     }
 
     let reload_fail_message = reload_fail_messages.join(";");
+    if reload_fail_message.is_empty() {
+        clear_config_reload_error();
+    } else {
+        set_config_reload_error(None, Some(reload_fail_message.clone()));
+    }
 
     if hot_reload_only {
         let (updated_category_list, original_diff_result) =
@@ -253,17 +561,13 @@ async fn diff_and_update_config(
         );
         // no update config
         if original_diff_result.is_empty() {
-            return Ok(());
+            return generation;
         }
         // update current config to be hot reload config
         set_current_config(&hot_realod_config);
         if !original_diff_result.is_empty() {
-            webhook::send_notification(webhook::SendNotificationParams {
-                category: webhook::NotificationCategory::DiffConfig,
-                msg: original_diff_result.join("\n").trim().to_string(),
-                ..Default::default()
-            })
-            .await;
+            send_diff_notification(current_config.changes(&hot_realod_config))
+                .await;
             if !reload_fail_message.is_empty() {
                 webhook::send_notification(webhook::SendNotificationParams {
                     category: webhook::NotificationCategory::ReloadConfigFail,
@@ -274,14 +578,14 @@ async fn diff_and_update_config(
                 .await;
             }
         }
-        return Ok(());
+        return generation;
     }
     // restart mode
     // update current config to be hot reload config
     set_current_config(&hot_realod_config);
 
     // diff hot reload config and new config
-    let (_, new_config_result) = hot_realod_config.diff(&new_config);
+    let (_, new_config_result) = hot_realod_config.diff(new_config);
     debug!(
         new_config_result = new_config_result.join("\n"),
         "hot reload config diff from new config"
@@ -294,12 +598,7 @@ async fn diff_and_update_config(
     }
 
     if !original_diff_result.is_empty() {
-        webhook::send_notification(webhook::SendNotificationParams {
-            category: webhook::NotificationCategory::DiffConfig,
-            msg: original_diff_result.join("\n").trim().to_string(),
-            ..Default::default()
-        })
-        .await;
+        send_diff_notification(current_config.changes(new_config)).await;
         if !reload_fail_message.is_empty() {
             webhook::send_notification(webhook::SendNotificationParams {
                 category: webhook::NotificationCategory::ReloadConfigFail,
@@ -313,7 +612,7 @@ async fn diff_and_update_config(
     if should_restart {
         restart().await;
     }
-    Ok(())
+    generation
 }
 
 struct AutoRestart {
@@ -327,6 +626,7 @@ pub fn new_auto_restart_service(
     interval: Duration,
     only_hot_reload: bool,
 ) -> CommonServiceTask {
+    HOT_RELOAD_ENABLED.store(true, Ordering::Relaxed);
     let mut restart_unit = 1_u32;
     let unit = Duration::from_secs(10);
     if interval > unit {
@@ -353,6 +653,7 @@ pub fn new_observer_service(
     interval: Duration,
     only_hot_reload: bool,
 ) -> ConfigObserverService {
+    HOT_RELOAD_ENABLED.store(true, Ordering::Relaxed);
     ConfigObserverService {
         interval,
         only_hot_reload,
@@ -412,9 +713,19 @@ impl BackgroundService for ConfigObserverService {
     }
 }
 
+/// Hot reload the saved config immediately, it's skipped if neither
+/// auto restart nor auto reload is enabled.
+pub async fn try_hot_reload_config() {
+    if HOT_RELOAD_ENABLED.load(Ordering::Relaxed) {
+        run_diff_and_update_config(true).await;
+    }
+}
+
 async fn run_diff_and_update_config(hot_reload_only: bool) {
+    let _guard = CONFIG_RELOAD_LOCK.lock().await;
     if let Err(e) = diff_and_update_config(hot_reload_only).await {
         error!(error = e.to_string(), "auto restart validate fail");
+        set_config_reload_error(Some(e.to_string()), None);
     }
 }
 
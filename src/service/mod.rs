@@ -187,4 +187,7 @@ impl BackgroundService for CommonServiceTask {
 
 mod auto_restart;
 
-pub use auto_restart::{new_auto_restart_service, new_observer_service};
+pub use auto_restart::{
+    get_config_reload_error, new_auto_restart_service, new_observer_service,
+    set_config_modifier, try_hot_reload_config,
+};
@@ -12,27 +12,42 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+#[cfg(feature = "wasm")]
+use crate::plugin::WasmInstance;
 use crate::util::format_duration;
-use crate::{proxy::Location, util};
+use crate::{
+    proxy::{BodyValidator, Location},
+    util,
+};
 use ahash::AHashMap;
 use bytes::{Bytes, BytesMut};
 use http::StatusCode;
 use http::Uri;
 use pingora::cache::CacheKey;
 
-#[cfg(feature = "full")]
+#[cfg(feature = "otel")]
 use opentelemetry::{
     global::{BoxedSpan, BoxedTracer, ObjectSafeSpan},
     trace::{SpanKind, TraceContextExt, Tracer},
     Context,
 };
 use pingora_limits::inflight::Guard;
+use std::collections::HashMap;
 use std::{sync::Arc, time::Duration};
+use tokio::sync::OwnedSemaphorePermit;
 
 pub trait ModifyResponseBody: Sync + Send {
     fn handle(&self, data: Bytes) -> Bytes;
 }
 
+/// Inspect the frames of upgraded websocket connection.
+pub trait WebSocketGuard: Sync + Send {
+    /// Inspect the data sent by client or upstream, it returns the data
+    /// which should be forwarded, the data may be truncated and appended
+    /// with a close frame if the policy is violated.
+    fn inspect(&mut self, data: Bytes, from_client: bool) -> Bytes;
+}
+
 pub struct CompressionStat {
     pub in_bytes: usize,
     pub out_bytes: usize,
@@ -45,13 +60,13 @@ impl CompressionStat {
     }
 }
 
-#[cfg(feature = "full")]
+#[cfg(feature = "otel")]
 pub struct OtelTracer {
     pub tracer: BoxedTracer,
     pub http_request_span: BoxedSpan,
 }
 
-#[cfg(feature = "full")]
+#[cfg(feature = "otel")]
 impl OtelTracer {
     #[inline]
     pub fn new_upstream_span(&self, name: &str) -> BoxedSpan {
@@ -104,13 +119,40 @@ pub struct State {
     pub server_port: Option<u16>,
     pub server_addr: Option<String>,
     pub guard: Option<Guard>,
+    // the permit of concurrency plugin, it is released when request done
+    pub concurrency_permit: Option<OwnedSemaphorePermit>,
     pub request_id: Option<String>,
     pub cache_namespace: Option<String>,
     pub cache_prefix: Option<String>,
+    // the cache key generated from the key template of cache plugin,
+    // it's used instead of method and uri
+    pub cache_key: Option<String>,
     pub check_cache_control: bool,
     pub cache_lookup_time: Option<u64>,
     pub cache_lock_time: Option<u64>,
+    // cache status: hit, miss, stale, revalidated, bypass, expired
+    pub cache_status: Option<&'static str>,
     pub cache_max_ttl: Option<Duration>,
+    // the default ttl of response without freshness
+    pub cache_ttl: Option<Duration>,
+    // the ttls of negative responses, e.g. 404 and 503
+    pub cache_negative_ttls: Option<Vec<(u16, Duration)>>,
+    // the response isn't cached because the key isn't requested enough
+    pub cache_not_admitted: bool,
+    // the response larger than it is cached by the probability of size
+    pub cache_admission_size: Option<usize>,
+    // the stale durations of cache, they override the cache control of upstream
+    pub cache_stale_while_revalidate: Option<Duration>,
+    pub cache_stale_if_error: Option<Duration>,
+    // the allowlist of varied request headers(lowercase) for cache variants
+    pub cache_vary: Option<Vec<String>>,
+    // the cache is prefetched if it's hit in the duration before expired
+    pub cache_prefetch: Option<Duration>,
+    pub cache_prefetch_min_hits: u32,
+    // the request is sent by prefetch service, the cache is forced to expire
+    pub cache_prefetching: bool,
+    // the range(inclusive) of slice, it's cached independently
+    pub cache_slice: Option<(u64, u64)>,
     pub upstream_reused: bool,
     pub upstream_processing: Option<i32>,
     // upstream connect time,
@@ -127,21 +169,41 @@ pub struct State {
     pub upstream_processing_time: Option<u64>,
     // upstream response time
     pub upstream_response_time: Option<u64>,
+    // the body bytes sent to upstream
+    pub upstream_bytes_sent: usize,
+    // the body bytes received from upstream
+    pub upstream_bytes_received: usize,
+    // validate the upstream body by content length and digest
+    pub upstream_body_validator: Option<BodyValidator>,
     // client payload size
     pub payload_size: usize,
+    // the request body read by plugin, it will be sent to upstream
+    pub request_body: Option<Bytes>,
     // compression stat, in/out bytes and compression duration
     pub compression_stat: Option<CompressionStat>,
     pub modify_response_body: Option<Box<dyn ModifyResponseBody>>,
     pub response_body: Option<BytesMut>,
+    // the guard of websocket connection
+    pub websocket_guard: Option<Box<dyn WebSocketGuard>>,
     // cache reading count
     pub cache_reading: Option<u32>,
     // cache writing count
     pub cache_writing: Option<u32>,
-    #[cfg(feature = "full")]
+    #[cfg(feature = "otel")]
     pub otel_tracer: Option<OtelTracer>,
-    #[cfg(feature = "full")]
+    #[cfg(feature = "otel")]
     pub upstream_span: Option<BoxedSpan>,
+    // the instances of wasm module for the request
+    #[cfg(feature = "wasm")]
+    pub wasm_instances: Option<AHashMap<String, WasmInstance>>,
+    // the match condition results of plugins by step, it's checked once
+    // per step of request
+    pub plugin_conditions: Option<AHashMap<String, bool>>,
     pub variables: Option<AHashMap<String, String>>,
+    // the data of session cookie
+    pub session: Option<HashMap<String, String>>,
+    // the session data is modified, the cookie should be updated
+    pub session_updated: bool,
 }
 
 impl State {
@@ -155,6 +217,10 @@ impl State {
 
 const ONE_HOUR_MS: u64 = 60 * 60 * 1000;
 
+// the identity of authenticated request
+pub const AUTH_USER_VARIABLE: &str = "auth_user";
+pub const AUTH_CLIENT_ID_VARIABLE: &str = "auth_client_id";
+
 impl State {
     #[inline]
     pub fn add_variable(&mut self, key: &str, value: &str) {
@@ -167,6 +233,21 @@ impl State {
             self.variables = Some(variables);
         }
     }
+    /// Set the identity of authenticated request by auth plugin,
+    /// it can be used as `$auth_user` and `$auth_client_id`.
+    #[inline]
+    pub fn set_auth_identity(
+        &mut self,
+        user: Option<&str>,
+        client_id: Option<&str>,
+    ) {
+        if let Some(user) = user.filter(|value| !value.is_empty()) {
+            self.add_variable(AUTH_USER_VARIABLE, user);
+        }
+        if let Some(client_id) = client_id.filter(|value| !value.is_empty()) {
+            self.add_variable(AUTH_CLIENT_ID_VARIABLE, client_id);
+        }
+    }
     #[inline]
     pub fn get_upstream_response_time(&self) -> Option<u64> {
         if let Some(value) = self.upstream_response_time {
@@ -242,6 +323,16 @@ impl State {
                     buf = format_duration(buf, ms);
                 }
             },
+            "upstream_bytes_sent" => buf.extend(
+                itoa::Buffer::new()
+                    .format(self.upstream_bytes_sent)
+                    .as_bytes(),
+            ),
+            "upstream_bytes_received" => buf.extend(
+                itoa::Buffer::new()
+                    .format(self.upstream_bytes_received)
+                    .as_bytes(),
+            ),
             "location" => {
                 if let Some(location) = &self.location {
                     buf.extend(location.name.as_bytes())
@@ -293,13 +384,27 @@ impl State {
                     buf = format_duration(buf, ms);
                 }
             },
+            "cache_status" => {
+                if let Some(value) = self.cache_status {
+                    buf.extend(value.as_bytes());
+                }
+            },
             "service_time" => {
                 buf = format_duration(
                     buf,
                     util::now().as_millis() as u64 - self.created_at,
                 )
             },
-            _ => {},
+            // the variable added by plugin
+            _ => {
+                if let Some(value) = self
+                    .variables
+                    .as_ref()
+                    .and_then(|variables| variables.get(&format!("${key}")))
+                {
+                    buf.extend(value.as_bytes());
+                }
+            },
         }
         buf
     }
@@ -307,11 +412,21 @@ impl State {
 
 pub fn get_cache_key(ctx: &State, method: &str, uri: &Uri) -> CacheKey {
     let namespace = ctx.cache_namespace.as_ref().map_or("", |v| v);
-    let key = if let Some(prefix) = &ctx.cache_prefix {
-        format!("{prefix}{method}:{uri}")
+    let key = if let Some(key) = &ctx.cache_key {
+        key.clone()
     } else {
         format!("{method}:{uri}")
     };
+    let key = if let Some(prefix) = &ctx.cache_prefix {
+        format!("{prefix}{key}")
+    } else {
+        key
+    };
+    let key = if let Some((start, end)) = ctx.cache_slice {
+        format!("{key}:slice:{start}-{end}")
+    } else {
+        key
+    };
 
     CacheKey::new(namespace, key, "")
 }
@@ -404,6 +519,19 @@ mod tests {
                 .as_ref()
         );
 
+        ctx.upstream_bytes_sent = 1024;
+        ctx.upstream_bytes_received = 4096;
+        assert_eq!(
+            b"1024",
+            ctx.append_value(BytesMut::new(), "upstream_bytes_sent")
+                .as_ref()
+        );
+        assert_eq!(
+            b"4096",
+            ctx.append_value(BytesMut::new(), "upstream_bytes_received")
+                .as_ref()
+        );
+
         ctx.location = Some(Arc::new(
             Location::new(
                 "pingap",
@@ -481,11 +609,34 @@ mod tests {
                 .as_ref()
         );
 
+        ctx.cache_status = Some("hit");
+        assert_eq!(
+            b"hit",
+            ctx.append_value(BytesMut::new(), "cache_status").as_ref()
+        );
+
         ctx.created_at = util::now().as_millis() as u64 - 1;
         assert_eq!(
             true,
             ctx.append_value(BytesMut::new(), "service_time")
                 .ends_with(b"ms")
         );
+
+        ctx.add_variable("operation_id", "getUser");
+        assert_eq!(
+            b"getUser",
+            ctx.append_value(BytesMut::new(), "operation_id").as_ref()
+        );
+
+        ctx.set_auth_identity(Some("tree"), Some(""));
+        assert_eq!(
+            b"tree",
+            ctx.append_value(BytesMut::new(), "auth_user").as_ref()
+        );
+        assert_eq!(
+            true,
+            ctx.append_value(BytesMut::new(), "auth_client_id")
+                .is_empty()
+        );
     }
 }
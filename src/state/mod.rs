@@ -13,23 +13,34 @@
 // limitations under the License.
 
 use crate::service::SimpleServiceTaskFuture;
-#[cfg(feature = "full")]
+#[cfg(feature = "metrics")]
 use snafu::Snafu;
 use tracing::info;
 
 mod ctx;
 mod process;
-#[cfg(feature = "full")]
+#[cfg(feature = "metrics")]
 mod prom;
+mod slo;
+mod traffic;
+mod upgrade;
 pub use ctx::*;
 pub use process::*;
-#[cfg(feature = "full")]
+#[cfg(feature = "metrics")]
 pub use prom::{
-    new_prometheus, new_prometheus_push_service, Prometheus,
-    CACHE_READING_TIME, CACHE_WRITING_TIME,
+    new_prometheus, new_prometheus_push_service, Prometheus, API_KEY_REQUESTS,
+    CACHE_READING_TIME, CACHE_WRITING_TIME, DLP_MATCHES,
+    DOWNSTREAM_CONNECTION_CLOSED, EVENT_EMITTER_DROPPED, PLUGIN_FAILURES,
+    WASM_CALLS, WASM_CALL_TIME,
 };
+pub use slo::{
+    get_slo_burn_rate, new_slo_burn_rate_service, parse_slo_target, record_slo,
+    Slo,
+};
+pub use traffic::{get_traffic_summary, record_traffic, TrafficSummary};
+pub use upgrade::{upgrade, UpgradeParams, UpgradeReport};
 
-#[cfg(feature = "full")]
+#[cfg(feature = "metrics")]
 #[derive(Debug, Snafu)]
 pub enum Error {
     #[snafu(display("{source}"))]
@@ -37,7 +48,7 @@ pub enum Error {
     #[snafu(display("{message}"))]
     Prometheus { message: String },
 }
-#[cfg(feature = "full")]
+#[cfg(feature = "metrics")]
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 pub fn new_performance_metrics_log_service() -> (String, SimpleServiceTaskFuture)
@@ -242,7 +242,7 @@ pub async fn restart() {
                     level: webhook::NotificationLevel::Error,
                     category: webhook::NotificationCategory::RestartFail,
                     msg: e.to_string(),
-                    remark: None,
+                    ..Default::default()
                 })
                 .await;
             },
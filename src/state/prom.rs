@@ -55,6 +55,91 @@ pub static CACHE_WRITING_TIME: Lazy<Box<Histogram>> = Lazy::new(|| {
     )
 });
 
+pub static API_KEY_REQUESTS: Lazy<Box<IntCounterVec>> = Lazy::new(|| {
+    Box::new(
+        new_int_counter_vec(
+            "",
+            "pingap_api_key_requests",
+            "pingap api key requests of consumer",
+            &["consumer", "tier", "result"],
+        )
+        .unwrap(),
+    )
+});
+
+pub static DLP_MATCHES: Lazy<Box<IntCounterVec>> = Lazy::new(|| {
+    Box::new(
+        new_int_counter_vec(
+            "",
+            "pingap_dlp_matches",
+            "pingap dlp matches of response pattern",
+            &["pattern", "action"],
+        )
+        .unwrap(),
+    )
+});
+
+pub static PLUGIN_FAILURES: Lazy<Box<IntCounterVec>> = Lazy::new(|| {
+    Box::new(
+        new_int_counter_vec(
+            "",
+            "pingap_plugin_failures",
+            "pingap plugin failures of error or panic",
+            &["plugin", "kind"],
+        )
+        .unwrap(),
+    )
+});
+
+pub static EVENT_EMITTER_DROPPED: Lazy<Box<IntCounter>> = Lazy::new(|| {
+    Box::new(
+        new_int_counter(
+            "",
+            "pingap_event_emitter_dropped",
+            "pingap events dropped by event emitter because the queue is full",
+        )
+        .unwrap(),
+    )
+});
+
+pub static WASM_CALLS: Lazy<Box<IntCounterVec>> = Lazy::new(|| {
+    Box::new(
+        new_int_counter_vec(
+            "",
+            "pingap_wasm_calls",
+            "pingap wasm calls of module function",
+            &["module", "function", "result"],
+        )
+        .unwrap(),
+    )
+});
+
+pub static WASM_CALL_TIME: Lazy<Box<HistogramVec>> = Lazy::new(|| {
+    Box::new(
+        new_histogram_vec(
+            "",
+            "pingap_wasm_call_time",
+            "pingap wasm call time of module function(second)",
+            &["module", "function"],
+            &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5],
+        )
+        .unwrap(),
+    )
+});
+
+pub static DOWNSTREAM_CONNECTION_CLOSED: Lazy<Box<IntCounterVec>> =
+    Lazy::new(|| {
+        Box::new(
+            new_int_counter_vec(
+                "",
+                "pingap_downstream_connection_closed",
+                "pingap downstream connection closed by keep-alive policy",
+                &["server", "policy"],
+            )
+            .unwrap(),
+        )
+    });
+
 pub struct Prometheus {
     r: Registry,
     http_requests_total: Box<IntCounterVec>,
@@ -74,8 +159,11 @@ pub struct Prometheus {
     upstream_reuses: Box<IntCounterVec>,
     upstream_processing_time: Box<HistogramVec>,
     upstream_response_time: Box<HistogramVec>,
+    upstream_sent_bytes: Box<IntCounterVec>,
+    upstream_received_bytes: Box<IntCounterVec>,
     cache_lookup_time: Box<Histogram>,
     cache_lock_time: Box<Histogram>,
+    cache_statuses: Box<IntCounterVec>,
     cache_reading: Box<IntGauge>,
     cache_writing: Box<IntGauge>,
     compression_ratio: Box<Histogram>,
@@ -219,6 +307,20 @@ impl Prometheus {
                     .with_label_values(upstream_labels)
                     .observe(upstream_response_time as f64 / SECOND);
             }
+            // bytes of upstream peer
+            if !ctx.upstream_address.is_empty() {
+                let peer_labels = &[upstream, &ctx.upstream_address];
+                if ctx.upstream_bytes_sent > 0 {
+                    self.upstream_sent_bytes
+                        .with_label_values(peer_labels)
+                        .inc_by(ctx.upstream_bytes_sent as u64);
+                }
+                if ctx.upstream_bytes_received > 0 {
+                    self.upstream_received_bytes
+                        .with_label_values(peer_labels)
+                        .inc_by(ctx.upstream_bytes_received as u64);
+                }
+            }
         }
 
         // cache stats
@@ -230,6 +332,11 @@ impl Prometheus {
             self.cache_lock_time
                 .observe(cache_lock_time as f64 / SECOND);
         }
+        if let Some(cache_status) = ctx.cache_status {
+            self.cache_statuses
+                .with_label_values(&[location, cache_status])
+                .inc();
+        }
         if let Some(cache_reading) = ctx.cache_reading {
             self.cache_reading.set(cache_reading as i64);
         }
@@ -572,6 +679,18 @@ pub fn new_prometheus(server: &str) -> Result<Prometheus> {
         &["upstream"],
         &[0.005, 0.01, 0.05, 0.1, 0.5, 1.0],
     )?);
+    let upstream_sent_bytes = Box::new(new_int_counter_vec(
+        server,
+        "pingap_upstream_sent_bytes",
+        "pingap body sent to upstream peer(bytes)",
+        &["upstream", "peer"],
+    )?);
+    let upstream_received_bytes = Box::new(new_int_counter_vec(
+        server,
+        "pingap_upstream_received_bytes",
+        "pingap body received from upstream peer(bytes)",
+        &["upstream", "peer"],
+    )?);
     let cache_lookup_time = Box::new(new_histogram(
         server,
         "pingap_cache_lookup_time",
@@ -584,6 +703,12 @@ pub fn new_prometheus(server: &str) -> Result<Prometheus> {
         "pingap cache lock time(second)",
         &[0.01, 0.05, 0.1, 1.0, 3.0],
     )?);
+    let cache_statuses = Box::new(new_int_counter_vec(
+        server,
+        "pingap_cache_statuses",
+        "pingap cache status count(hit, miss, stale, revalidated, bypass, expired)",
+        &["location", "status"],
+    )?);
     let cache_reading = Box::new(new_int_gauge(
         server,
         "pingap_cache_reading",
@@ -640,12 +765,22 @@ pub fn new_prometheus(server: &str) -> Result<Prometheus> {
         upstream_reuses.clone(),
         upstream_processing_time.clone(),
         upstream_response_time.clone(),
+        upstream_sent_bytes.clone(),
+        upstream_received_bytes.clone(),
         cache_lookup_time.clone(),
         cache_lock_time.clone(),
+        cache_statuses.clone(),
         cache_reading.clone(),
         cache_writing.clone(),
         CACHE_READING_TIME.clone(),
         CACHE_WRITING_TIME.clone(),
+        API_KEY_REQUESTS.clone(),
+        DLP_MATCHES.clone(),
+        PLUGIN_FAILURES.clone(),
+        EVENT_EMITTER_DROPPED.clone(),
+        WASM_CALLS.clone(),
+        WASM_CALL_TIME.clone(),
+        DOWNSTREAM_CONNECTION_CLOSED.clone(),
         compression_ratio.clone(),
         memory.clone(),
         fd_count.clone(),
@@ -677,8 +812,11 @@ pub fn new_prometheus(server: &str) -> Result<Prometheus> {
         upstream_reuses,
         upstream_processing_time,
         upstream_response_time,
+        upstream_sent_bytes,
+        upstream_received_bytes,
         cache_lookup_time,
         cache_lock_time,
+        cache_statuses,
         cache_reading,
         cache_writing,
         compression_ratio,
@@ -748,8 +886,12 @@ mod tests {
                 upstream_reused: true,
                 upstream_processing_time: Some(10),
                 upstream_response_time: Some(5),
+                upstream_address: "192.168.1.1:80".to_string(),
+                upstream_bytes_sent: 1024,
+                upstream_bytes_received: 4096,
                 cache_lookup_time: Some(11),
                 cache_lock_time: Some(12),
+                cache_status: Some("hit"),
                 compression_stat: Some(CompressionStat {
                     in_bytes: 1024,
                     out_bytes: 512,
@@ -760,6 +902,6 @@ mod tests {
             },
         );
         let buf = p.metrics().unwrap();
-        assert_eq!(221, std::str::from_utf8(&buf).unwrap().split('\n').count());
+        assert_eq!(230, std::str::from_utf8(&buf).unwrap().split('\n').count());
     }
 }
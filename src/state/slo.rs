@@ -0,0 +1,309 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::service::SimpleServiceTaskFuture;
+use crate::util;
+use crate::webhook::{
+    send_notification, NotificationCategory, NotificationLevel,
+    SendNotificationParams,
+};
+use ahash::AHashMap;
+use once_cell::sync::Lazy;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tracing::warn;
+
+// keep the per minute buckets of six hours
+const BUCKET_COUNT: u64 = 360;
+// don't send the same alert again in one hour
+const ALERT_INTERVAL_MINUTES: u64 = 60;
+
+/// The multi-window burn rate alerts, (short window, long window, threshold),
+/// both windows should be over the threshold to fire the alert.
+const BURN_RATE_ALERTS: [(u64, u64, f64, &str); 2] = [
+    // 2% budget consumption of 30 days in one hour
+    (5, 60, 14.4, "fast"),
+    // 5% budget consumption of 30 days in six hours
+    (30, 360, 6.0, "slow"),
+];
+
+/// The service level objective of location.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Slo {
+    /// The availability target, e.g. 0.999
+    pub target: f64,
+    /// The request is bad if its latency is greater than the threshold
+    pub latency: Option<Duration>,
+}
+
+/// Parse the slo target, e.g. `99.9` or `99.9%`,
+/// it should be greater than 0 and less than 100.
+pub fn parse_slo_target(value: &str) -> Result<f64, String> {
+    let target = value
+        .trim()
+        .trim_end_matches('%')
+        .parse::<f64>()
+        .map_err(|e| format!("slo target({value}) is invalid, {e}"))?;
+    if target <= 0.0 || target >= 100.0 {
+        return Err(format!(
+            "slo target({value}) should be greater than 0 and less than 100"
+        ));
+    }
+    Ok(target / 100.0)
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Bucket {
+    minute: u64,
+    total: u64,
+    bad: u64,
+}
+
+#[derive(Debug)]
+struct SloStats {
+    target: f64,
+    buckets: Vec<Bucket>,
+    alerted_at: Option<u64>,
+}
+
+impl SloStats {
+    fn new(target: f64) -> Self {
+        Self {
+            target,
+            buckets: vec![Bucket::default(); BUCKET_COUNT as usize],
+            alerted_at: None,
+        }
+    }
+    fn add(&mut self, minute: u64, bad: bool) {
+        let bucket = &mut self.buckets[(minute % BUCKET_COUNT) as usize];
+        if bucket.minute != minute {
+            *bucket = Bucket {
+                minute,
+                ..Default::default()
+            };
+        }
+        bucket.total += 1;
+        if bad {
+            bucket.bad += 1;
+        }
+    }
+    /// Get the burn rate of the last minutes window,
+    /// it's the error rate divided by the error budget.
+    fn burn_rate(&self, minute: u64, window: u64) -> Option<f64> {
+        let (mut total, mut bad) = (0, 0);
+        for bucket in self.buckets.iter() {
+            if bucket.total == 0
+                || bucket.minute > minute
+                || minute - bucket.minute >= window
+            {
+                continue;
+            }
+            total += bucket.total;
+            bad += bucket.bad;
+        }
+        if total == 0 {
+            return None;
+        }
+        Some(bad as f64 / total as f64 / (1.0 - self.target))
+    }
+    /// Check the burn rate alerts, the message will be returned if
+    /// the error budget is burning too fast.
+    fn check(&mut self, minute: u64) -> Option<(NotificationLevel, String)> {
+        if let Some(alerted_at) = self.alerted_at {
+            if minute - alerted_at < ALERT_INTERVAL_MINUTES {
+                return None;
+            }
+        }
+        for (short, long, threshold, name) in BURN_RATE_ALERTS {
+            let short_rate = self.burn_rate(minute, short).unwrap_or_default();
+            let long_rate = self.burn_rate(minute, long).unwrap_or_default();
+            if short_rate < threshold || long_rate < threshold {
+                continue;
+            }
+            self.alerted_at = Some(minute);
+            let level = if name == "fast" {
+                NotificationLevel::Error
+            } else {
+                NotificationLevel::Warn
+            };
+            let msg = format!(
+                "error budget is burning too {name}, burn rate: {short_rate:.1}({short}m) {long_rate:.1}({long}m), target: {}%",
+                self.target * 100.0
+            );
+            return Some((level, msg));
+        }
+        None
+    }
+}
+
+static SLO_STATS: Lazy<RwLock<AHashMap<String, Arc<Mutex<SloStats>>>>> =
+    Lazy::new(|| RwLock::new(AHashMap::new()));
+
+#[inline]
+fn now_minute() -> u64 {
+    util::now().as_secs() / 60
+}
+
+/// Record the request of location for slo, the request is bad if
+/// it's server error or its latency is over the threshold.
+pub fn record_slo(location: &str, slo: &Slo, status: u16, latency: u64) {
+    let bad = status == 0
+        || status >= 500
+        || slo
+            .latency
+            .map(|value| latency > value.as_millis() as u64)
+            .unwrap_or_default();
+    let stats = if let Ok(map) = SLO_STATS.read() {
+        map.get(location).cloned()
+    } else {
+        None
+    };
+    let stats = if let Some(stats) = stats {
+        stats
+    } else {
+        let Ok(mut map) = SLO_STATS.write() else {
+            return;
+        };
+        map.entry(location.to_string())
+            .or_insert_with(|| Arc::new(Mutex::new(SloStats::new(slo.target))))
+            .clone()
+    };
+    if let Ok(mut stats) = stats.lock() {
+        // the target may be changed by config reload
+        stats.target = slo.target;
+        stats.add(now_minute(), bad);
+    };
+}
+
+/// Get the burn rate of location in the last minutes window.
+pub fn get_slo_burn_rate(location: &str, window: u64) -> Option<f64> {
+    let stats = SLO_STATS.read().ok()?.get(location).cloned()?;
+    let stats = stats.lock().ok()?;
+    stats.burn_rate(now_minute(), window)
+}
+
+/// Create a service task to check the slo burn rate of locations,
+/// the webhook notification will be sent if the budget is burning too fast.
+pub fn new_slo_burn_rate_service() -> (String, SimpleServiceTaskFuture) {
+    let task: SimpleServiceTaskFuture = Box::new(move |_count: u32| {
+        Box::pin({
+            async move {
+                let list: Vec<(String, Arc<Mutex<SloStats>>)> =
+                    if let Ok(map) = SLO_STATS.read() {
+                        map.iter()
+                            .map(|(name, stats)| (name.clone(), stats.clone()))
+                            .collect()
+                    } else {
+                        vec![]
+                    };
+                let minute = now_minute();
+                let mut alerts = vec![];
+                for (name, stats) in list.iter() {
+                    if let Ok(mut stats) = stats.lock() {
+                        if let Some(alert) = stats.check(minute) {
+                            alerts.push((name.clone(), alert));
+                        }
+                    }
+                }
+                for (name, (level, msg)) in alerts {
+                    let msg = format!("location {name} {msg}");
+                    warn!(location = name, message = msg, "slo burn rate");
+                    send_notification(SendNotificationParams {
+                        category: NotificationCategory::SloBurnRate,
+                        level,
+                        msg,
+                        ..Default::default()
+                    })
+                    .await;
+                }
+                Ok(true)
+            }
+        })
+    });
+    ("sloBurnRate".to_string(), task)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        get_slo_burn_rate, parse_slo_target, record_slo, Slo, SloStats,
+    };
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_slo_target() {
+        assert_eq!(0.99, parse_slo_target("99%").unwrap());
+        assert_eq!(0.95, parse_slo_target("95").unwrap());
+        assert_eq!(
+            "slo target(100) should be greater than 0 and less than 100",
+            parse_slo_target("100").err().unwrap()
+        );
+        assert_eq!(true, parse_slo_target("abc").is_err());
+    }
+
+    #[test]
+    fn test_slo_burn_rate() {
+        let mut stats = SloStats::new(0.99);
+        let minute = 1000;
+        assert_eq!(None, stats.burn_rate(minute, 5));
+        // 1 bad of 100 requests six hours ago, it's out of the windows
+        for i in 0..100 {
+            stats.add(minute - 360, i == 0);
+        }
+        for i in 0..100 {
+            stats.add(minute, i < 20);
+        }
+        // 20% error rate, 20 times of the budget
+        assert_eq!(20, stats.burn_rate(minute, 5).unwrap().round() as u64);
+        assert_eq!(20, stats.burn_rate(minute, 360).unwrap().round() as u64);
+        let (_, msg) = stats.check(minute).unwrap();
+        assert_eq!(
+            "error budget is burning too fast, burn rate: 20.0(5m) 20.0(60m), target: 99%",
+            msg
+        );
+        // not alert again in the interval
+        assert_eq!(true, stats.check(minute + 1).is_none());
+
+        // the fast window is recovered
+        let mut stats = SloStats::new(0.99);
+        for i in 0..100 {
+            stats.add(minute - 29, i < 20);
+        }
+        for _ in 0..100 {
+            stats.add(minute, false);
+        }
+        assert_eq!(0.0, stats.burn_rate(minute, 5).unwrap());
+        let (_, msg) = stats.check(minute).unwrap();
+        assert_eq!(
+            "error budget is burning too slow, burn rate: 10.0(30m) 10.0(360m), target: 99%",
+            msg
+        );
+    }
+
+    #[test]
+    fn test_record_slo() {
+        let slo = Slo {
+            target: 0.9,
+            latency: Some(Duration::from_millis(100)),
+        };
+        record_slo("slo-test", &slo, 200, 10);
+        record_slo("slo-test", &slo, 502, 10);
+        record_slo("slo-test", &slo, 200, 200);
+        record_slo("slo-test", &slo, 200, 50);
+        // 50% error rate of 10% budget
+        assert_eq!(5, get_slo_burn_rate("slo-test", 5).unwrap().round() as u64);
+        assert_eq!(None, get_slo_burn_rate("slo-not-found", 5));
+    }
+}
@@ -0,0 +1,200 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::util;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::sync::Mutex;
+
+// keep the per second buckets of one minute
+const BUCKET_COUNT: u64 = 60;
+// the upper bounds(ms) of latency buckets,
+// the latency greater than the last one is in the overflow bucket
+const LATENCY_BOUNDS: [u64; 13] =
+    [1, 2, 5, 10, 20, 50, 100, 200, 500, 1000, 2000, 5000, 10000];
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Bucket {
+    second: u64,
+    total: u64,
+    // 1xx, 2xx, 3xx, 4xx, 5xx and the others(e.g. no response)
+    statuses: [u64; 6],
+    latencies: [u64; LATENCY_BOUNDS.len() + 1],
+}
+
+/// The traffic summary of the last seconds.
+#[derive(Debug, Default, Clone, Serialize, PartialEq)]
+pub struct TrafficSummary {
+    // the last second of summary
+    pub time: u64,
+    pub requests: u64,
+    // the requests per second
+    pub rps: f64,
+    pub status_1xx: u64,
+    pub status_2xx: u64,
+    pub status_3xx: u64,
+    pub status_4xx: u64,
+    pub status_5xx: u64,
+    pub status_other: u64,
+    // the percentiles(ms) are the upper bounds of latency buckets
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+}
+
+struct TrafficStats {
+    buckets: Vec<Bucket>,
+}
+
+impl TrafficStats {
+    fn new() -> Self {
+        Self {
+            buckets: vec![Bucket::default(); BUCKET_COUNT as usize],
+        }
+    }
+    fn add(&mut self, second: u64, status: u16, latency: u64) {
+        let bucket = &mut self.buckets[(second % BUCKET_COUNT) as usize];
+        if bucket.second != second {
+            *bucket = Bucket {
+                second,
+                ..Default::default()
+            };
+        }
+        bucket.total += 1;
+        let index = match status {
+            100..=599 => (status / 100 - 1) as usize,
+            _ => 5,
+        };
+        bucket.statuses[index] += 1;
+        let index = LATENCY_BOUNDS
+            .iter()
+            .position(|bound| latency <= *bound)
+            .unwrap_or(LATENCY_BOUNDS.len());
+        bucket.latencies[index] += 1;
+    }
+    /// Get the summary of the window seconds which end at the second.
+    fn summary(&self, second: u64, window: u64) -> TrafficSummary {
+        let window = window.clamp(1, BUCKET_COUNT);
+        let mut total = 0;
+        let mut statuses = [0; 6];
+        let mut latencies = [0; LATENCY_BOUNDS.len() + 1];
+        for bucket in self.buckets.iter() {
+            if bucket.total == 0
+                || bucket.second > second
+                || second - bucket.second >= window
+            {
+                continue;
+            }
+            total += bucket.total;
+            for (i, count) in bucket.statuses.iter().enumerate() {
+                statuses[i] += count;
+            }
+            for (i, count) in bucket.latencies.iter().enumerate() {
+                latencies[i] += count;
+            }
+        }
+        let percentile = |p: f64| {
+            if total == 0 {
+                return 0;
+            }
+            let target = ((total as f64) * p).ceil() as u64;
+            let mut count = 0;
+            for (i, value) in latencies.iter().enumerate() {
+                count += value;
+                if count >= target {
+                    return LATENCY_BOUNDS[i.min(LATENCY_BOUNDS.len() - 1)];
+                }
+            }
+            LATENCY_BOUNDS[LATENCY_BOUNDS.len() - 1]
+        };
+        TrafficSummary {
+            time: second,
+            requests: total,
+            rps: total as f64 / window as f64,
+            status_1xx: statuses[0],
+            status_2xx: statuses[1],
+            status_3xx: statuses[2],
+            status_4xx: statuses[3],
+            status_5xx: statuses[4],
+            status_other: statuses[5],
+            p50: percentile(0.5),
+            p90: percentile(0.9),
+            p99: percentile(0.99),
+        }
+    }
+}
+
+static TRAFFIC_STATS: Lazy<Mutex<TrafficStats>> =
+    Lazy::new(|| Mutex::new(TrafficStats::new()));
+
+/// Record the status and latency(ms) of request for the live traffic.
+pub fn record_traffic(status: u16, latency: u64) {
+    let second = util::now().as_secs();
+    TRAFFIC_STATS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .add(second, status, latency);
+}
+
+/// Get the traffic summary of the last window seconds,
+/// the current second is excluded because it's incomplete.
+pub fn get_traffic_summary(window: u64) -> TrafficSummary {
+    let second = util::now().as_secs() - 1;
+    TRAFFIC_STATS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .summary(second, window)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TrafficStats, TrafficSummary};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_traffic_stats() {
+        let mut stats = TrafficStats::new();
+        for i in 0..100 {
+            stats.add(100, 200, i);
+        }
+        stats.add(100, 502, 3000);
+        stats.add(100, 0, 20_000);
+        stats.add(101, 404, 1);
+        // expired
+        stats.add(30, 200, 1);
+
+        assert_eq!(
+            TrafficSummary {
+                time: 100,
+                requests: 102,
+                rps: 102.0,
+                status_2xx: 100,
+                status_5xx: 1,
+                status_other: 1,
+                p50: 50,
+                p90: 100,
+                p99: 5000,
+                ..Default::default()
+            },
+            stats.summary(100, 1)
+        );
+        let summary = stats.summary(101, 2);
+        assert_eq!(103, summary.requests);
+        assert_eq!(51.5, summary.rps);
+        assert_eq!(1, summary.status_4xx);
+
+        assert_eq!(0, stats.summary(200, 10).requests);
+        assert_eq!(0, stats.summary(200, 10).p99);
+    }
+}
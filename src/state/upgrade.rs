@@ -0,0 +1,242 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::webhook;
+use serde::Serialize;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+/// The params of upgrade, the new binary is started with the args,
+/// and it takes over the listeners of the running process.
+#[derive(Debug, Default)]
+pub struct UpgradeParams {
+    pub exec_path: PathBuf,
+    pub log_level: String,
+    pub args: Vec<String>,
+    pub pid_file: String,
+    // the new process receives the listeners by the socket
+    pub upgrade_sock: String,
+    // the deadline of the handover and the old process draining
+    pub timeout: Duration,
+}
+
+/// The report of upgrade, it's printed as json and sent by webhook.
+#[derive(Debug, Default, Serialize)]
+pub struct UpgradeReport {
+    pub success: bool,
+    pub old_pid: i32,
+    pub new_pid: i32,
+    // the elapsed milliseconds of upgrade
+    pub elapsed: u64,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub message: String,
+}
+
+fn read_pid(pid_file: &str) -> Option<i32> {
+    std::fs::read_to_string(pid_file)
+        .ok()?
+        .trim()
+        .parse::<i32>()
+        .ok()
+}
+
+#[cfg(unix)]
+fn is_process_alive(pid: i32) -> bool {
+    nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid), None).is_ok()
+}
+
+#[cfg(unix)]
+async fn do_upgrade(
+    params: &UpgradeParams,
+    report: &mut UpgradeReport,
+) -> io::Result<()> {
+    let not_running = |message: &str| {
+        io::Error::new(io::ErrorKind::NotFound, message.to_string())
+    };
+    let old_pid = read_pid(&params.pid_file)
+        .filter(|pid| is_process_alive(*pid))
+        .ok_or_else(|| not_running("the running process is not found"))?;
+    report.old_pid = old_pid;
+    let deadline = Instant::now() + params.timeout;
+
+    // the stale socket is removed, so the new process is ready
+    // when the socket exists
+    let upgrade_sock = std::path::Path::new(&params.upgrade_sock);
+    if let Err(e) = std::fs::remove_file(upgrade_sock) {
+        if e.kind() != io::ErrorKind::NotFound {
+            return Err(e);
+        }
+    }
+    let mut child = std::process::Command::new(&params.exec_path)
+        .env("RUST_LOG", &params.log_level)
+        .args(&params.args)
+        .spawn()?;
+    info!(old_pid, "new process is spawned");
+    // the old process isn't notified until the new process listens
+    // on the upgrade socket, otherwise the new process is killed
+    // and the old process keeps serving
+    while !upgrade_sock.exists() {
+        if let Some(status) = child.try_wait()? {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("new process exits with {status} before ready"),
+            ));
+        }
+        if Instant::now() > deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "new process is not ready before the deadline",
+            ));
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    info!(old_pid, "new process is ready");
+
+    // the old process sends the listeners to the new process,
+    // then drains the in-flight requests and exits
+    nix::sys::signal::kill(
+        nix::unistd::Pid::from_raw(old_pid),
+        nix::sys::signal::SIGQUIT,
+    )?;
+    // the spawned process exits with success after the new server is
+    // daemonized, so the handover is done when the new pid is written
+    let new_pid = loop {
+        if let Some(pid) = read_pid(&params.pid_file)
+            .filter(|pid| *pid != old_pid && is_process_alive(*pid))
+        {
+            break pid;
+        }
+        if let Some(status) = child.try_wait()? {
+            if !status.success() {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("new process exits with {status}"),
+                ));
+            }
+        }
+        if Instant::now() > deadline {
+            return Err(not_running(
+                "the new process is not running after the handover",
+            ));
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    };
+    report.new_pid = new_pid;
+    info!(old_pid, new_pid, "listeners are handed over");
+
+    while is_process_alive(old_pid) {
+        if Instant::now() > deadline {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "old process is still draining after the deadline",
+            ));
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+async fn do_upgrade(
+    _params: &UpgradeParams,
+    _report: &mut UpgradeReport,
+) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Not support upgrade".to_string(),
+    ))
+}
+
+/// Upgrade the running process to the new binary with zero downtime,
+/// the new process takes over the listeners, and the old one drains
+/// the in-flight requests before the deadline. The result is reported
+/// by webhook.
+pub async fn upgrade(params: &UpgradeParams) -> UpgradeReport {
+    let started_at = Instant::now();
+    let mut report = UpgradeReport::default();
+    let result = do_upgrade(params, &mut report).await;
+    report.elapsed = started_at.elapsed().as_millis() as u64;
+    let exec_path = params.exec_path.to_string_lossy();
+    let notification = match result {
+        Ok(_) => {
+            report.success = true;
+            info!(
+                old_pid = report.old_pid,
+                new_pid = report.new_pid,
+                elapsed = report.elapsed,
+                "upgrade success"
+            );
+            webhook::SendNotificationParams {
+                category: webhook::NotificationCategory::Upgrade,
+                msg: format!(
+                    "Upgrade to {exec_path} success, pid:{} -> {}, elapsed:{}ms",
+                    report.old_pid, report.new_pid, report.elapsed
+                ),
+                ..Default::default()
+            }
+        },
+        Err(e) => {
+            report.message = e.to_string();
+            error!(
+                error = report.message,
+                old_pid = report.old_pid,
+                "upgrade fail"
+            );
+            webhook::SendNotificationParams {
+                category: webhook::NotificationCategory::UpgradeFail,
+                level: webhook::NotificationLevel::Error,
+                msg: format!(
+                    "Upgrade to {exec_path} fail, pid:{}, {e}",
+                    report.old_pid
+                ),
+                ..Default::default()
+            }
+        },
+    };
+    webhook::send_notification(notification).await;
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_pid, upgrade, UpgradeParams};
+    use pretty_assertions::assert_eq;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_read_pid() {
+        let file = NamedTempFile::new().unwrap();
+        let path = file.path().to_string_lossy().to_string();
+        std::fs::write(&path, "1234\n").unwrap();
+        assert_eq!(Some(1234), read_pid(&path));
+        std::fs::write(&path, "").unwrap();
+        assert_eq!(None, read_pid(&path));
+        assert_eq!(None, read_pid("/not-exists/pingap.pid"));
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_not_running() {
+        let report = upgrade(&UpgradeParams {
+            pid_file: "/not-exists/pingap.pid".to_string(),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(false, report.success);
+        assert_eq!("the running process is not found", report.message);
+    }
+}
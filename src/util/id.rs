@@ -0,0 +1,180 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{now, Error};
+use ahash::RandomState;
+use once_cell::sync::{Lazy, OnceCell};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+// 2024-01-01T00:00:00Z, the custom epoch of snowflake id
+const SNOWFLAKE_EPOCH: u64 = 1_704_067_200_000;
+const SNOWFLAKE_NODE_BITS: u64 = 10;
+const SNOWFLAKE_SEQUENCE_BITS: u64 = 12;
+pub const MAX_NODE_ID: u16 = (1 << SNOWFLAKE_NODE_BITS) - 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum IdGenerator {
+    /// Keep the id of stream as connection id and uuid as request id.
+    #[default]
+    Default,
+    /// The id prefixed with the startup time, it's increasing
+    /// in the process and unique across restarts.
+    Monotonic,
+    /// The random id.
+    Random,
+    /// The 64 bits twitter snowflake id, the node id should be unique
+    /// for every instance.
+    Snowflake,
+}
+
+impl FromStr for IdGenerator {
+    type Err = Error;
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "" | "default" => Ok(Self::Default),
+            "monotonic" => Ok(Self::Monotonic),
+            "random" => Ok(Self::Random),
+            "snowflake" => Ok(Self::Snowflake),
+            _ => Err(Error::Invalid {
+                message: format!("id generator({value}) is not supported"),
+            }),
+        }
+    }
+}
+
+static ID_GENERATOR: OnceCell<(IdGenerator, u16)> = OnceCell::new();
+// the startup time(ms) of process
+static BOOT_MS: Lazy<u64> = Lazy::new(|| now().as_millis() as u64);
+static MONOTONIC_SEQUENCE: AtomicU64 = AtomicU64::new(0);
+// (last timestamp, sequence)
+static SNOWFLAKE_STATE: Mutex<(u64, u64)> = Mutex::new((0, 0));
+static RANDOM_STATE: Lazy<RandomState> = Lazy::new(|| {
+    let (a, b) = Uuid::now_v7().as_u64_pair();
+    let (c, d) = Uuid::now_v7().as_u64_pair();
+    RandomState::with_seeds(a, b, c, d)
+});
+
+/// Set the id generator of connection and request id,
+/// it can only be set once.
+pub fn set_id_generator(generator: IdGenerator, node_id: u16) {
+    ID_GENERATOR.get_or_init(|| (generator, node_id & MAX_NODE_ID));
+}
+
+fn get_id_generator() -> (IdGenerator, u16) {
+    ID_GENERATOR.get().cloned().unwrap_or_default()
+}
+
+fn snowflake(timestamp: u64, node_id: u16, sequence: u64) -> u64 {
+    (timestamp.saturating_sub(SNOWFLAKE_EPOCH)
+        << (SNOWFLAKE_NODE_BITS + SNOWFLAKE_SEQUENCE_BITS))
+        | ((node_id as u64) << SNOWFLAKE_SEQUENCE_BITS)
+        | (sequence & ((1 << SNOWFLAKE_SEQUENCE_BITS) - 1))
+}
+
+fn next_snowflake(node_id: u16) -> u64 {
+    let mut state = SNOWFLAKE_STATE.lock().unwrap_or_else(|e| e.into_inner());
+    let mut timestamp = now().as_millis() as u64;
+    // the clock moves backwards or the sequence is used up,
+    // borrow the next millisecond
+    if timestamp <= state.0 {
+        state.1 += 1;
+        if state.1 >> SNOWFLAKE_SEQUENCE_BITS != 0 {
+            state.0 += 1;
+            state.1 = 0;
+        }
+        timestamp = state.0;
+    } else {
+        *state = (timestamp, 0);
+    }
+    snowflake(timestamp, node_id, state.1)
+}
+
+/// Generate the connection id from the stream id and the established time(ms)
+/// of connection, so all the requests of the same connection have the same id.
+pub fn new_connection_id(stream_id: usize, established: u64) -> usize {
+    let (generator, node_id) = get_id_generator();
+    let established = if established == 0 {
+        *BOOT_MS
+    } else {
+        established
+    };
+    let id = match generator {
+        IdGenerator::Default => return stream_id,
+        IdGenerator::Monotonic => {
+            (established << 16) | (stream_id as u64 & 0xffff)
+        },
+        IdGenerator::Random => RANDOM_STATE.hash_one((stream_id, established)),
+        IdGenerator::Snowflake => {
+            snowflake(established, node_id, stream_id as u64)
+        },
+    };
+    id as usize
+}
+
+/// Generate the request id by the id generator.
+pub fn new_request_id() -> String {
+    let (generator, node_id) = get_id_generator();
+    match generator {
+        IdGenerator::Monotonic => {
+            let sequence = MONOTONIC_SEQUENCE.fetch_add(1, Ordering::Relaxed);
+            format!("{:011x}{sequence:013x}", *BOOT_MS)
+        },
+        IdGenerator::Snowflake => next_snowflake(node_id).to_string(),
+        IdGenerator::Random => Uuid::from_u64_pair(
+            RANDOM_STATE.hash_one(now().as_nanos()),
+            RANDOM_STATE
+                .hash_one(MONOTONIC_SEQUENCE.fetch_add(1, Ordering::Relaxed)),
+        )
+        .simple()
+        .to_string(),
+        IdGenerator::Default => Uuid::now_v7().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{next_snowflake, snowflake, IdGenerator, SNOWFLAKE_EPOCH};
+    use pretty_assertions::assert_eq;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_id_generator() {
+        assert_eq!(IdGenerator::Default, IdGenerator::from_str("").unwrap());
+        assert_eq!(
+            IdGenerator::Snowflake,
+            IdGenerator::from_str("snowflake").unwrap()
+        );
+        assert_eq!(
+            "Invalid id generator(xid) is not supported",
+            IdGenerator::from_str("xid").err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_snowflake() {
+        assert_eq!(
+            (1 << 22) | (3 << 12) | 5,
+            snowflake(SNOWFLAKE_EPOCH + 1, 3, 5)
+        );
+        let mut ids: Vec<u64> =
+            (0..10_000).map(|_| next_snowflake(1)).collect();
+        let count = ids.len();
+        assert_eq!(true, ids.windows(2).all(|items| items[0] < items[1]));
+        ids.dedup();
+        assert_eq!(count, ids.len());
+    }
+}
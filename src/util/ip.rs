@@ -13,9 +13,39 @@
 // limitations under the License.
 
 use ipnet::IpNet;
-use std::net::{AddrParseError, IpAddr};
+use std::net::{AddrParseError, IpAddr, SocketAddr};
 use std::str::FromStr;
 
+/// Convert the ipv4-mapped ipv6 address(`::ffff:1.1.1.1`) to ipv4,
+/// it's accepted by dual-stack listener for ipv4 client.
+pub fn to_canonical_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        _ => ip,
+    }
+}
+
+/// Parse the ip value, which may be `[ipv6]:port`, `ipv4:port` or ip,
+/// the ipv4-mapped address is converted to ipv4.
+pub fn parse_ip(value: &str) -> Result<IpAddr, AddrParseError> {
+    let value = value.trim();
+    let ip = match value.parse::<IpAddr>() {
+        Ok(ip) => ip,
+        Err(e) => {
+            if let Ok(addr) = value.parse::<SocketAddr>() {
+                addr.ip()
+            } else if let Some(value) =
+                value.strip_prefix('[').and_then(|v| v.strip_suffix(']'))
+            {
+                value.parse::<IpAddr>()?
+            } else {
+                return Err(e);
+            }
+        },
+    };
+    Ok(to_canonical_ip(ip))
+}
+
 #[derive(Clone, Debug)]
 pub struct IpRules {
     ip_net_list: Vec<IpNet>,
@@ -42,8 +72,9 @@ impl IpRules {
         let found = if self.ip_list.contains(ip) {
             true
         } else {
-            let addr = ip.parse::<IpAddr>()?;
-            self.ip_net_list.iter().any(|item| item.contains(&addr))
+            let addr = parse_ip(ip)?;
+            self.ip_list.contains(&addr.to_string())
+                || self.ip_net_list.iter().any(|item| item.contains(&addr))
         };
         Ok(found)
     }
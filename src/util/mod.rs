@@ -37,10 +37,15 @@ pub enum Error {
 }
 
 mod crypto;
+mod id;
 mod ip;
 
 pub use crypto::{aes_decrypt, aes_encrypt};
-pub use ip::IpRules;
+pub use id::{
+    new_connection_id, new_request_id, set_id_generator, IdGenerator,
+    MAX_NODE_ID,
+};
+pub use ip::{parse_ip, to_canonical_ip, IpRules};
 
 const NAME: &str = env!("CARGO_PKG_NAME");
 const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -55,6 +60,29 @@ pub fn get_pkg_version() -> &'static str {
     VERSION
 }
 
+/// Get the features which are enabled at compile time.
+pub fn get_features() -> Vec<&'static str> {
+    let features = [
+        ("metrics", cfg!(feature = "metrics")),
+        ("otel", cfg!(feature = "otel")),
+        ("sentry", cfg!(feature = "sentry")),
+        ("pyro", cfg!(feature = "pyro")),
+        ("perf", cfg!(feature = "perf")),
+        ("wasm", cfg!(feature = "wasm")),
+        ("redis", cfg!(feature = "redis")),
+        ("memcached", cfg!(feature = "memcached")),
+        ("s3", cfg!(feature = "s3")),
+        ("acme", cfg!(feature = "acme")),
+        ("admin-ui", cfg!(feature = "admin-ui")),
+        ("waf", cfg!(feature = "waf")),
+    ];
+    features
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| *name)
+        .collect()
+}
+
 /// Get the rustc version.
 pub fn get_rustc_version() -> String {
     rustc_version_runtime::version().to_string()
@@ -89,7 +117,7 @@ pub fn get_remote_addr(session: &Session) -> Option<(String, u16)> {
     session
         .client_addr()
         .and_then(|addr| addr.as_inet())
-        .map(|addr| (addr.ip().to_string(), addr.port()))
+        .map(|addr| (to_canonical_ip(addr.ip()).to_string(), addr.port()))
 }
 
 /// Gets client ip from X-Forwarded-For,
@@ -113,6 +141,97 @@ pub fn get_client_ip(session: &Session) -> String {
     "".to_string()
 }
 
+static HTTP_HEADER_FORWARDED: Lazy<http::HeaderName> =
+    Lazy::new(|| HeaderName::from_str("Forwarded").unwrap());
+
+// Gets the ip of `for` directive, e.g. `for="[2001:db8::1]:4711"`
+fn get_forwarded_for(value: &str) -> Option<String> {
+    let value = value.split(';').find_map(|item| {
+        let (key, value) = item.trim().split_once('=')?;
+        key.eq_ignore_ascii_case("for").then_some(value)
+    })?;
+    let value = value.trim().trim_matches('"');
+    if let Some(value) = value.strip_prefix('[') {
+        return value.split_once(']').map(|(ip, _)| ip.to_string());
+    }
+    // ipv4 with port
+    if value.matches(':').count() == 1 {
+        return value.split(':').next().map(|ip| ip.to_string());
+    }
+    Some(value.to_string())
+}
+
+/// Gets the chain of forwarded ips from Forwarded or X-Forwarded-For,
+/// the remote addr is appended as the last hop.
+pub fn get_forwarded_chain(
+    session: &Session,
+    remote_addr: Option<&str>,
+) -> Vec<String> {
+    let headers = &session.req_header().headers;
+    let mut chain: Vec<String> =
+        if headers.contains_key(&*HTTP_HEADER_FORWARDED) {
+            headers
+                .get_all(&*HTTP_HEADER_FORWARDED)
+                .iter()
+                .flat_map(|value| {
+                    value
+                        .to_str()
+                        .unwrap_or_default()
+                        .split(',')
+                        .filter_map(get_forwarded_for)
+                        .collect::<Vec<_>>()
+                })
+                .collect()
+        } else {
+            headers
+                .get_all(&*HTTP_HEADER_X_FORWARDED_FOR)
+                .iter()
+                .flat_map(|value| {
+                    value
+                        .to_str()
+                        .unwrap_or_default()
+                        .split(',')
+                        .map(|item| item.trim().to_string())
+                        .collect::<Vec<_>>()
+                })
+                .collect()
+        };
+    chain.retain(|item| !item.is_empty());
+    if let Some(addr) = remote_addr {
+        chain.push(addr.to_string());
+    } else if let Some((addr, _)) = get_remote_addr(session) {
+        chain.push(addr);
+    }
+    chain
+}
+
+/// Gets the client ip from the forwarded chain, the hops from the right
+/// side are skipped if they are trusted proxies. The proxy is trusted
+/// if it is in the trusted proxy list, or its position from the right
+/// side is less than the trusted hops.
+pub fn get_trusted_client_ip(
+    chain: &[String],
+    trusted_hops: usize,
+    trusted_proxies: Option<&IpRules>,
+) -> String {
+    if chain.is_empty() {
+        return "".to_string();
+    }
+    let mut index = chain.len() - 1;
+    for (hop, ip) in chain.iter().enumerate().rev() {
+        index = hop;
+        let distance = chain.len() - 1 - hop;
+        let trusted = distance < trusted_hops
+            || trusted_proxies
+                .map(|rules| rules.matched(ip).unwrap_or_default())
+                .unwrap_or_default();
+        if !trusted {
+            break;
+        }
+    }
+    chain[index].clone()
+}
+
 /// Gets string value from req header.
 pub fn get_req_header_value<'a>(
     req_header: &'a RequestHeader,
@@ -134,7 +253,7 @@ pub fn get_cookie_value<'a>(
     if let Some(cookie_value) = get_req_header_value(req_header, "Cookie") {
         for item in cookie_value.split(';') {
             if let Some((k, v)) = item.split_once('=') {
-                if k == cookie_name {
+                if k.trim() == cookie_name {
                     return Some(v.trim());
                 }
             }
@@ -437,7 +556,7 @@ pub fn elapsed_ms(time: SystemTime) -> u64 {
     time.elapsed().unwrap_or_default().as_millis() as u64
 }
 
-#[cfg(feature = "full")]
+#[cfg(feature = "metrics")]
 #[inline]
 pub fn elapsed_second(time: SystemTime) -> f64 {
     elapsed_ms(time) as f64 / 1000.0
@@ -478,14 +597,68 @@ pub fn path_join(value1: &str, value2: &str) -> String {
 #[cfg(test)]
 mod tests {
     use super::{
-        convert_tls_version, format_byte_size, format_duration, get_latency,
-        get_pkg_name, get_pkg_version, local_ip_list, remove_query_from_header,
-        resolve_path,
+        convert_tls_version, format_byte_size, format_duration, get_features,
+        get_forwarded_for, get_latency, get_pkg_name, get_pkg_version,
+        get_trusted_client_ip, local_ip_list, parse_ip,
+        remove_query_from_header, resolve_path, IpRules,
     };
     use bytes::BytesMut;
     use pingora::{http::RequestHeader, tls::ssl::SslVersion};
     use pretty_assertions::assert_eq;
     #[test]
+    fn test_get_trusted_client_ip() {
+        assert_eq!(
+            "2001:db8::1",
+            get_forwarded_for(r#"for="[2001:db8::1]:4711";proto=https"#)
+                .unwrap()
+        );
+        assert_eq!("1.1.1.1", get_forwarded_for("for=1.1.1.1:80").unwrap());
+        assert_eq!(true, get_forwarded_for("proto=https").is_none());
+
+        let chain: Vec<String> = ["1.1.1.1", "2.2.2.2", "10.0.0.1"]
+            .iter()
+            .map(|item| item.to_string())
+            .collect();
+        assert_eq!("10.0.0.1", get_trusted_client_ip(&chain, 0, None));
+        assert_eq!("2.2.2.2", get_trusted_client_ip(&chain, 1, None));
+        assert_eq!("1.1.1.1", get_trusted_client_ip(&chain, 5, None));
+        let rules = IpRules::new(&vec!["10.0.0.0/8".to_string()]);
+        assert_eq!("2.2.2.2", get_trusted_client_ip(&chain, 0, Some(&rules)));
+        assert_eq!("", get_trusted_client_ip(&[], 1, Some(&rules)));
+    }
+    #[test]
+    fn test_ip_rules() {
+        assert_eq!("1.1.1.1", parse_ip("::ffff:1.1.1.1").unwrap().to_string());
+        assert_eq!("1.1.1.1", parse_ip("1.1.1.1:3000").unwrap().to_string());
+        assert_eq!(
+            "2001:db8::1",
+            parse_ip("[2001:db8::1]:3000").unwrap().to_string()
+        );
+        assert_eq!(
+            "2001:db8::1",
+            parse_ip("[2001:db8::1]").unwrap().to_string()
+        );
+        assert_eq!(true, parse_ip("abc").is_err());
+
+        let rules = IpRules::new(&vec![
+            "192.168.1.0/24".to_string(),
+            "10.1.1.1".to_string(),
+            "2001:db8::/32".to_string(),
+        ]);
+        assert_eq!(true, rules.matched(&"192.168.1.10".to_string()).unwrap());
+        assert_eq!(
+            true,
+            rules.matched(&"::ffff:192.168.1.10".to_string()).unwrap()
+        );
+        assert_eq!(
+            true,
+            rules.matched(&"::ffff:10.1.1.1".to_string()).unwrap()
+        );
+        assert_eq!(true, rules.matched(&"2001:db8::2".to_string()).unwrap());
+        assert_eq!(false, rules.matched(&"2001:db9::2".to_string()).unwrap());
+        assert_eq!(false, rules.matched(&"10.1.1.2".to_string()).unwrap());
+    }
+    #[test]
     fn test_remove_query_from_header() {
         let mut req =
             RequestHeader::build("GET", b"/?apikey=123", None).unwrap();
@@ -503,6 +676,10 @@ mod tests {
     fn test_get_pkg_info() {
         assert_eq!("pingap", get_pkg_name());
         assert_eq!(false, get_pkg_version().is_empty());
+        assert_eq!(
+            cfg!(feature = "metrics"),
+            get_features().contains(&"metrics")
+        );
     }
 
     #[test]
@@ -12,8 +12,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::config::{get_app_name, ConfigChange};
+use crate::state;
 use crate::util;
-use crate::{config::get_app_name, state};
 use async_trait::async_trait;
 use once_cell::sync::OnceCell;
 use pingora::lb::health_check::HealthObserve;
@@ -51,6 +52,12 @@ pub enum NotificationCategory {
     TlsValidity,
     ParseCertificateFail,
     ServiceDiscoverFail,
+    SloBurnRate,
+    ScheduledConfig,
+    ScheduledConfigFail,
+    RollbackConfig,
+    Upgrade,
+    UpgradeFail,
 }
 
 impl Display for NotificationLevel {
@@ -101,6 +108,8 @@ pub struct SendNotificationParams {
     pub level: NotificationLevel,
     pub msg: String,
     pub remark: Option<String>,
+    // the structured changes of config diff
+    pub changes: Vec<ConfigChange>,
 }
 impl Default for SendNotificationParams {
     fn default() -> Self {
@@ -109,6 +118,7 @@ impl Default for SendNotificationParams {
             level: NotificationLevel::Info,
             msg: "".to_string(),
             remark: None,
+            changes: vec![],
         }
     }
 }
@@ -194,6 +204,14 @@ pub async fn send_notification(params: SendNotificationParams) {
             data.insert("ip".to_string(), Value::String(ip));
             data.insert("category".to_string(), Value::String(category));
             data.insert("message".to_string(), Value::String(params.msg));
+            data.insert("remark".to_string(), Value::String(remark));
+            if !params.changes.is_empty() {
+                data.insert(
+                    "changes".to_string(),
+                    serde_json::to_value(&params.changes)
+                        .unwrap_or(Value::Null),
+                );
+            }
         },
     }
 
@@ -70,7 +70,7 @@ export default {
     user: "Daemon User",
     group: "Daemon Group",
     configHash: "Config Hash",
-    enabledFull: "Enable Full Features",
+    features: "Features",
     rustc: "Rustc",
     machineCpu: "Machine CPU",
     machineMemory: "Machine Memory",
@@ -70,7 +70,7 @@ export default {
     user: "后台服务用户",
     group: "后台服务用户组",
     configHash: "应用配置哈希",
-    enabledFull: "启用所有特性",
+    features: "编译特性",
     rustc: "Rust编译器版本",
     machineCpu: "机器CPU",
     machineMemory: "机器内存",
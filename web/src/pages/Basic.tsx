@@ -199,7 +199,7 @@ export default function Basic() {
       category: ExFormItemCategory.TEXT,
     },
   ];
-  if (basicInfo.enabled_full) {
+  if (basicInfo.features.includes("sentry")) {
     items.push({
       name: "sentry",
       label: basicI18n("sentry"),
@@ -296,8 +296,8 @@ export default function Home() {
       value: basicInfo.group,
     },
     {
-      name: "enabledFull",
-      value: basicInfo.enabled_full ? homeI18n("yes") : homeI18n("no"),
+      name: "features",
+      value: basicInfo.features.join(", "),
     },
     {
       name: "rustc",
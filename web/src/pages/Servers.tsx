@@ -213,25 +213,25 @@ export default function Servers() {
     },
   ];
 
-  if (basicInfo.enabled_full) {
-    items.push(
-      {
-        name: "prometheus_metrics",
-        label: serverI18n("prometheusMetrics"),
-        placeholder: serverI18n("prometheusMetricsPlaceholder"),
-        defaultValue: serverConfig.prometheus_metrics,
-        span: 6,
-        category: ExFormItemCategory.TEXT,
-      },
-      {
-        name: "otlp_exporter",
-        label: serverI18n("otlpExporter"),
-        placeholder: serverI18n("otlpExporterPlaceholder"),
-        defaultValue: serverConfig.otlp_exporter,
-        span: 6,
-        category: ExFormItemCategory.TEXT,
-      },
-    );
+  if (basicInfo.features.includes("metrics")) {
+    items.push({
+      name: "prometheus_metrics",
+      label: serverI18n("prometheusMetrics"),
+      placeholder: serverI18n("prometheusMetricsPlaceholder"),
+      defaultValue: serverConfig.prometheus_metrics,
+      span: 6,
+      category: ExFormItemCategory.TEXT,
+    });
+  }
+  if (basicInfo.features.includes("otel")) {
+    items.push({
+      name: "otlp_exporter",
+      label: serverI18n("otlpExporter"),
+      placeholder: serverI18n("otlpExporterPlaceholder"),
+      defaultValue: serverConfig.otlp_exporter,
+      span: 6,
+      category: ExFormItemCategory.TEXT,
+    });
   }
   items.push({
     name: "remark",
@@ -19,7 +19,7 @@ interface Basic {
   physical_cpus: number;
   used_memory: string;
   total_memory: string;
-  enabled_full: boolean;
+  features: string[];
   enabled_pyroscope: boolean;
   fd_count: number;
   tcp_count: number;
@@ -51,7 +51,7 @@ const useBasicState = create<ConfigState>()((set) => ({
     physical_cpus: 0,
     used_memory: "",
     total_memory: "",
-    enabled_full: false,
+    features: [],
     enabled_pyroscope: false,
     fd_count: 0,
     tcp_count: 0,